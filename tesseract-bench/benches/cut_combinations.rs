@@ -0,0 +1,65 @@
+//! The logic layer expands cuts on several levels of a dimension into one
+//! query per combination of levels, and generates sql for each. This
+//! benchmarks `generate_ts_queries` and the sql generation for its queries,
+//! for cuts with growing numbers of members.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use serde_json::json;
+use tesseract_bench::{clickhouse_sql, schema};
+use tesseract_core::{Cube, Schema};
+use tesseract_olap::handlers::logic_layer::{generate_ts_queries, LogicLayerQueryOpt};
+use tesseract_olap::logic_layer::{populate_cache, CubeCache};
+
+/// A query cutting on two levels of the geography dimension and two levels
+/// of the product dimension, which expands to four combinations, each cut
+/// with `n` members.
+fn query(n: usize) -> LogicLayerQueryOpt {
+    let members = |f: &dyn Fn(usize) -> String| {
+        (0..n).map(f).collect::<Vec<_>>().join(",")
+    };
+
+    serde_json::from_value(json!({
+        "cube": "sales",
+        "drilldowns": "Year",
+        "measures": "Quantity",
+        "State": members(&|i| format!("04000US{:02}", i)),
+        "County": members(&|i| format!("05000US{:02}", i)),
+        "Product Group": members(&|i| i.to_string()),
+        "Product": members(&|i| i.to_string()),
+    }))
+    .expect("valid logic layer query")
+}
+
+fn expand_and_generate(schema: &Schema, cube: &Cube, cube_cache: &CubeCache, query: &LogicLayerQueryOpt) -> Vec<String> {
+    let (queries, _level_map) = generate_ts_queries(query.clone(), cube, cube_cache, &None, &None, usize::MAX)
+        .expect("valid logic layer query");
+
+    queries.iter()
+        .map(|query| clickhouse_sql(schema, query))
+        .collect()
+}
+
+fn cut_combinations(c: &mut Criterion) {
+    let schema = schema();
+    let cube = schema.get_cube_by_name("sales").expect("sales cube");
+
+    let mut sys = actix::System::new("tesseract-bench-cache");
+    let cache = populate_cache(schema.clone(), &None, Box::new(tesseract_test::cache_backend()), &mut sys)
+        .expect("cache populated from the cache backend");
+    let cube_cache = cache.find_cube_info(&cube.name).expect("sales cube cache");
+
+    let mut group = c.benchmark_group("cut combinations");
+
+    for n_members in &[1, 10, 50] {
+        group.bench_with_input(
+            BenchmarkId::from_parameter(n_members),
+            &query(*n_members),
+            |b, query| b.iter(|| expand_and_generate(&schema, cube, &cube_cache, query)),
+        );
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, cut_combinations);
+criterion_main!(benches);
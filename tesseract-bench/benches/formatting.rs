@@ -0,0 +1,28 @@
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+use tesseract_bench::dataframe;
+use tesseract_core::format::{format_records, FormatType};
+
+const ROWS: usize = 100_000;
+
+fn formatting(c: &mut Criterion) {
+    let headers: Vec<_> = dataframe(0).column_names();
+
+    let formats = vec![
+        ("csv", FormatType::Csv),
+        ("jsonrecords", FormatType::JsonRecords),
+        ("jsonarrays", FormatType::JsonArrays),
+    ];
+
+    for (name, format) in formats {
+        c.bench_function(&format!("format {} {} rows", name, ROWS), |b| {
+            b.iter_batched(
+                || dataframe(ROWS),
+                |df| format_records(&headers, df, format.clone()).unwrap(),
+                BatchSize::LargeInput,
+            )
+        });
+    }
+}
+
+criterion_group!(benches, formatting);
+criterion_main!(benches);
@@ -0,0 +1,49 @@
+use criterion::{criterion_group, criterion_main, Criterion};
+use tesseract_bench::{clickhouse_sql, schema};
+use tesseract_core::Query;
+
+fn sql_generation(c: &mut Criterion) {
+    let schema = schema();
+
+    let simple = Query::builder()
+        .drilldown("Year.Year")
+        .measure("Quantity")
+        .build()
+        .unwrap();
+
+    let cuts_properties = Query::builder()
+        .drilldown("Year.Year")
+        .drilldown("Geography.County")
+        .drilldown("Product.Product")
+        .cut("Geography.Geography.State.04000US06,04000US04,04000US36")
+        .cut("Year.Year.Year.2016,2017,2018")
+        .property("Product.Product.Product.Hexcode")
+        .measure("Quantity")
+        .measure("Price")
+        .build()
+        .unwrap();
+
+    let growth = Query::builder()
+        .drilldown("Year.Year")
+        .drilldown("Geography.State")
+        .measure("Quantity")
+        .growth("Year.Year,Quantity")
+        .build()
+        .unwrap();
+
+    let top = Query::builder()
+        .drilldown("Year.Year")
+        .drilldown("Geography.County")
+        .measure("Quantity")
+        .top("10,Geography.County,Quantity,desc")
+        .build()
+        .unwrap();
+
+    c.bench_function("sql simple", |b| b.iter(|| clickhouse_sql(&schema, &simple)));
+    c.bench_function("sql cuts and properties", |b| b.iter(|| clickhouse_sql(&schema, &cuts_properties)));
+    c.bench_function("sql growth", |b| b.iter(|| clickhouse_sql(&schema, &growth)));
+    c.bench_function("sql top", |b| b.iter(|| clickhouse_sql(&schema, &top)));
+}
+
+criterion_group!(benches, sql_generation);
+criterion_main!(benches);
@@ -0,0 +1,35 @@
+//! Shared fixtures for the benchmarks in `benches/`.
+
+use tesseract_core::{Column, ColumnData, DataFrame, Query, Schema};
+
+/// The sales schema of `tesseract-test`, so the benchmarks run against
+/// the same cube as the tests.
+pub use tesseract_test::{SCHEMA, schema};
+
+/// Generates the ClickHouse sql for a query against the sales cube.
+pub fn clickhouse_sql(schema: &Schema, query: &Query) -> String {
+    let (query_ir, _headers) = schema.sql_query("sales", query).expect("valid bench query");
+    tesseract_clickhouse::clickhouse_sql(&query_ir)
+}
+
+/// A DataFrame shaped like an aggregate result: two drilldowns
+/// (id and label each) and two measures.
+pub fn dataframe(rows: usize) -> DataFrame {
+    DataFrame::from_vec(vec![
+        Column::new("Year".to_owned(), ColumnData::UInt32(
+            (0..rows).map(|i| 2000 + (i % 20) as u32).collect()
+        )),
+        Column::new("State ID".to_owned(), ColumnData::Text(
+            (0..rows).map(|i| format!("04000US{:02}", i % 56)).collect()
+        )),
+        Column::new("State".to_owned(), ColumnData::Text(
+            (0..rows).map(|i| format!("State {}", i % 56)).collect()
+        )),
+        Column::new("Quantity".to_owned(), ColumnData::Int64(
+            (0..rows).map(|i| i as i64 * 7).collect()
+        )),
+        Column::new("Price".to_owned(), ColumnData::NullableFloat64(
+            (0..rows).map(|i| if i % 10 == 0 { None } else { Some(i as f64 / 3.0) }).collect()
+        )),
+    ])
+}
@@ -4,13 +4,14 @@ use failure::{Error, format_err};
 use futures::{future, Future, Stream};
 use log::*;
 use std::time::{Duration, Instant};
-use tesseract_core::{Backend, DataFrame, QueryIr};
+use tesseract_core::{Backend, DataFrame, QueryIr, TimeGrain};
+use tesseract_core::query_ir::Escaping;
 
 mod df;
 mod sql;
 
 use self::df::{block_to_df};
-use self::sql::clickhouse_sql;
+pub use self::sql::{ClickhouseDialect, clickhouse_sql, distribution_sql};
 
 // Ping timeout in millis
 const PING_TIMEOUT: u64 = 100_000;
@@ -74,6 +75,39 @@ impl Backend for Clickhouse {
         Box::new(fut_stream)
     }
 
+    fn explain_sql(&self, sql: String, kind: Option<&str>) -> Box<Future<Item=DataFrame, Error=Error>> {
+        let kind = match kind.unwrap_or("plan") {
+            "plan" => "plan",
+            "pipeline" => "pipeline",
+            "syntax" => "syntax",
+            kind => return Box::new(future::err(
+                format_err!("Unknown explain kind {}, expected one of: plan, pipeline, syntax", kind)
+            )),
+        };
+
+        self.exec_sql(format!("explain {} {}", kind, sql))
+    }
+
+    fn time_grain_sql(&self, grain: &TimeGrain, column: &str) -> String {
+        match grain {
+            TimeGrain::Year => format!("toYear({})", column),
+            TimeGrain::Quarter => format!("(toYear({col}) * 10 + toQuarter({col}))", col=column),
+            TimeGrain::Month => format!("toYYYYMM({})", column),
+            TimeGrain::Day => format!("toYYYYMMDD({})", column),
+            TimeGrain::IsoWeek => format!("(toISOYear({col}) * 100 + toISOWeek({col}))", col=column),
+            TimeGrain::DayOfWeek => format!("toDayOfWeek({})", column),
+            TimeGrain::MonthName => format!("monthName({})", column),
+        }
+    }
+
+    fn distribution_sql(&self, sql: String, buckets: u32) -> Result<String, Error> {
+        Ok(distribution_sql(&sql, buckets))
+    }
+
+    fn escaping(&self) -> Escaping {
+        Escaping::Backslash
+    }
+
     // https://users.rust-lang.org/t/solved-is-it-possible-to-clone-a-boxed-trait-object/1714/4
     fn box_clone(&self) -> Box<dyn Backend + Send + Sync> {
         Box::new((*self).clone())
@@ -1,5 +1,6 @@
 mod aggregator;
 mod cuts;
+mod distribution;
 mod growth;
 mod options;
 mod primary_agg;
@@ -22,8 +23,9 @@ use tesseract_core::query_ir::{
     RateSql,
     dim_subquery,
 };
-use tesseract_core::{QueryIr};
+use tesseract_core::{QueryIr, SqlDialect, dialect_sql};
 
+pub use self::distribution::distribution_sql;
 use self::options::wrap_options;
 use self::primary_agg::primary_agg;
 use self::rate::rate_calculation;
@@ -35,35 +37,72 @@ pub fn clickhouse_sql(
     query_ir: &QueryIr
     ) -> String
 {
-    let meas = &query_ir.meas;
-
-    let (mut final_sql, mut final_drill_cols) = {
-        // HiddenDrilldownSql, for grouped median, only works with primar agg,
-        // will currently silently fail if used for rca or rate. (you can see
-        // here that it's simply not passed to calculations.
-        let table = &query_ir.table;
-        let cuts = &query_ir.cuts;
-        let drills = &query_ir.drills;
-
-        let rca = &query_ir.rca;
-        let rate = &query_ir.rate;
-        if let Some(rca) = rca {
-            rca::calculate(table, cuts, drills, meas, rca)
-        } else if let Some(rate) = rate {
-            rate_calculation(table, cuts, drills, meas, rate)
-        } else {
-            primary_agg(table, cuts, drills, meas, Some(&query_ir.hidden_drills))
-        }
-    };
-    if let Some(growth) = &query_ir.growth {
-        let (sql, drill_cols) = growth::calculate(final_sql, &final_drill_cols, meas.len(), growth);
-        final_sql = sql;
-        final_drill_cols = drill_cols;
-    }
-
-    final_sql = wrap_options(final_sql, &final_drill_cols, &query_ir, meas.len());
-
-    final_sql
+    dialect_sql(&ClickhouseDialect, query_ir)
+}
+
+
+/// ClickHouse sql generation.
+#[derive(Debug, Clone, Copy)]
+pub struct ClickhouseDialect;
+
+impl SqlDialect for ClickhouseDialect {
+    fn primary_agg(
+        &self,
+        table: &TableSql,
+        cuts: &[CutSql],
+        drills: &[DrilldownSql],
+        meas: &[MeasureSql],
+        hidden_drills: Option<&[HiddenDrilldownSql]>,
+        ) -> (String, String)
+    {
+        primary_agg(table, cuts, drills, meas, hidden_drills)
+    }
+
+    fn rca(
+        &self,
+        table: &TableSql,
+        cuts: &[CutSql],
+        drills: &[DrilldownSql],
+        meas: &[MeasureSql],
+        rca: &RcaSql,
+        ) -> (String, String)
+    {
+        rca::calculate(table, cuts, drills, meas, rca)
+    }
+
+    fn rate(
+        &self,
+        table: &TableSql,
+        cuts: &[CutSql],
+        drills: &[DrilldownSql],
+        meas: &[MeasureSql],
+        rate: &RateSql,
+        ) -> (String, String)
+    {
+        rate_calculation(table, cuts, drills, meas, rate)
+    }
+
+    fn growth(
+        &self,
+        final_sql: String,
+        final_drill_cols: &str,
+        num_measures: usize,
+        growth: &GrowthSql,
+        ) -> (String, String)
+    {
+        growth::calculate(final_sql, final_drill_cols, num_measures, growth)
+    }
+
+    fn options(
+        &self,
+        final_sql: String,
+        final_drill_cols: &str,
+        query_ir: &QueryIr,
+        num_measures: usize,
+        ) -> String
+    {
+        wrap_options(final_sql, final_drill_cols, query_ir, num_measures)
+    }
 }
 
 
@@ -71,9 +110,11 @@ pub fn clickhouse_sql(
 #[cfg(test)]
 mod test {
     use super::*;
-    use tesseract_core::Table;
+    use tesseract_core::{Aggregator, Table};
     use tesseract_core::names::Mask;
-    use tesseract_core::query_ir::{LevelColumn, MemberType};
+    use tesseract_core::query::SortDirection;
+    use tesseract_core::query_ir::{Escaping, LevelColumn, MemberType, RatioToParentSql, TopOtherSql, quote_identifier};
+    use super::cuts::cut_sql_string;
 
     // TODO move this to better place?
     // Should all of these internal checks be moved to one place? Is this an ok place?
@@ -90,6 +131,7 @@ mod test {
                 member_type: MemberType::Text,
                 mask: Mask::Include,
                 for_match: false,
+                fact_column: None,
             },
             CutSql {
                 foreign_key: "".into(),
@@ -101,6 +143,7 @@ mod test {
                 member_type: MemberType::NonText,
                 mask: Mask::Include,
                 for_match: false,
+                fact_column: None,
             },
         ];
 
@@ -114,6 +157,65 @@ mod test {
         );
     }
 
+    #[test]
+    fn cutsql_hostile_members() {
+        let cut = |members: Vec<&str>, member_type| CutSql {
+            foreign_key: "".into(),
+            primary_key: "".into(),
+            inline_table: None,
+            table: Table { name: "".into(), schema: None, primary_key: None },
+            column: "geo".into(),
+            members: members.into_iter().map(|m| m.to_owned()).collect(),
+            member_type,
+            mask: Mask::Include,
+            for_match: false,
+            fact_column: None,
+        };
+
+        assert_eq!(
+            cut(vec!["O'Brien", "x') or 1=1 --", "\\' or 1=1 --"], MemberType::Text).members_string(),
+            r#"'O''Brien', 'x'') or 1=1 --', '\\'' or 1=1 --'"#,
+        );
+        assert_eq!(
+            cut(vec!["O'Brien"], MemberType::Text).members_string_escaped(Escaping::Standard),
+            "'O''Brien'",
+        );
+        // non-numeric members of nontext levels get quoted instead of
+        // being written as is
+        assert_eq!(
+            cut(vec!["3", "-1.5", "1 or 1=1"], MemberType::NonText).members_string(),
+            "3, -1.5, '1 or 1=1'",
+        );
+
+        let mut like_cut = cut(vec!["it's"], MemberType::Text);
+        like_cut.for_match = true;
+        assert_eq!(
+            cut_sql_string(&like_cut),
+            "(geo like '%it''s%')",
+        );
+
+        let mut like_cut = cut(vec!["50%", "a_b"], MemberType::Text);
+        like_cut.for_match = true;
+        assert_eq!(
+            cut_sql_string(&like_cut),
+            r#"(geo like '%50\\%%' or geo like '%a\\_b%')"#,
+        );
+    }
+
+    #[test]
+    fn quoted_table_names() {
+        let table = Table { name: "sales; drop table x".into(), schema: Some("my`db".into()), primary_key: None };
+        assert_eq!(table.full_name(), "`my``db`.`sales; drop table x`");
+
+        let table = Table { name: "sales".into(), schema: Some("db".into()), primary_key: None };
+        assert_eq!(table.full_name(), "db.sales");
+
+        let table = Table { name: "db.sales 2019".into(), schema: None, primary_key: None };
+        assert_eq!(table.full_name(), "db.`sales 2019`");
+
+        assert_eq!(quote_identifier("weird\"name", Escaping::Standard), "\"weird\"\"name\"");
+    }
+
     // TODO move this to better place?
     // Should all of these internal checks be moved to one place? Is this an ok place?
     #[test]
@@ -143,5 +245,325 @@ mod test {
         );
     }
 
+    #[test]
+    fn drilldown_key_only_skips_join() {
+        let table = TableSql { name: "sales".into(), primary_key: None };
+        let drill = DrilldownSql {
+            alias_postfix: "0".into(),
+            foreign_key: "product_id".into(),
+            primary_key: "id".into(),
+            inline_table: None,
+            table: Table { name: "dim_products".into(), schema: None, primary_key: None },
+            level_columns: vec![
+                LevelColumn {
+                    key_column: "id".into(),
+                    name_column: None,
+                },
+            ],
+            property_columns: vec![],
+        };
+        let meas = vec![
+            MeasureSql { aggregator: Aggregator::Sum, column: "quantity".into(), filter: None },
+        ];
+
+        let (sql, drill_cols) = primary_agg(&table, &[], &[drill.clone()], &meas, None);
+        assert_eq!(drill_cols, "id_0");
+        assert!(!sql.contains("join"));
+        assert!(sql.contains("select product_id as id_0"));
+
+        // a name column still needs the dim table
+        let mut named_drill = drill;
+        named_drill.level_columns[0].name_column = Some("name".into());
+
+        let (sql, _) = primary_agg(&table, &[], &[named_drill], &meas, None);
+        assert!(sql.contains("all inner join"));
+    }
+
+    #[test]
+    fn drilldown_on_key_expression() {
+        // e.g. a generated time level, read from the fact table
+        let table = TableSql { name: "sales".into(), primary_key: None };
+        let drill = DrilldownSql {
+            alias_postfix: "Date".into(),
+            foreign_key: "sale_date".into(),
+            primary_key: "sale_date".into(),
+            inline_table: None,
+            table: Table { name: "sales".into(), schema: None, primary_key: None },
+            level_columns: vec![
+                LevelColumn {
+                    key_column: "toYYYYMM(sale_date)".into(),
+                    name_column: None,
+                },
+            ],
+            property_columns: vec![],
+        };
+        let meas = vec![
+            MeasureSql { aggregator: Aggregator::Sum, column: "quantity".into(), filter: None },
+        ];
+
+        let (sql, drill_cols) = primary_agg(&table, &[], &[drill], &meas, None);
+        assert_eq!(drill_cols, "toYYYYMM_sale_date__Date");
+        assert!(sql.contains("select toYYYYMM(sale_date) as toYYYYMM_sale_date__Date"));
+    }
+
+    #[test]
+    fn cut_pushed_into_dim_subquery() {
+        let table = TableSql { name: "sales".into(), primary_key: None };
+        let dim_table = Table { name: "dim_products".into(), schema: None, primary_key: None };
+        let drill = DrilldownSql {
+            alias_postfix: "0".into(),
+            foreign_key: "product_id".into(),
+            primary_key: "id".into(),
+            inline_table: None,
+            table: dim_table.clone(),
+            level_columns: vec![
+                LevelColumn {
+                    key_column: "id".into(),
+                    name_column: Some("name".into()),
+                },
+            ],
+            property_columns: vec![],
+        };
+        let cut = CutSql {
+            foreign_key: "product_id".into(),
+            primary_key: "id".into(),
+            inline_table: None,
+            table: dim_table,
+            column: "id".into(),
+            members: vec!["1".into(), "2".into()],
+            member_type: MemberType::NonText,
+            mask: Mask::Include,
+            for_match: false,
+            fact_column: None,
+        };
+        let meas = vec![
+            MeasureSql { aggregator: Aggregator::Sum, column: "quantity".into(), filter: None },
+        ];
+
+        let (sql, _) = primary_agg(&table, &[cut], &[drill], &meas, None);
+        assert!(sql.contains("as product_id from dim_products where id in (1, 2)"));
+        assert!(sql.contains("where product_id in (select id from dim_products where id in (1, 2))"));
+    }
+
+    #[test]
+    fn cut_on_fact_column() {
+        let table = TableSql { name: "sales".into(), primary_key: None };
+        let dim_table = Table { name: "dim_date".into(), schema: None, primary_key: None };
+        let drill = DrilldownSql {
+            alias_postfix: "0".into(),
+            foreign_key: "date_id".into(),
+            primary_key: "date_id".into(),
+            inline_table: None,
+            table: dim_table.clone(),
+            level_columns: vec![
+                LevelColumn {
+                    key_column: "year".into(),
+                    name_column: None,
+                },
+            ],
+            property_columns: vec![],
+        };
+        let cut = CutSql {
+            foreign_key: "date_id".into(),
+            primary_key: "date_id".into(),
+            inline_table: None,
+            table: dim_table,
+            column: "year".into(),
+            members: vec!["2018".into()],
+            member_type: MemberType::NonText,
+            mask: Mask::Include,
+            for_match: false,
+            fact_column: Some("toYear(date)".into()),
+        };
+        let meas = vec![
+            MeasureSql { aggregator: Aggregator::Sum, column: "quantity".into(), filter: None },
+        ];
+
+        let (sql, _) = primary_agg(&table, &[cut], &[drill], &meas, None);
+        assert!(sql.contains("where date_id in (select date_id from dim_date where year in (2018)) and toYear(date) in (2018)"));
+    }
+
+    #[test]
+    fn primary_agg_ctes_golden() {
+        let table = TableSql { name: "sales".into(), primary_key: None };
+        let dim_table = Table { name: "dim_products".into(), schema: None, primary_key: None };
+        let drills = vec![
+            DrilldownSql {
+                alias_postfix: "0".into(),
+                foreign_key: "year".into(),
+                primary_key: "year".into(),
+                inline_table: None,
+                table: Table { name: "sales".into(), schema: None, primary_key: None },
+                level_columns: vec![
+                    LevelColumn {
+                        key_column: "year".into(),
+                        name_column: None,
+                    },
+                ],
+                property_columns: vec![],
+            },
+            DrilldownSql {
+                alias_postfix: "1".into(),
+                foreign_key: "product_id".into(),
+                primary_key: "id".into(),
+                inline_table: None,
+                table: dim_table.clone(),
+                level_columns: vec![
+                    LevelColumn {
+                        key_column: "id".into(),
+                        name_column: Some("name".into()),
+                    },
+                ],
+                property_columns: vec![],
+            },
+        ];
+        let cuts = vec![
+            CutSql {
+                foreign_key: "product_id".into(),
+                primary_key: "id".into(),
+                inline_table: None,
+                table: dim_table,
+                column: "id".into(),
+                members: vec!["1".into(), "2".into()],
+                member_type: MemberType::NonText,
+                mask: Mask::Include,
+                for_match: false,
+                fact_column: None,
+            },
+        ];
+        let meas = vec![
+            MeasureSql { aggregator: Aggregator::Sum, column: "quantity".into(), filter: None },
+        ];
+
+        let (sql, _) = primary_agg(&table, &cuts, &drills, &meas, None);
+        assert_eq!(sql, include_str!("sql/golden/primary_agg_ctes.sql").trim());
+    }
+
+    #[test]
+    fn ratio_to_parent_window() {
+        let ratio = RatioToParentSql {
+            partition_columns: vec!["state_id_Geography".into(), "state_name_Geography".into()],
+            mea: "final_m0".into(),
+        };
+
+        assert_eq!(
+            ClickhouseDialect.ratio_to_parent("select 1".to_owned(), &ratio),
+            "select *, 1.0 * final_m0 / sum(final_m0) over (partition by state_id_Geography, state_name_Geography) as ratio_to_parent from (select 1) as ratio_base"
+        );
+    }
+
+    #[test]
+    fn golden_fixtures() {
+        tesseract_test::golden::assert_fixtures(
+            &ClickhouseDialect,
+            concat!(env!("CARGO_MANIFEST_DIR"), "/src/sql/golden"),
+        );
+    }
+
+    #[test]
+    fn limit_pushed_into_final_agg() {
+        let mut query_ir = QueryIr {
+            table: TableSql { name: "sales".into(), primary_key: None },
+            cuts: vec![],
+            drills: vec![
+                DrilldownSql {
+                    alias_postfix: "0".into(),
+                    foreign_key: "year".into(),
+                    primary_key: "year".into(),
+                    inline_table: None,
+                    table: Table { name: "sales".into(), schema: None, primary_key: None },
+                    level_columns: vec![
+                        LevelColumn {
+                            key_column: "year".into(),
+                            name_column: None,
+                        },
+                    ],
+                    property_columns: vec![],
+                },
+            ],
+            meas: vec![
+                MeasureSql { aggregator: Aggregator::Sum, column: "quantity".into(), filter: None },
+            ],
+            hidden_drills: vec![],
+            filters: vec![],
+            top: None,
+            top_where: None,
+            sort: None,
+            limit: Some(LimitSql { offset: None, n: 10 }),
+            rca: None,
+            growth: None,
+            rate: None,
+            ratio_to_parent: None,
+            sparse: false,
+        };
+
+        let sql = clickhouse_sql(&query_ir);
+        assert!(!sql.starts_with("select * from"));
+        assert!(sql.ends_with("group by year_0 order by year_0 limit 10"));
+
+        // a post-calculation still needs the outer query
+        query_ir.sparse = true;
+
+        let sql = clickhouse_sql(&query_ir);
+        assert!(sql.starts_with("select * from"));
+        assert!(sql.ends_with("order by year_0 limit 10"));
+    }
+
+    #[test]
+    fn top_with_other_row() {
+        let drill = |column: &str, postfix: &str| DrilldownSql {
+            alias_postfix: postfix.into(),
+            foreign_key: column.into(),
+            primary_key: column.into(),
+            inline_table: None,
+            table: Table { name: "sales".into(), schema: None, primary_key: None },
+            level_columns: vec![
+                LevelColumn {
+                    key_column: column.into(),
+                    name_column: None,
+                },
+            ],
+            property_columns: vec![],
+        };
+
+        let query_ir = QueryIr {
+            table: TableSql { name: "sales".into(), primary_key: None },
+            cuts: vec![],
+            drills: vec![drill("year", "0"), drill("product", "1")],
+            meas: vec![
+                MeasureSql { aggregator: Aggregator::Sum, column: "quantity".into(), filter: None },
+            ],
+            hidden_drills: vec![],
+            filters: vec![],
+            top: Some(TopSql {
+                n: 2,
+                by_column: "year_0".into(),
+                sort_columns: vec!["final_m0".into()],
+                sort_direction: SortDirection::Desc,
+                other: Some(TopOtherSql {
+                    group_columns: vec!["year_0".into()],
+                    drill_columns: vec!["year_0".into(), "'Other' as product_1".into()],
+                }),
+            }),
+            top_where: None,
+            sort: None,
+            limit: None,
+            rca: None,
+            growth: None,
+            rate: None,
+            ratio_to_parent: None,
+            sparse: false,
+        };
+
+        let sql = clickhouse_sql(&query_ir);
+        assert!(sql.contains(
+            "order by final_m0 desc limit 2 by year_0 \
+            union all select year_0, 'Other' as product_1, sum(other_m0) as final_m0 \
+            from (select year_0, final_m0 as other_m0 from ("
+        ));
+        assert!(sql.contains("order by final_m0 desc limit 2, 18446744073709551615 by year_0) group by year_0"));
+        assert!(sql.ends_with("order by year_0 asc, final_m0 desc "));
+    }
+
 }
 
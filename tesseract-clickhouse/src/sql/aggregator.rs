@@ -98,6 +98,21 @@ pub fn agg_sql_string_pass_1(col: &str, aggregator: &Aggregator, mea_idx: usize)
     }
 }
 
+/// First pass for a measure with a `filter`, using the `-If` combinator so
+/// only the fact rows matching the condition are aggregated.
+///
+/// Schema validation only allows filters on the basic aggregators.
+pub fn agg_sql_string_pass_1_if(col: &str, aggregator: &Aggregator, filter: &str, mea_idx: usize) -> String {
+    match aggregator {
+        Aggregator::Sum => format!("sumIf({}, {}) as m{}", col, filter, mea_idx),
+        Aggregator::Count => format!("countIf({}, {}) as m{}", col, filter, mea_idx),
+        Aggregator::Average => format!("avgIf({}, {}) as m{}", col, filter, mea_idx),
+        Aggregator::Max => format!("maxIf({}, {}) as m{}", col, filter, mea_idx),
+        Aggregator::Min => format!("minIf({}, {}) as m{}", col, filter, mea_idx),
+        _ => agg_sql_string_pass_1(col, aggregator, mea_idx),
+    }
+}
+
 // this is used to select mea cols as they bubble up from the fact subquery through
 // each subquery join
 pub fn agg_sql_string_select_mea(aggregator: &Aggregator, mea_idx: usize) -> String {
@@ -244,6 +259,23 @@ mod test {
         );
     }
 
+    #[test]
+    fn filtered_aggs() {
+        assert_eq!(
+            agg_sql_string_pass_1_if("col_1".into(), &Aggregator::Sum, "flag = 1", 0),
+            "sumIf(col_1, flag = 1) as m0".to_owned(),
+        );
+        assert_eq!(
+            agg_sql_string_pass_1_if("col_1".into(), &Aggregator::Count, "flag = 1", 1),
+            "countIf(col_1, flag = 1) as m1".to_owned(),
+        );
+        // second pass is unchanged, the rows are already filtered
+        assert_eq!(
+            agg_sql_string_pass_2(&Aggregator::Sum, 0),
+            "sum(m0) as final_m0".to_owned(),
+        );
+    }
+
     #[test]
     fn max_agg() {
         assert_eq!(
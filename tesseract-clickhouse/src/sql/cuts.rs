@@ -1,10 +1,21 @@
-use super::CutSql;
+use tesseract_core::SqlDialect;
+
+use super::{CutSql, ClickhouseDialect};
 
 pub fn cut_sql_string(cut: &CutSql) -> String {
-    if cut.for_match {
-        format!("{}", cut.members_like_string())
-    } else {
-        // col not in ('', '',...)
-        format!("{} {} ({})", cut.column, cut.mask_sql_in_string(), cut.members_string())
+    // col not in ('', '',...)
+    ClickhouseDialect.cut(cut)
+}
+
+/// Cut applied directly on the fact table, for levels that declare their
+/// fact column (so the database can prune partitions).
+pub fn fact_cut_sql_string(cut: &CutSql) -> Option<String> {
+    if cut.for_match || cut.members.is_empty() {
+        return None;
     }
+
+    cut.fact_column.as_ref()
+        .map(|fact_column| {
+            format!("{} {} ({})", fact_column, cut.mask_sql_in_string(), cut.members_string())
+        })
 }
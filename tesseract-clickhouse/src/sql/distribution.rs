@@ -0,0 +1,35 @@
+/// Wraps an aggregate query with a single measure (`final_m0`) into a
+/// histogram of the measure over the query's rows: `buckets` buckets of
+/// equal width between the smallest and largest value.
+///
+/// Returns one row per bucket, including empty ones, with the columns
+/// `bucket` (from 0), `lower`, `upper` and `count`. The largest value goes
+/// in the last bucket, and if all values are the same they all go in the
+/// first one.
+pub fn distribution_sql(agg_sql: &str, buckets: u32) -> String {
+    format!("select b as bucket, \
+        lo + (hi - lo) * b / {n} as lower, \
+        lo + (hi - lo) * (b + 1) / {n} as upper, \
+        arrayCount(v -> if(hi = lo, 0, least(toUInt64(floor((v - lo) * {n} / (hi - lo))), {last})) = b, vs) as count \
+        from (select groupArray(final_m0) as vs, min(final_m0) as lo, max(final_m0) as hi from ({sql})) \
+        array join range({n}) as b",
+        n=buckets,
+        last=buckets.saturating_sub(1),
+        sql=agg_sql,
+    )
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_distribution_sql() {
+        let sql = distribution_sql("select year_0, sum(m0) as final_m0 from fact group by year_0", 10);
+
+        assert!(sql.starts_with("select b as bucket, lo + (hi - lo) * b / 10 as lower"));
+        assert!(sql.contains("least(toUInt64(floor((v - lo) * 10 / (hi - lo))), 9)"));
+        assert!(sql.ends_with("from (select year_0, sum(m0) as final_m0 from fact group by year_0)) array join range(10) as b"));
+    }
+}
@@ -0,0 +1 @@
+select * from (with fact as (select product_id, sum(quantity) as m0, avg(price) as m1 from sales where year in (2018) group by product_id), dim_0 as (select product_id as product_id_Product, product_label as product_label_Product, product_id as product_id from dim_products), join_0 as (select product_id, product_id_Product, product_label_Product, m0, m1 from dim_0 all inner join fact using product_id) select product_id_Product, product_label_Product, sum(m0) as final_m0, avg(m1) as final_m1 from join_0 group by product_id_Product, product_label_Product)  order by product_id_Product, product_label_Product
@@ -0,0 +1 @@
+select * from (select state_id_Geography, state_name_Geography, final_m0, sum(final_m0_rate) / avg(final_m0) as rate from (select state_id_Geography, state_name_Geography, product_group_id_Product_group, product_group_label_Product_group, final_m0_agg as final_m0, final_m0_rate from (select state_id_Geography, state_name_Geography, sum(final_m0) as final_m0_agg, groupArray(final_m0) as final_m0_rate, groupArray(product_group_id_Product) as product_group_id_Product_group, groupArray(product_group_label_Product) as product_group_label_Product_group from (with fact as (select product_id, customer_id, sum(quantity) as m0 from sales group by product_id, customer_id), dim_0 as (select product_group_id as product_group_id_Product, product_group_label as product_group_label_Product, product_id as product_id from dim_products), join_0 as (select product_id, customer_id, product_group_id_Product, product_group_label_Product, m0 from dim_0 all inner join fact using product_id), dim_1 as (select state_id as state_id_Geography, state_name as state_name_Geography, customer_id as customer_id from customer_geo), join_1 as (select product_id, customer_id, product_group_id_Product, product_group_label_Product, state_id_Geography, state_name_Geography, m0 from dim_1 all inner join join_0 using customer_id) select state_id_Geography, state_name_Geography, product_group_id_Product, product_group_label_Product, sum(m0) as final_m0 from join_1 group by state_id_Geography, state_name_Geography, product_group_id_Product, product_group_label_Product) group by state_id_Geography, state_name_Geography) array join product_group_id_Product_group as product_group_id_Product_group, product_group_label_Product_group as product_group_label_Product_group, final_m0_rate as final_m0_rate) where product_group_id_Product_group in (1) group by state_id_Geography, state_name_Geography, final_m0) where rate > 0.5 order by rate desc, state_id_Geography, state_name_Geography
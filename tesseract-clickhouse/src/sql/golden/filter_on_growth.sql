@@ -0,0 +1 @@
+select * from (select  state_id_Geography, state_name_Geography, final_times_0,  final_m, (final_m - final_m_diff) > 0?(final_m_diff / (final_m - final_m_diff)):(final_m_diff / 0) as growth, final_m_diff from (with groupArray(year_Year) as times_0,  groupArray(final_m0) as all_m_in_group, arrayEnumerate(all_m_in_group) as all_m_in_group_ids, arrayMap( i -> i > 1 ? all_m_in_group[i] - all_m_in_group[i-1]: 0, all_m_in_group_ids) as m_diff select  state_id_Geography, state_name_Geography,  times_0, all_m_in_group, m_diff from (with fact as (select year as year_Year, customer_id, sum(quantity) as m0 from sales group by year_Year, customer_id), dim_0 as (select state_id as state_id_Geography, state_name as state_name_Geography, customer_id as customer_id from customer_geo), join_0 as (select year_Year, customer_id, state_id_Geography, state_name_Geography, m0 from dim_0 all inner join fact using customer_id) select year_Year, state_id_Geography, state_name_Geography, sum(m0) as final_m0 from join_0 group by year_Year, state_id_Geography, state_name_Geography order by year_Year ) group by  state_id_Geography, state_name_Geography ) array Join m_diff as final_m_diff, all_m_in_group as final_m, times_0 as final_times_0 ) where growth >= 0.1 order by  state_id_Geography, state_name_Geography, final_times_0,  final_m, growth
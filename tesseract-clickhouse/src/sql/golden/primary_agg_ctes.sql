@@ -0,0 +1 @@
+with fact as (select year as year_0, product_id, sum(quantity) as m0 from sales where product_id in (select id from dim_products where id in (1, 2)) group by year_0, product_id), dim_0 as (select id as id_1, name as name_1, id as product_id from dim_products where id in (1, 2)), join_0 as (select year_0, product_id, id_1, name_1, m0 from dim_0 all inner join fact using product_id) select year_0, id_1, name_1, sum(m0) as final_m0 from join_0 group by year_0, id_1, name_1
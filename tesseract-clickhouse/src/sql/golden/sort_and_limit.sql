@@ -0,0 +1 @@
+with fact as (select year as year_Year, sum(quantity) as m0 from sales group by year_Year) select year_Year, sum(m0) as final_m0 from fact group by year_Year order by final_m0 desc, year_Year limit 5
@@ -26,14 +26,44 @@ pub fn wrap_options(
     // Now that final groupings are done, do wrapping options
     // like top, filter, sort
     if let Some(top) = top {
-        final_sql = format!("select * from ({}) {} order by {} {} limit {} by {}",
+        let top_where_sql = if let Some(tw) = top_where { format!("where {} {}", tw.by_column, tw.constraint.sql_string()) } else { "".into() };
+
+        let top_sql = format!("select * from ({}) {} order by {} {} limit {} by {}",
             final_sql,
-            if let Some(tw) = top_where { format!("where {} {}", tw.by_column, tw.constraint.sql_string()) } else { "".into() },
+            top_where_sql,
             join(&top.sort_columns, ", "),
             top.sort_direction.sql_string(),
             top.n,
             top.by_column,
         );
+
+        final_sql = if let Some(ref other) = top.other {
+            // the rows past the top n of each partition, rolled up. Measures
+            // are renamed so that the sums can take their names.
+            let rest_mea_cols = (0..num_measures).map(|i| format!("final_m{i} as other_m{i}", i=i));
+            let rest_sql = format!("select {}, {} from ({}) {} order by {} {} limit {}, 18446744073709551615 by {}",
+                join(&other.group_columns, ", "),
+                join(rest_mea_cols, ", "),
+                final_sql,
+                top_where_sql,
+                join(&top.sort_columns, ", "),
+                top.sort_direction.sql_string(),
+                top.n,
+                top.by_column,
+            );
+
+            let other_mea_cols = (0..num_measures).map(|i| format!("sum(other_m{i}) as final_m{i}", i=i));
+            let other_sql = format!("select {}, {} from ({}) group by {}",
+                join(&other.drill_columns, ", "),
+                join(other_mea_cols, ", "),
+                rest_sql,
+                join(&other.group_columns, ", "),
+            );
+
+            format!("{} union all {}", top_sql, other_sql)
+        } else {
+            top_sql
+        };
     }
 
     // There's a final wrapper clause no matter what.
@@ -94,6 +124,16 @@ pub fn wrap_options(
         }
     }
 
+    // With a limit and no post-calculations, the sort and limit go straight
+    // onto the final aggregation, instead of an outer query, so that the
+    // database can stop early.
+    if limit.is_some() && can_push_down_limit(query_ir) {
+        return format!("{} {} {}",
+            final_sql,
+            sort_sql,
+            limit_sql,
+        );
+    }
 
     final_sql = format!("select * from ({}) {} {} {}",
         final_sql,
@@ -105,3 +145,14 @@ pub fn wrap_options(
     final_sql
 }
 
+/// The final sql can only take a sort and limit directly if nothing else
+/// wraps it.
+fn can_push_down_limit(query_ir: &QueryIr) -> bool {
+    query_ir.top.is_none() &&
+        query_ir.rca.is_none() &&
+        query_ir.growth.is_none() &&
+        query_ir.rate.is_none() &&
+        query_ir.filters.is_empty() &&
+        !query_ir.sparse
+}
+
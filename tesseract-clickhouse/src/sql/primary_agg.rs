@@ -2,10 +2,12 @@ use itertools::join;
 
 use super::aggregator::{
     agg_sql_string_pass_1,
+    agg_sql_string_pass_1_if,
     agg_sql_string_pass_2,
     agg_sql_string_select_mea,
 };
-use super::cuts::cut_sql_string;
+use super::cuts::{cut_sql_string, fact_cut_sql_string};
+use tesseract_core::query_ir::{Escaping, column_alias, quote_identifier};
 use super::{
     TableSql,
     CutSql,
@@ -50,6 +52,15 @@ pub fn primary_agg(
                 d.table.name != table.name
             }
         })
+        .filter(|d| fact_key_drill_cols(d).is_none())
+        .collect();
+
+    // Drills that only need the level key, when the key is the fact table's
+    // foreign key, are read straight from the fact table instead of joining
+    // the dim table.
+    let fact_key_drills: Vec<_> = drills.iter()
+        .filter(|d| d.table.name != table.name || d.inline_table.is_some())
+        .filter_map(fact_key_drill_cols)
         .collect();
 
     let ext_cuts: Vec<_> = cuts.iter()
@@ -76,26 +87,24 @@ pub fn primary_agg(
     // external drill and cuts section
 
     while let Some(drill) = ext_drills.pop() {
-        // TODO can this be removed?
-//        if let Some(idx) = ext_cuts.iter().position(|c| c.table == drill.table) { // TODO bug here, can't just match on table
-//            let cut = ext_cuts.swap_remove(idx);
-//
-//            dim_subqueries.push(
-//                dim_subquery(Some(drill),Some(cut))
-//            );
-//        } else {
-            dim_subqueries.push(
-                dim_subquery(Some(drill), None)
-            );
-//        }
+        let mut subquery = dim_subquery(Some(drill), None, Escaping::Backslash);
+
+        // Cuts are applied on the fact table scan below, but are also applied
+        // in the matching dim subquery so that the whole dim table doesn't
+        // need to be read for the join.
+        let drill_cut_clause = join(
+            ext_cuts.iter()
+                .filter(|c| cut_matches_drill(c, drill))
+                .map(|c| cut_sql_string(c)),
+            " and "
+        );
+
+        if !drill_cut_clause.is_empty() {
+            subquery.sql.push_str(&format!(" where {}", drill_cut_clause));
+        }
+
+        dim_subqueries.push(subquery);
     }
-    // TODO can this be removed entirely?
-//
-//    for cut in &ext_cuts {
-//        dim_subqueries.push(
-//            dim_subquery(None, Some(cut))
-//        );
-//    }
 
     if let Some(ref primary_key) = table.primary_key {
         if let Some(idx) = dim_subqueries.iter().position(|d| d.foreign_key == *primary_key) {
@@ -117,13 +126,18 @@ pub fn primary_agg(
         .enumerate()
         .map(|(i, m)| {
             // should return "m.aggregator({m.col}) as m{i}" for simple cases
-            agg_sql_string_pass_1(&m.column, &m.aggregator, i)
+            match m.filter {
+                Some(ref filter) => agg_sql_string_pass_1_if(&m.column, &m.aggregator, filter, i),
+                None => agg_sql_string_pass_1(&m.column, &m.aggregator, i),
+            }
         }
         );
     let mea_cols = join(mea_cols, ", ");
 
-    let inline_dim_cols = inline_drills.iter().map(|d| d.col_alias_string());
-    let inline_dim_aliass = inline_drills.iter().map(|d| d.col_alias_only_string());
+    let inline_dim_cols = inline_drills.iter().map(|d| d.col_alias_string())
+        .chain(fact_key_drills.iter().map(|(col, _)| col.clone()));
+    let inline_dim_aliass = inline_drills.iter().map(|d| d.col_alias_only_string())
+        .chain(fact_key_drills.iter().map(|(_, alias)| alias.clone()));
 
     let dim_idx_cols = dim_subqueries.iter().map(|d| d.foreign_key.clone());
 
@@ -141,7 +155,7 @@ pub fn primary_agg(
         fact_sql.push_str(&format!(", {}", hidden_dim_cols));
     }
 
-    fact_sql.push_str(&format!(", {} from {}", mea_cols, table.name));
+    fact_sql.push_str(&format!(", {} from {}", mea_cols, quote_identifier(&table.name, Escaping::Backslash)));
 
     if (inline_cuts.len() > 0) || (ext_cuts_for_inline.len() > 0) {
         let inline_cut_clause = inline_cuts
@@ -159,7 +173,7 @@ pub fn primary_agg(
                     None => c.table.full_name()
                 };
 
-                if c.members.is_empty() {
+                let dim_cut = if c.members.is_empty() {
                     // this case is for default hierarchy
                     // in multiple hierarchies
                     format!("{} in (select {} from {})",
@@ -174,6 +188,13 @@ pub fn primary_agg(
                         cut_table,
                         cut_sql_string(&c),
                     )
+                };
+
+                // also cut directly on the fact table's column when declared,
+                // so that partitions can be pruned
+                match fact_cut_sql_string(&c) {
+                    Some(fact_cut) => format!("{} and {}", dim_cut, fact_cut),
+                    None => dim_cut,
                 }
             });
 
@@ -191,8 +212,13 @@ pub fn primary_agg(
 
     // Now second half, feed DimSubquery into the multiple joins with fact table
     // TODO allow for differently named cols to be joined on. (using an alias for as)
+    //
+    // Each step is a CTE with a stable alias: `fact` for the fact table scan,
+    // `dim_{i}` for each dim subquery, and `join_{i}` for the result of joining
+    // `dim_{i}` to the previous step.
 
-    let mut sub_queries = fact_sql;
+    let mut ctes = vec![format!("fact as ({})", fact_sql)];
+    let mut current_cte = "fact".to_owned();
 
     // initialize current dim cols with inline drills and idx cols (all dim cols)
     let mut current_dim_cols = vec![all_fact_dim_aliass];
@@ -208,7 +234,7 @@ pub fn primary_agg(
         });
     let select_mea_cols = join(select_mea_cols, ", ");
 
-    for dim_subquery in dim_subqueries {
+    for (i, dim_subquery) in dim_subqueries.into_iter().enumerate() {
         // This section needed to accumulate the dim cols that are being selected over
         // the recursive joins.
         if let Some(cols) = dim_subquery.dim_cols {
@@ -221,14 +247,19 @@ pub fn primary_agg(
             "".to_owned()
         };
 
-        // Now construct subquery
-        sub_queries = format!("select {}{} from ({}) all inner join ({}) using {}",
+        ctes.push(format!("dim_{} as ({})", i, dim_subquery.sql));
+
+        // Now construct join
+        ctes.push(format!("join_{} as (select {}{} from dim_{} all inner join {} using {})",
+            i,
             sub_queries_dim_cols,
             select_mea_cols,
-            dim_subquery.sql,
-            sub_queries,
+            i,
+            current_cte,
             dim_subquery.foreign_key
-        );
+        ));
+
+        current_cte = format!("join_{}", i);
     }
 
     // Finally, wrap with final agg and result
@@ -242,12 +273,44 @@ pub fn primary_agg(
     let final_mea_cols = join(final_mea_cols, ", ");
 
     // This is the final result of the groupings.
-    let final_sql = format!("select {}, {} from ({}) group by {}",
+    let final_sql = format!("with {} select {}, {} from {} group by {}",
+        join(ctes, ", "),
         final_drill_cols,
         final_mea_cols,
-        sub_queries,
+        current_cte,
         final_drill_cols,
     );
 
     (final_sql, final_drill_cols)
 }
+
+/// A cut matches a drilldown if it's on the same dim table, joined to the
+/// fact table on the same keys. Cuts without members (default hierarchy
+/// in multiple hierarchies) only restrict the fact table.
+fn cut_matches_drill(cut: &CutSql, drill: &DrilldownSql) -> bool {
+    !cut.members.is_empty() &&
+        cut.table == drill.table &&
+        cut.inline_table == drill.inline_table &&
+        cut.primary_key == drill.primary_key &&
+        cut.foreign_key == drill.foreign_key
+}
+
+/// If the drilldown only selects the level key, and that key is the one
+/// the dim table joins on, returns the fact table column (selecting the
+/// foreign key under the level key's alias) and its alias.
+fn fact_key_drill_cols(drill: &DrilldownSql) -> Option<(String, String)> {
+    if !drill.property_columns.is_empty() || drill.level_columns.len() != 1 {
+        return None;
+    }
+
+    let level_col = &drill.level_columns[0];
+
+    if level_col.name_column.is_some() || level_col.key_column != drill.primary_key {
+        return None;
+    }
+
+    let alias = format!("{}_{}", column_alias(&level_col.key_column), drill.alias_postfix);
+    let col = format!("{} as {}", drill.foreign_key, alias);
+
+    Some((col, alias))
+}
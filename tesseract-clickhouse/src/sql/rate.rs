@@ -11,6 +11,7 @@ use super::{
 use crate::sql::primary_agg::primary_agg;
 
 use tesseract_core::{Aggregator};
+use tesseract_core::query_ir::{Escaping, member_literal};
 
 
 pub fn rate_calculation(
@@ -81,12 +82,12 @@ pub fn rate_calculation(
     rate_sql = format!("{} final_m0_rate as final_m0_rate", rate_sql);
 
     // Final aggregation
-    rate_sql = format!("select {}, final_m0, {}(final_m0_rate) / avg(final_m0) from ({}) where {}_group in ({}) group by {}, final_m0",
+    rate_sql = format!("select {}, final_m0, {}(final_m0_rate) / avg(final_m0) as rate from ({}) where {}_group in ({}) group by {}, final_m0",
         original_drill_cols,
         rate_aggregator,
         rate_sql,
         rate_drill_cols[0],
-        join(rate.members.clone(), ", "),
+        join(rate.members.iter().map(|m| member_literal(m, Escaping::Backslash)), ", "),
         original_drill_cols
     );
 
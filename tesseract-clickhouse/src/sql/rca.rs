@@ -39,6 +39,7 @@
 use itertools::join;
 
 use crate::sql::primary_agg::primary_agg;
+use tesseract_core::query_ir::column_alias;
 use super::{
     TableSql,
     CutSql,
@@ -150,9 +151,9 @@ pub fn calculate(
             let alias_postfix = &d.alias_postfix;
             d.level_columns.iter().map(move |l| {
                 if let Some(ref name_col) = l.name_column {
-                    format!("groupArray({key_col}_{alias_postfix}) as {key_col}_{alias_postfix}_s, groupArray({name_col}_{alias_postfix}) as {name_col}_{alias_postfix}_s", key_col=l.key_column, name_col=name_col, alias_postfix=alias_postfix)
+                    format!("groupArray({key_col}_{alias_postfix}) as {key_col}_{alias_postfix}_s, groupArray({name_col}_{alias_postfix}) as {name_col}_{alias_postfix}_s", key_col=column_alias(&l.key_column), name_col=column_alias(name_col), alias_postfix=alias_postfix)
                 } else {
-                    format!("groupArray({col}_{alias_postfix}) as {col}_{alias_postfix}_s", col=l.key_column, alias_postfix=alias_postfix)
+                    format!("groupArray({col}_{alias_postfix}) as {col}_{alias_postfix}_s", col=column_alias(&l.key_column), alias_postfix=alias_postfix)
                 }
             })
         });
@@ -163,9 +164,9 @@ pub fn calculate(
             let alias_postfix = &d.alias_postfix;
             d.level_columns.iter().map(move |l| {
                 if let Some(ref name_col) = l.name_column {
-                    format!("{key_col}_{alias_postfix}_s as {key_col}_{alias_postfix}, {name_col}_{alias_postfix}_s as {name_col}_{alias_postfix}", key_col=l.key_column, name_col=name_col, alias_postfix=alias_postfix)
+                    format!("{key_col}_{alias_postfix}_s as {key_col}_{alias_postfix}, {name_col}_{alias_postfix}_s as {name_col}_{alias_postfix}", key_col=column_alias(&l.key_column), name_col=column_alias(name_col), alias_postfix=alias_postfix)
                 } else {
-                    format!("{col}_{alias_postfix}_s as {col}_{alias_postfix}", col=l.key_column, alias_postfix=alias_postfix)
+                    format!("{col}_{alias_postfix}_s as {col}_{alias_postfix}", col=column_alias(&l.key_column), alias_postfix=alias_postfix)
                 }
             })
         });
@@ -1,8 +1,9 @@
-use failure::Error;
+use failure::{Error, bail};
 use futures::{Future, Stream};
 
 use crate::dataframe::DataFrame;
-use crate::query_ir::QueryIr;
+use crate::query_ir::{Escaping, QueryIr};
+use crate::schema::TimeGrain;
 use crate::sql;
 
 
@@ -18,8 +19,35 @@ pub trait Backend {
         unimplemented!()
     }
 
+    /// Runs the database's EXPLAIN on a SQL string, and returns the output
+    /// as a DataFrame. `kind` selects the kind of explain for databases
+    /// that have more than one (e.g. `plan` or `pipeline` for ClickHouse).
+    fn explain_sql(&self, sql: String, _kind: Option<&str>) -> Box<Future<Item=DataFrame, Error=Error>> {
+        self.exec_sql(format!("explain {}", sql))
+    }
+
     fn box_clone(&self) -> Box<dyn Backend + Send + Sync>;
 
+    /// Wraps the sql of an aggregate query with a single measure into a
+    /// histogram of that measure over the query's rows, in `buckets` buckets
+    /// of equal width. Returns `bucket`, `lower`, `upper` and `count`
+    /// columns, one row per bucket. Not every backend supports it.
+    fn distribution_sql(&self, _sql: String, _buckets: u32) -> Result<String, Error> {
+        bail!("Distribution queries are not supported by this backend")
+    }
+
+    /// Sql for the key of a `generated_time` level, from the fact table's
+    /// date column. Defaults to standard sql (see `TimeGrain::standard_sql`).
+    fn time_grain_sql(&self, grain: &TimeGrain, column: &str) -> String {
+        grain.standard_sql(column)
+    }
+
+    /// How the sql from the default `generate_sql` quotes string literals
+    /// and identifiers. Defaults to standard sql.
+    fn escaping(&self) -> Escaping {
+        Escaping::Standard
+    }
+
     /// Receives an intermediate representation of the Query
     /// (the table, col, and relationship info needed for each drill,
     /// mea, cut, etc.) and generates a `String` of sql. Cannot error,
@@ -36,6 +64,7 @@ pub trait Backend {
             &query_ir.limit,
             &query_ir.rca,
             &query_ir.growth,
+            self.escaping(),
         )
     }
 }
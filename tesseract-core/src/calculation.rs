@@ -0,0 +1,457 @@
+//! Named calculations: formulas over measures, which can come from other
+//! cubes. They're computed after the queries for those measures return,
+//! by joining the results on their drilldown columns.
+
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use failure::{Error, bail, format_err};
+
+use crate::dataframe::{Column, ColumnData, DataFrame};
+use crate::names::Measure;
+use crate::query::Query;
+
+
+/// A measure used in a formula: `[Measure]` for a measure of the cube the
+/// calculation is queried on, or `[cube].[Measure]` for a measure of
+/// another cube.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct MeasureRef {
+    pub cube: Option<String>,
+    pub measure: String,
+}
+
+/// Arithmetic over measures: `+`, `-`, `*`, `/`, parentheses, numbers
+/// and measures in brackets, e.g. `[Income] / [acs_population].[Population]`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Formula {
+    Number(f64),
+    Measure(MeasureRef),
+    Neg(Box<Formula>),
+    Add(Box<Formula>, Box<Formula>),
+    Sub(Box<Formula>, Box<Formula>),
+    Mul(Box<Formula>, Box<Formula>),
+    Div(Box<Formula>, Box<Formula>),
+}
+
+impl Formula {
+    /// The measures used in the formula, in order of appearance.
+    pub fn measures(&self) -> Vec<&MeasureRef> {
+        match self {
+            Formula::Number(_) => vec![],
+            Formula::Measure(measure) => vec![measure],
+            Formula::Neg(f) => f.measures(),
+            Formula::Add(a, b) | Formula::Sub(a, b) | Formula::Mul(a, b) | Formula::Div(a, b) => {
+                let mut measures = a.measures();
+                measures.extend(b.measures());
+                measures
+            },
+        }
+    }
+
+    /// Drops the cube from references to measures of `cube`, the cube the
+    /// formula is queried on, so they're looked up like `[Measure]`.
+    pub fn localize(&mut self, cube: &str) {
+        match self {
+            Formula::Number(_) => (),
+            Formula::Measure(measure) => {
+                if measure.cube.as_ref().map(|c| c == cube).unwrap_or(false) {
+                    measure.cube = None;
+                }
+            },
+            Formula::Neg(f) => f.localize(cube),
+            Formula::Add(a, b) | Formula::Sub(a, b) | Formula::Mul(a, b) | Formula::Div(a, b) => {
+                a.localize(cube);
+                b.localize(cube);
+            },
+        }
+    }
+
+    /// Evaluates the formula with the measure values given by `value`.
+    /// Null if any value is null, or if the result isn't finite (e.g.
+    /// division by zero).
+    pub fn eval<F>(&self, value: &F) -> Option<f64>
+        where F: Fn(&MeasureRef) -> Option<f64>
+    {
+        let res = match self {
+            Formula::Number(n) => *n,
+            Formula::Measure(measure) => value(measure)?,
+            Formula::Neg(f) => -f.eval(value)?,
+            Formula::Add(a, b) => a.eval(value)? + b.eval(value)?,
+            Formula::Sub(a, b) => a.eval(value)? - b.eval(value)?,
+            Formula::Mul(a, b) => a.eval(value)? * b.eval(value)?,
+            Formula::Div(a, b) => a.eval(value)? / b.eval(value)?,
+        };
+
+        if res.is_finite() { Some(res) } else { None }
+    }
+}
+
+impl FromStr for Formula {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let tokens = tokenize(s)?;
+        let mut parser = FormulaParser { tokens: &tokens, pos: 0 };
+
+        let formula = parser.expr()
+            .map_err(|err| format_err!("{} in formula {}", err, s))?;
+
+        if parser.pos != tokens.len() {
+            bail!("Unexpected {} in formula {}", tokens[parser.pos], s);
+        }
+
+        Ok(formula)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(f64),
+    Name(String),
+    Dot,
+    Op(char),
+    Open,
+    Close,
+}
+
+impl std::fmt::Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Token::Number(n) => write!(f, "{}", n),
+            Token::Name(name) => write!(f, "[{}]", name),
+            Token::Dot => write!(f, "."),
+            Token::Op(op) => write!(f, "{}", op),
+            Token::Open => write!(f, "("),
+            Token::Close => write!(f, ")"),
+        }
+    }
+}
+
+fn tokenize(s: &str) -> Result<Vec<Token>, Error> {
+    let mut tokens = vec![];
+    let mut chars = s.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            ' ' | '\t' | '\n' => (),
+            '+' | '-' | '*' | '/' => tokens.push(Token::Op(c)),
+            '(' => tokens.push(Token::Open),
+            ')' => tokens.push(Token::Close),
+            '.' => tokens.push(Token::Dot),
+            '[' => {
+                let mut name = String::new();
+                loop {
+                    match chars.next() {
+                        Some(']') => break,
+                        Some(c) => name.push(c),
+                        None => bail!("Unclosed [ in formula {}", s),
+                    }
+                }
+                if name.is_empty() {
+                    bail!("Empty [] in formula {}", s);
+                }
+                tokens.push(Token::Name(name));
+            },
+            '0'..='9' => {
+                let mut number = c.to_string();
+                while let Some(&c) = chars.peek() {
+                    if !c.is_ascii_digit() && c != '.' {
+                        break;
+                    }
+                    number.push(c);
+                    chars.next();
+                }
+                let number = number.parse()
+                    .map_err(|_| format_err!("Bad number {} in formula {}", number, s))?;
+                tokens.push(Token::Number(number));
+            },
+            _ => bail!("Unexpected {} in formula {}", c, s),
+        }
+    }
+
+    Ok(tokens)
+}
+
+/// Recursive descent over:
+///
+/// ```text
+/// expr   = term (("+" | "-") term)*
+/// term   = factor (("*" | "/") factor)*
+/// factor = number | name ("." name)? | "(" expr ")" | "-" factor
+/// ```
+struct FormulaParser<'a> {
+    tokens: &'a [Token],
+    pos: usize,
+}
+
+impl<'a> FormulaParser<'a> {
+    fn next(&mut self) -> Option<&'a Token> {
+        let token = self.tokens.get(self.pos);
+        self.pos += 1;
+        token
+    }
+
+    fn peek(&self) -> Option<&'a Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn expr(&mut self) -> Result<Formula, Error> {
+        let mut formula = self.term()?;
+
+        while let Some(Token::Op(op)) = self.peek() {
+            let op = *op;
+            if op != '+' && op != '-' {
+                break;
+            }
+            self.pos += 1;
+
+            let rhs = self.term()?;
+            formula = if op == '+' {
+                Formula::Add(Box::new(formula), Box::new(rhs))
+            } else {
+                Formula::Sub(Box::new(formula), Box::new(rhs))
+            };
+        }
+
+        Ok(formula)
+    }
+
+    fn term(&mut self) -> Result<Formula, Error> {
+        let mut formula = self.factor()?;
+
+        while let Some(Token::Op(op)) = self.peek() {
+            let op = *op;
+            if op != '*' && op != '/' {
+                break;
+            }
+            self.pos += 1;
+
+            let rhs = self.factor()?;
+            formula = if op == '*' {
+                Formula::Mul(Box::new(formula), Box::new(rhs))
+            } else {
+                Formula::Div(Box::new(formula), Box::new(rhs))
+            };
+        }
+
+        Ok(formula)
+    }
+
+    fn factor(&mut self) -> Result<Formula, Error> {
+        match self.next() {
+            Some(Token::Number(n)) => Ok(Formula::Number(*n)),
+            Some(Token::Name(name)) => {
+                if self.peek() != Some(&Token::Dot) {
+                    return Ok(Formula::Measure(MeasureRef { cube: None, measure: name.clone() }));
+                }
+                self.pos += 1;
+
+                match self.next() {
+                    Some(Token::Name(measure)) => {
+                        Ok(Formula::Measure(MeasureRef { cube: Some(name.clone()), measure: measure.clone() }))
+                    },
+                    Some(token) => bail!("Expected a measure after [{}]., found {}", name, token),
+                    None => bail!("Expected a measure after [{}].", name),
+                }
+            },
+            Some(Token::Op('-')) => Ok(Formula::Neg(Box::new(self.factor()?))),
+            Some(Token::Open) => {
+                let formula = self.expr()?;
+                match self.next() {
+                    Some(Token::Close) => Ok(formula),
+                    _ => bail!("Unclosed ("),
+                }
+            },
+            Some(token) => bail!("Unexpected {}", token),
+            None => bail!("Unexpected end"),
+        }
+    }
+}
+
+
+/// How to answer a query asking for calculations among its measures (see
+/// `Schema::calculation_plan`): the queries to run, and how to combine
+/// their results.
+#[derive(Debug, Clone)]
+pub struct CalculationPlan {
+    /// The original query, with its calculations replaced by the cube's
+    /// measures they use.
+    pub query: Query,
+    /// Queries on other cubes, with the same drilldowns and cuts, for the
+    /// measures the calculations use from those cubes.
+    pub external: Vec<(String, Query)>,
+    /// Requested measures and calculations, in order.
+    pub(crate) columns: Vec<String>,
+    pub(crate) calculations: HashMap<String, Formula>,
+}
+
+impl CalculationPlan {
+    /// Computes the calculations from the (renamed) results of `query` and
+    /// of each `external` query, in the same order. External results are
+    /// joined on their drilldown columns, which must also be in `df`.
+    ///
+    /// Returns the drilldown columns of `df`, followed by the requested
+    /// measures and calculations; measures only used by calculations are
+    /// dropped.
+    pub fn apply(&self, df: DataFrame, external: Vec<DataFrame>) -> Result<DataFrame, Error> {
+        if external.len() != self.external.len() {
+            bail!("Expected results for {} external queries, got {}", self.external.len(), external.len());
+        }
+
+        let num_rows = df.len();
+        let measure_names: Vec<&str> = self.query.measures.iter().map(|m| m.0.as_str()).collect();
+
+        let mut values: HashMap<MeasureRef, Vec<Option<f64>>> = HashMap::new();
+        for col in &df.columns {
+            if measure_names.contains(&col.name.as_str()) {
+                let col_values = col.column_data.to_nullable_f64()
+                    .ok_or_else(|| format_err!("Measure {} is not numeric", col.name))?;
+                values.insert(MeasureRef { cube: None, measure: col.name.clone() }, col_values);
+            }
+        }
+
+        for ((cube, query), ext_df) in self.external.iter().zip(external) {
+            let ext_measures: Vec<&str> = query.measures.iter().map(|m| m.0.as_str()).collect();
+
+            // rows are joined on every column that isn't a measure
+            let mut df_keys: Vec<Vec<String>> = vec![];
+            let mut ext_keys: Vec<Vec<String>> = vec![];
+            for ext_col in ext_df.columns.iter().filter(|col| !ext_measures.contains(&col.name.as_str())) {
+                let col = df.columns.iter()
+                    .find(|col| col.name == ext_col.name)
+                    .ok_or_else(|| format_err!("Results from cube {} can't be joined on column {}", cube, ext_col.name))?;
+
+                df_keys.push(col.stringify_column_data());
+                ext_keys.push(ext_col.stringify_column_data());
+            }
+
+            let mut ext_rows: HashMap<Vec<&str>, usize> = HashMap::new();
+            for row in 0..ext_df.len() {
+                ext_rows.insert(ext_keys.iter().map(|k| k[row].as_str()).collect(), row);
+            }
+
+            let rows: Vec<Option<usize>> = (0..num_rows)
+                .map(|row| {
+                    let key: Vec<&str> = df_keys.iter().map(|k| k[row].as_str()).collect();
+                    ext_rows.get(&key).cloned()
+                })
+                .collect();
+
+            for col in ext_df.columns.iter().filter(|col| ext_measures.contains(&col.name.as_str())) {
+                let col_values = col.column_data.to_nullable_f64()
+                    .ok_or_else(|| format_err!("Measure {} of cube {} is not numeric", col.name, cube))?;
+                let col_values = rows.iter()
+                    .map(|&row| row.and_then(|row| col_values[row]))
+                    .collect();
+                values.insert(MeasureRef { cube: Some(cube.clone()), measure: col.name.clone() }, col_values);
+            }
+        }
+
+        let mut measure_cols: HashMap<String, Column> = HashMap::new();
+        let mut res = vec![];
+        for col in df.columns {
+            if measure_names.contains(&col.name.as_str()) {
+                measure_cols.insert(col.name.clone(), col);
+            } else {
+                res.push(col);
+            }
+        }
+
+        for name in &self.columns {
+            match self.calculations.get(name) {
+                Some(formula) => {
+                    let col_values = (0..num_rows)
+                        .map(|row| {
+                            formula.eval(&|measure| {
+                                values.get(measure).and_then(|v| v[row])
+                            })
+                        })
+                        .collect();
+                    res.push(Column::new(name.clone(), ColumnData::NullableFloat64(col_values)));
+                },
+                None => {
+                    let col = measure_cols.remove(name)
+                        .ok_or_else(|| format_err!("Missing measure {} in results", name))?;
+                    res.push(col);
+                },
+            }
+        }
+
+        Ok(DataFrame::from_vec(res))
+    }
+
+    /// Adds `measure` to `query` for a calculation, unless it's already there.
+    pub(crate) fn add_measure(query: &mut Query, measure: &str) {
+        if !query.measures.iter().any(|m| m.0 == measure) {
+            query.measures.push(Measure::new(measure));
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_formula() {
+        let formula: Formula = "([Income] - [Taxes]) / [acs].[Population] * 1000".parse().unwrap();
+
+        let measures: Vec<_> = formula.measures().into_iter().cloned().collect();
+        assert_eq!(measures, vec![
+            MeasureRef { cube: None, measure: "Income".into() },
+            MeasureRef { cube: None, measure: "Taxes".into() },
+            MeasureRef { cube: Some("acs".into()), measure: "Population".into() },
+        ]);
+
+        let value = |m: &MeasureRef| match m.measure.as_str() {
+            "Income" => Some(150.0),
+            "Taxes" => Some(50.0),
+            "Population" => Some(4.0),
+            _ => None,
+        };
+        assert_eq!(formula.eval(&value), Some(25000.0));
+
+        let formula: Formula = "-[Income] / ([Taxes] - 50)".parse().unwrap();
+        assert_eq!(formula.eval(&value), None);
+
+        assert!("[Income] +".parse::<Formula>().is_err());
+        assert!("[Income] [Taxes]".parse::<Formula>().is_err());
+        assert!("([Income]".parse::<Formula>().is_err());
+        assert!("[Income".parse::<Formula>().is_err());
+    }
+
+    #[test]
+    fn apply_plan() {
+        let mut query = Query::new();
+        query.measures = vec![Measure::new("Income")];
+
+        let mut ext_query = Query::new();
+        ext_query.measures = vec![Measure::new("Population")];
+
+        let mut calculations = HashMap::new();
+        calculations.insert("Income per Capita".to_owned(), "[Income] / [acs].[Population]".parse().unwrap());
+
+        let plan = CalculationPlan {
+            query,
+            external: vec![("acs".to_owned(), ext_query)],
+            columns: vec!["Income per Capita".to_owned()],
+            calculations,
+        };
+
+        let df = DataFrame::from_vec(vec![
+            Column::new("State".to_owned(), ColumnData::Text(vec!["CA".into(), "NY".into(), "TX".into()])),
+            Column::new("Income".to_owned(), ColumnData::UInt64(vec![100, 60, 30])),
+        ]);
+        let ext_df = DataFrame::from_vec(vec![
+            Column::new("State".to_owned(), ColumnData::Text(vec!["NY".into(), "CA".into()])),
+            Column::new("Population".to_owned(), ColumnData::UInt64(vec![3, 4])),
+        ]);
+
+        let res = plan.apply(df, vec![ext_df]).unwrap();
+
+        assert_eq!(res.column_names(), vec!["State", "Income per Capita"]);
+        assert_eq!(res.columns[1].stringify_column_data(), vec!["25", "20", ""]);
+    }
+}
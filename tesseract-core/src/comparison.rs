@@ -0,0 +1,169 @@
+//! Comparison of a query's measures between two sets of cuts (`compare`),
+//! e.g. sales in 2020 against sales in 2019, by product. The query is run
+//! once with each set of cuts, and the results are joined on their other
+//! columns; see `Schema::comparison_plan`.
+
+use failure::{Error, format_err};
+use std::collections::HashMap;
+
+use crate::dataframe::{Column, ColumnData, DataFrame};
+use crate::query::Query;
+
+
+/// How to answer a query with a `compare`: the two queries to run, and how
+/// to combine their results.
+#[derive(Debug, Clone)]
+pub struct ComparisonPlan {
+    /// The original query, with the first set of cuts added.
+    pub query_a: Query,
+    /// The original query, with the second set of cuts added.
+    pub query_b: Query,
+    pub(crate) measures: Vec<String>,
+}
+
+impl ComparisonPlan {
+    /// Joins the (renamed) results of `query_a` and `query_b` on every
+    /// column that isn't a measure. Rows found in only one of the results
+    /// are kept, with nulls for the other's values.
+    ///
+    /// Returns the joined columns, followed by four columns for each
+    /// measure `M`: `M Value A`, `M Value B`, `M Diff` (A minus B) and
+    /// `M Pct Diff` (the difference as a percentage of B, null when B is
+    /// zero).
+    pub fn apply(&self, a: DataFrame, b: DataFrame) -> Result<DataFrame, Error> {
+        let is_measure = |col: &Column| self.measures.contains(&col.name);
+
+        let mut a_keys: Vec<Vec<String>> = vec![];
+        let mut b_keys: Vec<Vec<String>> = vec![];
+        for a_col in a.columns.iter().filter(|col| !is_measure(col)) {
+            let b_col = b.columns.iter()
+                .find(|col| col.name == a_col.name)
+                .ok_or_else(|| format_err!("Results to compare can't be joined on column {}", a_col.name))?;
+
+            a_keys.push(a_col.stringify_column_data());
+            b_keys.push(b_col.stringify_column_data());
+        }
+
+        let mut b_rows: HashMap<Vec<&str>, usize> = HashMap::new();
+        for row in 0..b.len() {
+            b_rows.insert(b_keys.iter().map(|k| k[row].as_str()).collect(), row);
+        }
+
+        // rows of `a` in order, then the rows only in `b`
+        let mut rows_a: Vec<Option<usize>> = vec![];
+        let mut rows_b: Vec<Option<usize>> = vec![];
+        let mut b_matched = vec![false; b.len()];
+        for row in 0..a.len() {
+            let key: Vec<&str> = a_keys.iter().map(|k| k[row].as_str()).collect();
+            let b_row = b_rows.get(&key).cloned();
+            if let Some(b_row) = b_row {
+                b_matched[b_row] = true;
+            }
+            rows_a.push(Some(row));
+            rows_b.push(b_row);
+        }
+        let b_only: Vec<Option<usize>> = (0..b.len())
+            .filter(|&row| !b_matched[row])
+            .map(Some)
+            .collect();
+        for &row in &b_only {
+            rows_a.push(None);
+            rows_b.push(row);
+        }
+
+        let mut res = vec![];
+        for a_col in a.columns.iter().filter(|col| !is_measure(col)) {
+            let b_col = b.columns.iter()
+                .find(|col| col.name == a_col.name)
+                .ok_or_else(|| format_err!("Results to compare can't be joined on column {}", a_col.name))?;
+
+            let mut column_data = a_col.column_data.clone();
+            column_data.append(b_col.column_data.take(&b_only, false))?;
+            res.push(Column::new(a_col.name.clone(), column_data));
+        }
+
+        for measure in &self.measures {
+            let value_a = find_measure(&a, measure)?.column_data.take(&rows_a, false);
+            let value_b = find_measure(&b, measure)?.column_data.take(&rows_b, false);
+
+            let values_a = value_a.to_nullable_f64()
+                .ok_or_else(|| format_err!("Measure {} is not numeric", measure))?;
+            let values_b = value_b.to_nullable_f64()
+                .ok_or_else(|| format_err!("Measure {} is not numeric", measure))?;
+
+            let diff = values_a.iter().zip(&values_b)
+                .map(|(a, b)| match (a, b) {
+                    (Some(a), Some(b)) => Some(a - b),
+                    _ => None,
+                })
+                .collect();
+            let pct_diff = values_a.iter().zip(&values_b)
+                .map(|(a, b)| match (a, b) {
+                    (Some(a), Some(b)) if *b != 0.0 => Some((a - b) / b * 100.0),
+                    _ => None,
+                })
+                .collect();
+
+            res.push(Column::new(format!("{} Value A", measure), value_a));
+            res.push(Column::new(format!("{} Value B", measure), value_b));
+            res.push(Column::new(format!("{} Diff", measure), ColumnData::NullableFloat64(diff)));
+            res.push(Column::new(format!("{} Pct Diff", measure), ColumnData::NullableFloat64(pct_diff)));
+        }
+
+        Ok(DataFrame::from_vec(res))
+    }
+}
+
+fn find_measure<'a>(df: &'a DataFrame, measure: &str) -> Result<&'a Column, Error> {
+    df.columns.iter()
+        .find(|col| col.name == measure)
+        .ok_or_else(|| format_err!("Missing measure {} in results to compare", measure))
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn apply() {
+        let a = DataFrame::from_vec(vec![
+            Column::new("Product".into(), ColumnData::Text(vec!["Tea".into(), "Coffee".into(), "Cocoa".into()])),
+            Column::new("Quantity".into(), ColumnData::Int64(vec![110, 40, 5])),
+        ]);
+        let b = DataFrame::from_vec(vec![
+            Column::new("Product".into(), ColumnData::Text(vec!["Coffee".into(), "Tea".into(), "Juice".into()])),
+            Column::new("Quantity".into(), ColumnData::Int64(vec![0, 100, 7])),
+        ]);
+
+        let plan = ComparisonPlan {
+            query_a: Query::new(),
+            query_b: Query::new(),
+            measures: vec!["Quantity".into()],
+        };
+
+        let df = plan.apply(a, b).unwrap();
+
+        assert_eq!(
+            df.column_names(),
+            vec!["Product", "Quantity Value A", "Quantity Value B", "Quantity Diff", "Quantity Pct Diff"],
+        );
+        assert_eq!(df.columns[0].stringify_column_data(), vec!["Tea", "Coffee", "Cocoa", "Juice"]);
+        match df.columns[1].column_data {
+            ColumnData::NullableInt64(ref v) => assert_eq!(v, &vec![Some(110), Some(40), Some(5), None]),
+            ref col => panic!("unexpected column type {:?}", col),
+        }
+        match df.columns[2].column_data {
+            ColumnData::NullableInt64(ref v) => assert_eq!(v, &vec![Some(100), Some(0), None, Some(7)]),
+            ref col => panic!("unexpected column type {:?}", col),
+        }
+        match df.columns[3].column_data {
+            ColumnData::NullableFloat64(ref v) => assert_eq!(v, &vec![Some(10.0), Some(40.0), None, None]),
+            ref col => panic!("unexpected column type {:?}", col),
+        }
+        match df.columns[4].column_data {
+            ColumnData::NullableFloat64(ref v) => assert_eq!(v, &vec![Some(10.0), None, None, None]),
+            ref col => panic!("unexpected column type {:?}", col),
+        }
+    }
+}
@@ -1,7 +1,13 @@
-use failure::{Error, format_err};
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::mem;
+use std::str::FromStr;
 
+use failure::{Error, bail, format_err};
+use serde_derive::Serialize;
 
-#[derive(Debug)]
+
+#[derive(Debug, Clone)]
 pub struct DataFrame {
     pub columns: Vec<Column>,
 }
@@ -21,37 +27,285 @@ impl DataFrame {
 
     pub fn len(&self) -> usize {
         if let Some(col) = self.columns.get(0) {
-            match col.column_data {
-                ColumnData::Int8(ref ns) => ns.len(),
-                ColumnData::Int16(ref ns) => ns.len(),
-                ColumnData::Int32(ref ns) => ns.len(),
-                ColumnData::Int64(ref ns) => ns.len(),
-                ColumnData::UInt8(ref ns) => ns.len(),
-                ColumnData::UInt16(ref ns) => ns.len(),
-                ColumnData::UInt32(ref ns) => ns.len(),
-                ColumnData::UInt64(ref ns) => ns.len(),
-                ColumnData::Float32(ref ns) => ns.len(),
-                ColumnData::Float64(ref ns) => ns.len(),
-                ColumnData::Text(ref ss) => ss.len(),
-                ColumnData::NullableInt8(ref ns) => ns.len(),
-                ColumnData::NullableInt16(ref ns) => ns.len(),
-                ColumnData::NullableInt32(ref ns) => ns.len(),
-                ColumnData::NullableInt64(ref ns) => ns.len(),
-                ColumnData::NullableUInt8(ref ns) => ns.len(),
-                ColumnData::NullableUInt16(ref ns) => ns.len(),
-                ColumnData::NullableUInt32(ref ns) => ns.len(),
-                ColumnData::NullableUInt64(ref ns) => ns.len(),
-                ColumnData::NullableFloat32(ref ns) => ns.len(),
-                ColumnData::NullableFloat64(ref ns) => ns.len(),
-                ColumnData::NullableText(ref ss) => ss.len(),
-            }
+            col.column_data.len()
         } else {
             0
         }
     }
+
+    pub fn column_names(&self) -> Vec<String> {
+        self.columns.iter()
+            .map(|col| col.name.clone())
+            .collect()
+    }
+
+    /// Replaces backend column names (usually sql aliases) with the given
+    /// names, e.g. the headers generated from the schema.
+    pub fn rename_columns(&mut self, names: &[String]) -> Result<(), Error> {
+        if names.len() != self.columns.len() {
+            return Err(format_err!(
+                "Cannot rename {} columns using {} names",
+                self.columns.len(),
+                names.len(),
+            ));
+        }
+
+        for (col, name) in self.columns.iter_mut().zip(names) {
+            col.name = name.clone();
+        }
+
+        Ok(())
+    }
+
+    /// Reorders the columns, `order` holding the current position of each
+    /// column in the new order (see `Schema::column_order`).
+    pub fn reorder_columns(&mut self, order: &[usize]) -> Result<(), Error> {
+        if order.len() != self.columns.len() {
+            return Err(format_err!(
+                "Cannot reorder {} columns using {} positions",
+                self.columns.len(),
+                order.len(),
+            ));
+        }
+
+        let mut seen = vec![false; order.len()];
+        for &idx in order {
+            match seen.get_mut(idx) {
+                Some(seen) if !*seen => *seen = true,
+                _ => bail!("Column position {} is out of range or repeated", idx),
+            }
+        }
+
+        let mut columns: Vec<Option<Column>> = mem::replace(&mut self.columns, vec![])
+            .into_iter()
+            .map(Some)
+            .collect();
+
+        self.columns = order.iter()
+            .filter_map(|&idx| columns[idx].take())
+            .collect();
+
+        Ok(())
+    }
+
+    /// Rounds float columns. Columns named in `precisions` (usually measure
+    /// names) are rounded to the given number of decimal places; if `round`
+    /// is set, it is used for every float column instead.
+    pub fn round_columns(&mut self, precisions: &HashMap<String, u32>, round: Option<u32>) {
+        for col in self.columns.iter_mut() {
+            let precision = round.or_else(|| precisions.get(&col.name).cloned());
+
+            if let Some(precision) = precision {
+                col.column_data.round(precision);
+            }
+        }
+    }
+
+    /// Multiplies the values of columns named in `factors` (usually
+    /// measure names) by the given factor, e.g. to convert currencies.
+    /// Scaled columns become float columns.
+    pub fn scale_columns(&mut self, factors: &HashMap<String, f64>) {
+        for col in self.columns.iter_mut() {
+            if let Some(factor) = factors.get(&col.name) {
+                col.column_data.scale(*factor);
+            }
+        }
+    }
+
+    /// Concatenates the rows of several DataFrames sharing the same column
+    /// layout, e.g. the results of the queries generated by a logic layer
+    /// request. Columns are matched by position and must have the same names.
+    pub fn concat(dfs: Vec<DataFrame>) -> Result<Self, Error> {
+        let mut dfs = dfs.into_iter();
+
+        let mut final_df = match dfs.next() {
+            Some(df) => df,
+            None => return Err(format_err!("No dataframes were returned.")),
+        };
+
+        for df in dfs {
+            if df.columns.len() != final_df.columns.len() {
+                return Err(format_err!(
+                    "Cannot concatenate dataframes with {} and {} columns",
+                    final_df.columns.len(),
+                    df.columns.len(),
+                ));
+            }
+
+            for (col, other) in final_df.columns.iter_mut().zip(df.columns.into_iter()) {
+                if col.name != other.name {
+                    return Err(format_err!(
+                        "Cannot concatenate column {} with column {}",
+                        col.name,
+                        other.name,
+                    ));
+                }
+                col.column_data.append(other.column_data)?;
+            }
+        }
+
+        Ok(final_df)
+    }
+
+    /// Densifies a time series, so that every group has a row for every
+    /// period found in the DataFrame. Periods are identified by the values
+    /// of `time_columns` and groups by the values of every other column that
+    /// isn't in `value_columns` (usually measures). The values of added rows
+    /// are filled in using `method`.
+    ///
+    /// Rows are reordered by group (in order of first appearance) and then
+    /// by period. Nothing is changed if there are no gaps.
+    pub fn fill_gaps(&mut self, time_columns: &[String], value_columns: &[String], method: FillMethod) -> Result<(), Error> {
+        let mut time_idxs = vec![];
+        for name in time_columns {
+            let idx = self.columns.iter()
+                .position(|col| col.name == *name)
+                .ok_or_else(|| format_err!("Cannot fill gaps, time column {} not found", name))?;
+            time_idxs.push(idx);
+        }
+        time_idxs.sort();
+
+        if time_idxs.is_empty() {
+            bail!("Cannot fill gaps without a time column");
+        }
+
+        let is_time = |idx: usize| time_idxs.contains(&idx);
+        let is_value = |idx: usize| value_columns.contains(&self.columns[idx].name);
+
+        let group_idxs: Vec<_> = (0..self.columns.len())
+            .filter(|&idx| !is_time(idx) && !is_value(idx))
+            .collect();
+
+        let (key_rows, time_rows, value_rows) = {
+            let strings: Vec<_> = self.columns.iter()
+                .map(|col| col.stringify_column_data())
+                .collect();
+
+            // first row of each period
+            let mut periods: Vec<(Vec<&str>, usize)> = vec![];
+            // first row of each group, and its row for each period
+            let mut groups: Vec<(usize, HashMap<usize, usize>)> = vec![];
+            let mut period_positions = HashMap::new();
+            let mut group_positions = HashMap::new();
+
+            for row in 0..self.len() {
+                let period_key = row_key(&strings, &time_idxs, row);
+                let period = *period_positions.entry(period_key.clone())
+                    .or_insert_with(|| {
+                        periods.push((period_key, row));
+                        periods.len() - 1
+                    });
+
+                let group = *group_positions.entry(row_key(&strings, &group_idxs, row))
+                    .or_insert_with(|| {
+                        groups.push((row, HashMap::new()));
+                        groups.len() - 1
+                    });
+
+                if groups[group].1.insert(period, row).is_some() {
+                    bail!("Cannot fill gaps, there is more than one row per group and period");
+                }
+            }
+
+            if groups.len() * periods.len() == self.len() {
+                return Ok(());
+            }
+
+            let mut order: Vec<_> = (0..periods.len()).collect();
+            order.sort_by(|&a, &b| compare_period_keys(&periods[a].0, &periods[b].0));
+
+            let mut key_rows = vec![];
+            let mut time_rows = vec![];
+            let mut value_rows = vec![];
+
+            for (first_row, rows) in &groups {
+                let mut previous = None;
+
+                for &period in &order {
+                    let row = rows.get(&period).cloned();
+                    if row.is_some() {
+                        previous = row;
+                    }
+
+                    key_rows.push(Some(*first_row));
+                    time_rows.push(Some(periods[period].1));
+                    value_rows.push(match method {
+                        FillMethod::Previous => previous,
+                        FillMethod::Zero | FillMethod::Null => row,
+                    });
+                }
+            }
+
+            (key_rows, time_rows, value_rows)
+        };
+
+        let filled: Vec<_> = (0..self.columns.len())
+            .map(|idx| {
+                if is_time(idx) {
+                    self.columns[idx].column_data.take(&time_rows, false)
+                } else if is_value(idx) {
+                    self.columns[idx].column_data.take(&value_rows, method == FillMethod::Zero)
+                } else {
+                    self.columns[idx].column_data.take(&key_rows, false)
+                }
+            })
+            .collect();
+
+        for (col, column_data) in self.columns.iter_mut().zip(filled) {
+            col.column_data = column_data;
+        }
+
+        Ok(())
+    }
+}
+
+fn row_key<'a>(strings: &'a [Vec<String>], idxs: &[usize], row: usize) -> Vec<&'a str> {
+    idxs.iter().map(|&idx| strings[idx][row].as_str()).collect()
+}
+
+/// Orders periods by their time column values, numerically when both
+/// values are numbers (e.g. ids like 2019 or 201901).
+fn compare_period_keys(a: &[&str], b: &[&str]) -> Ordering {
+    for (x, y) in a.iter().zip(b) {
+        let ord = match (x.parse::<f64>(), y.parse::<f64>()) {
+            (Ok(x), Ok(y)) => x.partial_cmp(&y).unwrap_or(Ordering::Equal),
+            _ => x.cmp(y),
+        };
+
+        if ord != Ordering::Equal {
+            return ord;
+        }
+    }
+
+    Ordering::Equal
+}
+
+/// How `DataFrame::fill_gaps` fills in the values of the rows it adds.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all="lowercase")]
+pub enum FillMethod {
+    /// Zero (an empty string for text columns).
+    Zero,
+    /// Null, which makes the filled columns nullable.
+    Null,
+    /// The value of the group's previous period, or null before its first.
+    Previous,
+}
+
+impl FromStr for FillMethod {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "zero" => FillMethod::Zero,
+            "null" => FillMethod::Null,
+            "previous" => FillMethod::Previous,
+            _ => bail!("Could not parse fill method {}, expected zero, null or previous", s),
+        })
+    }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Column {
     pub name: String,
     pub column_data: ColumnData,
@@ -110,7 +364,314 @@ impl Column {
     /// DataFrame columns can come in many different types. This function converts
     /// all data to a common type (String).
     pub fn stringify_column_data(&self) -> Vec<String> {
-        return match &self.column_data {
+        self.column_data.stringify()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum ColumnData {
+    Int8(Vec<i8>),
+    Int16(Vec<i16>),
+    Int32(Vec<i32>),
+    Int64(Vec<i64>),
+    UInt8(Vec<u8>),
+    UInt16(Vec<u16>),
+    UInt32(Vec<u32>),
+    UInt64(Vec<u64>),
+    Float32(Vec<f32>),
+    Float64(Vec<f64>),
+    Text(Vec<String>),
+    NullableInt8(Vec<Option<i8>>),
+    NullableInt16(Vec<Option<i16>>),
+    NullableInt32(Vec<Option<i32>>),
+    NullableInt64(Vec<Option<i64>>),
+    NullableUInt8(Vec<Option<u8>>),
+    NullableUInt16(Vec<Option<u16>>),
+    NullableUInt32(Vec<Option<u32>>),
+    NullableUInt64(Vec<Option<u64>>),
+    NullableFloat32(Vec<Option<f32>>),
+    NullableFloat64(Vec<Option<f64>>),
+    NullableText(Vec<Option<String>>),
+}
+
+/// Converts any numeric `ColumnData` into the `$variant` (or
+/// `$nullable_variant`) column with values of type `$t`.
+macro_rules! cast_column {
+    ($col:expr, $nullable:expr, $variant:ident, $nullable_variant:ident, $t:ty) => {
+        match ($col, $nullable) {
+            (ColumnData::Int8(v), false) => ColumnData::$variant(v.into_iter().map(|x| x as $t).collect()),
+            (ColumnData::Int16(v), false) => ColumnData::$variant(v.into_iter().map(|x| x as $t).collect()),
+            (ColumnData::Int32(v), false) => ColumnData::$variant(v.into_iter().map(|x| x as $t).collect()),
+            (ColumnData::Int64(v), false) => ColumnData::$variant(v.into_iter().map(|x| x as $t).collect()),
+            (ColumnData::UInt8(v), false) => ColumnData::$variant(v.into_iter().map(|x| x as $t).collect()),
+            (ColumnData::UInt16(v), false) => ColumnData::$variant(v.into_iter().map(|x| x as $t).collect()),
+            (ColumnData::UInt32(v), false) => ColumnData::$variant(v.into_iter().map(|x| x as $t).collect()),
+            (ColumnData::UInt64(v), false) => ColumnData::$variant(v.into_iter().map(|x| x as $t).collect()),
+            (ColumnData::Float32(v), false) => ColumnData::$variant(v.into_iter().map(|x| x as $t).collect()),
+            (ColumnData::Float64(v), false) => ColumnData::$variant(v.into_iter().map(|x| x as $t).collect()),
+            (ColumnData::Int8(v), true) => ColumnData::$nullable_variant(v.into_iter().map(|x| Some(x as $t)).collect()),
+            (ColumnData::Int16(v), true) => ColumnData::$nullable_variant(v.into_iter().map(|x| Some(x as $t)).collect()),
+            (ColumnData::Int32(v), true) => ColumnData::$nullable_variant(v.into_iter().map(|x| Some(x as $t)).collect()),
+            (ColumnData::Int64(v), true) => ColumnData::$nullable_variant(v.into_iter().map(|x| Some(x as $t)).collect()),
+            (ColumnData::UInt8(v), true) => ColumnData::$nullable_variant(v.into_iter().map(|x| Some(x as $t)).collect()),
+            (ColumnData::UInt16(v), true) => ColumnData::$nullable_variant(v.into_iter().map(|x| Some(x as $t)).collect()),
+            (ColumnData::UInt32(v), true) => ColumnData::$nullable_variant(v.into_iter().map(|x| Some(x as $t)).collect()),
+            (ColumnData::UInt64(v), true) => ColumnData::$nullable_variant(v.into_iter().map(|x| Some(x as $t)).collect()),
+            (ColumnData::Float32(v), true) => ColumnData::$nullable_variant(v.into_iter().map(|x| Some(x as $t)).collect()),
+            (ColumnData::Float64(v), true) => ColumnData::$nullable_variant(v.into_iter().map(|x| Some(x as $t)).collect()),
+            (ColumnData::NullableInt8(v), _) => ColumnData::$nullable_variant(v.into_iter().map(|x| x.map(|x| x as $t)).collect()),
+            (ColumnData::NullableInt16(v), _) => ColumnData::$nullable_variant(v.into_iter().map(|x| x.map(|x| x as $t)).collect()),
+            (ColumnData::NullableInt32(v), _) => ColumnData::$nullable_variant(v.into_iter().map(|x| x.map(|x| x as $t)).collect()),
+            (ColumnData::NullableInt64(v), _) => ColumnData::$nullable_variant(v.into_iter().map(|x| x.map(|x| x as $t)).collect()),
+            (ColumnData::NullableUInt8(v), _) => ColumnData::$nullable_variant(v.into_iter().map(|x| x.map(|x| x as $t)).collect()),
+            (ColumnData::NullableUInt16(v), _) => ColumnData::$nullable_variant(v.into_iter().map(|x| x.map(|x| x as $t)).collect()),
+            (ColumnData::NullableUInt32(v), _) => ColumnData::$nullable_variant(v.into_iter().map(|x| x.map(|x| x as $t)).collect()),
+            (ColumnData::NullableUInt64(v), _) => ColumnData::$nullable_variant(v.into_iter().map(|x| x.map(|x| x as $t)).collect()),
+            (ColumnData::NullableFloat32(v), _) => ColumnData::$nullable_variant(v.into_iter().map(|x| x.map(|x| x as $t)).collect()),
+            (ColumnData::NullableFloat64(v), _) => ColumnData::$nullable_variant(v.into_iter().map(|x| x.map(|x| x as $t)).collect()),
+            (col, _) => col,
+        }
+    };
+}
+
+/// Takes the `$rows` of a non-nullable column, as a `$nullable_variant`
+/// column if there are missing rows that aren't filled with defaults.
+macro_rules! take_rows {
+    ($v:expr, $rows:expr, $fill_default:expr, $variant:ident, $nullable_variant:ident) => {
+        if $fill_default || $rows.iter().all(|row| row.is_some()) {
+            ColumnData::$variant($rows.iter().map(|row| row.map(|i| $v[i].clone()).unwrap_or_default()).collect())
+        } else {
+            ColumnData::$nullable_variant($rows.iter().map(|row| row.map(|i| $v[i].clone())).collect())
+        }
+    };
+}
+
+/// Takes the `$rows` of a nullable column.
+macro_rules! take_nullable_rows {
+    ($v:expr, $rows:expr, $fill_default:expr, $nullable_variant:ident) => {
+        ColumnData::$nullable_variant($rows.iter().map(|row| {
+            match row {
+                Some(i) => $v[*i].clone(),
+                None if $fill_default => Some(Default::default()),
+                None => None,
+            }
+        }).collect())
+    };
+}
+
+impl ColumnData {
+    pub fn len(&self) -> usize {
+        match *self {
+            ColumnData::Int8(ref v) => v.len(),
+            ColumnData::Int16(ref v) => v.len(),
+            ColumnData::Int32(ref v) => v.len(),
+            ColumnData::Int64(ref v) => v.len(),
+            ColumnData::UInt8(ref v) => v.len(),
+            ColumnData::UInt16(ref v) => v.len(),
+            ColumnData::UInt32(ref v) => v.len(),
+            ColumnData::UInt64(ref v) => v.len(),
+            ColumnData::Float32(ref v) => v.len(),
+            ColumnData::Float64(ref v) => v.len(),
+            ColumnData::Text(ref v) => v.len(),
+            ColumnData::NullableInt8(ref v) => v.len(),
+            ColumnData::NullableInt16(ref v) => v.len(),
+            ColumnData::NullableInt32(ref v) => v.len(),
+            ColumnData::NullableInt64(ref v) => v.len(),
+            ColumnData::NullableUInt8(ref v) => v.len(),
+            ColumnData::NullableUInt16(ref v) => v.len(),
+            ColumnData::NullableUInt32(ref v) => v.len(),
+            ColumnData::NullableUInt64(ref v) => v.len(),
+            ColumnData::NullableFloat32(ref v) => v.len(),
+            ColumnData::NullableFloat64(ref v) => v.len(),
+            ColumnData::NullableText(ref v) => v.len(),
+        }
+    }
+
+    /// Name of the column's type, in ClickHouse's notation (e.g. `UInt64`
+    /// or `Nullable(Float64)`), for describing results to clients.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            ColumnData::Int8(_) => "Int8",
+            ColumnData::Int16(_) => "Int16",
+            ColumnData::Int32(_) => "Int32",
+            ColumnData::Int64(_) => "Int64",
+            ColumnData::UInt8(_) => "UInt8",
+            ColumnData::UInt16(_) => "UInt16",
+            ColumnData::UInt32(_) => "UInt32",
+            ColumnData::UInt64(_) => "UInt64",
+            ColumnData::Float32(_) => "Float32",
+            ColumnData::Float64(_) => "Float64",
+            ColumnData::Text(_) => "String",
+            ColumnData::NullableInt8(_) => "Nullable(Int8)",
+            ColumnData::NullableInt16(_) => "Nullable(Int16)",
+            ColumnData::NullableInt32(_) => "Nullable(Int32)",
+            ColumnData::NullableInt64(_) => "Nullable(Int64)",
+            ColumnData::NullableUInt8(_) => "Nullable(UInt8)",
+            ColumnData::NullableUInt16(_) => "Nullable(UInt16)",
+            ColumnData::NullableUInt32(_) => "Nullable(UInt32)",
+            ColumnData::NullableUInt64(_) => "Nullable(UInt64)",
+            ColumnData::NullableFloat32(_) => "Nullable(Float32)",
+            ColumnData::NullableFloat64(_) => "Nullable(Float64)",
+            ColumnData::NullableText(_) => "Nullable(String)",
+        }
+    }
+
+    /// Appends the values of `other` to the end of this column.
+    ///
+    /// Values are moved over as-is when both columns share a type. Numeric
+    /// columns of different types are first cast to their common supertype
+    /// (see `NumericType::supertype`), and become nullable if either of them
+    /// is. Text can only be combined with numeric columns by stringifying
+    /// the numbers.
+    pub fn append(&mut self, other: ColumnData) -> Result<(), Error> {
+        let this = mem::replace(self, ColumnData::Text(vec![]));
+        *self = concat_column_data(this, other)?;
+        Ok(())
+    }
+
+    /// Returns a new column with the values at `rows`, in order. Missing
+    /// rows (`None`) are filled with the type's default value (zero) if
+    /// `fill_default` is set, and are null otherwise.
+    pub fn take(&self, rows: &[Option<usize>], fill_default: bool) -> ColumnData {
+        match self {
+            ColumnData::Int8(v) => take_rows!(v, rows, fill_default, Int8, NullableInt8),
+            ColumnData::Int16(v) => take_rows!(v, rows, fill_default, Int16, NullableInt16),
+            ColumnData::Int32(v) => take_rows!(v, rows, fill_default, Int32, NullableInt32),
+            ColumnData::Int64(v) => take_rows!(v, rows, fill_default, Int64, NullableInt64),
+            ColumnData::UInt8(v) => take_rows!(v, rows, fill_default, UInt8, NullableUInt8),
+            ColumnData::UInt16(v) => take_rows!(v, rows, fill_default, UInt16, NullableUInt16),
+            ColumnData::UInt32(v) => take_rows!(v, rows, fill_default, UInt32, NullableUInt32),
+            ColumnData::UInt64(v) => take_rows!(v, rows, fill_default, UInt64, NullableUInt64),
+            ColumnData::Float32(v) => take_rows!(v, rows, fill_default, Float32, NullableFloat32),
+            ColumnData::Float64(v) => take_rows!(v, rows, fill_default, Float64, NullableFloat64),
+            ColumnData::Text(v) => take_rows!(v, rows, fill_default, Text, NullableText),
+            ColumnData::NullableInt8(v) => take_nullable_rows!(v, rows, fill_default, NullableInt8),
+            ColumnData::NullableInt16(v) => take_nullable_rows!(v, rows, fill_default, NullableInt16),
+            ColumnData::NullableInt32(v) => take_nullable_rows!(v, rows, fill_default, NullableInt32),
+            ColumnData::NullableInt64(v) => take_nullable_rows!(v, rows, fill_default, NullableInt64),
+            ColumnData::NullableUInt8(v) => take_nullable_rows!(v, rows, fill_default, NullableUInt8),
+            ColumnData::NullableUInt16(v) => take_nullable_rows!(v, rows, fill_default, NullableUInt16),
+            ColumnData::NullableUInt32(v) => take_nullable_rows!(v, rows, fill_default, NullableUInt32),
+            ColumnData::NullableUInt64(v) => take_nullable_rows!(v, rows, fill_default, NullableUInt64),
+            ColumnData::NullableFloat32(v) => take_nullable_rows!(v, rows, fill_default, NullableFloat32),
+            ColumnData::NullableFloat64(v) => take_nullable_rows!(v, rows, fill_default, NullableFloat64),
+            ColumnData::NullableText(v) => take_nullable_rows!(v, rows, fill_default, NullableText),
+        }
+    }
+
+    /// Rounds float values to `precision` decimal places. Does nothing
+    /// for non-float columns.
+    pub fn round(&mut self, precision: u32) {
+        let factor = 10f64.powi(precision as i32);
+        let round_f64 = |x: f64| (x * factor).round() / factor;
+
+        match self {
+            ColumnData::Float32(v) => {
+                for x in v.iter_mut() {
+                    *x = round_f64(f64::from(*x)) as f32;
+                }
+            },
+            ColumnData::Float64(v) => {
+                for x in v.iter_mut() {
+                    *x = round_f64(*x);
+                }
+            },
+            ColumnData::NullableFloat32(v) => {
+                for x in v.iter_mut() {
+                    *x = x.map(|x| round_f64(f64::from(x)) as f32);
+                }
+            },
+            ColumnData::NullableFloat64(v) => {
+                for x in v.iter_mut() {
+                    *x = x.map(round_f64);
+                }
+            },
+            _ => (),
+        }
+    }
+
+    /// Multiplies numeric values by `factor`, converting the column to
+    /// a float column. Does nothing for text columns.
+    pub fn scale(&mut self, factor: f64) {
+        let nullable = match self.numeric_type() {
+            Some((_, nullable)) => nullable,
+            None => return,
+        };
+
+        let this = mem::replace(self, ColumnData::Text(vec![]));
+        *self = match this.cast(NumericType::Float(64), nullable) {
+            ColumnData::Float64(v) => {
+                ColumnData::Float64(v.into_iter().map(|x| x * factor).collect())
+            },
+            ColumnData::NullableFloat64(v) => {
+                ColumnData::NullableFloat64(v.into_iter().map(|x| x.map(|x| x * factor)).collect())
+            },
+            col => col,
+        };
+    }
+
+    /// The values of a numeric column as floats, or `None` for text columns.
+    pub fn to_nullable_f64(&self) -> Option<Vec<Option<f64>>> {
+        self.numeric_type()?;
+
+        match self.clone().cast(NumericType::Float(64), true) {
+            ColumnData::NullableFloat64(v) => Some(v),
+            _ => None,
+        }
+    }
+
+    /// Returns the numeric type of the column and whether it is nullable,
+    /// or `None` for text columns.
+    fn numeric_type(&self) -> Option<(NumericType, bool)> {
+        match self {
+            ColumnData::Int8(_) => Some((NumericType::Int(8), false)),
+            ColumnData::Int16(_) => Some((NumericType::Int(16), false)),
+            ColumnData::Int32(_) => Some((NumericType::Int(32), false)),
+            ColumnData::Int64(_) => Some((NumericType::Int(64), false)),
+            ColumnData::UInt8(_) => Some((NumericType::UInt(8), false)),
+            ColumnData::UInt16(_) => Some((NumericType::UInt(16), false)),
+            ColumnData::UInt32(_) => Some((NumericType::UInt(32), false)),
+            ColumnData::UInt64(_) => Some((NumericType::UInt(64), false)),
+            ColumnData::Float32(_) => Some((NumericType::Float(32), false)),
+            ColumnData::Float64(_) => Some((NumericType::Float(64), false)),
+            ColumnData::NullableInt8(_) => Some((NumericType::Int(8), true)),
+            ColumnData::NullableInt16(_) => Some((NumericType::Int(16), true)),
+            ColumnData::NullableInt32(_) => Some((NumericType::Int(32), true)),
+            ColumnData::NullableInt64(_) => Some((NumericType::Int(64), true)),
+            ColumnData::NullableUInt8(_) => Some((NumericType::UInt(8), true)),
+            ColumnData::NullableUInt16(_) => Some((NumericType::UInt(16), true)),
+            ColumnData::NullableUInt32(_) => Some((NumericType::UInt(32), true)),
+            ColumnData::NullableUInt64(_) => Some((NumericType::UInt(64), true)),
+            ColumnData::NullableFloat32(_) => Some((NumericType::Float(32), true)),
+            ColumnData::NullableFloat64(_) => Some((NumericType::Float(64), true)),
+            ColumnData::Text(_) | ColumnData::NullableText(_) => None,
+        }
+    }
+
+    /// Converts a numeric column to `ty`, wrapping values in `Some` if
+    /// `nullable` is set. Text columns are returned unchanged.
+    ///
+    /// Only meant for casts to a supertype (see `NumericType::supertype`),
+    /// so values are converted with `as`.
+    fn cast(self, ty: NumericType, nullable: bool) -> ColumnData {
+        match ty {
+            NumericType::Int(8) => cast_column!(self, nullable, Int8, NullableInt8, i8),
+            NumericType::Int(16) => cast_column!(self, nullable, Int16, NullableInt16, i16),
+            NumericType::Int(32) => cast_column!(self, nullable, Int32, NullableInt32, i32),
+            NumericType::Int(_) => cast_column!(self, nullable, Int64, NullableInt64, i64),
+            NumericType::UInt(8) => cast_column!(self, nullable, UInt8, NullableUInt8, u8),
+            NumericType::UInt(16) => cast_column!(self, nullable, UInt16, NullableUInt16, u16),
+            NumericType::UInt(32) => cast_column!(self, nullable, UInt32, NullableUInt32, u32),
+            NumericType::UInt(_) => cast_column!(self, nullable, UInt64, NullableUInt64, u64),
+            NumericType::Float(32) => cast_column!(self, nullable, Float32, NullableFloat32, f32),
+            NumericType::Float(_) => cast_column!(self, nullable, Float64, NullableFloat64, f64),
+        }
+    }
+
+    /// DataFrame columns can come in many different types. This function converts
+    /// all data to a common type (String).
+    pub fn stringify(&self) -> Vec<String> {
+        return match self {
             ColumnData::Int8(v) => v.iter().map(|&e| e.to_string()).collect(),
             ColumnData::Int16(v) => v.iter().map(|&e| e.to_string()).collect(),
             ColumnData::Int32(v) => v.iter().map(|&e| e.to_string()).collect(),
@@ -214,30 +775,170 @@ impl Column {
     }
 }
 
-#[derive(Debug)]
-pub enum ColumnData {
-    Int8(Vec<i8>),
-    Int16(Vec<i16>),
-    Int32(Vec<i32>),
-    Int64(Vec<i64>),
-    UInt8(Vec<u8>),
-    UInt16(Vec<u16>),
-    UInt32(Vec<u32>),
-    UInt64(Vec<u64>),
-    Float32(Vec<f32>),
-    Float64(Vec<f64>),
-    Text(Vec<String>),
-    NullableInt8(Vec<Option<i8>>),
-    NullableInt16(Vec<Option<i16>>),
-    NullableInt32(Vec<Option<i32>>),
-    NullableInt64(Vec<Option<i64>>),
-    NullableUInt8(Vec<Option<u8>>),
-    NullableUInt16(Vec<Option<u16>>),
-    NullableUInt32(Vec<Option<u32>>),
-    NullableUInt64(Vec<Option<u64>>),
-    NullableFloat32(Vec<Option<f32>>),
-    NullableFloat64(Vec<Option<f64>>),
-    NullableText(Vec<Option<String>>),
+fn concat_column_data(lhs: ColumnData, rhs: ColumnData) -> Result<ColumnData, Error> {
+    if !is_same_columndata_type(&lhs, &rhs) {
+        if let (Some((lhs_type, lhs_nullable)), Some((rhs_type, rhs_nullable))) = (lhs.numeric_type(), rhs.numeric_type()) {
+            let ty = lhs_type.supertype(rhs_type);
+            let nullable = lhs_nullable || rhs_nullable;
+
+            return concat_column_data(lhs.cast(ty, nullable), rhs.cast(ty, nullable));
+        }
+
+        let res = match (lhs, rhs) {
+            (ColumnData::Text(a), ColumnData::NullableText(b)) => {
+                ColumnData::NullableText(a.into_iter().map(Some).chain(b).collect())
+            },
+            (ColumnData::NullableText(a), ColumnData::Text(b)) => {
+                ColumnData::NullableText(a.into_iter().chain(b.into_iter().map(Some)).collect())
+            },
+            (lhs, rhs) => {
+                let mut values = lhs.stringify();
+                values.append(&mut rhs.stringify());
+                ColumnData::Text(values)
+            },
+        };
+
+        return Ok(res);
+    }
+
+    let res = match (lhs, rhs) {
+        (ColumnData::Int8(mut a), ColumnData::Int8(mut b)) => {
+            a.append(&mut b);
+            ColumnData::Int8(a)
+        },
+        (ColumnData::Int16(mut a), ColumnData::Int16(mut b)) => {
+            a.append(&mut b);
+            ColumnData::Int16(a)
+        },
+        (ColumnData::Int32(mut a), ColumnData::Int32(mut b)) => {
+            a.append(&mut b);
+            ColumnData::Int32(a)
+        },
+        (ColumnData::Int64(mut a), ColumnData::Int64(mut b)) => {
+            a.append(&mut b);
+            ColumnData::Int64(a)
+        },
+        (ColumnData::UInt8(mut a), ColumnData::UInt8(mut b)) => {
+            a.append(&mut b);
+            ColumnData::UInt8(a)
+        },
+        (ColumnData::UInt16(mut a), ColumnData::UInt16(mut b)) => {
+            a.append(&mut b);
+            ColumnData::UInt16(a)
+        },
+        (ColumnData::UInt32(mut a), ColumnData::UInt32(mut b)) => {
+            a.append(&mut b);
+            ColumnData::UInt32(a)
+        },
+        (ColumnData::UInt64(mut a), ColumnData::UInt64(mut b)) => {
+            a.append(&mut b);
+            ColumnData::UInt64(a)
+        },
+        (ColumnData::Float32(mut a), ColumnData::Float32(mut b)) => {
+            a.append(&mut b);
+            ColumnData::Float32(a)
+        },
+        (ColumnData::Float64(mut a), ColumnData::Float64(mut b)) => {
+            a.append(&mut b);
+            ColumnData::Float64(a)
+        },
+        (ColumnData::Text(mut a), ColumnData::Text(mut b)) => {
+            a.append(&mut b);
+            ColumnData::Text(a)
+        },
+        (ColumnData::NullableInt8(mut a), ColumnData::NullableInt8(mut b)) => {
+            a.append(&mut b);
+            ColumnData::NullableInt8(a)
+        },
+        (ColumnData::NullableInt16(mut a), ColumnData::NullableInt16(mut b)) => {
+            a.append(&mut b);
+            ColumnData::NullableInt16(a)
+        },
+        (ColumnData::NullableInt32(mut a), ColumnData::NullableInt32(mut b)) => {
+            a.append(&mut b);
+            ColumnData::NullableInt32(a)
+        },
+        (ColumnData::NullableInt64(mut a), ColumnData::NullableInt64(mut b)) => {
+            a.append(&mut b);
+            ColumnData::NullableInt64(a)
+        },
+        (ColumnData::NullableUInt8(mut a), ColumnData::NullableUInt8(mut b)) => {
+            a.append(&mut b);
+            ColumnData::NullableUInt8(a)
+        },
+        (ColumnData::NullableUInt16(mut a), ColumnData::NullableUInt16(mut b)) => {
+            a.append(&mut b);
+            ColumnData::NullableUInt16(a)
+        },
+        (ColumnData::NullableUInt32(mut a), ColumnData::NullableUInt32(mut b)) => {
+            a.append(&mut b);
+            ColumnData::NullableUInt32(a)
+        },
+        (ColumnData::NullableUInt64(mut a), ColumnData::NullableUInt64(mut b)) => {
+            a.append(&mut b);
+            ColumnData::NullableUInt64(a)
+        },
+        (ColumnData::NullableFloat32(mut a), ColumnData::NullableFloat32(mut b)) => {
+            a.append(&mut b);
+            ColumnData::NullableFloat32(a)
+        },
+        (ColumnData::NullableFloat64(mut a), ColumnData::NullableFloat64(mut b)) => {
+            a.append(&mut b);
+            ColumnData::NullableFloat64(a)
+        },
+        (ColumnData::NullableText(mut a), ColumnData::NullableText(mut b)) => {
+            a.append(&mut b);
+            ColumnData::NullableText(a)
+        },
+        (lhs, rhs) => {
+            return Err(format_err!("Cannot concatenate columns {:?} and {:?}", lhs, rhs));
+        },
+    };
+
+    Ok(res)
+}
+
+
+/// Numeric column types, by kind and bit width. Used to find the type two
+/// columns can be merged into without going through strings.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum NumericType {
+    Int(u8),
+    UInt(u8),
+    Float(u8),
+}
+
+impl NumericType {
+    /// The smallest type both `self` and `other` can be cast to:
+    /// - same kind: the widest of the two
+    /// - signed and unsigned: a signed type wide enough for the unsigned
+    ///   values, or Float64 for UInt64
+    /// - integer and float: Float32 for integers up to 16 bits, otherwise
+    ///   Float64
+    fn supertype(self, other: NumericType) -> NumericType {
+        match (self, other) {
+            (NumericType::Int(a), NumericType::Int(b)) => NumericType::Int(a.max(b)),
+            (NumericType::UInt(a), NumericType::UInt(b)) => NumericType::UInt(a.max(b)),
+            (NumericType::Float(a), NumericType::Float(b)) => NumericType::Float(a.max(b)),
+            (NumericType::Int(i), NumericType::UInt(u)) | (NumericType::UInt(u), NumericType::Int(i)) => {
+                if u < 64 {
+                    NumericType::Int(i.max(u * 2))
+                } else {
+                    NumericType::Float(64)
+                }
+            },
+            (NumericType::Int(i), NumericType::Float(f)) |
+            (NumericType::UInt(i), NumericType::Float(f)) |
+            (NumericType::Float(f), NumericType::Int(i)) |
+            (NumericType::Float(f), NumericType::UInt(i)) => {
+                if f == 32 && i <= 16 {
+                    NumericType::Float(32)
+                } else {
+                    NumericType::Float(64)
+                }
+            },
+        }
+    }
 }
 
 
@@ -377,3 +1078,228 @@ pub fn is_same_columndata_type(col_1: &ColumnData, col_2: &ColumnData) -> bool {
         },
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn concat_same_type() {
+        let dfs = vec![
+            DataFrame::from_vec(vec![Column::new("year".into(), ColumnData::Int32(vec![2017, 2018]))]),
+            DataFrame::from_vec(vec![Column::new("year".into(), ColumnData::Int32(vec![2019]))]),
+        ];
+        let df = DataFrame::concat(dfs).unwrap();
+
+        match df.columns[0].column_data {
+            ColumnData::Int32(ref v) => assert_eq!(v, &vec![2017, 2018, 2019]),
+            ref col => panic!("unexpected column type {:?}", col),
+        }
+    }
+
+    #[test]
+    fn scale_columns() {
+        let mut df = DataFrame::from_vec(vec![
+            Column::new("year".into(), ColumnData::Int32(vec![2017, 2018])),
+            Column::new("price".into(), ColumnData::NullableInt64(vec![Some(10), None])),
+        ]);
+
+        let mut factors = HashMap::new();
+        factors.insert("price".to_owned(), 0.5);
+        df.scale_columns(&factors);
+
+        match df.columns[0].column_data {
+            ColumnData::Int32(ref v) => assert_eq!(v, &vec![2017, 2018]),
+            ref col => panic!("unexpected column type {:?}", col),
+        }
+        match df.columns[1].column_data {
+            ColumnData::NullableFloat64(ref v) => assert_eq!(v, &vec![Some(5.0), None]),
+            ref col => panic!("unexpected column type {:?}", col),
+        }
+    }
+
+    #[test]
+    fn reorder_columns() {
+        let mut df = DataFrame::from_vec(vec![
+            Column::new("state".into(), ColumnData::Int32(vec![1])),
+            Column::new("year".into(), ColumnData::Int32(vec![2018])),
+            Column::new("pop".into(), ColumnData::Int32(vec![10])),
+        ]);
+
+        df.reorder_columns(&[1, 0, 2]).unwrap();
+        assert_eq!(df.column_names(), vec!["year", "state", "pop"]);
+
+        assert!(df.reorder_columns(&[0, 0, 1]).is_err());
+    }
+
+    #[test]
+    fn concat_widens_integers() {
+        let dfs = vec![
+            DataFrame::from_vec(vec![Column::new("pop".into(), ColumnData::Int32(vec![1, 2]))]),
+            DataFrame::from_vec(vec![Column::new("pop".into(), ColumnData::Int64(vec![3]))]),
+        ];
+        let df = DataFrame::concat(dfs).unwrap();
+
+        match df.columns[0].column_data {
+            ColumnData::Int64(ref v) => assert_eq!(v, &vec![1, 2, 3]),
+            ref col => panic!("unexpected column type {:?}", col),
+        }
+    }
+
+    #[test]
+    fn concat_promotes_numeric_types() {
+        let concat = |a: ColumnData, b: ColumnData| {
+            let mut a = a;
+            a.append(b).unwrap();
+            a
+        };
+
+        match concat(ColumnData::UInt32(vec![1]), ColumnData::Int32(vec![-1])) {
+            ColumnData::Int64(ref v) => assert_eq!(v, &vec![1, -1]),
+            ref col => panic!("unexpected column type {:?}", col),
+        }
+        match concat(ColumnData::UInt8(vec![1]), ColumnData::Int8(vec![-1])) {
+            ColumnData::Int16(ref v) => assert_eq!(v, &vec![1, -1]),
+            ref col => panic!("unexpected column type {:?}", col),
+        }
+        match concat(ColumnData::UInt64(vec![1]), ColumnData::Int8(vec![-1])) {
+            ColumnData::Float64(ref v) => assert_eq!(v, &vec![1.0, -1.0]),
+            ref col => panic!("unexpected column type {:?}", col),
+        }
+        match concat(ColumnData::Int32(vec![1]), ColumnData::Float32(vec![0.5])) {
+            ColumnData::Float64(ref v) => assert_eq!(v, &vec![1.0, 0.5]),
+            ref col => panic!("unexpected column type {:?}", col),
+        }
+        match concat(ColumnData::Int16(vec![1]), ColumnData::Float32(vec![0.5])) {
+            ColumnData::Float32(ref v) => assert_eq!(v, &vec![1.0, 0.5]),
+            ref col => panic!("unexpected column type {:?}", col),
+        }
+        match concat(ColumnData::Int32(vec![1]), ColumnData::NullableInt64(vec![None])) {
+            ColumnData::NullableInt64(ref v) => assert_eq!(v, &vec![Some(1), None]),
+            ref col => panic!("unexpected column type {:?}", col),
+        }
+        match concat(ColumnData::Text(vec!["a".into()]), ColumnData::Int32(vec![1])) {
+            ColumnData::Text(ref v) => assert_eq!(v, &vec!["a".to_owned(), "1".to_owned()]),
+            ref col => panic!("unexpected column type {:?}", col),
+        }
+    }
+
+    #[test]
+    fn concat_mismatched_columns() {
+        let dfs = vec![
+            DataFrame::from_vec(vec![Column::new("a".into(), ColumnData::Int32(vec![1]))]),
+            DataFrame::from_vec(vec![]),
+        ];
+        assert!(DataFrame::concat(dfs).is_err());
+        assert!(DataFrame::concat(vec![]).is_err());
+    }
+
+    #[test]
+    fn concat_keeps_column_names() {
+        let dfs = vec![
+            DataFrame::from_vec(vec![
+                Column::new("year".into(), ColumnData::Int32(vec![2017])),
+                Column::new("final_m0".into(), ColumnData::Float64(vec![1.5])),
+            ]),
+            DataFrame::from_vec(vec![
+                Column::new("year".into(), ColumnData::Int32(vec![2018])),
+                Column::new("final_m0".into(), ColumnData::Float64(vec![2.5])),
+            ]),
+        ];
+        let mut df = DataFrame::concat(dfs).unwrap();
+        assert_eq!(df.column_names(), vec!["year".to_owned(), "final_m0".to_owned()]);
+
+        df.rename_columns(&["Year".to_owned(), "Quantity".to_owned()]).unwrap();
+        assert_eq!(df.column_names(), vec!["Year".to_owned(), "Quantity".to_owned()]);
+
+        assert!(df.rename_columns(&["Year".to_owned()]).is_err());
+    }
+
+    #[test]
+    fn round_columns() {
+        let mut df = DataFrame::from_vec(vec![
+            Column::new("Year".into(), ColumnData::Int32(vec![2017])),
+            Column::new("Trade Value".into(), ColumnData::Float64(vec![1.23456])),
+            Column::new("Share".into(), ColumnData::NullableFloat32(vec![Some(0.123456), None])),
+        ]);
+
+        let mut precisions = HashMap::new();
+        precisions.insert("Trade Value".to_owned(), 2);
+
+        df.round_columns(&precisions, None);
+        match df.columns[1].column_data {
+            ColumnData::Float64(ref v) => assert_eq!(v, &vec![1.23]),
+            ref col => panic!("unexpected column type {:?}", col),
+        }
+        match df.columns[2].column_data {
+            ColumnData::NullableFloat32(ref v) => assert_eq!(v, &vec![Some(0.123456), None]),
+            ref col => panic!("unexpected column type {:?}", col),
+        }
+
+        df.round_columns(&precisions, Some(1));
+        match df.columns[2].column_data {
+            ColumnData::NullableFloat32(ref v) => assert_eq!(v, &vec![Some(0.1), None]),
+            ref col => panic!("unexpected column type {:?}", col),
+        }
+    }
+
+    #[test]
+    fn fill_gaps() {
+        let df = DataFrame::from_vec(vec![
+            Column::new("State".into(), ColumnData::Text(vec!["AL".into(), "AL".into(), "AK".into()])),
+            Column::new("Year".into(), ColumnData::Int32(vec![2017, 2019, 2018])),
+            Column::new("Quantity".into(), ColumnData::Int64(vec![1, 3, 2])),
+        ]);
+        let time = vec!["Year".to_owned()];
+        let values = vec!["Quantity".to_owned()];
+
+        let mut zero = df.clone();
+        zero.fill_gaps(&time, &values, FillMethod::Zero).unwrap();
+        assert_eq!(zero.columns[0].stringify_column_data(), vec!["AL", "AL", "AL", "AK", "AK", "AK"]);
+        match zero.columns[1].column_data {
+            ColumnData::Int32(ref v) => assert_eq!(v, &vec![2017, 2018, 2019, 2017, 2018, 2019]),
+            ref col => panic!("unexpected column type {:?}", col),
+        }
+        match zero.columns[2].column_data {
+            ColumnData::Int64(ref v) => assert_eq!(v, &vec![1, 0, 3, 0, 2, 0]),
+            ref col => panic!("unexpected column type {:?}", col),
+        }
+
+        let mut null = df.clone();
+        null.fill_gaps(&time, &values, FillMethod::Null).unwrap();
+        match null.columns[2].column_data {
+            ColumnData::NullableInt64(ref v) => assert_eq!(v, &vec![Some(1), None, Some(3), None, Some(2), None]),
+            ref col => panic!("unexpected column type {:?}", col),
+        }
+
+        let mut previous = df.clone();
+        previous.fill_gaps(&time, &values, FillMethod::Previous).unwrap();
+        match previous.columns[2].column_data {
+            ColumnData::NullableInt64(ref v) => assert_eq!(v, &vec![Some(1), Some(1), Some(3), None, Some(2), Some(2)]),
+            ref col => panic!("unexpected column type {:?}", col),
+        }
+
+        // no gaps, so the rows keep their order
+        let mut dense = DataFrame::from_vec(vec![
+            Column::new("Year".into(), ColumnData::Int32(vec![2019, 2018])),
+            Column::new("Quantity".into(), ColumnData::Int64(vec![3, 2])),
+        ]);
+        dense.fill_gaps(&time, &values, FillMethod::Zero).unwrap();
+        match dense.columns[0].column_data {
+            ColumnData::Int32(ref v) => assert_eq!(v, &vec![2019, 2018]),
+            ref col => panic!("unexpected column type {:?}", col),
+        }
+
+        assert!(df.clone().fill_gaps(&["Month".to_owned()], &values, FillMethod::Zero).is_err());
+        assert!("interpolate".parse::<FillMethod>().is_err());
+    }
+
+    #[test]
+    fn concat_mismatched_names() {
+        let dfs = vec![
+            DataFrame::from_vec(vec![Column::new("a".into(), ColumnData::Int32(vec![1]))]),
+            DataFrame::from_vec(vec![Column::new("b".into(), ColumnData::Int32(vec![2]))]),
+        ];
+        assert!(DataFrame::concat(dfs).is_err());
+    }
+}
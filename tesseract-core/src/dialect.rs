@@ -0,0 +1,158 @@
+//! Pluggable sql generation.
+//!
+//! A backend implements `SqlDialect` for the pieces of sql generation
+//! (primary aggregation, cuts, calculations, options), and `dialect_sql`
+//! puts them together in the same order for every database. New databases
+//! can then be supported from their own crate, without changes here.
+//!
+//! ```ignore
+//! impl Backend for MyDb {
+//!     fn generate_sql(&self, query_ir: QueryIr) -> String {
+//!         dialect_sql(&MyDbDialect, &query_ir)
+//!     }
+//!     ...
+//! }
+//! ```
+
+use itertools::join;
+
+use crate::query_ir::{
+    CutSql,
+    DrilldownSql,
+    Escaping,
+    GrowthSql,
+    HiddenDrilldownSql,
+    MeasureSql,
+    QueryIr,
+    RateSql,
+    RatioToParentSql,
+    RcaSql,
+    TableSql,
+};
+
+
+pub trait SqlDialect {
+    /// How string literals and identifiers are escaped.
+    fn escaping(&self) -> Escaping {
+        Escaping::Backslash
+    }
+
+    /// Sql condition for a cut, e.g. `col in (1, 2)`.
+    fn cut(&self, cut: &CutSql) -> String {
+        if cut.for_match {
+            cut.members_like_string_escaped(self.escaping())
+        } else {
+            format!("{} {} ({})",
+                cut.column,
+                cut.mask_sql_in_string(),
+                cut.members_string_escaped(self.escaping()),
+            )
+        }
+    }
+
+    /// Aggregates measures over the drilldowns, with cuts applied.
+    /// Returns the sql and the final drilldown columns.
+    fn primary_agg(
+        &self,
+        table: &TableSql,
+        cuts: &[CutSql],
+        drills: &[DrilldownSql],
+        meas: &[MeasureSql],
+        hidden_drills: Option<&[HiddenDrilldownSql]>,
+        ) -> (String, String);
+
+    /// Replaces the primary aggregation for rca queries.
+    /// Returns the sql and the final drilldown columns.
+    fn rca(
+        &self,
+        table: &TableSql,
+        cuts: &[CutSql],
+        drills: &[DrilldownSql],
+        meas: &[MeasureSql],
+        rca: &RcaSql,
+        ) -> (String, String);
+
+    /// Replaces the primary aggregation for rate queries.
+    /// Returns the sql and the final drilldown columns.
+    fn rate(
+        &self,
+        table: &TableSql,
+        cuts: &[CutSql],
+        drills: &[DrilldownSql],
+        meas: &[MeasureSql],
+        rate: &RateSql,
+        ) -> (String, String);
+
+    /// Wraps the aggregation with a growth calculation.
+    /// Returns the sql and the final drilldown columns.
+    fn growth(
+        &self,
+        final_sql: String,
+        final_drill_cols: &str,
+        num_measures: usize,
+        growth: &GrowthSql,
+        ) -> (String, String);
+
+    /// Adds the `ratio_to_parent` column, after the measures. Uses a window
+    /// sum, so the database must support window functions.
+    fn ratio_to_parent(
+        &self,
+        final_sql: String,
+        ratio: &RatioToParentSql,
+        ) -> String
+    {
+        let partition = if ratio.partition_columns.is_empty() {
+            "".to_owned()
+        } else {
+            format!("partition by {}", join(&ratio.partition_columns, ", "))
+        };
+
+        format!("select *, 1.0 * {mea} / sum({mea}) over ({partition}) as ratio_to_parent from ({sql}) as ratio_base",
+            mea=ratio.mea,
+            partition=partition,
+            sql=final_sql,
+        )
+    }
+
+    /// Wraps the final sql with top, filters, sort and limit.
+    fn options(
+        &self,
+        final_sql: String,
+        final_drill_cols: &str,
+        query_ir: &QueryIr,
+        num_measures: usize,
+        ) -> String;
+}
+
+
+/// Generates the sql for a query using a dialect.
+pub fn dialect_sql<D: SqlDialect + ?Sized>(dialect: &D, query_ir: &QueryIr) -> String {
+    let meas = &query_ir.meas;
+
+    let (mut final_sql, mut final_drill_cols) = {
+        // HiddenDrilldownSql, for grouped median, only works with primary agg.
+        let table = &query_ir.table;
+        let cuts = &query_ir.cuts;
+        let drills = &query_ir.drills;
+
+        if let Some(rca) = &query_ir.rca {
+            dialect.rca(table, cuts, drills, meas, rca)
+        } else if let Some(rate) = &query_ir.rate {
+            dialect.rate(table, cuts, drills, meas, rate)
+        } else {
+            dialect.primary_agg(table, cuts, drills, meas, Some(&query_ir.hidden_drills))
+        }
+    };
+
+    if let Some(growth) = &query_ir.growth {
+        let (sql, drill_cols) = dialect.growth(final_sql, &final_drill_cols, meas.len(), growth);
+        final_sql = sql;
+        final_drill_cols = drill_cols;
+    }
+
+    if let Some(ratio) = &query_ir.ratio_to_parent {
+        final_sql = dialect.ratio_to_parent(final_sql, ratio);
+    }
+
+    dialect.options(final_sql, &final_drill_cols, query_ir, meas.len())
+}
@@ -0,0 +1,230 @@
+//! `Engine` bundles a `Schema` and a `Backend`, for applications that want
+//! to run tesseract queries without going through tesseract-server.
+//!
+//! ```ignore
+//! let schema = Schema::from_json(&raw_schema)?;
+//! let engine = Engine::new(schema, Box::new(Clickhouse::from_url(&db_url)?))?;
+//!
+//! let query = Query::builder()
+//!     .drilldown("Year.Year")
+//!     .measure("Quantity")
+//!     .build()?;
+//!
+//! let df = engine.execute_blocking("sales", &query)?;
+//! let csv = format_records(&df.column_names(), df, FormatType::Csv)?;
+//! ```
+
+use failure::Error;
+use futures::future::{self, Future};
+
+use crate::backend::Backend;
+use crate::dataframe::DataFrame;
+use crate::query::Query;
+use crate::schema::Schema;
+
+
+pub struct Engine {
+    schema: Schema,
+    backend: Box<dyn Backend + Send + Sync>,
+}
+
+impl Engine {
+    /// Validates the schema and creates an `Engine` executing
+    /// queries against `backend`.
+    pub fn new(mut schema: Schema, backend: Box<dyn Backend + Send + Sync>) -> Result<Self, Error> {
+        schema.validate()?;
+
+        Ok(Engine {
+            schema,
+            backend,
+        })
+    }
+
+    pub fn schema(&self) -> &Schema {
+        &self.schema
+    }
+
+    pub fn backend(&self) -> &(dyn Backend + Send + Sync) {
+        &*self.backend
+    }
+
+    /// Generates the sql for a query, along with the headers
+    /// of the columns it returns.
+    pub fn sql(&self, cube: &str, query: &Query) -> Result<(String, Vec<String>), Error> {
+        let (query_ir, headers) = self.schema.sql_query(cube, query)?;
+        let sql = self.backend.generate_sql(query_ir);
+
+        Ok((sql, headers))
+    }
+
+    /// Executes a query. Columns in the resulting `DataFrame` are named
+    /// after the query headers, and float measures are rounded according
+    /// to their precision in the schema (or the query's `round`).
+    ///
+    /// Masked measures are suppressed by the cube's masking rules, like in
+    /// tesseract-server, with a `Suppressed` column added last (see
+    /// `MaskingPlan::apply`).
+    pub fn execute(&self, cube: &str, query: &Query) -> Box<Future<Item=DataFrame, Error=Error>> {
+        // masking adds the measures its rules need to the query
+        let mut query = query.clone();
+        let masking_plan = match self.schema.masking_plan(cube, &mut query) {
+            Ok(plan) => plan,
+            Err(err) => return Box::new(future::err(err)),
+        };
+
+        let (sql, headers) = match self.sql(cube, &query) {
+            Ok(x) => x,
+            Err(err) => return Box::new(future::err(err)),
+        };
+
+        let precisions = match self.schema.get_cube_by_name(cube) {
+            Ok(cube) => cube.get_measure_precisions(),
+            Err(err) => return Box::new(future::err(err)),
+        };
+        let round = query.round;
+
+        Box::new(
+            self.backend
+                .exec_sql(sql)
+                .and_then(move |mut df| {
+                    df.rename_columns(&headers)?;
+                    df.round_columns(&precisions, round);
+                    if let Some(ref plan) = masking_plan {
+                        plan.apply(&mut df)?;
+                    }
+                    Ok(df)
+                })
+        )
+    }
+
+    /// Executes a query, blocking the current thread until the result
+    /// is ready. Runs the query on a new single-threaded tokio runtime,
+    /// so it must not be called from within a runtime.
+    #[cfg(feature = "blocking")]
+    pub fn execute_blocking(&self, cube: &str, query: &Query) -> Result<DataFrame, Error> {
+        let mut rt = tokio::runtime::current_thread::Runtime::new()?;
+        rt.block_on(self.execute(cube, query))
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+    use crate::dataframe::{Column, ColumnData};
+
+    const SCHEMA: &str = r#"{ "name": "test", "cubes": [ { "name": "sales", "table": { "name": "sales" }, "dimensions": [{ "name": "Year", "foreign_key": "year", "hierarchies": [ { "name": "Year", "levels": [ { "name": "Year", "key_column": "year" } ] } ] }], "measures": [ { "name": "Quantity", "column": "quantity", "aggregator": "sum" }, { "name": "Price", "column": "price", "aggregator": "avg", "precision": 2 } ] } ] }"#;
+
+    /// Answers every query with the same results, and keeps the sql.
+    #[derive(Clone)]
+    struct TestBackend {
+        df: DataFrame,
+        queries: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl TestBackend {
+        fn new(df: DataFrame) -> Self {
+            TestBackend { df, queries: Arc::new(Mutex::new(vec![])) }
+        }
+    }
+
+    impl Backend for TestBackend {
+        fn exec_sql(&self, sql: String) -> Box<Future<Item=DataFrame, Error=Error>> {
+            self.queries.lock().unwrap().push(sql);
+            Box::new(future::ok(self.df.clone()))
+        }
+
+        fn box_clone(&self) -> Box<dyn Backend + Send + Sync> {
+            Box::new(self.clone())
+        }
+    }
+
+    fn year_quantity_price() -> DataFrame {
+        DataFrame::from_vec(vec![
+            Column::new("year".to_owned(), ColumnData::UInt32(vec![2018, 2019])),
+            Column::new("final_m0".to_owned(), ColumnData::Int64(vec![10, 20])),
+            Column::new("final_m1".to_owned(), ColumnData::Float64(vec![1.2345, 2.5])),
+        ])
+    }
+
+    fn test_engine(schema: &str, backend: &TestBackend) -> Result<Engine, Error> {
+        Engine::new(Schema::from_json(schema)?, Box::new(backend.clone()))
+    }
+
+    fn query(round: Option<u32>) -> Query {
+        let builder = Query::builder()
+            .drilldown("Year.Year")
+            .measure("Quantity")
+            .measure("Price");
+
+        match round {
+            Some(round) => builder.round(round),
+            None => builder,
+        }.build().unwrap()
+    }
+
+    #[test]
+    fn new_validates_schema() {
+        let backend = TestBackend::new(DataFrame::new());
+
+        let engine = test_engine(SCHEMA, &backend).unwrap();
+        assert_eq!(engine.schema().cubes.len(), 1);
+
+        let invalid = SCHEMA.replace(r#""measures": ["#, r#""masking": [{ "measure": "Revenue", "min": 5 }], "measures": ["#);
+        let err = test_engine(&invalid, &backend).err().unwrap();
+        assert_eq!(err.to_string(), "Masking rule uses measure Revenue, which is not in cube sales");
+    }
+
+    #[test]
+    fn unknown_cube() {
+        let backend = TestBackend::new(year_quantity_price());
+        let engine = test_engine(SCHEMA, &backend).unwrap();
+
+        assert!(engine.sql("sale", &query(None)).is_err());
+        assert!(engine.execute("sale", &query(None)).wait().is_err());
+        assert!(backend.queries.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn execute_renames_and_rounds() {
+        let backend = TestBackend::new(year_quantity_price());
+        let engine = test_engine(SCHEMA, &backend).unwrap();
+
+        let df = engine.execute("sales", &query(None)).wait().unwrap();
+        assert_eq!(df.column_names(), vec!["Year", "Quantity", "Price"]);
+        // rounded to the measure's precision
+        assert_eq!(df.columns[2].stringify_column_data(), vec!["1.23", "2.5"]);
+
+        let (sql, _) = engine.sql("sales", &query(None)).unwrap();
+        assert_eq!(*backend.queries.lock().unwrap(), vec![sql]);
+
+        // the query's round applies to every float column
+        let df = engine.execute("sales", &query(Some(0))).wait().unwrap();
+        assert_eq!(df.columns[2].stringify_column_data(), vec!["1", "3"]);
+    }
+
+    #[test]
+    fn execute_masks_measures() {
+        let schema = SCHEMA.replace(r#""measures": ["#, r#""masking": [{ "measure": "Quantity", "min": 5 }], "measures": ["#);
+        let backend = TestBackend::new(DataFrame::from_vec(vec![
+            Column::new("year".to_owned(), ColumnData::UInt32(vec![2018, 2019])),
+            Column::new("final_m0".to_owned(), ColumnData::Int64(vec![3, 8])),
+            Column::new("final_m1".to_owned(), ColumnData::Int64(vec![10, 20])),
+        ]));
+        let engine = test_engine(&schema, &backend).unwrap();
+
+        let query = Query::builder()
+            .drilldown("Year.Year")
+            .measure("Quantity")
+            .build()
+            .unwrap();
+
+        // the fact count is added to the query for the rule, and dropped
+        let df = engine.execute("sales", &query).wait().unwrap();
+        assert_eq!(df.column_names(), vec!["Year", "Quantity", "Suppressed"]);
+        assert_eq!(df.columns[1].column_data.to_nullable_f64().unwrap(), vec![None, Some(20.0)]);
+        assert_eq!(df.columns[2].stringify_column_data(), vec!["1", "0"]);
+        assert!(backend.queries.lock().unwrap()[0].contains("count(*)"));
+    }
+}
@@ -6,12 +6,14 @@ use serde::ser::{SerializeSeq};
 use serde_json::{Value};
 
 use crate::dataframe::{DataFrame, ColumnData};
+use crate::format_orc::format_orc;
 
 #[derive(Debug, Clone)]
 pub enum FormatType{
     Csv,
     JsonRecords,
     JsonArrays,
+    Orc,
 }
 
 impl std::str::FromStr for FormatType {
@@ -22,17 +24,41 @@ impl std::str::FromStr for FormatType {
             "csv" => Ok(FormatType::Csv),
             "jsonrecords" => Ok(FormatType::JsonRecords),
             "jsonarrays" => Ok(FormatType::JsonArrays),
+            "orc" => Ok(FormatType::Orc),
             _ => Err(format_err!("{} is not a supported format", s)),
         }
     }
 }
 
+impl FormatType {
+    /// Whether the format is binary, and must be written with
+    /// `format_records_bytes`.
+    pub fn is_binary(&self) -> bool {
+        match self {
+            FormatType::Orc => true,
+            _ => false,
+        }
+    }
+}
+
 /// Wrapper to format `DataFrame` to the desired output format.
+/// Binary formats (see `FormatType::is_binary`) are an error; use
+/// `format_records_bytes` for those.
 pub fn format_records(headers: &[String], df: DataFrame, format_type: FormatType) -> Result<String, Error> {
     match format_type {
         FormatType::Csv => Ok(format_csv(headers, df)?),
         FormatType::JsonRecords => Ok(format_jsonrecords(headers, df)?),
         FormatType::JsonArrays => Ok(format_jsonarrays(headers, df)?),
+        FormatType::Orc => Err(format_err!("orc is a binary format")),
+    }
+}
+
+/// Formats `DataFrame` to the desired output format, as bytes.
+/// Supports all formats.
+pub fn format_records_bytes(headers: &[String], df: DataFrame, format_type: FormatType) -> Result<Vec<u8>, Error> {
+    match format_type {
+        FormatType::Orc => format_orc(headers, df),
+        _ => Ok(format_records(headers, df, format_type)?.into_bytes()),
     }
 }
 
@@ -86,6 +112,20 @@ fn format_csv(headers: &[String], df: DataFrame) -> Result<String, Error> {
     Ok(res)
 }
 
+/// Formats response `DataFrame` to JSON records, followed by a `metadata`
+/// object describing the results (e.g. the measures' aggregators).
+pub fn format_jsonrecords_with_metadata(headers: &[String], df: DataFrame, metadata: &Value) -> Result<Vec<u8>, Error> {
+    let mut res = format_jsonrecords(headers, df)?;
+
+    // replaces the closing '}'
+    res.pop();
+    res.push_str(",\"metadata\":");
+    res.push_str(&serde_json::to_string(metadata)?);
+    res.push('}');
+
+    Ok(res.into_bytes())
+}
+
 /// Formats response `DataFrame` to JSON records.
 fn format_jsonrecords(headers: &[String], df: DataFrame) -> Result<String, Error> {
     // use streaming serializer
@@ -0,0 +1,697 @@
+//! Writes a `DataFrame` as an [ORC](https://orc.apache.org/specification/ORCv1/)
+//! file, for consumers on Hadoop-based stacks.
+//!
+//! This is a minimal writer: the whole DataFrame goes into one stripe, all
+//! columns use the `DIRECT` encoding (run length encoding v1, written as
+//! literal runs), and there are no row indexes or bloom filters. Streams and
+//! footers are compressed with ZLIB.
+//!
+//! Column types map to ORC types as follows:
+//! - Int8 -> byte
+//! - Int16, UInt8 -> short
+//! - Int32, UInt16 -> int
+//! - Int64, UInt32, UInt64 -> bigint (UInt64 values above `i64::MAX` are an
+//!   error, as bigint can't hold them)
+//! - Float32 -> float
+//! - Float64 -> double
+//! - Text -> string
+
+use failure::{Error, bail};
+use flate2::Compression;
+use flate2::write::DeflateEncoder;
+use std::io::Write;
+
+use crate::dataframe::{DataFrame, ColumnData};
+
+const MAGIC: &[u8] = b"ORC";
+const COMPRESSION_BLOCK_SIZE: usize = 256 * 1024;
+
+// Enum values from orc_proto.proto
+const COMPRESSION_ZLIB: u64 = 1;
+
+const STREAM_PRESENT: u64 = 0;
+const STREAM_DATA: u64 = 1;
+const STREAM_LENGTH: u64 = 2;
+
+const ENCODING_DIRECT: u64 = 0;
+
+const TYPE_BYTE: u64 = 1;
+const TYPE_SHORT: u64 = 2;
+const TYPE_INT: u64 = 3;
+const TYPE_LONG: u64 = 4;
+const TYPE_FLOAT: u64 = 5;
+const TYPE_DOUBLE: u64 = 6;
+const TYPE_STRING: u64 = 7;
+const TYPE_STRUCT: u64 = 12;
+
+/// Formats a `DataFrame` as an ORC file. Columns are named after `headers`.
+pub fn format_orc(headers: &[String], df: DataFrame) -> Result<Vec<u8>, Error> {
+    let num_rows = df.len() as u64;
+
+    let mut file = MAGIC.to_vec();
+
+    // Stripe: streams, then the stripe footer.
+    let mut stripe_footer = vec![];
+    let mut statistics = vec![];
+    let mut data_length = 0;
+
+    // Root struct column. It has no streams, but needs an encoding.
+    proto_message(&mut stripe_footer, 2, &column_encoding());
+    statistics.push(column_statistics(num_rows, false));
+
+    for (idx, column) in df.columns.iter().enumerate() {
+        let column_id = idx as u64 + 1;
+        let streams = column_streams(headers.get(idx).unwrap_or(&column.name), &column.column_data)?;
+
+        let non_null = streams.iter()
+            .find(|(kind, _)| *kind == STREAM_PRESENT)
+            .map(|_| count_present(&column.column_data))
+            .unwrap_or(num_rows);
+        statistics.push(column_statistics(non_null, non_null < num_rows));
+
+        for (kind, stream) in streams {
+            let compressed = compress(&stream)?;
+
+            let mut stream_info = vec![];
+            proto_varint(&mut stream_info, 1, kind);
+            proto_varint(&mut stream_info, 2, column_id);
+            proto_varint(&mut stream_info, 3, compressed.len() as u64);
+            proto_message(&mut stripe_footer, 1, &stream_info);
+
+            data_length += compressed.len() as u64;
+            file.extend(compressed);
+        }
+
+        proto_message(&mut stripe_footer, 2, &column_encoding());
+    }
+
+    let stripe_footer = compress(&stripe_footer)?;
+    let stripe_footer_length = stripe_footer.len() as u64;
+    file.extend(stripe_footer);
+
+    // An empty file has no stripes
+    if num_rows == 0 {
+        file.truncate(MAGIC.len());
+    }
+
+    // File footer
+    let mut footer = vec![];
+    proto_varint(&mut footer, 1, MAGIC.len() as u64);
+    proto_varint(&mut footer, 2, (file.len() - MAGIC.len()) as u64);
+
+    if num_rows > 0 {
+        let mut stripe_info = vec![];
+        proto_varint(&mut stripe_info, 1, MAGIC.len() as u64);
+        proto_varint(&mut stripe_info, 2, 0);
+        proto_varint(&mut stripe_info, 3, data_length);
+        proto_varint(&mut stripe_info, 4, stripe_footer_length);
+        proto_varint(&mut stripe_info, 5, num_rows);
+        proto_message(&mut footer, 3, &stripe_info);
+    }
+
+    let mut root_type = vec![];
+    proto_varint(&mut root_type, 1, TYPE_STRUCT);
+    let mut subtypes = vec![];
+    for idx in 0..df.columns.len() {
+        write_varint(&mut subtypes, idx as u64 + 1);
+    }
+    proto_bytes(&mut root_type, 2, &subtypes);
+    for header in headers {
+        proto_bytes(&mut root_type, 3, header.as_bytes());
+    }
+    proto_message(&mut footer, 4, &root_type);
+
+    for column in &df.columns {
+        let mut column_type = vec![];
+        proto_varint(&mut column_type, 1, orc_type(&column.column_data));
+        proto_message(&mut footer, 4, &column_type);
+    }
+
+    proto_varint(&mut footer, 6, num_rows);
+
+    for stats in &statistics {
+        proto_message(&mut footer, 7, stats);
+    }
+
+    // no row index
+    proto_varint(&mut footer, 8, 0);
+
+    let footer = compress(&footer)?;
+    let footer_length = footer.len() as u64;
+    file.extend(footer);
+
+    // Postscript, which is never compressed
+    let mut postscript = vec![];
+    proto_varint(&mut postscript, 1, footer_length);
+    proto_varint(&mut postscript, 2, COMPRESSION_ZLIB);
+    proto_varint(&mut postscript, 3, COMPRESSION_BLOCK_SIZE as u64);
+    let mut version = vec![];
+    write_varint(&mut version, 0);
+    write_varint(&mut version, 12);
+    proto_bytes(&mut postscript, 4, &version);
+    proto_varint(&mut postscript, 5, 0);
+    proto_varint(&mut postscript, 6, 1);
+    proto_bytes(&mut postscript, 8000, MAGIC);
+
+    let postscript_length = postscript.len() as u8;
+    file.extend(postscript);
+    file.push(postscript_length);
+
+    Ok(file)
+}
+
+fn orc_type(column_data: &ColumnData) -> u64 {
+    match column_data {
+        ColumnData::Int8(_) | ColumnData::NullableInt8(_) => TYPE_BYTE,
+        ColumnData::Int16(_) | ColumnData::NullableInt16(_) => TYPE_SHORT,
+        ColumnData::UInt8(_) | ColumnData::NullableUInt8(_) => TYPE_SHORT,
+        ColumnData::Int32(_) | ColumnData::NullableInt32(_) => TYPE_INT,
+        ColumnData::UInt16(_) | ColumnData::NullableUInt16(_) => TYPE_INT,
+        ColumnData::Int64(_) | ColumnData::NullableInt64(_) => TYPE_LONG,
+        ColumnData::UInt32(_) | ColumnData::NullableUInt32(_) => TYPE_LONG,
+        ColumnData::UInt64(_) | ColumnData::NullableUInt64(_) => TYPE_LONG,
+        ColumnData::Float32(_) | ColumnData::NullableFloat32(_) => TYPE_FLOAT,
+        ColumnData::Float64(_) | ColumnData::NullableFloat64(_) => TYPE_DOUBLE,
+        ColumnData::Text(_) | ColumnData::NullableText(_) => TYPE_STRING,
+    }
+}
+
+/// Uncompressed streams for a column, as (stream kind, bytes). Nullable
+/// columns get a PRESENT stream, but only if they contain nulls.
+fn column_streams(header: &str, column_data: &ColumnData) -> Result<Vec<(u64, Vec<u8>)>, Error> {
+    fn with_present<T: Clone>(values: &[Option<T>], data: impl Fn(&[T]) -> Vec<(u64, Vec<u8>)>) -> Vec<(u64, Vec<u8>)> {
+        let non_null: Vec<T> = values.iter().filter_map(|v| v.clone()).collect();
+        let mut streams = vec![];
+
+        if non_null.len() < values.len() {
+            let present: Vec<bool> = values.iter().map(|v| v.is_some()).collect();
+            streams.push((STREAM_PRESENT, boolean_rle(&present)));
+        }
+
+        streams.extend(data(&non_null));
+        streams
+    }
+
+    let bytes = |vs: &[i8]| vec![(STREAM_DATA, byte_rle(&vs.iter().map(|v| *v as u8).collect::<Vec<_>>()))];
+    let ints = |vs: Vec<i64>| vec![(STREAM_DATA, int_rle(&vs, true))];
+    let floats = |vs: &[f32]| vec![(STREAM_DATA, vs.iter().flat_map(|v| v.to_bits().to_le_bytes().to_vec()).collect::<Vec<u8>>())];
+    let doubles = |vs: &[f64]| vec![(STREAM_DATA, vs.iter().flat_map(|v| v.to_bits().to_le_bytes().to_vec()).collect::<Vec<u8>>())];
+    let strings = |vs: &[String]| {
+        let data: Vec<u8> = vs.iter().flat_map(|v| v.as_bytes().to_vec()).collect();
+        let lengths: Vec<i64> = vs.iter().map(|v| v.len() as i64).collect();
+        vec![(STREAM_DATA, data), (STREAM_LENGTH, int_rle(&lengths, false))]
+    };
+
+    // bigint is signed, so UInt64 values above i64::MAX don't fit
+    let too_large = match column_data {
+        ColumnData::UInt64(v) => v.iter().cloned().find(|x| *x > i64::MAX as u64),
+        ColumnData::NullableUInt64(v) => v.iter().filter_map(|x| *x).find(|x| *x > i64::MAX as u64),
+        _ => None,
+    };
+    if let Some(value) = too_large {
+        bail!("Value {} in column {} is too large for an ORC bigint", value, header);
+    }
+
+    let streams = match column_data {
+        ColumnData::Int8(v) => bytes(v),
+        ColumnData::Int16(v) => ints(v.iter().map(|x| *x as i64).collect()),
+        ColumnData::Int32(v) => ints(v.iter().map(|x| *x as i64).collect()),
+        ColumnData::Int64(v) => ints(v.clone()),
+        ColumnData::UInt8(v) => ints(v.iter().map(|x| *x as i64).collect()),
+        ColumnData::UInt16(v) => ints(v.iter().map(|x| *x as i64).collect()),
+        ColumnData::UInt32(v) => ints(v.iter().map(|x| *x as i64).collect()),
+        ColumnData::UInt64(v) => ints(v.iter().map(|x| *x as i64).collect()),
+        ColumnData::Float32(v) => floats(v),
+        ColumnData::Float64(v) => doubles(v),
+        ColumnData::Text(v) => strings(v),
+        ColumnData::NullableInt8(v) => with_present(v, |v| bytes(v)),
+        ColumnData::NullableInt16(v) => with_present(v, |v| ints(v.iter().map(|x| *x as i64).collect())),
+        ColumnData::NullableInt32(v) => with_present(v, |v| ints(v.iter().map(|x| *x as i64).collect())),
+        ColumnData::NullableInt64(v) => with_present(v, |v| ints(v.to_vec())),
+        ColumnData::NullableUInt8(v) => with_present(v, |v| ints(v.iter().map(|x| *x as i64).collect())),
+        ColumnData::NullableUInt16(v) => with_present(v, |v| ints(v.iter().map(|x| *x as i64).collect())),
+        ColumnData::NullableUInt32(v) => with_present(v, |v| ints(v.iter().map(|x| *x as i64).collect())),
+        ColumnData::NullableUInt64(v) => with_present(v, |v| ints(v.iter().map(|x| *x as i64).collect())),
+        ColumnData::NullableFloat32(v) => with_present(v, |v| floats(v)),
+        ColumnData::NullableFloat64(v) => with_present(v, |v| doubles(v)),
+        ColumnData::NullableText(v) => with_present(v, |v| strings(v)),
+    };
+
+    Ok(streams)
+}
+
+fn count_present(column_data: &ColumnData) -> u64 {
+    fn count<T>(v: &[Option<T>]) -> u64 {
+        v.iter().filter(|x| x.is_some()).count() as u64
+    }
+
+    match column_data {
+        ColumnData::NullableInt8(v) => count(v),
+        ColumnData::NullableInt16(v) => count(v),
+        ColumnData::NullableInt32(v) => count(v),
+        ColumnData::NullableInt64(v) => count(v),
+        ColumnData::NullableUInt8(v) => count(v),
+        ColumnData::NullableUInt16(v) => count(v),
+        ColumnData::NullableUInt32(v) => count(v),
+        ColumnData::NullableUInt64(v) => count(v),
+        ColumnData::NullableFloat32(v) => count(v),
+        ColumnData::NullableFloat64(v) => count(v),
+        ColumnData::NullableText(v) => count(v),
+        col => col.len() as u64,
+    }
+}
+
+fn column_encoding() -> Vec<u8> {
+    let mut encoding = vec![];
+    proto_varint(&mut encoding, 1, ENCODING_DIRECT);
+    encoding
+}
+
+fn column_statistics(number_of_values: u64, has_null: bool) -> Vec<u8> {
+    let mut stats = vec![];
+    proto_varint(&mut stats, 1, number_of_values);
+    proto_varint(&mut stats, 10, has_null as u64);
+    stats
+}
+
+// Run length encodings. Values are always written as literal runs, which
+// every reader accepts; runs of repeated values would only save space, and
+// the streams are compressed anyway.
+
+/// Byte run length encoding: a header byte of `-n` followed by `n` bytes.
+fn byte_rle(values: &[u8]) -> Vec<u8> {
+    let mut buf = vec![];
+
+    for chunk in values.chunks(128) {
+        buf.push((-(chunk.len() as i16)) as u8);
+        buf.extend(chunk);
+    }
+
+    buf
+}
+
+/// Boolean run length encoding: bits packed into bytes (most significant
+/// bit first), then byte run length encoded.
+fn boolean_rle(values: &[bool]) -> Vec<u8> {
+    let bytes: Vec<u8> = values.chunks(8)
+        .map(|chunk| {
+            chunk.iter()
+                .enumerate()
+                .fold(0u8, |byte, (i, &bit)| if bit { byte | (0x80 >> i) } else { byte })
+        })
+        .collect();
+
+    byte_rle(&bytes)
+}
+
+/// Integer run length encoding, version 1: a header byte of `-n` followed
+/// by `n` base 128 varints, zigzag encoded if `signed`.
+fn int_rle(values: &[i64], signed: bool) -> Vec<u8> {
+    let mut buf = vec![];
+
+    for chunk in values.chunks(128) {
+        buf.push((-(chunk.len() as i16)) as u8);
+
+        for &value in chunk {
+            let value = if signed {
+                ((value << 1) ^ (value >> 63)) as u64
+            } else {
+                value as u64
+            };
+            write_varint(&mut buf, value);
+        }
+    }
+
+    buf
+}
+
+/// Compresses a stream or footer: chunks of raw deflate, each with a
+/// 3 byte header holding the chunk length and whether the chunk was
+/// stored uncompressed (when compressing doesn't make it smaller).
+fn compress(data: &[u8]) -> Result<Vec<u8>, Error> {
+    let mut buf = vec![];
+
+    for chunk in data.chunks(COMPRESSION_BLOCK_SIZE) {
+        let mut encoder = DeflateEncoder::new(vec![], Compression::default());
+        encoder.write_all(chunk)?;
+        let compressed = encoder.finish()?;
+
+        let (body, is_original) = if compressed.len() < chunk.len() {
+            (&compressed[..], 0)
+        } else {
+            (chunk, 1)
+        };
+
+        let header = (body.len() << 1) | is_original;
+        buf.extend(&[header as u8, (header >> 8) as u8, (header >> 16) as u8]);
+        buf.extend(body);
+    }
+
+    Ok(buf)
+}
+
+// Protobuf encoding, just enough for the ORC metadata messages.
+
+fn write_varint(buf: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buf.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+fn proto_varint(buf: &mut Vec<u8>, field: u64, value: u64) {
+    write_varint(buf, field << 3);
+    write_varint(buf, value);
+}
+
+fn proto_bytes(buf: &mut Vec<u8>, field: u64, bytes: &[u8]) {
+    write_varint(buf, (field << 3) | 2);
+    write_varint(buf, bytes.len() as u64);
+    buf.extend(bytes);
+}
+
+fn proto_message(buf: &mut Vec<u8>, field: u64, message: &[u8]) {
+    proto_bytes(buf, field, message);
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::dataframe::Column;
+
+    #[test]
+    fn test_int_rle() {
+        assert_eq!(int_rle(&[0, -1, 1, 64], true), vec![0xfc, 0, 1, 2, 0x80, 0x01]);
+        assert_eq!(int_rle(&[3, 300], false), vec![0xfe, 3, 0xac, 0x02]);
+    }
+
+    #[test]
+    fn test_boolean_rle() {
+        let values = [true, false, true, true, false, false, false, false, true];
+        assert_eq!(boolean_rle(&values), vec![0xfe, 0b1011_0000, 0b1000_0000]);
+    }
+
+    #[test]
+    fn test_compress_small_chunk_is_stored() {
+        // header: length 2, original
+        assert_eq!(compress(b"ab").unwrap(), vec![5, 0, 0, b'a', b'b']);
+    }
+
+    #[test]
+    fn test_format_orc() {
+        let df = DataFrame::from_vec(vec![
+            Column::new("year".into(), ColumnData::Int32(vec![2017, 2018, 2019])),
+            Column::new("name".into(), ColumnData::NullableText(vec![Some("a".into()), None, Some("ccc".into())])),
+            Column::new("delta".into(), ColumnData::Int64(vec![-1, 0, i64::MIN])),
+            Column::new("flag".into(), ColumnData::NullableInt8(vec![None, Some(-5), None])),
+            Column::new("share".into(), ColumnData::Float64(vec![0.5, -1.25, 3.0])),
+            Column::new("rate".into(), ColumnData::NullableFloat32(vec![Some(1.5), None, None])),
+        ]);
+        let headers: Vec<String> = ["Year", "Name", "Delta", "Flag", "Share", "Rate"]
+            .iter()
+            .map(|h| h.to_string())
+            .collect();
+
+        let orc = format_orc(&headers, df).unwrap();
+
+        assert_eq!(&orc[..3], MAGIC);
+
+        let (schema, columns) = read_orc(&orc);
+        assert_eq!(schema, vec![
+            ("Year".to_owned(), TYPE_INT),
+            ("Name".to_owned(), TYPE_STRING),
+            ("Delta".to_owned(), TYPE_LONG),
+            ("Flag".to_owned(), TYPE_BYTE),
+            ("Share".to_owned(), TYPE_DOUBLE),
+            ("Rate".to_owned(), TYPE_FLOAT),
+        ]);
+
+        use self::Value::*;
+        assert_eq!(columns, vec![
+            vec![Some(Int(2017)), Some(Int(2018)), Some(Int(2019))],
+            vec![Some(Str("a".into())), None, Some(Str("ccc".into()))],
+            vec![Some(Int(-1)), Some(Int(0)), Some(Int(i64::MIN))],
+            vec![None, Some(Int(-5)), None],
+            vec![Some(Float(0.5)), Some(Float(-1.25)), Some(Float(3.0))],
+            vec![Some(Float(1.5)), None, None],
+        ]);
+    }
+
+    #[test]
+    fn test_format_orc_empty() {
+        let df = DataFrame::from_vec(vec![
+            Column::new("year".into(), ColumnData::Int32(vec![])),
+        ]);
+        let headers = vec!["Year".to_owned()];
+
+        let (schema, columns) = read_orc(&format_orc(&headers, df).unwrap());
+        assert_eq!(schema, vec![("Year".to_owned(), TYPE_INT)]);
+        assert_eq!(columns, vec![Vec::<Option<Value>>::new()]);
+    }
+
+    #[test]
+    fn test_format_orc_uint64_overflow() {
+        let headers = vec!["Count".to_owned()];
+
+        let df = DataFrame::from_vec(vec![
+            Column::new("count".into(), ColumnData::UInt64(vec![1, i64::MAX as u64])),
+        ]);
+        assert!(format_orc(&headers, df).is_ok());
+
+        let df = DataFrame::from_vec(vec![
+            Column::new("count".into(), ColumnData::NullableUInt64(vec![None, Some(i64::MAX as u64 + 1)])),
+        ]);
+        let err = format_orc(&headers, df).unwrap_err();
+        assert_eq!(err.to_string(), "Value 9223372036854775808 in column Count is too large for an ORC bigint");
+    }
+
+    // A reader for the subset of ORC that `format_orc` writes, following the
+    // spec rather than the writer, to check files round trip.
+
+    #[derive(Debug, PartialEq)]
+    enum Value {
+        Int(i64),
+        Float(f64),
+        Str(String),
+    }
+
+    enum Field<'a> {
+        Varint(u64),
+        Bytes(&'a [u8]),
+    }
+
+    fn read_varint(buf: &[u8], pos: &mut usize) -> u64 {
+        let mut value = 0;
+        let mut shift = 0;
+        loop {
+            let byte = buf[*pos];
+            *pos += 1;
+            value |= ((byte & 0x7f) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return value;
+            }
+            shift += 7;
+        }
+    }
+
+    fn read_proto(buf: &[u8]) -> Vec<(u64, Field<'_>)> {
+        let mut fields = vec![];
+        let mut pos = 0;
+        while pos < buf.len() {
+            let key = read_varint(buf, &mut pos);
+            let field = match key & 7 {
+                0 => Field::Varint(read_varint(buf, &mut pos)),
+                2 => {
+                    let len = read_varint(buf, &mut pos) as usize;
+                    pos += len;
+                    Field::Bytes(&buf[pos - len..pos])
+                },
+                wire_type => panic!("unexpected wire type {}", wire_type),
+            };
+            fields.push((key >> 3, field));
+        }
+        fields
+    }
+
+    fn varints(fields: &[(u64, Field)], field: u64) -> Vec<u64> {
+        fields.iter()
+            .filter(|(f, _)| *f == field)
+            .flat_map(|(_, v)| match v {
+                Field::Varint(v) => vec![*v],
+                // packed repeated field
+                Field::Bytes(b) => {
+                    let mut pos = 0;
+                    let mut values = vec![];
+                    while pos < b.len() {
+                        values.push(read_varint(b, &mut pos));
+                    }
+                    values
+                },
+            })
+            .collect()
+    }
+
+    fn messages<'a>(fields: &[(u64, Field<'a>)], field: u64) -> Vec<&'a [u8]> {
+        fields.iter()
+            .filter(|(f, _)| *f == field)
+            .map(|(_, v)| match v {
+                Field::Bytes(b) => *b,
+                Field::Varint(_) => panic!("field {} is not a message", field),
+            })
+            .collect()
+    }
+
+    fn decompress(buf: &[u8]) -> Vec<u8> {
+        use flate2::read::DeflateDecoder;
+        use std::io::Read;
+
+        let mut data = vec![];
+        let mut pos = 0;
+        while pos < buf.len() {
+            let header = buf[pos] as usize | (buf[pos + 1] as usize) << 8 | (buf[pos + 2] as usize) << 16;
+            let body = &buf[pos + 3..pos + 3 + (header >> 1)];
+            if header & 1 == 1 {
+                data.extend(body);
+            } else {
+                DeflateDecoder::new(body).read_to_end(&mut data).unwrap();
+            }
+            pos += 3 + (header >> 1);
+        }
+        data
+    }
+
+    fn read_byte_rle(buf: &[u8]) -> Vec<u8> {
+        let mut values = vec![];
+        let mut pos = 0;
+        while pos < buf.len() {
+            let header = buf[pos] as i8;
+            pos += 1;
+            if header < 0 {
+                let n = -(header as i16) as usize;
+                values.extend(&buf[pos..pos + n]);
+                pos += n;
+            } else {
+                values.extend(std::iter::repeat(buf[pos]).take(header as usize + 3));
+                pos += 1;
+            }
+        }
+        values
+    }
+
+    fn read_int_rle(buf: &[u8], signed: bool) -> Vec<i64> {
+        let decode = |v: u64| if signed { (v >> 1) as i64 ^ -((v & 1) as i64) } else { v as i64 };
+        let mut values = vec![];
+        let mut pos = 0;
+        while pos < buf.len() {
+            let header = buf[pos] as i8;
+            pos += 1;
+            if header < 0 {
+                for _ in 0..-(header as i16) {
+                    values.push(decode(read_varint(buf, &mut pos)));
+                }
+            } else {
+                let delta = buf[pos] as i8 as i64;
+                pos += 1;
+                let base = decode(read_varint(buf, &mut pos));
+                values.extend((0..header as i64 + 3).map(|i| base + i * delta));
+            }
+        }
+        values
+    }
+
+    /// Reads the schema, as (name, type kind) for each column, and the
+    /// values of each column.
+    fn read_orc(orc: &[u8]) -> (Vec<(String, u64)>, Vec<Vec<Option<Value>>>) {
+        let postscript_length = *orc.last().unwrap() as usize;
+        let postscript = read_proto(&orc[orc.len() - 1 - postscript_length..orc.len() - 1]);
+        assert_eq!(messages(&postscript, 8000), vec![MAGIC]);
+        assert_eq!(varints(&postscript, 2), vec![COMPRESSION_ZLIB]);
+
+        let footer_length = varints(&postscript, 1)[0] as usize;
+        let footer_end = orc.len() - 1 - postscript_length;
+        let footer = decompress(&orc[footer_end - footer_length..footer_end]);
+        let footer = read_proto(&footer);
+
+        let types: Vec<_> = messages(&footer, 4).into_iter().map(read_proto).collect();
+        assert_eq!(varints(&types[0], 1), vec![TYPE_STRUCT]);
+        let names = messages(&types[0], 3).into_iter()
+            .map(|name| String::from_utf8(name.to_vec()).unwrap());
+        let schema: Vec<(String, u64)> = names
+            .zip(varints(&types[0], 2))
+            .map(|(name, subtype)| (name, varints(&types[subtype as usize], 1)[0]))
+            .collect();
+
+        let num_rows = varints(&footer, 6)[0] as usize;
+        let mut columns: Vec<Vec<Option<Value>>> = schema.iter().map(|_| vec![]).collect();
+
+        for stripe in messages(&footer, 3) {
+            let stripe = read_proto(stripe);
+            let offset = varints(&stripe, 1)[0] as usize;
+            let data_length = varints(&stripe, 3)[0] as usize;
+            let footer_length = varints(&stripe, 4)[0] as usize;
+            let stripe_rows = varints(&stripe, 5)[0] as usize;
+
+            let stripe_footer = decompress(&orc[offset + data_length..offset + data_length + footer_length]);
+            let stripe_footer = read_proto(&stripe_footer);
+
+            // streams are laid out in the order of the stripe footer
+            let mut streams: Vec<Vec<(u64, Vec<u8>)>> = schema.iter().map(|_| vec![]).collect();
+            let mut pos = offset;
+            for stream in messages(&stripe_footer, 1) {
+                let stream = read_proto(stream);
+                let (kind, column, length) = (varints(&stream, 1)[0], varints(&stream, 2)[0], varints(&stream, 3)[0] as usize);
+                streams[column as usize - 1].push((kind, decompress(&orc[pos..pos + length])));
+                pos += length;
+            }
+            assert_eq!(pos, offset + data_length);
+
+            for (idx, (_, kind)) in schema.iter().enumerate() {
+                let stream = |k| streams[idx].iter().find(|(kind, _)| *kind == k).map(|(_, s)| &s[..]);
+
+                let present: Vec<bool> = match stream(STREAM_PRESENT) {
+                    Some(s) => read_byte_rle(s).iter()
+                        .flat_map(|byte| (0..8).map(move |i| byte & (0x80 >> i) != 0))
+                        .take(stripe_rows)
+                        .collect(),
+                    None => vec![true; stripe_rows],
+                };
+                let data = stream(STREAM_DATA).unwrap();
+
+                let values: Vec<Value> = match *kind {
+                    TYPE_BYTE => read_byte_rle(data).into_iter().map(|v| Value::Int(v as i8 as i64)).collect(),
+                    TYPE_SHORT | TYPE_INT | TYPE_LONG => read_int_rle(data, true).into_iter().map(Value::Int).collect(),
+                    TYPE_FLOAT => data.chunks(4)
+                        .map(|b| Value::Float(f32::from_bits(u32::from_le_bytes([b[0], b[1], b[2], b[3]])) as f64))
+                        .collect(),
+                    TYPE_DOUBLE => data.chunks(8)
+                        .map(|b| {
+                            let mut bytes = [0; 8];
+                            bytes.copy_from_slice(b);
+                            Value::Float(f64::from_bits(u64::from_le_bytes(bytes)))
+                        })
+                        .collect(),
+                    TYPE_STRING => {
+                        let mut pos = 0;
+                        read_int_rle(stream(STREAM_LENGTH).unwrap(), false).into_iter()
+                            .map(|len| {
+                                pos += len as usize;
+                                Value::Str(String::from_utf8(data[pos - len as usize..pos].to_vec()).unwrap())
+                            })
+                            .collect()
+                    },
+                    kind => panic!("unexpected type kind {}", kind),
+                };
+
+                let mut values = values.into_iter();
+                columns[idx].extend(present.iter().map(|&p| if p { values.next() } else { None }));
+                assert!(values.next().is_none());
+            }
+        }
+
+        assert!(columns.iter().all(|c| c.len() == num_rows));
+
+        (schema, columns)
+    }
+}
@@ -3,6 +3,7 @@ use csv;
 use failure::{Error, format_err};
 use futures::{Stream, Async, Poll};
 use indexmap::IndexMap;
+use log::error;
 use serde::Serializer;
 use serde::ser::{SerializeSeq};
 use serde_json::{Value};
@@ -42,6 +43,28 @@ impl<S> RecordBlockStream<S>
             headers,
         }
     }
+
+    /// By the time an error comes from the inner stream, the response has
+    /// already started, so just cutting off the stream would leave output
+    /// that looks complete (especially csv). Instead, the error is logged
+    /// and the stream ends with a trailer that clients can check for:
+    /// a `#error` line for csv, and an `error` field for json.
+    fn error_trailer(&mut self, err: Error) -> Result<Bytes, Error> {
+        error!("Error while streaming response, results are incomplete: {}", err);
+        self.eof = true;
+
+        let message = "results are incomplete, an error occurred while streaming";
+
+        match self.format_type {
+            FormatType::Csv => {
+                Ok(format!("#error: {}\n", message).into_bytes().into())
+            },
+            FormatType::JsonRecords | FormatType::JsonArrays => {
+                Ok(format!("],\"error\":{}}}", serde_json::to_string(message)?).into_bytes().into())
+            },
+            _ => Err(err),
+        }
+    }
 }
 
 impl<S> Stream for RecordBlockStream<S>
@@ -114,7 +137,7 @@ impl<S> Stream for RecordBlockStream<S>
 
         loop {
             let df_res = match self.inner.poll() {
-                Err(err) => return Err(err),
+                Err(err) => return self.error_trailer(err).map(|bytes| Async::Ready(Some(bytes))),
                 Ok(Async::NotReady) => return Ok(Async::NotReady),
                 Ok(Async::Ready(Some(df_res))) => df_res,
                 Ok(Async::Ready(None)) => {
@@ -193,7 +216,7 @@ impl<S> Stream for RecordBlockStream<S>
 
                     return Ok(Async::Ready(Some(formatted)));
                 },
-                Err(err) => return Err(err),
+                Err(err) => return self.error_trailer(err).map(|bytes| Async::Ready(Some(bytes))),
 
             }
         }
@@ -367,3 +390,38 @@ fn format_jsonarrays_body(headers: &[String], df: DataFrame, lead_byte: u8) -> R
     Ok(res.into())
 }
 
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use futures::{Future, stream};
+    use crate::dataframe::Column;
+
+    fn stream_output(format_type: FormatType) -> String {
+        let df = DataFrame::from_vec(vec![
+            Column::new("a".to_owned(), ColumnData::Int32(vec![1, 2])),
+        ]);
+        let dfs = stream::iter_ok::<_, Error>(vec![Ok(df), Err(format_err!("connection reset"))]);
+
+        let chunks = format_records_stream(vec!["a".to_owned()], dfs, format_type)
+            .collect()
+            .wait()
+            .unwrap();
+
+        chunks.iter()
+            .map(|chunk| String::from_utf8(chunk.to_vec()).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn error_trailer() {
+        assert_eq!(
+            stream_output(FormatType::Csv),
+            "a\n1\n2\n#error: results are incomplete, an error occurred while streaming\n",
+        );
+
+        let json: Value = serde_json::from_str(&stream_output(FormatType::JsonRecords)).unwrap();
+        assert_eq!(json["data"].as_array().unwrap().len(), 2);
+        assert!(json["error"].is_string());
+    }
+}
@@ -0,0 +1,266 @@
+//! Writes a `DataFrame` as an [XLSX](https://www.ecma-international.org/publications-and-standards/standards/ecma-376/)
+//! workbook, for users opening results in spreadsheet software.
+//!
+//! This is a minimal writer: the workbook has a single worksheet with a
+//! header row, text cells are inline strings (no shared strings table), and
+//! there's no styling. Numeric columns are written as numbers, and nulls as
+//! empty cells. The parts are zipped with DEFLATE.
+
+use failure::{Error, format_err};
+use flate2::{Compression, Crc};
+use flate2::write::DeflateEncoder;
+use std::io::Write;
+
+use crate::dataframe::DataFrame;
+
+const CONTENT_TYPES: &str = r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<Types xmlns="http://schemas.openxmlformats.org/package/2006/content-types"><Default Extension="rels" ContentType="application/vnd.openxmlformats-package.relationships+xml"/><Default Extension="xml" ContentType="application/xml"/><Override PartName="/xl/workbook.xml" ContentType="application/vnd.openxmlformats-officedocument.spreadsheetml.sheet.main+xml"/><Override PartName="/xl/worksheets/sheet1.xml" ContentType="application/vnd.openxmlformats-officedocument.spreadsheetml.worksheet+xml"/><Override PartName="/xl/styles.xml" ContentType="application/vnd.openxmlformats-officedocument.spreadsheetml.styles+xml"/></Types>"#;
+
+const ROOT_RELS: &str = r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<Relationships xmlns="http://schemas.openxmlformats.org/package/2006/relationships"><Relationship Id="rId1" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/officeDocument" Target="xl/workbook.xml"/></Relationships>"#;
+
+const WORKBOOK_RELS: &str = r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<Relationships xmlns="http://schemas.openxmlformats.org/package/2006/relationships"><Relationship Id="rId1" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/worksheet" Target="worksheets/sheet1.xml"/><Relationship Id="rId2" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/styles" Target="styles.xml"/></Relationships>"#;
+
+const STYLES: &str = r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<styleSheet xmlns="http://schemas.openxmlformats.org/spreadsheetml/2006/main"><fonts count="1"><font/></fonts><fills count="1"><fill/></fills><borders count="1"><border/></borders><cellStyleXfs count="1"><xf/></cellStyleXfs><cellXfs count="1"><xf/></cellXfs></styleSheet>"#;
+
+/// Sheet names can't be longer than this.
+const MAX_SHEET_NAME_LEN: usize = 31;
+
+/// Formats a `DataFrame` as an XLSX workbook with one sheet, named
+/// `sheet_name`. Columns are named after `headers`.
+pub fn format_xlsx(sheet_name: &str, headers: &[String], df: &DataFrame) -> Result<Vec<u8>, Error> {
+    if headers.len() != df.columns.len() {
+        return Err(format_err!("{} headers for {} columns", headers.len(), df.columns.len()));
+    }
+
+    let workbook = format!(
+        r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<workbook xmlns="http://schemas.openxmlformats.org/spreadsheetml/2006/main" xmlns:r="http://schemas.openxmlformats.org/officeDocument/2006/relationships"><sheets><sheet name="{}" sheetId="1" r:id="rId1"/></sheets></workbook>"#,
+        xml_escape(&sheet_title(sheet_name)),
+    );
+
+    let parts = vec![
+        ("[Content_Types].xml", CONTENT_TYPES.to_owned()),
+        ("_rels/.rels", ROOT_RELS.to_owned()),
+        ("xl/workbook.xml", workbook),
+        ("xl/_rels/workbook.xml.rels", WORKBOOK_RELS.to_owned()),
+        ("xl/styles.xml", STYLES.to_owned()),
+        ("xl/worksheets/sheet1.xml", worksheet(headers, df)),
+    ];
+
+    zip(&parts)
+}
+
+fn worksheet(headers: &[String], df: &DataFrame) -> String {
+    let mut sheet = String::from(r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<worksheet xmlns="http://schemas.openxmlformats.org/spreadsheetml/2006/main"><sheetData>"#);
+
+    sheet.push_str(r#"<row r="1">"#);
+    for (col_idx, header) in headers.iter().enumerate() {
+        sheet.push_str(&text_cell(&cell_ref(col_idx, 0), header));
+    }
+    sheet.push_str("</row>");
+
+    // numeric columns as numbers, the rest as text
+    let columns: Vec<Result<Vec<Option<f64>>, Vec<String>>> = df.columns.iter()
+        .map(|col| {
+            match col.column_data.to_nullable_f64() {
+                Some(values) => Ok(values),
+                None => Err(col.column_data.stringify()),
+            }
+        })
+        .collect();
+
+    for row_idx in 0..df.len() {
+        sheet.push_str(&format!(r#"<row r="{}">"#, row_idx + 2));
+
+        for (col_idx, column) in columns.iter().enumerate() {
+            let cell = cell_ref(col_idx, row_idx + 1);
+
+            match column {
+                Ok(values) => {
+                    if let Some(value) = values[row_idx] {
+                        if value.is_finite() {
+                            sheet.push_str(&format!(r#"<c r="{}"><v>{}</v></c>"#, cell, value));
+                        }
+                    }
+                },
+                Err(values) => {
+                    if !values[row_idx].is_empty() {
+                        sheet.push_str(&text_cell(&cell, &values[row_idx]));
+                    }
+                },
+            }
+        }
+
+        sheet.push_str("</row>");
+    }
+
+    sheet.push_str("</sheetData></worksheet>");
+    sheet
+}
+
+fn text_cell(cell: &str, text: &str) -> String {
+    format!(r#"<c r="{}" t="inlineStr"><is><t xml:space="preserve">{}</t></is></c>"#, cell, xml_escape(text))
+}
+
+/// A1-style reference of a cell, from zero-based column and row indexes.
+fn cell_ref(col_idx: usize, row_idx: usize) -> String {
+    let mut letters = vec![];
+    let mut n = col_idx + 1;
+
+    while n > 0 {
+        let rem = (n - 1) % 26;
+        letters.push((b'A' + rem as u8) as char);
+        n = (n - 1) / 26;
+    }
+
+    letters.iter().rev().collect::<String>() + &(row_idx + 1).to_string()
+}
+
+/// Sheet names can't contain `[]:*?/\` and are limited to 31 characters.
+fn sheet_title(name: &str) -> String {
+    let title: String = name.chars()
+        .filter(|c| !"[]:*?/\\".contains(*c))
+        .take(MAX_SHEET_NAME_LEN)
+        .collect();
+
+    if title.is_empty() { "Sheet1".to_owned() } else { title }
+}
+
+/// Escapes xml special characters, and drops control characters that
+/// aren't allowed in xml.
+fn xml_escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\t' | '\n' | '\r' => escaped.push(c),
+            c if c.is_control() => (),
+            c => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+/// Writes a zip archive with each part deflated.
+fn zip(parts: &[(&str, String)]) -> Result<Vec<u8>, Error> {
+    // 1980-01-01 00:00, the earliest date zip can represent
+    const DOS_TIME: u16 = 0;
+    const DOS_DATE: u16 = (1 << 5) | 1;
+    const VERSION: u16 = 20;
+    const METHOD_DEFLATE: u16 = 8;
+
+    let mut archive = vec![];
+    let mut central_directory = vec![];
+
+    for (name, content) in parts {
+        let content = content.as_bytes();
+
+        let mut crc = Crc::new();
+        crc.update(content);
+
+        let mut encoder = DeflateEncoder::new(vec![], Compression::default());
+        encoder.write_all(content)?;
+        let compressed = encoder.finish()?;
+
+        let offset = archive.len() as u32;
+
+        // local file header
+        put_u32(&mut archive, 0x0403_4b50);
+        put_u16(&mut archive, VERSION);
+        put_u16(&mut archive, 0); // flags
+        put_u16(&mut archive, METHOD_DEFLATE);
+        put_u16(&mut archive, DOS_TIME);
+        put_u16(&mut archive, DOS_DATE);
+        put_u32(&mut archive, crc.sum());
+        put_u32(&mut archive, compressed.len() as u32);
+        put_u32(&mut archive, content.len() as u32);
+        put_u16(&mut archive, name.len() as u16);
+        put_u16(&mut archive, 0); // extra field length
+        archive.extend_from_slice(name.as_bytes());
+        archive.extend_from_slice(&compressed);
+
+        // central directory header
+        put_u32(&mut central_directory, 0x0201_4b50);
+        put_u16(&mut central_directory, VERSION); // version made by
+        put_u16(&mut central_directory, VERSION); // version needed
+        put_u16(&mut central_directory, 0); // flags
+        put_u16(&mut central_directory, METHOD_DEFLATE);
+        put_u16(&mut central_directory, DOS_TIME);
+        put_u16(&mut central_directory, DOS_DATE);
+        put_u32(&mut central_directory, crc.sum());
+        put_u32(&mut central_directory, compressed.len() as u32);
+        put_u32(&mut central_directory, content.len() as u32);
+        put_u16(&mut central_directory, name.len() as u16);
+        put_u16(&mut central_directory, 0); // extra field length
+        put_u16(&mut central_directory, 0); // comment length
+        put_u16(&mut central_directory, 0); // disk number
+        put_u16(&mut central_directory, 0); // internal attributes
+        put_u32(&mut central_directory, 0); // external attributes
+        put_u32(&mut central_directory, offset);
+        central_directory.extend_from_slice(name.as_bytes());
+    }
+
+    let central_directory_offset = archive.len() as u32;
+    archive.extend_from_slice(&central_directory);
+
+    // end of central directory record
+    put_u32(&mut archive, 0x0605_4b50);
+    put_u16(&mut archive, 0); // disk number
+    put_u16(&mut archive, 0); // disk with the central directory
+    put_u16(&mut archive, parts.len() as u16);
+    put_u16(&mut archive, parts.len() as u16);
+    put_u32(&mut archive, central_directory.len() as u32);
+    put_u32(&mut archive, central_directory_offset);
+    put_u16(&mut archive, 0); // comment length
+
+    Ok(archive)
+}
+
+fn put_u16(buf: &mut Vec<u8>, n: u16) {
+    buf.extend_from_slice(&n.to_le_bytes());
+}
+
+fn put_u32(buf: &mut Vec<u8>, n: u32) {
+    buf.extend_from_slice(&n.to_le_bytes());
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::dataframe::{Column, ColumnData};
+
+    #[test]
+    fn cell_refs() {
+        assert_eq!(cell_ref(0, 0), "A1");
+        assert_eq!(cell_ref(25, 1), "Z2");
+        assert_eq!(cell_ref(26, 2), "AA3");
+        assert_eq!(cell_ref(701, 0), "ZZ1");
+        assert_eq!(cell_ref(702, 0), "AAA1");
+    }
+
+    #[test]
+    fn xlsx_worksheet() {
+        let df = DataFrame::from_vec(vec![
+            Column::new("Name".to_owned(), ColumnData::Text(vec!["a & b".to_owned(), "".to_owned()])),
+            Column::new("Value".to_owned(), ColumnData::NullableInt32(vec![Some(1), None])),
+        ]);
+        let headers = vec!["Name".to_owned(), "Value".to_owned()];
+
+        let sheet = worksheet(&headers, &df);
+        assert!(sheet.contains(r#"<c r="A2" t="inlineStr"><is><t xml:space="preserve">a &amp; b</t></is></c><c r="B2"><v>1</v></c>"#));
+        assert!(sheet.contains(r#"<row r="3"></row>"#));
+
+        let xlsx = format_xlsx("sales", &headers, &df).unwrap();
+        assert_eq!(&xlsx[..4], b"PK\x03\x04");
+        assert_eq!(&xlsx[xlsx.len() - 22..xlsx.len() - 18], b"PK\x05\x06");
+    }
+}
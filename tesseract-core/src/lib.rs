@@ -1,12 +1,21 @@
 mod backend;
+pub mod calculation;
+pub mod comparison;
 mod dataframe;
+pub mod dialect;
+mod engine;
+pub mod masking;
 mod sql;
 pub mod format;
 pub mod format_stream;
+mod format_orc;
+pub mod format_xlsx;
 pub mod names;
 pub mod schema;
 pub mod query;
 pub mod query_ir;
+#[cfg(feature = "wasm")]
+pub mod wasm;
 
 use std::env;
 use failure::{Error, format_err, bail};
@@ -18,16 +27,25 @@ use std::str::FromStr;
 use crate::schema::{SchemaConfigJson, SchemaConfigXML, InlineTableColumnDefinition};
 
 pub use self::backend::Backend;
-pub use self::dataframe::{DataFrame, Column, ColumnData, is_same_columndata_type};
+use self::calculation::{CalculationPlan, Formula, MeasureRef};
+use self::comparison::ComparisonPlan;
+use self::masking::{MaskedColumns, MaskingPlan};
+pub use self::dialect::{SqlDialect, dialect_sql};
+pub use self::engine::Engine;
+pub use self::dataframe::{DataFrame, Column, ColumnData, FillMethod, is_same_columndata_type};
 use self::names::{
     Cut,
     Drilldown,
     Measure,
+    NameResolution,
     Property,
     LevelName,
     Mask,
+    ALL_LEVEL,
+    ALL_MEMBER,
 };
-pub use self::schema::{Schema, Cube, Dimension, Table, Aggregator};
+pub use self::schema::{Schema, Cube, Dimension, Table, Aggregator, TimeGrain};
+use self::schema::{DimensionType, Level};
 use self::schema::metadata::{SchemaMetadata, CubeMetadata};
 use self::query_ir::{
     CutSql,
@@ -38,14 +56,18 @@ use self::query_ir::{
     TableSql,
     LevelColumn,
     TopSql,
+    TopOtherSql,
     TopWhereSql,
     SortSql,
     RcaSql,
     GrowthSql,
     RateSql,
+    RatioToParentSql,
     FilterSql,
+    column_alias,
 };
-pub use self::query::{Query, MeaOrCalc, FilterQuery};
+pub use self::query::{Query, QueryBuilder, QueryErrors, MeaOrCalc, FilterQuery};
+use self::query::{Calculation, SortDirection};
 pub use self::query_ir::QueryIr;
 
 
@@ -68,6 +90,30 @@ impl Schema {
         Schema::from_json(&serialized)
     }
 
+    /// Replaces the standard sql keys of `generated_time` levels with
+    /// `grain_sql(grain, date_column)`, so that backends can use their
+    /// own date functions.
+    pub fn set_time_grain_sql<F>(&mut self, grain_sql: F)
+        where F: Fn(&TimeGrain, &str) -> String
+    {
+        for cube in self.cubes.iter_mut() {
+            for dim in cube.dimensions.iter_mut() {
+                let date_column = match dim.foreign_key {
+                    Some(ref fk) if dim.dim_type == DimensionType::GeneratedTime => fk.clone(),
+                    _ => continue,
+                };
+
+                for hier in dim.hierarchies.iter_mut() {
+                    for level in hier.levels.iter_mut() {
+                        if let Some(grain) = level.time_grain {
+                            level.key_column = grain_sql(&grain, &date_column);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
     /// schema validation
     pub fn validate(&mut self) -> Result<(), Error> {
         // There should be at least one dimension. Both dim and shared dim are optional,
@@ -92,6 +138,161 @@ impl Schema {
             }
         };
 
+        // levels can only be sorted by a column that's in their results
+        for cube in &self.cubes {
+            for dim in &cube.dimensions {
+                for hier in &dim.hierarchies {
+                    for level in &hier.levels {
+                        if let Some(ref order_by) = level.order_by {
+                            if *order_by != level.key_column && Some(order_by) != level.name_column.as_ref() {
+                                bail!("Level {} in cube {} can only be ordered by its key_column or name_column", level.name, cube.name);
+                            }
+                        }
+
+                        if let (Some(min), Some(max)) = (level.min_cardinality, level.max_cardinality) {
+                            if min > max {
+                                bail!("Level {} in cube {} has a min_cardinality above its max_cardinality", level.name, cube.name);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        // measure filters are compiled into conditional aggregation, which only
+        // works for aggregators that are a single aggregate of the column
+        for cube in &self.cubes {
+            for measure in &cube.measures {
+                if measure.filter.is_none() {
+                    continue;
+                }
+                match measure.aggregator {
+                    Aggregator::Sum | Aggregator::Count | Aggregator::Average | Aggregator::Max | Aggregator::Min => (),
+                    _ => bail!("Measure {} in cube {} has a filter, which is only supported for sum, count, avg, max and min aggregators", measure.name, cube.name),
+                }
+            }
+        }
+
+        // drillthrough columns are the headers of the drillthrough results
+        for cube in &self.cubes {
+            let names = cube.drillthrough.iter()
+                .map(|col| &col.name)
+                .collect::<HashSet<_>>();
+
+            if names.len() != cube.drillthrough.len() {
+                bail!("Duplicate drillthrough column names not allowed in cube {}", cube.name);
+            }
+        }
+
+        // calculations are shared by name, and their measures are checked
+        // against each cube using them
+        let calculations = self.calculations.iter()
+            .map(|calc| {
+                let formula = calc.formula.parse::<Formula>()?;
+                Ok((calc.name.as_str(), formula))
+            })
+            .collect::<Result<HashMap<_, _>, Error>>()?;
+
+        if calculations.len() != self.calculations.len() {
+            bail!("Duplicate calculation names not allowed");
+        }
+
+        for cube in &self.cubes {
+            for calc_name in &cube.calculations {
+                let formula = calculations.get(calc_name.as_str())
+                    .ok_or_else(|| format_err!("Cube {} uses calculation {}, which is not in the schema", cube.name, calc_name))?;
+
+                if cube.measures.iter().any(|m| m.name == *calc_name) {
+                    bail!("Calculation {} has the same name as a measure in cube {}", calc_name, cube.name);
+                }
+
+                for measure_ref in formula.measures() {
+                    let measure_cube = match measure_ref.cube {
+                        Some(ref cube_name) => self.cubes.iter()
+                            .find(|c| c.name == *cube_name)
+                            .ok_or_else(|| format_err!("Calculation {} uses cube {}, which does not exist", calc_name, cube_name))?,
+                        None => cube,
+                    };
+
+                    if !measure_cube.measures.iter().any(|m| m.name == measure_ref.measure) {
+                        bail!("Calculation {} uses measure {}, which is not in cube {}", calc_name, measure_ref.measure, measure_cube.name);
+                    }
+                }
+            }
+        }
+
+        // masking rules mask a measure by another of the same cube
+        for cube in &self.cubes {
+            let masked = cube.masking.iter()
+                .map(|rule| &rule.measure)
+                .collect::<HashSet<_>>();
+
+            if masked.len() != cube.masking.len() {
+                bail!("Duplicate masking rules for a measure not allowed in cube {}", cube.name);
+            }
+
+            for rule in &cube.masking {
+                for measure in &[&rule.measure, &rule.by] {
+                    if !cube.measures.iter().any(|m| m.name == **measure) {
+                        bail!("Masking rule uses measure {}, which is not in cube {}", measure, cube.name);
+                    }
+                }
+            }
+        }
+
+        // row filters are cuts, which must apply to at least one cube
+        if let Some(ref security) = self.security {
+            let role_names = security.roles.iter()
+                .map(|role| &role.name)
+                .collect::<HashSet<_>>();
+
+            if role_names.len() != security.roles.len() {
+                bail!("Duplicate role names not allowed");
+            }
+
+            if let Some(ref default_role) = security.default_role {
+                if !role_names.contains(default_role) {
+                    bail!("Default role {} is not one of the roles", default_role);
+                }
+            }
+
+            for role in &security.roles {
+                for row_filter in &role.row_filters {
+                    let cut = row_filter.cut.parse::<Cut>()
+                        .map_err(|err| format_err!("Row filter {} of role {} is not a cut: {}", row_filter.cut, role.name, err))?;
+
+                    match row_filter.cube {
+                        Some(ref cube_name) => {
+                            let cube = self.cubes.iter()
+                                .find(|cube| cube.name == *cube_name)
+                                .ok_or_else(|| format_err!("Row filter of role {} uses cube {}, which does not exist", role.name, cube_name))?;
+
+                            if cube.get_level(&cut.level_name).is_none() {
+                                bail!("Row filter of role {} uses level {}, which is not in cube {}", role.name, cut.level_name, cube.name);
+                            }
+                        },
+                        None => {
+                            if !self.cubes.iter().any(|cube| cube.get_level(&cut.level_name).is_some()) {
+                                bail!("Row filter of role {} uses level {}, which is not in any cube", role.name, cut.level_name);
+                            }
+                        },
+                    }
+                }
+            }
+        }
+
+        // generated time and banded dimensions read their levels from a fact table column
+        for cube in &self.cubes {
+            for dim in &cube.dimensions {
+                if dim.dim_type == DimensionType::GeneratedTime && dim.foreign_key.is_none() {
+                    bail!("Generated time dimension {} in cube {} requires a foreign_key (the date column)", dim.name, cube.name);
+                }
+                if dim.dim_type == DimensionType::Banded && (dim.foreign_key.is_none() || dim.hierarchies.is_empty()) {
+                    bail!("Banded dimension {} in cube {} requires a foreign_key (the column to band) and bands", dim.name, cube.name);
+                }
+            }
+        }
+
         // if there's multiple hierarchies in a dim, there must be a default hierarchy.
         // also, the default hierarchy must match names with an actual hierarchy.
         //
@@ -139,17 +340,21 @@ impl Schema {
         self.into()
     }
 
+    /// Level and property names can repeat across the hierarchies of a
+    /// dimension (the logic layer then uses the default hierarchy's), but
+    /// not across dimensions.
     pub fn has_unique_levels_properties(&self) -> CubeHasUniqueLevelsAndProperties {
         for cube in &self.cubes {
-            let mut levels = HashSet::new();
-            let mut properties = HashSet::new();
+            // names, to the dimension they're in
+            let mut levels = HashMap::new();
+            let mut properties = HashMap::new();
 
             for dimension in &cube.dimensions {
                 for hierarchy in &dimension.hierarchies {
 
                     // Check each cube for unique level and property names
                     for level in &hierarchy.levels {
-                        if !levels.insert(&level.name) {
+                        if *levels.entry(&level.name).or_insert(&dimension.name) != &dimension.name {
                             info!(
                                 "Found repeated level name: {}.{}.{}.{}",
                                 cube.name, dimension.name, hierarchy.name, level.name
@@ -162,7 +367,7 @@ impl Schema {
 
                         if let Some(ref props) = level.properties {
                             for property in props {
-                                if !properties.insert(&property.name) {
+                                if *properties.entry(&property.name).or_insert(&dimension.name) != &dimension.name {
                                     info!(
                                         "Found repeated property name: {}.{}.{}.{}.{}",
                                         cube.name, dimension.name, hierarchy.name, level.name, property.name
@@ -188,7 +393,23 @@ impl Schema {
         level_name: &LevelName,
         ) -> Result<(String, Vec<String>), Error> // Sql and then Header
     {
-        let members_query_ir = self.get_dim_col_table(cube, level_name)?;
+        self.members_sql_where(cube, level_name, "")
+    }
+
+    /// `members_sql`, with a where clause (including the leading space)
+    /// from `members_cut_clause`.
+    fn members_sql_where(
+        &self,
+        cube: &str,
+        level_name: &LevelName,
+        where_clause: &str,
+        ) -> Result<(String, Vec<String>), Error>
+    {
+        let cube = self.get_cube_by_name(cube)?;
+        let level_name = &cube.resolve_level_name(level_name, self.name_resolution)
+            .unwrap_or_else(|| level_name.clone());
+
+        let members_query_ir = self.get_dim_col_table(&cube.name, level_name)?;
 
         let header = if members_query_ir.name_column.is_some() {
             vec!["ID".into(), "Label".into()]
@@ -202,16 +423,58 @@ impl Schema {
             "".into()
         };
 
-        let sql = format!("select distinct {}{}{} from {}",
+        let order_sql = match members_query_ir.order_sql {
+            Some(ref order) => format!(" order by {}", order),
+            None => "".into(),
+        };
+
+        let sql = format!("select distinct {}{}{} from {}{}{}",
             members_query_ir.key_column,
             if members_query_ir.name_column.is_some() { ", " } else { "" },
             name_col,
             members_query_ir.table_sql,
+            where_clause,
+            order_sql,
         );
 
         Ok((sql, header))
     }
 
+    /// Generates SQL to look up the captions of a cut's members, returning
+    /// the `ID` and `Label` of each. `None` if there's nothing to look up:
+    /// the level has no name column (members are their own captions), or
+    /// the cut matches members by pattern.
+    pub fn cut_captions_sql(&self, cube_name: &str, cut: &Cut) -> Result<Option<String>, Error> {
+        if cut.for_match || cut.members.is_empty() || cut.level_name.is_all() {
+            return Ok(None);
+        }
+
+        let cube = self.get_cube_by_name(cube_name)?;
+        let mut cut = cut.clone();
+        if let Some(resolved) = cube.resolve_level_name(&cut.level_name, self.name_resolution) {
+            cut.level_name = resolved;
+        }
+
+        let members_query_ir = self.get_dim_col_table(&cube.name, &cut.level_name)?;
+        if members_query_ir.name_column.is_none() {
+            return Ok(None);
+        }
+
+        // only used for quoting the members according to the level's key type
+        let cut_cols = self.cube_cut_cols(&cube.name, &[cut.clone()])?;
+        let cut_col = cut_cols.first()
+            .ok_or_else(|| format_err!("could not find column for cut {}", cut.level_name))?;
+
+        let where_clause = format!(" where {} in ({})",
+            members_query_ir.key_column,
+            cut_col.members_string(),
+        );
+
+        let (sql, _) = self.members_sql_where(&cube.name, &cut.level_name, &where_clause)?;
+
+        Ok(Some(sql))
+    }
+
     /// Generates SQL to resolve a members locale query.
     /// Supports resolving multiple locales at the same time.
     pub fn members_locale_sql(
@@ -220,12 +483,25 @@ impl Schema {
         level_name: &LevelName,
         locale: &str
     ) -> Result<(String, Vec<String>), Error> // Sql and then Header
+    {
+        self.members_locale_sql_where(cube_name, level_name, locale, "")
+    }
+
+    /// `members_locale_sql`, with a where clause (including the leading
+    /// space) from `members_cut_clause`.
+    fn members_locale_sql_where(
+        &self,
+        cube_name: &str,
+        level_name: &LevelName,
+        locale: &str,
+        where_clause: &str,
+    ) -> Result<(String, Vec<String>), Error>
     {
         let locales: Vec<String> = locale.split(",").map(|s| s.to_string()).collect();
 
-        let cube = self.cubes.iter()
-            .find(|cube| &cube.name == &cube_name)
-            .ok_or(format_err!("Could not find cube"))?;
+        let cube = self.get_cube_by_name(cube_name)?;
+        let level_name = &cube.resolve_level_name(level_name, self.name_resolution)
+            .unwrap_or_else(|| level_name.clone());
 
         let dim = cube.dimensions.iter()
             .find(|dim| dim.name == level_name.dimension)
@@ -284,198 +560,1170 @@ impl Schema {
             table.full_name()
         };
 
-        let sql = format!("select distinct {}{}{} from {} order by {}",
+        let mut columns = name_columns.clone();
+        columns.push(key_column.clone());
+
+        let sql = format!("select distinct {}{}{} from {}{} order by {}",
             key_column,
             if name_columns.len() > 0 { ", " } else { "" },
             name_columns.join(", "),
             table_sql,
-            key_column
+            where_clause,
+            level.order_sql(&columns),
         );
 
         Ok((sql, header))
     }
 
-    /// Convert user parameters into required default member cuts based on cube definition.
-    ///
-    /// Given a cube and user supplied Query parameters and a boolean for negate mode, this function will:
-    ///
-    /// 1. Use the get_dims_for_default_member to determine which dimensions are not involved in
-    /// a cut or drilldown for the query and are therefore candidates for default member filtering logic
-    /// 2. It will read out the default_member strings from relevant hierarchies and parse them into cuts
-    /// 3. Returns a list of the cuts that should be added to the query based on the default members
+    /// Generates SQL for a members query which can also return the
+    /// members' ancestors (`parents`) and localized captions (`locale`,
+    /// comma separated). Ancestors are read from the same hierarchy table,
+    /// so this is still a single query.
     ///
-    /// If the function runs in negate mode, it will add or remove a ~ from the front of the default member
-    /// cut string to flip the logic in order to exclude the default member from being returned in queries.
-    fn build_default_member_cuts(&self, schema_cube: &Cube, query: &Query, negate: bool) -> Result<Vec<Cut>, Error> {
-        let target_dims = self.get_dims_for_default_member(schema_cube, query, negate);
-        let result = target_dims.iter().filter_map(|dim| {
-            let target_hierarchy_name = match &dim.default_hierarchy {
-                Some(hierarchy_name) => hierarchy_name,
-                None => &dim.hierarchies.get(0).unwrap().name
-            };
-            let hierarchy_obj = &dim.hierarchies.iter()
-                .find(|h| &h.name == target_hierarchy_name).expect("bad hierarchy unpacking");
-            let default_member = &hierarchy_obj.default_member;
-
-            default_member.as_ref().map(|val| {
-                let mut new_cut_str: String = val.to_string();
-                if negate {
-                    let first_ch = new_cut_str.chars().next().expect("Expected at least one character in default member");
-                    new_cut_str = match first_ch {
-                        '~' => new_cut_str[1..].to_string(),
-                        _ => format!("~{}", new_cut_str)
-                    }
-
-                }
-                Cut::from_str(&new_cut_str)
-            })
-        })
-        .collect::<Result<Vec<_>,_>>();
-
-        result
-    }
-
-    /// Helper function for build_default_member_cuts to get a list of dimensions.
+    /// Ancestor columns come first, from the top level down, headed
+    /// `<Level> ID` and `<Level> Label` (or `<Level> <LOCALE> Label`).
     ///
-    /// Negate is a boolean value which indicates if the the list is being built for a negate mode query.
-    /// In a negate mode, the idea is to build a cut which will exclude the default member when
-    /// drilling down which is why in negate mode the logic for the dimension filter differs.
-    fn get_dims_for_default_member<'a>(&self, schema_cube: &'a Cube, query: &Query, negate: bool) -> Vec<&'a Dimension> {
-        let dims = schema_cube.dimensions.iter()
-            .filter(|dim| {
-                // filter out dims that have a drilldown or cut
-                let dim_contains_drill = query.drilldowns.iter()
-                    .any(|drill| dim.name == drill.0.dimension());
-                let dim_contains_cut = query.cuts.iter()
-                    .any(|c| dim.name == c.level_name.dimension());
-                match negate {
-                    false => !(dim_contains_drill || dim_contains_cut),
-                    true => dim_contains_drill && !dim_contains_cut
-                }
-            })
-            // Keep only the dims that have a default hierarchy value set
-            // OR have only one hierarchy. Note that due to the schema validation process,
-            // if a dimension has more than one hierarchy, it must have a default hierarchy specified.
-            .filter(|dim| dim.default_hierarchy.is_some() || dim.hierarchies.len() == 1)
-            .collect();
-        dims
-    }
-
-    pub fn sql_query(
+    /// With `cuts`, only members that appear in the facts matching the
+    /// cuts are returned. `role` is the principal's, as for `Query::role`;
+    /// its row filters restrict the members like cuts.
+    pub fn members_context_sql(
         &self,
-        cube: &str,
-        query: &Query,
-        ) -> Result<(QueryIr, Vec<String>), Error>
+        cube_name: &str,
+        level_name: &LevelName,
+        parents: bool,
+        locale: Option<&str>,
+        cuts: &[Cut],
+        role: Option<&str>,
+    ) -> Result<(String, Vec<String>), Error> // Sql and then Header
     {
-        // TODO check that cuts have members:
-        // at the beginning of sql_query, (or maybe on cut parsing?), to make
-        // clear that blank members will trigger default hierarchy behavior in sql generation
-
-        // First do checks, like making sure there's a measure, and that there's
-        // either a cut or drilldown
-        if query.measures.is_empty() && query.rca.is_none() {
-            return Err(format_err!("No measure found; please specify at least one"));
-        }
-        if query.drilldowns.is_empty() && query.cuts.is_empty(){
-            return Err(format_err!("Either a drilldown or cut is required"));
-        }
-
-        // also check that properties have a matching drilldown
-        if let Some(ref rca) = query.rca {
-            let rca_drills = [&rca.drill_1, &rca.drill_2];
-            for property in &query.properties {
-                let has_drill = rca_drills.iter()
-                    .any(|d| d.0 == property.level_name);
+        let mut cuts = cuts.to_vec();
+        cuts.extend(self.row_filter_cuts(self.get_cube_by_name(cube_name)?, role)?);
 
-                if !has_drill {
-                    return Err(format_err!("Property {} has no matching drilldown", property));
-                }
-            }
-        } else {
-            for property in &query.properties {
-                let has_drill = query.drilldowns.iter()
-                    .any(|d| d.0 == property.level_name);
+        let where_clause = self.members_cut_clause(cube_name, level_name, &cuts)?;
 
-                if !has_drill {
-                    return Err(format_err!("Property {} has no matching drilldown", property));
-                }
-            }
+        if !parents {
+            return match locale {
+                Some(locale) => self.members_locale_sql_where(cube_name, level_name, locale, &where_clause),
+                None => self.members_sql_where(cube_name, level_name, &where_clause),
+            };
         }
 
-        // check for default hierarchy that isn't drilled down on. And create a cut for it.
-        // TODO should do this at top, and everything is method on cube, instead of on schema
-        let schema_cube = self.cubes.iter()
-            .find(|c| c.name == cube)
-            .ok_or_else(|| format_err!("schema does not contain cube"))?;
-
-        // Note that the marker for a default hierarchy cuts query is that there are no members
-        let default_hierarchy_cuts_query: Result<Vec<_>, Error> = schema_cube.dimensions.iter()
-            .filter(|dim| {
-                // filter out dims that have a drilldown or cut
-                let dim_contains_drill = query.drilldowns.iter()
-                    .any(|drill| dim.name == drill.0.dimension());
-
-                let dim_contains_cut = query.cuts.iter()
-                    .any(|c| dim.name == c.level_name.dimension());
+        let locales: Option<Vec<String>> = locale
+            .map(|l| l.split(",").map(|s| s.to_string()).collect());
 
-                !(dim_contains_drill || dim_contains_cut)
-            })
-            .filter(|dim| dim.default_hierarchy.is_some())
-            .map(|dim| {
-                // for each default hierarchy, get the lowest level
-                // the join will actually be on primary key, which does the actual
-                // filtering. But this is to be consistent
-                let default_hierarchy = dim.default_hierarchy.clone()
-                    .ok_or_else(|| format_err!("logic err, is_some already checked"))?;
+        let cube = self.get_cube_by_name(cube_name)?;
+        let level_name = &cube.resolve_level_name(level_name, self.name_resolution)
+            .unwrap_or_else(|| level_name.clone());
 
-                let level_name = dim.hierarchies.iter()
-                    .find(|hier| hier.name == default_hierarchy)
-                    .ok_or_else(|| format_err!("logic error, validation occurred for matching default hier"))
-                    .and_then(|hier| {
-                        hier.levels.last()
-                            .ok_or_else(|| format_err!("logic error, must have a level in hier"))
-                    })
-                    .map(|level| level.name.clone())?;
+        let dim = cube.dimensions.iter()
+            .find(|dim| dim.name == level_name.dimension)
+            .ok_or(format_err!("could not find dimension for level name"))?;
+        let hier = dim.hierarchies.iter()
+            .find(|hier| hier.name == level_name.hierarchy)
+            .ok_or(format_err!("could not find hierarchy for level name"))?;
+        let level = hier.levels.iter()
+            .find(|lvl| lvl.name == level_name.level)
+            .ok_or(format_err!("could not find level for level name"))?;
 
-                Ok(Cut::new(dim.name.clone(), default_hierarchy, level_name, vec![], Mask::Include, false))
-            })
-            .collect();
-        let default_hierarchy_cuts_query = default_hierarchy_cuts_query?;
+        let table_sql = if let Some(ref inline) = hier.inline_table {
+            format!("({})", inline.sql_string())
+        } else {
+            hier.table.clone().unwrap_or_else(|| cube.table.clone()).full_name()
+        };
 
+        let mut columns = vec![];
+        let mut header = vec![];
+        let mut order_by = vec![];
 
-        // TODO check that top dim and mea are in here?
-        // TODO check that top_where maps to a mea that's not in top, but is in meas.
+        for parent in cube.get_level_parents(level_name)? {
+            columns.push(parent.key_column.clone());
+            header.push(format!("{} ID", parent.name));
 
-        // for growth, check if time dim and mea are in drilldown and measures
-        if let Some(ref growth) = query.growth {
-            if !query.drilldowns.contains(&growth.time_drill) {
-                bail!("Growth time drilldown {} is not in drilldowns", growth.time_drill);
-            }
-            if !query.measures.contains(&growth.mea) {
-                bail!("Growth measure {} is not in measures", growth.mea);
+            for (label, column) in self.level_label_columns(&parent, &locales) {
+                columns.push(column);
+                header.push(format!("{} {}", parent.name, label));
             }
+
+            order_by.push(parent.order_sql(&columns));
         }
 
-        // for rca, disallow cuts on the second drilldown for now, until better system
-        // is figured out.
-        // There is internal filtering of cuts internally also, which should follow the
-        // pattern of the check here.
-        if let Some(ref rca) = query.rca {
-            let cuts_contain_drill_2 = query.cuts.iter()
-                .any(|c| c.level_name == rca.drill_2.0);
+        columns.push(level.key_column.clone());
+        header.push("ID".into());
 
-            if cuts_contain_drill_2 {
-                return Err(format_err!("Cut on rca drill 2 is not allowed; for rca, \
-                    only external cuts and cuts on drill 1 allowed", ));
-            }
+        for (label, column) in self.level_label_columns(level, &locales) {
+            columns.push(column);
+            header.push(label);
         }
 
-        // now get the database metadata
-        let table = self.cube_table(&cube)
-            .ok_or(format_err!("No table found for cube {}", cube))?;
-
-        let mut cut_cols = self.cube_cut_cols(&cube, &query.cuts)
-            .map_err(|err| format_err!("Error getting cut cols: {}", err))?;
+        order_by.push(level.order_sql(&columns));
+
+        let sql = format!("select distinct {} from {}{} order by {}",
+            columns.join(", "),
+            table_sql,
+            where_clause,
+            order_by.join(", "),
+        );
+
+        Ok((sql, header))
+    }
+
+    /// Where clause (with a leading space) restricting the members of a
+    /// level to the ones in the facts that match `cuts`:
+    /// `where pk in (select fk from fact where <cuts>)`. Empty without cuts.
+    fn members_cut_clause(
+        &self,
+        cube_name: &str,
+        level_name: &LevelName,
+        cuts: &[Cut],
+    ) -> Result<String, Error>
+    {
+        if cuts.is_empty() {
+            return Ok("".to_owned());
+        }
+
+        let cube = self.get_cube_by_name(cube_name)?;
+        let level_name = cube.resolve_level_name(level_name, self.name_resolution)
+            .unwrap_or_else(|| level_name.clone());
+
+        let dim = cube.dimensions.iter()
+            .find(|dim| dim.name == level_name.dimension)
+            .ok_or(format_err!("could not find dimension for level name"))?;
+        let hier = dim.hierarchies.iter()
+            .find(|hier| hier.name == level_name.hierarchy)
+            .ok_or(format_err!("could not find hierarchy for level name"))?;
+        let foreign_key = dim.foreign_key.clone()
+            .ok_or(format_err!("No foreign key for dimension {}", dim.name))?;
+
+        let conditions = self.fact_cut_conditions(cube, cuts)?;
+
+        // levels read from the fact table (e.g. generated time) are cut directly
+        if hier.table.is_none() && hier.inline_table.is_none() {
+            return Ok(format!(" where {}", conditions));
+        }
+
+        Ok(format!(" where {} in (select {} from {} where {})",
+            hier.primary_key,
+            foreign_key,
+            cube.table.full_name(),
+            conditions,
+        ))
+    }
+
+    /// Generates SQL for the distinct values of a property, e.g. for the
+    /// options of a filter on property cuts. `property` is a full property
+    /// name (`Dimension.Hierarchy.Level.Property`), or just the property's
+    /// name when only one level of the cube has it (levels shared by
+    /// hierarchies resolve to the default hierarchy).
+    ///
+    /// With `with_key`, each value comes with the keys of the level's
+    /// members having it, headed `ID`. `role` is the principal's, as for
+    /// `Query::role`; with row filters, only the values of the members in
+    /// the role's facts are returned.
+    pub fn property_members_sql(
+        &self,
+        cube_name: &str,
+        property: &str,
+        with_key: bool,
+        role: Option<&str>,
+    ) -> Result<(String, Vec<String>), Error> // Sql and then Header
+    {
+        let cube = self.get_cube_by_name(cube_name)?;
+        let property = self.resolve_property(cube, property)?;
+        let row_filter_cuts = self.row_filter_cuts(cube, role)?;
+
+        let level = cube.get_level(&property.level_name)
+            .ok_or_else(|| format_err!("could not find level for property {}", property.property))?;
+        let column = level.properties.iter()
+            .flatten()
+            .find(|p| p.name == property.property)
+            .map(|p| p.column.clone())
+            .ok_or_else(|| format_err!("Property {} not found in level {}", property.property, property.level_name))?;
+
+        let members_query_ir = self.get_dim_col_table(&cube.name, &property.level_name)?;
+
+        let (columns, header) = if with_key {
+            (
+                format!("{}, {}", members_query_ir.key_column, column),
+                vec!["ID".to_owned(), property.property.clone()],
+            )
+        } else {
+            (column.clone(), vec![property.property.clone()])
+        };
+
+        let where_clause = self.members_cut_clause(&cube.name, &property.level_name, &row_filter_cuts)?;
+
+        let sql = format!("select distinct {} from {}{} order by {}",
+            columns,
+            members_query_ir.table_sql,
+            where_clause,
+            column,
+        );
+
+        Ok((sql, header))
+    }
+
+    /// Finds a property of `cube` by its full name, or by its name alone.
+    fn resolve_property(&self, cube: &Cube, property: &str) -> Result<Property, Error> {
+        if property.is_empty() {
+            bail!("A property is required");
+        }
+
+        if property.starts_with('[') || property.contains('.') {
+            let mut property: Property = property.parse()?;
+            property.level_name = cube.resolve_level_name(&property.level_name, self.name_resolution)
+                .ok_or_else(|| format_err!("Level {} not found in cube {}", property.level_name, cube.name))?;
+            property.property = cube.resolve_property_name(&property.level_name, &property.property, self.name_resolution)
+                .ok_or_else(|| format_err!("Property {} not found in level {}", property.property, property.level_name))?;
+            return Ok(property);
+        }
+
+        let mut found: Vec<(Property, bool)> = vec![];
+        for exact in &[true, false] {
+            for dimension in &cube.dimensions {
+                for hierarchy in &dimension.hierarchies {
+                    for level in &hierarchy.levels {
+                        for p in level.properties.iter().flatten() {
+                            let matches = if *exact {
+                                p.name == property
+                            } else {
+                                self.name_resolution.matches(&p.name, property)
+                            };
+                            if matches {
+                                found.push((
+                                    Property::new(&dimension.name, &hierarchy.name, &level.name, &p.name),
+                                    dimension.is_default_hierarchy(&hierarchy.name),
+                                ));
+                            }
+                        }
+                    }
+                }
+            }
+
+            // an exact match is always preferred
+            if !found.is_empty() {
+                break;
+            }
+        }
+
+        let one_dimension = found.iter()
+            .all(|(p, _)| p.level_name.dimension == found[0].0.level_name.dimension);
+        if found.len() > 1 && one_dimension {
+            found.retain(|(_, is_default)| *is_default);
+        }
+
+        match found.len() {
+            0 => bail!("Property {} not found in cube {}", property, cube.name),
+            1 => Ok(found.remove(0).0),
+            _ => bail!("Property {} is in several levels of cube {}, use its full name", property, cube.name),
+        }
+    }
+
+    /// Sql for the fact rows matching `cuts`, with the cube's drillthrough
+    /// columns, and the headers for them. At most `limit` rows are returned.
+    /// `role` is the principal's, as for `Query::role`.
+    pub fn drillthrough_sql(
+        &self,
+        cube_name: &str,
+        cuts: &[Cut],
+        role: Option<&str>,
+        limit: u64,
+    ) -> Result<(String, Vec<String>), Error> // Sql and then Header
+    {
+        let cube = self.get_cube_by_name(cube_name)?;
+
+        if cube.drillthrough.is_empty() {
+            bail!("Cube {} has no drillthrough columns", cube.name);
+        }
+        // single fact rows are always below a masking threshold
+        if !cube.masking.is_empty() {
+            bail!("Cube {} has masked measures, so it can't be drilled through", cube.name);
+        }
+
+        let mut cuts = cuts.to_vec();
+        cuts.extend(self.row_filter_cuts(cube, role)?);
+
+        let columns: Vec<_> = cube.drillthrough.iter()
+            .map(|col| col.column.clone())
+            .collect();
+        let header = cube.drillthrough.iter()
+            .map(|col| col.name.clone())
+            .collect();
+
+        let where_clause = if cuts.is_empty() {
+            "".to_owned()
+        } else {
+            format!(" where {}", self.fact_cut_conditions(cube, &cuts)?)
+        };
+
+        let sql = format!("select {} from {}{} limit {}",
+            columns.join(", "),
+            cube.table.full_name(),
+            where_clause,
+            limit,
+        );
+
+        Ok((sql, header))
+    }
+
+    /// Cuts that restrict the queries of `role` on `cube`, from the row
+    /// filters of the schema's security. Without a role, the default role
+    /// is used; if there's neither, or the role isn't in the schema, the
+    /// query is rejected. Schemas without security don't restrict queries.
+    pub fn row_filter_cuts(&self, cube: &Cube, role: Option<&str>) -> Result<Vec<Cut>, Error> {
+        let security = match self.security {
+            Some(ref security) => security,
+            None => return Ok(vec![]),
+        };
+
+        let role_name = role
+            .or_else(|| security.default_role.as_ref().map(|r| r.as_str()))
+            .ok_or_else(|| format_err!("A role is required to query cube {}", cube.name))?;
+
+        let role = security.roles.iter()
+            .find(|r| r.name == role_name)
+            .ok_or_else(|| format_err!("Role {} is not in the schema", role_name))?;
+
+        let mut cuts = vec![];
+
+        for row_filter in &role.row_filters {
+            if let Some(ref cube_name) = row_filter.cube {
+                if *cube_name != cube.name {
+                    continue;
+                }
+            }
+
+            let cut = row_filter.cut.parse::<Cut>()?;
+
+            // unscoped filters only apply to the cubes with their level
+            if row_filter.cube.is_none() && cube.get_level(&cut.level_name).is_none() {
+                continue;
+            }
+
+            cuts.push(cut);
+        }
+
+        Ok(cuts)
+    }
+
+    /// Conditions on the fact table for `cuts`, joined by `and`. Cuts on
+    /// dimension tables become `fk in (select pk from dim where ...)`.
+    fn fact_cut_conditions(&self, cube: &Cube, cuts: &[Cut]) -> Result<String, Error> {
+        let cuts: Vec<_> = cuts.iter()
+            .map(|cut| {
+                let mut cut = cut.clone();
+                if let Some(resolved) = cube.resolve_level_name(&cut.level_name, self.name_resolution) {
+                    cut.level_name = resolved;
+                }
+                cut
+            })
+            .collect();
+        let cut_cols = self.cube_cut_cols(&cube.name, &cuts)?;
+
+        let conditions = cut_cols.iter()
+            .map(|cut| {
+                let condition = if cut.for_match {
+                    cut.members_like_string()
+                } else {
+                    format!("{} {} ({})", cut.column, cut.mask_sql_in_string(), cut.members_string())
+                };
+
+                if cut.table.name == cube.table.name && cut.inline_table.is_none() {
+                    return condition;
+                }
+
+                let cut_table = match cut.inline_table {
+                    Some(ref inline) => format!("({}) as {}", inline.sql_string(), cut.table.full_name()),
+                    None => cut.table.full_name(),
+                };
+
+                format!("{} in (select {} from {} where {})",
+                    cut.foreign_key,
+                    cut.primary_key,
+                    cut_table,
+                    condition,
+                )
+            });
+
+        Ok(conditions.collect::<Vec<_>>().join(" and "))
+    }
+
+    /// Label columns for a level, as (header, column). Without locales
+    /// this is the name column; with locales it's the caption property
+    /// for each locale, falling back to the name column for the default
+    /// locale.
+    fn level_label_columns(&self, level: &Level, locales: &Option<Vec<String>>) -> Vec<(String, String)> {
+        let locales = match locales {
+            Some(locales) => locales,
+            None => {
+                return level.name_column.iter()
+                    .map(|col| ("Label".to_owned(), col.clone()))
+                    .collect();
+            },
+        };
+
+        let mut label_columns = vec![];
+
+        for locale in locales {
+            let caption_column = level.properties.iter()
+                .flatten()
+                .find(|property| property.caption_set.as_ref() == Some(locale))
+                .map(|property| property.column.clone());
+
+            let caption_column = match caption_column {
+                Some(column) => Some(column),
+                None if locale == &self.default_locale => level.name_column.clone(),
+                None => None,
+            };
+
+            if let Some(column) = caption_column {
+                label_columns.push((format!("{} Label", locale.to_uppercase()), column));
+            }
+        }
+
+        label_columns
+    }
+
+    /// Convert user parameters into required default member cuts based on cube definition.
+    ///
+    /// Given a cube and user supplied Query parameters and a boolean for negate mode, this function will:
+    ///
+    /// 1. Use the get_dims_for_default_member to determine which dimensions are not involved in
+    /// a cut or drilldown for the query and are therefore candidates for default member filtering logic
+    /// 2. It will read out the default_member strings from relevant hierarchies and parse them into cuts
+    /// 3. Returns a list of the cuts that should be added to the query based on the default members
+    ///
+    /// If the function runs in negate mode, it will add or remove a ~ from the front of the default member
+    /// cut string to flip the logic in order to exclude the default member from being returned in queries.
+    fn build_default_member_cuts(&self, schema_cube: &Cube, query: &Query, negate: bool) -> Result<Vec<Cut>, Error> {
+        let target_dims = self.get_dims_for_default_member(schema_cube, query, negate);
+        let result = target_dims.iter().filter_map(|dim| {
+            let target_hierarchy_name = match &dim.default_hierarchy {
+                Some(hierarchy_name) => hierarchy_name,
+                None => &dim.hierarchies.get(0).unwrap().name
+            };
+            let hierarchy_obj = &dim.hierarchies.iter()
+                .find(|h| &h.name == target_hierarchy_name).expect("bad hierarchy unpacking");
+            let default_member = &hierarchy_obj.default_member;
+
+            default_member.as_ref().map(|val| {
+                let mut new_cut_str: String = val.to_string();
+                if negate {
+                    let first_ch = new_cut_str.chars().next().expect("Expected at least one character in default member");
+                    new_cut_str = match first_ch {
+                        '~' => new_cut_str[1..].to_string(),
+                        _ => format!("~{}", new_cut_str)
+                    }
+
+                }
+                Cut::from_str(&new_cut_str)
+            })
+        })
+        .collect::<Result<Vec<_>,_>>();
+
+        result
+    }
+
+    /// Helper function for build_default_member_cuts to get a list of dimensions.
+    ///
+    /// Negate is a boolean value which indicates if the the list is being built for a negate mode query.
+    /// In a negate mode, the idea is to build a cut which will exclude the default member when
+    /// drilling down which is why in negate mode the logic for the dimension filter differs.
+    fn get_dims_for_default_member<'a>(&self, schema_cube: &'a Cube, query: &Query, negate: bool) -> Vec<&'a Dimension> {
+        let dims = schema_cube.dimensions.iter()
+            .filter(|dim| {
+                // filter out dims that have a drilldown or cut
+                let dim_contains_drill = query.drilldowns.iter()
+                    .any(|drill| dim.name == drill.0.dimension())
+                    || query.property_drilldowns.iter()
+                    .any(|p| dim.name == p.level_name.dimension());
+                let dim_contains_cut = query.cuts.iter()
+                    .any(|c| dim.name == c.level_name.dimension());
+                match negate {
+                    false => !(dim_contains_drill || dim_contains_cut),
+                    true => dim_contains_drill && !dim_contains_cut
+                }
+            })
+            // Keep only the dims that have a default hierarchy value set
+            // OR have only one hierarchy. Note that due to the schema validation process,
+            // if a dimension has more than one hierarchy, it must have a default hierarchy specified.
+            .filter(|dim| dim.default_hierarchy.is_some() || dim.hierarchies.len() == 1)
+            .collect();
+        dims
+    }
+
+    /// Rewrites the names in a query to the names in the cube, according
+    /// to the schema's `name_resolution`. Names that don't resolve are
+    /// left as they are, for validation to report.
+    pub fn resolve_query(&self, cube: &Cube, query: &Query) -> Query {
+        let mut query = query.clone();
+
+        if self.name_resolution == NameResolution::Exact {
+            return query;
+        }
+
+        let resolution = self.name_resolution;
+        let level = |level_name: &mut LevelName| {
+            if let Some(resolved) = cube.resolve_level_name(level_name, resolution) {
+                *level_name = resolved;
+            }
+        };
+        let measure = |measure: &mut Measure| {
+            if let Some(resolved) = cube.resolve_measure_name(&measure.0, resolution) {
+                measure.0 = resolved;
+            }
+        };
+        let mea_or_calc = |m: &mut MeaOrCalc| {
+            if let MeaOrCalc::Mea(m) = m {
+                measure(m);
+            }
+        };
+        let property = |property: &mut Property| {
+            level(&mut property.level_name);
+            if let Some(resolved) = cube.resolve_property_name(&property.level_name, &property.property, resolution) {
+                property.property = resolved;
+            }
+        };
+
+        query.drilldowns.iter_mut().for_each(|d| level(&mut d.0));
+        query.cuts.iter_mut().for_each(|c| level(&mut c.level_name));
+        query.measures.iter_mut().for_each(|m| measure(m));
+        query.property_drilldowns.iter_mut().for_each(|p| property(p));
+        query.properties.iter_mut().for_each(|p| property(p));
+        query.captions.iter_mut().for_each(|p| property(p));
+        query.filters.iter_mut().for_each(|f| mea_or_calc(&mut f.by_mea_or_calc));
+
+        if let Some(ref mut top) = query.top {
+            level(&mut top.by_dimension);
+            top.sort_mea_or_calc.iter_mut().for_each(|m| mea_or_calc(m));
+        }
+        if let Some(ref mut top_where) = query.top_where {
+            mea_or_calc(&mut top_where.by_mea_or_calc);
+        }
+        if let Some(ref mut sort) = query.sort {
+            measure(&mut sort.measure);
+        }
+        if let Some(ref mut rca) = query.rca {
+            level(&mut rca.drill_1.0);
+            level(&mut rca.drill_2.0);
+            measure(&mut rca.mea);
+        }
+        if let Some(ref mut growth) = query.growth {
+            level(&mut growth.time_drill.0);
+            measure(&mut growth.mea);
+        }
+        if let Some(ref mut rate) = query.rate {
+            level(&mut rate.level_name);
+        }
+        if let Some(ref mut ratio) = query.ratio_to_parent {
+            level(&mut ratio.drill.0);
+            measure(&mut ratio.mea);
+        }
+        if let Some(ref mut rate_against) = query.rate_against {
+            measure(&mut rate_against.mea);
+        }
+        if let Some(ref mut compare) = query.compare {
+            compare.cuts_a.iter_mut()
+                .chain(compare.cuts_b.iter_mut())
+                .for_each(|c| level(&mut c.level_name));
+        }
+
+        query
+    }
+
+    /// Checks a query against a cube. Unlike sql generation, which stops
+    /// at the first problem, this collects all of them (including the
+    /// ones from `Query::validate`) into a `QueryErrors`.
+    pub fn validate_query(&self, cube: &Cube, query: &Query) -> Result<(), Error> {
+        let mut errors = query.errors();
+
+        // the other cube's measure is fetched separately, see `calculation_plan`
+        if query.rate_against.is_some() {
+            errors.push("rate_against can only be computed by an aggregate query".to_owned());
+        }
+        // the two sets of cuts are queried separately, see `comparison_plan`
+        if query.compare.is_some() {
+            errors.push("compare can only be computed by an aggregate query".to_owned());
+        }
+
+        for measure in &query.measures {
+            if !cube.measures.iter().any(|m| m.name == measure.0) {
+                errors.push(format!("Measure {} not found in cube {}{}",
+                    measure,
+                    cube.name,
+                    names::suggestion(&measure.0, cube.measures.iter().map(|m| m.name.as_str())),
+                ));
+            }
+        }
+
+        let level_suggestion = |name: &LevelName| level_suggestion(cube, name);
+
+        for drill in &query.drilldowns {
+            if drill.0.is_all() {
+                let has_hierarchy = cube.dimensions.iter()
+                    .filter(|dim| dim.name == drill.0.dimension)
+                    .flat_map(|dim| dim.hierarchies.iter())
+                    .any(|hier| hier.name == drill.0.hierarchy);
+
+                if !has_hierarchy {
+                    errors.push(format!("Drilldown {} not found in cube {}", drill, cube.name));
+                }
+            } else if cube.get_level(&drill.0).is_none() {
+                errors.push(format!("Drilldown {} not found in cube {}{}",
+                    drill,
+                    cube.name,
+                    level_suggestion(&drill.0),
+                ));
+            }
+        }
+
+        // the (All) level has no column to partition or compare on
+        let calculation_levels = query.top.iter().map(|t| &t.by_dimension)
+            .chain(query.growth.iter().map(|g| &g.time_drill.0))
+            .chain(query.rca.iter().flat_map(|r| vec![&r.drill_1.0, &r.drill_2.0]))
+            .chain(query.rate.iter().map(|r| &r.level_name));
+
+        for level_name in calculation_levels.filter(|l| l.is_all()) {
+            errors.push(format!("{} level {} cannot be used for top, growth, rca or rate", ALL_LEVEL, level_name));
+        }
+
+        if query.fill.is_some() && !query.drilldowns.iter().any(|d| is_time_drill(cube, d)) {
+            errors.push("fill requires a drilldown on a time dimension".to_owned());
+        }
+
+        for cut in &query.cuts {
+            if cube.get_level(&cut.level_name).is_none() {
+                errors.push(format!("Cut level {} not found in cube {}{}",
+                    cut.level_name,
+                    cube.name,
+                    level_suggestion(&cut.level_name),
+                ));
+            }
+        }
+
+        for property in query.property_drilldowns.iter().chain(&query.properties).chain(&query.captions) {
+            let level_properties = cube.get_level(&property.level_name)
+                .and_then(|level| level.properties)
+                .unwrap_or_default();
+
+            if !level_properties.iter().any(|p| p.name == property.property) {
+                errors.push(format!("Property {} not found in cube {}{}",
+                    property,
+                    cube.name,
+                    names::suggestion(&property.property, level_properties.iter().map(|p| p.name.as_str())),
+                ));
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(QueryErrors(errors).into())
+        }
+    }
+
+    /// Checks that the levels of a query's drilldowns and cuts (after name
+    /// resolution) are in the cube, for servers to tell an unknown level
+    /// apart from an otherwise invalid query. Errors on the first level
+    /// that isn't.
+    pub fn check_levels(&self, cube: &Cube, query: &Query) -> Result<(), Error> {
+        let query = self.resolve_query(cube, query);
+
+        let levels = query.drilldowns.iter()
+            .map(|d| &d.0)
+            .filter(|l| !l.is_all())
+            .chain(query.cuts.iter().map(|c| &c.level_name));
+
+        for level_name in levels {
+            if cube.get_level(level_name).is_none() {
+                bail!("Level {} not found in cube {}{}",
+                    level_name,
+                    cube.name,
+                    level_suggestion(cube, level_name),
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Fills the gaps in the time series of an aggregate result, for
+    /// queries with `fill` (see `DataFrame::fill_gaps`). The time columns are
+    /// those of the query's time drilldowns, and the measure columns get
+    /// filled in. Expects the DataFrame's columns to be named with the
+    /// headers from `sql_query`.
+    pub fn fill_time_gaps(&self, cube: &str, query: &Query, df: &mut DataFrame) -> Result<(), Error> {
+        let method = match query.fill {
+            Some(method) => method,
+            None => return Ok(()),
+        };
+
+        let schema_cube = self.get_cube_by_name(cube)?;
+        let query = self.resolve_query(schema_cube, query).deduplicated();
+
+        let time_drills: Vec<_> = query.drilldowns.iter()
+            .filter(|d| is_time_drill(schema_cube, d))
+            .cloned()
+            .collect();
+
+        let time_headers = self.cube_drill_headers(&schema_cube.name, &time_drills, &query.properties, &query.captions, query.parents)?;
+        let mea_headers = self.cube_mea_headers(&schema_cube.name, &query.measures)?;
+
+        df.fill_gaps(&time_headers, &mea_headers, method)
+    }
+
+    /// Positions of an aggregate result's columns in the default column
+    /// order: time drilldowns, then the other drilldowns in the order
+    /// requested, then properties, then measures and any other columns, in
+    /// their original order. `headers` are the result's columns, named with
+    /// the headers from `sql_query`.
+    pub fn column_order(&self, cube: &str, query: &Query, headers: &[String]) -> Result<Vec<usize>, Error> {
+        let schema_cube = self.get_cube_by_name(cube)?;
+        let query = self.resolve_query(schema_cube, query).deduplicated();
+
+        let (time_drills, other_drills): (Vec<_>, Vec<_>) = query.drilldowns.iter()
+            .cloned()
+            .partition(|d| is_time_drill(schema_cube, d));
+
+        let mut names = vec![];
+        for drill in time_drills.into_iter().chain(other_drills) {
+            names.extend(self.cube_drill_headers(&schema_cube.name, &[drill], &[], &query.captions, query.parents)?);
+        }
+        names.extend(query.properties.iter()
+            .filter(|p| query.drilldowns.iter().any(|d| d.0 == p.level_name))
+            .map(|p| p.property.clone())
+        );
+        names.extend(query.property_drilldowns.iter().map(|p| p.property.clone()));
+
+        let mut order: Vec<usize> = vec![];
+        for name in &names {
+            let idx = headers.iter().enumerate()
+                .position(|(i, header)| header == name && !order.contains(&i));
+
+            if let Some(idx) = idx {
+                order.push(idx);
+            }
+        }
+
+        let rest: Vec<usize> = (0..headers.len())
+            .filter(|i| !order.contains(i))
+            .collect();
+        order.extend(rest);
+
+        Ok(order)
+    }
+
+    /// Plans a query asking for some of the cube's calculations among its
+    /// measures, or for a `rate_against`, or returns `None` if it doesn't.
+    /// The plan's queries fetch the measures the calculations use, from
+    /// this cube and from other cubes, with the same drilldowns and cuts;
+    /// other cubes must have those levels.
+    pub fn calculation_plan(&self, cube: &str, query: &Query) -> Result<Option<CalculationPlan>, Error> {
+        let schema_cube = self.get_cube_by_name(cube)?;
+        let query = self.resolve_query(schema_cube, query);
+
+        let find_calculation = |measure: &Measure| {
+            self.calculations.iter()
+                .filter(|calc| schema_cube.calculations.contains(&calc.name))
+                .find(|calc| calc.name == measure.0 || self.name_resolution.matches(&calc.name, &measure.0))
+        };
+
+        if query.rate_against.is_none() && !query.measures.iter().any(|m| find_calculation(m).is_some()) {
+            return Ok(None);
+        }
+
+        if query.growth.is_some() || query.rca.is_some() || query.rate.is_some() || query.ratio_to_parent.is_some() {
+            bail!("Calculations cannot be used with growth, rca, rate or ratio_to_parent");
+        }
+        if query.top.is_some() || query.top_where.is_some() || !query.filters.is_empty() || query.fill.is_some() {
+            bail!("Calculations cannot be used with top, top_where, filters or fill");
+        }
+        if let Some(ref sort) = query.sort {
+            if find_calculation(&sort.measure).is_some() {
+                bail!("Cannot sort on calculation {}", sort.measure.0);
+            }
+        }
+
+        let mut plan = CalculationPlan {
+            query: query.clone(),
+            external: vec![],
+            columns: vec![],
+            calculations: HashMap::new(),
+        };
+        plan.query.measures = vec![];
+        plan.query.rate_against = None;
+
+        for measure in &query.measures {
+            match find_calculation(measure) {
+                Some(calc) => {
+                    let formula = calc.formula.parse::<Formula>()?;
+                    self.plan_formula(schema_cube, &query, &mut plan, &calc.name, formula)?;
+                },
+                None => {
+                    CalculationPlan::add_measure(&mut plan.query, &measure.0);
+                    plan.columns.push(measure.0.clone());
+                },
+            }
+        }
+
+        if let Some(ref rate_against) = query.rate_against {
+            let against_cube = self.get_cube_by_name(&rate_against.cube)
+                .map_err(|_| format_err!("Could not find cube {} for rate_against", rate_against.cube))?;
+            let against = against_cube.resolve_measure_name(&rate_against.against.0, self.name_resolution)
+                .ok_or_else(|| format_err!("Rate against measure {} is not in cube {}", rate_against.against, against_cube.name))?;
+
+            let formula = Formula::Div(
+                Box::new(Formula::Measure(MeasureRef { cube: None, measure: rate_against.mea.0.clone() })),
+                Box::new(Formula::Measure(MeasureRef { cube: Some(against_cube.name.clone()), measure: against })),
+            );
+            self.plan_formula(schema_cube, &query, &mut plan, &rate_against.header(), formula)?;
+        }
+
+        if plan.query.measures.is_empty() {
+            bail!("Calculations need at least one measure of cube {} in the query", schema_cube.name);
+        }
+
+        Ok(Some(plan))
+    }
+
+    /// Plans the masking of a query on a cube with `masking` rules, or
+    /// returns `None` if the query asks for none of the masked measures,
+    /// nor for anything calculated from them. The `by` measures of the
+    /// rules are added to the front of the query's measures, and dropped
+    /// from the results by the plan.
+    ///
+    /// Queries can't filter on masked columns, which would reveal their
+    /// values, nor compute a rate of a masked measure.
+    pub fn masking_plan(&self, cube: &str, query: &mut Query) -> Result<Option<MaskingPlan>, Error> {
+        let schema_cube = self.get_cube_by_name(cube)?;
+        if schema_cube.masking.is_empty() {
+            return Ok(None);
+        }
+
+        let resolved = self.resolve_query(schema_cube, query);
+
+        let calculations = self.calculations.iter()
+            .filter(|calc| schema_cube.calculations.contains(&calc.name))
+            .filter(|calc| resolved.measures.iter().any(|m| calc.name == m.0 || self.name_resolution.matches(&calc.name, &m.0)))
+            .map(|calc| {
+                let mut formula = calc.formula.parse::<Formula>()?;
+                formula.localize(&schema_cube.name);
+                Ok((calc.name.as_str(), formula))
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+
+        // measures from other cubes are fetched by separate queries, which
+        // aren't masked
+        let is_masked = |cube_name: &str, measure: &str| {
+            self.get_cube_by_name(cube_name)
+                .map(|cube| cube.masking.iter().any(|rule| rule.measure == measure))
+                .unwrap_or(false)
+        };
+        for (name, formula) in &calculations {
+            for measure_ref in formula.measures() {
+                if let Some(ref cube_name) = measure_ref.cube {
+                    if is_masked(cube_name, &measure_ref.measure) {
+                        bail!("Calculation {} uses measure {} of cube {}, which is masked", name, measure_ref.measure, cube_name);
+                    }
+                }
+            }
+        }
+        if let Some(ref rate_against) = resolved.rate_against {
+            let against = self.get_cube_by_name(&rate_against.cube).ok()
+                .and_then(|cube| cube.resolve_measure_name(&rate_against.against.0, self.name_resolution));
+            if let Some(against) = against {
+                if is_masked(&rate_against.cube, &against) {
+                    bail!("Rate against measure {} of cube {} is masked", against, rate_against.cube);
+                }
+            }
+        }
+
+        let mut rules = vec![];
+        for rule in &schema_cube.masking {
+            let measure = &rule.measure;
+            let mut columns = vec![];
+
+            if resolved.measures.iter().any(|m| m.0 == *measure) {
+                columns.push(measure.clone());
+            }
+            if let Some(ref growth) = resolved.growth {
+                if growth.mea.0 == *measure {
+                    columns.push(format!("{} Growth", measure));
+                    columns.push(format!("{} Growth Value", measure));
+                }
+            }
+            if let Some(ref rca) = resolved.rca {
+                if rca.mea.0 == *measure {
+                    columns.push(format!("{} RCA", measure));
+                }
+            }
+            if let Some(ref ratio) = resolved.ratio_to_parent {
+                if ratio.mea.0 == *measure {
+                    columns.push(format!("{} Ratio to Parent", measure));
+                }
+            }
+            if let Some(ref rate_against) = resolved.rate_against {
+                if rate_against.mea.0 == *measure {
+                    columns.push(rate_against.header());
+                }
+            }
+            for (name, formula) in &calculations {
+                if formula.measures().iter().any(|m| m.cube.is_none() && m.measure == *measure) {
+                    columns.push((*name).to_owned());
+                }
+            }
+
+            if columns.is_empty() {
+                continue;
+            }
+            // rates take a single measure, so there's no room for `by`
+            if resolved.rate.is_some() {
+                bail!("Rate cannot be used with masked measure {}", measure);
+            }
+
+            rules.push(MaskedColumns {
+                columns,
+                by: rule.by.clone(),
+                min: rule.min,
+                complementary: rule.complementary,
+            });
+        }
+
+        if rules.is_empty() {
+            return Ok(None);
+        }
+
+        let filtered = resolved.filters.iter()
+            .map(|filter| &filter.by_mea_or_calc)
+            .chain(resolved.top_where.iter().map(|top_where| &top_where.by_mea_or_calc));
+
+        for mea_or_calc in filtered {
+            let header = match mea_or_calc {
+                MeaOrCalc::Mea(measure) => Some(measure.0.clone()),
+                MeaOrCalc::Calc(Calculation::Growth) => resolved.growth.as_ref().map(|growth| format!("{} Growth", growth.mea.0)),
+                MeaOrCalc::Calc(Calculation::Rca) => resolved.rca.as_ref().map(|rca| format!("{} RCA", rca.mea.0)),
+                MeaOrCalc::Calc(Calculation::Rate) => None,
+            };
+
+            if let Some(header) = header {
+                if rules.iter().any(|rule| rule.columns.contains(&header)) {
+                    bail!("Cannot filter on masked column {}", header);
+                }
+            }
+        }
+
+        let mut added: Vec<String> = vec![];
+        for rule in &rules {
+            if !resolved.measures.iter().any(|m| m.0 == rule.by) && !added.contains(&rule.by) {
+                added.push(rule.by.clone());
+            }
+        }
+        for (i, by) in added.iter().enumerate() {
+            query.measures.insert(i, Measure::new(by.as_str()));
+        }
+
+        // for complementary suppression; rca drilldowns also split the rows
+        // into groups
+        let drills = if rules.iter().any(|rule| rule.complementary) {
+            resolved.drilldowns.iter()
+                .chain(resolved.rca.iter().flat_map(|rca| vec![&rca.drill_1, &rca.drill_2]))
+                .map(|drill| {
+                    self.cube_drill_headers(&schema_cube.name, &[drill.clone()], &resolved.properties, &resolved.captions, resolved.parents)
+                })
+                .collect::<Result<Vec<_>, Error>>()?
+        } else {
+            vec![]
+        };
+
+        Ok(Some(MaskingPlan { rules, added, drills }))
+    }
+
+    /// Plans a query comparing its measures between two sets of cuts (its
+    /// `compare`), or returns `None` if it has none. Each of the plan's
+    /// queries is the original one with a set of cuts added.
+    ///
+    /// The results are matched row by row, so nothing computed over the
+    /// rows of each query, or that drops rows, can be used.
+    pub fn comparison_plan(&self, cube: &str, query: &Query) -> Result<Option<ComparisonPlan>, Error> {
+        if query.compare.is_none() {
+            return Ok(None);
+        }
+
+        let schema_cube = self.get_cube_by_name(cube)?;
+        let query = self.resolve_query(schema_cube, query);
+
+        if query.growth.is_some() || query.rca.is_some() || query.rate.is_some() || query.ratio_to_parent.is_some() || query.rate_against.is_some() {
+            bail!("compare cannot be used with growth, rca, rate, ratio_to_parent or rate_against");
+        }
+        if query.top.is_some() || query.top_where.is_some() || !query.filters.is_empty() || query.sort.is_some() || query.limit.is_some() || query.fill.is_some() {
+            bail!("compare cannot be used with top, top_where, filters, sort, limit or fill");
+        }
+        if !schema_cube.masking.is_empty() {
+            bail!("compare cannot be used on cube {}, which has masking rules", schema_cube.name);
+        }
+
+        let is_calculation = |measure: &Measure| {
+            self.calculations.iter()
+                .filter(|calc| schema_cube.calculations.contains(&calc.name))
+                .any(|calc| calc.name == measure.0 || self.name_resolution.matches(&calc.name, &measure.0))
+        };
+        if let Some(measure) = query.measures.iter().find(|m| is_calculation(m)) {
+            bail!("compare cannot be used with calculation {}", measure);
+        }
+        if query.measures.is_empty() {
+            bail!("compare needs at least one measure");
+        }
+
+        let mut query_a = query.clone();
+        query_a.compare = None;
+        let mut query_b = query_a.clone();
+
+        if let Some(ref compare) = query.compare {
+            query_a.cuts.extend(compare.cuts_a.iter().cloned());
+            query_b.cuts.extend(compare.cuts_b.iter().cloned());
+        }
+
+        let measures = self.cube_mea_headers(&schema_cube.name, &query.measures)?;
+
+        Ok(Some(ComparisonPlan { query_a, query_b, measures }))
+    }
+
+    /// Adds a calculation to `plan`, with the measures its formula uses
+    /// added to the plan's queries.
+    fn plan_formula(
+        &self,
+        schema_cube: &Cube,
+        query: &Query,
+        plan: &mut CalculationPlan,
+        name: &str,
+        mut formula: Formula,
+        ) -> Result<(), Error>
+    {
+        formula.localize(&schema_cube.name);
+
+        for measure_ref in formula.measures() {
+            let cube_name = match measure_ref.cube {
+                Some(ref cube_name) => cube_name,
+                None => {
+                    CalculationPlan::add_measure(&mut plan.query, &measure_ref.measure);
+                    continue;
+                },
+            };
+
+            let idx = match plan.external.iter().position(|(c, _)| c == cube_name) {
+                Some(idx) => idx,
+                None => {
+                    let ext_cube = self.get_cube_by_name(cube_name)?;
+                    let levels = query.drilldowns.iter().map(|d| &d.0)
+                        .chain(query.cuts.iter().map(|c| &c.level_name));
+
+                    for level_name in levels {
+                        if ext_cube.get_level(level_name).is_none() {
+                            bail!("{} can't be used with {}, which is not in cube {}", name, level_name, cube_name);
+                        }
+                    }
+
+                    let mut ext_query = Query::new();
+                    ext_query.drilldowns = query.drilldowns.clone();
+                    ext_query.cuts = query.cuts.clone();
+                    ext_query.exclude_default_members = query.exclude_default_members;
+                    ext_query.role = query.role.clone();
+                    plan.external.push((cube_name.clone(), ext_query));
+                    plan.external.len() - 1
+                },
+            };
+
+            CalculationPlan::add_measure(&mut plan.external[idx].1, &measure_ref.measure);
+        }
+
+        plan.columns.push(name.to_owned());
+        plan.calculations.insert(name.to_owned(), formula);
+
+        Ok(())
+    }
+
+    pub fn sql_query(
+        &self,
+        cube: &str,
+        query: &Query,
+        ) -> Result<(QueryIr, Vec<String>), Error>
+    {
+        // TODO check that cuts have members:
+        // at the beginning of sql_query, (or maybe on cut parsing?), to make
+        // clear that blank members will trigger default hierarchy behavior in sql generation
+
+        let schema_cube = self.get_cube_by_name(cube)
+            .map_err(|_| format_err!("schema does not contain cube"))?;
+        let cube = schema_cube.name.as_str();
+
+        // checked first, so that queries the role can't run don't reveal
+        // anything else about the cube
+        let row_filter_cuts = self.row_filter_cuts(schema_cube, query.role.as_ref().map(|r| r.as_str()))?;
+
+        // repeats would generate duplicate columns, and the headers are
+        // generated from the same query
+        let query = &self.resolve_query(schema_cube, query).deduplicated();
+
+        self.validate_query(schema_cube, query)?;
+
+        // rca drops cuts on its drilldowns from some of its aggregations,
+        // which would then include rows outside the row filters
+        if let Some(ref rca) = query.rca {
+            let rca_dims = [rca.drill_1.0.dimension(), rca.drill_2.0.dimension()];
+            if let Some(cut) = row_filter_cuts.iter().find(|cut| rca_dims.contains(&cut.level_name.dimension())) {
+                bail!("rca cannot drill down on dimension {}, which the role's row filters restrict", cut.level_name.dimension());
+            }
+        }
+
+        // check for default hierarchy that isn't drilled down on. And create a cut for it.
+        // TODO should do this at top, and everything is method on cube, instead of on schema
+
+        // Note that the marker for a default hierarchy cuts query is that there are no members
+        let default_hierarchy_cuts_query: Result<Vec<_>, Error> = schema_cube.dimensions.iter()
+            .filter(|dim| {
+                // filter out dims that have a drilldown or cut. A drilldown
+                // on the (All) level doesn't restrict the dimension.
+                let dim_contains_drill = query.drilldowns.iter()
+                    .any(|drill| dim.name == drill.0.dimension() && !drill.0.is_all())
+                    || query.property_drilldowns.iter()
+                    .any(|p| dim.name == p.level_name.dimension());
+
+                let dim_contains_cut = query.cuts.iter()
+                    .any(|c| dim.name == c.level_name.dimension());
+
+                !(dim_contains_drill || dim_contains_cut)
+            })
+            .filter(|dim| dim.default_hierarchy.is_some())
+            // all the hierarchies of a generated time dimension read from
+            // the fact table, so there's nothing to restrict
+            .filter(|dim| dim.dim_type != DimensionType::GeneratedTime)
+            .map(|dim| {
+                // for each default hierarchy, get the lowest level
+                // the join will actually be on primary key, which does the actual
+                // filtering. But this is to be consistent
+                let default_hierarchy = dim.default_hierarchy.clone()
+                    .ok_or_else(|| format_err!("logic err, is_some already checked"))?;
+
+                let level_name = dim.hierarchies.iter()
+                    .find(|hier| hier.name == default_hierarchy)
+                    .ok_or_else(|| format_err!("logic error, validation occurred for matching default hier"))
+                    .and_then(|hier| {
+                        hier.levels.last()
+                            .ok_or_else(|| format_err!("logic error, must have a level in hier"))
+                    })
+                    .map(|level| level.name.clone())?;
+
+                Ok(Cut::new(dim.name.clone(), default_hierarchy, level_name, vec![], Mask::Include, false))
+            })
+            .collect();
+        let default_hierarchy_cuts_query = default_hierarchy_cuts_query?;
+
+
+        // now get the database metadata
+        let table = self.cube_table(&cube)
+            .ok_or(format_err!("No table found for cube {}", cube))?;
+
+        let mut cut_cols = self.cube_cut_cols(&cube, &query.cuts)
+            .map_err(|err| format_err!("Error getting cut cols: {}", err))?;
 
         let default_hierarchy_cut_cols = self.cube_cut_cols(&cube, &default_hierarchy_cuts_query)
             .map_err(|err| format_err!("Error getting cut cols for default hierarchy: {}", err))?;
@@ -494,10 +1742,20 @@ impl Schema {
             cut_cols.extend_from_slice(&exclude_default_member_cut_cols);
         }
 
+        // row filters are and-ed with the query's own cuts, so the query
+        // can narrow them down but not widen them
+        let row_filter_cut_cols = self.cube_cut_cols(&cube, &row_filter_cuts)
+            .map_err(|err| format_err!("Error creating cuts for row filters: {}", err))?;
+        cut_cols.extend_from_slice(&row_filter_cut_cols);
+
 
-        let drill_cols = self.cube_drill_cols(&cube, &query.drilldowns, &query.properties, &query.captions, query.parents)
+        let mut drill_cols = self.cube_drill_cols(&cube, &query.drilldowns, &query.properties, &query.captions, query.parents)
             .map_err(|err| format_err!("Error getting drill cols: {}", err))?;
 
+        let property_drill_cols = self.cube_property_drill_cols(&cube, &query.property_drilldowns)
+            .map_err(|err| format_err!("Error getting property drill cols: {}", err))?;
+        drill_cols.extend_from_slice(&property_drill_cols);
+
         let mea_cols = self.cube_mea_cols(&cube, &query.measures)
             .map_err(|err| format_err!("Error getting mea cols: {}", err))?;
 
@@ -573,11 +1831,20 @@ impl Schema {
                     .ok_or(format_err!("Top by_dimension must be in drilldowns"))?;
             }
 
+            let by_column = self.get_dim_col_alias(&cube, &t.by_dimension)?;
+
+            let other = if t.other {
+                Some(top_other_sql(schema_cube, query, &drill_cols, &mea_cols, &by_column)?)
+            } else {
+                None
+            };
+
             Some(TopSql {
                 n: t.n,
-                by_column: self.get_dim_col_alias(&cube, &t.by_dimension)?,
+                by_column,
                 sort_columns: top_sort_columns,
                 sort_direction: t.sort_direction.clone(),
+                other,
             })
         } else {
             None
@@ -625,6 +1892,8 @@ impl Schema {
                         // mea value must be retrieved through explicit drilldown,
                         // don't need to do an extra rca check here.
 
+                        // a filter can also be on the result of a calculation,
+                        // by its header; it's applied after the calculation.
                         query.measures.iter()
                             .position(|col| col == m )
                             .map(|idx| {
@@ -635,7 +1904,8 @@ impl Schema {
                                 };
                                 format!("final_m{}", idx)
                             })
-                            .ok_or(format_err!("measure {} for Top must be in measures", m))
+                            .or_else(|| calculated_column(query, &m.0))
+                            .ok_or(format_err!("measure {} for Filter must be in measures, or be a calculated column", m))
                     },
                     MeaOrCalc::Calc(c) => {
                         Ok(c.sql_string())
@@ -654,16 +1924,31 @@ impl Schema {
         let filters = filters?;
 
         let sort = if let Some(ref s) = query.sort {
-            // sort column needs to be named by alias
+            // sort column needs to be named by alias.
+            // Calculated columns (by header, or `growth`, `rca`, `rate`) are
+            // sorted on after the calculation, in the wrapping query.
             let sort_column = query.measures.iter()
                 .position(|m| m == &s.measure)
-                .ok_or_else(|| format_err!("sort {:?} not found in measures", &s.measure))?;
+                .map(|idx| format!("final_m{}", idx))
+                .or_else(|| calculated_column(query, &s.measure.0))
+                .or_else(|| {
+                    s.measure.0.parse::<Calculation>().ok()
+                        .filter(|c| match c {
+                            Calculation::Growth => query.growth.is_some(),
+                            Calculation::Rca => query.rca.is_some(),
+                            Calculation::Rate => query.rate.is_some(),
+                        })
+                        .map(|c| c.sql_string())
+                })
+                .ok_or_else(|| format_err!("sort {:?} not found in measures or calculated columns", &s.measure))?;
 
 
             Some(SortSql {
                 direction: s.direction.clone(),
-                column: format!("final_m{}", sort_column),
+                column: sort_column,
             })
+        } else if query.top.is_none() {
+            drill_default_sort(schema_cube, query)
         } else {
             None
         };
@@ -739,9 +2024,46 @@ impl Schema {
             None
         };
 
+        let ratio_to_parent = if let Some(ref ratio) = query.ratio_to_parent {
+            let drill_idx = query.drilldowns.iter()
+                .position(|drill| *drill == ratio.drill)
+                .ok_or(format_err!("drilldown for ratio_to_parent must be in drilldowns"))?;
+
+            let mea = query.measures.iter()
+                .position(|mea| *mea == ratio.mea)
+                .map(|idx| format!("final_m{}", idx))
+                .ok_or(format_err!("measure for ratio_to_parent must be in measures"))?;
+
+            // with parents, the drill's last level column is the drilled
+            // level, and the ones before it are its parents
+            let partition_columns = drill_cols.iter()
+                .enumerate()
+                .flat_map(|(i, drill)| {
+                    if i == drill_idx {
+                        let num_parents = drill.level_columns.len().saturating_sub(1);
+                        DrilldownSql {
+                            level_columns: drill.level_columns[..num_parents].to_vec(),
+                            property_columns: vec![],
+                            ..drill.clone()
+                        }.col_alias_only_vec()
+                    } else {
+                        drill.col_alias_only_vec()
+                    }
+                })
+                .collect();
+
+            Some(RatioToParentSql {
+                partition_columns,
+                mea,
+            })
+        } else {
+            None
+        };
+
         // getting headers, not for sql but needed for formatting
-        let mut drill_headers = self.cube_drill_headers(&cube, &query.drilldowns, &query.properties, query.parents)
+        let mut drill_headers = self.cube_drill_headers(&cube, &query.drilldowns, &query.properties, &query.captions, query.parents)
             .map_err(|err| format_err!("Error getting drill headers: {}", err))?;
+        drill_headers.extend(query.property_drilldowns.iter().map(|p| p.property.clone()));
 
         let mut mea_headers = self.cube_mea_headers(&cube, &query.measures)
             .map_err(|err| format_err!("Error getting mea headers: {}", err))?;
@@ -749,7 +2071,7 @@ impl Schema {
         // rca mea will always be first, so just put
         // in `Mea RCA` second
         if let Some(ref rca) = query.rca {
-            let rca_drill_headers = self.cube_drill_headers(&cube, &[rca.drill_1.clone(), rca.drill_2.clone()], &query.properties, query.parents)
+            let rca_drill_headers = self.cube_drill_headers(&cube, &[rca.drill_1.clone(), rca.drill_2.clone()], &query.properties, &query.captions, query.parents)
                 .map_err(|err| format_err!("Error getting rca drill headers: {}", err))?;
 
             drill_headers.extend_from_slice(&rca_drill_headers);
@@ -775,7 +2097,7 @@ impl Schema {
             mea_headers.push(format!("{} Growth Value", growth.mea.0));
 
             // swapping around drilldown headers. Move time to back
-            let time_headers = self.cube_drill_headers(&cube, &[growth.time_drill.clone()], &[], query.parents)
+            let time_headers = self.cube_drill_headers(&cube, &[growth.time_drill.clone()], &[], &[], query.parents)
                 .map_err(|err| format_err!("Error getting time drill headers for Growth: {}", err))?;
 
             let time_header_idxs: Result<Vec<_>,_> = time_headers.iter()
@@ -804,6 +2126,9 @@ impl Schema {
         if query.rate.is_some() {
             headers.push("Rate".to_string());
         }
+        if let Some(ref ratio) = query.ratio_to_parent {
+            headers.push(format!("{} Ratio to Parent", ratio.mea.0));
+        }
 
         Ok((
             QueryIr {
@@ -820,6 +2145,7 @@ impl Schema {
                 rca,
                 growth,
                 rate,
+                ratio_to_parent,
                 sparse: query.sparse,
             },
             headers,
@@ -827,6 +2153,83 @@ impl Schema {
     }
 }
 
+/// Moves the columns named in `first` to the front of a column `order`
+/// (see `Schema::column_order`), e.g. for a `column_order` param. `names`
+/// are the names of the columns, by their original position.
+pub fn columns_first(order: &[usize], names: &[String], first: &[String]) -> Result<Vec<usize>, Error> {
+    let mut res: Vec<usize> = vec![];
+
+    for name in first {
+        let idx = names.iter()
+            .position(|n| n == name)
+            .ok_or_else(|| format_err!("Column {} is not in the results, columns are: {}", name, names.join(", ")))?;
+
+        if !res.contains(&idx) {
+            res.push(idx);
+        }
+    }
+
+    let rest: Vec<usize> = order.iter()
+        .cloned()
+        .filter(|i| !res.contains(i))
+        .collect();
+    res.extend(rest);
+
+    Ok(res)
+}
+
+/// Whether a drilldown is on a level (not the (All) level) of a time
+/// dimension.
+/// Suggestion for a level name that isn't in the cube. Suggests on the
+/// level part of the name, since that's the part most likely to be
+/// misspelled.
+fn level_suggestion(cube: &Cube, name: &LevelName) -> String {
+    let level_names = cube.get_all_level_names();
+
+    names::closest_match(&name.level, level_names.iter().map(|l| l.level.as_str()))
+        .and_then(|level| level_names.iter().find(|l| l.level == level))
+        .map(|l| format!("; did you mean {}?", l))
+        .unwrap_or_default()
+}
+
+fn is_time_drill(cube: &Cube, drill: &Drilldown) -> bool {
+    if drill.0.is_all() {
+        return false;
+    }
+
+    cube.get_dimension(&drill.0)
+        .map(|dim| dim.dim_type == DimensionType::Time || dim.dim_type == DimensionType::GeneratedTime)
+        .unwrap_or(false)
+}
+
+/// The column holding the result of a calculation in the query, looked up
+/// by the header that the result is returned under (e.g. `Quantity Growth`).
+fn calculated_column(query: &Query, header: &str) -> Option<String> {
+    if let Some(ref growth) = query.growth {
+        if header == format!("{} Growth", growth.mea.0) {
+            return Some(Calculation::Growth.sql_string());
+        }
+        if header == format!("{} Growth Value", growth.mea.0) {
+            return Some("final_m_diff".to_owned());
+        }
+    }
+    if let Some(ref rca) = query.rca {
+        if header == format!("{} RCA", rca.mea.0) {
+            return Some(Calculation::Rca.sql_string());
+        }
+    }
+    if query.rate.is_some() && header == "Rate" {
+        return Some(Calculation::Rate.sql_string());
+    }
+    if let Some(ref ratio) = query.ratio_to_parent {
+        if header == format!("{} Ratio to Parent", ratio.mea.0) {
+            return Some("ratio_to_parent".to_owned());
+        }
+    }
+
+    None
+}
+
 impl Schema {
     fn cube_table(&self, cube_name: &str) -> Option<TableSql> {
         self.cubes.iter()
@@ -892,14 +2295,15 @@ impl Schema {
                 mask: cut.mask.clone(),
                 for_match: cut.for_match,
                 inline_table: hier.inline_table.clone(),
+                fact_column: if cut.for_match { None } else { level.fact_column.clone() },
             });
         }
 
         Ok(res)
     }
 
-    // TODO as currently written, properties that don't get picked up by a drilldown
-    // will just silently fail.
+    // properties that don't get picked up by a drilldown are skipped here;
+    // `validate_query` makes sure that every property has a drilldown.
     fn cube_drill_cols(
         &self,
         cube_name: &str,
@@ -925,6 +2329,24 @@ impl Schema {
                 .ok_or(format_err!("could not find hierarchy for drill {}", drill.0))?;
             let levels = &hier.levels;
 
+            // The (All) level is a constant read from the fact table, so it
+            // doesn't group the results, but cuts on the dimension still apply.
+            if drill.0.is_all() {
+                res.push(DrilldownSql {
+                    alias_postfix: dim.name.replace(" ", "_"),
+                    table: cube.table.clone(),
+                    primary_key: hier.primary_key.clone(),
+                    foreign_key: dim.foreign_key.clone().unwrap_or_default(),
+                    level_columns: vec![LevelColumn {
+                        key_column: format!("'{}'", ALL_MEMBER),
+                        name_column: None,
+                    }],
+                    property_columns: vec![],
+                    inline_table: None,
+                });
+                continue;
+            }
+
             // for this drill, get related properties.
             // - filter by properties for this drilldown
             // - for each property, get the level
@@ -965,7 +2387,7 @@ impl Schema {
                 ) as Box<Iterator<Item=&Property>>
             };
 
-            let caption_cols: Result<HashMap<_, _>, _> = captions_filtered
+            let caption_cols: Result<Vec<_>, _> = captions_filtered
                 .map(|p| {
                     levels.iter()
                         .find(|lvl| lvl.name == p.level_name.level)
@@ -983,7 +2405,19 @@ impl Schema {
                         .ok_or(format_err!("cannot find property-caption for {}", p))
                 })
                 .collect();
-            let caption_cols = caption_cols?;
+            // The first caption of a level (the first requested locale)
+            // replaces its name column, and the others are added after the
+            // properties (see `extra_captions`).
+            let mut first_caption_cols: HashMap<String, String> = HashMap::new();
+            let mut property_columns = property_columns;
+            for (level, column) in caption_cols? {
+                if first_caption_cols.contains_key(&level) {
+                    property_columns.push(column);
+                } else {
+                    first_caption_cols.insert(level, column);
+                }
+            }
+            let caption_cols = first_caption_cols;
             if !parents {
                 assert!(caption_cols.len() <= 1);
             }
@@ -1053,6 +2487,52 @@ impl Schema {
         Ok(res)
     }
 
+    /// Property drilldowns group by the property column of the level's
+    /// dimension table, without any of the level's columns.
+    fn cube_property_drill_cols(
+        &self,
+        cube_name: &str,
+        properties: &[Property],
+        ) -> Result<Vec<DrilldownSql>, Error>
+    {
+        let cube = self.cubes.iter()
+            .find(|cube| &cube.name == &cube_name)
+            .ok_or(format_err!("Could not find cube"))?;
+
+        let mut res = vec![];
+
+        for property in properties {
+            let dim = cube.dimensions.iter()
+                .find(|dim| dim.name == property.level_name.dimension)
+                .ok_or(format_err!("could not find dimension for property drill {}", property))?;
+            let hier = dim.hierarchies.iter()
+                .find(|hier| hier.name == property.level_name.hierarchy)
+                .ok_or(format_err!("could not find hierarchy for property drill {}", property))?;
+            let column = hier.levels.iter()
+                .find(|lvl| lvl.name == property.level_name.level)
+                .and_then(|lvl| lvl.properties.as_ref())
+                .and_then(|props| props.iter().find(|p| p.name == property.property))
+                .map(|p| p.column.clone())
+                .ok_or(format_err!("cannot find property for {}", property))?;
+
+            let foreign_key = dim.foreign_key
+                .clone()
+                .ok_or(format_err!("No foreign key; it's required for now (until inline dim implemented)"))?;
+
+            res.push(DrilldownSql {
+                alias_postfix: dim.name.replace(" ", "_"),
+                table: hier.table.clone().unwrap_or(cube.table.clone()),
+                primary_key: hier.primary_key.clone(),
+                foreign_key,
+                level_columns: vec![],
+                property_columns: vec![column],
+                inline_table: hier.inline_table.clone(),
+            });
+        }
+
+        Ok(res)
+    }
+
     fn cube_mea_cols(&self, cube_name: &str, meas: &[Measure]) -> Result<Vec<MeasureSql>, Error> {
         let cube = self.cubes.iter()
             .find(|cube| &cube.name == &cube_name)
@@ -1068,6 +2548,7 @@ impl Schema {
             res.push(MeasureSql {
                 column: mea.column.clone(),
                 aggregator: mea.aggregator.clone(),
+                filter: mea.filter.clone(),
             });
         }
 
@@ -1082,6 +2563,7 @@ impl Schema {
         cube_name: &str,
         drills: &[Drilldown],
         properties: &[Property],
+        captions: &[Property],
         parents: bool,
         ) -> Result<Vec<String>, Error>
     {
@@ -1100,6 +2582,11 @@ impl Schema {
                 .ok_or(format_err!("could not find hierarchy for drill"))?;
             let levels = &hier.levels;
 
+            if drill.0.is_all() {
+                level_headers.push(dim.name.clone());
+                continue;
+            }
+
             // logic for getting level names.
             // if parents = true, then get all columns down to level
             // if not,then just level name
@@ -1148,6 +2635,9 @@ impl Schema {
             let property_columns = property_columns?;
 
             level_headers.extend(property_columns);
+
+            // captions after the first of their level, e.g. `State ES`
+            level_headers.extend(extra_captions(levels, &drill.0, captions, parents));
         }
 
         Ok(level_headers)
@@ -1199,10 +2689,16 @@ impl Schema {
         let key_column = level.key_column.clone();
         let name_column = level.name_column.clone();
 
+        let mut columns = vec![key_column.clone()];
+        columns.extend(name_column.clone());
+        let order_sql = level.order_by.as_ref()
+            .map(|_| level.order_sql(&columns));
+
         Ok(MembersQueryIR {
             table_sql,
             key_column,
             name_column,
+            order_sql,
         })
     }
 
@@ -1222,7 +2718,7 @@ impl Schema {
             .ok_or(format_err!("could not find level for level name"))?;
 
         // TODO centralize where to get the alias
-        let column = format!("{}_{}", level.key_column, dim.name.replace(" ", "_"));
+        let column = format!("{}_{}", column_alias(&level.key_column), dim.name.replace(" ", "_"));
 
         Ok(column)
     }
@@ -1241,18 +2737,165 @@ impl Schema {
         Ok(column)
     }
 
+    /// Finds a cube by name, matched according to the schema's
+    /// `name_resolution`. An exact match is always preferred.
     pub fn get_cube_by_name(&self, cube_name: &str) -> Result<&Cube, Error> {
         self.cubes.iter()
             .find(|c| &c.name == &cube_name)
+            .or_else(|| {
+                self.cubes.iter()
+                    .find(|c| self.name_resolution.matches(&c.name, cube_name))
+            })
             .ok_or(format_err!("Could not find cube"))
     }
 }
 
+/// Columns for the "Other" row of each top partition. The drilldown holding
+/// `by_column` is kept; of the other drilldowns, the label of the level
+/// (or its key, if it's text and there's no label) is 'Other', and all
+/// their other columns are null.
+fn top_other_sql(
+    cube: &Cube,
+    query: &Query,
+    drills: &[DrilldownSql],
+    meas: &[MeasureSql],
+    by_column: &str,
+    ) -> Result<TopOtherSql, Error>
+{
+    for mea in meas {
+        match mea.aggregator {
+            Aggregator::Sum | Aggregator::Count => (),
+            _ => bail!("top_other requires sum or count measures, since the Other row is summed"),
+        }
+    }
+
+    let mut group_columns = vec![];
+    let mut drill_columns = vec![];
+
+    for (i, drill) in drills.iter().enumerate() {
+        let aliases = drill.col_alias_only_vec();
+
+        // the (All) level is the same for every row
+        let is_all = query.drilldowns.get(i)
+            .map(|d| d.0.is_all())
+            .unwrap_or(false);
+
+        if is_all || aliases.iter().any(|alias| alias == by_column) {
+            group_columns.extend(aliases.clone());
+            drill_columns.extend(aliases);
+            continue;
+        }
+
+        // property drilldowns come after the drilldowns
+        let text_key = query.drilldowns.get(i)
+            .and_then(|d| cube.get_level(&d.0))
+            .map(|level| level.key_type == Some(MemberType::Text))
+            .unwrap_or(false);
+
+        let last_idx = drill.level_columns.len().saturating_sub(1);
+        for (idx, level_column) in drill.level_columns.iter().enumerate() {
+            let key_alias = format!("{}_{}", column_alias(&level_column.key_column), drill.alias_postfix);
+
+            match level_column.name_column {
+                Some(ref name_column) => {
+                    drill_columns.push(format!("NULL as {}", key_alias));
+
+                    let name_value = if idx == last_idx { "'Other'" } else { "NULL" };
+                    drill_columns.push(format!("{} as {}_{}", name_value, column_alias(name_column), drill.alias_postfix));
+                },
+                None => {
+                    let key_value = if idx == last_idx && text_key { "'Other'" } else { "NULL" };
+                    drill_columns.push(format!("{} as {}", key_value, key_alias));
+                },
+            }
+        }
+
+        for property_column in &drill.property_columns {
+            drill_columns.push(format!("NULL as {}", property_column));
+        }
+    }
+
+    Ok(TopOtherSql {
+        group_columns,
+        drill_columns,
+    })
+}
+
+/// Headers for the captions of a drilldown that don't replace the name
+/// column of their level, because an earlier caption (locale) already did:
+/// the level name and the caption's locale, e.g. `State ES`. They're
+/// returned after the drilldown's properties, in the order requested.
+fn extra_captions(levels: &[Level], drill: &LevelName, captions: &[Property], parents: bool) -> Vec<String> {
+    let mut seen_levels: Vec<&str> = vec![];
+    let mut headers = vec![];
+
+    let captions = captions.iter()
+        .filter(|p| {
+            if parents {
+                p.level_name.dimension == drill.dimension
+            } else {
+                p.level_name == *drill
+            }
+        });
+
+    for caption in captions {
+        let level = levels.iter().find(|lvl| lvl.name == caption.level_name.level);
+        let property = level
+            .and_then(|lvl| lvl.properties.as_ref())
+            .and_then(|props| props.iter().find(|p| p.name == caption.property));
+
+        let (level, property) = match (level, property) {
+            (Some(level), Some(property)) => (level, property),
+            _ => continue,
+        };
+
+        if seen_levels.contains(&level.name.as_str()) {
+            let locale = property.caption_set.as_ref().unwrap_or(&property.name);
+            headers.push(format!("{} {}", level.name, locale.to_uppercase()));
+        } else {
+            seen_levels.push(&level.name);
+        }
+    }
+
+    headers
+}
+
+/// Default sort for an aggregate query without a `sort` or `top`: by the
+/// first drilldown whose level has an `order_by`, using the drilldown's
+/// column alias. The name column can't be sorted on if a caption replaces it.
+fn drill_default_sort(cube: &Cube, query: &Query) -> Option<SortSql> {
+    query.drilldowns.iter()
+        .filter_map(|drill| {
+            let level = cube.get_level(&drill.0)?;
+            let order_by = level.order_by.as_ref()?;
+            let alias_postfix = drill.0.dimension.replace(" ", "_");
+
+            let column = if *order_by == level.key_column {
+                format!("{}_{}", column_alias(&level.key_column), alias_postfix)
+            } else {
+                let has_caption = query.captions.iter()
+                    .any(|c| c.level_name == drill.0);
+                if has_caption {
+                    return None;
+                }
+                format!("{}_{}", column_alias(order_by), alias_postfix)
+            };
+
+            Some(SortSql {
+                direction: level.order_direction.clone().unwrap_or(SortDirection::Asc),
+                column,
+            })
+        })
+        .next()
+}
+
 #[derive(Debug)]
 struct MembersQueryIR {
     table_sql: String,
     key_column: String,
     name_column: Option<String>,
+    /// Only set when the level has an `order_by`.
+    order_sql: Option<String>,
 }
 
 
@@ -1276,6 +2919,406 @@ mod test {
     const SCHEMA_STR_SINGLE_HIER_DEFAULT: &str = r#"{ "name": "test", "cubes": [ { "name": "sales", "table": { "name": "sales", "primary_key": "product_id" }, "dimensions": [{ "name": "Geography", "foreign_key": "customer_id", "default_hierarchy": "Tract", "hierarchies": [ { "name": "Tract", "table": { "name": "customer_geo" }, "primary_key": "customer_id", "levels": [ { "name": "State", "key_column": "state_id", "name_column": "state_name", "key_type": "text" }, { "name": "County", "key_column": "county_id", "name_column": "county_name", "key_type": "text" }, { "name": "Tract", "key_column": "tract_id", "name_column": "tract_name", "key_type": "text" } ] } ] } ], "measures": [ { "name": "Quantity", "column": "quantity", "aggregator": "sum" } ] } ] }"#;
     const SCHEMA_NO_DIM: &str = r#"{ "name": "test", "cubes": [ { "name": "sales", "table": { "name": "sales", "primary_key": "product_id" }, "dimensions": [], "measures": [ { "name": "Quantity", "column": "quantity", "aggregator": "sum" } ] } ] }"#;
 
+    #[test]
+    fn test_members_context_sql() {
+        let schema: Schema = Schema::from_json(SCHEMA_STR_SINGLE_HIER_NO_DEFAULT).unwrap();
+        let level_name: LevelName = "Geography.Tract.County".parse().unwrap();
+
+        let (sql, header) = schema.members_context_sql("sales", &level_name, true, None, &[], None).unwrap();
+        assert_eq!(
+            sql,
+            "select distinct state_id, state_name, county_id, county_name from customer_geo order by state_id, county_id"
+        );
+        assert_eq!(header, vec!["State ID", "State Label", "ID", "Label"]);
+
+        let (sql, header) = schema.members_context_sql("sales", &level_name, true, Some("en,es"), &[], None).unwrap();
+        assert_eq!(
+            sql,
+            "select distinct state_id, state_name, county_id, county_name from customer_geo order by state_id, county_id"
+        );
+        assert_eq!(header, vec!["State ID", "State EN Label", "ID", "EN Label"]);
+
+        let (sql, _) = schema.members_context_sql("sales", &level_name, false, None, &[], None).unwrap();
+        assert_eq!(sql, schema.members_sql("sales", &level_name).unwrap().0);
+    }
+
+    #[test]
+    fn members_with_cuts() {
+        let schema: Schema = Schema::from_json(SCHEMA_STR_SINGLE_HIER_NO_DEFAULT).unwrap();
+        let level_name: LevelName = "Geography.Tract.County".parse().unwrap();
+        let cuts = vec!["Geography.Tract.State.06".parse::<Cut>().unwrap()];
+
+        let (sql, header) = schema.members_context_sql("sales", &level_name, false, None, &cuts, None).unwrap();
+        assert_eq!(
+            sql,
+            "select distinct county_id, county_name from customer_geo where customer_id in \
+            (select customer_id from sales where customer_id in (select customer_id from customer_geo where state_id in ('06')))"
+        );
+        assert_eq!(header, vec!["ID", "Label"]);
+
+        let (sql, _) = schema.members_context_sql("sales", &level_name, true, None, &cuts, None).unwrap();
+        assert!(sql.ends_with("where state_id in ('06'))) order by state_id, county_id"));
+    }
+
+    #[test]
+    fn property_members_sql() {
+        let schema_str = SCHEMA_STR_SINGLE_HIER_NO_DEFAULT.replace(
+            r#""name_column": "state_name", "key_type": "text" }"#,
+            r#""name_column": "state_name", "key_type": "text", "properties": [{ "name": "ISO", "column": "state_iso" }] }"#,
+        );
+        let schema: Schema = Schema::from_json(&schema_str).unwrap();
+
+        let (sql, header) = schema.property_members_sql("sales", "ISO", false, None).unwrap();
+        assert_eq!(sql, "select distinct state_iso from customer_geo order by state_iso");
+        assert_eq!(header, vec!["ISO"]);
+
+        let (sql, header) = schema.property_members_sql("sales", "Geography.Tract.State.ISO", true, None).unwrap();
+        assert_eq!(sql, "select distinct state_id, state_iso from customer_geo order by state_iso");
+        assert_eq!(header, vec!["ID", "ISO"]);
+
+        assert!(schema.property_members_sql("sales", "FIPS", false, None).is_err());
+        assert!(schema.property_members_sql("sales", "", false, None).is_err());
+
+        // a role's row filters restrict the values to its members'
+        let schema_str = schema_str.replacen(
+            r#"{ "name": "test", "#,
+            r#"{ "name": "test", "security": { "roles": [{ "name": "county_01", "row_filters": [{ "cut": "[Geography].[Tract].[County].[01]" }] }] }, "#,
+            1,
+        );
+        let schema: Schema = Schema::from_json(&schema_str).unwrap();
+
+        let (sql, _) = schema.property_members_sql("sales", "ISO", false, Some("county_01")).unwrap();
+        assert_eq!(
+            sql,
+            "select distinct state_iso from customer_geo where customer_id in \
+            (select customer_id from sales where customer_id in (select customer_id from customer_geo where county_id in ('01'))) \
+            order by state_iso"
+        );
+        assert!(schema.property_members_sql("sales", "ISO", false, None).is_err());
+    }
+
+    #[test]
+    fn drillthrough_sql() {
+        let schema_str = SCHEMA_STR_SINGLE_HIER_NO_DEFAULT.replace(
+            r#""measures": ["#,
+            r#""drillthrough": [{ "name": "Product", "column": "product_id" }, { "name": "Quantity", "column": "quantity" }], "measures": ["#,
+        );
+        let mut schema: Schema = Schema::from_json(&schema_str).unwrap();
+        schema.validate().unwrap();
+
+        let cuts = vec!["Geography.Tract.State.06".parse::<Cut>().unwrap()];
+        let (sql, header) = schema.drillthrough_sql("sales", &cuts, None, 10).unwrap();
+        assert_eq!(
+            sql,
+            "select product_id, quantity from sales where customer_id in \
+            (select customer_id from customer_geo where state_id in ('06')) limit 10"
+        );
+        assert_eq!(header, vec!["Product", "Quantity"]);
+
+        let (sql, _) = schema.drillthrough_sql("sales", &[], None, 10).unwrap();
+        assert_eq!(sql, "select product_id, quantity from sales limit 10");
+
+        // cubes without drillthrough columns can't be drilled through
+        let schema: Schema = Schema::from_json(SCHEMA_STR_SINGLE_HIER_NO_DEFAULT).unwrap();
+        assert!(schema.drillthrough_sql("sales", &[], None, 10).is_err());
+    }
+
+    #[test]
+    fn row_filters() {
+        let schema_str = SCHEMA_STR_SINGLE_HIER_NO_DEFAULT
+            .replace(
+                r#""measures": ["#,
+                r#""drillthrough": [{ "name": "Quantity", "column": "quantity" }], "measures": ["#,
+            )
+            .replacen(
+                r#"{ "name": "test", "#,
+                r#"{ "name": "test", "security": { "roles": [{ "name": "state_ca", "row_filters": [{ "cut": "[Geography].[Tract].[State].[06]" }] }, { "name": "admin" }] }, "#,
+                1,
+            );
+        let mut schema: Schema = Schema::from_json(&schema_str).unwrap();
+        schema.validate().unwrap();
+
+        let mut query = Query::builder()
+            .drilldown("Geography.Tract.County")
+            .measure("Quantity")
+            .build()
+            .unwrap();
+
+        // no role, and no default role
+        assert!(schema.sql_query("sales", &query).is_err());
+
+        query.role = Some("unknown".into());
+        assert!(schema.sql_query("sales", &query).is_err());
+
+        query.role = Some("state_ca".into());
+        let (query_ir, _) = schema.sql_query("sales", &query).unwrap();
+        assert_eq!(query_ir.cuts.len(), 1);
+        assert_eq!(query_ir.cuts[0].column, "state_id");
+        assert_eq!(query_ir.cuts[0].members, vec!["06"]);
+
+        // the query's own cuts can't widen the filter
+        let mut cut_query = query.clone();
+        cut_query.cuts = vec!["Geography.Tract.State.08".parse::<Cut>().unwrap()];
+        let (query_ir, _) = schema.sql_query("sales", &cut_query).unwrap();
+        assert_eq!(query_ir.cuts.len(), 2);
+
+        let (sql, _) = schema.drillthrough_sql("sales", &[], Some("state_ca"), 10).unwrap();
+        assert_eq!(
+            sql,
+            "select quantity from sales where customer_id in \
+            (select customer_id from customer_geo where state_id in ('06')) limit 10"
+        );
+
+        // members are only the ones in the role's facts
+        let level_name: LevelName = "Geography.Tract.County".parse().unwrap();
+        let (sql, _) = schema.members_context_sql("sales", &level_name, false, None, &[], Some("state_ca")).unwrap();
+        assert_eq!(
+            sql,
+            "select distinct county_id, county_name from customer_geo where customer_id in \
+            (select customer_id from sales where customer_id in (select customer_id from customer_geo where state_id in ('06')))"
+        );
+        assert!(schema.members_context_sql("sales", &level_name, false, None, &[], None).is_err());
+
+        query.role = Some("admin".into());
+        let (query_ir, _) = schema.sql_query("sales", &query).unwrap();
+        assert!(query_ir.cuts.is_empty());
+
+        // the default role applies to queries without a role
+        schema.security.as_mut().unwrap().default_role = Some("state_ca".into());
+        query.role = None;
+        let (query_ir, _) = schema.sql_query("sales", &query).unwrap();
+        assert_eq!(query_ir.cuts.len(), 1);
+
+        let bad_schema_str = schema_str.replace("[State]", "[Region]");
+        let mut bad_schema: Schema = Schema::from_json(&bad_schema_str).unwrap();
+        assert!(bad_schema.validate().is_err());
+    }
+
+    #[test]
+    fn cut_captions_sql() {
+        let schema: Schema = Schema::from_json(SCHEMA_STR_SINGLE_HIER_NO_DEFAULT).unwrap();
+
+        let cut = "Geography.Tract.State.06,08".parse::<Cut>().unwrap();
+        assert_eq!(
+            schema.cut_captions_sql("sales", &cut).unwrap(),
+            Some("select distinct state_id, state_name from customer_geo where state_id in ('06', '08')".to_owned())
+        );
+
+        let cut = "*Geography.Tract.State.Cal".parse::<Cut>().unwrap();
+        assert_eq!(schema.cut_captions_sql("sales", &cut).unwrap(), None);
+    }
+
+    #[test]
+    fn calculation_plan() {
+        let population_cube = SCHEMA_STR_SINGLE_HIER_NO_DEFAULT
+            .replace(r#"{ "name": "test", "cubes": [ "#, "")
+            .replace(r#""name": "sales", "table": { "name": "sales""#, r#""name": "population", "table": { "name": "population""#)
+            .replace(r#"{ "name": "Quantity", "column": "quantity", "aggregator": "sum" } ] } ] }"#, r#"{ "name": "Population", "column": "population", "aggregator": "sum" } ] }"#);
+        let schema_str = SCHEMA_STR_SINGLE_HIER_NO_DEFAULT
+            .replace(r#""measures": ["#, r#""calculations": [{ "name": "Quantity per Capita" }], "measures": ["#)
+            .replace(r#""aggregator": "sum" } ] } ] }"#, &format!(r#""aggregator": "sum" }} ] }}, {} ], "calculations": [{{ "name": "Quantity per Capita", "formula": "[Quantity] / [population].[Population]" }}] }}"#, population_cube));
+        let mut schema: Schema = Schema::from_json(&schema_str).unwrap();
+        schema.validate().unwrap();
+
+        let query = Query::builder()
+            .drilldown("Geography.Tract.State")
+            .measure("Quantity per Capita")
+            .build()
+            .unwrap();
+        let plan = schema.calculation_plan("sales", &query).unwrap().unwrap();
+        assert_eq!(plan.query.measures, vec![Measure::new("Quantity")]);
+        assert_eq!(plan.external.len(), 1);
+        assert_eq!(plan.external[0].0, "population");
+        assert_eq!(plan.external[0].1.measures, vec![Measure::new("Population")]);
+        assert_eq!(plan.external[0].1.drilldowns, query.drilldowns);
+
+        let query = Query::builder()
+            .drilldown("Geography.Tract.State")
+            .measure("Quantity")
+            .rate_against("Quantity,population,Population")
+            .build()
+            .unwrap();
+        let plan = schema.calculation_plan("sales", &query).unwrap().unwrap();
+        assert_eq!(plan.query.measures, vec![Measure::new("Quantity")]);
+        assert!(plan.query.rate_against.is_none());
+        assert_eq!(plan.external[0].1.measures, vec![Measure::new("Population")]);
+        assert!(schema.sql_query("sales", &query).is_err());
+
+        // queries without calculations don't need a plan
+        let query = Query::builder()
+            .drilldown("Geography.Tract.State")
+            .measure("Quantity")
+            .build()
+            .unwrap();
+        assert!(schema.calculation_plan("sales", &query).unwrap().is_none());
+
+        // measures in formulas must exist
+        let bad_schema_str = schema_str.replace("[population].[Population]", "[population].[Households]");
+        let mut schema: Schema = Schema::from_json(&bad_schema_str).unwrap();
+        assert!(schema.validate().is_err());
+    }
+
+    #[test]
+    fn comparison_plan() {
+        let mut schema: Schema = Schema::from_json(SCHEMA_STR_SINGLE_HIER_NO_DEFAULT).unwrap();
+        schema.validate().unwrap();
+
+        let query = Query::builder()
+            .drilldown("Geography.Tract.County")
+            .cut("Geography.Tract.Tract.01")
+            .measure("Quantity")
+            .compare("Geography.Tract.State.CA|Geography.Tract.State.NY,NJ")
+            .build()
+            .unwrap();
+        let plan = schema.comparison_plan("sales", &query).unwrap().unwrap();
+        assert_eq!(plan.measures, vec!["Quantity"]);
+        assert!(plan.query_a.compare.is_none());
+        let cut_members = |query: &Query| -> Vec<(String, Vec<String>)> {
+            query.cuts.iter()
+                .map(|c| (c.level_name.level.clone(), c.members.clone()))
+                .collect()
+        };
+        assert_eq!(cut_members(&plan.query_a), vec![
+            ("Tract".to_owned(), vec!["01".to_owned()]),
+            ("State".to_owned(), vec!["CA".to_owned()]),
+        ]);
+        assert_eq!(cut_members(&plan.query_b), vec![
+            ("Tract".to_owned(), vec!["01".to_owned()]),
+            ("State".to_owned(), vec!["NY".to_owned(), "NJ".to_owned()]),
+        ]);
+        assert_eq!(plan.query_b.drilldowns, query.drilldowns);
+        assert!(schema.sql_query("sales", &query).is_err());
+        assert!(schema.sql_query("sales", &plan.query_a).is_ok());
+
+        // results are matched row by row
+        let query = Query::builder()
+            .drilldown("Geography.Tract.County")
+            .measure("Quantity")
+            .limit(10, None)
+            .compare("Geography.Tract.State.CA|Geography.Tract.State.NY")
+            .build()
+            .unwrap();
+        assert!(schema.comparison_plan("sales", &query).is_err());
+
+        // the compared levels can't be drilled down on
+        let res = Query::builder()
+            .drilldown("Geography.Tract.State")
+            .measure("Quantity")
+            .compare("Geography.Tract.State.CA|Geography.Tract.State.NY")
+            .build();
+        assert!(res.is_err());
+
+        assert!("Geography.Tract.State.CA".parse::<query::CompareQuery>().is_err());
+        assert!("Geography.Tract.State.CA|".parse::<query::CompareQuery>().is_err());
+    }
+
+    #[test]
+    fn masking_plan() {
+        let schema_str = SCHEMA_STR_SINGLE_HIER_NO_DEFAULT
+            .replace(
+                r#""measures": ["#,
+                r#""drillthrough": [{ "name": "Quantity", "column": "quantity" }], "masking": [{ "measure": "Quantity", "min": 5 }], "measures": [ { "name": "Price", "column": "price", "aggregator": "sum" }, "#,
+            );
+        let mut schema: Schema = Schema::from_json(&schema_str).unwrap();
+        schema.validate().unwrap();
+
+        let mut query = Query::builder()
+            .drilldown("Geography.Tract.State")
+            .measure("Quantity")
+            .growth("Geography.Tract.State,Quantity")
+            .build()
+            .unwrap();
+        let plan = schema.masking_plan("sales", &mut query).unwrap().unwrap();
+        assert_eq!(query.measures, vec![Measure::new("Fact Count"), Measure::new("Quantity")]);
+        assert_eq!(plan.rules[0].columns, vec!["Quantity", "Quantity Growth", "Quantity Growth Value"]);
+        assert_eq!(plan.rules[0].by, "Fact Count");
+        assert_eq!(plan.added, vec!["Fact Count"]);
+
+        // queries without masked measures don't need a plan
+        let mut query = Query::builder()
+            .drilldown("Geography.Tract.State")
+            .measure("Price")
+            .build()
+            .unwrap();
+        assert!(schema.masking_plan("sales", &mut query).unwrap().is_none());
+        assert_eq!(query.measures, vec![Measure::new("Price")]);
+
+        // masked values can't be filtered on
+        let mut query = Query::builder()
+            .drilldown("Geography.Tract.State")
+            .measure("Quantity")
+            .filter("Quantity,gt.10")
+            .build()
+            .unwrap();
+        assert!(schema.masking_plan("sales", &mut query).is_err());
+
+        // nor can masked cubes be drilled through
+        assert!(schema.drillthrough_sql("sales", &[], None, 10).is_err());
+
+        // complementary suppression groups the rows by drilldown
+        let complementary_schema_str = schema_str.replace(r#""min": 5"#, r#""min": 5, "complementary": true"#);
+        let mut complementary_schema: Schema = Schema::from_json(&complementary_schema_str).unwrap();
+        complementary_schema.validate().unwrap();
+        let mut query = Query::builder()
+            .drilldown("Geography.Tract.State")
+            .drilldown("Geography.Tract.County")
+            .measure("Quantity")
+            .build()
+            .unwrap();
+        let plan = complementary_schema.masking_plan("sales", &mut query).unwrap().unwrap();
+        assert!(plan.is_complementary());
+        assert_eq!(plan.drills, vec![vec!["State ID", "State"], vec!["County ID", "County"]]);
+
+        let bad_schema_str = schema_str.replace(r#""measure": "Quantity""#, r#""measure": "Quantty""#);
+        let mut schema: Schema = Schema::from_json(&bad_schema_str).unwrap();
+        assert!(schema.validate().is_err());
+    }
+
+    #[test]
+    fn level_order_by() {
+        let schema_str = SCHEMA_STR_SINGLE_HIER_NO_DEFAULT.replace(
+            r#""name_column": "county_name", "key_type": "text""#,
+            r#""name_column": "county_name", "key_type": "text", "order_by": "county_name", "order_direction": "desc""#,
+        );
+        let mut schema: Schema = Schema::from_json(&schema_str).unwrap();
+        schema.validate().unwrap();
+        let level_name: LevelName = "Geography.Tract.County".parse().unwrap();
+
+        let (sql, _) = schema.members_sql("sales", &level_name).unwrap();
+        assert_eq!(sql, "select distinct county_id, county_name from customer_geo order by county_name desc");
+
+        let (sql, _) = schema.members_context_sql("sales", &level_name, true, None, &[], None).unwrap();
+        assert!(sql.ends_with("order by state_id, county_name desc"));
+
+        let query = Query::builder()
+            .drilldown("Geography.Tract.County")
+            .measure("Quantity")
+            .build()
+            .unwrap();
+        let (query_ir, _) = schema.sql_query("sales", &query).unwrap();
+        let sort = query_ir.sort.unwrap();
+        assert_eq!(sort.column, "county_name_Geography");
+        assert_eq!(sort.direction, SortDirection::Desc);
+
+        // an explicit sort takes precedence
+        let query = Query::builder()
+            .drilldown("Geography.Tract.County")
+            .measure("Quantity")
+            .sort("Quantity.asc")
+            .build()
+            .unwrap();
+        let (query_ir, _) = schema.sql_query("sales", &query).unwrap();
+        assert_eq!(query_ir.sort.unwrap().column, "final_m0");
+
+        // only the key and name columns can be sorted on
+        let schema_str = SCHEMA_STR_SINGLE_HIER_NO_DEFAULT.replace(
+            r#""name_column": "county_name", "key_type": "text""#,
+            r#""name_column": "county_name", "key_type": "text", "order_by": "county_population""#,
+        );
+        let mut schema: Schema = Schema::from_json(&schema_str).unwrap();
+        assert!(schema.validate().is_err());
+    }
+
     #[test]
     #[should_panic]
     fn test_validate_schema_multiple_hier_no_default() {
@@ -1308,6 +3351,37 @@ mod test {
         schema.cubes[0].dimensions[0].default_hierarchy.clone().unwrap();
     }
 
+    #[test]
+    fn test_unique_levels_across_hierarchies() {
+        // the Place hierarchy also has a State level
+        let schema_str = SCHEMA_STR_MULTIPLE_HIER_DEFAULT.replace(
+            r#""levels": [ { "name": "Place""#,
+            r#""levels": [ { "name": "State", "key_column": "place_state_id", "key_type": "text" }, { "name": "Place""#,
+        );
+        let mut schema: Schema = Schema::from_json(&schema_str).unwrap();
+        schema.validate().unwrap();
+
+        match schema.has_unique_levels_properties() {
+            CubeHasUniqueLevelsAndProperties::True => (),
+            CubeHasUniqueLevelsAndProperties::False { name, .. } => panic!("duplicate name {}", name),
+        }
+        assert!(schema.cubes[0].dimensions[0].is_default_hierarchy("Tract"));
+        assert!(!schema.cubes[0].dimensions[0].is_default_hierarchy("Place"));
+
+        // but not across dimensions
+        let mut other_dim = schema.cubes[0].dimensions[0].clone();
+        other_dim.name = "Store Geography".to_owned();
+        schema.cubes[0].dimensions.push(other_dim);
+
+        match schema.has_unique_levels_properties() {
+            CubeHasUniqueLevelsAndProperties::False { cube, name } => {
+                assert_eq!(cube, "sales");
+                assert_eq!(name, "State");
+            },
+            CubeHasUniqueLevelsAndProperties::True => panic!("expected a duplicate name"),
+        }
+    }
+
     #[test]
     #[should_panic]
     fn test_validate_schema_dimension_number() {
@@ -1315,6 +3389,642 @@ mod test {
         schema.validate().unwrap();
     }
 
+    #[test]
+    fn validate_query_collects_all_errors() {
+        let mut schema: Schema = Schema::from_json(SCHEMA_STR_SINGLE_HIER_NO_DEFAULT).unwrap();
+        schema.validate().unwrap();
+
+        let query = Query::builder()
+            .drilldown("Geography.Tract.Zip")
+            .drilldown("Geography.Tract.county")
+            .cut("Geography.Tract.Block.1")
+            .measure("Quantity")
+            .measure("Price")
+            .measure("Quantty")
+            .build()
+            .unwrap();
+
+        let err = schema.sql_query("sales", &query).unwrap_err();
+        let errors = err.downcast::<QueryErrors>().unwrap();
+
+        assert_eq!(errors.0, vec![
+            "Measure Price not found in cube sales".to_owned(),
+            "Measure Quantty not found in cube sales; did you mean Quantity?".to_owned(),
+            "Drilldown [Geography].[Tract].[Zip] not found in cube sales".to_owned(),
+            "Drilldown [Geography].[Tract].[county] not found in cube sales; did you mean [Geography].[Tract].[County]?".to_owned(),
+            "Cut level [Geography].[Tract].[Block] not found in cube sales".to_owned(),
+        ]);
+    }
+
+    #[test]
+    fn check_levels() {
+        let mut schema: Schema = Schema::from_json(SCHEMA_STR_SINGLE_HIER_NO_DEFAULT).unwrap();
+        schema.validate().unwrap();
+        let cube = schema.get_cube_by_name("sales").unwrap();
+
+        let query = Query::builder()
+            .drilldown("Geography.Tract.County")
+            .cut("Geography.Tract.State.06")
+            .measure("Quantty")
+            .build()
+            .unwrap();
+        assert!(schema.check_levels(cube, &query).is_ok());
+
+        let query = Query::builder()
+            .drilldown("Geography.Tract.county")
+            .measure("Quantity")
+            .build()
+            .unwrap();
+        assert_eq!(
+            schema.check_levels(cube, &query).unwrap_err().to_string(),
+            "Level [Geography].[Tract].[county] not found in cube sales; did you mean [Geography].[Tract].[County]?",
+        );
+    }
+
+    #[test]
+    fn lenient_name_resolution() {
+        let mut schema: Schema = Schema::from_json(SCHEMA_STR_SINGLE_HIER_NO_DEFAULT).unwrap();
+        schema.validate().unwrap();
+
+        let query = Query::builder()
+            .drilldown("geography.tract.county")
+            .cut("Geography.Tract.STATE.06")
+            .measure("quantity")
+            .build()
+            .unwrap();
+
+        assert!(schema.sql_query("SALES", &query).is_err());
+
+        schema.name_resolution = NameResolution::Lenient;
+
+        let resolved = schema.resolve_query(schema.get_cube_by_name("SALES").unwrap(), &query);
+        assert_eq!(resolved.drilldowns, vec![Drilldown::new("Geography", "Tract", "County")]);
+        assert_eq!(resolved.cuts[0].level_name, LevelName::new("Geography", "Tract", "State"));
+        assert_eq!(resolved.measures, vec![Measure::new("Quantity")]);
+
+        assert!(schema.sql_query("SALES", &query).is_ok());
+    }
+
+    #[test]
+    fn property_drilldown() {
+        let schema_str = SCHEMA_STR_SINGLE_HIER_NO_DEFAULT.replace(
+            r#""name_column": "state_name", "key_type": "text" }"#,
+            r#""name_column": "state_name", "key_type": "text", "properties": [ { "name": "Region", "column": "region" } ] }"#,
+        );
+        let mut schema: Schema = Schema::from_json(&schema_str).unwrap();
+        schema.validate().unwrap();
+
+        let query = Query::builder()
+            .property_drilldown("Geography.Tract.State.Region")
+            .measure("Quantity")
+            .build()
+            .unwrap();
+
+        let (query_ir, headers) = schema.sql_query("sales", &query).unwrap();
+        assert_eq!(headers, vec!["Region", "Quantity"]);
+        assert_eq!(query_ir.drills.len(), 1);
+        assert!(query_ir.drills[0].level_columns.is_empty());
+        assert_eq!(query_ir.drills[0].property_columns, vec!["region"]);
+        assert_eq!(query_ir.drills[0].foreign_key, "customer_id");
+
+        // can't be mixed with a level drilldown on the same dimension
+        let res = Query::builder()
+            .drilldown("Geography.Tract.County")
+            .property_drilldown("Geography.Tract.State.Region")
+            .measure("Quantity")
+            .build();
+        assert!(res.is_err());
+
+        let query = Query::builder()
+            .property_drilldown("Geography.Tract.State.Division")
+            .measure("Quantity")
+            .build()
+            .unwrap();
+        assert!(schema.sql_query("sales", &query).is_err());
+    }
+
+    #[test]
+    fn multiple_locale_captions() {
+        let schema_str = SCHEMA_STR_SINGLE_HIER_NO_DEFAULT.replace(
+            r#""name_column": "state_name", "key_type": "text" }"#,
+            r#""name_column": "state_name", "key_type": "text", "properties": [ { "name": "Name EN", "column": "state_name_en", "caption_set": "en" }, { "name": "Name ES", "column": "state_name_es", "caption_set": "es" } ] }"#,
+        );
+        let mut schema: Schema = Schema::from_json(&schema_str).unwrap();
+        schema.validate().unwrap();
+
+        let level_name: LevelName = "Geography.Tract.State".parse().unwrap();
+        let level = schema.get_cube_by_name("sales").unwrap().get_level(&level_name).unwrap();
+        let captions = level.get_captions(&level_name, &vec!["es".to_owned(), "en".to_owned()]);
+        assert_eq!(captions[0].property, "Name ES");
+        assert_eq!(captions[1].property, "Name EN");
+
+        let query = Query::builder()
+            .drilldown("Geography.Tract.State")
+            .measure("Quantity")
+            .caption("Geography.Tract.State.Name EN")
+            .caption("Geography.Tract.State.Name ES")
+            .build()
+            .unwrap();
+
+        // the first locale replaces the name column, the others come after it
+        let (query_ir, headers) = schema.sql_query("sales", &query).unwrap();
+        assert_eq!(headers, vec!["State ID", "State", "State ES", "Quantity"]);
+        assert_eq!(query_ir.drills[0].level_columns[0].name_column, Some("state_name_en".to_owned()));
+        assert_eq!(query_ir.drills[0].property_columns, vec!["state_name_es"]);
+    }
+
+    #[test]
+    fn filter_on_calculated_column() {
+        let mut schema: Schema = Schema::from_json(SCHEMA_STR_SINGLE_HIER_NO_DEFAULT).unwrap();
+        schema.validate().unwrap();
+
+        let query = Query::builder()
+            .drilldown("Geography.Tract.State")
+            .measure("Quantity")
+            .growth("Geography.Tract.State,Quantity")
+            .filter("Quantity Growth,gte.0.1")
+            .filter("Quantity Growth Value,lt.-5")
+            .build()
+            .unwrap();
+
+        let (query_ir, _) = schema.sql_query("sales", &query).unwrap();
+        assert_eq!(query_ir.filters[0].by_column, "growth");
+        assert_eq!(query_ir.filters[0].constraint.sql_string(), ">= 0.1");
+        assert_eq!(query_ir.filters[1].by_column, "final_m_diff");
+        assert_eq!(query_ir.filters[1].constraint.sql_string(), "< -5");
+
+        let query = Query::builder()
+            .drilldown("Geography.Tract.County")
+            .measure("Quantity")
+            .rate("Geography.Tract.State.06")
+            .filter("Rate,gt.0.5")
+            .build()
+            .unwrap();
+
+        let (query_ir, _) = schema.sql_query("sales", &query).unwrap();
+        assert_eq!(query_ir.filters[0].by_column, "rate");
+        assert_eq!(query_ir.filters[0].constraint.sql_string(), "> 0.5");
+
+        // calculated columns are only available when the calculation is in the query
+        let query = Query::builder()
+            .drilldown("Geography.Tract.State")
+            .measure("Quantity")
+            .filter("Quantity Growth,gte.0.1")
+            .build()
+            .unwrap();
+        assert!(schema.sql_query("sales", &query).is_err());
+    }
+
+    #[test]
+    fn sort_on_calculated_column() {
+        let mut schema: Schema = Schema::from_json(SCHEMA_STR_SINGLE_HIER_NO_DEFAULT).unwrap();
+        schema.validate().unwrap();
+
+        let query = Query::builder()
+            .drilldown("Geography.Tract.State")
+            .measure("Quantity")
+            .growth("Geography.Tract.State,Quantity")
+            .sort("Quantity Growth.desc")
+            .limit(10, None)
+            .build()
+            .unwrap();
+
+        let (query_ir, _) = schema.sql_query("sales", &query).unwrap();
+        assert_eq!(query_ir.sort.unwrap().column, "growth");
+
+        let query = Query::builder()
+            .drilldown("Geography.Tract.County")
+            .measure("Quantity")
+            .rate("Geography.Tract.State.06")
+            .sort("rate.asc")
+            .build()
+            .unwrap();
+
+        let (query_ir, _) = schema.sql_query("sales", &query).unwrap();
+        let sort = query_ir.sort.unwrap();
+        assert_eq!(sort.column, "rate");
+        assert_eq!(sort.direction.sql_string(), "asc");
+
+        // no rca in the query to sort on
+        let query = Query::builder()
+            .drilldown("Geography.Tract.State")
+            .measure("Quantity")
+            .sort("rca.desc")
+            .build()
+            .unwrap();
+        assert!(schema.sql_query("sales", &query).is_err());
+    }
+
+    #[test]
+    fn column_order() {
+        let schema_str = SCHEMA_STR_SINGLE_HIER_NO_DEFAULT
+            .replace(
+                r#""dimensions": ["#,
+                r#""dimensions": [{ "name": "Date", "type": "generated_time", "foreign_key": "sale_date" }, "#,
+            )
+            .replace(
+                r#""name_column": "state_name", "key_type": "text" }"#,
+                r#""name_column": "state_name", "key_type": "text", "properties": [ { "name": "Region", "column": "region" } ] }"#,
+            );
+        let mut schema: Schema = Schema::from_json(&schema_str).unwrap();
+        schema.validate().unwrap();
+
+        let query = Query::builder()
+            .drilldown("Geography.Tract.State")
+            .drilldown("Date.Date.Year")
+            .property("Geography.Tract.State.Region")
+            .measure("Quantity")
+            .build()
+            .unwrap();
+
+        let (_, headers) = schema.sql_query("sales", &query).unwrap();
+        assert_eq!(headers, vec!["State ID", "State", "Region", "Year", "Quantity"]);
+
+        let order = schema.column_order("sales", &query, &headers).unwrap();
+        assert_eq!(order, vec![3, 0, 1, 2, 4]);
+
+        let order = columns_first(&order, &headers, &["Quantity".to_owned()]).unwrap();
+        assert_eq!(order, vec![4, 3, 0, 1, 2]);
+
+        assert!(columns_first(&order, &headers, &["County".to_owned()]).is_err());
+    }
+
+    #[test]
+    fn generated_time_dimension() {
+        let schema_str = SCHEMA_STR_SINGLE_HIER_NO_DEFAULT.replace(
+            r#""dimensions": ["#,
+            r#""dimensions": [{ "name": "Date", "type": "generated_time", "foreign_key": "sale_date" }, "#,
+        );
+        let mut schema: Schema = Schema::from_json(&schema_str).unwrap();
+        schema.validate().unwrap();
+
+        let query = Query::builder()
+            .drilldown("Date.Date.Month")
+            .cut("Date.Date.Year.2019")
+            .measure("Quantity")
+            .build()
+            .unwrap();
+
+        let (query_ir, headers) = schema.sql_query("sales", &query).unwrap();
+        assert_eq!(headers, vec!["Month", "Quantity"]);
+        assert_eq!(query_ir.drills[0].table.name, "sales");
+        assert_eq!(
+            query_ir.drills[0].level_columns[0].key_column,
+            "(extract(year from sale_date) * 100 + extract(month from sale_date))"
+        );
+        assert_eq!(query_ir.cuts[0].column, "extract(year from sale_date)");
+
+        schema.set_time_grain_sql(|grain, column| format!("{}_of({})", grain.level_name(), column));
+
+        let (query_ir, _) = schema.sql_query("sales", &query).unwrap();
+        assert_eq!(query_ir.drills[0].level_columns[0].key_column, "Month_of(sale_date)");
+
+        // derived levels have a hierarchy each
+        let query = Query::builder()
+            .drilldown("Date.Day of Week.Day of Week")
+            .measure("Quantity")
+            .build()
+            .unwrap();
+
+        let (query_ir, headers) = schema.sql_query("sales", &query).unwrap();
+        assert_eq!(headers, vec!["Day of Week", "Quantity"]);
+        assert_eq!(query_ir.drills[0].level_columns[0].key_column, "Day of Week_of(sale_date)");
+
+        // not drilled on, the generated time dimension doesn't add a cut
+        let query = Query::builder()
+            .drilldown("Geography.Tract.State")
+            .measure("Quantity")
+            .build()
+            .unwrap();
+
+        let (query_ir, _) = schema.sql_query("sales", &query).unwrap();
+        assert!(query_ir.cuts.is_empty());
+
+        // the date column is required
+        let schema_str = SCHEMA_STR_SINGLE_HIER_NO_DEFAULT.replace(
+            r#""dimensions": ["#,
+            r#""dimensions": [{ "name": "Date", "type": "generated_time" }, "#,
+        );
+        let mut schema: Schema = Schema::from_json(&schema_str).unwrap();
+        assert!(schema.validate().is_err());
+    }
+
+    #[test]
+    fn top_other() {
+        let schema_str = SCHEMA_STR_SINGLE_HIER_NO_DEFAULT.replace(
+            r#""dimensions": ["#,
+            r#""dimensions": [{ "name": "Date", "type": "generated_time", "foreign_key": "sale_date" }, "#,
+        );
+        let mut schema: Schema = Schema::from_json(&schema_str).unwrap();
+        schema.validate().unwrap();
+
+        let query = Query::builder()
+            .drilldown("Date.Date.Year")
+            .drilldown("Geography.Tract.State")
+            .measure("Quantity")
+            .top("3,Date.Date.Year,Quantity,desc")
+            .top_other(true)
+            .build()
+            .unwrap();
+
+        let (query_ir, _) = schema.sql_query("sales", &query).unwrap();
+        let top = query_ir.top.unwrap();
+        let other = top.other.unwrap();
+        assert_eq!(other.group_columns, vec![top.by_column.clone()]);
+        assert_eq!(other.drill_columns, vec![
+            top.by_column.clone(),
+            "NULL as state_id_Geography".to_owned(),
+            "'Other' as state_name_Geography".to_owned(),
+        ]);
+
+        // the Other row can't be recalculated
+        let res = Query::builder()
+            .drilldown("Date.Date.Year")
+            .drilldown("Geography.Tract.State")
+            .measure("Quantity")
+            .top("3,Date.Date.Year,Quantity,desc")
+            .top_other(true)
+            .growth("Date.Date.Year,Quantity")
+            .build();
+        assert!(res.is_err());
+
+        // and needs a top
+        assert!(Query::builder().measure("Quantity").top_other(true).build().is_err());
+    }
+
+    #[test]
+    fn banded_dimension() {
+        let schema_str = SCHEMA_STR_SINGLE_HIER_NO_DEFAULT.replace(
+            r#""dimensions": ["#,
+            r#""dimensions": [{ "name": "Age", "type": "banded", "foreign_key": "age", "bands": [
+                { "label": "Under 18", "max": 18 },
+                { "label": "18-64", "min": 18, "max": 65 },
+                { "label": "65+", "min": 65 }
+            ] }, "#,
+        );
+        let mut schema: Schema = Schema::from_json(&schema_str).unwrap();
+        schema.validate().unwrap();
+
+        let query = Query::builder()
+            .drilldown("Age.Age.Age")
+            .cut("Age.Age.Age.65+")
+            .measure("Quantity")
+            .build()
+            .unwrap();
+
+        let (query_ir, headers) = schema.sql_query("sales", &query).unwrap();
+        assert_eq!(headers, vec!["Age", "Quantity"]);
+        assert_eq!(query_ir.drills[0].table.name, "sales");
+        assert_eq!(
+            query_ir.drills[0].level_columns[0].key_column,
+            "case when age < 18 then 'Under 18' when age >= 18 and age < 65 then '18-64' when age >= 65 then '65+' end"
+        );
+        assert_eq!(query_ir.cuts[0].members_string(), "'65+'");
+
+        // the column to band and the bands are required
+        let schema_str = SCHEMA_STR_SINGLE_HIER_NO_DEFAULT.replace(
+            r#""dimensions": ["#,
+            r#""dimensions": [{ "name": "Age", "type": "banded", "foreign_key": "age" }, "#,
+        );
+        let mut schema: Schema = Schema::from_json(&schema_str).unwrap();
+        assert!(schema.validate().is_err());
+    }
+
+    #[test]
+    fn ratio_to_parent() {
+        let mut schema: Schema = Schema::from_json(SCHEMA_STR_SINGLE_HIER_NO_DEFAULT).unwrap();
+        schema.validate().unwrap();
+
+        let query = Query::builder()
+            .drilldown("Geography.Tract.County")
+            .measure("Quantity")
+            .parents(true)
+            .ratio_to_parent("Geography.Tract.County,Quantity")
+            .sort("Quantity Ratio to Parent.desc")
+            .build()
+            .unwrap();
+
+        let (query_ir, headers) = schema.sql_query("sales", &query).unwrap();
+        assert_eq!(headers, vec!["State ID", "State", "County ID", "County", "Quantity", "Quantity Ratio to Parent"]);
+
+        let ratio = query_ir.ratio_to_parent.as_ref().unwrap();
+        assert_eq!(ratio.partition_columns, vec!["state_id_Geography", "state_name_Geography"]);
+        assert_eq!(ratio.mea, "final_m0");
+        assert_eq!(query_ir.sort.unwrap().column, "ratio_to_parent");
+
+        // parents are required
+        let query = Query::builder()
+            .drilldown("Geography.Tract.County")
+            .measure("Quantity")
+            .ratio_to_parent("Geography.Tract.County,Quantity")
+            .build();
+        assert!(query.is_err());
+    }
+
+    #[test]
+    fn caption_expression() {
+        let schema_str = SCHEMA_STR_SINGLE_HIER_NO_DEFAULT.replace(
+            r#""name_column": "state_name", "key_type": "text""#,
+            r#""name_column": "state_name", "caption_expression": "concat(state_id, ' - ', state_name)", "key_type": "text""#,
+        );
+        let mut schema: Schema = Schema::from_json(&schema_str).unwrap();
+        schema.validate().unwrap();
+
+        let query = Query::builder()
+            .drilldown("Geography.Tract.State")
+            .measure("Quantity")
+            .build()
+            .unwrap();
+
+        let (query_ir, headers) = schema.sql_query("sales", &query).unwrap();
+        assert_eq!(headers, vec!["State ID", "State", "Quantity"]);
+
+        let drill = &query_ir.drills[0];
+        assert_eq!(drill.level_columns[0].name_column, Some("concat(state_id, ' - ', state_name)".to_owned()));
+        assert_eq!(
+            drill.col_alias_string(),
+            "state_id as state_id_Geography, concat(state_id, ' - ', state_name) as concat_state_id_________state_name__Geography",
+        );
+
+        let level_name: LevelName = "Geography.Tract.State".parse().unwrap();
+        let (sql, _) = schema.members_sql("sales", &level_name).unwrap();
+        assert_eq!(sql, "select distinct state_id, concat(state_id, ' - ', state_name) from customer_geo");
+    }
+
+    #[test]
+    fn fill_time_gaps() {
+        let schema_str = SCHEMA_STR_SINGLE_HIER_NO_DEFAULT.replace(
+            r#""dimensions": ["#,
+            r#""dimensions": [{ "name": "Date", "type": "generated_time", "foreign_key": "sale_date" }, "#,
+        );
+        let mut schema: Schema = Schema::from_json(&schema_str).unwrap();
+        schema.validate().unwrap();
+
+        let query = Query::builder()
+            .drilldown("Date.Date.Year")
+            .drilldown("Geography.Tract.State")
+            .measure("Quantity")
+            .fill("zero")
+            .build()
+            .unwrap();
+
+        let (_, headers) = schema.sql_query("sales", &query).unwrap();
+        assert_eq!(headers, vec!["Year", "State ID", "State", "Quantity"]);
+
+        let mut df = DataFrame::from_vec(vec![
+            Column::new("Year".into(), ColumnData::Int32(vec![2018, 2019])),
+            Column::new("State ID".into(), ColumnData::Text(vec!["01".into(), "02".into()])),
+            Column::new("State".into(), ColumnData::Text(vec!["Alabama".into(), "Alaska".into()])),
+            Column::new("Quantity".into(), ColumnData::Int64(vec![1, 2])),
+        ]);
+        schema.fill_time_gaps("sales", &query, &mut df).unwrap();
+        assert_eq!(df.columns[0].stringify_column_data(), vec!["2018", "2019", "2018", "2019"]);
+        assert_eq!(df.columns[2].stringify_column_data(), vec!["Alabama", "Alabama", "Alaska", "Alaska"]);
+        assert_eq!(df.columns[3].stringify_column_data(), vec!["1", "0", "0", "2"]);
+
+        // requires a time drilldown
+        let query = Query::builder()
+            .drilldown("Geography.Tract.State")
+            .measure("Quantity")
+            .fill("null")
+            .build()
+            .unwrap();
+        assert!(schema.sql_query("sales", &query).is_err());
+
+        assert!(Query::builder()
+            .drilldown("Date.Date.Year")
+            .measure("Quantity")
+            .fill("zero")
+            .growth("Date.Date.Year,Quantity")
+            .build()
+            .is_err());
+    }
+
+    #[test]
+    fn measure_metadata() {
+        let schema_str = SCHEMA_STR_SINGLE_HIER_NO_DEFAULT.replace(
+            r#"{ "name": "Quantity", "column": "quantity", "aggregator": "sum" }"#,
+            r#"{ "name": "Quantity", "column": "quantity", "aggregator": "sum", "datatype": "UInt64", "annotations": [{ "name": "unit", "text": "Units" }] }, { "name": "Price", "column": "price", "aggregator": "avg", "type": { "standard": { "units": "USD" } } }"#,
+        );
+        let schema: Schema = Schema::from_json(&schema_str).unwrap();
+
+        let metadata = schema.cube_metadata("sales").unwrap();
+        assert_eq!(metadata.measures[0].aggregator.name, "sum");
+        assert_eq!(metadata.measures[0].units, Some("Units".to_owned()));
+        assert_eq!(metadata.measures[0].datatype, Some("UInt64".to_owned()));
+        assert_eq!(metadata.measures[1].aggregator.name, "avg");
+        assert_eq!(metadata.measures[1].units, Some("USD".to_owned()));
+        assert_eq!(metadata.measures[1].datatype, None);
+    }
+
+    #[test]
+    fn measure_filter() {
+        let schema_str = SCHEMA_STR_SINGLE_HIER_NO_DEFAULT.replace(
+            r#"{ "name": "Quantity", "column": "quantity", "aggregator": "sum" }"#,
+            r#"{ "name": "Quantity", "column": "quantity", "aggregator": "sum" }, { "name": "Online Quantity", "column": "quantity", "aggregator": "sum", "filter": "online = 1" }"#,
+        );
+        let mut schema: Schema = Schema::from_json(&schema_str).unwrap();
+        schema.validate().unwrap();
+
+        let query = Query::builder()
+            .drilldown("Geography.Tract.State")
+            .measure("Quantity")
+            .measure("Online Quantity")
+            .build()
+            .unwrap();
+
+        let (query_ir, _) = schema.sql_query("sales", &query).unwrap();
+        assert_eq!(query_ir.meas[0].filter, None);
+        assert_eq!(query_ir.meas[1].filter, Some("online = 1".to_owned()));
+
+        // only basic aggregators can be filtered
+        let schema_str = SCHEMA_STR_SINGLE_HIER_NO_DEFAULT.replace(
+            r#""aggregator": "sum" }"#,
+            r#""aggregator": { "weighted_sum": { "weight_column": "pop" } }, "filter": "online = 1" }"#,
+        );
+        let mut schema: Schema = Schema::from_json(&schema_str).unwrap();
+        assert!(schema.validate().is_err());
+    }
+
+    #[test]
+    fn measure_caption() {
+        let schema_str = SCHEMA_STR_SINGLE_HIER_NO_DEFAULT.replace(
+            r#""aggregator": "sum" }"#,
+            r#""aggregator": "sum", "annotations": [{ "name": "caption_es", "text": "Cantidad" }] }"#,
+        );
+        let schema: Schema = Schema::from_json(&schema_str).unwrap();
+        let measure = &schema.get_cube_by_name("sales").unwrap().measures[0];
+
+        assert_eq!(measure.caption("es"), Some("Cantidad"));
+        assert_eq!(measure.caption("en"), None);
+    }
+
+    #[test]
+    fn fact_count_measure() {
+        let mut schema: Schema = Schema::from_json(SCHEMA_STR_SINGLE_HIER_NO_DEFAULT).unwrap();
+        schema.validate().unwrap();
+
+        let query = Query::builder()
+            .drilldown("Geography.Tract.State")
+            .measure("Fact Count")
+            .build()
+            .unwrap();
+
+        let (query_ir, headers) = schema.sql_query("sales", &query).unwrap();
+        assert_eq!(headers, vec!["State ID", "State", "Fact Count"]);
+        assert_eq!(query_ir.meas[0].column, "*");
+        assert_eq!(query_ir.meas[0].aggregator, Aggregator::Count);
+
+        // a measure declared in the schema takes its place
+        let schema_str = SCHEMA_STR_SINGLE_HIER_NO_DEFAULT.replace(
+            r#""name": "Quantity", "column": "quantity""#,
+            r#""name": "Fact Count", "column": "quantity""#,
+        );
+        let schema: Schema = Schema::from_json(&schema_str).unwrap();
+        let measures = &schema.get_cube_by_name("sales").unwrap().measures;
+        assert_eq!(measures.len(), 1);
+        assert_eq!(measures[0].column, "quantity");
+    }
+
+    #[test]
+    fn all_level_drilldown() {
+        let mut schema: Schema = Schema::from_json(SCHEMA_STR_SINGLE_HIER_NO_DEFAULT).unwrap();
+        schema.validate().unwrap();
+
+        let query = Query::builder()
+            .drilldown("Geography.Tract.(All)")
+            .cut("Geography.Tract.State.06")
+            .measure("Quantity")
+            .build()
+            .unwrap();
+
+        let (query_ir, headers) = schema.sql_query("sales", &query).unwrap();
+        assert_eq!(headers, vec!["Geography", "Quantity"]);
+        assert_eq!(query_ir.drills[0].table.name, "sales");
+        assert_eq!(query_ir.drills[0].level_columns, vec![
+            LevelColumn { key_column: "'All'".into(), name_column: None },
+        ]);
+        assert_eq!(query_ir.drills[0].col_alias_only_string(), "_All__Geography");
+        // still constrained by the cut
+        assert_eq!(query_ir.cuts.len(), 1);
+        assert_eq!(query_ir.cuts[0].column, "state_id");
+
+        let query = Query::builder()
+            .drilldown("Geography.Place.(All)")
+            .measure("Quantity")
+            .build()
+            .unwrap();
+        assert!(schema.sql_query("sales", &query).is_err());
+
+        let query = Query::builder()
+            .drilldown("Geography.Tract.(All)")
+            .measure("Quantity")
+            .top("1,Geography.Tract.(All),Quantity,desc")
+            .build()
+            .unwrap();
+        assert!(schema.sql_query("sales", &query).is_err());
+    }
+
     #[test]
     fn shared_dim_two_dims() {
         let s = r##"
@@ -0,0 +1,259 @@
+//! Masking of sensitive measures: values computed from too few facts (by
+//! a cube's `masking` rules, e.g. fewer than 5 fact rows) are returned as
+//! nulls, with a flag column marking the rows that were suppressed.
+//!
+//! Masking is applied to the results, after the aggregation; see
+//! `Schema::masking_plan` for the checks made on the query beforehand.
+//!
+//! With complementary suppression, masking a single row of a drilldown
+//! group (the rows that only differ in one drilldown) also masks the
+//! group's next smallest row, so that the masked value can't be worked
+//! out by subtracting the others from the group's total, which a query
+//! without that drilldown returns. This is repeated for each drilldown,
+//! until every group has no masked rows or at least two.
+
+use failure::{Error, format_err};
+use std::collections::BTreeMap;
+
+use crate::dataframe::{Column, ColumnData, DataFrame};
+
+
+/// Name of the column flagging (with 1) the rows where values were masked.
+pub const SUPPRESSED_COLUMN: &str = "Suppressed";
+
+/// How to mask the results of a query (see `Schema::masking_plan`).
+#[derive(Debug, Clone)]
+pub struct MaskingPlan {
+    pub(crate) rules: Vec<MaskedColumns>,
+    /// Measures added to the query for the rules, and dropped from the
+    /// results after masking.
+    pub(crate) added: Vec<String>,
+    /// Headers of each drilldown of the query, for complementary
+    /// suppression.
+    pub(crate) drills: Vec<Vec<String>>,
+}
+
+/// The result columns masked by one rule: the measure, and the columns
+/// calculated from it.
+#[derive(Debug, Clone)]
+pub(crate) struct MaskedColumns {
+    pub(crate) columns: Vec<String>,
+    pub(crate) by: String,
+    pub(crate) min: f64,
+    pub(crate) complementary: bool,
+}
+
+impl MaskingPlan {
+    /// Whether rows are masked depending on other rows, so that all the
+    /// results must be masked at once.
+    pub fn is_complementary(&self) -> bool {
+        self.rules.iter().any(|rule| rule.complementary)
+    }
+
+    /// The headers of the results after masking, from the headers of the
+    /// query's results.
+    pub fn output_headers(&self, headers: &[String]) -> Vec<String> {
+        let mut res: Vec<String> = headers.iter()
+            .filter(|header| !self.added.contains(header))
+            .cloned()
+            .collect();
+        res.push(SUPPRESSED_COLUMN.to_owned());
+        res
+    }
+
+    /// Nulls the masked columns of `df` (the renamed results of the query)
+    /// in the rows where a rule's `by` measure is below its minimum, or
+    /// null, and flags those rows in a `Suppressed` column added last.
+    pub fn apply(&self, df: &mut DataFrame) -> Result<(), Error> {
+        let num_rows = df.len();
+        let mut suppressed = vec![false; num_rows];
+
+        for rule in &self.rules {
+            let by_values = df.columns.iter()
+                .find(|col| col.name == rule.by)
+                .ok_or_else(|| format_err!("Masking needs measure {} in the results", rule.by))?
+                .column_data
+                .to_nullable_f64()
+                .ok_or_else(|| format_err!("Masking measure {} is not numeric", rule.by))?;
+
+            let mut masked: Vec<bool> = by_values.iter()
+                .map(|value| value.map(|value| value < rule.min).unwrap_or(true))
+                .collect();
+
+            if rule.complementary {
+                self.suppress_complementary(df, &by_values, &mut masked);
+            }
+
+            // the measure being masked could be the `by` measure itself,
+            // so its values are read before any column is masked
+            let rows: Vec<Option<usize>> = masked.iter()
+                .enumerate()
+                .map(|(i, &masked)| if masked { None } else { Some(i) })
+                .collect();
+
+            let mut any_masked = false;
+            for col in df.columns.iter_mut().filter(|col| rule.columns.contains(&col.name)) {
+                col.column_data = col.column_data.take(&rows, false);
+                any_masked = true;
+            }
+
+            if any_masked {
+                for (flag, masked) in suppressed.iter_mut().zip(masked) {
+                    *flag |= masked;
+                }
+            }
+        }
+
+        df.columns.retain(|col| !self.added.contains(&col.name));
+        df.columns.push(Column::new(
+            SUPPRESSED_COLUMN.to_owned(),
+            ColumnData::UInt8(suppressed.into_iter().map(|flag| flag as u8).collect()),
+        ));
+
+        Ok(())
+    }
+
+    /// Masks the smallest unmasked row, by the rule's `by` measure, of each
+    /// drilldown group with a single masked row.
+    fn suppress_complementary(&self, df: &DataFrame, by_values: &[Option<f64>], masked: &mut [bool]) {
+        let drill_values: Vec<Vec<Vec<String>>> = self.drills.iter()
+            .map(|headers| {
+                df.columns.iter()
+                    .filter(|col| headers.contains(&col.name))
+                    .map(|col| col.stringify_column_data())
+                    .collect()
+            })
+            .collect();
+
+        loop {
+            let mut changed = false;
+
+            for i in 0..drill_values.len() {
+                // rows with the same members of the other drilldowns
+                let mut groups: BTreeMap<Vec<&str>, Vec<usize>> = BTreeMap::new();
+                for row in 0..masked.len() {
+                    let key = drill_values.iter()
+                        .enumerate()
+                        .filter(|(j, _)| *j != i)
+                        .flat_map(|(_, cols)| cols.iter().map(move |values| values[row].as_str()))
+                        .collect();
+                    groups.entry(key).or_insert_with(Vec::new).push(row);
+                }
+
+                for rows in groups.values() {
+                    if rows.iter().filter(|&&row| masked[row]).count() != 1 {
+                        continue;
+                    }
+
+                    let smallest = rows.iter()
+                        .filter(|&&row| !masked[row])
+                        .min_by(|&&a, &&b| {
+                            by_values[a].partial_cmp(&by_values[b]).unwrap_or(std::cmp::Ordering::Equal)
+                        });
+
+                    if let Some(&row) = smallest {
+                        masked[row] = true;
+                        changed = true;
+                    }
+                }
+            }
+
+            if !changed {
+                break;
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn apply() {
+        let mut df = DataFrame::from_vec(vec![
+            Column::new("Year".into(), ColumnData::Int32(vec![2018, 2019, 2020])),
+            Column::new("Fact Count".into(), ColumnData::NullableUInt64(vec![Some(10), Some(2), None])),
+            Column::new("Deaths".into(), ColumnData::Int64(vec![7, 1, 0])),
+            Column::new("Deaths Growth".into(), ColumnData::Float64(vec![0.5, -0.8, 0.0])),
+            Column::new("Population".into(), ColumnData::Int64(vec![100, 200, 300])),
+        ]);
+
+        let plan = MaskingPlan {
+            rules: vec![
+                MaskedColumns {
+                    columns: vec!["Deaths".into(), "Deaths Growth".into()],
+                    by: "Fact Count".into(),
+                    min: 5.0,
+                    complementary: false,
+                },
+            ],
+            added: vec!["Fact Count".into()],
+            drills: vec![vec!["Year".into()]],
+        };
+
+        assert_eq!(
+            plan.output_headers(&df.column_names()),
+            vec!["Year", "Deaths", "Deaths Growth", "Population", "Suppressed"],
+        );
+
+        plan.apply(&mut df).unwrap();
+
+        assert_eq!(df.column_names(), vec!["Year", "Deaths", "Deaths Growth", "Population", "Suppressed"]);
+        match df.columns[1].column_data {
+            ColumnData::NullableInt64(ref v) => assert_eq!(v, &vec![Some(7), None, None]),
+            ref col => panic!("unexpected column type {:?}", col),
+        }
+        match df.columns[2].column_data {
+            ColumnData::NullableFloat64(ref v) => assert_eq!(v, &vec![Some(0.5), None, None]),
+            ref col => panic!("unexpected column type {:?}", col),
+        }
+        match df.columns[3].column_data {
+            ColumnData::Int64(ref v) => assert_eq!(v, &vec![100, 200, 300]),
+            ref col => panic!("unexpected column type {:?}", col),
+        }
+        match df.columns[4].column_data {
+            ColumnData::UInt8(ref v) => assert_eq!(v, &vec![0, 1, 1]),
+            ref col => panic!("unexpected column type {:?}", col),
+        }
+    }
+
+    #[test]
+    fn complementary_suppression() {
+        // a 2x3 table of states by years, with one small cell
+        let mut df = DataFrame::from_vec(vec![
+            Column::new("State".into(), ColumnData::Text(vec!["CA", "CA", "CA", "NY", "NY", "NY"].into_iter().map(String::from).collect())),
+            Column::new("Year".into(), ColumnData::Int32(vec![2018, 2019, 2020, 2018, 2019, 2020])),
+            Column::new("Deaths".into(), ColumnData::Int64(vec![3, 20, 30, 40, 10, 60])),
+        ]);
+
+        let plan = MaskingPlan {
+            rules: vec![
+                MaskedColumns {
+                    columns: vec!["Deaths".into()],
+                    by: "Deaths".into(),
+                    min: 5.0,
+                    complementary: true,
+                },
+            ],
+            added: vec![],
+            drills: vec![vec!["State".into()], vec!["Year".into()]],
+        };
+        assert!(plan.is_complementary());
+
+        plan.apply(&mut df).unwrap();
+
+        // CA 2018 is small. NY 2018 is masked so that the 2018 total can't
+        // give it away, then the smallest rows left of CA and NY, for the
+        // state totals
+        match df.columns[2].column_data {
+            ColumnData::NullableInt64(ref v) => assert_eq!(v, &vec![None, None, Some(30), None, None, Some(60)]),
+            ref col => panic!("unexpected column type {:?}", col),
+        }
+        match df.columns[3].column_data {
+            ColumnData::UInt8(ref v) => assert_eq!(v, &vec![1, 1, 0, 1, 1, 0]),
+            ref col => panic!("unexpected column type {:?}", col),
+        }
+    }
+}
@@ -22,10 +22,18 @@
 
 use failure::{Error, bail, format_err, ensure};
 use serde_derive::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::fmt;
 use std::str::FromStr;
 
 
+/// Name of the implicit level above the first level of every hierarchy,
+/// which has a single member for all of the hierarchy's members.
+pub const ALL_LEVEL: &str = "(All)";
+
+/// Member of the `ALL_LEVEL`.
+pub const ALL_MEMBER: &str = "All";
+
 /// Fully qualified name of Dimension, Hierarchy, and Level
 /// Basis for other names.
 #[derive(Debug, Clone, PartialEq, Deserialize, Serialize, Eq, Hash)]
@@ -78,6 +86,11 @@ impl LevelName {
     pub fn level(&self) -> &str {
         self.level.as_str()
     }
+
+    /// Whether this is the hierarchy's `(All)` level.
+    pub fn is_all(&self) -> bool {
+        self.level == ALL_LEVEL
+    }
 }
 
 impl fmt::Display for LevelName {
@@ -453,6 +466,110 @@ impl FromStr for Property {
 }
 
 
+/// How names in a query are matched to the names in the schema.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all="lowercase")]
+pub enum NameResolution {
+    /// Names must match exactly.
+    Exact,
+    /// Names match case-insensitively, and underscores match spaces, so
+    /// that url-friendly slugs can be used: `product_category` resolves
+    /// to `Product Category`.
+    Lenient,
+}
+
+impl Default for NameResolution {
+    fn default() -> Self {
+        NameResolution::Exact
+    }
+}
+
+impl NameResolution {
+    pub fn matches(&self, schema_name: &str, name: &str) -> bool {
+        match self {
+            NameResolution::Exact => schema_name == name,
+            NameResolution::Lenient => schema_name == name || slug(schema_name) == slug(name),
+        }
+    }
+
+    /// Looks up a name in a map keyed by schema names. An exact match is
+    /// always preferred. Errors, listing the candidates, if the name
+    /// matches several schema names leniently.
+    pub fn get<'a, V>(&self, map: &'a HashMap<String, V>, name: &str) -> Result<Option<&'a V>, Error> {
+        if let Some(value) = map.get(name) {
+            return Ok(Some(value));
+        }
+
+        match self {
+            NameResolution::Exact => Ok(None),
+            NameResolution::Lenient => {
+                let name_slug = slug(name);
+                let matches: Vec<_> = map.iter()
+                    .filter(|(k, _)| slug(k) == name_slug)
+                    .collect();
+
+                match matches.as_slice() {
+                    [] => Ok(None),
+                    [(_, value)] => Ok(Some(value)),
+                    _ => {
+                        let mut candidates: Vec<_> = matches.iter().map(|(k, _)| k.as_str()).collect();
+                        candidates.sort();
+                        bail!("{} is ambiguous, it could be any of: {}", name, candidates.join(", "))
+                    },
+                }
+            },
+        }
+    }
+}
+
+/// Lowercases a name and replaces spaces with underscores.
+pub fn slug(name: &str) -> String {
+    name.trim().to_lowercase().replace(' ', "_")
+}
+
+/// Finds the candidate closest to a name that failed to resolve, for
+/// "did you mean" suggestions in error messages. Comparison ignores case;
+/// candidates that are too far off aren't suggested.
+pub fn closest_match<'a, I>(name: &str, candidates: I) -> Option<&'a str>
+    where I: IntoIterator<Item=&'a str>
+{
+    let name = name.to_lowercase();
+    let max_distance = std::cmp::max(2, name.chars().count() / 3);
+
+    candidates.into_iter()
+        .map(|c| (edit_distance(&name, &c.to_lowercase()), c))
+        .filter(|(distance, _)| *distance <= max_distance)
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, c)| c)
+}
+
+/// Formats the suggestion from `closest_match`, for appending to an
+/// error message.
+pub fn suggestion<'a, I>(name: &str, candidates: I) -> String
+    where I: IntoIterator<Item=&'a str>
+{
+    closest_match(name, candidates)
+        .map(|c| format!("; did you mean {}?", c))
+        .unwrap_or_default()
+}
+
+/// Levenshtein distance
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+
+    for (i, ca) in a.chars().enumerate() {
+        let mut curr = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = prev[j] + if ca == *cb { 0 } else { 1 };
+            curr.push(substitution.min(prev[j + 1] + 1).min(curr[j] + 1));
+        }
+        prev = curr;
+    }
+
+    prev[b.len()]
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -594,5 +711,48 @@ mod test {
         assert_eq!(property, property_test_2);
         assert_eq!(property, property_test_3);
     }
-}
 
+    #[test]
+    fn test_closest_match() {
+        let candidates = vec!["Quantity", "Price", "Revenue"];
+
+        assert_eq!(closest_match("quantty", candidates.clone()), Some("Quantity"));
+        assert_eq!(closest_match("Prices", candidates.clone()), Some("Price"));
+        assert_eq!(closest_match("Population", candidates.clone()), None);
+        assert_eq!(suggestion("revenu", candidates.clone()), "; did you mean Revenue?");
+        assert_eq!(suggestion("Population", candidates), "");
+    }
+
+    #[test]
+    fn test_name_resolution() {
+        assert!(NameResolution::Exact.matches("Product Category", "Product Category"));
+        assert!(!NameResolution::Exact.matches("Product Category", "product_category"));
+        assert!(NameResolution::Lenient.matches("Product Category", "product_category"));
+        assert!(NameResolution::Lenient.matches("Product Category", "PRODUCT CATEGORY"));
+        assert!(!NameResolution::Lenient.matches("Product Category", "product"));
+
+        let mut map = HashMap::new();
+        map.insert("Year".to_owned(), 1);
+        map.insert("Fiscal Year".to_owned(), 2);
+
+        assert_eq!(NameResolution::Exact.get(&map, "fiscal_year").unwrap(), None);
+        assert_eq!(NameResolution::Lenient.get(&map, "fiscal_year").unwrap(), Some(&2));
+        assert_eq!(NameResolution::Lenient.get(&map, "year").unwrap(), Some(&1));
+        assert_eq!(NameResolution::Lenient.get(&map, "month").unwrap(), None);
+    }
+
+    #[test]
+    fn test_name_resolution_ambiguous() {
+        let mut map = HashMap::new();
+        map.insert("Fiscal Year".to_owned(), 1);
+        map.insert("fiscal_year".to_owned(), 2);
+
+        // an exact match is never ambiguous
+        assert_eq!(NameResolution::Lenient.get(&map, "Fiscal Year").unwrap(), Some(&1));
+
+        assert_eq!(
+            NameResolution::Lenient.get(&map, "FISCAL YEAR").unwrap_err().to_string(),
+            "FISCAL YEAR is ambiguous, it could be any of: Fiscal Year, fiscal_year",
+        );
+    }
+}
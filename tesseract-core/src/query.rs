@@ -1,8 +1,12 @@
 use itertools::join;
 
-use failure::{Error, format_err, bail};
+use failure::{Error, Fail, format_err, bail};
+use serde_derive::{Serialize, Deserialize};
+use std::convert::TryFrom;
+use std::fmt;
 use std::str::FromStr;
 
+use crate::dataframe::FillMethod;
 use crate::names::{
     Cut,
     Drilldown,
@@ -11,10 +15,13 @@ use crate::names::{
     LevelName,
 };
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub struct Query {
     pub cuts: Vec<Cut>,
     pub drilldowns: Vec<Drilldown>,
+    /// Level properties that are grouped by directly, like a drilldown,
+    /// without a drilldown on their level.
+    pub property_drilldowns: Vec<Property>,
     pub measures: Vec<Measure>,
     pub properties: Vec<Property>,
     pub filters: Vec<FilterQuery>,
@@ -27,15 +34,31 @@ pub struct Query {
     pub rca: Option<RcaQuery>,
     pub growth: Option<GrowthQuery>,
     pub rate: Option<RateQuery>,
+    pub ratio_to_parent: Option<RatioToParentQuery>,
+    /// Divides a measure by a measure of another cube, computed by
+    /// `Schema::calculation_plan`.
+    pub rate_against: Option<RateAgainstQuery>,
+    /// Compares the measures between two sets of cuts, computed by
+    /// `Schema::comparison_plan`.
+    pub compare: Option<CompareQuery>,
     pub debug: bool,
     pub sparse: bool,
     pub exclude_default_members: bool,
+    pub round: Option<u32>,
+    /// Fills in the periods missing from a time drilldown's series, for
+    /// each group of the other drilldowns (see `Schema::fill_time_gaps`).
+    pub fill: Option<FillMethod>,
+    /// Role of the principal running the query, for the schema's row
+    /// filters. Set by the server from the request, never from the query
+    /// string.
+    pub role: Option<String>,
 }
 
 impl Query {
     pub fn new() -> Self {
         Query {
             drilldowns: vec![],
+            property_drilldowns: vec![],
             cuts: vec![],
             measures: vec![],
             properties: vec![],
@@ -49,10 +72,499 @@ impl Query {
             rca: None,
             growth: None,
             rate: None,
+            ratio_to_parent: None,
+            rate_against: None,
+            compare: None,
             debug: false,
             sparse: false,
             exclude_default_members: false,
+            round: None,
+            fill: None,
+            role: None,
+        }
+    }
+
+    /// Returns a `QueryBuilder` for constructing a `Query` piece by piece.
+    pub fn builder() -> QueryBuilder {
+        QueryBuilder::new()
+    }
+
+    /// Returns a copy of the query with repeated drilldowns, measures,
+    /// properties, captions and cut members removed, keeping the first
+    /// occurrence of each. Repeats would otherwise generate duplicate
+    /// columns and aggregations.
+    pub fn deduplicated(&self) -> Query {
+        let mut query = self.clone();
+
+        dedup_stable(&mut query.drilldowns);
+        dedup_stable(&mut query.property_drilldowns);
+        dedup_stable(&mut query.measures);
+        dedup_stable(&mut query.properties);
+        dedup_stable(&mut query.captions);
+        for cut in query.cuts.iter_mut() {
+            dedup_stable(&mut cut.members);
+        }
+
+        query
+    }
+
+    /// Returns a copy of the query with cuts, and the members within each
+    /// cut, sorted. Queries that only differ in cut order generate the
+    /// same results, so this can be used as a cache key.
+    pub fn normalized(&self) -> Query {
+        let mut query = self.deduplicated();
+
+        for cut in query.cuts.iter_mut() {
+            cut.members.sort();
+        }
+        query.cuts.sort_by_key(|cut| cut.to_string());
+
+        query
+    }
+
+    /// Checks the parts of a query that don't depend on the schema,
+    /// like making sure there's a measure, and that there's either
+    /// a cut or drilldown.
+    ///
+    /// All problems are reported together as `QueryErrors`.
+    pub fn validate(&self) -> Result<(), Error> {
+        let errors = self.errors();
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(QueryErrors(errors).into())
+        }
+    }
+
+    /// Returns every problem with the query that can be found without
+    /// the schema.
+    pub fn errors(&self) -> Vec<String> {
+        let mut errors = vec![];
+
+        if self.measures.is_empty() && self.rca.is_none() {
+            errors.push("No measure found; please specify at least one".to_owned());
+        }
+        if self.drilldowns.is_empty() && self.property_drilldowns.is_empty() && self.cuts.is_empty(){
+            errors.push("Either a drilldown or cut is required".to_owned());
+        }
+
+        // also check that properties have a matching drilldown
+        if let Some(ref rca) = self.rca {
+            let rca_drills = [&rca.drill_1, &rca.drill_2];
+            for property in &self.properties {
+                let has_drill = rca_drills.iter()
+                    .any(|d| d.0 == property.level_name);
+
+                if !has_drill {
+                    errors.push(format!("Property {} has no matching drilldown", property));
+                }
+            }
+        } else {
+            for property in &self.properties {
+                let has_drill = self.drilldowns.iter()
+                    .any(|d| d.0 == property.level_name);
+
+                if !has_drill {
+                    errors.push(format!("Property {} has no matching drilldown", property));
+                }
+            }
+        }
+
+        // a property drilldown groups by the property instead of the level,
+        // so it can't be mixed with a drilldown on its own dimension.
+        for property in &self.property_drilldowns {
+            let has_drill = self.drilldowns.iter()
+                .any(|d| d.0.dimension == property.level_name.dimension);
+
+            if has_drill {
+                errors.push(format!("Property drilldown {} cannot be combined with a drilldown on the same dimension", property));
+            }
+        }
+
+        // the "Other" row is rolled up from the final measures, which
+        // calculations would have to be redone for
+        if self.top.as_ref().map(|t| t.other).unwrap_or(false) {
+            if self.growth.is_some() || self.rca.is_some() || self.rate.is_some() {
+                errors.push("top_other cannot be combined with growth, rca or rate".to_owned());
+            }
         }
+
+        // the share is of the parent level's total, so the parent levels
+        // must be in the results
+        if let Some(ref ratio) = self.ratio_to_parent {
+            if !self.parents {
+                errors.push("ratio_to_parent requires parents=true".to_owned());
+            }
+            if !self.drilldowns.contains(&ratio.drill) {
+                errors.push(format!("Ratio to parent drilldown {} is not in drilldowns", ratio.drill));
+            }
+            if !self.measures.contains(&ratio.mea) {
+                errors.push(format!("Ratio to parent measure {} is not in measures", ratio.mea));
+            }
+            if self.growth.is_some() || self.rca.is_some() || self.rate.is_some() {
+                errors.push("ratio_to_parent cannot be combined with growth, rca or rate".to_owned());
+            }
+            if self.top.as_ref().map(|t| t.other).unwrap_or(false) {
+                errors.push("ratio_to_parent cannot be combined with top_other".to_owned());
+            }
+        }
+
+        if let Some(ref rate_against) = self.rate_against {
+            if !self.measures.contains(&rate_against.mea) {
+                errors.push(format!("Rate against measure {} is not in measures", rate_against.mea));
+            }
+        }
+
+        // the two results are joined on the drilldowns, so they can't be
+        // on the compared levels
+        if let Some(ref compare) = self.compare {
+            for cut in compare.cuts_a.iter().chain(&compare.cuts_b) {
+                if self.drilldowns.iter().any(|d| d.0 == cut.level_name) {
+                    errors.push(format!("Compare cut level {} cannot also be a drilldown", cut.level_name));
+                }
+                if self.cuts.iter().any(|c| c.level_name == cut.level_name) {
+                    errors.push(format!("Compare cut level {} cannot also be in cuts", cut.level_name));
+                }
+            }
+        }
+
+        // gaps are filled in on the final rows, after calculations that
+        // would have to be redone for the added rows
+        if self.fill.is_some() {
+            let top_other = self.top.as_ref().map(|t| t.other).unwrap_or(false);
+            if self.growth.is_some() || self.rca.is_some() || self.rate.is_some() || self.ratio_to_parent.is_some() || top_other {
+                errors.push("fill cannot be combined with growth, rca, rate, ratio_to_parent or top_other".to_owned());
+            }
+        }
+
+        // TODO check that top dim and mea are in here?
+        // TODO check that top_where maps to a mea that's not in top, but is in meas.
+
+        // for growth, check if time dim and mea are in drilldown and measures
+        if let Some(ref growth) = self.growth {
+            if !self.drilldowns.contains(&growth.time_drill) {
+                errors.push(format!("Growth time drilldown {} is not in drilldowns", growth.time_drill));
+            }
+            if !self.measures.contains(&growth.mea) {
+                errors.push(format!("Growth measure {} is not in measures", growth.mea));
+            }
+        }
+
+        // for rca, disallow cuts on the second drilldown for now, until better system
+        // is figured out.
+        // There is internal filtering of cuts internally also, which should follow the
+        // pattern of the check here.
+        if let Some(ref rca) = self.rca {
+            let cuts_contain_drill_2 = self.cuts.iter()
+                .any(|c| c.level_name == rca.drill_2.0);
+
+            if cuts_contain_drill_2 {
+                errors.push("Cut on rca drill 2 is not allowed; for rca, \
+                    only external cuts and cuts on drill 1 allowed".to_owned());
+            }
+        }
+
+        errors
+    }
+}
+
+/// Removes repeated items, keeping the order of first occurrence.
+fn dedup_stable<T: PartialEq>(items: &mut Vec<T>) {
+    let mut i = 0;
+    while i < items.len() {
+        if items[..i].contains(&items[i]) {
+            items.remove(i);
+        } else {
+            i += 1;
+        }
+    }
+}
+
+/// All the problems found when validating a query, so that they can be
+/// fixed in one go instead of one request per problem.
+#[derive(Debug, Clone, PartialEq)]
+pub struct QueryErrors(pub Vec<String>);
+
+impl fmt::Display for QueryErrors {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", join(&self.0, "; "))
+    }
+}
+
+impl Fail for QueryErrors {}
+
+/// Builds a `Query` from the same string formats accepted in the
+/// aggregate endpoint's query params, e.g.
+///
+/// ```ignore
+/// let query = Query::builder()
+///     .drilldown("Geography.State")
+///     .cut("Year.Year.Year.2018")
+///     .measure("Quantity")
+///     .top("10,Geography.State,Quantity,desc")
+///     .build()?;
+/// ```
+///
+/// Parse errors are collected as the query is built, and returned
+/// together with any validation error from `build()`.
+#[derive(Debug, Clone)]
+pub struct QueryBuilder {
+    query: Query,
+    errors: Vec<String>,
+}
+
+impl QueryBuilder {
+    pub fn new() -> Self {
+        QueryBuilder {
+            query: Query::new(),
+            errors: vec![],
+        }
+    }
+
+    pub fn drilldown(mut self, drilldown: &str) -> Self {
+        if let Some(d) = self.parse(drilldown, "drilldown") {
+            self.query.drilldowns.push(d);
+        }
+        self
+    }
+
+    pub fn property_drilldown(mut self, property: &str) -> Self {
+        if let Some(p) = self.parse(property, "property drilldown") {
+            self.query.property_drilldowns.push(p);
+        }
+        self
+    }
+
+    pub fn cut(mut self, cut: &str) -> Self {
+        if let Some(c) = self.parse(cut, "cut") {
+            self.query.cuts.push(c);
+        }
+        self
+    }
+
+    pub fn measure(mut self, measure: &str) -> Self {
+        if let Some(m) = self.parse(measure, "measure") {
+            self.query.measures.push(m);
+        }
+        self
+    }
+
+    pub fn property(mut self, property: &str) -> Self {
+        if let Some(p) = self.parse(property, "property") {
+            self.query.properties.push(p);
+        }
+        self
+    }
+
+    pub fn caption(mut self, caption: &str) -> Self {
+        if let Some(c) = self.parse(caption, "caption") {
+            self.query.captions.push(c);
+        }
+        self
+    }
+
+    pub fn filter(mut self, filter: &str) -> Self {
+        if let Some(f) = self.parse(filter, "filter") {
+            self.query.filters.push(f);
+        }
+        self
+    }
+
+    pub fn top(mut self, top: &str) -> Self {
+        self.query.top = self.parse(top, "top");
+        self
+    }
+
+    /// Sets `other` on the top, so it has to come after `top`.
+    pub fn top_other(mut self, other: bool) -> Self {
+        match self.query.top {
+            Some(ref mut top) => top.other = other,
+            None if other => self.errors.push("top_other requires a top".to_owned()),
+            None => (),
+        }
+        self
+    }
+
+    pub fn top_where(mut self, top_where: &str) -> Self {
+        self.query.top_where = self.parse(top_where, "top_where");
+        self
+    }
+
+    pub fn sort(mut self, sort: &str) -> Self {
+        self.query.sort = self.parse(sort, "sort");
+        self
+    }
+
+    pub fn limit(mut self, n: u64, offset: Option<u64>) -> Self {
+        self.query.limit = Some(LimitQuery { offset, n });
+        self
+    }
+
+    pub fn rca(mut self, rca: &str) -> Self {
+        self.query.rca = self.parse(rca, "rca");
+        self
+    }
+
+    pub fn growth(mut self, growth: &str) -> Self {
+        self.query.growth = self.parse(growth, "growth");
+        self
+    }
+
+    pub fn rate(mut self, rate: &str) -> Self {
+        self.query.rate = self.parse(rate, "rate");
+        self
+    }
+
+    pub fn ratio_to_parent(mut self, ratio_to_parent: &str) -> Self {
+        self.query.ratio_to_parent = self.parse(ratio_to_parent, "ratio_to_parent");
+        self
+    }
+
+    pub fn rate_against(mut self, rate_against: &str) -> Self {
+        self.query.rate_against = self.parse(rate_against, "rate_against");
+        self
+    }
+
+    pub fn compare(mut self, compare: &str) -> Self {
+        self.query.compare = self.parse(compare, "compare");
+        self
+    }
+
+    pub fn parents(mut self, parents: bool) -> Self {
+        self.query.parents = parents;
+        self
+    }
+
+    pub fn debug(mut self, debug: bool) -> Self {
+        self.query.debug = debug;
+        self
+    }
+
+    pub fn sparse(mut self, sparse: bool) -> Self {
+        self.query.sparse = sparse;
+        self
+    }
+
+    pub fn exclude_default_members(mut self, exclude_default_members: bool) -> Self {
+        self.query.exclude_default_members = exclude_default_members;
+        self
+    }
+
+    pub fn round(mut self, round: u32) -> Self {
+        self.query.round = Some(round);
+        self
+    }
+
+    pub fn fill(mut self, fill: &str) -> Self {
+        if let Some(f) = self.parse(fill, "fill") {
+            self.query.fill = Some(f);
+        }
+        self
+    }
+
+    /// Returns the `Query`, or all the errors found while building it.
+    pub fn build(self) -> Result<Query, Error> {
+        if !self.errors.is_empty() {
+            bail!("Invalid query: {}", self.errors.join("; "));
+        }
+
+        self.query.validate()?;
+
+        Ok(self.query)
+    }
+
+    fn parse<T>(&mut self, s: &str, kind: &str) -> Option<T>
+        where T: FromStr<Err=Error>
+    {
+        match s.parse::<T>() {
+            Ok(x) => Some(x),
+            Err(err) => {
+                self.errors.push(format!("could not parse {} '{}': {}", kind, s, err));
+                None
+            },
+        }
+    }
+}
+
+/// A query described in JSON, using the same string formats
+/// as the aggregate endpoint's query params. Used by the wasm
+/// and C bindings.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct QueryJson {
+    pub drilldowns: Vec<String>,
+    pub property_drilldowns: Vec<String>,
+    pub cuts: Vec<String>,
+    pub measures: Vec<String>,
+    pub properties: Vec<String>,
+    pub filters: Vec<String>,
+    pub parents: bool,
+    pub top: Option<String>,
+    pub top_other: bool,
+    pub top_where: Option<String>,
+    pub sort: Option<String>,
+    pub limit: Option<u64>,
+    pub growth: Option<String>,
+    pub rca: Option<String>,
+    pub rate: Option<String>,
+    pub ratio_to_parent: Option<String>,
+    pub round: Option<u32>,
+}
+
+impl TryFrom<QueryJson> for Query {
+    type Error = Error;
+
+    fn try_from(query_json: QueryJson) -> Result<Self, Self::Error> {
+        let mut builder = Query::builder().parents(query_json.parents);
+
+        for drilldown in &query_json.drilldowns {
+            builder = builder.drilldown(drilldown);
+        }
+        for property in &query_json.property_drilldowns {
+            builder = builder.property_drilldown(property);
+        }
+        for cut in &query_json.cuts {
+            builder = builder.cut(cut);
+        }
+        for measure in &query_json.measures {
+            builder = builder.measure(measure);
+        }
+        for property in &query_json.properties {
+            builder = builder.property(property);
+        }
+        for filter in &query_json.filters {
+            builder = builder.filter(filter);
+        }
+        if let Some(ref top) = query_json.top {
+            builder = builder.top(top).top_other(query_json.top_other);
+        }
+        if let Some(ref top_where) = query_json.top_where {
+            builder = builder.top_where(top_where);
+        }
+        if let Some(ref sort) = query_json.sort {
+            builder = builder.sort(sort);
+        }
+        if let Some(limit) = query_json.limit {
+            builder = builder.limit(limit, None);
+        }
+        if let Some(ref growth) = query_json.growth {
+            builder = builder.growth(growth);
+        }
+        if let Some(ref rca) = query_json.rca {
+            builder = builder.rca(rca);
+        }
+        if let Some(ref rate) = query_json.rate {
+            builder = builder.rate(rate);
+        }
+        if let Some(ref ratio_to_parent) = query_json.ratio_to_parent {
+            builder = builder.ratio_to_parent(ratio_to_parent);
+        }
+        if let Some(round) = query_json.round {
+            builder = builder.round(round);
+        }
+
+        builder.build()
     }
 }
 
@@ -61,12 +573,15 @@ impl Query {
 /// ClickHouse:
 /// select * from table_name order by sort_measures sort_direction
 /// limit n by by_dimension
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub struct TopQuery {
     pub n: u64,
     pub by_dimension: LevelName,
     pub sort_mea_or_calc: Vec<MeaOrCalc>,
     pub sort_direction: SortDirection,
+    /// Also return an "Other" row for each `by_dimension` member, rolling
+    /// up the rows outside of the top n.
+    pub other: bool,
 }
 
 impl TopQuery  {
@@ -78,7 +593,8 @@ impl TopQuery  {
             n,
             by_dimension,
             sort_mea_or_calc,
-            sort_direction
+            sort_direction,
+            other: false,
         }
     }
 }
@@ -101,6 +617,7 @@ impl FromStr for TopQuery {
                     by_dimension,
                     sort_mea_or_calc,
                     sort_direction,
+                    other: false,
                 })
             },
             _ => bail!("Could not parse a top query"),
@@ -111,7 +628,8 @@ impl FromStr for TopQuery {
 // Just for TopQuery
 /// Currently rca and growth will be reserved keywords. This may be changed in the future,
 /// to allow measures that are named rca and growth
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all="snake_case")]
 pub enum MeaOrCalc {
     Mea(Measure),
     Calc(Calculation),
@@ -131,10 +649,12 @@ impl FromStr for MeaOrCalc {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all="snake_case")]
 pub enum Calculation {
     Rca,
     Growth,
+    Rate,
 }
 
 impl Calculation {
@@ -142,6 +662,7 @@ impl Calculation {
         match self {
             Calculation::Rca => "rca".to_owned(),
             Calculation::Growth => "growth".to_owned(),
+            Calculation::Rate => "rate".to_owned(),
         }
     }
 }
@@ -153,13 +674,14 @@ impl FromStr for Calculation {
         match &s.to_lowercase()[..] {
             "rca" => Ok(Calculation::Rca),
             "growth" => Ok(Calculation::Growth),
+            "rate" => Ok(Calculation::Rate),
             _ => Err(format_err!("'{}' is not a supported calculation", s)),
         }
     }
 }
 
 /// For filtering on a measure before Top is calculated
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub struct TopWhereQuery {
     pub by_mea_or_calc: MeaOrCalc,
     pub constraint: Constraint,
@@ -189,10 +711,10 @@ impl FromStr for TopWhereQuery {
 // Constraint: less than, greater than a number
 // This is a little less straightforward, so we should
 // probably test this
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub struct Constraint {
     pub comparison: Comparison,
-    pub n: i64,
+    pub n: f64,
 }
 
 impl Constraint {
@@ -205,11 +727,12 @@ impl FromStr for Constraint {
     type Err = Error;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        match &s.split(".").collect::<Vec<_>>()[..] {
+        // split once, so that the number may have a decimal point
+        match &s.splitn(2, ".").collect::<Vec<_>>()[..] {
             [comparison, n] => {
 
                 let comparison = comparison.parse::<Comparison>()?;
-                let n = n.parse::<i64>()?;
+                let n = n.parse::<f64>()?;
 
                 Ok(Constraint {
                     comparison,
@@ -221,7 +744,8 @@ impl FromStr for Constraint {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all="snake_case")]
 pub enum Comparison {
     Equal,
     NotEqual,
@@ -260,7 +784,7 @@ impl FromStr for Comparison {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub struct LimitQuery {
     pub offset: Option<u64>,
     pub n: u64,
@@ -288,7 +812,7 @@ impl FromStr for LimitQuery {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub struct SortQuery {
     pub direction: SortDirection,
     pub measure: Measure,
@@ -313,9 +837,11 @@ impl FromStr for SortQuery {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum SortDirection {
+    #[serde(rename="asc")]
     Asc,
+    #[serde(rename="desc")]
     Desc,
 }
 
@@ -340,7 +866,7 @@ impl FromStr for SortDirection {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub struct RcaQuery {
     pub drill_1: Drilldown,
     pub drill_2: Drilldown,
@@ -387,7 +913,7 @@ impl FromStr for RcaQuery {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub struct GrowthQuery {
     pub time_drill: Drilldown,
     pub mea: Measure,
@@ -425,8 +951,101 @@ impl FromStr for GrowthQuery {
     }
 }
 
+/// Share of a measure in the total of the drilldown's parent member, e.g.
+/// of each county in its state. Requires `parents`.
+#[derive(Debug, Clone, Serialize)]
+pub struct RatioToParentQuery {
+    pub drill: Drilldown,
+    pub mea: Measure,
+}
+
+impl FromStr for RatioToParentQuery {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match &s.split(",").collect::<Vec<_>>()[..] {
+            [drill, measure] => {
+                Ok(RatioToParentQuery {
+                    drill: drill.parse::<Drilldown>()?,
+                    mea: measure.parse::<Measure>()?,
+                })
+            },
+            _ => bail!("Could not parse a ratio_to_parent query, wrong number of args"),
+        }
+    }
+}
+
+/// A measure divided by a measure of another cube (the denominator, e.g.
+/// population), joined on the query's drilldowns.
+#[derive(Debug, Clone, Serialize)]
+pub struct RateAgainstQuery {
+    pub mea: Measure,
+    pub cube: String,
+    pub against: Measure,
+}
+
+impl RateAgainstQuery {
+    /// Name of the results column, e.g. `Income per Population`.
+    pub fn header(&self) -> String {
+        format!("{} per {}", self.mea.0, self.against.0)
+    }
+}
+
+impl FromStr for RateAgainstQuery {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match &s.split(",").collect::<Vec<_>>()[..] {
+            [measure, cube, against] => {
+                Ok(RateAgainstQuery {
+                    mea: measure.parse::<Measure>()?,
+                    cube: cube.to_string(),
+                    against: against.parse::<Measure>()?,
+                })
+            },
+            _ => bail!("Could not parse a rate_against query, wrong number of args"),
+        }
+    }
+}
+
+/// Two sets of cuts to compare the query's measures between, e.g.
+/// `Date.Year.2020|Date.Year.2019`: the query is run once with each set
+/// added to its cuts. Cuts in a set are separated by `;`.
+#[derive(Debug, Clone, Serialize)]
+pub struct CompareQuery {
+    pub cuts_a: Vec<Cut>,
+    pub cuts_b: Vec<Cut>,
+}
+
+impl FromStr for CompareQuery {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parse_cuts = |cuts: &str| -> Result<Vec<Cut>, Error> {
+            let cuts = cuts.split(';')
+                .filter(|cut| !cut.trim().is_empty())
+                .map(|cut| cut.parse::<Cut>())
+                .collect::<Result<Vec<_>, _>>()?;
+            if cuts.is_empty() {
+                bail!("Each set of cuts to compare needs at least one cut");
+            }
+            Ok(cuts)
+        };
+
+        match &s.split("|").collect::<Vec<_>>()[..] {
+            [cuts_a, cuts_b] => {
+                Ok(CompareQuery {
+                    cuts_a: parse_cuts(cuts_a)?,
+                    cuts_b: parse_cuts(cuts_b)?,
+                })
+            },
+            _ => bail!("Could not parse a compare query, expected two sets of cuts separated by |"),
+        }
+    }
+}
+
 /// For filtering on a measure after Top is calculated (wrapper around end aggregation)
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub struct FilterQuery {
     pub by_mea_or_calc: MeaOrCalc,
     pub constraint: Constraint,
@@ -454,7 +1073,7 @@ impl FromStr for FilterQuery {
 }
 
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub struct RateQuery {
     pub level_name: LevelName,
     pub values: Vec<String>,
@@ -490,3 +1109,120 @@ impl FromStr for RateQuery {
         })
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn normalized_query() {
+        let a = Query::builder()
+            .drilldown("Geography.State")
+            .cut("Year.Year.Year.2019,2018")
+            .cut("Geography.State.State.06")
+            .measure("Quantity")
+            .build()
+            .unwrap();
+        let b = Query::builder()
+            .drilldown("Geography.State")
+            .cut("Geography.State.State.06")
+            .cut("Year.Year.Year.2018,2019")
+            .measure("Quantity")
+            .build()
+            .unwrap();
+
+        assert_eq!(
+            format!("{:?}", a.normalized()),
+            format!("{:?}", b.normalized()),
+        );
+        assert_eq!(a.normalized().cuts[1].members, vec!["2018", "2019"]);
+    }
+
+    #[test]
+    fn build_query() {
+        let query = Query::builder()
+            .drilldown("Geography.State")
+            .cut("Year.Year.Year.2018")
+            .measure("Quantity")
+            .growth("Geography.State,Quantity")
+            .limit(10, None)
+            .build()
+            .unwrap();
+
+        assert_eq!(query.drilldowns, vec!["Geography.State".parse::<Drilldown>().unwrap()]);
+        assert_eq!(query.measures, vec![Measure::new("Quantity")]);
+        assert_eq!(query.cuts.len(), 1);
+        assert!(query.growth.is_some());
+        assert_eq!(query.limit.map(|l| l.n), Some(10));
+    }
+
+    #[test]
+    fn serialize_query() {
+        let query = Query::builder()
+            .drilldown("Geography.State")
+            .measure("Quantity")
+            .top("10,Geography.State,Quantity,desc")
+            .limit(10, None)
+            .build()
+            .unwrap();
+
+        let json = serde_json::to_value(&query).unwrap();
+        assert_eq!(json["measures"], serde_json::json!(["Quantity"]));
+        assert_eq!(json["limit"]["n"], 10);
+        assert_eq!(json["top"]["sort_mea_or_calc"], serde_json::json!([{ "mea": "Quantity" }]));
+        assert_eq!(json["top"]["sort_direction"], "desc");
+    }
+
+    #[test]
+    fn build_query_parse_errors() {
+        let res = Query::builder()
+            .drilldown("Geography.State")
+            .measure("Quantity")
+            .sort("Quantity")
+            .top("ten")
+            .build();
+
+        let err = res.unwrap_err().to_string();
+        assert!(err.contains("sort 'Quantity'"));
+        assert!(err.contains("top 'ten'"));
+    }
+
+    #[test]
+    fn build_query_validation_errors() {
+        // no measure
+        assert!(Query::builder().drilldown("Geography.State").build().is_err());
+
+        // no drilldown or cut
+        assert!(Query::builder().measure("Quantity").build().is_err());
+
+        // growth measure not in measures
+        let res = Query::builder()
+            .drilldown("Year.Year")
+            .measure("Quantity")
+            .growth("Year.Year,Price")
+            .build();
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn deduplicated_query() {
+        let query = Query::builder()
+            .drilldown("Geography.State")
+            .drilldown("Year.Year")
+            .drilldown("Geography.State")
+            .cut("Year.Year.Year.2019,2018,2019")
+            .measure("Quantity")
+            .measure("Price")
+            .measure("Quantity")
+            .build()
+            .unwrap()
+            .deduplicated();
+
+        assert_eq!(query.drilldowns, vec![
+            "Geography.State".parse().unwrap(),
+            "Year.Year".parse().unwrap(),
+        ]);
+        assert_eq!(query.measures, vec![Measure::new("Quantity"), Measure::new("Price")]);
+        assert_eq!(query.cuts[0].members, vec!["2019", "2018"]);
+    }
+}
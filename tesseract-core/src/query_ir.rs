@@ -23,6 +23,7 @@ pub struct QueryIr {
     pub rca: Option<RcaSql>,
     pub growth: Option<GrowthSql>,
     pub rate: Option<RateSql>,
+    pub ratio_to_parent: Option<RatioToParentSql>,
     pub sparse: bool,
 }
 
@@ -59,16 +60,16 @@ impl DrilldownSql {
                 if let Some(ref name_col) = l.name_column {
                     format!("{} as {}_{}, {} as {}_{}",
                         l.key_column,
-                        l.key_column,
+                        column_alias(&l.key_column),
                         self.alias_postfix,
                         name_col,
-                        name_col,
+                        column_alias(name_col),
                         self.alias_postfix,
                     )
                 } else {
                     format!("{} as {}_{}",
                         l.key_column,
-                        l.key_column,
+                        column_alias(&l.key_column),
                         self.alias_postfix,
                     )
                 }
@@ -97,13 +98,13 @@ impl DrilldownSql {
         // version to map onto single cols.
         for l in self.level_columns.iter() {
             cols.push(format!("{}_{}",
-                l.key_column,
+                column_alias(&l.key_column),
                 self.alias_postfix,
             ));
 
             if let Some(ref name_col) = l.name_column {
                 cols.push(format!("{}_{}",
-                    name_col,
+                    column_alias(name_col),
                     self.alias_postfix,
                 ));
             }
@@ -119,24 +120,30 @@ impl DrilldownSql {
     }
 
     pub fn col_qual_string(&self) -> String {
-        let cols = self.col_qual_vec();
+        self.col_qual_string_escaped(Escaping::Backslash)
+    }
+
+    pub fn col_qual_string_escaped(&self, escaping: Escaping) -> String {
+        let cols = self.col_qual_vec(escaping);
         join(cols, ", ")
     }
 
-    fn col_qual_vec(&self) -> Vec<String> {
+    fn col_qual_vec(&self, escaping: Escaping) -> Vec<String> {
+        let table = quote_identifier(&self.table.name, escaping);
+
         let mut cols: Vec<_> = self.level_columns.iter()
             .map(|l| {
                 if let Some(ref name_col) = l.name_column {
-                    format!("{}.{}, {}.{}", self.table.name, l.key_column, self.table.name, name_col)
+                    format!("{}.{}, {}.{}", table, l.key_column, table, name_col)
                 } else {
-                    format!("{}.{}", self.table.name, l.key_column)
+                    format!("{}.{}", table, l.key_column)
                 }
             }).collect();
 
         if self.property_columns.len() != 0 {
             let prop_cols_qual = self.property_columns.iter()
                 .map(|p| {
-                    format!("{}.{}", self.table.name, p)
+                    format!("{}.{}", table, p)
                 });
 
             cols.push(
@@ -148,6 +155,16 @@ impl DrilldownSql {
     }
 }
 
+/// The part of a drilldown column's alias that comes from the column. Key
+/// and name columns can be sql expressions (e.g. for `generated_time`
+/// levels, or a level's `caption_expression`), so
+/// anything that can't go in an identifier is replaced with `_`.
+pub fn column_alias(column: &str) -> String {
+    column.chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '_' { c } else { '_' })
+        .collect()
+}
+
 #[derive(Debug, Clone)]
 pub struct HiddenDrilldownSql {
     pub drilldown_sql: DrilldownSql,
@@ -174,15 +191,25 @@ pub struct CutSql {
     // if for_match, then use LIKE syntax
     pub for_match: bool,
     pub inline_table: Option<InlineTable>,
+    // fact table column for the level, to cut on the fact table directly
+    pub fact_column: Option<String>,
 }
 
 impl CutSql {
     pub fn members_string(&self) -> String {
+        self.members_string_escaped(Escaping::Backslash)
+    }
+
+    pub fn members_string_escaped(&self, escaping: Escaping) -> String {
         let members = match self.member_type {
-            MemberType::NonText => join(&self.members, ", "),
+            MemberType::NonText => {
+                let literals = self.members.iter()
+                    .map(|m| member_literal(m, escaping));
+                join(literals, ", ")
+            },
             MemberType::Text => {
                 let quoted = self.members.iter()
-                .map(|m| format!("'{}'", m));
+                    .map(|m| quote_string(m, escaping));
                 join(quoted, ", ")
             }
         };
@@ -191,12 +218,24 @@ impl CutSql {
     }
 
     pub fn members_like_string(&self) -> String {
+        self.members_like_string_escaped(Escaping::Backslash)
+    }
+
+    pub fn members_like_string_escaped(&self, escaping: Escaping) -> String {
         match self.member_type {
             MemberType::NonText => {
                 // this behavior doesn't really make sense; it should be for
                 // labels only, which are almost always strings.
                 let unquoted = self.members.iter()
-                    .map(|m| format!("{} {} {}", self.column, self.mask_sql_like_string(), m));
+                    .map(|m| {
+                        let pattern = if is_number(m) {
+                            m.to_owned()
+                        } else {
+                            like_literal(&escape_like(m), escaping)
+                        };
+
+                        format!("{} {} {}", self.column, self.mask_sql_like_string(), pattern)
+                    });
 
                 match self.mask {
                     Mask::Include => format!("({})", join(unquoted, " or ")),
@@ -205,7 +244,13 @@ impl CutSql {
             },
             MemberType::Text => {
                 let quoted = self.members.iter()
-                    .map(|m| format!("{} {} '%{}%'", self.column, self.mask_sql_like_string(), m));
+                    .map(|m| {
+                        format!("{} {} {}",
+                            self.column,
+                            self.mask_sql_like_string(),
+                            like_literal(&format!("%{}%", escape_like(m)), escaping),
+                        )
+                    });
 
                 match self.mask {
                     Mask::Include => format!("({})", join(quoted, " or ")),
@@ -216,7 +261,11 @@ impl CutSql {
     }
 
     pub fn col_qual_string(&self) -> String {
-        format!("{}.{}", self.table.name, self.column)
+        self.col_qual_string_escaped(Escaping::Backslash)
+    }
+
+    pub fn col_qual_string_escaped(&self, escaping: Escaping) -> String {
+        format!("{}.{}", quote_identifier(&self.table.name, escaping), self.column)
     }
 
     pub fn mask_sql_in_string(&self) -> String {
@@ -234,6 +283,83 @@ impl CutSql {
     }
 }
 
+/// How string literals and identifiers are escaped in a sql dialect.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Escaping {
+    /// Backslash is an escape character in string literals, and
+    /// identifiers are quoted with backticks (ClickHouse, MySQL).
+    Backslash,
+    /// Only quotes are escaped in string literals, and identifiers are
+    /// quoted with double quotes (Postgres, ANSI sql).
+    Standard,
+}
+
+/// Quotes a string literal, escaping quotes (and backslashes, if the
+/// dialect treats them as escapes).
+pub fn quote_string(s: &str, escaping: Escaping) -> String {
+    let escaped = match escaping {
+        Escaping::Backslash => s.replace('\\', "\\\\").replace('\'', "''"),
+        Escaping::Standard => s.replace('\'', "''"),
+    };
+
+    format!("'{}'", escaped)
+}
+
+/// Escapes the `like` wildcards `%` and `_` (and the `\` escape
+/// character itself), so that they only match themselves.
+pub fn escape_like(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_")
+}
+
+/// Quotes a `like` pattern escaped with `escape_like`. Standard sql has no
+/// default escape character for `like`, so it's given explicitly.
+pub fn like_literal(pattern: &str, escaping: Escaping) -> String {
+    let quoted = quote_string(pattern, escaping);
+
+    match escaping {
+        Escaping::Backslash => quoted,
+        Escaping::Standard => format!("{} escape '\\'", quoted),
+    }
+}
+
+fn is_number(member: &str) -> bool {
+    !member.is_empty() &&
+        member.chars().all(|c| c.is_ascii_digit() || c == '.' || c == '-' || c == '+' || c == 'e' || c == 'E') &&
+        member.parse::<f64>().is_ok()
+}
+
+/// Numeric members are written as is; anything else is quoted as a string,
+/// so that members can't inject sql.
+pub fn member_literal(member: &str, escaping: Escaping) -> String {
+    if is_number(member) {
+        member.to_owned()
+    } else {
+        quote_string(member, escaping)
+    }
+}
+
+/// Quotes an identifier (e.g. a table name), unless it's a plain
+/// identifier that doesn't need quoting. A dotted name (e.g. `db.table`)
+/// is quoted part by part. Column names aren't quoted, since schemas may
+/// use sql expressions for them.
+pub fn quote_identifier(ident: &str, escaping: Escaping) -> String {
+    join(ident.split('.').map(|part| quote_identifier_part(part, escaping)), ".")
+}
+
+fn quote_identifier_part(ident: &str, escaping: Escaping) -> String {
+    let is_plain = ident.chars().next().map(|c| c.is_ascii_alphabetic() || c == '_').unwrap_or(false) &&
+        ident.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+
+    if is_plain {
+        return ident.to_owned();
+    }
+
+    match escaping {
+        Escaping::Backslash => format!("`{}`", ident.replace('\\', "\\\\").replace('`', "``")),
+        Escaping::Standard => format!("\"{}\"", ident.replace('"', "\"\"")),
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
 pub enum MemberType {
     #[serde(rename="text")]
@@ -246,6 +372,9 @@ pub enum MemberType {
 pub struct MeasureSql {
     pub aggregator: Aggregator,
     pub column: String,
+    /// Condition restricting the fact rows aggregated, from the measure's
+    /// `filter` in the schema.
+    pub filter: Option<String>,
 }
 
 // NOTE: This is now specific to each db, because of the custom aggregators
@@ -262,6 +391,18 @@ pub struct TopSql {
     pub by_column: String,
     pub sort_columns: Vec<String>,
     pub sort_direction: SortDirection,
+    pub other: Option<TopOtherSql>,
+}
+
+/// The "Other" row of each top partition, rolling up the rows outside of
+/// the top n. Measures are summed.
+#[derive(Debug, Clone)]
+pub struct TopOtherSql {
+    /// Drill columns of the partition's drilldown, which the row keeps.
+    pub group_columns: Vec<String>,
+    /// Select expressions for all the drill columns, in the result's order:
+    /// the group columns, and null or 'Other' for the rest.
+    pub drill_columns: Vec<String>,
 }
 
 #[derive(Debug, Clone)]
@@ -314,6 +455,15 @@ pub struct GrowthSql {
     pub mea: String,
 }
 
+/// `mea` divided by its sum over the rows with the same
+/// `partition_columns`, which are all the drilldown columns except for
+/// those of the drilled level.
+#[derive(Debug, Clone)]
+pub struct RatioToParentSql {
+    pub partition_columns: Vec<String>,
+    pub mea: String,
+}
+
 #[derive(Debug, Clone)]
 pub struct RateSql {
     pub drilldown_sql: DrilldownSql,
@@ -332,7 +482,7 @@ pub struct DimSubquery {
 /// Collects a drilldown and cut together to create a subquery for the dimension table
 /// Does not check for matching name, because that had to have been done
 /// before submitting to this fn.
-pub fn dim_subquery(drill: Option<&DrilldownSql>, cut: Option<&CutSql>) -> DimSubquery {
+pub fn dim_subquery(drill: Option<&DrilldownSql>, cut: Option<&CutSql>, escaping: Escaping) -> DimSubquery {
     match drill {
         Some(drill) => {
             let drill_table = match &drill.inline_table {
@@ -340,7 +490,7 @@ pub fn dim_subquery(drill: Option<&DrilldownSql>, cut: Option<&CutSql>) -> DimSu
                     let inline_table_sql = it.sql_string();
                     format!("({}) as {}", inline_table_sql, it.alias)
                 },
-                None => drill.table.full_name()
+                None => drill.table.full_name_escaped(escaping)
             };
 
             // TODO
@@ -377,9 +527,9 @@ pub fn dim_subquery(drill: Option<&DrilldownSql>, cut: Option<&CutSql>) -> DimSu
                 let sql = format!("select {} as {} from {} where {} in ({})",
                     cut.primary_key.clone(),
                     cut.foreign_key.clone(),
-                    cut.table.full_name(),
+                    cut.table.full_name_escaped(escaping),
                     cut.column.clone(),
-                    cut.members_string(),
+                    cut.members_string_escaped(escaping),
                 );
 
                 return DimSubquery {
@@ -1,14 +1,21 @@
 use serde_derive::{Serialize, Deserialize};
+use std::collections::HashMap;
 use std::convert::From;
 use failure::{Error, format_err};
 
 pub mod aggregator;
+pub mod dictionary;
+pub mod lint;
 pub mod metadata;
 mod json;
 mod xml;
 
 const DEFAULT_LOCALE_STR: &str = "en";
 
+/// Name of the measure counting the fact rows of a cube, which every cube
+/// has without declaring it in the schema (unless it declares its own).
+pub const FACT_COUNT_MEASURE: &str = "Fact Count";
+
 
 pub use crate::schema::{
     json::SchemaConfigJson,
@@ -19,6 +26,14 @@ pub use crate::schema::{
     json::TableConfigJson,
     json::PropertyConfigJson,
     json::AnnotationConfigJson,
+    json::DrillthroughColumnConfigJson,
+    json::MaskingRuleConfigJson,
+    json::CalculationConfigJson,
+    json::SecurityConfigJson,
+    json::RoleConfigJson,
+    json::RowFilterConfigJson,
+    json::BandConfigJson,
+    json::KeyCoercionConfigJson,
     json::InlineTableJson,
     json::InlineTableColumnDefinitionJson,
     json::InlineTableRowJson,
@@ -31,17 +46,34 @@ pub use crate::schema::{
     xml::TableConfigXML,
     xml::PropertyConfigXML,
 };
-use crate::names::{LevelName, Measure as MeasureName, Property as TsProperty};
-use crate::query_ir::MemberType;
+use crate::names::{LevelName, Measure as MeasureName, NameResolution, Property as TsProperty};
+use crate::query::SortDirection;
+use crate::query_ir::{Escaping, MemberType, quote_identifier, quote_string};
 pub use self::aggregator::Aggregator;
+pub use self::dictionary::{dictionary, dictionary_headers};
+pub use self::lint::{lint, Diagnostic, DiagnosticKind};
 
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Schema {
     pub name: String,
     pub cubes: Vec<Cube>,
+    /// Named calculations that cubes can use like measures.
+    #[serde(default)]
+    pub calculations: Vec<NamedCalculation>,
     pub annotations: Option<Vec<Annotation>>,
     pub default_locale: String,
+    /// How cube, level, property and measure names in queries are matched.
+    #[serde(default)]
+    pub name_resolution: NameResolution,
+    /// Shared dimensions that no cube uses, which are otherwise dropped
+    /// (cubes copy the shared dimensions they use), for `lint`.
+    #[serde(default)]
+    pub unused_shared_dimensions: Vec<String>,
+    /// Roles and the rows each can query. `None` if queries aren't
+    /// restricted.
+    #[serde(default)]
+    pub security: Option<Security>,
 }
 
 impl From<SchemaConfigJson> for Schema {
@@ -52,6 +84,7 @@ impl From<SchemaConfigJson> for Schema {
         // - duplicate dim names
 
         let mut cubes = vec![];
+        let mut used_shared_dimensions = vec![];
 
         for cube_config in schema_config.cubes {
             let mut dimensions: Vec<_> = cube_config.dimensions
@@ -59,9 +92,12 @@ impl From<SchemaConfigJson> for Schema {
                 .into_iter()
                 .map(|dim| dim.into())
                 .collect();
-            let measures = cube_config.measures.into_iter()
+            let mut measures: Vec<Measure> = cube_config.measures.into_iter()
                 .map(|mea| mea.into())
                 .collect();
+            if !measures.iter().any(|mea| mea.name == FACT_COUNT_MEASURE) {
+                measures.push(Measure::fact_count());
+            }
             let cube_annotations = cube_config.annotations
                 .map(|anns| {
                     anns.into_iter()
@@ -77,6 +113,8 @@ impl From<SchemaConfigJson> for Schema {
             // in validate method
             if let Some(dim_usages) = cube_config.dimension_usages {
                 for dim_usage in dim_usages {
+                    used_shared_dimensions.push(dim_usage.source.clone());
+
                     // prep annotations to be merged with shared dim annotations
                     let dim_usage_annotations: Option<Vec<Annotation>> = dim_usage.annotations
                         .map(|anns| {
@@ -131,6 +169,17 @@ impl From<SchemaConfigJson> for Schema {
                 can_aggregate: false,
                 dimensions,
                 measures,
+                default_time: cube_config.default_time,
+                auto_top: cube_config.auto_top,
+                drillthrough: cube_config.drillthrough
+                    .map(|cols| cols.into_iter().map(|col| col.into()).collect())
+                    .unwrap_or_default(),
+                calculations: cube_config.calculations
+                    .map(|calcs| calcs.into_iter().map(|calc| calc.name).collect())
+                    .unwrap_or_default(),
+                masking: cube_config.masking
+                    .map(|rules| rules.into_iter().map(|rule| rule.into()).collect())
+                    .unwrap_or_default(),
                 annotations: cube_annotations,
             });
         }
@@ -145,8 +194,18 @@ impl From<SchemaConfigJson> for Schema {
         Schema {
             name: schema_config.name,
             cubes,
+            calculations: schema_config.calculations
+                .map(|calcs| calcs.into_iter().map(|calc| calc.into()).collect())
+                .unwrap_or_default(),
             annotations: schema_annotations,
             default_locale: schema_config.default_locale.unwrap_or_else(|| DEFAULT_LOCALE_STR.to_owned()),
+            name_resolution: schema_config.name_resolution.unwrap_or_default(),
+            unused_shared_dimensions: schema_config.shared_dimensions.iter()
+                .flatten()
+                .map(|shared_dim| shared_dim.name.clone())
+                .filter(|name| !used_shared_dimensions.contains(name))
+                .collect(),
+            security: schema_config.security.map(|security| security.into()),
         }
     }
 }
@@ -160,6 +219,23 @@ pub struct Cube {
     pub can_aggregate: bool,
     pub dimensions: Vec<Dimension>,
     pub measures: Vec<Measure>,
+    /// Time constraint for queries that don't mention the time dimension.
+    pub default_time: Option<DefaultTime>,
+    /// Number of rows kept per member of the smallest drilldown, with an
+    /// "Other" row for the rest, when a query could return too many rows,
+    /// instead of rejecting it.
+    pub auto_top: Option<u64>,
+    /// Fact table columns returned by the drillthrough endpoint. Empty if
+    /// the cube's fact rows can't be drilled through to.
+    #[serde(default)]
+    pub drillthrough: Vec<DrillthroughColumn>,
+    /// Names of the schema's `calculations` available in the cube.
+    #[serde(default)]
+    pub calculations: Vec<String>,
+    /// Measures whose values are suppressed where too few facts are behind
+    /// them. See `Schema::masking_plan`.
+    #[serde(default)]
+    pub masking: Vec<MaskingRule>,
     pub annotations: Option<Vec<Annotation>>,
 }
 
@@ -188,6 +264,26 @@ impl Cube {
         dimension_names
     }
 
+    /// Returns a map from measure name to the precision results for
+    /// that measure should be rounded to, for measures that set one.
+    pub fn get_measure_precisions(&self) -> HashMap<String, u32> {
+        self.measures.iter()
+            .filter_map(|measure| {
+                measure.precision.map(|p| (measure.name.clone(), p))
+            })
+            .collect()
+    }
+
+    /// Returns a map from measure name to the unit (or currency) of the
+    /// measure, for measures that declare one.
+    pub fn get_measure_units(&self) -> HashMap<String, String> {
+        self.measures.iter()
+            .filter_map(|measure| {
+                measure.unit().map(|u| (measure.name.clone(), u.to_owned()))
+            })
+            .collect()
+    }
+
     /// Returns a Vec<String> of all the measure names for a given Cube.
     pub fn get_all_measure_names(&self) -> Vec<MeasureName> {
         let mut measure_names: Vec<MeasureName> = vec![];
@@ -294,6 +390,43 @@ impl Cube {
         None
     }
 
+    /// Returns the schema's name for a level name from a query, matched
+    /// according to `resolution`. An exact match is always preferred.
+    pub fn resolve_level_name(&self, level_name: &LevelName, resolution: NameResolution) -> Option<LevelName> {
+        let level_names = self.get_all_level_names();
+
+        if level_names.contains(level_name) {
+            return Some(level_name.clone());
+        }
+
+        level_names.into_iter()
+            .find(|l| {
+                resolution.matches(&l.dimension, &level_name.dimension) &&
+                resolution.matches(&l.hierarchy, &level_name.hierarchy) &&
+                resolution.matches(&l.level, &level_name.level)
+            })
+    }
+
+    /// Returns the schema's name for a measure name from a query, matched
+    /// according to `resolution`. An exact match is always preferred.
+    pub fn resolve_measure_name(&self, measure: &str, resolution: NameResolution) -> Option<String> {
+        self.measures.iter()
+            .find(|m| m.name == measure)
+            .or_else(|| self.measures.iter().find(|m| resolution.matches(&m.name, measure)))
+            .map(|m| m.name.clone())
+    }
+
+    /// Returns the schema's name for a property of a level, matched
+    /// according to `resolution`. `level_name` must already be resolved.
+    pub fn resolve_property_name(&self, level_name: &LevelName, property: &str, resolution: NameResolution) -> Option<String> {
+        let properties = self.get_level(level_name)?.properties?;
+
+        properties.iter()
+            .find(|p| p.name == property)
+            .or_else(|| properties.iter().find(|p| resolution.matches(&p.name, property)))
+            .map(|p| p.name.clone())
+    }
+
     pub fn get_child_level(&self, level_name: &LevelName) -> Result<Option<Level>, Error> {
         let hierarchy = self.get_hierarchy(level_name)
             .ok_or_else(|| format_err!("Could not find parent hierarchy for level: {}", level_name.level))?;
@@ -341,7 +474,7 @@ pub struct Dimension {
 
 impl From<DimensionConfigJson> for Dimension {
     fn from(dimension_config: DimensionConfigJson) -> Self {
-        let hierarchies = dimension_config.hierarchies.into_iter()
+        let mut hierarchies: Vec<Hierarchy> = dimension_config.hierarchies.into_iter()
             .map(|h| h.into())
             .collect();
         let annotations = dimension_config.annotations
@@ -353,10 +486,37 @@ impl From<DimensionConfigJson> for Dimension {
 
         let dim_type = dimension_config.dim_type.unwrap_or(DimensionType::default());
 
+        let mut default_hierarchy = dimension_config.default_hierarchy;
+
+        // the foreign key is the fact table's date column
+        if dim_type == DimensionType::GeneratedTime && hierarchies.is_empty() {
+            if let Some(ref date_column) = dimension_config.foreign_key {
+                hierarchies.push(Hierarchy::generated_time(
+                    &dimension_config.name,
+                    date_column,
+                    &TimeGrain::generated_levels(),
+                ));
+                for grain in TimeGrain::derived_levels() {
+                    hierarchies.push(Hierarchy::generated_time(grain.level_name(), date_column, &[grain]));
+                }
+                default_hierarchy = default_hierarchy.or(Some(dimension_config.name.clone()));
+            }
+        }
+
+        // the foreign key is the fact table's column to band
+        if dim_type == DimensionType::Banded && hierarchies.is_empty() && !dimension_config.bands.is_empty() {
+            if let Some(ref column) = dimension_config.foreign_key {
+                let bands: Vec<Band> = dimension_config.bands.into_iter()
+                    .map(|b| b.into())
+                    .collect();
+                hierarchies.push(Hierarchy::banded(&dimension_config.name, column, &bands));
+            }
+        }
+
         Dimension {
             name: dimension_config.name,
             foreign_key: dimension_config.foreign_key,
-            default_hierarchy: dimension_config.default_hierarchy,
+            default_hierarchy,
             hierarchies,
             dim_type,
             annotations,
@@ -365,6 +525,17 @@ impl From<DimensionConfigJson> for Dimension {
     }
 }
 
+impl Dimension {
+    /// Whether a hierarchy is the one used by default. With a single
+    /// hierarchy, there's no default set (see `Schema::validate`), and it's
+    /// always the default.
+    pub fn is_default_hierarchy(&self, hierarchy: &str) -> bool {
+        self.default_hierarchy.as_ref()
+            .map(|default| default == hierarchy)
+            .unwrap_or(true)
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum DimensionType {
     #[serde(rename="standard")]
@@ -373,12 +544,103 @@ pub enum DimensionType {
     Time,
     #[serde(rename="geo")]
     Geo,
+    /// Time dimension without a dim table; its levels are derived from
+    /// a date column of the fact table (the dimension's foreign key).
+    #[serde(rename="generated_time")]
+    GeneratedTime,
+    /// Dimension without a dim table, whose single level buckets a numeric
+    /// column of the fact table (the dimension's foreign key) into bands.
+    #[serde(rename="banded")]
+    Banded,
 }
 
 impl std::default::Default for DimensionType {
     fn default() -> Self { DimensionType::Standard }
 }
 
+/// Time constraint applied (by the logic layer) to queries on a cube that
+/// don't drill down or cut on its time dimension.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum DefaultTime {
+    /// Cut to the latest period of the cube's time dimension.
+    #[serde(rename="latest")]
+    Latest,
+}
+
+
+/// A level of a `generated_time` dimension.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum TimeGrain {
+    #[serde(rename="year")]
+    Year,
+    #[serde(rename="quarter")]
+    Quarter,
+    #[serde(rename="month")]
+    Month,
+    #[serde(rename="day")]
+    Day,
+    #[serde(rename="iso_week")]
+    IsoWeek,
+    #[serde(rename="day_of_week")]
+    DayOfWeek,
+    #[serde(rename="month_name")]
+    MonthName,
+}
+
+impl TimeGrain {
+    /// The levels of a `generated_time` dimension's main hierarchy,
+    /// from the top down.
+    pub fn generated_levels() -> Vec<TimeGrain> {
+        vec![TimeGrain::Year, TimeGrain::Quarter, TimeGrain::Month, TimeGrain::Day]
+    }
+
+    /// Levels that don't fit in the main hierarchy; each one gets
+    /// a hierarchy of its own.
+    pub fn derived_levels() -> Vec<TimeGrain> {
+        vec![TimeGrain::IsoWeek, TimeGrain::DayOfWeek, TimeGrain::MonthName]
+    }
+
+    pub fn level_name(&self) -> &'static str {
+        match self {
+            TimeGrain::Year => "Year",
+            TimeGrain::Quarter => "Quarter",
+            TimeGrain::Month => "Month",
+            TimeGrain::Day => "Day",
+            TimeGrain::IsoWeek => "ISO Week",
+            TimeGrain::DayOfWeek => "Day of Week",
+            TimeGrain::MonthName => "Month Name",
+        }
+    }
+
+    pub fn member_type(&self) -> MemberType {
+        match self {
+            TimeGrain::MonthName => MemberType::Text,
+            _ => MemberType::NonText,
+        }
+    }
+
+    /// Standard sql for the level's key, from a date column. Where there's
+    /// no standard function, Postgres' is used.
+    ///
+    /// Keys are numbers in the usual time id format: 2019, 20191 (quarter),
+    /// 201901 and 20190115. ISO weeks are 201901 (ISO year and week), days
+    /// of the week go from 1 (Monday) to 7, and month names are in English.
+    ///
+    /// Backends can use their own date functions, see
+    /// `Backend::time_grain_sql`.
+    pub fn standard_sql(&self, column: &str) -> String {
+        match self {
+            TimeGrain::Year => format!("extract(year from {})", column),
+            TimeGrain::Quarter => format!("(extract(year from {col}) * 10 + extract(quarter from {col}))", col=column),
+            TimeGrain::Month => format!("(extract(year from {col}) * 100 + extract(month from {col}))", col=column),
+            TimeGrain::Day => format!("(extract(year from {col}) * 10000 + extract(month from {col}) * 100 + extract(day from {col}))", col=column),
+            TimeGrain::IsoWeek => format!("(extract(isoyear from {col}) * 100 + extract(week from {col}))", col=column),
+            TimeGrain::DayOfWeek => format!("extract(isodow from {})", column),
+            TimeGrain::MonthName => format!("trim(to_char({}, 'Month'))", column),
+        }
+    }
+}
+
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Hierarchy {
@@ -425,6 +687,122 @@ impl From<HierarchyConfigJson> for Hierarchy {
     }
 }
 
+impl Hierarchy {
+    /// A hierarchy of a `generated_time` dimension, read from the fact
+    /// table, with a level for each of `grains`.
+    pub fn generated_time(name: &str, date_column: &str, grains: &[TimeGrain]) -> Self {
+        let levels = grains.iter()
+            .map(|grain| {
+                Level {
+                    name: grain.level_name().to_owned(),
+                    key_column: grain.standard_sql(date_column),
+                    name_column: None,
+                    properties: None,
+                    key_type: Some(grain.member_type()),
+                    fact_column: None,
+                    order_by: None,
+                    order_direction: None,
+                    key_coercion: None,
+                    time_grain: Some(*grain),
+                    min_cardinality: None,
+                    max_cardinality: None,
+                    annotations: None,
+                }
+            })
+            .collect();
+
+        Hierarchy {
+            name: name.to_owned(),
+            table: None,
+            primary_key: date_column.to_owned(),
+            levels,
+            annotations: None,
+            inline_table: None,
+            default_member: None,
+        }
+    }
+
+    /// The hierarchy of a `banded` dimension, read from the fact table,
+    /// with a single level named after the dimension. Members are the
+    /// band labels.
+    pub fn banded(name: &str, column: &str, bands: &[Band]) -> Self {
+        let level = Level {
+            name: name.to_owned(),
+            key_column: Band::case_sql(bands, column),
+            name_column: None,
+            properties: None,
+            key_type: Some(MemberType::Text),
+            fact_column: None,
+            order_by: None,
+            order_direction: None,
+            key_coercion: None,
+            time_grain: None,
+            // the bands are the members
+            min_cardinality: Some(bands.len() as u64),
+            max_cardinality: Some(bands.len() as u64),
+            annotations: None,
+        };
+
+        Hierarchy {
+            name: name.to_owned(),
+            table: None,
+            primary_key: column.to_owned(),
+            levels: vec![level],
+            annotations: None,
+            inline_table: None,
+            default_member: None,
+        }
+    }
+}
+
+
+/// A range of values of a `banded` dimension's column. `min` is inclusive
+/// and `max` exclusive; either can be left open.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Band {
+    pub label: String,
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+}
+
+impl Band {
+    /// A `case` expression giving the label of the first band each value of
+    /// `column` falls in, or null if it's in none of them.
+    pub fn case_sql(bands: &[Band], column: &str) -> String {
+        let whens: Vec<_> = bands.iter()
+            .map(|band| {
+                let mut conditions = vec![];
+                if let Some(min) = band.min {
+                    conditions.push(format!("{} >= {}", column, min));
+                }
+                if let Some(max) = band.max {
+                    conditions.push(format!("{} < {}", column, max));
+                }
+                if conditions.is_empty() {
+                    conditions.push(format!("{} is not null", column));
+                }
+
+                format!("when {} then {}",
+                    conditions.join(" and "),
+                    quote_string(&band.label, Escaping::Standard),
+                )
+            })
+            .collect();
+
+        format!("case {} end", whens.join(" "))
+    }
+}
+
+impl From<BandConfigJson> for Band {
+    fn from(band_config: BandConfigJson) -> Self {
+        Band {
+            label: band_config.label,
+            min: band_config.min,
+            max: band_config.max,
+        }
+    }
+}
+
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct InlineTable {
@@ -544,30 +922,73 @@ impl From<InlineTableRowValueJson> for InlineTableRowValue {
 pub struct Level {
     pub name: String,
     pub key_column: String,
+    /// Name column, or the level's `caption_expression`: a sql expression
+    /// over the dim table's columns.
     pub name_column: Option<String>,
     pub properties: Option<Vec<Property>>,
     pub key_type: Option<MemberType>,
+    /// Column (or expression) on the fact table holding this level's key,
+    /// e.g. `toYear(sale_date)` for a Year level. Cuts on the level are
+    /// then also applied directly on the fact table, which lets the
+    /// database prune time partitions.
+    pub fact_column: Option<String>,
+    /// Column the level's members are sorted by, in members queries and
+    /// by default in aggregate results. Must be the key or name column.
+    pub order_by: Option<String>,
+    /// Defaults to `asc`.
+    pub order_direction: Option<SortDirection>,
+    /// Normalizes member ids given in logic layer cuts, e.g. restoring the
+    /// leading zeros of FIPS codes sent as numbers.
+    pub key_coercion: Option<KeyCoercion>,
+    /// Set for the levels of a `generated_time` dimension, whose key column
+    /// is generated from the fact table's date column.
+    pub time_grain: Option<TimeGrain>,
+    /// Declared estimates of the level's number of members, for clients
+    /// to warn about large queries before the cache knows the level.
+    pub min_cardinality: Option<u64>,
+    pub max_cardinality: Option<u64>,
     pub annotations: Option<Vec<Annotation>>,
 }
 
 impl Level {
+    /// Sql to sort the level's members by: the `order_by` column and
+    /// direction if it's one of the selected `columns`, or else the key
+    /// column.
+    pub fn order_sql(&self, columns: &[String]) -> String {
+        match self.order_by {
+            Some(ref col) if columns.contains(col) => {
+                let direction = self.order_direction.clone().unwrap_or(SortDirection::Asc);
+                format!("{} {}", col, direction.sql_string())
+            },
+            _ => self.key_column.clone(),
+        }
+    }
+
+    /// Applies the level's `key_coercion`, if any, to a member id.
+    pub fn coerce_key(&self, key: &str) -> String {
+        match self.key_coercion {
+            Some(ref coercion) => coercion.apply(key),
+            None => key.to_owned(),
+        }
+    }
+
+    /// Caption properties of the level for `locales`, in the order of
+    /// `locales`, so that the first one replaces the level's name column.
     pub fn get_captions(&self, level_name: &LevelName, locales: &Vec<String>) -> Vec<TsProperty> {
         let mut captions: Vec<TsProperty> = vec![];
 
-        if let Some(props) = self.properties.clone() {
-            for prop in props {
-                if let Some(cap) = prop.caption_set {
-                    for locale in locales.clone() {
-                        if locale == cap {
-                            captions.push(
-                                TsProperty::new(
-                                    level_name.dimension.clone(),
-                                    level_name.hierarchy.clone(),
-                                    level_name.level.clone(),
-                                    prop.name.clone()
-                                )
+        if let Some(ref props) = self.properties {
+            for locale in locales {
+                for prop in props {
+                    if prop.caption_set.as_ref() == Some(locale) {
+                        captions.push(
+                            TsProperty::new(
+                                level_name.dimension.clone(),
+                                level_name.hierarchy.clone(),
+                                level_name.level.clone(),
+                                prop.name.clone()
                             )
-                        }
+                        )
                     }
                 }
             }
@@ -595,14 +1016,76 @@ impl From<LevelConfigJson> for Level {
         Level {
             name: level_config.name,
             key_column: level_config.key_column,
-            name_column: level_config.name_column,
+            // a caption expression is selected wherever the name column would be
+            name_column: level_config.caption_expression.or(level_config.name_column),
             properties,
             key_type: level_config.key_type,
+            fact_column: level_config.fact_column,
+            order_by: level_config.order_by,
+            order_direction: level_config.order_direction,
+            key_coercion: level_config.key_coercion.map(|k| k.into()),
+            time_grain: None,
+            min_cardinality: level_config.min_cardinality,
+            max_cardinality: level_config.max_cardinality,
             annotations,
         }
     }
 }
 
+/// Rules normalizing a member id before it's matched against a level's
+/// keys: trimming whitespace, then folding case, then left padding to
+/// `pad` characters with `pad_char` (`0` by default).
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct KeyCoercion {
+    pub trim: bool,
+    pub case: Option<KeyCase>,
+    pub pad: Option<usize>,
+    pub pad_char: char,
+}
+
+impl KeyCoercion {
+    pub fn apply(&self, key: &str) -> String {
+        let key = if self.trim { key.trim() } else { key };
+
+        let mut key = match self.case {
+            Some(KeyCase::Upper) => key.to_uppercase(),
+            Some(KeyCase::Lower) => key.to_lowercase(),
+            None => key.to_owned(),
+        };
+
+        if let Some(width) = self.pad {
+            let len = key.chars().count();
+            if len < width {
+                let padding: String = std::iter::repeat(self.pad_char)
+                    .take(width - len)
+                    .collect();
+                key.insert_str(0, &padding);
+            }
+        }
+
+        key
+    }
+}
+
+impl From<KeyCoercionConfigJson> for KeyCoercion {
+    fn from(coercion_config: KeyCoercionConfigJson) -> Self {
+        KeyCoercion {
+            trim: coercion_config.trim.unwrap_or(false),
+            case: coercion_config.case,
+            pad: coercion_config.pad,
+            pad_char: coercion_config.pad_char.unwrap_or('0'),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum KeyCase {
+    #[serde(rename="upper")]
+    Upper,
+    #[serde(rename="lower")]
+    Lower,
+}
+
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Measure{
@@ -610,9 +1093,54 @@ pub struct Measure{
     pub column: String,
     pub aggregator: Aggregator,
     pub measure_type: MeasureType,
+    /// Number of decimal places float results for this measure are rounded to.
+    pub precision: Option<u32>,
+    /// Sql condition on fact table columns; only the fact rows matching it
+    /// are aggregated into the measure.
+    pub filter: Option<String>,
+    /// Type of the measure's values, for clients (e.g. `UInt64`), since
+    /// it can't be told from the schema otherwise.
+    pub datatype: Option<String>,
     pub annotations: Option<Vec<Annotation>>,
 }
 
+impl Measure {
+    /// The implicit `Fact Count` measure, `count(*)` on the fact table.
+    pub fn fact_count() -> Self {
+        Measure {
+            name: FACT_COUNT_MEASURE.to_owned(),
+            column: "*".to_owned(),
+            aggregator: Aggregator::Count,
+            measure_type: MeasureType::default(),
+            precision: None,
+            filter: None,
+            datatype: None,
+            annotations: None,
+        }
+    }
+
+    /// The measure's unit, from its `currency` or `unit` annotation.
+    pub fn unit(&self) -> Option<&str> {
+        let annotations = self.annotations.as_ref()?;
+
+        annotations.iter()
+            .find(|ann| ann.name == "currency")
+            .or_else(|| annotations.iter().find(|ann| ann.name == "unit"))
+            .map(|ann| ann.text.as_str())
+    }
+
+    /// The measure's localized name for `locale`, from its
+    /// `caption_<locale>` annotation (e.g. `caption_es`).
+    pub fn caption(&self, locale: &str) -> Option<&str> {
+        let caption_name = format!("caption_{}", locale);
+
+        self.annotations.as_ref()?
+            .iter()
+            .find(|ann| ann.name == caption_name)
+            .map(|ann| ann.text.as_str())
+    }
+}
+
 impl From<MeasureConfigJson> for Measure {
     fn from(measure_config: MeasureConfigJson) -> Self {
         let annotations = measure_config.annotations
@@ -627,6 +1155,9 @@ impl From<MeasureConfigJson> for Measure {
             column: measure_config.column,
             aggregator: measure_config.aggregator,
             measure_type: measure_config.measure_type.unwrap_or_else(|| MeasureType::default()),
+            precision: measure_config.precision,
+            filter: measure_config.filter,
+            datatype: measure_config.datatype,
             annotations,
         }
     }
@@ -672,11 +1203,21 @@ impl From<TableConfigJson> for Table {
 }
 
 impl Table {
+    /// Schema-qualified table name, with each part quoted with backticks
+    /// if it isn't a plain identifier (as for ClickHouse and MySQL).
     pub fn full_name(&self) -> String {
+        self.full_name_escaped(Escaping::Backslash)
+    }
+
+    /// Schema-qualified table name, with each part quoted for the
+    /// dialect's `escaping` if it isn't a plain identifier.
+    pub fn full_name_escaped(&self, escaping: Escaping) -> String {
+        let name = quote_identifier(&self.name, escaping);
+
         if let Some(ref schema) = self.schema {
-            format!("{}.{}", schema, self.name)
+            format!("{}.{}", quote_identifier(schema, escaping), name)
         } else {
-            self.name.to_owned()
+            name
         }
     }
 }
@@ -707,6 +1248,123 @@ impl From<PropertyConfigJson> for Property {
     }
 }
 
+/// A fact table column (or expression on it) shown when drilling through
+/// to the rows behind an aggregate, under `name`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DrillthroughColumn {
+    pub name: String,
+    pub column: String,
+}
+
+impl From<DrillthroughColumnConfigJson> for DrillthroughColumn {
+    fn from(column_config: DrillthroughColumnConfigJson) -> Self {
+        DrillthroughColumn {
+            name: column_config.name,
+            column: column_config.column,
+        }
+    }
+}
+
+/// Nulls `measure` (and the calculations on it) in result rows where the
+/// `by` measure is below `min`, or null.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MaskingRule {
+    pub measure: String,
+    pub by: String,
+    pub min: f64,
+    /// Also mask other rows of the same drilldown groups, so that masked
+    /// values can't be worked out from the group's total.
+    #[serde(default)]
+    pub complementary: bool,
+}
+
+impl From<MaskingRuleConfigJson> for MaskingRule {
+    fn from(rule_config: MaskingRuleConfigJson) -> Self {
+        MaskingRule {
+            measure: rule_config.measure,
+            by: rule_config.by.unwrap_or_else(|| FACT_COUNT_MEASURE.to_owned()),
+            min: rule_config.min,
+            complementary: rule_config.complementary.unwrap_or(false),
+        }
+    }
+}
+
+/// A formula over measures, defined once for the schema and queried like
+/// a measure in the cubes that use it. See `calculation::Formula` for the
+/// syntax.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NamedCalculation {
+    pub name: String,
+    pub formula: String,
+}
+
+impl From<CalculationConfigJson> for NamedCalculation {
+    fn from(calculation_config: CalculationConfigJson) -> Self {
+        NamedCalculation {
+            name: calculation_config.name,
+            formula: calculation_config.formula,
+        }
+    }
+}
+
+/// Row-level security: the principal running a query has a role, and the
+/// role's row filters are added as cuts to every query it runs (see
+/// `Schema::row_filter_cuts`).
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Security {
+    /// Role of queries that don't have one, e.g. anonymous requests.
+    /// Without it, those queries are rejected.
+    pub default_role: Option<String>,
+    pub roles: Vec<Role>,
+}
+
+impl From<SecurityConfigJson> for Security {
+    fn from(security_config: SecurityConfigJson) -> Self {
+        Security {
+            default_role: security_config.default_role,
+            roles: security_config.roles
+                .map(|roles| roles.into_iter().map(|role| role.into()).collect())
+                .unwrap_or_default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Role {
+    pub name: String,
+    /// A role without row filters can query all rows.
+    pub row_filters: Vec<RowFilter>,
+}
+
+impl From<RoleConfigJson> for Role {
+    fn from(role_config: RoleConfigJson) -> Self {
+        Role {
+            name: role_config.name,
+            row_filters: role_config.row_filters
+                .map(|filters| filters.into_iter().map(|filter| filter.into()).collect())
+                .unwrap_or_default(),
+        }
+    }
+}
+
+/// A cut, in the query syntax (e.g. `[Geography].[Geography].[State].[06]`),
+/// that restricts a role's queries. It applies to `cube`, or to every cube
+/// with the cut's level if there's no `cube`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RowFilter {
+    pub cube: Option<String>,
+    pub cut: String,
+}
+
+impl From<RowFilterConfigJson> for RowFilter {
+    fn from(row_filter_config: RowFilterConfigJson) -> Self {
+        RowFilter {
+            cube: row_filter_config.cube,
+            cut: row_filter_config.cut,
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Annotation{
     pub name: String,
@@ -739,6 +1397,7 @@ mod test {
     fn test_dimension_usage() {
         let schema_config = SchemaConfigJson {
             default_locale: Some(DEFAULT_LOCALE_STR.into()),
+            security: None,
             name: "test".into(),
             shared_dimensions: Some(vec![
                 SharedDimensionConfigJson {
@@ -757,8 +1416,15 @@ mod test {
                                     name: "tract".into(),
                                     key_column: "geoid".into(),
                                     name_column: None,
+                                    caption_expression: None,
                                     properties: None,
                                     key_type: None,
+                                    fact_column: None,
+                                    order_by: None,
+                                    order_direction: None,
+                                    key_coercion: None,
+                                    min_cardinality: None,
+                                    max_cardinality: None,
                                     annotations: None,
                                 },
                             ],
@@ -790,10 +1456,17 @@ mod test {
                         }
                     ]),
                     measures: vec![],
+                    default_time: None,
+                    auto_top: None,
+                    drillthrough: None,
+                    calculations: None,
+                    masking: None,
                     annotations: None,
                 }
             ],
+            calculations: None,
             annotations: None,
+            name_resolution: None,
         };
 
         let schema: Schema = schema_config.into();
@@ -801,6 +1474,66 @@ mod test {
         assert_eq!(schema.cubes[0].dimensions.len(), 1);
     }
 
+    #[test]
+    fn key_coercion() {
+        let level_config: LevelConfigJson = serde_json::from_str(r#"{
+            "name": "County",
+            "key_column": "fips",
+            "key_coercion": { "trim": true, "pad": 5 }
+        }"#).unwrap();
+        let level: Level = level_config.into();
+
+        assert_eq!(level.coerce_key("1001"), "01001");
+        assert_eq!(level.coerce_key(" 6037 "), "06037");
+        assert_eq!(level.coerce_key("48201"), "48201");
+
+        let coercion = KeyCoercion {
+            trim: false,
+            case: Some(KeyCase::Upper),
+            pad: Some(4),
+            pad_char: '_',
+        };
+        assert_eq!(coercion.apply("ny"), "__NY");
+    }
+
+    #[test]
+    fn level_cardinality() {
+        let level_config: LevelConfigJson = serde_json::from_str(r#"{
+            "name": "County",
+            "key_column": "fips",
+            "min_cardinality": 3000,
+            "max_cardinality": 3300
+        }"#).unwrap();
+        let level: Level = level_config.into();
+
+        let level_metadata = metadata::LevelMetadata::from(&level);
+        assert_eq!(level_metadata.min_cardinality, Some(3000));
+        assert_eq!(level_metadata.max_cardinality, Some(3300));
+
+        let bands = vec![
+            Band { label: "low".into(), min: None, max: Some(10.0) },
+            Band { label: "high".into(), min: Some(10.0), max: None },
+        ];
+        let hier = Hierarchy::banded("Size", "size", &bands);
+        assert_eq!(hier.levels[0].max_cardinality, Some(2));
+    }
+
+    #[test]
+    fn table_full_name_quoting() {
+        let table = Table { name: "sales".into(), schema: Some("db".into()), primary_key: None };
+        assert_eq!(table.full_name(), "db.sales");
+        assert_eq!(table.full_name_escaped(Escaping::Standard), "db.sales");
+
+        let table = Table { name: "sales-2019".into(), schema: Some("my db".into()), primary_key: None };
+        assert_eq!(table.full_name(), "`my db`.`sales-2019`");
+        assert_eq!(table.full_name_escaped(Escaping::Standard), "\"my db\".\"sales-2019\"");
+
+        // dotted names are quoted part by part
+        let table = Table { name: "analytics.sales-2019".into(), schema: None, primary_key: None };
+        assert_eq!(table.full_name(), "analytics.`sales-2019`");
+        assert_eq!(table.full_name_escaped(Escaping::Standard), "analytics.\"sales-2019\"");
+    }
+
     // End to end, from xml
     use serde_xml_rs::from_reader;
 
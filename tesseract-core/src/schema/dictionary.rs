@@ -0,0 +1,196 @@
+//! Data dictionary: a human-readable listing of a cube's dimensions,
+//! hierarchies, levels, properties and measures, with their annotations and
+//! the tables and columns they come from, for documentation and data
+//! catalogs.
+
+use crate::dataframe::{DataFrame, Column, ColumnData};
+
+use super::{
+    Cube,
+    DimensionType,
+    Hierarchy,
+    Annotation,
+    metadata::AggregatorMetadata,
+};
+
+
+/// Headers of the dictionary's columns.
+pub fn dictionary_headers() -> Vec<String> {
+    vec![
+        "Element".to_owned(),
+        "Name".to_owned(),
+        "Type".to_owned(),
+        "Source".to_owned(),
+        "Annotations".to_owned(),
+    ]
+}
+
+/// The data dictionary of a cube, one row per element. Names are full
+/// names (e.g. `Geography.State.State`), and sources are `table.column`.
+pub fn dictionary(cube: &Cube) -> DataFrame {
+    let mut rows = DictionaryRows::default();
+
+    rows.push("cube", cube.name.clone(), "", cube.table.full_name(), &cube.annotations);
+
+    for dim in &cube.dimensions {
+        let dim_source = dim.foreign_key.as_ref()
+            .map(|fk| format!("{}.{}", cube.table.full_name(), fk))
+            .unwrap_or_default();
+
+        rows.push("dimension", dim.name.clone(), dimension_type(&dim.dim_type), dim_source, &dim.annotations);
+
+        for hier in &dim.hierarchies {
+            let hier_name = format!("{}.{}", dim.name, hier.name);
+            let table = hierarchy_table(cube, hier);
+
+            rows.push("hierarchy", hier_name.clone(), "", table.clone(), &hier.annotations);
+
+            for level in &hier.levels {
+                let level_name = format!("{}.{}", hier_name, level.name);
+
+                let mut source = format!("{}.{}", table, level.key_column);
+                if let Some(ref name_column) = level.name_column {
+                    source.push_str(&format!(", {}.{}", table, name_column));
+                }
+
+                rows.push("level", level_name.clone(), "", source, &level.annotations);
+
+                for property in level.properties.iter().flatten() {
+                    let property_type = property.caption_set.as_ref()
+                        .map(|caption_set| format!("caption ({})", caption_set))
+                        .unwrap_or_default();
+
+                    rows.push(
+                        "property",
+                        format!("{}.{}", level_name, property.name),
+                        &property_type,
+                        format!("{}.{}", table, property.column),
+                        &property.annotations,
+                    );
+                }
+            }
+        }
+    }
+
+    for measure in &cube.measures {
+        rows.push(
+            "measure",
+            measure.name.clone(),
+            &AggregatorMetadata::from(&measure.aggregator).name,
+            format!("{}.{}", cube.table.full_name(), measure.column),
+            &measure.annotations,
+        );
+    }
+
+    rows.into_dataframe()
+}
+
+fn dimension_type(dim_type: &DimensionType) -> &'static str {
+    match dim_type {
+        DimensionType::Standard => "standard",
+        DimensionType::Time => "time",
+        DimensionType::Geo => "geo",
+        DimensionType::GeneratedTime => "generated_time",
+        DimensionType::Banded => "banded",
+    }
+}
+
+/// Table a hierarchy's levels are read from: its own table, its inline
+/// table, or the fact table.
+fn hierarchy_table(cube: &Cube, hier: &Hierarchy) -> String {
+    if let Some(ref table) = hier.table {
+        table.full_name()
+    } else if let Some(ref inline_table) = hier.inline_table {
+        inline_table.alias.clone()
+    } else {
+        cube.table.full_name()
+    }
+}
+
+fn annotations_text(annotations: &Option<Vec<Annotation>>) -> String {
+    annotations.iter()
+        .flatten()
+        .map(|ann| format!("{}: {}", ann.name, ann.text))
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
+#[derive(Default)]
+struct DictionaryRows {
+    elements: Vec<String>,
+    names: Vec<String>,
+    types: Vec<String>,
+    sources: Vec<String>,
+    annotations: Vec<String>,
+}
+
+impl DictionaryRows {
+    fn push(&mut self, element: &str, name: String, element_type: &str, source: String, annotations: &Option<Vec<Annotation>>) {
+        self.elements.push(element.to_owned());
+        self.names.push(name);
+        self.types.push(element_type.to_owned());
+        self.sources.push(source);
+        self.annotations.push(annotations_text(annotations));
+    }
+
+    fn into_dataframe(self) -> DataFrame {
+        let columns = vec![
+            self.elements,
+            self.names,
+            self.types,
+            self.sources,
+            self.annotations,
+        ];
+
+        DataFrame::from_vec(
+            dictionary_headers().into_iter()
+                .zip(columns)
+                .map(|(header, values)| Column::new(header, ColumnData::Text(values)))
+                .collect()
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::schema::Schema;
+
+    #[test]
+    fn cube_dictionary() {
+        let schema_str = r#"{
+            "name": "test",
+            "cubes": [ {
+                "name": "sales",
+                "table": { "name": "sales" },
+                "dimensions": [
+                    { "name": "Geography", "foreign_key": "geo_id", "annotations": [ { "name": "source", "text": "Census" } ], "hierarchies": [ { "name": "Geography", "table": { "name": "geo" }, "primary_key": "geo_id", "levels": [
+                        { "name": "State", "key_column": "state_id", "name_column": "state_name", "properties": [ { "name": "Abbreviation", "column": "state_abbr" } ] }
+                    ] } ] }
+                ],
+                "measures": [
+                    { "name": "Quantity", "column": "quantity", "aggregator": "sum", "annotations": [ { "name": "units", "text": "items" } ] }
+                ]
+            } ]
+        }"#;
+        let schema = Schema::from_json(schema_str).unwrap();
+
+        let df = dictionary(&schema.cubes[0]);
+        let columns: Vec<Vec<String>> = df.columns.iter()
+            .map(|col| col.column_data.stringify())
+            .collect();
+        let rows: Vec<Vec<&str>> = (0..df.len())
+            .map(|i| columns.iter().map(|col| col[i].as_str()).collect())
+            .collect();
+
+        assert_eq!(rows, vec![
+            vec!["cube", "sales", "", "sales", ""],
+            vec!["dimension", "Geography", "standard", "sales.geo_id", "source: Census"],
+            vec!["hierarchy", "Geography.Geography", "", "geo", ""],
+            vec!["level", "Geography.Geography.State", "", "geo.state_id, geo.state_name", ""],
+            vec!["property", "Geography.Geography.State.Abbreviation", "", "geo.state_abbr", ""],
+            vec!["measure", "Quantity", "sum", "sales.quantity", "units: items"],
+            vec!["measure", "Fact Count", "count", "sales.*", ""],
+        ]);
+    }
+}
@@ -1,8 +1,10 @@
 use serde_derive::Deserialize;
 
+use crate::names::NameResolution;
+use crate::query::SortDirection;
 use crate::query_ir::MemberType;
 use super::aggregator::Aggregator;
-use super::{DimensionType, MeasureType};
+use super::{DefaultTime, DimensionType, KeyCase, MeasureType};
 
 
 #[derive(Debug, Clone, PartialEq, Deserialize)]
@@ -10,8 +12,40 @@ pub struct SchemaConfigJson {
     pub name: String,
     pub shared_dimensions: Option<Vec<SharedDimensionConfigJson>>,
     pub cubes: Vec<CubeConfigJson>,
+    pub calculations: Option<Vec<CalculationConfigJson>>,
     pub annotations: Option<Vec<AnnotationConfigJson>>,
     pub default_locale: Option<String>,
+    pub name_resolution: Option<NameResolution>,
+    pub security: Option<SecurityConfigJson>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct CalculationConfigJson {
+    pub name: String,
+    pub formula: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct SecurityConfigJson {
+    pub default_role: Option<String>,
+    pub roles: Option<Vec<RoleConfigJson>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct RoleConfigJson {
+    pub name: String,
+    pub row_filters: Option<Vec<RowFilterConfigJson>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct RowFilterConfigJson {
+    pub cube: Option<String>,
+    pub cut: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct CalculationUsageJson {
+    pub name: String,
 }
 
 #[derive(Debug, Clone, PartialEq, Deserialize)]
@@ -21,20 +55,53 @@ pub struct CubeConfigJson {
     pub dimensions: Option<Vec<DimensionConfigJson>>,
     pub dimension_usages: Option<Vec<DimensionUsageJson>>,
     pub measures: Vec<MeasureConfigJson>,
+    pub default_time: Option<DefaultTime>,
+    pub auto_top: Option<u64>,
+    pub drillthrough: Option<Vec<DrillthroughColumnConfigJson>>,
+    pub calculations: Option<Vec<CalculationUsageJson>>,
+    pub masking: Option<Vec<MaskingRuleConfigJson>>,
     pub annotations: Option<Vec<AnnotationConfigJson>>,
 }
 
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct DrillthroughColumnConfigJson {
+    pub name: String,
+    pub column: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct MaskingRuleConfigJson {
+    pub measure: String,
+    /// Defaults to the cube's fact count.
+    pub by: Option<String>,
+    pub min: f64,
+    pub complementary: Option<bool>,
+}
+
 #[derive(Debug, Clone, PartialEq, Deserialize)]
 pub struct DimensionConfigJson {
     pub name: String,
     pub foreign_key: Option<String>, // does not exist for shared dims
+    /// Empty for `generated_time` and `banded` dimensions, whose hierarchy
+    /// is generated.
+    #[serde(default)]
     pub hierarchies: Vec<HierarchyConfigJson>,
     pub default_hierarchy: Option<String>,
     #[serde(rename="type")]
     pub dim_type: Option<DimensionType>,
+    /// Only for `banded` dimensions.
+    #[serde(default)]
+    pub bands: Vec<BandConfigJson>,
     pub annotations: Option<Vec<AnnotationConfigJson>>,
 }
 
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct BandConfigJson {
+    pub label: String,
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+}
+
 #[derive(Debug, Clone, PartialEq, Deserialize)]
 pub struct SharedDimensionConfigJson {
     pub name: String,
@@ -95,11 +162,26 @@ pub struct LevelConfigJson {
     pub name: String,
     pub key_column: String,
     pub name_column: Option<String>,
+    pub caption_expression: Option<String>,
     pub properties: Option<Vec<PropertyConfigJson>>,
     pub key_type: Option<MemberType>,
+    pub fact_column: Option<String>,
+    pub order_by: Option<String>,
+    pub order_direction: Option<SortDirection>,
+    pub key_coercion: Option<KeyCoercionConfigJson>,
+    pub min_cardinality: Option<u64>,
+    pub max_cardinality: Option<u64>,
     pub annotations: Option<Vec<AnnotationConfigJson>>,
 }
 
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct KeyCoercionConfigJson {
+    pub trim: Option<bool>,
+    pub case: Option<KeyCase>,
+    pub pad: Option<usize>,
+    pub pad_char: Option<char>,
+}
+
 #[derive(Debug, Clone, PartialEq, Deserialize)]
 pub struct MeasureConfigJson {
     pub name: String,
@@ -107,6 +189,9 @@ pub struct MeasureConfigJson {
     pub aggregator: Aggregator,
     #[serde(rename="type")]
     pub measure_type: Option<MeasureType>,
+    pub precision: Option<u32>,
+    pub filter: Option<String>,
+    pub datatype: Option<String>,
     pub annotations: Option<Vec<AnnotationConfigJson>>,
 }
 
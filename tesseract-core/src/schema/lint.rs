@@ -0,0 +1,210 @@
+//! Schema lint: problems that don't make a schema invalid (see
+//! `Schema::validate`), but are likely mistakes, reported as structured
+//! diagnostics for the CLI, the flush endpoint and external tooling.
+
+use serde_derive::Serialize;
+use std::fmt;
+
+use super::{
+    Schema,
+    Cube,
+    DimensionType,
+    Hierarchy,
+    Aggregator,
+    TimeGrain,
+};
+
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Diagnostic {
+    pub kind: DiagnosticKind,
+    /// `None` for diagnostics about the whole schema.
+    pub cube: Option<String>,
+    /// Full name of the dimension, level or measure the diagnostic is about.
+    pub item: String,
+    pub message: String,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.cube {
+            Some(ref cube) => write!(f, "cube {}: {}", cube, self.message),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub enum DiagnosticKind {
+    /// A level without a name column, whose members are shown by their ids.
+    #[serde(rename="missing_name_column")]
+    MissingNameColumn,
+    /// Levels of a hierarchy sharing a key column, or time levels that
+    /// aren't ordered from the top down.
+    #[serde(rename="suspicious_level_order")]
+    SuspiciousLevelOrder,
+    /// A measure aggregating a key column of the fact table.
+    #[serde(rename="measure_key_column")]
+    MeasureKeyColumn,
+    /// A shared dimension that no cube uses.
+    #[serde(rename="unused_shared_dimension")]
+    UnusedSharedDimension,
+}
+
+
+/// Lints a schema, returning a diagnostic for each likely mistake found.
+pub fn lint(schema: &Schema) -> Vec<Diagnostic> {
+    let mut diagnostics = vec![];
+
+    for shared_dim in &schema.unused_shared_dimensions {
+        diagnostics.push(Diagnostic {
+            kind: DiagnosticKind::UnusedSharedDimension,
+            cube: None,
+            item: shared_dim.clone(),
+            message: format!("Shared dimension {} is not used by any cube", shared_dim),
+        });
+    }
+
+    for cube in &schema.cubes {
+        lint_levels(cube, &mut diagnostics);
+        lint_measures(cube, &mut diagnostics);
+    }
+
+    diagnostics
+}
+
+fn lint_levels(cube: &Cube, diagnostics: &mut Vec<Diagnostic>) {
+    for dim in &cube.dimensions {
+        for hier in &dim.hierarchies {
+            let hier_name = format!("{}.{}", dim.name, hier.name);
+
+            // time and banded levels are their own names
+            let has_names = dim.dim_type == DimensionType::Standard || dim.dim_type == DimensionType::Geo;
+
+            for level in &hier.levels {
+                let has_captions = level.properties.iter()
+                    .flatten()
+                    .any(|p| p.caption_set.is_some());
+
+                if has_names && level.name_column.is_none() && !has_captions {
+                    diagnostics.push(Diagnostic {
+                        kind: DiagnosticKind::MissingNameColumn,
+                        cube: Some(cube.name.clone()),
+                        item: format!("{}.{}", hier_name, level.name),
+                        message: format!("Level {}.{} has no name_column, its members will be shown by id", hier_name, level.name),
+                    });
+                }
+            }
+
+            if let Some(message) = suspicious_level_order(hier) {
+                diagnostics.push(Diagnostic {
+                    kind: DiagnosticKind::SuspiciousLevelOrder,
+                    cube: Some(cube.name.clone()),
+                    item: hier_name.clone(),
+                    message: format!("Hierarchy {}: {}", hier_name, message),
+                });
+            }
+        }
+    }
+}
+
+fn suspicious_level_order(hier: &Hierarchy) -> Option<String> {
+    for (i, level) in hier.levels.iter().enumerate() {
+        let same_key = hier.levels[i+1..].iter()
+            .find(|other| other.key_column == level.key_column);
+
+        if let Some(other) = same_key {
+            return Some(format!("levels {} and {} share the key column {}", level.name, other.name, level.key_column));
+        }
+    }
+
+    // levels named like time grains should go from the top down, e.g.
+    // Year before Month
+    let grain_idxs: Vec<(usize, &str)> = hier.levels.iter()
+        .filter_map(|level| {
+            TimeGrain::generated_levels().iter()
+                .position(|grain| grain.level_name() == level.name)
+                .map(|idx| (idx, level.name.as_str()))
+        })
+        .collect();
+
+    grain_idxs.windows(2)
+        .find(|pair| pair[0].0 > pair[1].0)
+        .map(|pair| format!("level {} comes before level {}", pair[0].1, pair[1].1))
+}
+
+fn lint_measures(cube: &Cube, diagnostics: &mut Vec<Diagnostic>) {
+    // key columns on the fact table: dimension foreign keys, and the level
+    // keys of hierarchies without a table of their own
+    let mut key_columns: Vec<&str> = vec![];
+
+    for dim in &cube.dimensions {
+        if let Some(ref foreign_key) = dim.foreign_key {
+            key_columns.push(foreign_key);
+        }
+
+        for hier in dim.hierarchies.iter().filter(|hier| hier.table.is_none() && hier.inline_table.is_none()) {
+            key_columns.extend(hier.levels.iter().map(|level| level.key_column.as_str()));
+        }
+    }
+
+    for measure in &cube.measures {
+        // counting keys is fine, e.g. the number of rows with a product
+        if measure.aggregator == Aggregator::Count {
+            continue;
+        }
+
+        if key_columns.contains(&measure.column.as_str()) {
+            diagnostics.push(Diagnostic {
+                kind: DiagnosticKind::MeasureKeyColumn,
+                cube: Some(cube.name.clone()),
+                item: measure.name.clone(),
+                message: format!("Measure {} aggregates the key column {}", measure.name, measure.column),
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn lint_schema() {
+        let schema_str = r#"{
+            "name": "test",
+            "shared_dimensions": [
+                { "name": "Geography", "hierarchies": [ { "name": "Geography", "table": { "name": "geo" }, "primary_key": "geo_id", "levels": [ { "name": "State", "key_column": "state_id", "name_column": "state_name" } ] } ] },
+                { "name": "Product", "hierarchies": [ { "name": "Product", "table": { "name": "product" }, "primary_key": "product_id", "levels": [ { "name": "Product", "key_column": "product_id", "name_column": "product_name" } ] } ] }
+            ],
+            "cubes": [ {
+                "name": "sales",
+                "table": { "name": "sales" },
+                "dimension_usages": [ { "source": "Geography", "foreign_key": "geo_id" } ],
+                "dimensions": [
+                    { "name": "Customer", "foreign_key": "customer_id", "hierarchies": [ { "name": "Customer", "table": { "name": "customer" }, "primary_key": "customer_id", "levels": [ { "name": "Customer", "key_column": "customer_id" } ] } ] },
+                    { "name": "Date", "type": "time", "foreign_key": "date_id", "hierarchies": [ { "name": "Date", "table": { "name": "dates" }, "primary_key": "date_id", "levels": [ { "name": "Month", "key_column": "month" }, { "name": "Year", "key_column": "year" } ] } ] }
+                ],
+                "measures": [
+                    { "name": "Quantity", "column": "quantity", "aggregator": "sum" },
+                    { "name": "Customer Sum", "column": "customer_id", "aggregator": "sum" },
+                    { "name": "Customers", "column": "customer_id", "aggregator": "count" }
+                ]
+            } ]
+        }"#;
+        let schema = Schema::from_json(schema_str).unwrap();
+
+        let diagnostics = lint(&schema);
+        let kinds: Vec<_> = diagnostics.iter()
+            .map(|d| (d.kind, d.item.as_str()))
+            .collect();
+
+        assert_eq!(kinds, vec![
+            (DiagnosticKind::UnusedSharedDimension, "Product"),
+            (DiagnosticKind::MissingNameColumn, "Customer.Customer.Customer"),
+            (DiagnosticKind::SuspiciousLevelOrder, "Date.Date"),
+            (DiagnosticKind::MeasureKeyColumn, "Customer Sum"),
+        ]);
+        assert_eq!(diagnostics[2].message, "Hierarchy Date.Date: level Month comes before level Year");
+    }
+}
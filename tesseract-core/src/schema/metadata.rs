@@ -5,6 +5,7 @@ use std::convert::From;
 use super::{
     Schema,
     Cube,
+    DefaultTime,
     Dimension,
     DimensionType,
     Hierarchy,
@@ -40,6 +41,7 @@ pub struct CubeMetadata {
     pub name: String,
     pub dimensions: Vec<DimensionMetadata>,
     pub measures: Vec<MeasureMetadata>,
+    pub default_time: Option<DefaultTime>,
     pub annotations: AnnotationMetadata,
 }
 
@@ -51,6 +53,7 @@ impl From<&Cube> for CubeMetadata {
             name: cube.name.clone(),
             dimensions: cube.dimensions.iter().map(|d| d.into()).collect(),
             measures: cube.measures.iter().map(|m| m.into()).collect(),
+            default_time: cube.default_time.clone(),
             annotations,
         }
     }
@@ -103,6 +106,10 @@ impl From<&Hierarchy> for HierarchyMetadata {
 pub struct LevelMetadata {
     pub name: String,
     pub properties: Option<Vec<PropertyMetadata>>,
+    /// Declared estimates of the number of members. In the extended
+    /// metadata, both are the member count when the level is cached.
+    pub min_cardinality: Option<u64>,
+    pub max_cardinality: Option<u64>,
     pub annotations: AnnotationMetadata,
 }
 
@@ -116,6 +123,8 @@ impl From<&Level> for LevelMetadata {
         LevelMetadata {
             name: level.name.clone(),
             properties,
+            min_cardinality: level.min_cardinality,
+            max_cardinality: level.max_cardinality,
             annotations,
         }
     }
@@ -126,6 +135,9 @@ pub struct MeasureMetadata {
     pub name: String,
     pub aggregator: AggregatorMetadata,
     pub measure_type: MeasureTypeMetadata,
+    /// The units of a standard measure, or its `currency`/`unit` annotation.
+    pub units: Option<String>,
+    pub datatype: Option<String>,
     pub annotations: AnnotationMetadata,
 }
 
@@ -133,10 +145,17 @@ impl From<&Measure> for MeasureMetadata {
     fn from(measure: &Measure) -> Self {
         let annotations = (&measure.annotations).into();
 
+        let units = match measure.measure_type {
+            MeasureType::Standard { ref units } => units.clone(),
+            MeasureType::Error { .. } => None,
+        };
+
         MeasureMetadata {
             name: measure.name.clone(),
             aggregator: (&measure.aggregator).into(),
             measure_type: (&measure.measure_type).into(),
+            units: units.or_else(|| measure.unit().map(|u| u.to_owned())),
+            datatype: measure.datatype.clone(),
             annotations,
         }
     }
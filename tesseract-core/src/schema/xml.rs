@@ -12,9 +12,11 @@
 use serde_derive::Deserialize;
 use serde_derive::Serialize;
 
+use crate::names::NameResolution;
+use crate::query::SortDirection;
 use crate::query_ir::MemberType;
 use super::aggregator::Aggregator;
-use super::{DimensionType, MeasureType};
+use super::{DefaultTime, DimensionType, KeyCase, MeasureType};
 
 
 #[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
@@ -24,9 +26,45 @@ pub struct SchemaConfigXML {
     pub shared_dimensions: Option<Vec<SharedDimensionConfigXML>>,
     #[serde(rename(deserialize="Cube"))]
     pub cubes: Vec<CubeConfigXML>,
+    #[serde(rename(deserialize="Calculation"))]
+    pub calculations: Option<Vec<CalculationConfigXML>>,
     #[serde(rename(deserialize="Annotation"))]
     pub annotations: Option<Vec<AnnotationConfigXML>>,
     pub default_locale: Option<String>,
+    pub name_resolution: Option<NameResolution>,
+    #[serde(rename(deserialize="Security"))]
+    pub security: Option<SecurityConfigXML>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct CalculationConfigXML {
+    pub name: String,
+    pub formula: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct SecurityConfigXML {
+    pub default_role: Option<String>,
+    #[serde(rename(deserialize="Role"))]
+    pub roles: Option<Vec<RoleConfigXML>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct RoleConfigXML {
+    pub name: String,
+    #[serde(rename(deserialize="RowFilter"))]
+    pub row_filters: Option<Vec<RowFilterConfigXML>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct RowFilterConfigXML {
+    pub cube: Option<String>,
+    pub cut: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct CalculationUsageXML {
+    pub name: String,
 }
 
 #[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
@@ -40,23 +78,53 @@ pub struct CubeConfigXML {
     pub dimension_usages: Option<Vec<DimensionUsageXML>>,
     #[serde(rename(deserialize="Measure"))]
     pub measures: Vec<MeasureConfigXML>,
+    pub default_time: Option<DefaultTime>,
+    #[serde(rename(deserialize="DrillthroughColumn"))]
+    pub drillthrough: Option<Vec<DrillthroughColumnConfigXML>>,
+    #[serde(rename(deserialize="CalculationUsage"))]
+    pub calculations: Option<Vec<CalculationUsageXML>>,
+    #[serde(rename(deserialize="MaskingRule"))]
+    pub masking: Option<Vec<MaskingRuleConfigXML>>,
     #[serde(rename(deserialize="Annotation"))]
     pub annotations: Option<Vec<AnnotationConfigXML>>,
 }
 
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct DrillthroughColumnConfigXML {
+    pub name: String,
+    pub column: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct MaskingRuleConfigXML {
+    pub measure: String,
+    pub by: Option<String>,
+    pub min: f64,
+    pub complementary: Option<bool>,
+}
+
 #[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
 pub struct DimensionConfigXML {
     pub name: String,
     pub foreign_key: Option<String>, // does not exist for shared dims
-    #[serde(rename(deserialize="Hierarchy"))]
+    #[serde(rename(deserialize="Hierarchy"), default)]
     pub hierarchies: Vec<HierarchyConfigXML>,
     pub default_hierarchy: Option<String>,
     #[serde(rename="type")]
     pub dim_type: Option<DimensionType>,
+    #[serde(rename(deserialize="Band"), default)]
+    pub bands: Vec<BandConfigXML>,
     #[serde(rename(deserialize="Annotation"))]
     pub annotations: Option<Vec<AnnotationConfigXML>>,
 }
 
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct BandConfigXML {
+    pub label: String,
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+}
+
 #[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
 pub struct SharedDimensionConfigXML {
     pub name: String,
@@ -128,13 +196,29 @@ pub struct LevelConfigXML {
     pub name: String,
     pub key_column: String,
     pub name_column: Option<String>,
+    pub caption_expression: Option<String>,
     #[serde(rename(deserialize="Property"))]
     pub properties: Option<Vec<PropertyConfigXML>>,
     pub key_type: Option<MemberType>,
+    pub fact_column: Option<String>,
+    pub order_by: Option<String>,
+    pub order_direction: Option<SortDirection>,
+    #[serde(rename(deserialize="KeyCoercion"))]
+    pub key_coercion: Option<KeyCoercionConfigXML>,
+    pub min_cardinality: Option<u64>,
+    pub max_cardinality: Option<u64>,
     #[serde(rename(deserialize="Annotation"))]
     pub annotations: Option<Vec<AnnotationConfigXML>>,
 }
 
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct KeyCoercionConfigXML {
+    pub trim: Option<bool>,
+    pub case: Option<KeyCase>,
+    pub pad: Option<usize>,
+    pub pad_char: Option<char>,
+}
+
 #[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
 pub struct MeasureConfigXML {
     pub name: String,
@@ -142,6 +226,9 @@ pub struct MeasureConfigXML {
     pub aggregator: Aggregator,
     #[serde(rename="type")]
     pub measure_type: Option<MeasureType>,
+    pub precision: Option<u32>,
+    pub filter: Option<String>,
+    pub datatype: Option<String>,
     #[serde(rename(deserialize="Annotation"))]
     pub annotations: Option<Vec<AnnotationConfigXML>>,
 }
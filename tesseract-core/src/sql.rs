@@ -5,7 +5,9 @@ use crate::query_ir::{
     TableSql,
     CutSql,
     DrilldownSql,
+    Escaping,
     MeasureSql,
+    quote_identifier,
     TopSql,
     SortSql,
     LimitSql,
@@ -28,17 +30,23 @@ pub(crate) fn standard_sql(
     _limit: &Option<LimitSql>,
     _rca: &Option<RcaSql>,
     _growth: &Option<GrowthSql>,
+    escaping: Escaping,
     ) -> String
 {
     // hack for now... remove later
     // it's unneeded, except for standard_sql
     fn agg_sql_string(m: &MeasureSql) -> String {
+        let column = match &m.filter {
+            Some(filter) => format!("case when {} then {} end", filter, m.column),
+            None => m.column.clone(),
+        };
+
         match &m.aggregator {
-            Aggregator::Sum => format!("sum({})", &m.column),
-            Aggregator::Count => format!("count({})", &m.column),
-            Aggregator::Average => format!("avg({})", &m.column),
-            Aggregator::Max => format!("max({})", &m.column),
-            Aggregator::Min => format!("min({})", &m.column),
+            Aggregator::Sum => format!("sum({})", column),
+            Aggregator::Count => format!("count({})", column),
+            Aggregator::Average => format!("avg({})", column),
+            Aggregator::Max => format!("max({})", column),
+            Aggregator::Min => format!("min({})", column),
             // median doesn't work like this
             Aggregator::BasicGroupedMedian { .. } => format!("median"),
             Aggregator::WeightedAverage {..} => format!("avg"),
@@ -70,13 +78,14 @@ pub(crate) fn standard_sql(
     //    .collect();
     // --------------------------------------------------
 
-    let drill_cols = join(drills.iter().map(|d| d.col_qual_string()), ", ");
+    let table_name = quote_identifier(&table.name, escaping);
+    let drill_cols = join(drills.iter().map(|d| d.col_qual_string_escaped(escaping)), ", ");
     let mea_cols = join(meas.iter().map(|m| agg_sql_string(m)), ", ");
 
     let mut final_sql = format!("select {}, {} from {}",
         drill_cols,
         mea_cols,
-        table.name,
+        table_name,
     );
 
     // join external dims
@@ -84,10 +93,10 @@ pub(crate) fn standard_sql(
         let join_ext_dim_clauses = join(ext_drills.iter()
             .map(|d| {
                 format!("inner join {} on {}.{} = {}.{}",
-                    d.table.full_name(),
-                    d.table.full_name(),
+                    d.table.full_name_escaped(escaping),
+                    quote_identifier(&d.table.name, escaping),
                     d.primary_key,
-                    table.name,
+                    table_name,
                     d.foreign_key,
                 )
         }), ", ");
@@ -96,7 +105,7 @@ pub(crate) fn standard_sql(
     }
 
     if !cuts.is_empty() {
-        let cut_clauses = join(cuts.iter().map(|c| format!("{} in ({})", c.col_qual_string(), c.members_string())), " and ");
+        let cut_clauses = join(cuts.iter().map(|c| format!("{} in ({})", c.col_qual_string_escaped(escaping), c.members_string_escaped(escaping))), " and ");
         final_sql = format!("{} where {}", final_sql, cut_clauses);
     }
 
@@ -134,7 +143,8 @@ mod test {
                 members: vec!["3".into()],
                 member_type: MemberType::NonText,
                 mask: Mask::Include,
-                for_match: false
+                for_match: false,
+                fact_column: None,
             },
         ];
         let drills = vec![
@@ -156,13 +166,91 @@ mod test {
             },
         ];
         let meas = vec![
-            MeasureSql { aggregator: Aggregator::Sum, column: "commits".into() }
+            MeasureSql { aggregator: Aggregator::Sum, column: "commits".into(), filter: None }
         ];
 
         assert_eq!(
-            standard_sql(&table, &cuts, &drills, &meas, &None, &None, &None, &None, &None),
+            standard_sql(&table, &cuts, &drills, &meas, &None, &None, &None, &None, &None, Escaping::Standard),
             "select valid_projects.id, valid_projects.name, sum(commits) from project_facts inner join valid_projects on valid_projects.id = project_facts.project_id where valid_projects.id in (3) group by valid_projects.id, valid_projects.name;".to_owned()
         );
     }
+
+    #[test]
+    fn standard_sql_quotes_tables() {
+        let table = TableSql {
+            name: "project facts".into(),
+            primary_key: None,
+        };
+        let dim_table = Table { name: "valid\"projects".into(), schema: Some("public".into()), primary_key: None };
+        let cuts = vec![
+            CutSql {
+                foreign_key: "project_id".into(),
+                primary_key: "id".into(),
+                inline_table: None,
+                table: dim_table.clone(),
+                column: "name".into(),
+                members: vec!["it's".into()],
+                member_type: MemberType::Text,
+                mask: Mask::Include,
+                for_match: false,
+                fact_column: None,
+            },
+        ];
+        let drills = vec![
+            DrilldownSql {
+                alias_postfix: "".into(),
+                foreign_key: "project_id".into(),
+                primary_key: "id".into(),
+                inline_table: None,
+                table: dim_table,
+                level_columns: vec![
+                    LevelColumn {
+                        key_column: "id".into(),
+                        name_column: None,
+                    },
+                ],
+                property_columns: vec![],
+            },
+        ];
+        let meas = vec![
+            MeasureSql { aggregator: Aggregator::Sum, column: "commits".into(), filter: None }
+        ];
+
+        assert_eq!(
+            standard_sql(&table, &cuts, &drills, &meas, &None, &None, &None, &None, &None, Escaping::Standard),
+            r#"select "valid""projects".id, sum(commits) from "project facts" inner join public."valid""projects" on "valid""projects".id = "project facts".project_id where "valid""projects".name in ('it''s') group by "valid""projects".id;"#
+        );
+    }
+
+    #[test]
+    fn like_cut_escapes_wildcards() {
+        let cut = CutSql {
+            foreign_key: "".into(),
+            primary_key: "".into(),
+            inline_table: None,
+            table: Table { name: "".into(), schema: None, primary_key: None },
+            column: "name".into(),
+            members: vec!["100%_a\\b".into()],
+            member_type: MemberType::Text,
+            mask: Mask::Include,
+            for_match: true,
+            fact_column: None,
+        };
+
+        assert_eq!(
+            cut.members_like_string_escaped(Escaping::Backslash),
+            r#"(name like '%100\\%\\_a\\\\b%')"#,
+        );
+        assert_eq!(
+            cut.members_like_string_escaped(Escaping::Standard),
+            r#"(name like '%100\%\_a\\b%' escape '\')"#,
+        );
+
+        let cut = CutSql { members: vec!["5".into(), "a_b".into()], member_type: MemberType::NonText, mask: Mask::Exclude, ..cut };
+        assert_eq!(
+            cut.members_like_string_escaped(Escaping::Standard),
+            r#"name not like 5 and name not like 'a\_b' escape '\'"#,
+        );
+    }
 }
 
@@ -0,0 +1,83 @@
+//! Schema parsing and sql generation for the browser, with the `wasm`
+//! feature. The module is built by the tesseract-wasm crate.
+//!
+//! There's no backend here, so sql is generated by the standard sql
+//! generator. Queries are passed as JSON, with the same string formats
+//! as the aggregate endpoint's query params:
+//!
+//! ```json
+//! {
+//!     "drilldowns": ["Year.Year"],
+//!     "cuts": ["Geography.State.State.06"],
+//!     "measures": ["Quantity"]
+//! }
+//! ```
+
+use failure::Error;
+use std::convert::TryFrom;
+use wasm_bindgen::prelude::*;
+
+use crate::query::{Query, QueryJson};
+use crate::query_ir::Escaping;
+use crate::schema::Schema;
+use crate::sql;
+
+
+fn to_js_err(err: Error) -> JsValue {
+    JsValue::from_str(&err.to_string())
+}
+
+/// Reads a JSON or XML schema.
+fn read_schema(raw_schema: &str) -> Result<Schema, Error> {
+    let mut schema = if raw_schema.trim_start().starts_with('<') {
+        Schema::from_xml(raw_schema)?
+    } else {
+        Schema::from_json(raw_schema)?
+    };
+    schema.validate()?;
+
+    Ok(schema)
+}
+
+fn generate_sql(raw_schema: &str, cube: &str, raw_query: &str) -> Result<String, Error> {
+    let schema = read_schema(raw_schema)?;
+    let query = Query::try_from(serde_json::from_str::<QueryJson>(raw_query)?)?;
+
+    let (query_ir, _headers) = schema.sql_query(cube, &query)?;
+
+    Ok(sql::standard_sql(
+        &query_ir.table,
+        &query_ir.cuts,
+        &query_ir.drills,
+        &query_ir.meas,
+        &query_ir.top,
+        &query_ir.sort,
+        &query_ir.limit,
+        &query_ir.rca,
+        &query_ir.growth,
+        Escaping::Standard,
+    ))
+}
+
+#[wasm_bindgen]
+pub fn validate_schema(raw_schema: &str) -> Result<(), JsValue> {
+    read_schema(raw_schema)
+        .map(|_| ())
+        .map_err(to_js_err)
+}
+
+/// Checks that the query is valid for the cube, returning the
+/// error message otherwise.
+#[wasm_bindgen]
+pub fn validate_query(raw_schema: &str, cube: &str, raw_query: &str) -> Result<(), JsValue> {
+    generate_sql(raw_schema, cube, raw_query)
+        .map(|_| ())
+        .map_err(to_js_err)
+}
+
+/// Generates the sql for a query.
+#[wasm_bindgen]
+pub fn sql_for_query(raw_schema: &str, cube: &str, raw_query: &str) -> Result<String, JsValue> {
+    generate_sql(raw_schema, cube, raw_query)
+        .map_err(to_js_err)
+}
@@ -0,0 +1,33 @@
+#ifndef TESSERACT_H
+#define TESSERACT_H
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef struct tesseract_schema tesseract_schema;
+
+/* Loads and validates a JSON or XML schema. Returns NULL on failure,
+ * setting *err (if err is not NULL) to a message freed with
+ * tesseract_string_free. */
+tesseract_schema *tesseract_schema_load(const char *raw_schema, char **err);
+
+void tesseract_schema_free(tesseract_schema *schema);
+
+/* Generates the ClickHouse sql for a JSON query against cube. The result
+ * must be freed with tesseract_string_free. Returns NULL on failure,
+ * setting *err as in tesseract_schema_load. */
+char *tesseract_sql_for_query(
+    const tesseract_schema *schema,
+    const char *cube,
+    const char *raw_query,
+    char **err
+);
+
+void tesseract_string_free(char *s);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
@@ -0,0 +1,235 @@
+//! C bindings for loading tesseract schemas and generating ClickHouse sql,
+//! for services that aren't written in rust. See `include/tesseract.h`.
+//!
+//! Strings passed in must be nul-terminated utf-8. Strings and schemas
+//! returned are owned by the caller, and must be released with
+//! `tesseract_string_free` and `tesseract_schema_free`.
+//!
+//! On failure, functions return null and, if `err` is not null, set it
+//! to an error message (which must also be freed by the caller). Panics
+//! are failures too: they never unwind into the caller.
+
+use failure::{Error, format_err};
+use std::any::Any;
+use std::convert::TryFrom;
+use std::ffi::{CStr, CString};
+use std::os::raw::c_char;
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+
+use tesseract_clickhouse::clickhouse_sql;
+use tesseract_core::Schema;
+use tesseract_core::query::{Query, QueryJson};
+
+
+unsafe fn read_str<'a>(s: *const c_char) -> Result<&'a str, Error> {
+    if s.is_null() {
+        return Err(format_err!("Unexpected null string"));
+    }
+
+    Ok(CStr::from_ptr(s).to_str()?)
+}
+
+fn to_c_string(s: String) -> *mut c_char {
+    // nul bytes can't be represented in a C string, so they're dropped
+    let s = CString::new(s)
+        .unwrap_or_else(|err| {
+            let mut bytes = err.into_vec();
+            bytes.retain(|b| *b != 0);
+            CString::new(bytes).expect("nul bytes removed")
+        });
+
+    s.into_raw()
+}
+
+unsafe fn set_err(err_out: *mut *mut c_char, err: Error) {
+    if !err_out.is_null() {
+        *err_out = to_c_string(err.to_string());
+    }
+}
+
+/// Runs `f`, with a panic turned into an error, since unwinding out of an
+/// `extern "C"` fn is undefined behavior.
+fn catch_panic<T, F>(f: F) -> Result<T, Error>
+    where F: FnOnce() -> Result<T, Error>
+{
+    panic::catch_unwind(AssertUnwindSafe(f))
+        .unwrap_or_else(|payload| Err(format_err!("Internal error: {}", panic_message(&payload))))
+}
+
+fn panic_message(payload: &Box<dyn Any + Send>) -> &str {
+    if let Some(s) = payload.downcast_ref::<&str>() {
+        s
+    } else if let Some(s) = payload.downcast_ref::<String>() {
+        s
+    } else {
+        "panic"
+    }
+}
+
+unsafe fn load_schema(raw_schema: *const c_char) -> Result<Schema, Error> {
+    let raw_schema = read_str(raw_schema)?;
+
+    let mut schema = if raw_schema.trim_start().starts_with('<') {
+        Schema::from_xml(raw_schema)?
+    } else {
+        Schema::from_json(raw_schema)?
+    };
+    schema.validate()?;
+
+    Ok(schema)
+}
+
+unsafe fn sql_for_query(
+    schema: *const Schema,
+    cube: *const c_char,
+    raw_query: *const c_char,
+    ) -> Result<String, Error>
+{
+    let schema = schema.as_ref()
+        .ok_or_else(|| format_err!("Unexpected null schema"))?;
+    let cube = read_str(cube)?;
+    let raw_query = read_str(raw_query)?;
+
+    let query = Query::try_from(serde_json::from_str::<QueryJson>(raw_query)?)?;
+    let (query_ir, _headers) = schema.sql_query(cube, &query)?;
+
+    Ok(clickhouse_sql(&query_ir))
+}
+
+
+/// Loads and validates a JSON or XML schema.
+#[no_mangle]
+pub unsafe extern "C" fn tesseract_schema_load(
+    raw_schema: *const c_char,
+    err: *mut *mut c_char,
+    ) -> *mut Schema
+{
+    match catch_panic(|| load_schema(raw_schema)) {
+        Ok(schema) => Box::into_raw(Box::new(schema)),
+        Err(e) => {
+            set_err(err, e);
+            ptr::null_mut()
+        },
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn tesseract_schema_free(schema: *mut Schema) {
+    if !schema.is_null() {
+        let _ = panic::catch_unwind(|| drop(Box::from_raw(schema)));
+    }
+}
+
+/// Generates the ClickHouse sql for a JSON query against `cube`. Query parts
+/// use the same string formats as the aggregate endpoint's query params.
+#[no_mangle]
+pub unsafe extern "C" fn tesseract_sql_for_query(
+    schema: *const Schema,
+    cube: *const c_char,
+    raw_query: *const c_char,
+    err: *mut *mut c_char,
+    ) -> *mut c_char
+{
+    match catch_panic(|| sql_for_query(schema, cube, raw_query)) {
+        Ok(sql) => to_c_string(sql),
+        Err(e) => {
+            set_err(err, e);
+            ptr::null_mut()
+        },
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn tesseract_string_free(s: *mut c_char) {
+    if !s.is_null() {
+        let _ = panic::catch_unwind(|| drop(CString::from_raw(s)));
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const SCHEMA: &str = r#"{
+        "name": "test",
+        "cubes": [{
+            "name": "sales",
+            "table": { "name": "sales" },
+            "dimensions": [{
+                "name": "Year",
+                "foreign_key": "year",
+                "hierarchies": [{
+                    "name": "Year",
+                    "primary_key": "year",
+                    "levels": [{ "name": "Year", "key_column": "year" }]
+                }]
+            }],
+            "measures": [{ "name": "Quantity", "column": "quantity", "aggregator": "sum" }]
+        }]
+    }"#;
+
+    fn c_string(s: &str) -> CString {
+        CString::new(s).unwrap()
+    }
+
+    /// Copies and frees a string returned by the bindings.
+    unsafe fn take_string(s: *mut c_char) -> String {
+        assert!(!s.is_null());
+        let copy = CStr::from_ptr(s).to_str().unwrap().to_owned();
+        tesseract_string_free(s);
+        copy
+    }
+
+    #[test]
+    fn sql_for_query() {
+        unsafe {
+            let mut err = ptr::null_mut();
+            let schema = tesseract_schema_load(c_string(SCHEMA).as_ptr(), &mut err);
+            assert!(!schema.is_null());
+            assert!(err.is_null());
+
+            let query = c_string(r#"{ "drilldowns": ["Year.Year"], "measures": ["Quantity"], "cuts": ["Year.Year.Year.2018"] }"#);
+            let sql = tesseract_sql_for_query(schema, c_string("sales").as_ptr(), query.as_ptr(), &mut err);
+            let sql = take_string(sql);
+            assert!(err.is_null());
+            assert!(sql.contains("sum(quantity)"), "{}", sql);
+            assert!(sql.contains("2018"), "{}", sql);
+
+            let sql = tesseract_sql_for_query(schema, c_string("sale").as_ptr(), query.as_ptr(), &mut err);
+            assert!(sql.is_null());
+            assert_eq!(take_string(err), "schema does not contain cube");
+
+            tesseract_schema_free(schema);
+        }
+    }
+
+    #[test]
+    fn invalid_input() {
+        unsafe {
+            let mut err = ptr::null_mut();
+            let schema = tesseract_schema_load(c_string("{").as_ptr(), &mut err);
+            assert!(schema.is_null());
+            take_string(err);
+
+            // null pointers are errors, and err is optional
+            let sql = tesseract_sql_for_query(ptr::null(), ptr::null(), ptr::null(), &mut err);
+            assert!(sql.is_null());
+            assert_eq!(take_string(err), "Unexpected null schema");
+            assert!(tesseract_schema_load(ptr::null(), ptr::null_mut()).is_null());
+
+            tesseract_schema_free(ptr::null_mut());
+            tesseract_string_free(ptr::null_mut());
+        }
+    }
+
+    #[test]
+    fn panics_are_errors() {
+        let res: Result<(), Error> = catch_panic(|| panic!("boom"));
+        assert_eq!(res.unwrap_err().to_string(), "Internal error: boom");
+
+        let res: Result<(), Error> = catch_panic(|| panic!("{} {}", "formatted", "boom"));
+        assert_eq!(res.unwrap_err().to_string(), "Internal error: formatted boom");
+    }
+}
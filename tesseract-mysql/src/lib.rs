@@ -1,6 +1,7 @@
 use failure::{Error, format_err};
 use futures::future::Future;
-use tesseract_core::{Backend, DataFrame};
+use tesseract_core::{Backend, DataFrame, TimeGrain};
+use tesseract_core::query_ir::Escaping;
 
 extern crate futures;
 extern crate mysql_async as my;
@@ -40,6 +41,19 @@ impl Backend for MySql {
         Box::new(future)
     }
 
+    fn time_grain_sql(&self, grain: &TimeGrain, column: &str) -> String {
+        match grain {
+            TimeGrain::IsoWeek => format!("yearweek({}, 3)", column),
+            TimeGrain::DayOfWeek => format!("(weekday({}) + 1)", column),
+            TimeGrain::MonthName => format!("monthname({})", column),
+            _ => grain.standard_sql(column),
+        }
+    }
+
+    fn escaping(&self) -> Escaping {
+        Escaping::Backslash
+    }
+
     fn box_clone(&self) -> Box<dyn Backend + Send + Sync> {
         Box::new((*self).clone())
     }
@@ -0,0 +1,189 @@
+//! Convert a Pinot broker response to tesseract_core::DataFrame
+
+use failure::{Error, bail, format_err};
+use serde_derive::Deserialize;
+use serde_json::Value;
+use tesseract_core::{DataFrame, Column, ColumnData};
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all="camelCase")]
+pub struct PinotResponse {
+    result_table: Option<ResultTable>,
+    #[serde(default)]
+    exceptions: Vec<PinotException>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all="camelCase")]
+struct ResultTable {
+    data_schema: DataSchema,
+    rows: Vec<Vec<Value>>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all="camelCase")]
+struct DataSchema {
+    column_names: Vec<String>,
+    column_data_types: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all="camelCase")]
+struct PinotException {
+    error_code: Option<i64>,
+    message: String,
+}
+
+/// Column types map to `ColumnData` as follows (nullable when the column
+/// has nulls):
+/// - INT -> Int32
+/// - LONG -> Int64
+/// - FLOAT -> Float32
+/// - DOUBLE, BIG_DECIMAL -> Float64
+/// - anything else (STRING, BOOLEAN, TIMESTAMP, JSON, BYTES, arrays) -> Text
+pub fn response_to_df(res: PinotResponse) -> Result<DataFrame, Error> {
+    if let Some(exception) = res.exceptions.first() {
+        match exception.error_code {
+            Some(code) => bail!("Pinot error {}: {}", code, exception.message),
+            None => bail!("Pinot error: {}", exception.message),
+        }
+    }
+
+    let table = res.result_table
+        .ok_or_else(|| format_err!("Pinot response has no result table"))?;
+    let schema = table.data_schema;
+
+    if schema.column_names.len() != schema.column_data_types.len() {
+        bail!("Pinot response has {} column names for {} column types",
+            schema.column_names.len(),
+            schema.column_data_types.len(),
+        );
+    }
+
+    let columns = schema.column_names.into_iter()
+        .zip(schema.column_data_types.iter())
+        .enumerate()
+        .map(|(idx, (name, data_type))| {
+            let values = table.rows.iter().map(|row| row.get(idx).unwrap_or(&Value::Null));
+            Ok(Column::new(name, column_data(data_type, values)?))
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
+
+    Ok(DataFrame::from_vec(columns))
+}
+
+fn column_data<'a, I>(data_type: &str, values: I) -> Result<ColumnData, Error>
+    where I: Iterator<Item=&'a Value>
+{
+    let column_data = match data_type {
+        "INT" => {
+            let values = nullable(values, |v| v.as_i64().map(|n| n as i32))?;
+            if all_present(&values) {
+                ColumnData::Int32(values.into_iter().flatten().collect())
+            } else {
+                ColumnData::NullableInt32(values)
+            }
+        },
+        "LONG" => {
+            let values = nullable(values, |v| v.as_i64())?;
+            if all_present(&values) {
+                ColumnData::Int64(values.into_iter().flatten().collect())
+            } else {
+                ColumnData::NullableInt64(values)
+            }
+        },
+        "FLOAT" => {
+            let values = nullable(values, |v| number(v).map(|n| n as f32))?;
+            if all_present(&values) {
+                ColumnData::Float32(values.into_iter().flatten().collect())
+            } else {
+                ColumnData::NullableFloat32(values)
+            }
+        },
+        "DOUBLE" | "BIG_DECIMAL" => {
+            let values = nullable(values, number)?;
+            if all_present(&values) {
+                ColumnData::Float64(values.into_iter().flatten().collect())
+            } else {
+                ColumnData::NullableFloat64(values)
+            }
+        },
+        _ => {
+            let values: Vec<Option<String>> = values
+                .map(|v| match v {
+                    Value::Null => None,
+                    Value::String(s) => Some(s.clone()),
+                    v => Some(v.to_string()),
+                })
+                .collect();
+            if all_present(&values) {
+                ColumnData::Text(values.into_iter().flatten().collect())
+            } else {
+                ColumnData::NullableText(values)
+            }
+        },
+    };
+
+    Ok(column_data)
+}
+
+/// Numbers can come as strings, e.g. BIG_DECIMAL values, or `"NaN"` and
+/// `"Infinity"` for doubles.
+fn number(value: &Value) -> Option<f64> {
+    match value {
+        Value::String(s) => s.parse().ok(),
+        v => v.as_f64(),
+    }
+}
+
+fn nullable<'a, I, T, F>(values: I, convert: F) -> Result<Vec<Option<T>>, Error>
+    where I: Iterator<Item=&'a Value>,
+          F: Fn(&Value) -> Option<T>,
+{
+    values
+        .map(|v| match v {
+            Value::Null => Ok(None),
+            v => convert(v)
+                .map(Some)
+                .ok_or_else(|| format_err!("Unexpected value {} in Pinot response", v)),
+        })
+        .collect()
+}
+
+fn all_present<T>(values: &[Option<T>]) -> bool {
+    values.iter().all(|v| v.is_some())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn pinot_response() {
+        let res: PinotResponse = serde_json::from_str(r#"{
+            "resultTable": {
+                "dataSchema": {
+                    "columnNames": ["year_0", "name_0", "final_m0", "final_m1"],
+                    "columnDataTypes": ["INT", "STRING", "DOUBLE", "LONG"]
+                },
+                "rows": [
+                    [2018, "a", 1.5, 3],
+                    [2019, "b", "NaN", null]
+                ]
+            },
+            "exceptions": [],
+            "numDocsScanned": 10
+        }"#).unwrap();
+
+        let df = response_to_df(res).unwrap();
+        let types: Vec<_> = df.columns.iter().map(|col| col.column_data.type_name()).collect();
+        assert_eq!(types, vec!["Int32", "String", "Float64", "Nullable(Int64)"]);
+        assert_eq!(df.columns[1].column_data.stringify(), vec!["a", "b"]);
+        assert_eq!(df.columns[3].column_data.stringify(), vec!["3", ""]);
+
+        let res: PinotResponse = serde_json::from_str(r#"{
+            "exceptions": [ { "errorCode": 150, "message": "SQLParsingError" } ]
+        }"#).unwrap();
+        assert_eq!(response_to_df(res).unwrap_err().to_string(), "Pinot error 150: SQLParsingError");
+    }
+}
@@ -0,0 +1,122 @@
+//! Backend for [Apache Pinot](https://pinot.apache.org/), so that tesseract
+//! can front real-time Pinot tables with the same semantic schema.
+//!
+//! Queries are sent to the broker's `/query/sql` endpoint and run on the
+//! multi-stage engine, which supports the joins, subqueries and window
+//! functions that the generated sql uses.
+
+use actix_web::{client, HttpMessage};
+use failure::{Error, format_err};
+use futures::{future, Future};
+use log::*;
+use serde_json::json;
+use std::time::{Duration, Instant};
+use tesseract_core::{Backend, DataFrame, QueryIr, TimeGrain};
+
+mod df;
+mod sql;
+
+use self::df::{PinotResponse, response_to_df};
+pub use self::sql::{PinotDialect, pinot_sql};
+
+/// Time allowed for the broker to answer; Pinot enforces its own query
+/// timeout, so this only guards against an unresponsive broker.
+const REQUEST_TIMEOUT: u64 = 600;
+
+/// Largest response body accepted from the broker.
+const MAX_RESPONSE_SIZE: usize = 1024 * 1024 * 1024;
+
+#[derive(Clone)]
+pub struct Pinot {
+    /// Url of the broker's sql endpoint
+    query_url: String,
+}
+
+impl Pinot {
+    /// `url` is the broker's address, e.g. `localhost:8099` (http is
+    /// assumed) or `https://pinot-broker.example.com`.
+    pub fn from_url(url: &str) -> Result<Self, Error> {
+        let url = url.trim_end_matches('/');
+
+        let base_url = if url.starts_with("http://") || url.starts_with("https://") {
+            url.to_owned()
+        } else {
+            format!("http://{}", url)
+        };
+
+        Ok(Pinot {
+            query_url: format!("{}/query/sql", base_url),
+        })
+    }
+}
+
+impl Backend for Pinot {
+    fn exec_sql(&self, sql: String) -> Box<Future<Item=DataFrame, Error=Error>> {
+        let time_start = Instant::now();
+
+        let body = json!({
+            "sql": sql,
+            "queryOptions": "useMultistageEngine=true",
+        }).to_string();
+
+        let req = match client::post(&self.query_url)
+            .header("Content-Type", "application/json")
+            .body(body)
+        {
+            Ok(req) => req,
+            Err(err) => return Box::new(future::err(format_err!("{}", err))),
+        };
+
+        let fut = req.send()
+            .timeout(Duration::from_secs(REQUEST_TIMEOUT))
+            .map_err(|err| format_err!("Pinot request failed: {}", err))
+            .and_then(|res| {
+                res.json::<PinotResponse>()
+                    .limit(MAX_RESPONSE_SIZE)
+                    .map_err(|err| format_err!("Could not read Pinot response: {}", err))
+            })
+            .and_then(move |res| {
+                let timing = time_start.elapsed();
+                info!("Time for sql execution: {}.{:03}", timing.as_secs(), timing.subsec_millis());
+
+                response_to_df(res)
+            });
+
+        Box::new(fut)
+    }
+
+    fn explain_sql(&self, sql: String, _kind: Option<&str>) -> Box<Future<Item=DataFrame, Error=Error>> {
+        self.exec_sql(format!("explain plan for {}", sql))
+    }
+
+    fn time_grain_sql(&self, grain: &TimeGrain, column: &str) -> String {
+        match grain {
+            TimeGrain::Year => format!("year({})", column),
+            TimeGrain::Quarter => format!("(year({col}) * 10 + quarter({col}))", col=column),
+            TimeGrain::Month => format!("(year({col}) * 100 + month({col}))", col=column),
+            TimeGrain::Day => format!("(year({col}) * 10000 + month({col}) * 100 + dayOfMonth({col}))", col=column),
+            TimeGrain::IsoWeek => format!("(yearOfWeek({col}) * 100 + week({col}))", col=column),
+            TimeGrain::DayOfWeek => format!("dayOfWeek({})", column),
+            TimeGrain::MonthName => format!("toDateTime({}, 'MMMM')", column),
+        }
+    }
+
+    fn box_clone(&self) -> Box<dyn Backend + Send + Sync> {
+        Box::new((*self).clone())
+    }
+
+    fn generate_sql(&self, query_ir: QueryIr) -> String {
+        pinot_sql(&query_ir)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn broker_url() {
+        assert_eq!(Pinot::from_url("localhost:8099").unwrap().query_url, "http://localhost:8099/query/sql");
+        assert_eq!(Pinot::from_url("https://broker.example.com/").unwrap().query_url, "https://broker.example.com/query/sql");
+    }
+}
@@ -0,0 +1,227 @@
+mod aggregator;
+mod growth;
+mod options;
+mod primary_agg;
+mod rate;
+mod rca;
+
+use tesseract_core::query_ir::{
+    TableSql,
+    CutSql,
+    DrilldownSql,
+    MeasureSql,
+    HiddenDrilldownSql,
+    RcaSql,
+    GrowthSql,
+    RateSql,
+    Escaping,
+};
+use tesseract_core::{QueryIr, SqlDialect, dialect_sql};
+
+use self::options::wrap_options;
+use self::primary_agg::primary_agg;
+use self::rate::rate_calculation;
+
+
+/// Error checking is done before this point. This string formatter
+/// accepts any input
+pub fn pinot_sql(
+    query_ir: &QueryIr
+    ) -> String
+{
+    dialect_sql(&PinotDialect, query_ir)
+}
+
+
+/// Pinot sql generation, for the multi-stage engine.
+///
+/// Pinot doesn't support CTEs or `using` joins, so the aggregation is
+/// written with nested subqueries, and growth, rate and rca use window
+/// functions instead of ClickHouse's arrays. String literals and
+/// identifiers use standard sql quoting.
+#[derive(Debug, Clone, Copy)]
+pub struct PinotDialect;
+
+impl SqlDialect for PinotDialect {
+    fn escaping(&self) -> Escaping {
+        Escaping::Standard
+    }
+
+    fn primary_agg(
+        &self,
+        table: &TableSql,
+        cuts: &[CutSql],
+        drills: &[DrilldownSql],
+        meas: &[MeasureSql],
+        hidden_drills: Option<&[HiddenDrilldownSql]>,
+        ) -> (String, String)
+    {
+        primary_agg(table, cuts, drills, meas, hidden_drills)
+    }
+
+    fn rca(
+        &self,
+        table: &TableSql,
+        cuts: &[CutSql],
+        drills: &[DrilldownSql],
+        meas: &[MeasureSql],
+        rca: &RcaSql,
+        ) -> (String, String)
+    {
+        rca::calculate(table, cuts, drills, meas, rca)
+    }
+
+    fn rate(
+        &self,
+        table: &TableSql,
+        cuts: &[CutSql],
+        drills: &[DrilldownSql],
+        meas: &[MeasureSql],
+        rate: &RateSql,
+        ) -> (String, String)
+    {
+        rate_calculation(table, cuts, drills, meas, rate)
+    }
+
+    fn growth(
+        &self,
+        final_sql: String,
+        final_drill_cols: &str,
+        num_measures: usize,
+        growth: &GrowthSql,
+        ) -> (String, String)
+    {
+        growth::calculate(final_sql, final_drill_cols, num_measures, growth)
+    }
+
+    fn options(
+        &self,
+        final_sql: String,
+        final_drill_cols: &str,
+        query_ir: &QueryIr,
+        num_measures: usize,
+        ) -> String
+    {
+        wrap_options(final_sql, final_drill_cols, query_ir, num_measures)
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tesseract_core::{Aggregator, Table};
+    use tesseract_core::names::Mask;
+    use tesseract_core::query::SortDirection;
+    use tesseract_core::query_ir::{LevelColumn, LimitSql, MemberType, TopSql};
+
+    fn drill(table: &str, column: &str, postfix: &str) -> DrilldownSql {
+        DrilldownSql {
+            alias_postfix: postfix.into(),
+            foreign_key: format!("{}_id", column),
+            primary_key: "id".into(),
+            inline_table: None,
+            table: Table { name: table.into(), schema: None, primary_key: None },
+            level_columns: vec![
+                LevelColumn {
+                    key_column: column.into(),
+                    name_column: None,
+                },
+            ],
+            property_columns: vec![],
+        }
+    }
+
+    fn query_ir(drills: Vec<DrilldownSql>) -> QueryIr {
+        QueryIr {
+            table: TableSql { name: "sales".into(), primary_key: None },
+            cuts: vec![],
+            drills,
+            meas: vec![
+                MeasureSql { aggregator: Aggregator::Sum, column: "quantity".into(), filter: None },
+            ],
+            hidden_drills: vec![],
+            filters: vec![],
+            top: None,
+            top_where: None,
+            sort: None,
+            limit: None,
+            rca: None,
+            growth: None,
+            rate: None,
+            ratio_to_parent: None,
+            sparse: false,
+        }
+    }
+
+    #[test]
+    fn joins_and_cuts() {
+        let mut query_ir = query_ir(vec![drill("sales", "year", "0"), drill("dim_products", "product", "1")]);
+        query_ir.cuts.push(CutSql {
+            foreign_key: "product_id".into(),
+            primary_key: "id".into(),
+            inline_table: None,
+            table: Table { name: "dim_products".into(), schema: None, primary_key: None },
+            column: "product".into(),
+            members: vec!["O'Brien".into()],
+            member_type: MemberType::Text,
+            mask: Mask::Include,
+            for_match: false,
+            fact_column: None,
+        });
+
+        assert_eq!(
+            pinot_sql(&query_ir),
+            "select * from (\
+                select year_0, product_1, sum(m0) as final_m0 from (\
+                    select year as year_0, product_id, sum(quantity) as m0 from sales \
+                    where product_id in (select id from dim_products where product in ('O''Brien')) \
+                    group by year, product_id\
+                ) as fact \
+                inner join (select product as product_1, id as product_id from dim_products) as dim_0 \
+                on fact.product_id = dim_0.product_id \
+                group by year_0, product_1\
+            ) as options_base  order by year_0, product_1 ",
+        );
+    }
+
+    #[test]
+    fn limit_pushed_down() {
+        let mut query_ir = query_ir(vec![drill("sales", "year", "0")]);
+        query_ir.limit = Some(LimitSql { offset: Some(20), n: 10 });
+
+        let sql = pinot_sql(&query_ir);
+        assert!(sql.starts_with("select year_0, sum(m0) as final_m0 from ("));
+        assert!(sql.ends_with("group by year_0 order by year_0 limit 10 offset 20"));
+    }
+
+    #[test]
+    fn growth_with_lag() {
+        let mut query_ir = query_ir(vec![drill("sales", "year", "0"), drill("dim_products", "product", "1")]);
+        query_ir.growth = Some(GrowthSql {
+            time_drill: drill("sales", "year", "0"),
+            mea: "final_m0".into(),
+        });
+
+        let sql = pinot_sql(&query_ir);
+        assert!(sql.contains("select product_1, year_0, final_m0, case when growth_prev is null"));
+        assert!(sql.contains("lag(final_m0) over (partition by product_1 order by year_0) as growth_prev"));
+        assert!(sql.ends_with("order by product_1, year_0 "));
+    }
+
+    #[test]
+    fn top_with_row_number() {
+        let mut query_ir = query_ir(vec![drill("sales", "year", "0"), drill("sales", "month", "1")]);
+        query_ir.top = Some(TopSql {
+            n: 3,
+            by_column: "year_0".into(),
+            sort_columns: vec!["final_m0".into()],
+            sort_direction: SortDirection::Desc,
+            other: None,
+        });
+
+        let sql = pinot_sql(&query_ir);
+        assert!(sql.contains("select year_0, month_1, final_m0 from (select *, row_number() over (partition by year_0 order by final_m0 desc) as top_rank"));
+        assert!(sql.contains("as top_ranked where top_rank <= 3"));
+    }
+}
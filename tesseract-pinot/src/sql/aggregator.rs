@@ -0,0 +1,191 @@
+//! Applying aggregates to measures
+//!
+//! Like the ClickHouse backend, measures are aggregated twice: once on the
+//! fact table scan, and again when rolling up after the dim joins. For the
+//! aggregators whose formula can't be applied in two parts (average,
+//! weighted average, moe), the first pass only sums the components and the
+//! formula is applied in the second pass.
+//!
+//! Pinot differences:
+//! - there are no `-If` combinators, so filtered measures aggregate a
+//! `case when` expression (aggregate functions skip the nulls).
+//! - integer sums and counts are cast to double before dividing.
+//! - grouped median uses `percentile(col, 50)`.
+
+use itertools::join;
+use tesseract_core::Aggregator;
+
+/// First pass for aggregator, on the fact table. Returns the aggregated
+/// expressions with their aliases.
+///
+/// `filter` is only set for the basic aggregators; schema validation
+/// doesn't allow it on the others.
+pub fn pass_1(col: &str, aggregator: &Aggregator, filter: Option<&str>, mea_idx: usize) -> Vec<(String, String)> {
+    let col = match filter {
+        Some(filter) => format!("case when {} then {} end", filter, col),
+        None => col.to_owned(),
+    };
+
+    match aggregator {
+        Aggregator::Sum => vec![(format!("sum({})", col), format!("m{}", mea_idx))],
+        Aggregator::Count => vec![(format!("count({})", col), format!("m{}", mea_idx))],
+        Aggregator::Average => vec![
+            (format!("sum({})", col), format!("m{}_avg_sum", mea_idx)),
+            (format!("count({})", col), format!("m{}_avg_count", mea_idx)),
+        ],
+        Aggregator::Max => vec![(format!("max({})", col), format!("m{}", mea_idx))],
+        Aggregator::Min => vec![(format!("min({})", col), format!("m{}", mea_idx))],
+        Aggregator::BasicGroupedMedian { group_aggregator, .. } => {
+            vec![(format!("{}({})", group_aggregator, col), format!("m{}", mea_idx))]
+        },
+        Aggregator::WeightedAverage { weight_column } => vec![
+            (format!("sum({} * {})", col, weight_column), format!("m{}_weighted_avg_num", mea_idx)),
+            (format!("sum({})", weight_column), format!("m{}_weighted_avg_denom", mea_idx)),
+        ],
+        Aggregator::WeightedSum { weight_column } => vec![
+            (format!("sum({} * {})", col, weight_column), format!("m{}_weighted_sum_pass_1", mea_idx)),
+        ],
+        Aggregator::ReplicateWeightMoe { secondary_columns, .. } => {
+            let mut cols = vec![(format!("sum({})", col), format!("m{}_moe_primary", mea_idx))];
+            cols.extend(secondary_columns.iter().enumerate()
+                .map(|(n, s_col)| {
+                    (format!("sum({})", s_col), format!("m{}_moe_secondary_{}", mea_idx, n))
+                })
+            );
+            cols
+        },
+        Aggregator::Moe { critical_value, .. } => vec![
+            (format!("sum(power({} / {}, 2))", col, critical_value), format!("m{}_moe_sub_agg", mea_idx)),
+        ],
+        Aggregator::WeightedAverageMoe { primary_weight, secondary_weight_columns, .. } => {
+            let mut cols = vec![
+                (format!("sum({} * {})", col, primary_weight), format!("m{}_moe_primary_weighted_avg_num", mea_idx)),
+                (format!("sum({})", primary_weight), format!("m{}_moe_primary_weighted_avg_denom", mea_idx)),
+            ];
+            for (n, s_col) in secondary_weight_columns.iter().enumerate() {
+                cols.push((format!("sum({} * {})", col, s_col), format!("m{}_moe_secondary_weighted_avg_num_{}", mea_idx, n)));
+                cols.push((format!("sum({})", s_col), format!("m{}_moe_secondary_weighted_avg_denom_{}", mea_idx, n)));
+            }
+            cols
+        },
+        Aggregator::Custom(s) => vec![(s.replace("{}", &col), format!("m{}", mea_idx))],
+    }
+}
+
+/// First pass as a select list, e.g. `sum(col) as m0`.
+pub fn agg_sql_string_pass_1(col: &str, aggregator: &Aggregator, filter: Option<&str>, mea_idx: usize) -> String {
+    let cols = pass_1(col, aggregator, filter, mea_idx).into_iter()
+        .map(|(expr, alias)| format!("{} as {}", expr, alias));
+    join(cols, ", ")
+}
+
+/// Computes the final formula for aggregates after all joins.
+pub fn agg_sql_string_pass_2(aggregator: &Aggregator, mea_idx: usize) -> String {
+    let final_expr = match aggregator {
+        Aggregator::Sum => format!("sum(m{})", mea_idx),
+        Aggregator::Count => format!("sum(m{})", mea_idx),
+        Aggregator::Average => {
+            format!("cast(sum(m{0}_avg_sum) as double) / sum(m{0}_avg_count)", mea_idx)
+        },
+        Aggregator::Max => format!("max(m{})", mea_idx),
+        Aggregator::Min => format!("min(m{})", mea_idx),
+        Aggregator::BasicGroupedMedian { .. } => format!("percentile(m{}, 50)", mea_idx),
+        Aggregator::WeightedAverage { .. } => {
+            format!("cast(sum(m{0}_weighted_avg_num) as double) / sum(m{0}_weighted_avg_denom)", mea_idx)
+        },
+        Aggregator::WeightedSum { .. } => format!("sum(m{}_weighted_sum_pass_1)", mea_idx),
+        Aggregator::ReplicateWeightMoe { critical_value, design_factor, secondary_columns } => {
+            let inner_seq = secondary_columns.iter().enumerate()
+                .map(|(n, _)| {
+                    format!("power(sum(m{0}_moe_primary) - sum(m{0}_moe_secondary_{1}), 2)", mea_idx, n)
+                });
+
+            format!("{} * sqrt({} * ({}))",
+                critical_value,
+                design_factor / secondary_columns.len() as f64,
+                join(inner_seq, " + "),
+            )
+        },
+        Aggregator::Moe { critical_value, .. } => {
+            format!("{} * sqrt(sum(m{}_moe_sub_agg))", critical_value, mea_idx)
+        },
+        Aggregator::WeightedAverageMoe { critical_value, design_factor, secondary_weight_columns, .. } => {
+            let inner_seq = secondary_weight_columns.iter().enumerate()
+                .map(|(n, _)| {
+                    format!("power(\
+                        (cast(sum(m{0}_moe_primary_weighted_avg_num) as double) / sum(m{0}_moe_primary_weighted_avg_denom)) - \
+                        (cast(sum(m{0}_moe_secondary_weighted_avg_num_{1}) as double) / sum(m{0}_moe_secondary_weighted_avg_denom_{1}))\
+                        , 2)",
+                        mea_idx,
+                        n,
+                    )
+                });
+
+            format!("{} * sqrt({} * ({}))",
+                critical_value,
+                design_factor / secondary_weight_columns.len() as f64,
+                join(inner_seq, " + "),
+            )
+        },
+        Aggregator::Custom(s) => s.replace("{}", &format!("m{}", mea_idx)),
+    };
+
+    format!("{} as final_m{}", final_expr, mea_idx)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn basic_aggs() {
+        assert_eq!(
+            agg_sql_string_pass_1("col_1", &Aggregator::Sum, None, 0),
+            "sum(col_1) as m0",
+        );
+        assert_eq!(
+            agg_sql_string_pass_2(&Aggregator::Sum, 0),
+            "sum(m0) as final_m0",
+        );
+        assert_eq!(
+            agg_sql_string_pass_1("col_1", &Aggregator::Count, Some("flag = 1"), 1),
+            "count(case when flag = 1 then col_1 end) as m1",
+        );
+    }
+
+    #[test]
+    fn average() {
+        assert_eq!(
+            agg_sql_string_pass_1("col_1", &Aggregator::Average, None, 0),
+            "sum(col_1) as m0_avg_sum, count(col_1) as m0_avg_count",
+        );
+        assert_eq!(
+            agg_sql_string_pass_2(&Aggregator::Average, 0),
+            "cast(sum(m0_avg_sum) as double) / sum(m0_avg_count) as final_m0",
+        );
+    }
+
+    #[test]
+    fn moe() {
+        let agg = Aggregator::ReplicateWeightMoe {
+            critical_value: 1.645,
+            design_factor: 3.0,
+            secondary_columns: vec!["s0".into(), "s1".into(), "s2".into()],
+        };
+        assert_eq!(
+            agg_sql_string_pass_1("col_1", &agg, None, 0),
+            "sum(col_1) as m0_moe_primary, \
+                sum(s0) as m0_moe_secondary_0, \
+                sum(s1) as m0_moe_secondary_1, \
+                sum(s2) as m0_moe_secondary_2",
+        );
+        assert_eq!(
+            agg_sql_string_pass_2(&agg, 0),
+            "1.645 * sqrt(1 * (\
+                power(sum(m0_moe_primary) - sum(m0_moe_secondary_0), 2) + \
+                power(sum(m0_moe_primary) - sum(m0_moe_secondary_1), 2) + \
+                power(sum(m0_moe_primary) - sum(m0_moe_secondary_2), 2)\
+                )) as final_m0",
+        );
+    }
+}
@@ -0,0 +1,62 @@
+//! Growth is calculated with a `lag` window over the time columns,
+//! partitioned by the other drilldowns. The first period of each
+//! partition, and periods following a null or zero value, have no growth.
+
+use itertools::join;
+
+use super::GrowthSql;
+
+pub fn calculate(
+    final_sql: String,
+    final_drill_cols: &str,
+    num_measures: usize,
+    growth: &GrowthSql,
+    ) -> (String, String)
+{
+    // properties come joined in one entry
+    let time_cols: Vec<String> = growth.time_drill.col_alias_only_vec().iter()
+        .flat_map(|cols| cols.split(", ").map(|c| c.to_owned()).collect::<Vec<_>>())
+        .collect();
+
+    let other_drill_cols: Vec<_> = final_drill_cols.split(", ")
+        .filter(|col| !col.is_empty() && !time_cols.iter().any(|t| t == col))
+        .collect();
+
+    let other_meas: Vec<_> = (0..num_measures)
+        .map(|i| format!("final_m{}", i))
+        .filter(|mea| *mea != growth.mea)
+        .collect();
+
+    let partition_sql = if other_drill_cols.is_empty() {
+        "".to_owned()
+    } else {
+        format!("partition by {} ", join(&other_drill_cols, ", "))
+    };
+
+    let leading_cols = join(
+        other_drill_cols.iter().cloned()
+            .chain(time_cols.iter().map(|c| c.as_str()))
+            .chain(other_meas.iter().map(|c| c.as_str())),
+        ", "
+    );
+
+    let final_sql = format!("\
+        select {leading_cols}, {mea}, \
+            case when growth_prev is null or growth_prev = 0 then null \
+            else cast({mea} - growth_prev as double) / growth_prev end as growth, \
+            {mea} - growth_prev as final_m_diff \
+        from (\
+            select *, lag({mea}) over ({partition}order by {time_cols}) as growth_prev \
+            from ({fnl_sql}) as growth_base\
+        ) as growth_lag",
+        leading_cols = leading_cols,
+        mea = growth.mea,
+        partition = partition_sql,
+        time_cols = join(&time_cols, ", "),
+        fnl_sql = final_sql,
+    );
+
+    let final_drill_cols = join(other_drill_cols.iter().cloned().chain(time_cols.iter().map(|c| c.as_str())), ", ");
+
+    (final_sql, final_drill_cols)
+}
@@ -0,0 +1,163 @@
+use itertools::join;
+use tesseract_core::{QueryIr};
+
+pub fn wrap_options(
+    final_sql: String,
+    final_drill_cols: &str,
+    query_ir: &QueryIr,
+    num_measures: usize
+    ) -> String
+{
+    let mut final_sql = final_sql;
+    let top = &query_ir.top;
+    let top_where = &query_ir.top_where;
+    let sort = &query_ir.sort;
+    let limit = &query_ir.limit;
+    let filters = &query_ir.filters;
+
+    // Top n per partition, with `row_number`. The rank column is dropped
+    // afterwards, which needs the explicit list of result columns.
+    if let Some(top) = top {
+        let top_where_sql = if let Some(tw) = top_where { format!(" where {} {}", tw.by_column, tw.constraint.sql_string()) } else { "".into() };
+
+        let ranked_sql = format!("select *, row_number() over (partition by {} order by {} {}) as top_rank from ({}) as top_base{}",
+            top.by_column,
+            join(&top.sort_columns, ", "),
+            top.sort_direction.sql_string(),
+            final_sql,
+            top_where_sql,
+        );
+
+        let top_sql = format!("select {} from ({}) as top_ranked where top_rank <= {}",
+            join(output_columns(final_drill_cols, query_ir, num_measures), ", "),
+            ranked_sql,
+            top.n,
+        );
+
+        final_sql = if let Some(ref other) = top.other {
+            // the rows past the top n of each partition, rolled up.
+            let other_mea_cols = (0..num_measures).map(|i| format!("sum(final_m{i}) as final_m{i}", i=i));
+            let other_sql = format!("select {}, {} from ({}) as top_ranked where top_rank > {} group by {}",
+                join(&other.drill_columns, ", "),
+                join(other_mea_cols, ", "),
+                ranked_sql,
+                top.n,
+                join(&other.group_columns, ", "),
+            );
+
+            format!("{} union all {}", top_sql, other_sql)
+        } else {
+            top_sql
+        };
+    }
+
+    let limit_sql = {
+        if let Some(limit) = limit {
+            if let Some(offset) = limit.offset {
+                format!("limit {} offset {}", limit.n, offset)
+            } else {
+                format!("limit {}", limit.n)
+            }
+        } else {
+            "".to_string()
+        }
+    };
+
+    let sort_sql = {
+        if let Some(sort) = sort {
+            if final_drill_cols.is_empty() {
+                format!("order by {} {}", sort.column, sort.direction.sql_string())
+            } else {
+                format!("order by {} {}, {}",
+                    sort.column,
+                    sort.direction.sql_string(),
+                    final_drill_cols,
+                )
+            }
+        } else if let Some(top) = top {
+            format!("order by {} asc, {}",
+                top.by_column,
+                join(top.sort_columns.iter().map(|c| format!("{} desc", c)), ", "),
+            )
+        } else if final_drill_cols.is_empty() {
+            "".to_owned()
+        } else {
+            format!("order by {}", final_drill_cols)
+        }
+    };
+
+    let mut filter_clauses: Vec<_> = filters.iter()
+        .map(|f| format!("{} {}", f.by_column, f.constraint.sql_string()))
+        .collect();
+
+    if query_ir.sparse {
+        filter_clauses.extend((0..num_measures).map(|i| format!("final_m{} is not null", i)));
+    }
+
+    let filters_sql = if filter_clauses.is_empty() {
+        "".to_owned()
+    } else {
+        format!("where {}", join(filter_clauses, " and "))
+    };
+
+    // With a limit and no post-calculations, the sort and limit go straight
+    // onto the final aggregation.
+    if limit.is_some() && can_push_down_limit(query_ir) {
+        return format!("{} {} {}",
+            final_sql,
+            sort_sql,
+            limit_sql,
+        );
+    }
+
+    format!("select * from ({}) as options_base {} {} {}",
+        final_sql,
+        filters_sql,
+        sort_sql,
+        limit_sql,
+    )
+}
+
+/// Result columns, in order, for each kind of calculation.
+fn output_columns(final_drill_cols: &str, query_ir: &QueryIr, num_measures: usize) -> Vec<String> {
+    let mut cols: Vec<String> = final_drill_cols.split(", ")
+        .filter(|col| !col.is_empty())
+        .map(|col| col.to_owned())
+        .collect();
+
+    if let Some(growth) = &query_ir.growth {
+        cols.extend((0..num_measures).map(|i| format!("final_m{}", i)).filter(|mea| *mea != growth.mea));
+        cols.push(growth.mea.clone());
+        cols.push("growth".to_owned());
+        cols.push("final_m_diff".to_owned());
+    } else if let Some(rca) = &query_ir.rca {
+        if rca.debug {
+            cols.extend(["a", "b", "c", "d"].iter().map(|c| c.to_string()));
+        }
+        cols.push("rca".to_owned());
+        cols.extend((1..=num_measures).map(|i| format!("final_m{}", i)));
+    } else if query_ir.rate.is_some() {
+        cols.push("final_m0".to_owned());
+        cols.push("rate".to_owned());
+    } else {
+        cols.extend((0..num_measures).map(|i| format!("final_m{}", i)));
+    }
+
+    if query_ir.ratio_to_parent.is_some() {
+        cols.push("ratio_to_parent".to_owned());
+    }
+
+    cols
+}
+
+/// The final sql can only take a sort and limit directly if nothing else
+/// wraps it.
+fn can_push_down_limit(query_ir: &QueryIr) -> bool {
+    query_ir.top.is_none() &&
+        query_ir.rca.is_none() &&
+        query_ir.growth.is_none() &&
+        query_ir.rate.is_none() &&
+        query_ir.ratio_to_parent.is_none() &&
+        query_ir.filters.is_empty() &&
+        !query_ir.sparse
+}
@@ -0,0 +1,178 @@
+use itertools::join;
+
+use super::aggregator::{agg_sql_string_pass_1, agg_sql_string_pass_2};
+use super::PinotDialect;
+use tesseract_core::SqlDialect;
+use tesseract_core::query_ir::{Escaping, column_alias, dim_subquery, quote_identifier};
+use super::{
+    TableSql,
+    CutSql,
+    DrilldownSql,
+    MeasureSql,
+    HiddenDrilldownSql,
+};
+
+
+/// Error checking is done before this point. This string formatter
+/// accepts any input
+///
+/// Same two passes as the ClickHouse backend: the fact table is aggregated
+/// on the inline drill columns and the dim foreign keys, then joined to
+/// each dim subquery and rolled up on the drill aliases. All dims are
+/// joined to the fact subquery in a single select; each dim subquery
+/// carries its own foreign key, so the join conditions are qualified.
+pub fn primary_agg(
+    table: &TableSql,
+    cuts: &[CutSql],
+    drills: &[DrilldownSql],
+    meas: &[MeasureSql],
+    hidden_drills: Option<&[HiddenDrilldownSql]>,
+    ) -> (String, String)
+{
+    let ext_drills: Vec<_> = drills.iter()
+        .filter(|d| d.inline_table.is_some() || d.table.name != table.name)
+        .collect();
+
+    let inline_drills: Vec<_> = drills.iter()
+        .filter(|d| d.inline_table.is_none() && d.table.name == table.name)
+        .collect();
+
+    let ext_cuts: Vec<_> = cuts.iter()
+        .filter(|c| c.table.name != table.name || c.inline_table.is_some())
+        .collect();
+
+    let inline_cuts: Vec<_> = cuts.iter()
+        .filter(|c| c.table.name == table.name && c.inline_table.is_none())
+        .collect();
+
+    // Fact table scan. Hidden drilldowns (for grouped median) are grouped on
+    // here, but not projected past the fact subquery.
+    let hidden_drills = hidden_drills.unwrap_or(&[]);
+
+    let mut fact_dim_cols: Vec<(String, String)> = inline_drills.iter()
+        .flat_map(|d| drill_columns(d))
+        .collect();
+
+    for drill in &ext_drills {
+        if !fact_dim_cols.iter().any(|(_, alias)| *alias == drill.foreign_key) {
+            fact_dim_cols.push((drill.foreign_key.clone(), drill.foreign_key.clone()));
+        }
+    }
+
+    fact_dim_cols.extend(hidden_drills.iter().flat_map(|d| drill_columns(&d.drilldown_sql)));
+
+    let mea_cols = meas.iter().enumerate()
+        .map(|(i, m)| agg_sql_string_pass_1(&m.column, &m.aggregator, m.filter.as_ref().map(|f| f.as_str()), i));
+
+    let fact_select = fact_dim_cols.iter()
+        .map(|(expr, alias)| {
+            if expr == alias { expr.clone() } else { format!("{} as {}", expr, alias) }
+        })
+        .chain(mea_cols);
+
+    let mut fact_sql = format!("select {} from {}",
+        join(fact_select, ", "),
+        quote_identifier(&table.name, Escaping::Standard),
+    );
+
+    let cut_clauses: Vec<_> = inline_cuts.iter()
+        .map(|c| PinotDialect.cut(c))
+        .chain(ext_cuts.iter().map(|c| ext_cut_sql_string(c)))
+        .collect();
+
+    if !cut_clauses.is_empty() {
+        fact_sql.push_str(&format!(" where {}", join(cut_clauses, " and ")));
+    }
+
+    if !fact_dim_cols.is_empty() {
+        fact_sql.push_str(&format!(" group by {}", join(fact_dim_cols.iter().map(|(expr, _)| expr), ", ")));
+    }
+
+    // Join the dims to the fact subquery
+    let mut from_sql = format!("({}) as fact", fact_sql);
+
+    for (i, drill) in ext_drills.iter().enumerate() {
+        let subquery = dim_subquery(Some(drill), None, Escaping::Standard);
+
+        from_sql.push_str(&format!(" inner join ({}) as dim_{i} on fact.{fk} = dim_{i}.{fk}",
+            subquery.sql,
+            i=i,
+            fk=subquery.foreign_key,
+        ));
+    }
+
+    // Finally, wrap with final agg and result
+    let final_drill_cols = join(drills.iter().map(|drill| drill.col_alias_only_string()), ", ");
+
+    let final_mea_cols = meas.iter().enumerate()
+        .map(|(i, mea)| agg_sql_string_pass_2(&mea.aggregator, i));
+
+    let final_sql = if final_drill_cols.is_empty() {
+        format!("select {} from {}", join(final_mea_cols, ", "), from_sql)
+    } else {
+        format!("select {}, {} from {} group by {}",
+            final_drill_cols,
+            join(final_mea_cols, ", "),
+            from_sql,
+            final_drill_cols,
+        )
+    };
+
+    (final_sql, final_drill_cols)
+}
+
+/// Columns selected for a drilldown, as (expression, alias). Properties
+/// keep their column name.
+fn drill_columns(drill: &DrilldownSql) -> Vec<(String, String)> {
+    let mut cols = vec![];
+
+    for l in &drill.level_columns {
+        cols.push((l.key_column.clone(), format!("{}_{}", column_alias(&l.key_column), drill.alias_postfix)));
+
+        if let Some(ref name_col) = l.name_column {
+            cols.push((name_col.clone(), format!("{}_{}", column_alias(name_col), drill.alias_postfix)));
+        }
+    }
+
+    cols.extend(drill.property_columns.iter().map(|p| (p.clone(), p.clone())));
+
+    cols
+}
+
+/// Cut on a dim table, as a semi-join on the fact table's foreign key. Also
+/// cuts directly on the fact table's column when the level declares one.
+fn ext_cut_sql_string(cut: &CutSql) -> String {
+    let cut_table = match &cut.inline_table {
+        Some(it) => format!("({}) as {}", it.sql_string(), cut.table.name),
+        None => cut.table.full_name_escaped(Escaping::Standard),
+    };
+
+    let dim_cut = if cut.members.is_empty() {
+        // this case is for default hierarchy
+        // in multiple hierarchies
+        format!("{} in (select {} from {})",
+            cut.foreign_key,
+            cut.primary_key,
+            cut_table,
+        )
+    } else {
+        format!("{} in (select {} from {} where {})",
+            cut.foreign_key,
+            cut.primary_key,
+            cut_table,
+            PinotDialect.cut(cut),
+        )
+    };
+
+    match &cut.fact_column {
+        Some(fact_column) if !cut.for_match && !cut.members.is_empty() => {
+            format!("{} and {} {} ({})",
+                dim_cut,
+                fact_column,
+                cut.mask_sql_in_string(),
+                cut.members_string_escaped(Escaping::Standard),
+            )
+        },
+        _ => dim_cut,
+    }
+}
@@ -0,0 +1,65 @@
+use itertools::join;
+
+use super::{
+    TableSql,
+    CutSql,
+    DrilldownSql,
+    MeasureSql,
+    RateSql,
+};
+
+use crate::sql::primary_agg::primary_agg;
+
+use tesseract_core::{Aggregator};
+use tesseract_core::query_ir::{Escaping, member_literal};
+
+
+/// The rate is the share of the measure for the rate level's members, so
+/// the measure is first aggregated with a drilldown on the rate level, then
+/// rolled back up to the original drilldowns with a conditional aggregate.
+pub fn rate_calculation(
+    table: &TableSql,
+    cuts: &[CutSql],
+    drills: &[DrilldownSql],
+    meas: &[MeasureSql],
+    rate: &RateSql
+) -> (String, String)
+{
+    // Add a drilldown on the level we are getting the rate for
+    let mut new_drills: Vec<DrilldownSql> = drills.iter()
+        .filter(|drill| *drill != &rate.drilldown_sql)
+        .cloned()
+        .collect();
+    new_drills.push(rate.drilldown_sql.clone());
+
+    let (final_sql, _) = primary_agg(table, cuts, &new_drills, meas, None);
+
+    let original_drill_cols = join(drills.iter().map(|drill| drill.col_alias_only_string()), ", ");
+
+    let rate_aggregator = match meas[0].aggregator {
+        Aggregator::Count => "count",
+        _ => "sum",
+    };
+
+    let rate_drill_col = &rate.drilldown_sql.col_alias_only_vec()[0];
+
+    let rate_cols = format!("{agg}(final_m0) as final_m0, \
+        cast({agg}(case when {col} in ({members}) then final_m0 end) as double) / {agg}(final_m0) as rate",
+        agg = rate_aggregator,
+        col = rate_drill_col,
+        members = join(rate.members.iter().map(|m| member_literal(m, Escaping::Standard)), ", "),
+    );
+
+    let rate_sql = if original_drill_cols.is_empty() {
+        format!("select {} from ({}) as rate_base", rate_cols, final_sql)
+    } else {
+        format!("select {}, {} from ({}) as rate_base group by {}",
+            original_drill_cols,
+            rate_cols,
+            final_sql,
+            original_drill_cols,
+        )
+    };
+
+    (rate_sql, original_drill_cols)
+}
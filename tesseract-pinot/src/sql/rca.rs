@@ -0,0 +1,118 @@
+//! RCA is `(a/b) / (c/d)`, where
+//! - a: (each drill_1, each drill_2)
+//! - b: (all drill_1, each drill_2)
+//! - c: (each drill_1, all drill_2)
+//! - d: (all drill_1, all drill_2)
+//!
+//! all within the query's other drilldowns. Only a and b are scanned; c
+//! and d are window sums over a and b, and the two are then joined on the
+//! b drilldowns.
+
+use itertools::join;
+
+use crate::sql::primary_agg::primary_agg;
+use super::{
+    TableSql,
+    CutSql,
+    DrilldownSql,
+    MeasureSql,
+    RcaSql,
+};
+
+pub fn calculate(
+    table: &TableSql,
+    cuts: &[CutSql],
+    drills: &[DrilldownSql],
+    meas: &[MeasureSql],
+    rca: &RcaSql,
+    ) -> (String, String)
+{
+    let mut a_drills = drills.to_vec();
+    let mut b_drills = drills.to_vec();
+    let mut c_drills = drills.to_vec();
+
+    a_drills.extend_from_slice(&rca.drill_1);
+    a_drills.extend_from_slice(&rca.drill_2);
+
+    b_drills.extend_from_slice(&rca.drill_2);
+
+    c_drills.extend_from_slice(&rca.drill_1);
+
+    // the rca measure goes first, so it's always final_m0
+    let all_meas = {
+        let mut temp = vec![rca.mea.clone()];
+        temp.extend_from_slice(meas);
+        temp
+    };
+
+    // a and c can be cut on drill_1, b and d can't be cut on either rca
+    // drill. Parents of the rca drills are not filtered, because they are
+    // meant to limit the rca calculation space.
+    let ac_cut_cols_blacklist: Vec<_> = rca.drill_2.iter()
+        .flat_map(|d| d.level_columns.iter().map(|l| l.key_column.clone()))
+        .collect();
+
+    let bd_cut_cols_blacklist: Vec<_> = rca.drill_1.iter().chain(rca.drill_2.iter())
+        .flat_map(|d| d.level_columns.iter().map(|l| l.key_column.clone()))
+        .collect();
+
+    let ac_cuts: Vec<_> = cuts.iter()
+        .filter(|cut| !ac_cut_cols_blacklist.contains(&cut.column))
+        .cloned()
+        .collect();
+
+    let bd_cuts: Vec<_> = cuts.iter()
+        .filter(|cut| !bd_cut_cols_blacklist.contains(&cut.column))
+        .cloned()
+        .collect();
+
+    let (a, a_final_drills) = primary_agg(table, &ac_cuts, &a_drills, &all_meas, None);
+    let (b, b_final_drills) = primary_agg(table, &bd_cuts, &b_drills, &[rca.mea.clone()], None);
+
+    let ext_meas = join((1..=meas.len()).map(|i| format!("final_m{}", i)), ", ");
+
+    let ac = format!("select {}, final_m0 as a, sum(final_m0) over ({}) as c{}{} from ({}) as rca_a",
+        a_final_drills,
+        partition_sql(&c_drills),
+        if ext_meas.is_empty() { "" } else { ", " },
+        ext_meas,
+        a,
+    );
+
+    let bd = format!("select {}, final_m0 as b, sum(final_m0) over ({}) as d from ({}) as rca_b",
+        b_final_drills,
+        partition_sql(drills),
+        b,
+    );
+
+    let join_on = split_cols(&b_final_drills).into_iter()
+        .map(|col| format!("ac.{0} = bd.{0}", col));
+
+    let final_drill_cols = split_cols(&a_final_drills).into_iter()
+        .map(|col| format!("ac.{}", col));
+
+    let final_sql = format!("select {}, {}(cast(a as double) / b) / (cast(c as double) / d) as rca{}{} \
+        from ({}) as ac inner join ({}) as bd on {}",
+        join(final_drill_cols, ", "),
+        if rca.debug { "a, b, c, d, " } else { "" },
+        if ext_meas.is_empty() { "" } else { ", " },
+        ext_meas,
+        ac,
+        bd,
+        join(join_on, " and "),
+    );
+
+    (final_sql, a_final_drills)
+}
+
+fn partition_sql(drills: &[DrilldownSql]) -> String {
+    if drills.is_empty() {
+        "".to_owned()
+    } else {
+        format!("partition by {}", join(drills.iter().map(|d| d.col_alias_only_string()), ", "))
+    }
+}
+
+fn split_cols(cols: &str) -> Vec<&str> {
+    cols.split(", ").filter(|col| !col.is_empty()).collect()
+}
@@ -0,0 +1,232 @@
+//! Python bindings for tesseract-core, exposed as the `tesseract_core`
+//! python module.
+//!
+//! Gives notebooks access to the same schema, query and sql generation
+//! as the server, and returns query results as pandas DataFrames.
+
+use pyo3::exceptions::ValueError;
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+use std::convert::TryFrom;
+
+use tesseract_clickhouse::Clickhouse;
+use ts_core::{
+    ColumnData,
+    DataFrame,
+    Engine as TsEngine,
+    Query as TsQuery,
+    Schema as TsSchema,
+};
+use ts_core::query::QueryJson;
+
+
+fn to_py_err(err: failure::Error) -> PyErr {
+    ValueError::py_err(err.to_string())
+}
+
+
+/// A tesseract schema, read from a JSON or XML string.
+#[pyclass]
+#[derive(Clone)]
+struct Schema {
+    inner: TsSchema,
+}
+
+#[pymethods]
+impl Schema {
+    #[staticmethod]
+    fn from_json(raw_schema: &str) -> PyResult<Self> {
+        let mut schema = TsSchema::from_json(raw_schema).map_err(to_py_err)?;
+        schema.validate().map_err(to_py_err)?;
+
+        Ok(Schema { inner: schema })
+    }
+
+    #[staticmethod]
+    fn from_xml(raw_schema: &str) -> PyResult<Self> {
+        let mut schema = TsSchema::from_xml(raw_schema).map_err(to_py_err)?;
+        schema.validate().map_err(to_py_err)?;
+
+        Ok(Schema { inner: schema })
+    }
+
+    fn cube_names(&self) -> Vec<String> {
+        self.inner.cubes.iter()
+            .map(|cube| cube.name.clone())
+            .collect()
+    }
+
+    /// Schema metadata, as returned by the `/cubes` endpoint (JSON string).
+    fn metadata(&self) -> PyResult<String> {
+        serde_json::to_string(&self.inner.metadata())
+            .map_err(|err| ValueError::py_err(err.to_string()))
+    }
+}
+
+
+/// A tesseract query. Parts use the same string formats as the
+/// aggregate endpoint's query params.
+#[pyclass]
+#[derive(Clone)]
+struct Query {
+    inner: TsQuery,
+}
+
+#[pymethods]
+impl Query {
+    #[new]
+    #[args(
+        drilldowns="Vec::new()",
+        property_drilldowns="Vec::new()",
+        cuts="Vec::new()",
+        measures="Vec::new()",
+        properties="Vec::new()",
+        filters="Vec::new()",
+        parents="false",
+        top="None",
+        top_other="false",
+        top_where="None",
+        sort="None",
+        limit="None",
+        growth="None",
+        rca="None",
+        rate="None",
+        ratio_to_parent="None",
+        round="None",
+    )]
+    fn new(
+        drilldowns: Vec<String>,
+        property_drilldowns: Vec<String>,
+        cuts: Vec<String>,
+        measures: Vec<String>,
+        properties: Vec<String>,
+        filters: Vec<String>,
+        parents: bool,
+        top: Option<String>,
+        top_other: bool,
+        top_where: Option<String>,
+        sort: Option<String>,
+        limit: Option<u64>,
+        growth: Option<String>,
+        rca: Option<String>,
+        rate: Option<String>,
+        ratio_to_parent: Option<String>,
+        round: Option<u32>,
+    ) -> PyResult<Self>
+    {
+        // built like the queries of the wasm and C bindings
+        let query_json = QueryJson {
+            drilldowns,
+            property_drilldowns,
+            cuts,
+            measures,
+            properties,
+            filters,
+            parents,
+            top,
+            top_other,
+            top_where,
+            sort,
+            limit,
+            growth,
+            rca,
+            rate,
+            ratio_to_parent,
+            round,
+        };
+        let query = TsQuery::try_from(query_json).map_err(to_py_err)?;
+
+        Ok(Query { inner: query })
+    }
+}
+
+
+/// Runs queries against a ClickHouse database.
+#[pyclass]
+struct Engine {
+    inner: TsEngine,
+}
+
+#[pymethods]
+impl Engine {
+    #[new]
+    fn new(schema: PyRef<Schema>, database_url: &str) -> PyResult<Self> {
+        let backend = Clickhouse::from_url(database_url).map_err(to_py_err)?;
+        let engine = TsEngine::new(schema.inner.clone(), Box::new(backend))
+            .map_err(to_py_err)?;
+
+        Ok(Engine { inner: engine })
+    }
+
+    /// Returns the generated sql and the headers of the columns it returns.
+    fn sql(&self, cube: &str, query: PyRef<Query>) -> PyResult<(String, Vec<String>)> {
+        self.inner.sql(cube, &query.inner).map_err(to_py_err)
+    }
+
+    /// Executes the query and returns the result as a pandas DataFrame.
+    fn execute(&self, py: Python, cube: &str, query: PyRef<Query>) -> PyResult<PyObject> {
+        let engine = &self.inner;
+        let query = &query.inner;
+
+        let df = py.allow_threads(|| engine.execute_blocking(cube, query))
+            .map_err(to_py_err)?;
+
+        dataframe_to_pandas(py, df)
+    }
+}
+
+
+fn dataframe_to_pandas(py: Python, df: DataFrame) -> PyResult<PyObject> {
+    let data = PyDict::new(py);
+    let mut columns = vec![];
+
+    for col in df.columns {
+        data.set_item(&col.name, column_to_py(py, col.column_data))?;
+        columns.push(col.name);
+    }
+
+    let kwargs = PyDict::new(py);
+    kwargs.set_item("columns", columns)?;
+
+    let pandas = py.import("pandas")?;
+    let pandas_df = pandas.call("DataFrame", (data,), Some(kwargs))?;
+
+    Ok(pandas_df.to_object(py))
+}
+
+fn column_to_py(py: Python, column_data: ColumnData) -> PyObject {
+    match column_data {
+        ColumnData::Int8(v) => v.to_object(py),
+        ColumnData::Int16(v) => v.to_object(py),
+        ColumnData::Int32(v) => v.to_object(py),
+        ColumnData::Int64(v) => v.to_object(py),
+        ColumnData::UInt8(v) => v.to_object(py),
+        ColumnData::UInt16(v) => v.to_object(py),
+        ColumnData::UInt32(v) => v.to_object(py),
+        ColumnData::UInt64(v) => v.to_object(py),
+        ColumnData::Float32(v) => v.to_object(py),
+        ColumnData::Float64(v) => v.to_object(py),
+        ColumnData::Text(v) => v.to_object(py),
+        ColumnData::NullableInt8(v) => v.to_object(py),
+        ColumnData::NullableInt16(v) => v.to_object(py),
+        ColumnData::NullableInt32(v) => v.to_object(py),
+        ColumnData::NullableInt64(v) => v.to_object(py),
+        ColumnData::NullableUInt8(v) => v.to_object(py),
+        ColumnData::NullableUInt16(v) => v.to_object(py),
+        ColumnData::NullableUInt32(v) => v.to_object(py),
+        ColumnData::NullableUInt64(v) => v.to_object(py),
+        ColumnData::NullableFloat32(v) => v.to_object(py),
+        ColumnData::NullableFloat64(v) => v.to_object(py),
+        ColumnData::NullableText(v) => v.to_object(py),
+    }
+}
+
+
+#[pymodule]
+fn tesseract_core(_py: Python, m: &PyModule) -> PyResult<()> {
+    m.add_class::<Schema>()?;
+    m.add_class::<Query>()?;
+    m.add_class::<Engine>()?;
+
+    Ok(())
+}
@@ -0,0 +1,58 @@
+import pytest
+
+import tesseract_core as ts
+
+SCHEMA = """{
+    "name": "test",
+    "cubes": [{
+        "name": "sales",
+        "table": { "name": "sales" },
+        "dimensions": [{
+            "name": "Year",
+            "foreign_key": "year",
+            "hierarchies": [{
+                "name": "Year",
+                "primary_key": "year",
+                "levels": [{ "name": "Year", "key_column": "year" }]
+            }]
+        }],
+        "measures": [
+            { "name": "Quantity", "column": "quantity", "aggregator": "sum" },
+            { "name": "Price", "column": "price", "aggregator": "avg", "precision": 2 }
+        ]
+    }]
+}"""
+
+
+@pytest.fixture
+def engine():
+    schema = ts.Schema.from_json(SCHEMA)
+    # sql generation doesn't connect to the database
+    return ts.Engine(schema, "default@127.0.0.1:9000/default")
+
+
+def test_sql(engine):
+    query = ts.Query(
+        drilldowns=["Year.Year"],
+        cuts=["Year.Year.Year.2018,2019"],
+        measures=["Quantity", "Price"],
+        round=1,
+    )
+
+    sql, headers = engine.sql("sales", query)
+
+    assert "sum(quantity)" in sql
+    assert "2018" in sql
+    assert headers == ["Year", "Quantity", "Price"]
+
+
+def test_invalid_query():
+    with pytest.raises(ValueError, match="drilldown"):
+        ts.Query(drilldowns=["Year.Year.Year.Year"], measures=["Quantity"])
+
+
+def test_unknown_cube(engine):
+    query = ts.Query(drilldowns=["Year.Year"], measures=["Quantity"])
+
+    with pytest.raises(ValueError):
+        engine.sql("sale", query)
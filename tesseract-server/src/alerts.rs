@@ -0,0 +1,272 @@
+//! Alerts: saved queries evaluated on a schedule, POSTing to a webhook when
+//! a measure crosses a threshold.
+//!
+//! Alerts are listed in the json file at `TESSERACT_ALERTS_FILEPATH`:
+//!
+//! ```json
+//! {
+//!     "alerts": [
+//!         {
+//!             "name": "exports_drop",
+//!             "query": "monthly_exports",
+//!             "measure": "Trade Value",
+//!             "value": "change",
+//!             "comparison": "lt",
+//!             "threshold": -20,
+//!             "webhook": "https://hooks.example.com/alerts",
+//!             "schedule": "0 0 6 * * *"
+//!         }
+//!     ]
+//! }
+//! ```
+//!
+//! `query` is the name of a saved query (see `saved_queries`), looked up on
+//! each run. The measure's value in the last row of the results is
+//! compared to `threshold`, or with `"value": "change"`, its percent change
+//! from the row before. `comparison` is one of `eq`, `neq`, `lt`, `lte`,
+//! `gt` and `gte`, and `schedule` is a cron expression (with seconds).
+
+use actix::{Actor, Arbiter, AsyncContext, Context};
+use chrono::Utc;
+use cron::Schedule;
+use failure::{Error, bail, format_err};
+use futures::future::{self, Future};
+use log::*;
+use serde_derive::Deserialize;
+use serde_json::json;
+use std::fs;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tesseract_core::{Backend, DataFrame, Schema};
+use tesseract_core::masking::MaskingPlan;
+use tesseract_core::query::Comparison;
+
+use crate::clickhouse_settings::ClickhouseSettings;
+use crate::db_config::Database;
+use crate::extracts::parse_query;
+use crate::freshness::{parse_schedule, post_webhook};
+use crate::saved_queries::SavedQueries;
+
+
+#[derive(Debug, Clone, Deserialize)]
+struct AlertsConfigJson {
+    alerts: Vec<AlertConfigJson>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct AlertConfigJson {
+    name: String,
+    query: String,
+    measure: String,
+    value: Option<String>,
+    comparison: String,
+    threshold: f64,
+    webhook: String,
+    schedule: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct Alert {
+    pub name: String,
+    /// Name of the saved query
+    pub query: String,
+    pub measure: String,
+    pub value: AlertValue,
+    pub comparison: Comparison,
+    pub threshold: f64,
+    pub webhook: String,
+    pub schedule: Schedule,
+}
+
+/// What an alert compares to its threshold.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AlertValue {
+    /// The measure in the last row
+    Latest,
+    /// The percent change of the measure from the second to last row to
+    /// the last row
+    Change,
+}
+
+impl Alert {
+    /// The value compared to the threshold, `None` if the results don't
+    /// have enough rows or the values are null.
+    fn value(&self, df: &DataFrame) -> Result<Option<f64>, Error> {
+        let column = df.columns.iter()
+            .find(|col| col.name == self.measure)
+            .ok_or_else(|| format_err!("measure {} is not in the results", self.measure))?;
+        let values = column.column_data.to_nullable_f64()
+            .ok_or_else(|| format_err!("measure {} is not numeric", self.measure))?;
+
+        let mut last = values.iter().rev();
+
+        let value = match self.value {
+            AlertValue::Latest => last.next().cloned().and_then(|v| v),
+            AlertValue::Change => {
+                match (last.next(), last.next()) {
+                    (Some(Some(latest)), Some(Some(previous))) if *previous != 0.0 => {
+                        Some((latest - previous) / previous.abs() * 100.0)
+                    },
+                    _ => None,
+                }
+            },
+        };
+
+        Ok(value)
+    }
+
+    fn is_triggered(&self, value: f64) -> bool {
+        match self.comparison {
+            Comparison::Equal => value == self.threshold,
+            Comparison::NotEqual => value != self.threshold,
+            Comparison::LessThan => value < self.threshold,
+            Comparison::LessThanOrEqual => value <= self.threshold,
+            Comparison::GreaterThan => value > self.threshold,
+            Comparison::GreaterThanOrEqual => value >= self.threshold,
+        }
+    }
+
+    fn payload(&self, value: f64) -> String {
+        json!({
+            "alert": self.name,
+            "query": self.query,
+            "measure": self.measure,
+            "value": value,
+            "comparison": self.comparison.sql_string(),
+            "threshold": self.threshold,
+        }).to_string()
+    }
+
+    /// Time until the next scheduled run.
+    fn next_delay(&self) -> Option<Duration> {
+        let next = self.schedule.upcoming(Utc).next()?;
+        let delay = next.signed_duration_since(Utc::now());
+
+        Some(delay.to_std().unwrap_or(Duration::from_secs(0)))
+    }
+}
+
+/// Reads the alerts config file.
+pub fn read_config(path: &str) -> Result<Vec<Alert>, Error> {
+    let raw = fs::read_to_string(path)?;
+    let config: AlertsConfigJson = serde_json::from_str(&raw)?;
+
+    let mut alerts: Vec<Alert> = vec![];
+
+    for alert in config.alerts {
+        if alerts.iter().any(|a| a.name == alert.name) {
+            bail!("Duplicate alert name {}", alert.name);
+        }
+
+        let value = match alert.value.as_ref().map(|v| v.as_str()) {
+            None | Some("latest") => AlertValue::Latest,
+            Some("change") => AlertValue::Change,
+            Some(value) => bail!("Alert {}: value must be latest or change, not {}", alert.name, value),
+        };
+        let comparison = alert.comparison.parse::<Comparison>()
+            .map_err(|err| format_err!("Alert {}: {}", alert.name, err))?;
+        let schedule = parse_schedule(&alert.schedule)
+            .map_err(|err| format_err!("Alert {}: invalid schedule: {}", alert.name, err))?;
+
+        alerts.push(Alert {
+            name: alert.name,
+            query: alert.query,
+            measure: alert.measure,
+            value,
+            comparison,
+            threshold: alert.threshold,
+            webhook: alert.webhook,
+            schedule,
+        });
+    }
+
+    Ok(alerts)
+}
+
+/// Evaluates each alert on its schedule.
+pub struct AlertScheduler {
+    pub alerts: Vec<Alert>,
+    pub backend: Box<dyn Backend + Sync + Send>,
+    pub db_type: Database,
+    pub clickhouse_settings: ClickhouseSettings,
+    pub schema: Arc<RwLock<Schema>>,
+    pub saved_queries: Arc<RwLock<SavedQueries>>,
+}
+
+impl Actor for AlertScheduler {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        for i in 0..self.alerts.len() {
+            self.schedule(i, ctx);
+        }
+    }
+}
+
+impl AlertScheduler {
+    fn schedule(&self, i: usize, ctx: &mut Context<Self>) {
+        let delay = match self.alerts[i].next_delay() {
+            Some(delay) => delay,
+            None => return,
+        };
+
+        ctx.run_later(delay, move |act, ctx| {
+            act.run(i);
+            act.schedule(i, ctx);
+        });
+    }
+
+    fn run(&self, i: usize) {
+        let alert = self.alerts[i].clone();
+        debug!("Evaluating alert {}", alert.name);
+
+        let sql_headers = self.generate_sql(&alert);
+        let fut: Box<Future<Item=(), Error=Error>> = match sql_headers {
+            Ok((sql, headers, masking_plan)) => Box::new(
+                self.backend.exec_sql(sql)
+                    .and_then(move |mut df| {
+                        df.rename_columns(&headers)?;
+                        if let Some(ref plan) = masking_plan {
+                            plan.apply(&mut df)?;
+                        }
+
+                        match alert.value(&df)? {
+                            Some(value) if alert.is_triggered(value) => {
+                                info!("Alert {} triggered with {}", alert.name, value);
+                                post_webhook(&alert.webhook, alert.payload(value));
+                            },
+                            _ => (),
+                        }
+                        Ok(())
+                    })
+            ),
+            Err(err) => Box::new(future::err(err)),
+        };
+
+        let name = self.alerts[i].name.clone();
+        Arbiter::spawn(fut.map_err(move |err| {
+            error!("Alert {} failed: {}", name, err);
+        }));
+    }
+
+    fn generate_sql(&self, alert: &Alert) -> Result<(String, Vec<String>, Option<MaskingPlan>), Error> {
+        let saved_query = self.saved_queries.read().unwrap()
+            .get(&alert.query)
+            .cloned()
+            .ok_or_else(|| format_err!("saved query {} not found", alert.query))?;
+
+        let mut query = parse_query(&saved_query.query)?;
+        query.role = saved_query.role.clone();
+        let schema = self.schema.read().unwrap();
+        let masking_plan = schema.masking_plan(&saved_query.cube, &mut query)?;
+        let (query_ir, headers) = schema.sql_query(&saved_query.cube, &query)?;
+        let sql = self.backend.generate_sql(query_ir);
+
+        let sql = match self.db_type {
+            Database::Clickhouse => self.clickhouse_settings.apply(sql),
+            _ => sql,
+        };
+
+        Ok((sql, headers, masking_plan))
+    }
+}
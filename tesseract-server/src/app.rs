@@ -6,14 +6,27 @@ use actix_web::{
 };
 use tesseract_core::{Backend, Schema, CubeHasUniqueLevelsAndProperties};
 
+use crate::clickhouse_settings::ClickhouseSettings;
+use crate::conversion::ConversionRates;
 use crate::db_config::Database;
+use crate::extracts::Extract;
 use crate::handlers::{
     aggregate_handler,
     aggregate_default_handler,
     aggregate_stream_handler,
     aggregate_stream_default_handler,
+    dictionary_handler,
+    dictionary_default_handler,
+    distribution_handler,
+    distribution_default_handler,
+    drillthrough_handler,
+    drillthrough_default_handler,
+    explain_handler,
+    extracts_handler,
     logic_layer_default_handler,
     logic_layer_handler,
+    logic_layer_stream_default_handler,
+    logic_layer_stream_handler,
     logic_layer_non_unique_levels_handler,
     logic_layer_non_unique_levels_default_handler,
     logic_layer_members_handler,
@@ -24,8 +37,25 @@ use crate::handlers::{
     metadata_all_handler,
     members_handler,
     members_default_handler,
+    members_stream_handler,
+    members_stream_default_handler,
+    property_members_handler,
+    property_members_default_handler,
+    saved_queries_handler,
+    saved_query_handler,
+    save_query_handler,
+    delete_query_handler,
+    run_saved_query_handler,
+    run_saved_query_default_handler,
 };
 use crate::logic_layer::{Cache, LogicLayerConfig};
+use crate::metadata_cache::MetadataCache;
+use crate::query_hints::QueryPriority;
+use crate::query_limiter::QueryLimiter;
+use crate::saved_queries::SavedQueries;
+use crate::single_flight::SingleFlight;
+use crate::slow_query_log::SlowQueryLog;
+use crate::sql_cache::SqlCache;
 
 use std::sync::{Arc, RwLock};
 use url::Url;
@@ -46,6 +76,22 @@ pub struct EnvVars {
     pub geoservice_url: Option<Url>,
     pub schema_source: SchemaSource,
     pub flush_secret: Option<String>,
+    pub saved_queries_secret: Option<String>,
+    /// Request header with the role of the principal, for the schema's
+    /// row filters. Only set it behind a proxy that sets the header.
+    pub role_header: Option<String>,
+    pub clickhouse_settings: ClickhouseSettings,
+    /// Highest priority a query can ask for with the `priority` hint
+    pub max_query_priority: QueryPriority,
+    pub strict_params: bool,
+    /// Reloaded on a schedule, see `conversion::RatesRefresher`
+    pub conversion_rates: Option<Arc<RwLock<ConversionRates>>>,
+    pub max_estimated_rows: Option<u64>,
+    /// Max number of cut combinations a logic layer query can expand into
+    pub logic_layer_max_combinations: usize,
+    /// Max number of a logic layer query's cut combinations running at once
+    pub logic_layer_concurrency: usize,
+    pub extracts: Vec<Extract>,
 }
 
 /// Holds [ActixWeb State](https://actix.rs/docs/application/).
@@ -58,6 +104,12 @@ pub struct AppState {
     pub env_vars: EnvVars,
     pub schema: Arc<RwLock<Schema>>,
     pub cache: Arc<RwLock<Cache>>,
+    pub sql_cache: Arc<RwLock<SqlCache>>,
+    pub metadata_cache: Arc<RwLock<MetadataCache>>,
+    pub saved_queries: Arc<RwLock<SavedQueries>>,
+    pub query_limiter: QueryLimiter,
+    pub single_flight: SingleFlight,
+    pub slow_query_log: Option<SlowQueryLog>,
     pub logic_layer_config: Option<Arc<RwLock<LogicLayerConfig>>>,
     // TODO is there a way to acces this that's not through state? Tried using closures to
     // capture, but the handlers need to implement Fn, not FnOnce (which happens once capturing
@@ -73,6 +125,12 @@ pub fn create_app(
         env_vars: EnvVars,
         schema: Arc<RwLock<Schema>>,
         cache: Arc<RwLock<Cache>>,
+        sql_cache: Arc<RwLock<SqlCache>>,
+        metadata_cache: Arc<RwLock<MetadataCache>>,
+        saved_queries: Arc<RwLock<SavedQueries>>,
+        query_limiter: QueryLimiter,
+        single_flight: SingleFlight,
+        slow_query_log: Option<SlowQueryLog>,
         logic_layer_config: Option<Arc<RwLock<LogicLayerConfig>>>,
         streaming_response: bool,
         has_unique_levels_properties: CubeHasUniqueLevelsAndProperties,
@@ -86,6 +144,12 @@ pub fn create_app(
                 env_vars,
                 schema,
                 cache,
+                sql_cache,
+                metadata_cache,
+                saved_queries,
+                query_limiter,
+                single_flight,
+                slow_query_log,
                 logic_layer_config,
                 has_unique_levels_properties: has_unique_levels_properties.clone(),
         })
@@ -103,11 +167,53 @@ pub fn create_app(
         })
 
         // Helpers
-        .resource("/cubes/{cube}/members", |r| {
-            r.method(Method::GET).with(members_default_handler)
+        .resource("/cubes/{cube}/aggregate/explain", |r| {
+            r.method(Method::GET).with(explain_handler)
         })
-        .resource("/cubes/{cube}/members.{format}", |r| {
-            r.method(Method::GET).with(members_handler)
+        .resource("/cubes/{cube}/dictionary", |r| {
+            r.method(Method::GET).with(dictionary_default_handler)
+        })
+        .resource("/cubes/{cube}/dictionary.{format}", |r| {
+            r.method(Method::GET).with(dictionary_handler)
+        })
+        .resource("/cubes/{cube}/distribution", |r| {
+            r.method(Method::GET).with(distribution_default_handler)
+        })
+        .resource("/cubes/{cube}/distribution.{format}", |r| {
+            r.method(Method::GET).with(distribution_handler)
+        })
+        .resource("/cubes/{cube}/drillthrough", |r| {
+            r.method(Method::GET).with(drillthrough_default_handler)
+        })
+        .resource("/cubes/{cube}/drillthrough.{format}", |r| {
+            r.method(Method::GET).with(drillthrough_handler)
+        })
+
+        .resource("/cubes/{cube}/property-members", |r| {
+            r.method(Method::GET).with(property_members_default_handler)
+        })
+        .resource("/cubes/{cube}/property-members.{format}", |r| {
+            r.method(Method::GET).with(property_members_handler)
+        })
+
+        .resource("/extracts/{name}", |r| {
+            r.method(Method::GET).with(extracts_handler)
+        })
+
+        // Saved queries
+        .resource("/queries", |r| {
+            r.method(Method::GET).with(saved_queries_handler)
+        })
+        .resource("/queries/{name}", |r| {
+            r.method(Method::GET).with(saved_query_handler);
+            r.method(Method::PUT).with(save_query_handler);
+            r.method(Method::DELETE).with(delete_query_handler)
+        })
+        .resource("/queries/{name}/run", |r| {
+            r.method(Method::GET).with(run_saved_query_default_handler)
+        })
+        .resource("/queries/{name}/run.{format}", |r| {
+            r.method(Method::GET).with(run_saved_query_handler)
         })
 
         .resource("/flush", |r| {
@@ -124,6 +230,12 @@ pub fn create_app(
             .resource("/cubes/{cube}/aggregate.{format}", |r| {
                 r.method(Method::GET).with(aggregate_stream_handler)
             })
+            .resource("/cubes/{cube}/members", |r| {
+                r.method(Method::GET).with(members_stream_default_handler)
+            })
+            .resource("/cubes/{cube}/members.{format}", |r| {
+                r.method(Method::GET).with(members_stream_handler)
+            })
     } else {
         app
             .resource("/cubes/{cube}/aggregate", |r| {
@@ -132,18 +244,36 @@ pub fn create_app(
             .resource("/cubes/{cube}/aggregate.{format}", |r| {
                 r.method(Method::GET).with(aggregate_handler)
             })
+            .resource("/cubes/{cube}/members", |r| {
+                r.method(Method::GET).with(members_default_handler)
+            })
+            .resource("/cubes/{cube}/members.{format}", |r| {
+                r.method(Method::GET).with(members_handler)
+            })
     };
 
     match has_unique_levels_properties {
         CubeHasUniqueLevelsAndProperties::True => {
             // Logic Layer
+            let app = if streaming_response {
+                app
+                    .resource("/data", |r| {
+                        r.method(Method::GET).with(logic_layer_stream_default_handler)
+                    })
+                    .resource("/data.{format}", |r| {
+                        r.method(Method::GET).with(logic_layer_stream_handler)
+                    })
+            } else {
+                app
+                    .resource("/data", |r| {
+                        r.method(Method::GET).with(logic_layer_default_handler)
+                    })
+                    .resource("/data.{format}", |r| {
+                        r.method(Method::GET).with(logic_layer_handler)
+                    })
+            };
+
             app
-                .resource("/data", |r| {
-                    r.method(Method::GET).with(logic_layer_default_handler)
-                })
-                .resource("/data.{format}", |r| {
-                    r.method(Method::GET).with(logic_layer_handler)
-                })
                 .resource("/members", |r| {
                     r.method(Method::GET).with(logic_layer_members_default_handler)
                 })
@@ -170,3 +300,123 @@ pub fn create_app(
     }
 
 }
+
+
+/// A test server for handler tests, over the schema fixtures of
+/// tesseract-test and a `MockBackend`.
+#[cfg(test)]
+pub(crate) mod test {
+    use actix_web::test::TestServer;
+    use failure::Error;
+    use std::thread;
+    use tesseract_test::MockBackend;
+
+    use super::*;
+    use crate::logic_layer::populate_cache;
+    use crate::query_limiter::QueryLimits;
+    use crate::sql_cache::DEFAULT_SQL_CACHE_SIZE;
+
+    /// Env vars with every optional feature off.
+    pub(crate) fn env_vars() -> EnvVars {
+        EnvVars {
+            database_url: "mock".to_owned(),
+            geoservice_url: None,
+            schema_source: SchemaSource::LocalSchema { filepath: "schema.json".to_owned() },
+            flush_secret: None,
+            saved_queries_secret: None,
+            role_header: None,
+            clickhouse_settings: ClickhouseSettings::default(),
+            max_query_priority: QueryPriority::High,
+            strict_params: true,
+            conversion_rates: None,
+            max_estimated_rows: None,
+            logic_layer_max_combinations: crate::logic_layer::DEFAULT_MAX_CUT_COMBINATIONS,
+            logic_layer_concurrency: crate::logic_layer::DEFAULT_CUT_COMBINATION_CONCURRENCY,
+            extracts: vec![],
+        }
+    }
+
+    /// Serves `schema`, with queries answered by `backend`. The logic layer
+    /// cache is populated from `tesseract_test::cache_backend`, so `schema`
+    /// should have the hierarchies of `tesseract_test::SCHEMA`.
+    pub(crate) fn test_server(schema: Schema, backend: MockBackend, env_vars: EnvVars) -> TestServer {
+        server(schema, backend, env_vars, Database::Postgres, false)
+    }
+
+    /// Like `test_server`, with streaming aggregate and members responses.
+    pub(crate) fn test_stream_server(schema: Schema, backend: MockBackend, env_vars: EnvVars) -> TestServer {
+        server(schema, backend, env_vars, Database::Postgres, true)
+    }
+
+    /// Like `test_server`, for handling that depends on the database being
+    /// ClickHouse. The sql is still generated by `backend`.
+    pub(crate) fn test_clickhouse_server(schema: Schema, backend: MockBackend, env_vars: EnvVars) -> TestServer {
+        server(schema, backend, env_vars, Database::Clickhouse, false)
+    }
+
+    /// The logic layer cache of `schema`, populated from `backend`.
+    pub(crate) fn test_cache(schema: &Schema, backend: MockBackend) -> Result<Cache, Error> {
+        // the cache is populated on its own actix system, like at startup
+        let schema = schema.clone();
+        thread::spawn(move || {
+            let mut sys = actix::System::new("tesseract-test-cache");
+            populate_cache(schema, &None, Box::new(backend), &mut sys)
+        })
+            .join()
+            .expect("cache population doesn't panic")
+    }
+
+    fn server(schema: Schema, backend: MockBackend, env_vars: EnvVars, db_type: Database, streaming_response: bool) -> TestServer {
+        let cache = test_cache(&schema, tesseract_test::cache_backend())
+            .expect("cache populated from the cache backend");
+
+        let has_unique_levels_properties = schema.has_unique_levels_properties();
+        let backend: Box<dyn Backend + Sync + Send> = Box::new(backend);
+        let schema = Arc::new(RwLock::new(schema));
+        let cache = Arc::new(RwLock::new(cache));
+        let sql_cache = Arc::new(RwLock::new(SqlCache::new(DEFAULT_SQL_CACHE_SIZE)));
+        let metadata_cache = Arc::new(RwLock::new(MetadataCache::new()));
+        let saved_queries = Arc::new(RwLock::new(SavedQueries::load(None).expect("no saved queries")));
+        let query_limiter = QueryLimiter::new(QueryLimits::default());
+        let single_flight = SingleFlight::new();
+
+        TestServer::with_factory(move || create_app(
+            false,
+            backend.clone(),
+            db_type.clone(),
+            env_vars.clone(),
+            schema.clone(),
+            cache.clone(),
+            sql_cache.clone(),
+            metadata_cache.clone(),
+            saved_queries.clone(),
+            query_limiter.clone(),
+            single_flight.clone(),
+            None,
+            None,
+            streaming_response,
+            has_unique_levels_properties.clone(),
+        ))
+    }
+
+    /// Sends a GET request to the server, with `headers`, and returns the
+    /// status and body of the response.
+    pub(crate) fn get(srv: &mut TestServer, path: &str, headers: &[(&str, &str)]) -> (u16, String) {
+        send(srv, Method::GET, path, headers, "")
+    }
+
+    /// Like `get`, for any method and with a request body.
+    pub(crate) fn send(srv: &mut TestServer, method: Method, path: &str, headers: &[(&str, &str)], body: &str) -> (u16, String) {
+        let mut request = srv.client(method, path);
+        for (name, value) in headers {
+            request.header(*name, *value);
+        }
+        let request = request.body(body.to_owned()).expect("valid test request");
+
+        let response = srv.execute(request.send()).expect("test server responds");
+        let status = response.status().as_u16();
+        let body = srv.execute(response.body()).expect("test server response body");
+
+        (status, String::from_utf8_lossy(&body).into_owned())
+    }
+}
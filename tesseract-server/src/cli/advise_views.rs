@@ -0,0 +1,273 @@
+use failure::{Error, format_err};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use structopt::StructOpt;
+use tesseract_core::{Aggregator, Cube, Schema};
+use tesseract_core::names::LevelName;
+use tesseract_core::query_ir::{Escaping, column_alias, quote_identifier};
+
+use crate::schema_config;
+use crate::slow_query_log::{SlowQuery, read_log};
+
+
+#[derive(Debug, StructOpt)]
+pub struct AdviseViewsOpt {
+    #[structopt(long="schema")]
+    pub schema: String,
+
+    /// Slow query log, as written to `TESSERACT_SLOW_QUERY_LOG_FILEPATH`
+    #[structopt(long="log")]
+    pub log: String,
+
+    /// Only suggest query shapes logged at least this many times
+    #[structopt(long="min-count", default_value="2")]
+    pub min_count: usize,
+
+    #[structopt(long="max-suggestions", default_value="10")]
+    pub max_suggestions: usize,
+
+    /// File to write the DDL to. Defaults to stdout.
+    #[structopt(long="output")]
+    pub output: Option<String>,
+}
+
+/// Slow queries on the same cube and levels (drilldowns and cut levels),
+/// which one aggregate table can serve.
+#[derive(Debug)]
+struct Suggestion {
+    cube: String,
+    levels: Vec<String>,
+    /// All the measures asked for by the queries
+    measures: Vec<String>,
+    count: usize,
+    total_ms: u64,
+}
+
+/// Aggregates the slow query log by query shape, and writes ClickHouse DDL
+/// for an aggregate table (and the materialized view keeping it up to
+/// date) for each shape that recurs, the most time spent first.
+///
+/// The tables are `SummingMergeTree`s grouped by the fact table columns
+/// the levels read (the foreign keys of dimension tables, or the level
+/// columns of dimensions on the fact table), so only sum and count
+/// measures can be kept; counts are then summed. To use a table, add a
+/// cube on it with the same dimensions and measures.
+pub fn run(opt: AdviseViewsOpt) -> Result<(), Error> {
+    let schema = schema_config::read_schema(&opt.schema)?;
+    let queries = read_log(Path::new(&opt.log))?;
+
+    let suggestions = suggest(&queries, opt.min_count, opt.max_suggestions);
+
+    let mut out = String::new();
+
+    if suggestions.is_empty() {
+        out.push_str(&format!("-- No query shape in the log was slow at least {} times\n", opt.min_count));
+    }
+
+    for suggestion in &suggestions {
+        out.push_str(&format!("-- {}: {} slow queries, {} ms in total\n",
+            suggestion.cube,
+            suggestion.count,
+            suggestion.total_ms,
+        ));
+        out.push_str(&format!("-- levels: {}\n", suggestion.levels.join(", ")));
+
+        match aggregate_table_ddl(&schema, suggestion) {
+            Ok(ddl) => out.push_str(&ddl),
+            Err(err) => out.push_str(&format!("-- skipped: {}\n", err)),
+        }
+
+        out.push('\n');
+    }
+
+    match opt.output {
+        Some(path) => fs::write(&path, out)
+            .map_err(|err| format_err!("Could not write {}: {}", path, err))?,
+        None => print!("{}", out),
+    }
+
+    Ok(())
+}
+
+fn suggest(queries: &[SlowQuery], min_count: usize, max_suggestions: usize) -> Vec<Suggestion> {
+    let mut shapes: BTreeMap<(String, Vec<String>), Suggestion> = BTreeMap::new();
+
+    for query in queries {
+        let mut levels: Vec<String> = query.drilldowns.iter()
+            .chain(query.cuts.iter())
+            .cloned()
+            .collect();
+        levels.sort();
+        levels.dedup();
+
+        let suggestion = shapes.entry((query.cube.clone(), levels.clone()))
+            .or_insert_with(|| Suggestion {
+                cube: query.cube.clone(),
+                levels,
+                measures: vec![],
+                count: 0,
+                total_ms: 0,
+            });
+
+        for measure in &query.measures {
+            if !suggestion.measures.contains(measure) {
+                suggestion.measures.push(measure.clone());
+            }
+        }
+        suggestion.count += 1;
+        suggestion.total_ms += query.duration_ms;
+    }
+
+    let mut suggestions: Vec<_> = shapes.into_iter()
+        .map(|(_, suggestion)| suggestion)
+        .filter(|suggestion| suggestion.count >= min_count)
+        .collect();
+
+    suggestions.sort_by(|a, b| b.total_ms.cmp(&a.total_ms));
+    suggestions.truncate(max_suggestions);
+
+    suggestions
+}
+
+fn aggregate_table_ddl(schema: &Schema, suggestion: &Suggestion) -> Result<String, Error> {
+    let cube = schema.get_cube_by_name(&suggestion.cube)?;
+
+    let mut group_cols: Vec<String> = vec![];
+    for level in &suggestion.levels {
+        for col in level_fact_columns(cube, level)? {
+            if !group_cols.contains(&col) {
+                group_cols.push(col);
+            }
+        }
+    }
+
+    if group_cols.is_empty() {
+        return Err(format_err!("the queries have no drilldowns or cuts"));
+    }
+
+    let mut out = String::new();
+    let mut mea_cols: Vec<(String, String)> = vec![];
+
+    for name in &suggestion.measures {
+        let measure = match cube.measures.iter().find(|m| m.name == *name) {
+            Some(measure) => measure,
+            None => {
+                out.push_str(&format!("-- measure {} is not in the cube\n", name));
+                continue;
+            },
+        };
+
+        let col = match (&measure.aggregator, &measure.filter) {
+            (Aggregator::Sum, None) => {
+                let alias = if is_plain_identifier(&measure.column) {
+                    measure.column.clone()
+                } else {
+                    column_alias(&measure.name).to_lowercase()
+                };
+                (format!("sum({})", measure.column), alias)
+            },
+            (Aggregator::Count, None) => {
+                let count = if measure.column == "*" {
+                    "count()".to_owned()
+                } else {
+                    format!("count({})", measure.column)
+                };
+                (count, column_alias(&measure.name).to_lowercase())
+            },
+            _ => {
+                out.push_str(&format!("-- measure {} can't be kept in a SummingMergeTree, only unfiltered sums and counts can\n", name));
+                continue;
+            },
+        };
+
+        if !mea_cols.iter().any(|(_, alias)| *alias == col.1) {
+            mea_cols.push(col);
+        }
+    }
+
+    if mea_cols.is_empty() {
+        out.push_str("-- skipped: none of the measures can be pre-aggregated\n");
+        return Ok(out);
+    }
+
+    let table_name = format!("{}_by_{}",
+        cube.table.name,
+        group_cols.iter().map(|c| column_alias(c)).collect::<Vec<_>>().join("_"),
+    );
+    // in the fact table's database
+    let qualified = |name: &str| match cube.table.schema {
+        Some(ref db) => format!("{}.{}", quote_identifier(db, Escaping::Backslash), quote_identifier(name, Escaping::Backslash)),
+        None => quote_identifier(name, Escaping::Backslash),
+    };
+    let table = qualified(&table_name);
+    let view = qualified(&format!("{}_mv", table_name));
+
+    let select_sql = format!("SELECT {}, {} FROM {} GROUP BY {}",
+        group_cols.join(", "),
+        mea_cols.iter().map(|(expr, alias)| format!("{} AS {}", expr, alias)).collect::<Vec<_>>().join(", "),
+        cube.table.full_name(),
+        group_cols.join(", "),
+    );
+
+    out.push_str(&format!("CREATE TABLE {}\nENGINE = SummingMergeTree\nORDER BY ({})\nAS {};\n",
+        table,
+        group_cols.join(", "),
+        select_sql,
+    ));
+    out.push_str("-- create the view before the next load of the fact table; rows loaded in between are missed\n");
+    out.push_str(&format!("CREATE MATERIALIZED VIEW {} TO {}\nAS {};\n",
+        view,
+        table,
+        select_sql,
+    ));
+
+    Ok(out)
+}
+
+/// Fact table columns needed to drill down or cut on a level: the foreign
+/// key for dimension tables, or the columns of the level and its parents
+/// for dimensions on the fact table.
+fn level_fact_columns(cube: &Cube, level: &str) -> Result<Vec<String>, Error> {
+    let level_name: LevelName = level.parse()?;
+
+    let dimension = cube.get_dimension(&level_name)
+        .ok_or_else(|| format_err!("level {} is not in cube {}", level, cube.name))?;
+    let hierarchy = cube.get_hierarchy(&level_name)
+        .ok_or_else(|| format_err!("level {} is not in cube {}", level, cube.name))?;
+
+    let on_fact_table = hierarchy.inline_table.is_none() &&
+        hierarchy.table.as_ref().map(|t| t.name == cube.table.name).unwrap_or(true);
+
+    if !on_fact_table {
+        return dimension.foreign_key
+            .map(|fk| vec![fk])
+            .ok_or_else(|| format_err!("dimension {} has no foreign key", dimension.name));
+    }
+
+    let mut cols = vec![];
+
+    for l in &hierarchy.levels {
+        // generated time levels and level expressions are computed from the
+        // hierarchy's column, which is then kept instead
+        if l.time_grain.is_some() || !is_plain_identifier(&l.key_column) {
+            cols.push(hierarchy.primary_key.clone());
+        } else {
+            cols.push(l.key_column.clone());
+        }
+
+        cols.extend(l.name_column.iter().filter(|c| is_plain_identifier(c)).cloned());
+        cols.extend(l.properties.iter().flatten().map(|p| p.column.clone()).filter(|c| is_plain_identifier(c)));
+
+        if l.name == level_name.level {
+            break;
+        }
+    }
+
+    Ok(cols)
+}
+
+fn is_plain_identifier(col: &str) -> bool {
+    col.chars().next().map(|c| c.is_ascii_alphabetic() || c == '_').unwrap_or(false) &&
+        col.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
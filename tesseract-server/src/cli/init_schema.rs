@@ -0,0 +1,262 @@
+use failure::{Error, format_err};
+use serde_json::{json, Value};
+use std::fs;
+use structopt::StructOpt;
+use tesseract_core::query_ir::{Escaping, quote_identifier, quote_string};
+
+use crate::db_config::{self, Database};
+
+
+#[derive(Debug, StructOpt)]
+pub struct InitSchemaOpt {
+    #[structopt(long="db-url")]
+    pub database_url: String,
+
+    /// Fact table, optionally qualified with a database/schema name
+    #[structopt(long="fact-table")]
+    pub fact_table: String,
+
+    /// Text columns with at most this many distinct values become
+    /// dimensions
+    #[structopt(long="max-members", default_value="1000")]
+    pub max_members: u64,
+
+    /// File to write the schema to. Defaults to stdout.
+    #[structopt(long="output")]
+    pub output: Option<String>,
+}
+
+/// Introspects the fact table and writes a starter schema, to be edited
+/// by hand:
+/// - `<x>_id` columns become dimensions, using `<x>_name` or `<x>` as the
+///   name column when there is one.
+/// - other numeric columns become measures, with a sum aggregator.
+/// - text columns with few distinct values become dimensions.
+///
+/// All dimensions are on the fact table; move them to dimension tables
+/// as needed.
+pub fn run(opt: InitSchemaOpt) -> Result<(), Error> {
+    let (db, _, db_type) = db_config::get_db(&opt.database_url)?;
+    let mut sys = actix::System::new("tesseract-init-schema");
+
+    let columns_sql = introspection_sql(&db_type, &opt.fact_table)?;
+    let df = sys.block_on(db.exec_sql(columns_sql))
+        .map_err(|err| format_err!("Could not introspect table {}: {}", opt.fact_table, err))?;
+
+    if df.columns.len() < 2 || df.len() == 0 {
+        return Err(format_err!("No columns found for table {}", opt.fact_table));
+    }
+
+    let columns: Vec<(String, String)> = df.columns[0].stringify_column_data().into_iter()
+        .zip(df.columns[1].stringify_column_data())
+        .collect();
+
+    let is_column = |name: &str| columns.iter().any(|(c, _)| c == name);
+    let mut used = vec![];
+    let mut dimensions = vec![];
+    let mut measures = vec![];
+
+    // id + name column pairs
+    for (column, column_type) in &columns {
+        if !(column.ends_with("_id") && is_numeric(column_type)) {
+            continue;
+        }
+
+        let base = column.trim_end_matches("_id");
+        let name_column = vec![format!("{}_name", base), base.to_owned()].into_iter()
+            .find(|c| is_column(c));
+
+        dimensions.push(dimension(base, column, name_column.as_ref().map(|c| c.as_str()), false));
+        used.push(column.clone());
+        used.extend(name_column);
+    }
+
+    for (column, column_type) in &columns {
+        if used.contains(column) {
+            continue;
+        }
+
+        if is_numeric(column_type) {
+            measures.push(json!({
+                "name": title_case(column),
+                "column": column,
+                "aggregator": "sum",
+            }));
+        } else {
+            let count_sql = distinct_count_sql(&db_type, &opt.fact_table, column);
+            let count = sys.block_on(db.exec_sql(count_sql))
+                .ok()
+                .and_then(|df| df.columns.get(0).map(|c| c.stringify_column_data()))
+                .and_then(|values| values.get(0).and_then(|v| v.parse::<u64>().ok()));
+
+            if let Some(count) = count {
+                if count <= opt.max_members {
+                    dimensions.push(dimension(column, column, None, true));
+                }
+            }
+        }
+    }
+
+    let table_name = opt.fact_table.rsplit('.').next().unwrap_or(&opt.fact_table);
+
+    let schema = json!({
+        "name": table_name,
+        "cubes": [{
+            "name": table_name,
+            "table": { "name": opt.fact_table },
+            "dimensions": dimensions,
+            "measures": measures,
+        }],
+    });
+
+    let schema = serde_json::to_string_pretty(&schema)?;
+
+    match opt.output {
+        Some(ref output) => {
+            fs::write(output, schema)
+                .map_err(|err| format_err!("Could not write to {}: {}", output, err))?;
+        },
+        None => println!("{}", schema),
+    }
+
+    Ok(())
+}
+
+/// Sql listing (column name, column type) for a table.
+fn introspection_sql(db_type: &Database, table: &str) -> Result<String, Error> {
+    let (database, table) = match table.rfind('.') {
+        Some(idx) => (Some(&table[..idx]), &table[idx+1..]),
+        None => (None, table),
+    };
+
+    let sql = match db_type {
+        Database::Clickhouse => {
+            let database = database
+                .map(|d| quote_string(d, Escaping::Backslash))
+                .unwrap_or_else(|| "currentDatabase()".to_owned());
+            format!("select name, type from system.columns where database = {} and table = {} order by position",
+                database,
+                quote_string(table, Escaping::Backslash),
+            )
+        },
+        Database::MySql => {
+            let database = database
+                .map(|d| quote_string(d, Escaping::Backslash))
+                .unwrap_or_else(|| "database()".to_owned());
+            format!("select column_name, data_type from information_schema.columns \
+                where table_schema = {} and table_name = {} order by ordinal_position",
+                database,
+                quote_string(table, Escaping::Backslash),
+            )
+        },
+        Database::Postgres => {
+            let database = database
+                .map(|d| quote_string(d, Escaping::Standard))
+                .unwrap_or_else(|| "current_schema()".to_owned());
+            format!("select column_name, data_type from information_schema.columns \
+                where table_schema = {} and table_name = {} order by ordinal_position",
+                database,
+                quote_string(table, Escaping::Standard),
+            )
+        },
+        Database::Pinot => {
+            // Pinot has no information_schema; its table schemas are only
+            // available from the controller api.
+            return Err(format_err!("init-schema is not supported for Pinot"));
+        },
+    };
+
+    Ok(sql)
+}
+
+/// Sql counting the distinct values of a column, with the table and column
+/// names quoted for the database.
+fn distinct_count_sql(db_type: &Database, table: &str, column: &str) -> String {
+    let escaping = match db_type {
+        Database::Clickhouse | Database::MySql => Escaping::Backslash,
+        Database::Postgres | Database::Pinot => Escaping::Standard,
+    };
+
+    format!("select count(distinct {}) from {}",
+        quote_identifier(column, escaping),
+        quote_identifier(table, escaping),
+    )
+}
+
+fn dimension(name: &str, key_column: &str, name_column: Option<&str>, text_key: bool) -> Value {
+    let name = title_case(name);
+
+    let mut level = json!({
+        "name": name,
+        "key_column": key_column,
+    });
+    if let Some(name_column) = name_column {
+        level["name_column"] = json!(name_column);
+    }
+    if text_key {
+        level["key_type"] = json!("text");
+    }
+
+    json!({
+        "name": name,
+        "foreign_key": key_column,
+        "hierarchies": [{
+            "name": name,
+            "primary_key": key_column,
+            "levels": [level],
+        }],
+    })
+}
+
+fn is_numeric(column_type: &str) -> bool {
+    let column_type = column_type.to_lowercase();
+    // ClickHouse wraps types, e.g. Nullable(Int32)
+    let column_type = column_type
+        .trim_start_matches("nullable(")
+        .trim_start_matches("lowcardinality(");
+
+    ["int", "uint", "float", "decimal", "numeric", "double", "real", "smallint", "bigint", "tinyint", "mediumint"]
+        .iter()
+        .any(|t| column_type.starts_with(t))
+}
+
+/// `product_category` -> `Product Category`
+fn title_case(column: &str) -> String {
+    column.split('_')
+        .filter(|word| !word.is_empty())
+        .map(|word| {
+            let mut chars = word.chars();
+            match chars.next() {
+                Some(c) => c.to_uppercase().chain(chars).collect(),
+                None => String::new(),
+            }
+        })
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn introspection_sql_keeps_column_order() {
+        assert_eq!(
+            introspection_sql(&Database::Clickhouse, "db.sales").unwrap(),
+            "select name, type from system.columns where database = 'db' and table = 'sales' order by position",
+        );
+    }
+
+    #[test]
+    fn distinct_count_sql_quotes_names() {
+        assert_eq!(
+            distinct_count_sql(&Database::Clickhouse, "db.sales", "product id"),
+            "select count(distinct `product id`) from db.sales",
+        );
+        assert_eq!(
+            distinct_count_sql(&Database::Postgres, "sales", "product id"),
+            "select count(distinct \"product id\") from sales",
+        );
+    }
+}
@@ -0,0 +1,54 @@
+//! Subcommands that run without starting the server, for use in CI and
+//! batch jobs.
+
+mod advise_views;
+mod init_schema;
+mod query;
+mod validate;
+
+use failure::Error;
+use structopt::StructOpt;
+
+
+#[derive(Debug, StructOpt)]
+pub enum Command {
+    /// Validates a schema, and checks that its columns exist when a
+    /// database url is given. Exits non-zero if there are any problems.
+    #[structopt(name="validate")]
+    Validate {
+        #[structopt(long="schema")]
+        schema: String,
+
+        #[structopt(long="db-url")]
+        database_url: Option<String>,
+
+        #[structopt(long="logic-layer-config")]
+        logic_layer_config: Option<String>,
+    },
+
+    /// Runs a query and writes the results to a file (or stdout), for
+    /// batch jobs.
+    #[structopt(name="query")]
+    Query(query::QueryOpt),
+
+    /// Introspects a fact table and writes a starter schema, guessing
+    /// measures and dimensions from its columns.
+    #[structopt(name="init-schema")]
+    InitSchema(init_schema::InitSchemaOpt),
+
+    /// Aggregates the slow query log, and suggests ClickHouse aggregate
+    /// tables for the query shapes that recur, as DDL.
+    #[structopt(name="advise-views")]
+    AdviseViews(advise_views::AdviseViewsOpt),
+}
+
+pub fn run(command: Command) -> Result<(), Error> {
+    match command {
+        Command::Validate { schema, database_url, logic_layer_config } => {
+            validate::run(&schema, database_url.as_ref(), logic_layer_config.as_ref())
+        },
+        Command::Query(opt) => query::run(opt),
+        Command::InitSchema(opt) => init_schema::run(opt),
+        Command::AdviseViews(opt) => advise_views::run(opt),
+    }
+}
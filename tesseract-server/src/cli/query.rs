@@ -0,0 +1,108 @@
+use failure::{Error, format_err};
+use std::fs;
+use std::io::{self, Write};
+use structopt::StructOpt;
+use tesseract_core::format::{format_records_bytes, FormatType};
+use tesseract_core::{Backend, Engine, Query};
+
+use crate::db_config;
+use crate::schema_config;
+
+
+/// Query options from the command line. Repeat a flag to pass several
+/// values (e.g. `--measures Quantity --measures Price`), since cut members
+/// are already comma separated.
+#[derive(Debug, StructOpt)]
+pub struct QueryOpt {
+    #[structopt(long="schema")]
+    pub schema: String,
+
+    #[structopt(long="db-url")]
+    pub database_url: String,
+
+    #[structopt(long="cube")]
+    pub cube: String,
+
+    #[structopt(long="drilldowns", number_of_values=1)]
+    pub drilldowns: Vec<String>,
+
+    #[structopt(long="cuts", number_of_values=1)]
+    pub cuts: Vec<String>,
+
+    #[structopt(long="measures", number_of_values=1)]
+    pub measures: Vec<String>,
+
+    #[structopt(long="properties", number_of_values=1)]
+    pub properties: Vec<String>,
+
+    #[structopt(long="parents")]
+    pub parents: bool,
+
+    #[structopt(long="sparse")]
+    pub sparse: bool,
+
+    /// csv, jsonrecords, jsonarrays or orc. Defaults to jsonrecords for
+    /// a `.json` output file, orc for a `.orc` output file, and csv
+    /// otherwise.
+    #[structopt(long="format")]
+    pub format: Option<String>,
+
+    /// File to write the results to. Defaults to stdout.
+    #[structopt(long="output")]
+    pub output: Option<String>,
+}
+
+/// Runs a query through the full pipeline (sql generation, execution and
+/// formatting) without starting the server.
+pub fn run(opt: QueryOpt) -> Result<(), Error> {
+    let mut schema = schema_config::read_schema(&opt.schema)?;
+    let (db, _, _) = db_config::get_db(&opt.database_url)?;
+    schema.set_time_grain_sql(|grain, column| db.time_grain_sql(grain, column));
+    let engine = Engine::new(schema, db)?;
+
+    let format = match opt.format {
+        Some(ref format) => format.parse()?,
+        None => {
+            match opt.output {
+                Some(ref output) if output.ends_with(".json") => FormatType::JsonRecords,
+                Some(ref output) if output.ends_with(".orc") => FormatType::Orc,
+                _ => FormatType::Csv,
+            }
+        },
+    };
+
+    let mut builder = Query::builder()
+        .parents(opt.parents)
+        .sparse(opt.sparse);
+    for drilldown in &opt.drilldowns {
+        builder = builder.drilldown(drilldown);
+    }
+    for cut in &opt.cuts {
+        builder = builder.cut(cut);
+    }
+    for measure in &opt.measures {
+        builder = builder.measure(measure);
+    }
+    for property in &opt.properties {
+        builder = builder.property(property);
+    }
+    let query = builder.build()?;
+
+    let mut sys = actix::System::new("tesseract-query");
+    let df = sys.block_on(engine.execute(&opt.cube, &query))?;
+
+    let headers = df.column_names();
+    let res = format_records_bytes(&headers, df, format)?;
+
+    match opt.output {
+        Some(ref output) => {
+            fs::write(output, res)
+                .map_err(|err| format_err!("Could not write to {}: {}", output, err))?;
+        },
+        None => {
+            io::stdout().write_all(&res)?;
+        },
+    }
+
+    Ok(())
+}
@@ -0,0 +1,122 @@
+use failure::{Error, bail};
+use std::collections::BTreeSet;
+use tesseract_core::{Backend, CubeHasUniqueLevelsAndProperties, Schema};
+use tesseract_core::schema::lint;
+
+use crate::db_config;
+use crate::logic_layer;
+use crate::schema_config;
+
+
+/// Runs all the schema checks and prints a report of every problem found.
+pub fn run(
+    schema_path: &String,
+    database_url: Option<&String>,
+    logic_layer_config_path: Option<&String>,
+    ) -> Result<(), Error>
+{
+    let mut problems = vec![];
+
+    // a schema that can't be read can't be checked any further
+    let mut schema = schema_config::read_schema(schema_path)?;
+
+    if let Err(err) = schema.validate() {
+        problems.push(format!("{}", err));
+    }
+
+    if let CubeHasUniqueLevelsAndProperties::False { cube, name } = schema.has_unique_levels_properties() {
+        problems.push(format!("Duplicate level/property name {} in cube {}", name, cube));
+    }
+
+    if let Some(config_path) = logic_layer_config_path {
+        let config = logic_layer::read_config(config_path)?;
+
+        if let CubeHasUniqueLevelsAndProperties::False { cube, name } = config.has_unique_levels_properties(&schema)? {
+            problems.push(format!("Logic layer duplicate name {} in cube {}", name, cube));
+        }
+    }
+
+    if let Some(database_url) = database_url {
+        let (db, _, _) = db_config::get_db(database_url)?;
+        let mut sys = actix::System::new("tesseract-validate");
+
+        problems.extend(missing_columns(&schema, db, &mut sys));
+    }
+
+    // lint diagnostics are likely mistakes, but don't fail validation
+    for diagnostic in lint(&schema) {
+        println!("{}: warning: {}", schema_path, diagnostic);
+    }
+
+    if problems.is_empty() {
+        println!("{}: ok", schema_path);
+        return Ok(());
+    }
+
+    for problem in &problems {
+        println!("{}: {}", schema_path, problem);
+    }
+
+    bail!("{} problem(s) found in schema {}", problems.len(), schema_path);
+}
+
+/// Checks every table and column referenced by the schema by selecting it,
+/// one column at a time so that the report names the missing column.
+/// Inline tables are skipped.
+fn missing_columns(
+    schema: &Schema,
+    backend: Box<dyn Backend + Send + Sync>,
+    sys: &mut actix::SystemRunner,
+    ) -> Vec<String>
+{
+    // (table, column)
+    let mut columns = BTreeSet::new();
+
+    for cube in &schema.cubes {
+        let fact_table = cube.table.full_name();
+
+        for measure in &cube.measures {
+            columns.insert((fact_table.clone(), measure.column.clone()));
+        }
+
+        for dimension in &cube.dimensions {
+            if let Some(ref foreign_key) = dimension.foreign_key {
+                columns.insert((fact_table.clone(), foreign_key.clone()));
+            }
+
+            for hierarchy in &dimension.hierarchies {
+                if hierarchy.inline_table.is_some() {
+                    continue;
+                }
+
+                let table = hierarchy.table.as_ref()
+                    .map(|t| t.full_name())
+                    .unwrap_or_else(|| fact_table.clone());
+
+                columns.insert((table.clone(), hierarchy.primary_key.clone()));
+
+                for level in &hierarchy.levels {
+                    columns.insert((table.clone(), level.key_column.clone()));
+
+                    if let Some(ref name_column) = level.name_column {
+                        columns.insert((table.clone(), name_column.clone()));
+                    }
+
+                    for property in level.properties.iter().flatten() {
+                        columns.insert((table.clone(), property.column.clone()));
+                    }
+                }
+            }
+        }
+    }
+
+    columns.into_iter()
+        .filter_map(|(table, column)| {
+            let sql = format!("select {} from {} limit 0", column, table);
+
+            sys.block_on(backend.exec_sql(sql))
+                .err()
+                .map(|err| format!("Column {} not found in table {}: {}", column, table, err))
+        })
+        .collect()
+}
@@ -0,0 +1,136 @@
+//! ClickHouse `SETTINGS` appended to generated sql, to put resource
+//! budgets on queries.
+//!
+//! Default settings come from the `TESSERACT_CLICKHOUSE_SETTINGS` env var,
+//! and can be set per query with the `settings` query param. Both use the
+//! format `max_threads:4,max_execution_time:30`. Only allow-listed settings
+//! are accepted, and a query can't raise a setting above the server's value.
+
+use failure::{Error, bail, format_err};
+use std::collections::BTreeMap;
+
+use crate::query_hints::QueryHints;
+
+
+/// Settings that may be set from the server config or the query.
+pub const ALLOWED_SETTINGS: &[&str] = &[
+    "max_memory_usage",
+    "max_execution_time",
+    "max_threads",
+];
+
+
+#[derive(Debug, Clone, Default)]
+pub struct ClickhouseSettings {
+    settings: BTreeMap<String, u64>,
+}
+
+impl ClickhouseSettings {
+    pub fn parse(raw: &str) -> Result<Self, Error> {
+        let mut settings = BTreeMap::new();
+
+        for setting in raw.split(',').filter(|s| !s.is_empty()) {
+            let kv: Vec<_> = setting.splitn(2, ':').collect();
+
+            if kv.len() != 2 {
+                bail!("Could not parse setting {}, expected name:value", setting);
+            }
+
+            let name = kv[0].trim();
+            if !ALLOWED_SETTINGS.contains(&name) {
+                bail!("Setting {} is not allowed, allowed settings are: {}", name, ALLOWED_SETTINGS.join(", "));
+            }
+
+            let value = kv[1].trim().parse::<u64>()
+                .map_err(|_| format_err!("Could not parse value for setting {}", name))?;
+
+            settings.insert(name.to_owned(), value);
+        }
+
+        Ok(ClickhouseSettings { settings })
+    }
+
+    /// Combines server settings with settings from a query. Query settings
+    /// can only lower the server's values. A value of 0 means unlimited in
+    /// ClickHouse, so it's higher than any other.
+    pub fn with_query_settings(&self, query_settings: &ClickhouseSettings) -> Self {
+        let mut settings = self.settings.clone();
+
+        for (name, value) in &query_settings.settings {
+            let value = match settings.get(name) {
+                Some(&0) => *value,
+                Some(server_value) if *value == 0 => *server_value,
+                Some(server_value) => *server_value.min(value),
+                None => *value,
+            };
+            settings.insert(name.clone(), value);
+        }
+
+        ClickhouseSettings { settings }
+    }
+
+    /// Applies a query's hints: the timeout is a `max_execution_time`, which
+    /// can only lower the server's value, and the priority is set as is.
+    pub fn with_hints(&self, hints: &QueryHints) -> Self {
+        let mut hint_settings = BTreeMap::new();
+        if let Some(timeout) = hints.timeout {
+            hint_settings.insert("max_execution_time".to_owned(), timeout);
+        }
+
+        let mut settings = self.with_query_settings(&ClickhouseSettings { settings: hint_settings });
+        settings.settings.insert("priority".to_owned(), hints.priority.clickhouse_priority());
+
+        settings
+    }
+
+    /// Appends a `SETTINGS` clause to the sql, if there are any settings.
+    pub fn apply(&self, sql: String) -> String {
+        if self.settings.is_empty() {
+            return sql;
+        }
+
+        let settings = self.settings.iter()
+            .map(|(name, value)| format!("{}={}", name, value))
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        format!("{} settings {}", sql, settings)
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn query_settings_lower_server_settings() {
+        let server = ClickhouseSettings::parse("max_threads:4,max_execution_time:30").unwrap();
+        let query = ClickhouseSettings::parse("max_threads:8,max_execution_time:10,max_memory_usage:1000").unwrap();
+
+        assert_eq!(
+            server.with_query_settings(&query).apply("select 1".to_owned()),
+            "select 1 settings max_execution_time=10, max_memory_usage=1000, max_threads=4",
+        );
+    }
+
+    #[test]
+    fn zero_query_settings_keep_server_settings() {
+        let server = ClickhouseSettings::parse("max_threads:4,max_execution_time:30").unwrap();
+        let query = ClickhouseSettings::parse("max_threads:0,max_execution_time:0").unwrap();
+
+        assert_eq!(
+            server.with_query_settings(&query).apply("select 1".to_owned()),
+            "select 1 settings max_execution_time=30, max_threads=4",
+        );
+
+        // unlimited on the server
+        let server = ClickhouseSettings::parse("max_execution_time:0").unwrap();
+        let query = ClickhouseSettings::parse("max_execution_time:10").unwrap();
+
+        assert_eq!(
+            server.with_query_settings(&query).apply("select 1".to_owned()),
+            "select 1 settings max_execution_time=10",
+        );
+    }
+}
@@ -0,0 +1,301 @@
+//! Estimates how many rows an aggregate query can return, before running it,
+//! from the member counts in the logic layer cache (or the levels' declared
+//! `max_cardinality`, for levels that aren't cached): the product of the
+//! number of members of each drilled level, after cuts on that level or its
+//! parent level.
+//!
+//! Queries above `TESSERACT_MAX_ESTIMATED_ROWS` are rejected, so that an
+//! accidental group-by on a few large levels doesn't reach the database,
+//! or truncated with a top, for cubes with an `auto_top`.
+
+use failure::{Error, bail};
+use tesseract_core::names::{Cut, LevelName, Mask};
+use tesseract_core::query::{SortDirection, TopQuery};
+use tesseract_core::schema::Cube;
+use tesseract_core::{Aggregator, MeaOrCalc, Query as TsQuery};
+
+use crate::logic_layer::CubeCache;
+
+
+/// Response header describing the top applied by a cube's `auto_top`.
+pub const AUTO_TOP_HEADER: &str = "X-Tesseract-Auto-Top";
+
+/// Value of the `X-Tesseract-Auto-Top` header, e.g.
+/// `100 per Geography.Geography.State`.
+pub fn auto_top_header_value(top: &TopQuery) -> String {
+    format!("{} per {}", top.n, top.by_dimension)
+}
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RowEstimate {
+    pub rows: u64,
+    /// Estimated number of members for each drilldown. Levels that aren't
+    /// in the cache and have no declared `max_cardinality` are left out.
+    pub levels: Vec<(LevelName, u64)>,
+}
+
+impl RowEstimate {
+    pub fn estimate(cube: &Cube, cube_cache: &CubeCache, query: &TsQuery) -> Self {
+        let levels: Vec<_> = query.drilldowns.iter()
+            .filter(|drill| !drill.0.is_all())
+            .filter_map(|drill| {
+                level_size(cube, cube_cache, &drill.0, &query.cuts)
+                    .map(|size| (drill.0.clone(), size))
+            })
+            .collect();
+
+        let rows = levels.iter()
+            .fold(1u64, |rows, (_, size)| rows.saturating_mul(*size));
+
+        RowEstimate {
+            rows,
+            levels,
+        }
+    }
+
+    /// Estimate for the query truncated by `top`: at most `top.n` rows, and
+    /// the "Other" row, for each member of the top's level.
+    pub fn with_top(&self, top: &TopQuery) -> Self {
+        let by_size = match self.levels.iter().find(|(level_name, _)| *level_name == top.by_dimension) {
+            Some((_, size)) => *size,
+            None => return self.clone(),
+        };
+
+        let rest = self.levels.iter()
+            .filter(|(level_name, _)| *level_name != top.by_dimension)
+            .fold(1u64, |rows, (_, size)| rows.saturating_mul(*size));
+        let per_member = rest.min(top.n.saturating_add(top.other as u64));
+
+        RowEstimate {
+            rows: by_size.saturating_mul(per_member),
+            levels: self.levels.clone(),
+        }
+    }
+
+    /// Errors, explaining where the estimate comes from, if it's above
+    /// `max_rows`.
+    pub fn check(&self, max_rows: u64) -> Result<(), Error> {
+        if self.rows <= max_rows {
+            return Ok(());
+        }
+
+        let levels: Vec<_> = self.levels.iter()
+            .map(|(level_name, size)| format!("{}: {}", level_name, size))
+            .collect();
+
+        bail!("Query could return an estimated {} rows ({}), above the limit of {}. Add cuts or remove drilldowns to narrow it down",
+            self.rows,
+            levels.join(" x "),
+            max_rows,
+        );
+    }
+}
+
+/// Top of `n` rows, by the first measure, for each member of the drilldown
+/// with the fewest estimated members, with an "Other" row for the rest: the
+/// cube's `auto_top` for a query above `max_rows`. `None` if the query is
+/// within the limit, or can't be truncated that way: it needs another
+/// drilldown to keep the top of, sum or count measures for the "Other"
+/// row, and no calculations that can't be combined with it.
+pub fn auto_top(cube: &Cube, estimate: &RowEstimate, query: &TsQuery, max_rows: u64) -> Option<TopQuery> {
+    let n = cube.auto_top?;
+
+    if estimate.rows <= max_rows {
+        return None;
+    }
+
+    let has_calculations = query.top.is_some() || query.growth.is_some() || query.rca.is_some() ||
+        query.rate.is_some() || query.ratio_to_parent.is_some() || query.fill.is_some();
+    if has_calculations || query.drilldowns.len() < 2 || !query.property_drilldowns.is_empty() {
+        return None;
+    }
+
+    let summable = query.measures.iter()
+        .all(|mea| {
+            cube.measures.iter()
+                .find(|m| m.name == mea.0)
+                .map(|m| m.aggregator == Aggregator::Sum || m.aggregator == Aggregator::Count)
+                .unwrap_or(false)
+        });
+    if !summable {
+        return None;
+    }
+
+    let first_measure = query.measures.first()?;
+    let (by_dimension, _) = estimate.levels.iter()
+        .min_by_key(|(_, size)| *size)?;
+
+    let mut top = TopQuery::new(
+        n,
+        by_dimension.clone(),
+        vec![MeaOrCalc::Mea(first_measure.clone())],
+        SortDirection::Desc,
+    );
+    top.other = true;
+
+    Some(top)
+}
+
+/// Number of members of a drilled level, after an include or exclude cut on
+/// the level itself, or an include cut on its parent level.
+fn level_size(cube: &Cube, cube_cache: &CubeCache, level_name: &LevelName, cuts: &[Cut]) -> Option<u64> {
+    let member_count = match cube_cache.find_level_cache(level_name) {
+        Some(level_cache) => level_cache.member_count as u64,
+        // not in the cache: the level's declared max_cardinality, if any
+        None => cube.get_level(level_name)?.max_cardinality?,
+    };
+
+    let level_cut = cuts.iter()
+        .find(|cut| cut.level_name == *level_name && !cut.for_match);
+
+    if let Some(cut) = level_cut {
+        let cut_count = cut.members.len() as u64;
+
+        return Some(match cut.mask {
+            Mask::Include => cut_count,
+            Mask::Exclude => member_count.saturating_sub(cut_count),
+        });
+    }
+
+    let parent_size = cube.get_level_parents(level_name).ok()
+        .and_then(|parents| parents.last().cloned())
+        .and_then(|parent| {
+            let parent_name = LevelName::new(
+                level_name.dimension.clone(),
+                level_name.hierarchy.clone(),
+                parent.name,
+            );
+
+            let parent_cut = cuts.iter()
+                .find(|cut| cut.level_name == parent_name && cut.mask == Mask::Include && !cut.for_match)?;
+            let children_map = cube_cache.find_level_cache(&parent_name)?
+                .children_map.as_ref()?;
+
+            let children = parent_cut.members.iter()
+                .map(|member| children_map.get(member).map(|c| c.len()).unwrap_or(0) as u64)
+                .sum();
+
+            Some(children)
+        });
+
+    Some(parent_size.unwrap_or(member_count))
+}
+
+#[cfg(test)]
+mod test {
+    use crate::app::test::test_cache;
+
+    use super::*;
+
+    /// The sales cube, with an auto top of 5, and its cache.
+    fn sales() -> (Cube, CubeCache) {
+        let schema = tesseract_test::schema();
+        let cache = test_cache(&schema, tesseract_test::cache_backend()).unwrap();
+
+        let mut cube = schema.get_cube_by_name("sales").unwrap().clone();
+        cube.auto_top = Some(5);
+        let cube_cache = cache.find_cube_info(&cube.name).unwrap();
+
+        (cube, cube_cache)
+    }
+
+    fn level(dimension: &str, level: &str) -> LevelName {
+        LevelName::new(dimension, dimension, level)
+    }
+
+    #[test]
+    fn estimate() {
+        let (cube, cube_cache) = sales();
+        let query = TsQuery::builder()
+            .drilldown("Year.Year")
+            .drilldown("Geography.County")
+            .drilldown("Product.Product")
+            .cut("Geography.State.04000US01,04000US02")
+            .cut("~Product.Product.0,1,2")
+            .measure("Quantity")
+            .build()
+            .unwrap();
+
+        let estimate = RowEstimate::estimate(&cube, &cube_cache, &query);
+
+        // each state has a county; products are all but the excluded ones
+        assert_eq!(estimate.levels, vec![
+            (level("Year", "Year"), 10),
+            (level("Geography", "County"), 2),
+            (level("Product", "Product"), 53),
+        ]);
+        assert_eq!(estimate.rows, 1060);
+        assert!(estimate.check(1060).is_ok());
+        assert!(estimate.check(1059).unwrap_err().to_string().contains("1060 rows"));
+    }
+
+    #[test]
+    fn auto_top_by_smallest_level() {
+        let (cube, cube_cache) = sales();
+        let query = TsQuery::builder()
+            .drilldown("Geography.State")
+            .drilldown("Year.Year")
+            .drilldown("Product.Product")
+            .measure("Quantity")
+            .build()
+            .unwrap();
+        let estimate = RowEstimate::estimate(&cube, &cube_cache, &query);
+        assert_eq!(estimate.rows, 56 * 10 * 56);
+
+        let top = auto_top(&cube, &estimate, &query, 1000).unwrap();
+
+        assert_eq!(top.n, 5);
+        assert_eq!(top.by_dimension, level("Year", "Year"));
+        match &top.sort_mea_or_calc[..] {
+            [MeaOrCalc::Mea(measure)] => assert_eq!(measure.0, "Quantity"),
+            sort => panic!("sorted by {:?}", sort),
+        }
+        assert_eq!(top.sort_direction, SortDirection::Desc);
+        assert!(top.other);
+        // the top 5 and "Other" for each year
+        assert_eq!(estimate.with_top(&top).rows, 60);
+
+        assert!(auto_top(&cube, &estimate, &query, estimate.rows).is_none());
+    }
+
+    #[test]
+    fn auto_top_not_applicable() {
+        let (cube, cube_cache) = sales();
+        let queries = vec![
+            // a single drilldown
+            TsQuery::builder().drilldown("Product.Product").measure("Quantity"),
+            // an average
+            TsQuery::builder().drilldown("Year.Year").drilldown("Product.Product").measure("Price"),
+            // a calculation
+            TsQuery::builder().drilldown("Year.Year").drilldown("Product.Product").measure("Quantity")
+                .growth("Year.Year,Quantity"),
+        ];
+
+        for query in queries {
+            let query = query.build().unwrap();
+            let estimate = RowEstimate::estimate(&cube, &cube_cache, &query);
+
+            assert!(auto_top(&cube, &estimate, &query, 10).is_none(), "{:?}", query);
+        }
+    }
+
+    #[test]
+    fn too_large_with_auto_top() {
+        let (cube, cube_cache) = sales();
+        let query = TsQuery::builder()
+            .drilldown("Geography.State")
+            .drilldown("Product.Product")
+            .measure("Quantity")
+            .build()
+            .unwrap();
+        let estimate = RowEstimate::estimate(&cube, &cube_cache, &query);
+
+        let top = auto_top(&cube, &estimate, &query, 100).unwrap();
+
+        // the top 5 and "Other" for each of the 56 states
+        let top_estimate = estimate.with_top(&top);
+        assert_eq!(top_estimate.rows, 56 * 6);
+        assert!(top_estimate.check(100).is_err());
+    }
+}
@@ -0,0 +1,216 @@
+//! Unit and currency conversion of measure values.
+//!
+//! Measures declare their unit with a `currency` (or `unit`) annotation.
+//! Rates come from the `TESSERACT_CONVERSION_RATES` env var, which is either
+//! the path to a json file or the url of an endpoint serving one, in the
+//! format `{ "base": "USD", "rates": { "EUR": 0.92, "MXN": 17.1 } }`, where
+//! each rate is the amount of that unit worth one `base` unit.
+//!
+//! A query with `convert=EUR` then has every measure with a unit converted
+//! to EUR, and the conversions are listed in the `X-Tesseract-Conversion`
+//! response header.
+//!
+//! Rates are read at startup, and reloaded on the cron schedule in
+//! `TESSERACT_CONVERSION_RATES_REFRESH_SCHEDULE`, if set.
+
+use actix::{Actor, Arbiter, AsyncContext, Context, SystemRunner};
+use actix_web::{client, HttpMessage};
+use chrono::Utc;
+use cron::Schedule;
+use failure::{Error, bail, format_err};
+use futures::future::{self, Future};
+use log::*;
+use serde_derive::Deserialize;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+
+/// Response header listing the conversions applied to a response.
+pub const CONVERSION_HEADER: &str = "X-Tesseract-Conversion";
+
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ConversionRates {
+    base: String,
+    rates: HashMap<String, f64>,
+}
+
+impl ConversionRates {
+    pub fn parse(raw: &str) -> Result<Self, Error> {
+        let rates: ConversionRates = serde_json::from_str(raw)?;
+
+        if let Some((unit, _)) = rates.rates.iter().find(|(_, rate)| !(**rate > 0.0)) {
+            bail!("Conversion rate for {} must be positive", unit);
+        }
+
+        Ok(rates)
+    }
+
+    /// Reads rates from a url (fetched with `sys`) or a file path.
+    pub fn load(source: &str, sys: &mut SystemRunner) -> Result<Self, Error> {
+        sys.block_on(Self::fetch(source))
+    }
+
+    /// Reads rates from a url or a file path.
+    pub fn fetch(source: &str) -> Box<dyn Future<Item=Self, Error=Error>> {
+        if !(source.starts_with("http://") || source.starts_with("https://")) {
+            return Box::new(future::result(
+                fs::read_to_string(source)
+                    .map_err(Error::from)
+                    .and_then(|raw| Self::parse(&raw))
+            ));
+        }
+
+        let req = match client::get(source)
+            .header("User-Agent", "Actix-web")
+            .finish()
+        {
+            Ok(req) => req,
+            Err(err) => return Box::new(future::err(format_err!("{}", err))),
+        };
+
+        Box::new(
+            req.send()
+                .map_err(|err| format_err!("{}", err))
+                .and_then(|res| res.body().map_err(|err| format_err!("{}", err)))
+                .and_then(|body| Self::parse(&String::from_utf8(body.to_vec())?))
+        )
+    }
+
+    /// Amount of `to` worth one `from`, if both units have rates.
+    pub fn rate(&self, from: &str, to: &str) -> Option<f64> {
+        let from = from.to_uppercase();
+        let to = to.to_uppercase();
+
+        if from == to {
+            return Some(1.0);
+        }
+
+        let per_base = |unit: &str| {
+            if unit == self.base.to_uppercase() {
+                Some(1.0)
+            } else {
+                self.rates.iter()
+                    .find(|(u, _)| u.to_uppercase() == unit)
+                    .map(|(_, rate)| *rate)
+            }
+        };
+
+        Some(per_base(&to)? / per_base(&from)?)
+    }
+
+    /// Conversions to `target` for `measures`, given the units of the
+    /// cube's measures. Measures without a unit are left alone; a measure
+    /// with a unit that can't be converted is an error.
+    pub fn conversions(
+        &self,
+        units: &HashMap<String, String>,
+        measures: &[String],
+        target: &str,
+    ) -> Result<Vec<Conversion>, Error>
+    {
+        let mut conversions = vec![];
+
+        for measure in measures {
+            let unit = match units.get(measure) {
+                Some(unit) => unit,
+                None => continue,
+            };
+
+            let rate = self.rate(unit, target)
+                .ok_or_else(|| format_err!("No conversion rate from {} to {} for measure {}", unit, target, measure))?;
+
+            conversions.push(Conversion {
+                measure: measure.clone(),
+                from: unit.clone(),
+                to: target.to_uppercase(),
+                rate,
+            });
+        }
+
+        Ok(conversions)
+    }
+}
+
+
+#[derive(Debug, Clone)]
+pub struct Conversion {
+    pub measure: String,
+    pub from: String,
+    pub to: String,
+    pub rate: f64,
+}
+
+impl Conversion {
+    /// Factors to pass to `DataFrame::scale_columns`.
+    pub fn factors(conversions: &[Conversion]) -> HashMap<String, f64> {
+        conversions.iter()
+            .map(|c| (c.measure.clone(), c.rate))
+            .collect()
+    }
+
+    /// Value of the `X-Tesseract-Conversion` header.
+    pub fn header_value(conversions: &[Conversion]) -> String {
+        conversions.iter()
+            .map(|c| c.to_string())
+            .collect::<Vec<_>>()
+            .join("; ")
+    }
+}
+
+impl fmt::Display for Conversion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {} to {} at {}", self.measure, self.from, self.to, self.rate)
+    }
+}
+
+
+/// Reloads the conversion rates on a schedule. If a reload fails, the
+/// previous rates are kept.
+pub struct RatesRefresher {
+    pub schedule: Schedule,
+    pub source: String,
+    pub rates: Arc<RwLock<ConversionRates>>,
+}
+
+impl Actor for RatesRefresher {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        self.schedule_next(ctx);
+    }
+}
+
+impl RatesRefresher {
+    fn schedule_next(&self, ctx: &mut Context<Self>) {
+        let next = match self.schedule.upcoming(Utc).next() {
+            Some(next) => next,
+            None => return,
+        };
+        let delay = next.signed_duration_since(Utc::now())
+            .to_std()
+            .unwrap_or(Duration::from_secs(0));
+
+        ctx.run_later(delay, |act, ctx| {
+            act.refresh();
+            act.schedule_next(ctx);
+        });
+    }
+
+    fn refresh(&self) {
+        let rates = self.rates.clone();
+        let source = self.source.clone();
+
+        Arbiter::spawn(
+            ConversionRates::fetch(&self.source)
+                .map(move |new_rates| {
+                    *rates.write().unwrap() = new_rates;
+                    info!("Conversion rates reloaded");
+                })
+                .map_err(move |err| error!("Reloading conversion rates from {} failed: {}", source, err))
+        );
+    }
+}
@@ -20,6 +20,7 @@ use std::str::FromStr;
 use tesseract_clickhouse::Clickhouse;
 use tesseract_core::Backend;
 use tesseract_mysql::MySql;
+use tesseract_pinot::Pinot;
 use tesseract_postgres::Postgres;
 
 /// from a full url e.g. clickhouse://127.0.0.1:9000 returns
@@ -54,6 +55,12 @@ pub fn get_db(db_url_full: &str) -> Result<(Box<dyn Backend + Send + Sync>, Stri
             Box::new(Postgres::from_addr(&db_url_full)?) as
                 Box<dyn Backend + Send + Sync>
         },
+        Database::Pinot => {
+            // the broker url can have its own scheme,
+            // e.g. pinot://https://broker:8099
+            Box::new(Pinot::from_url(&db_url_full["pinot://".len()..])?) as
+                Box<dyn Backend + Send + Sync>
+        },
     };
 
     // Remove password when there's a user:password@host in the url
@@ -78,6 +85,7 @@ pub enum Database {
     Clickhouse,
     MySql,
     Postgres,
+    Pinot,
 }
 
 impl FromStr for Database {
@@ -88,6 +96,7 @@ impl FromStr for Database {
             "clickhouse" => Ok(Database::Clickhouse),
             "mysql" => Ok(Database::MySql),
             "postgres" => Ok(Database::Postgres),
+            "pinot" => Ok(Database::Pinot),
             _ => Err(format_err!("database {} not supported or not parsed", s)),
         }
     }
@@ -99,6 +108,7 @@ impl fmt::Display for Database {
             Database::Clickhouse => write!(f, "Clickhouse"),
             Database::MySql => write!(f, "MySql"),
             Database::Postgres => write!(f, "Postgres"),
+            Database::Pinot => write!(f, "Pinot"),
         }
     }
 }
@@ -1,9 +1,22 @@
 use actix_web::{
+    http::StatusCode,
     HttpResponse,
 };
-use failure::Fail;
+use failure::{Error, Fail};
+use log::error;
+use serde_derive::Serialize;
+use std::fmt::Display;
+use tesseract_core::QueryErrors;
 
-#[derive(Debug, Fail)]
+/// Errors returned by handlers. Responses are a JSON envelope:
+///
+/// ```json
+/// { "code": "invalid_query", "message": "...", "detail": null, "hint": null }
+/// ```
+///
+/// `code` is stable and meant for machines; `message` is meant for people.
+/// Invalid queries may also list every problem found in `errors`.
+#[derive(Debug, Clone, Fail)]
 pub enum ServerError {
     // the display is shown in the logs as an explanation of the error
     #[fail(display="db internal error")]
@@ -24,17 +37,188 @@ pub enum ServerError {
     #[fail(display="Internal Server Error {}", code)]
     ErrorCode {
         code: String,
+    },
+
+    /// The query couldn't be parsed, or isn't valid for the cube.
+    #[fail(display="{}", cause)]
+    InvalidQuery {
+        cause: String,
+        hint: Option<String>,
+        errors: Vec<String>,
+    },
+
+    /// A cube, level or other named resource doesn't exist.
+    #[fail(display="{}", cause)]
+    NotFound {
+        cause: String,
+        hint: Option<String>,
+    },
+
+    #[fail(display="Unauthorized")]
+    Unauthorized,
+
+    /// The query limiter's queue is full.
+    #[fail(display="{}", cause)]
+    TooManyRequests {
+        cause: String,
+    },
+
+    /// The query could return more rows than the server allows, even once
+    /// truncated.
+    #[fail(display="{}", cause)]
+    TooLarge {
+        cause: String,
+    },
+
+    /// The cause is logged, and not shown to clients.
+    #[fail(display="Internal Server Error")]
+    Internal {
+        cause: String,
+    },
+}
+
+impl ServerError {
+    pub fn invalid_query<E: Display>(err: E) -> Self {
+        ServerError::InvalidQuery { cause: err.to_string(), hint: None, errors: vec![] }
+    }
+
+    pub fn not_found<E: Display>(err: E) -> Self {
+        ServerError::NotFound { cause: err.to_string(), hint: None }
     }
+
+    /// For errors from query processing that returns `failure::Error`:
+    /// a `ServerError` raised along the way is kept, validation errors
+    /// are listed in `errors`, and anything else is treated as an invalid
+    /// query.
+    pub fn from_query_error(err: Error) -> Self {
+        let err = match err.downcast::<ServerError>() {
+            Ok(server_err) => return server_err,
+            Err(err) => err,
+        };
+
+        match err.downcast::<QueryErrors>() {
+            Ok(QueryErrors(errors)) => ServerError::InvalidQuery {
+                cause: "Invalid query".to_owned(),
+                hint: None,
+                errors,
+            },
+            Err(err) => ServerError::invalid_query(err),
+        }
+    }
+
+    /// For errors from running a query on the backend: a `ServerError`
+    /// (e.g. from the query limiter) is kept, anything else is a `Db` error,
+    /// with the cause only shown in debug mode.
+    pub fn from_backend_error(err: Error, debug: bool) -> Self {
+        match err.downcast::<ServerError>() {
+            Ok(server_err) => server_err,
+            Err(err) => {
+                if debug {
+                    ServerError::Db { cause: err.to_string() }
+                } else {
+                    ServerError::Db { cause: "Internal Server Error 1010".to_owned() }
+                }
+            },
+        }
+    }
+
+    /// Adds a hint for fixing the request. Only used by `InvalidQuery`
+    /// and `NotFound`.
+    pub fn with_hint<S: Into<String>>(mut self, new_hint: S) -> Self {
+        match self {
+            ServerError::InvalidQuery { ref mut hint, .. } |
+            ServerError::NotFound { ref mut hint, .. } => {
+                *hint = Some(new_hint.into());
+            },
+            _ => (),
+        }
+        self
+    }
+
+    fn status(&self) -> StatusCode {
+        match self {
+            ServerError::Db { .. } => StatusCode::BAD_GATEWAY,
+            ServerError::LogicLayerDuplicateNames { .. } => StatusCode::INTERNAL_SERVER_ERROR,
+            ServerError::ErrorCode { .. } => StatusCode::INTERNAL_SERVER_ERROR,
+            ServerError::InvalidQuery { .. } => StatusCode::BAD_REQUEST,
+            ServerError::NotFound { .. } => StatusCode::NOT_FOUND,
+            ServerError::Unauthorized => StatusCode::UNAUTHORIZED,
+            ServerError::TooManyRequests { .. } => StatusCode::TOO_MANY_REQUESTS,
+            ServerError::TooLarge { .. } => StatusCode::PAYLOAD_TOO_LARGE,
+            ServerError::Internal { .. } => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn code(&self) -> &'static str {
+        match self {
+            ServerError::Db { .. } => "backend_error",
+            ServerError::LogicLayerDuplicateNames { .. } => "logic_layer_duplicate_names",
+            ServerError::ErrorCode { .. } => "internal_error",
+            ServerError::InvalidQuery { .. } => "invalid_query",
+            ServerError::NotFound { .. } => "not_found",
+            ServerError::Unauthorized => "unauthorized",
+            ServerError::TooManyRequests { .. } => "too_many_requests",
+            ServerError::TooLarge { .. } => "too_large",
+            ServerError::Internal { .. } => "internal_error",
+        }
+    }
+
+    fn body(&self) -> ErrorBody {
+        let (message, detail, hint) = match self {
+            ServerError::Db { cause } => ("Backend error".to_owned(), Some(cause.clone()), None),
+            ServerError::InvalidQuery { cause, hint, .. } => (cause.clone(), None, hint.clone()),
+            ServerError::NotFound { cause, hint } => (cause.clone(), None, hint.clone()),
+            _ => (self.to_string(), None, None),
+        };
+
+        let errors = match self {
+            ServerError::InvalidQuery { errors, .. } => errors.clone(),
+            _ => vec![],
+        };
+
+        ErrorBody {
+            code: self.code(),
+            message,
+            detail,
+            hint,
+            errors,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct ErrorBody {
+    code: &'static str,
+    message: String,
+    detail: Option<String>,
+    hint: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    errors: Vec<String>,
 }
 
 impl actix_web::error::ResponseError for ServerError {
     fn error_response(&self) -> HttpResponse {
-        match self {
-            ServerError::Db { cause } => HttpResponse::InternalServerError().body(cause.clone()),
-            ServerError::LogicLayerDuplicateNames { .. } => HttpResponse::InternalServerError().body(self.to_string()),
-            ServerError::ErrorCode { .. } => HttpResponse::InternalServerError().body(self.to_string()),
+        if let ServerError::Internal { cause } = self {
+            error!("{}", cause);
         }
+
+        HttpResponse::build(self.status()).json(self.body())
     }
 }
 
 
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn internal_cause_not_shown() {
+        let err = ServerError::Internal { cause: "/srv/tesseract/queries.json: permission denied".to_owned() };
+        let body = serde_json::to_value(err.body()).unwrap();
+
+        assert_eq!(body["code"], "internal_error");
+        assert_eq!(body["message"], "Internal Server Error");
+        assert!(body["detail"].is_null());
+        assert!(!body.to_string().contains("permission denied"));
+    }
+}
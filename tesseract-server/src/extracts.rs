@@ -0,0 +1,246 @@
+//! Extracts: aggregate queries materialized to files on a schedule.
+//!
+//! Extracts are listed in the json file at `TESSERACT_EXTRACTS_FILEPATH`:
+//!
+//! ```json
+//! {
+//!     "extracts": [
+//!         {
+//!             "name": "sales_by_year",
+//!             "cube": "sales",
+//!             "query": "drilldowns[]=Date.Year&measures[]=Quantity",
+//!             "format": "csv",
+//!             "destination": "/var/lib/tesseract/extracts",
+//!             "schedule": "0 0 3 * * *"
+//!         }
+//!     ]
+//! }
+//! ```
+//!
+//! `query` is the query string of an aggregate query on `cube`, and
+//! `schedule` is a cron expression (with seconds). Each run writes
+//! `{destination}/{name}.{format}`, which is then served at
+//! `/extracts/{name}`. Only local destinations are supported.
+//!
+//! For schemas with row filters, an extract can have a `role` to run as
+//! (otherwise it runs as the default role), and is then only served to
+//! requests with the same role.
+
+use actix::{Actor, Arbiter, AsyncContext, Context};
+use chrono::Utc;
+use cron::Schedule;
+use failure::{Error, bail, format_err};
+use futures::future::{self, Future};
+use log::*;
+use serde_derive::Deserialize;
+use serde_qs as qs;
+use std::convert::TryInto;
+use std::fs;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tesseract_core::format::{format_records_bytes, FormatType};
+use tesseract_core::{Backend, Query as TsQuery, Schema};
+use tesseract_core::masking::MaskingPlan;
+
+use crate::clickhouse_settings::ClickhouseSettings;
+use crate::db_config::Database;
+use crate::handlers::AggregateQueryOpt;
+
+
+#[derive(Debug, Clone, Deserialize)]
+struct ExtractsConfigJson {
+    extracts: Vec<ExtractConfigJson>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct ExtractConfigJson {
+    name: String,
+    cube: String,
+    query: String,
+    format: Option<String>,
+    destination: String,
+    schedule: String,
+    role: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Extract {
+    pub name: String,
+    pub cube: String,
+    pub query: String,
+    pub format: FormatType,
+    pub destination: PathBuf,
+    pub schedule: Schedule,
+    pub role: Option<String>,
+}
+
+impl Extract {
+    /// Path of the latest artifact of the extract.
+    pub fn path(&self) -> PathBuf {
+        self.destination.join(format!("{}.{}", self.name, format_extension(&self.format)))
+    }
+
+    /// Time until the next scheduled run.
+    fn next_delay(&self) -> Option<Duration> {
+        let next = self.schedule.upcoming(Utc).next()?;
+        let delay = next.signed_duration_since(Utc::now());
+
+        Some(delay.to_std().unwrap_or(Duration::from_secs(0)))
+    }
+}
+
+/// Reads the extracts config file, checking each extract's query against
+/// `schema`.
+pub fn read_config(path: &str, schema: &Schema) -> Result<Vec<Extract>, Error> {
+    let raw = fs::read_to_string(path)?;
+    let config: ExtractsConfigJson = serde_json::from_str(&raw)?;
+
+    let mut extracts: Vec<Extract> = vec![];
+
+    for extract in config.extracts {
+        if extracts.iter().any(|e| e.name == extract.name) {
+            bail!("Duplicate extract name {}", extract.name);
+        }
+        if extract.destination.contains("://") {
+            bail!("Extract {}: only local destinations are supported", extract.name);
+        }
+
+        let format = extract.format.as_ref().map(|f| f.as_str()).unwrap_or("csv")
+            .parse::<FormatType>()
+            .map_err(|err| format_err!("Extract {}: {}", extract.name, err))?;
+        let schedule = Schedule::from_str(&extract.schedule)
+            .map_err(|err| format_err!("Extract {}: invalid schedule: {}", extract.name, err))?;
+
+        let mut query = parse_query(&extract.query)
+            .map_err(|err| format_err!("Extract {}: {}", extract.name, err))?;
+        query.role = extract.role.clone();
+        schema.masking_plan(&extract.cube, &mut query)
+            .and_then(|_| schema.sql_query(&extract.cube, &query))
+            .map_err(|err| format_err!("Extract {}: {}", extract.name, err))?;
+
+        extracts.push(Extract {
+            name: extract.name,
+            cube: extract.cube,
+            query: extract.query,
+            format,
+            destination: PathBuf::from(extract.destination),
+            schedule,
+            role: extract.role,
+        });
+    }
+
+    Ok(extracts)
+}
+
+pub(crate) fn parse_query(query: &str) -> Result<TsQuery, Error> {
+    let agg_query = qs::Config::new(5, false)
+        .deserialize_str::<AggregateQueryOpt>(query)
+        .map_err(|err| format_err!("{}", err))?;
+
+    agg_query.try_into()
+}
+
+pub fn format_extension(format: &FormatType) -> &'static str {
+    match format {
+        FormatType::Csv => "csv",
+        FormatType::JsonRecords => "jsonrecords",
+        FormatType::JsonArrays => "jsonarrays",
+        FormatType::Orc => "orc",
+    }
+}
+
+/// Runs each extract on its schedule. An extract without an artifact yet
+/// also runs on startup.
+pub struct ExtractScheduler {
+    pub extracts: Vec<Extract>,
+    pub backend: Box<dyn Backend + Sync + Send>,
+    pub db_type: Database,
+    pub clickhouse_settings: ClickhouseSettings,
+    pub schema: Arc<RwLock<Schema>>,
+}
+
+impl Actor for ExtractScheduler {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        for i in 0..self.extracts.len() {
+            if !self.extracts[i].path().exists() {
+                self.run(i);
+            }
+            self.schedule(i, ctx);
+        }
+    }
+}
+
+impl ExtractScheduler {
+    fn schedule(&self, i: usize, ctx: &mut Context<Self>) {
+        let delay = match self.extracts[i].next_delay() {
+            Some(delay) => delay,
+            None => return,
+        };
+
+        ctx.run_later(delay, move |act, ctx| {
+            act.run(i);
+            act.schedule(i, ctx);
+        });
+    }
+
+    fn run(&self, i: usize) {
+        let extract = self.extracts[i].clone();
+        info!("Running extract {}", extract.name);
+
+        let sql_headers = self.generate_sql(&extract);
+        let fut: Box<Future<Item=(), Error=Error>> = match sql_headers {
+            Ok((sql, headers, masking_plan)) => Box::new(
+                self.backend.exec_sql(sql)
+                    .and_then(move |mut df| {
+                        df.rename_columns(&headers)?;
+                        if let Some(ref plan) = masking_plan {
+                            plan.apply(&mut df)?;
+                        }
+                        let headers = df.column_names();
+                        let bytes = format_records_bytes(&headers, df, extract.format.clone())?;
+                        write_artifact(&extract, &bytes)
+                    })
+            ),
+            Err(err) => Box::new(future::err(err)),
+        };
+
+        let name = self.extracts[i].name.clone();
+        Arbiter::spawn(fut.map_err(move |err| {
+            error!("Extract {} failed: {}", name, err);
+        }));
+    }
+
+    fn generate_sql(&self, extract: &Extract) -> Result<(String, Vec<String>, Option<MaskingPlan>), Error> {
+        let mut query = parse_query(&extract.query)?;
+        query.role = extract.role.clone();
+        let schema = self.schema.read().unwrap();
+        let masking_plan = schema.masking_plan(&extract.cube, &mut query)?;
+        let (query_ir, headers) = schema.sql_query(&extract.cube, &query)?;
+        let sql = self.backend.generate_sql(query_ir);
+
+        let sql = match self.db_type {
+            Database::Clickhouse => self.clickhouse_settings.apply(sql),
+            _ => sql,
+        };
+
+        Ok((sql, headers, masking_plan))
+    }
+}
+
+/// Writes to a temporary file first, so that the artifact being served is
+/// never partially written.
+fn write_artifact(extract: &Extract, bytes: &[u8]) -> Result<(), Error> {
+    fs::create_dir_all(&extract.destination)?;
+
+    let path = extract.path();
+    let tmp_path = path.with_extension("tmp");
+    fs::write(&tmp_path, bytes)?;
+    fs::rename(&tmp_path, &path)?;
+
+    info!("Extract {} written to {}", extract.name, path.display());
+    Ok(())
+}
@@ -0,0 +1,180 @@
+//! Scheduled refresh of the cached time members, with a webhook for when a
+//! cube's latest period changes.
+//!
+//! `TESSERACT_CACHE_REFRESH_SCHEDULE` is a cron expression (with seconds)
+//! for the refresh. On each run, the members of every cube's time levels
+//! are queried again and replace the ones in the cache, so that
+//! `time=month.latest` and `default_time` follow new data without a
+//! restart.
+//!
+//! When the latest member of a cube's finest time level changes, the
+//! payload is POSTed to `TESSERACT_FRESHNESS_WEBHOOK_URL`. The payload is
+//! `{"cube": ..., "level": ..., "old": ..., "new": ...}` unless
+//! `TESSERACT_FRESHNESS_WEBHOOK_PAYLOAD` sets a template, in which
+//! `{cube}`, `{level}`, `{old}` and `{new}` are replaced.
+
+use actix::{Actor, Arbiter, AsyncContext, Context};
+use actix_web::client;
+use chrono::Utc;
+use cron::Schedule;
+use failure::{Error, format_err};
+use futures::future::{Future, join_all};
+use log::*;
+use serde_json::json;
+use std::str::FromStr;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tesseract_core::{Backend, Schema};
+
+use crate::logic_layer::{Cache, sorted_distinct_values, time_level_sql};
+
+
+#[derive(Debug, Clone)]
+pub struct Webhook {
+    pub url: String,
+    pub template: Option<String>,
+}
+
+impl Webhook {
+    /// Payload for a change of the latest period of `cube`.
+    pub fn payload(&self, cube: &str, level: &str, old: Option<&str>, new: &str) -> String {
+        match &self.template {
+            Some(template) => {
+                template
+                    .replace("{cube}", cube)
+                    .replace("{level}", level)
+                    .replace("{old}", old.unwrap_or(""))
+                    .replace("{new}", new)
+            },
+            None => {
+                json!({
+                    "cube": cube,
+                    "level": level,
+                    "old": old,
+                    "new": new,
+                }).to_string()
+            },
+        }
+    }
+
+    fn send(&self, payload: String) {
+        post_webhook(&self.url, payload);
+    }
+}
+
+/// POSTs a json payload to a webhook, logging failures, which aren't
+/// retried.
+pub fn post_webhook(url: &str, payload: String) {
+    let url = url.to_owned();
+
+    let req = match client::post(&url)
+        .header("Content-Type", "application/json")
+        .body(payload)
+    {
+        Ok(req) => req,
+        Err(err) => {
+            error!("Webhook {}: {}", url, err);
+            return;
+        },
+    };
+
+    Arbiter::spawn(
+        req.send()
+            .map(|_| ())
+            .map_err(move |err| error!("Webhook {}: {}", url, err))
+    );
+}
+
+pub fn parse_schedule(raw: &str) -> Result<Schedule, Error> {
+    Schedule::from_str(raw).map_err(|err| format_err!("{}", err))
+}
+
+/// Refreshes the cached time members on a schedule.
+pub struct CacheRefresher {
+    pub schedule: Schedule,
+    pub backend: Box<dyn Backend + Sync + Send>,
+    pub schema: Arc<RwLock<Schema>>,
+    pub cache: Arc<RwLock<Cache>>,
+    pub webhook: Option<Webhook>,
+}
+
+impl Actor for CacheRefresher {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        self.schedule_next(ctx);
+    }
+}
+
+impl CacheRefresher {
+    fn schedule_next(&self, ctx: &mut Context<Self>) {
+        let next = match self.schedule.upcoming(Utc).next() {
+            Some(next) => next,
+            None => return,
+        };
+        let delay = next.signed_duration_since(Utc::now())
+            .to_std()
+            .unwrap_or(Duration::from_secs(0));
+
+        ctx.run_later(delay, |act, ctx| {
+            act.refresh();
+            act.schedule_next(ctx);
+        });
+    }
+
+    fn refresh(&self) {
+        info!("Refreshing cached time members...");
+
+        let cube_futs: Vec<_> = self.schema.read().unwrap().cubes.iter()
+            .map(|cube| {
+                let (levels, sqls): (Vec<_>, Vec<_>) = time_level_sql(cube).into_iter().unzip();
+                let level_futs: Vec<_> = sqls.into_iter()
+                    .map(|sql| {
+                        self.backend.exec_sql(sql)
+                            .and_then(sorted_distinct_values)
+                    })
+                    .collect();
+
+                let cube_name = cube.name.clone();
+                join_all(level_futs)
+                    .map(move |values| (cube_name, levels, values))
+            })
+            .collect();
+
+        let cache = self.cache.clone();
+        let webhook = self.webhook.clone();
+
+        Arbiter::spawn(
+            join_all(cube_futs)
+                .map(move |cubes| {
+                    let mut cache = cache.write().unwrap();
+
+                    for (cube_name, levels, values) in cubes {
+                        let cube_cache = match cache.cubes.iter_mut().find(|c| c.name == cube_name) {
+                            Some(cube_cache) => cube_cache,
+                            None => continue,
+                        };
+
+                        let old_latest = cube_cache.latest_period();
+                        for (level, level_values) in levels.iter().zip(values) {
+                            cube_cache.set_time_values(level, level_values);
+                        }
+                        let new_latest = cube_cache.latest_period();
+
+                        if let Some((level, new)) = new_latest {
+                            let old = old_latest.map(|(_, old)| old);
+                            if old.as_ref() != Some(&new) {
+                                info!("Latest {} of cube {} is now {}", level, cube_name, new);
+                                if let Some(ref webhook) = webhook {
+                                    webhook.send(webhook.payload(&cube_name, &level, old.as_ref().map(|o| o.as_str()), &new));
+                                }
+                            }
+                        }
+                    }
+
+                    info!("Cached time members refreshed");
+                })
+                .map_err(|err| error!("Cache refresh failed: {}", err))
+        );
+    }
+}
@@ -4,19 +4,29 @@ use actix_web::{
     HttpRequest,
     HttpResponse,
     Path,
+    ResponseError,
 };
-use failure::Error;
-use futures::future::{self, Future};
+use failure::{Error, bail, format_err};
+use futures::future::{self, Future, join_all};
 use lazy_static::lazy_static;
 use log::*;
 use serde_derive::{Serialize, Deserialize};
+use serde_json::{json, Map, Value};
 use serde_qs as qs;
+use std::collections::HashMap;
 use std::convert::{TryFrom, TryInto};
-use tesseract_core::format::{format_records, FormatType};
-use tesseract_core::Query as TsQuery;
+use tesseract_core::format::{format_jsonrecords_with_metadata, format_records_bytes, FormatType};
+use tesseract_core::{columns_first, Cube, DataFrame, Query as TsQuery};
+use tesseract_core::names::{Cut, Mask};
+use tesseract_core::query::TopQuery;
+use tesseract_core::schema::metadata::MeasureMetadata;
 
 use crate::app::AppState;
+use crate::complexity::{AUTO_TOP_HEADER, auto_top_header_value};
+use crate::conversion::{Conversion, CONVERSION_HEADER};
 use crate::errors::ServerError;
+use crate::query_hints::{QueryHints, QueryPriority};
+use crate::util::{boxed_error, format_error};
 use super::util;
 
 /// Handles default aggregation when a format is not specified.
@@ -29,17 +39,11 @@ pub fn aggregate_default_handler(
     do_aggregate(req, cube_format)
 }
 
-macro_rules! ok_or_404 {
-    ($expr:expr) => {
+macro_rules! ok_or_error {
+    ($expr:expr, $to_err:expr) => {
         match $expr {
             Ok(val) => val,
-            Err(err) => {
-                return Box::new(
-                    future::result(
-                        Ok(HttpResponse::NotFound().json(err.to_string()))
-                    )
-                );
-            }
+            Err(err) => return boxed_error($to_err(err)),
         }
     };
 }
@@ -57,68 +61,364 @@ pub fn do_aggregate(
     req: HttpRequest<AppState>,
     cube_format: (String, String),
     ) -> FutureResponse<HttpResponse>
+{
+    let query = req.query_string().to_owned();
+    aggregate_query(req, cube_format, query)
+}
+
+/// Performs data aggregation for the aggregate query string `query`, which
+/// need not be the request's, e.g. for saved queries.
+pub fn aggregate_query(
+    req: HttpRequest<AppState>,
+    cube_format: (String, String),
+    query: String,
+    ) -> FutureResponse<HttpResponse>
 {
     let (cube, format) = cube_format;
 
     let format = format.parse::<FormatType>();
-    let format = ok_or_404!(format);
+    let format = ok_or_error!(format, format_error);
 
     info!("cube: {}, format: {:?}", cube, format);
 
-    let query = req.query_string();
     lazy_static!{
         static ref QS_NON_STRICT: qs::Config = qs::Config::new(5, false);
     }
+    ok_or_error!(
+        util::check_params(req.state(), &query, AGGREGATE_PARAMS, |_| false, std::iter::empty()),
+        ServerError::from_query_error
+    );
     let agg_query_res = QS_NON_STRICT.deserialize_str::<AggregateQueryOpt>(&query);
-    let agg_query = ok_or_404!(agg_query_res);
+    let mut agg_query = ok_or_error!(agg_query_res, ServerError::invalid_query);
     info!("query opts:{:?}", agg_query);
 
+    let members_with_data = {
+        let schema = req.state().schema.read().unwrap();
+        let cube = ok_or_error!(schema.get_cube_by_name(&cube), ServerError::not_found);
+        ok_or_error!(agg_query.members_with_data(cube), ServerError::invalid_query)
+    };
+
+    let query_settings = agg_query.settings.clone();
+    let convert = agg_query.convert.clone();
+    let column_order = agg_query.column_order.clone().unwrap_or_default();
+    let hints = ok_or_error!(agg_query.hints(req.state().env_vars.max_query_priority), ServerError::invalid_query);
+
+    let with_metadata = agg_query.metadata.unwrap_or(false);
+    let with_echo = agg_query.echo.unwrap_or(false);
+    match format {
+        FormatType::JsonRecords => (),
+        _ if with_metadata => {
+            return boxed_error(ServerError::invalid_query("metadata is only available for the jsonrecords format"));
+        },
+        _ if with_echo => {
+            return boxed_error(ServerError::invalid_query("echo is only available for the jsonrecords format"));
+        },
+        _ => (),
+    }
+
     // Turn AggregateQueryOpt into Query
     let ts_query: Result<TsQuery, _> = agg_query.try_into();
-    let ts_query = ok_or_404!(ts_query);
+    let mut ts_query = ok_or_error!(ts_query, ServerError::invalid_query);
+    ts_query.role = util::request_role(&req);
+
+    let (sql, headers, precisions, conversions, measures, cut_captions, masking_plan, calculation_plan, comparison_plan, external, auto_top, echo, drop_measures) = {
+        let schema = req.state().schema.read().unwrap();
+
+        let ts_cube = ok_or_error!(schema.get_cube_by_name(&cube), ServerError::not_found);
+        // an unknown level is a not found, rather than an invalid query
+        ok_or_error!(schema.check_levels(ts_cube, &ts_query), ServerError::not_found);
+        let precisions = ts_cube.get_measure_precisions();
+        let measures: Vec<MeasureMetadata> = ts_cube.measures.iter().map(|m| m.into()).collect();
+
+        // read before masking adds its measures to the query
+        let drop_measures = if members_with_data {
+            util::measure_headers(&schema, ts_cube, &ts_query)
+        } else {
+            vec![]
+        };
+
+        // comparisons run the query once for each set of cuts, and join
+        // the results
+        let comparison_plan = ok_or_error!(schema.comparison_plan(&cube, &ts_query), ServerError::invalid_query);
+
+        // masked measures need the measures they're masked by, which are
+        // added to the query and dropped again by the plan
+        let masking_plan = ok_or_error!(schema.masking_plan(&cube, &mut ts_query), ServerError::invalid_query);
 
-    let query_ir_headers = req
-        .state()
-        .schema.read().unwrap()
-        .sql_query(&cube, &ts_query);
+        // calculations are computed from the results of queries for the
+        // measures they use, on this cube and on others
+        let calculation_plan = ok_or_error!(schema.calculation_plan(&cube, &ts_query), ServerError::invalid_query);
+        if calculation_plan.is_some() && members_with_data {
+            return boxed_error(ServerError::invalid_query("members_with_data cannot be used with calculations"));
+        }
+
+        // queries with calculations or comparisons can't be truncated,
+        // since they're split
+        let auto_top = match (&calculation_plan, &comparison_plan) {
+            (Some(plan), _) => {
+                ok_or_error!(util::check_complexity(req.state(), ts_cube, &plan.query), ServerError::invalid_query);
+                None
+            },
+            (None, Some(plan)) => {
+                ok_or_error!(util::check_complexity(req.state(), ts_cube, &plan.query_a), ServerError::invalid_query);
+                ok_or_error!(util::check_complexity(req.state(), ts_cube, &plan.query_b), ServerError::invalid_query);
+                None
+            },
+            (None, None) => ok_or_error!(util::limit_complexity(req.state(), ts_cube, &mut ts_query), ServerError::from_query_error),
+        };
+        let base_query = calculation_plan.as_ref().map(|plan| &plan.query)
+            .or_else(|| comparison_plan.as_ref().map(|plan| &plan.query_a))
+            .unwrap_or(&ts_query);
+
+        let sql_headers = util::generate_sql(req.state(), &schema, &cube, base_query);
+        let (sql, headers) = ok_or_error!(sql_headers, ServerError::from_query_error);
+
+        // the second query of a comparison runs alongside the external ones
+        let external: Result<Vec<_>, _> = calculation_plan.iter()
+            .flat_map(|plan| plan.external.iter().map(|(ext_cube, ext_query)| (ext_cube, ext_query)))
+            .chain(comparison_plan.iter().map(|plan| (&cube, &plan.query_b)))
+            .map(|(ext_cube, ext_query)| {
+                util::generate_sql(req.state(), &schema, ext_cube, ext_query)
+                    .map(|(sql, headers)| (ext_cube.clone(), sql, headers))
+            })
+            .collect();
+        let external = ok_or_error!(external, ServerError::from_query_error);
 
-    let (query_ir, headers) = ok_or_404!(query_ir_headers);
+        let conversions = ok_or_error!(
+            util::conversions(req.state(), &schema, &cube, &ts_query, convert.as_ref().map(|c| c.as_str())),
+            ServerError::invalid_query
+        );
 
-    let sql = req.state()
-        .backend
-        .generate_sql(query_ir);
+        // sql to look up the captions of cut members, for the metadata
+        let cut_captions: Result<Vec<_>, _> = ts_query.cuts.iter()
+            .filter(|_| with_metadata)
+            .map(|cut| {
+                schema.cut_captions_sql(&cube, cut)
+                    .map(|sql| (cut.clone(), sql))
+            })
+            .collect();
+        let cut_captions = ok_or_error!(cut_captions, ServerError::invalid_query);
+
+        // the query as run, with names resolved and the auto top applied
+        let echo = if with_echo {
+            Some(ok_or_error!(serde_json::to_value(schema.resolve_query(ts_cube, &ts_query)), ServerError::invalid_query))
+        } else {
+            None
+        };
+
+        (sql, headers, precisions, conversions, measures, cut_captions, masking_plan, calculation_plan, comparison_plan, external, auto_top, echo, drop_measures)
+    };
+    let round = ts_query.round;
+
+    let sql = ok_or_error!(
+        util::apply_clickhouse_settings(req.state(), query_settings.as_ref().map(|s| s.as_str()), &hints, sql),
+        ServerError::invalid_query
+    );
+
+    let mut external_futs: Vec<Box<Future<Item=DataFrame, Error=Error>>> = vec![];
+    let mut external_headers: Vec<Vec<String>> = vec![];
+    for (ext_cube, ext_sql, ext_headers) in external {
+        let ext_sql = ok_or_error!(
+            util::apply_clickhouse_settings(req.state(), query_settings.as_ref().map(|s| s.as_str()), &hints, ext_sql),
+            ServerError::invalid_query
+        );
+        info!("Sql query for cube {}: {}", ext_cube, ext_sql);
+
+        external_futs.push(util::exec_sql(req.state(), &ext_cube, hints.priority, ext_sql));
+        external_headers.push(ext_headers);
+    }
 
     info!("Sql query: {}", sql);
     info!("Headers: {:?}", headers);
 
-    req.state()
-        .backend
-        .exec_sql(sql)
-        .and_then(move |df| {
+    let schema = req.state().schema.clone();
+
+    let caption_futs: Vec<Box<Future<Item=Option<DataFrame>, Error=Error>>> = cut_captions.iter()
+        .map(|(_, sql)| -> Box<Future<Item=Option<DataFrame>, Error=Error>> {
+            match sql {
+                Some(sql) => Box::new(util::exec_sql(req.state(), &cube, hints.priority, sql.clone()).map(Some)),
+                None => Box::new(future::ok(None)),
+            }
+        })
+        .collect();
+    let cuts: Vec<Cut> = cut_captions.into_iter().map(|(cut, _)| cut).collect();
+
+    let slow_query = req.state().slow_query_log.as_ref()
+        .map(|log| log.start(&cube, &ts_query));
+
+    util::exec_sql(req.state(), &cube, hints.priority, sql)
+        .map(move |df| {
+            if let Some(slow_query) = slow_query {
+                slow_query.finish();
+            }
+            df
+        })
+        .join(join_all(caption_futs))
+        .join(join_all(external_futs))
+        .and_then(move |((mut df, captions), external_dfs)| {
+            df.rename_columns(&headers)?;
+            schema.read().unwrap().fill_time_gaps(&cube, &ts_query, &mut df)?;
+            df.scale_columns(&Conversion::factors(&conversions));
+
+            let mut ext_dfs = vec![];
+            for (mut ext_df, ext_headers) in external_dfs.into_iter().zip(&external_headers) {
+                ext_df.rename_columns(ext_headers)?;
+                ext_dfs.push(ext_df);
+            }
+
+            if let Some(ref plan) = calculation_plan {
+                df = plan.apply(df, ext_dfs)?;
+            } else if let Some(ref plan) = comparison_plan {
+                let mut df_b = ext_dfs.pop()
+                    .ok_or_else(|| format_err!("Missing results of the second query to compare"))?;
+                df_b.scale_columns(&Conversion::factors(&conversions));
+                df = plan.apply(df, df_b)?;
+            }
+
+            df.round_columns(&precisions, round);
+
+            if let Some(ref plan) = masking_plan {
+                plan.apply(&mut df)?;
+            }
+
+            df.columns.retain(|col| !drop_measures.contains(&col.name));
+
+            // default column order, with the columns in `column_order` first
+            let names = df.column_names();
+            let order = schema.read().unwrap()
+                .column_order(&cube, &ts_query, &names)
+                .and_then(|order| columns_first(&order, &names, &column_order));
+            match order {
+                Ok(order) => df.reorder_columns(&order)?,
+                Err(err) => return Ok(ServerError::invalid_query(err).error_response()),
+            }
+
+            let headers = df.column_names();
             let content_type = util::format_to_content_type(&format);
 
-            match format_records(&headers, df, format) {
+            let formatted = if with_metadata || echo.is_some() {
+                let mut metadata = if with_metadata {
+                    results_metadata(&df, &measures, &cuts, captions, auto_top.as_ref())
+                } else {
+                    json!({})
+                };
+                if let Some(echo) = echo {
+                    metadata["query"] = echo;
+                }
+                format_jsonrecords_with_metadata(&headers, df, &metadata)
+            } else {
+                format_records_bytes(&headers, df, format)
+            };
+
+            match formatted {
                 Ok(res) => {
-                    Ok(HttpResponse::Ok()
-                        .set(content_type)
-                        .body(res))
+                    let mut response = HttpResponse::Ok();
+                    response.set(content_type);
+                    if !conversions.is_empty() {
+                        response.header(CONVERSION_HEADER, Conversion::header_value(&conversions));
+                    }
+                    if let Some(ref top) = auto_top {
+                        response.header(AUTO_TOP_HEADER, auto_top_header_value(top));
+                    }
+                    Ok(response.body(res))
                 },
-                Err(err) => Ok(HttpResponse::NotFound().json(err.to_string())),
+                Err(err) => Ok(ServerError::Internal { cause: err.to_string() }.error_response()),
             }
         })
         .map_err(move |e| {
-            if req.state().debug {
-                ServerError::Db { cause: e.to_string() }.into()
-            } else {
-                ServerError::Db { cause: "Internal Server Error 1010".to_owned() }.into()
-            }
+            ServerError::from_backend_error(e, req.state().debug).into()
         })
         .responder()
 }
 
+/// The `metadata` block for jsonrecords results: the aggregator, units and
+/// datatype of each measure column, and the cuts applied with the caption
+/// of each member. The datatype comes from the schema, or else from the
+/// results.
+///
+/// `captions` are the results of the caption lookups for each cut (see
+/// `Schema::cut_captions_sql`); without a lookup, members are their own
+/// captions.
+///
+/// If the results were truncated by the cube's `auto_top`, its top is
+/// described under `auto_top`.
+fn results_metadata(
+    df: &DataFrame,
+    measures: &[MeasureMetadata],
+    cuts: &[Cut],
+    captions: Vec<Option<DataFrame>>,
+    auto_top: Option<&TopQuery>,
+    ) -> Value
+{
+    let mut res = Map::new();
+
+    for col in &df.columns {
+        if let Some(measure) = measures.iter().find(|m| m.name == col.name) {
+            let datatype = measure.datatype.clone()
+                .unwrap_or_else(|| col.column_data.type_name().to_owned());
+
+            res.insert(col.name.clone(), json!({
+                "aggregator": measure.aggregator.name,
+                "units": measure.units,
+                "datatype": datatype,
+            }));
+        }
+    }
+
+    let cuts_applied: Vec<Value> = cuts.iter()
+        .zip(captions)
+        .map(|(cut, captions_df)| {
+            let captions: Option<HashMap<String, String>> = captions_df
+                .filter(|df| df.columns.len() == 2)
+                .map(|df| {
+                    df.columns[0].stringify_column_data().into_iter()
+                        .zip(df.columns[1].stringify_column_data())
+                        .collect()
+                });
+
+            let members: Vec<Value> = cut.members.iter()
+                .map(|member| {
+                    let caption = match captions {
+                        Some(ref captions) => captions.get(member).cloned(),
+                        None => Some(member.clone()),
+                    };
+                    json!({ "key": member, "caption": caption })
+                })
+                .collect();
+
+            json!({
+                "level": cut.level_name.to_string(),
+                "exclude": cut.mask == Mask::Exclude,
+                "members": members,
+            })
+        })
+        .collect();
+
+    let mut metadata = json!({ "measures": res, "cuts_applied": cuts_applied });
+
+    if let Some(top) = auto_top {
+        metadata["auto_top"] = json!({
+            "n": top.n,
+            "by_dimension": top.by_dimension.to_string(),
+        });
+    }
+
+    metadata
+}
+
+/// Query params accepted by `AggregateQueryOpt`, for strict param checking.
+pub const AGGREGATE_PARAMS: &[&str] = &[
+    "drilldowns", "property_drilldowns", "cuts", "measures", "properties", "filters", "captions",
+    "parents", "top", "top_other", "top_where", "sort", "limit", "growth", "rca", "rate",
+    "ratio_to_parent", "rate_against", "compare", "debug", "exclude_default_members", "sparse", "round", "settings", "convert",
+    "timeout", "priority", "members_with_data", "fill", "metadata", "column_order",
+    "echo",
+];
+
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct AggregateQueryOpt {
     drilldowns: Option<Vec<String>>,
+    property_drilldowns: Option<Vec<String>>,
     cuts: Option<Vec<String>>,
     measures: Option<Vec<String>>,
     properties: Option<Vec<String>>,
@@ -126,17 +426,94 @@ pub struct AggregateQueryOpt {
     captions: Option<Vec<String>>,
     parents: Option<bool>,
     top: Option<String>,
+    top_other: Option<bool>,
     top_where: Option<String>,
     sort: Option<String>,
     limit: Option<String>,
     growth: Option<String>,
     rca: Option<String>,
     rate: Option<String>,
+    ratio_to_parent: Option<String>,
+    rate_against: Option<String>,
+    compare: Option<String>,
     debug: Option<bool>,
     exclude_default_members: Option<bool>,
 //    distinct: Option<bool>,
 //    nonempty: Option<bool>,
     sparse: Option<bool>,
+    round: Option<u32>,
+    pub settings: Option<String>,
+    pub convert: Option<String>,
+    pub timeout: Option<u64>,
+    pub priority: Option<String>,
+    members_with_data: Option<bool>,
+    fill: Option<String>,
+    pub metadata: Option<bool>,
+    pub column_order: Option<Vec<String>>,
+    pub echo: Option<bool>,
+}
+
+impl AggregateQueryOpt {
+    pub fn hints(&self, max_priority: QueryPriority) -> Result<QueryHints, Error> {
+        QueryHints::parse(self.timeout, self.priority.as_ref().map(|p| p.as_str()), max_priority)
+    }
+
+    /// With `members_with_data=true`, only the drilldown members that have
+    /// data are returned, without measure values. The measures still decide
+    /// which rows have data (and can be filtered on), so if none are given
+    /// the cube's first measure is used.
+    ///
+    /// Returns whether the measure columns are to be dropped from the
+    /// results (see `util::measure_headers`).
+    pub fn members_with_data(&mut self, cube: &Cube) -> Result<bool, Error> {
+        if !self.members_with_data.unwrap_or(false) {
+            return Ok(false);
+        }
+
+        if self.growth.is_some() || self.rca.is_some() || self.rate.is_some() || self.ratio_to_parent.is_some() {
+            bail!("members_with_data cannot be used with growth, rca, rate or ratio_to_parent");
+        }
+        if self.fill.is_some() {
+            bail!("members_with_data cannot be used with fill");
+        }
+        if self.compare.is_some() {
+            bail!("members_with_data cannot be used with compare");
+        }
+
+        let has_measures = self.measures.as_ref()
+            .map(|ms| !ms.is_empty())
+            .unwrap_or(false);
+
+        if !has_measures {
+            let measure = cube.measures.first()
+                .ok_or_else(|| format_err!("Cube {} has no measures", cube.name))?;
+            self.measures = Some(vec![measure.name.clone()]);
+        }
+
+        Ok(true)
+    }
+
+    /// For distribution queries, which bucket the values of a single
+    /// measure over the rows of the aggregate, so any `measures` param is
+    /// replaced and calculations that add measure columns are rejected.
+    pub fn distribution_of(&mut self, measure: String) -> Result<(), Error> {
+        if self.growth.is_some() || self.rca.is_some() || self.rate.is_some() || self.ratio_to_parent.is_some() {
+            bail!("distribution cannot be used with growth, rca, rate or ratio_to_parent");
+        }
+        if self.members_with_data.unwrap_or(false) {
+            bail!("distribution cannot be used with members_with_data");
+        }
+        if self.fill.is_some() {
+            bail!("distribution cannot be used with fill");
+        }
+        if self.rate_against.is_some() {
+            bail!("distribution cannot be used with rate_against");
+        }
+
+        self.measures = Some(vec![measure]);
+
+        Ok(())
+    }
 }
 
 impl TryFrom<AggregateQueryOpt> for TsQuery {
@@ -149,6 +526,12 @@ impl TryFrom<AggregateQueryOpt> for TsQuery {
             })
             .unwrap_or(Ok(vec![]));
 
+        let property_drilldowns: Result<Vec<_>, _> = agg_query_opt.property_drilldowns
+            .map(|ps| {
+                ps.iter().map(|p| p.parse()).collect()
+            })
+            .unwrap_or(Ok(vec![]));
+
         let cuts: Result<Vec<_>, _> = agg_query_opt.cuts
             .map(|cs| {
                 cs.iter().map(|c| c.parse()).collect()
@@ -180,6 +563,7 @@ impl TryFrom<AggregateQueryOpt> for TsQuery {
             .unwrap_or(Ok(vec![]));
 
         let drilldowns = drilldowns?;
+        let property_drilldowns = property_drilldowns?;
         let cuts = cuts?;
         let measures = measures?;
         let properties = properties?;
@@ -188,9 +572,14 @@ impl TryFrom<AggregateQueryOpt> for TsQuery {
 
         let parents = agg_query_opt.parents.unwrap_or(false);
 
-        let top = agg_query_opt.top
+        let mut top: Option<TopQuery> = agg_query_opt.top
             .map(|t| t.parse())
             .transpose()?;
+        if agg_query_opt.top_other.unwrap_or(false) {
+            top.as_mut()
+                .ok_or_else(|| format_err!("top_other requires a top"))?
+                .other = true;
+        }
         let top_where = agg_query_opt.top_where
             .map(|t| t.parse())
             .transpose()?;
@@ -213,13 +602,30 @@ impl TryFrom<AggregateQueryOpt> for TsQuery {
             .map(|r| r.parse())
             .transpose()?;
 
+        let ratio_to_parent = agg_query_opt.ratio_to_parent
+            .map(|r| r.parse())
+            .transpose()?;
+
+        let rate_against = agg_query_opt.rate_against
+            .map(|r| r.parse())
+            .transpose()?;
+
+        let compare = agg_query_opt.compare
+            .map(|c| c.parse())
+            .transpose()?;
+
         let debug = agg_query_opt.debug.unwrap_or(false);
         let sparse = agg_query_opt.sparse.unwrap_or(false);
         let exclude_default_members = agg_query_opt.exclude_default_members.unwrap_or(false);
 
+        let fill = agg_query_opt.fill
+            .map(|f| f.parse())
+            .transpose()?;
+
         // TODO: deserialize rate
         Ok(TsQuery {
             drilldowns,
+            property_drilldowns,
             cuts,
             measures,
             parents,
@@ -234,8 +640,131 @@ impl TryFrom<AggregateQueryOpt> for TsQuery {
             growth,
             debug,
             rate,
+            ratio_to_parent,
+            rate_against,
+            compare,
             sparse,
             exclude_default_members,
+            round: agg_query_opt.round,
+            fill,
+            role: None,
         })
     }
 }
+
+
+#[cfg(test)]
+mod test {
+    use tesseract_core::{Column, ColumnData, DataFrame, Schema};
+    use tesseract_test::MockBackend;
+
+    use crate::app::EnvVars;
+    use crate::app::test::{env_vars, get, test_clickhouse_server, test_server};
+
+    fn year_quantity() -> DataFrame {
+        DataFrame::from_vec(vec![
+            Column::new("year".to_owned(), ColumnData::UInt32(vec![2018, 2019])),
+            Column::new("quantity".to_owned(), ColumnData::Int64(vec![10, 20])),
+        ])
+    }
+
+    #[test]
+    fn aggregate() {
+        let backend = MockBackend::new()
+            .respond(r"sum\(quantity\)", year_quantity);
+        let mut srv = test_server(tesseract_test::schema(), backend.clone(), env_vars());
+
+        let (status, body) = get(
+            &mut srv,
+            "/cubes/sales/aggregate.csv?drilldowns%5B%5D=Year.Year&measures%5B%5D=Quantity&cuts%5B%5D=Year.Year.Year.2018,2019",
+            &[],
+        );
+
+        assert_eq!(status, 200, "{}", body);
+        assert!(body.starts_with("Year,Quantity\n"), "{}", body);
+        assert!(body.contains("2018,10\n") && body.contains("2019,20\n"), "{}", body);
+
+        assert_eq!(backend.queries().len(), 1);
+        assert!(backend.last_query().unwrap().contains("sales.year in (2018, 2019)"));
+    }
+
+    #[test]
+    fn aggregate_members_with_data_repeated_measure() {
+        let backend = MockBackend::new()
+            .respond(r"sum\(quantity\)", year_quantity);
+        let mut srv = test_server(tesseract_test::schema(), backend.clone(), env_vars());
+
+        // the repeats are dropped from the sql, which has one measure column
+        let (status, body) = get(
+            &mut srv,
+            "/cubes/sales/aggregate.csv?drilldowns%5B%5D=Year.Year&measures%5B%5D=Quantity&measures%5B%5D=Quantity&measures%5B%5D=Quantity&members_with_data=true",
+            &[],
+        );
+
+        assert_eq!(status, 200, "{}", body);
+        assert!(body.starts_with("Year\n"), "{}", body);
+        assert!(body.contains("2018\n") && body.contains("2019\n"), "{}", body);
+        assert!(!body.contains(','), "{}", body);
+
+        assert_eq!(backend.queries().len(), 1);
+        assert_eq!(backend.last_query().unwrap().matches("sum(quantity)").count(), 1);
+    }
+
+    #[test]
+    fn aggregate_unknown_param() {
+        let backend = MockBackend::new()
+            .respond(r"sum\(quantity\)", year_quantity);
+        let mut srv = test_server(tesseract_test::schema(), backend.clone(), env_vars());
+
+        let (status, body) = get(
+            &mut srv,
+            "/cubes/sales/aggregate.csv?drildowns%5B%5D=Year.Year&measures%5B%5D=Quantity",
+            &[],
+        );
+
+        assert_eq!(status, 400, "{}", body);
+        assert!(body.contains("drildowns"), "{}", body);
+        assert!(backend.queries().is_empty());
+    }
+
+    #[test]
+    fn aggregate_unknown_level() {
+        let backend = MockBackend::new()
+            .respond(r"sum\(quantity\)", year_quantity);
+        let mut srv = test_server(tesseract_test::schema(), backend.clone(), env_vars());
+
+        let (status, body) = get(
+            &mut srv,
+            "/cubes/sales/aggregate.csv?drilldowns%5B%5D=Geography.Geography.Stat&measures%5B%5D=Quantity",
+            &[],
+        );
+
+        assert_eq!(status, 404, "{}", body);
+        assert!(body.contains("did you mean [Geography].[Geography].[State]?"), "{}", body);
+        assert!(backend.queries().is_empty());
+    }
+
+    #[test]
+    fn aggregate_too_large_with_auto_top() {
+        let schema = Schema::from_json(&tesseract_test::SCHEMA.replace(
+            r#""name": "sales","#,
+            r#""name": "sales", "auto_top": 5,"#,
+        )).expect("valid test schema");
+        let backend = MockBackend::new()
+            .respond(r"sum\(quantity\)", year_quantity);
+        let env_vars = EnvVars { max_estimated_rows: Some(100), ..env_vars() };
+        let mut srv = test_clickhouse_server(schema, backend.clone(), env_vars);
+
+        // the top 5 products and "Other" for each of the 56 states
+        let (status, body) = get(
+            &mut srv,
+            "/cubes/sales/aggregate.csv?drilldowns%5B%5D=Geography.Geography.State&drilldowns%5B%5D=Product.Product.Product&measures%5B%5D=Quantity",
+            &[],
+        );
+
+        assert_eq!(status, 413, "{}", body);
+        assert!(body.contains("too_large"), "{}", body);
+        assert!(body.contains("estimated 336 rows"), "{}", body);
+        assert!(backend.queries().is_empty());
+    }
+}
@@ -4,17 +4,21 @@ use actix_web::{
     HttpResponse,
     Path,
 };
-use futures::future;
+use futures::{Future, Stream};
 use lazy_static::lazy_static;
 use log::*;
 use serde_qs as qs;
 use std::convert::TryInto;
 use tesseract_core::format::FormatType;
 use tesseract_core::format_stream::format_records_stream;
-use tesseract_core::Query as TsQuery;
+use tesseract_core::{columns_first, Query as TsQuery};
 
 use crate::app::AppState;
-use super::aggregate::AggregateQueryOpt;
+use crate::complexity::{AUTO_TOP_HEADER, auto_top_header_value};
+use crate::conversion::{Conversion, CONVERSION_HEADER};
+use crate::errors::ServerError;
+use crate::util::{boxed_error, format_error, stream_format_error};
+use super::aggregate::{AggregateQueryOpt, AGGREGATE_PARAMS};
 use super::util;
 
 /// Handles default aggregation when a format is not specified.
@@ -46,14 +50,11 @@ pub fn do_aggregate(
     let format = format.parse::<FormatType>();
     let format = match format {
         Ok(f) => f,
-        Err(err) => {
-            return Box::new(
-                future::result(
-                    Ok(HttpResponse::NotFound().json(err.to_string()))
-                )
-            );
-        },
+        Err(err) => return boxed_error(format_error(err)),
     };
+    if format.is_binary() {
+        return boxed_error(stream_format_error(&format));
+    }
 
     info!("cube: {}, format: {:?}", cube, format);
 
@@ -61,80 +62,241 @@ pub fn do_aggregate(
     lazy_static!{
         static ref QS_NON_STRICT: qs::Config = qs::Config::new(5, false);
     }
+    let params_res = util::check_params(req.state(), &query, AGGREGATE_PARAMS, |_| false, std::iter::empty());
+    if let Err(err) = params_res {
+        return boxed_error(ServerError::from_query_error(err));
+    }
+
     let agg_query_res = QS_NON_STRICT.deserialize_str::<AggregateQueryOpt>(&query);
-    let agg_query = match agg_query_res {
+    let mut agg_query = match agg_query_res {
         Ok(q) => q,
-        Err(err) => {
-            return Box::new(
-                future::result(
-                    Ok(HttpResponse::NotFound().json(err.to_string()))
-                )
-            );
-        },
+        Err(err) => return boxed_error(ServerError::invalid_query(err)),
     };
     info!("query opts:{:?}", agg_query);
 
+    let members_with_data = {
+        let schema = req.state().schema.read().unwrap();
+        schema.get_cube_by_name(&cube)
+            .map_err(ServerError::not_found)
+            .and_then(|cube| {
+                agg_query.members_with_data(cube)
+                    .map(|members_with_data| (members_with_data, cube.get_measure_precisions()))
+                    .map_err(ServerError::invalid_query)
+            })
+    };
+    let (members_with_data, precisions) = match members_with_data {
+        Ok(x) => x,
+        Err(err) => return boxed_error(err),
+    };
+
+    let query_settings = agg_query.settings.clone();
+    let convert = agg_query.convert.clone();
+    let column_order = agg_query.column_order.clone().unwrap_or_default();
+    let with_metadata = agg_query.metadata.unwrap_or(false);
+    let with_echo = agg_query.echo.unwrap_or(false);
+    let hints = match agg_query.hints(req.state().env_vars.max_query_priority) {
+        Ok(h) => h,
+        Err(err) => return boxed_error(ServerError::invalid_query(err)),
+    };
+
     // Turn AggregateQueryOpt into Query
     let ts_query: Result<TsQuery, _> = agg_query.try_into();
-    let ts_query = match ts_query {
+    let mut ts_query = match ts_query {
         Ok(q) => q,
-        Err(err) => {
-            return Box::new(
-                future::result(
-                    Ok(HttpResponse::NotFound().json(err.to_string()))
-                )
-            );
-        },
+        Err(err) => return boxed_error(ServerError::invalid_query(err)),
     };
+    ts_query.role = util::request_role(&req);
+
+    // gaps can only be filled in once all the rows are in
+    if ts_query.fill.is_some() {
+        return boxed_error(ServerError::invalid_query("fill cannot be used with streaming"));
+    }
+    // as are measures from other cubes
+    if ts_query.rate_against.is_some() {
+        return boxed_error(ServerError::invalid_query("rate_against cannot be used with streaming"));
+    }
+    if ts_query.compare.is_some() {
+        return boxed_error(ServerError::invalid_query("compare cannot be used with streaming"));
+    }
+    if with_metadata {
+        return boxed_error(ServerError::invalid_query("metadata cannot be used with streaming"));
+    }
+    if with_echo {
+        return boxed_error(ServerError::invalid_query("echo cannot be used with streaming"));
+    }
 
-    let query_ir_headers = req
-        .state()
-        .schema.read().unwrap()
-        .sql_query(&cube, &ts_query);
+    let sql_headers = {
+        let schema = req.state().schema.read().unwrap();
+        schema.get_cube_by_name(&cube)
+            .map_err(ServerError::not_found)
+            .and_then(|ts_cube| {
+                // an unknown level is a not found, rather than an invalid query
+                schema.check_levels(ts_cube, &ts_query)
+                    .map_err(ServerError::not_found)?;
+                // read before masking adds its measures to the query
+                let drop_measures = if members_with_data {
+                    util::measure_headers(&schema, ts_cube, &ts_query)
+                } else {
+                    vec![]
+                };
+                let masking_plan = schema.masking_plan(&cube, &mut ts_query)
+                    .map_err(ServerError::invalid_query)?;
+                util::limit_complexity(req.state(), ts_cube, &mut ts_query)
+                    .map(|auto_top| (masking_plan, auto_top, drop_measures))
+                    .map_err(ServerError::from_query_error)
+            })
+            .and_then(|(masking_plan, auto_top, drop_measures)| {
+                util::generate_sql(req.state(), &schema, &cube, &ts_query)
+                    .map(|(sql, headers)| (sql, headers, masking_plan, auto_top, drop_measures))
+                    .map_err(ServerError::from_query_error)
+            })
+            .and_then(|(sql, headers, masking_plan, auto_top, drop_measures)| {
+                let convert = convert.as_ref().map(|c| c.as_str());
+                let conversions = util::conversions(req.state(), &schema, &cube, &ts_query, convert)
+                    .map_err(ServerError::invalid_query)?;
+                Ok((sql, headers, conversions, masking_plan, auto_top, drop_measures))
+            })
+    }
+    .and_then(|(sql, headers, conversions, masking_plan, auto_top, drop_measures)| {
+        let query_settings = query_settings.as_ref().map(|s| s.as_str());
+        let sql = util::apply_clickhouse_settings(req.state(), query_settings, &hints, sql)
+            .map_err(ServerError::invalid_query)?;
+        Ok((sql, headers, conversions, masking_plan, auto_top, drop_measures))
+    });
 
-    let (query_ir, headers) = match query_ir_headers {
+    let (sql, mut headers, conversions, masking_plan, auto_top, drop_measures) = match sql_headers {
         Ok(x) => x,
-        Err(err) => {
-            return Box::new(
-                future::result(
-                    Ok(HttpResponse::NotFound().json(err.to_string()))
-                )
-            );
-        },
+        Err(err) => return boxed_error(err),
     };
 
-    let sql = req.state()
-        .backend
-        .generate_sql(query_ir);
+    // complementary suppression needs all the rows at once
+    if masking_plan.as_ref().map(|plan| plan.is_complementary()).unwrap_or(false) {
+        return boxed_error(ServerError::invalid_query("complementary suppression cannot be used with streaming"));
+    }
 
     info!("Sql query: {}", sql);
     info!("Headers: {:?}", headers);
 
-    let df_stream = req.state()
-        .backend
-        .exec_sql_stream(sql);
+    let factors = Conversion::factors(&conversions);
+    let round = ts_query.round;
+    let df_headers = headers.clone();
+    if let Some(ref plan) = masking_plan {
+        headers = plan.output_headers(&headers);
+    }
+    headers.retain(|header| !drop_measures.contains(header));
 
-    let content_type = util::format_to_content_type(&format);
+    // default column order, with the columns in `column_order` first
+    let order = {
+        let schema = req.state().schema.read().unwrap();
+        schema.column_order(&cube, &ts_query, &headers)
+            .and_then(|order| columns_first(&order, &headers, &column_order))
+    };
+    let order = match order {
+        Ok(order) => order,
+        Err(err) => return boxed_error(ServerError::invalid_query(err)),
+    };
+    let headers: Vec<String> = order.iter().map(|&idx| headers[idx].clone()).collect();
+    let debug = req.state().debug;
 
+    // Nothing is committed to the response until the query limiter lets the
+    // query through and the backend has returned its first chunk, so that
+    // those errors still get an error status. Errors after that end the
+    // stream with an error trailer (see `format_records_stream`).
     Box::new(
-        futures::future::ok(
-            HttpResponse::Ok()
-            .set(content_type)
-            .streaming(format_records_stream(headers, df_stream, format))
-        )
+        util::exec_sql_stream(req.state(), &cube, hints.priority, sql)
+            .map(move |df_stream| {
+                let df_stream = df_stream.map(move |df_res| {
+                    df_res.and_then(|mut df| {
+                        // conversions, rounding, masking and
+                        // members_with_data find their columns by name
+                        df.rename_columns(&df_headers)?;
+                        df.scale_columns(&factors);
+                        df.round_columns(&precisions, round);
+                        if let Some(ref plan) = masking_plan {
+                            plan.apply(&mut df)?;
+                        }
+                        df.columns.retain(|col| !drop_measures.contains(&col.name));
+                        df.reorder_columns(&order)?;
+                        Ok(df)
+                    })
+                });
+
+                let content_type = util::format_to_content_type(&format);
+
+                let mut response = HttpResponse::Ok();
+                response.set(content_type);
+                if !conversions.is_empty() {
+                    response.header(CONVERSION_HEADER, Conversion::header_value(&conversions));
+                }
+                if let Some(ref top) = auto_top {
+                    response.header(AUTO_TOP_HEADER, auto_top_header_value(top));
+                }
+
+                response.streaming(format_records_stream(headers, df_stream, format))
+            })
+            .map_err(move |err| {
+                ServerError::from_backend_error(err, debug).into()
+            })
     )
-    //    .and_then(move |df_stream_res| {
-    //        match df_stream_res {
-    //            Ok(df_stream) => Ok(HttpResponse::Ok().streaming(format_records_stream(headers, df_stream, format))),
-    //            Err(err) => Ok(HttpResponse::NotFound().json(err.to_string())),
-    //    })
-    //    .map_err(move |e| {
-    //        if req.state().debug {
-    //            ServerError::Db { cause: e.to_string() }.into()
-    //        } else {
-    //            ServerError::Db { cause: "Internal Server Error 1010".to_owned() }.into()
-    //        }
-    //    })
-    //    .responder()
 }
 
+
+#[cfg(test)]
+mod test {
+    use tesseract_core::{Column, ColumnData, DataFrame};
+    use tesseract_test::MockBackend;
+
+    use crate::app::test::{env_vars, get, test_stream_server};
+
+    fn year_quantity() -> DataFrame {
+        DataFrame::from_vec(vec![
+            Column::new("year".to_owned(), ColumnData::UInt32(vec![2018, 2019])),
+            Column::new("quantity".to_owned(), ColumnData::Int64(vec![10, 20])),
+        ])
+    }
+
+    fn year_price() -> DataFrame {
+        DataFrame::from_vec(vec![
+            Column::new("year".to_owned(), ColumnData::UInt32(vec![2018, 2019])),
+            Column::new("price".to_owned(), ColumnData::Float64(vec![1.2345, 2.5])),
+        ])
+    }
+
+    #[test]
+    fn aggregate_stream_round() {
+        let backend = MockBackend::new()
+            .respond(r"avg\(price\)", year_price);
+        let mut srv = test_stream_server(tesseract_test::schema(), backend.clone(), env_vars());
+
+        // Price has a precision of 2 in the schema
+        let (status, body) = get(&mut srv, "/cubes/sales/aggregate.csv?drilldowns%5B%5D=Year.Year&measures%5B%5D=Price", &[]);
+
+        assert_eq!(status, 200, "{}", body);
+        assert!(body.starts_with("Year,Price\n"), "{}", body);
+        assert!(body.contains("2018,1.23\n") && body.contains("2019,2.5\n"), "{}", body);
+
+        let (status, body) = get(&mut srv, "/cubes/sales/aggregate.csv?drilldowns%5B%5D=Year.Year&measures%5B%5D=Price&round=0", &[]);
+
+        assert_eq!(status, 200, "{}", body);
+        assert!(body.contains("2018,1\n") && body.contains("2019,3\n"), "{}", body);
+    }
+
+    #[test]
+    fn aggregate_stream_members_with_data_repeated_measure() {
+        let backend = MockBackend::new()
+            .respond(r"sum\(quantity\)", year_quantity);
+        let mut srv = test_stream_server(tesseract_test::schema(), backend.clone(), env_vars());
+
+        let (status, body) = get(
+            &mut srv,
+            "/cubes/sales/aggregate.csv?drilldowns%5B%5D=Year.Year&measures%5B%5D=Quantity&measures%5B%5D=Quantity&measures%5B%5D=Quantity&members_with_data=true",
+            &[],
+        );
+
+        assert_eq!(status, 200, "{}", body);
+        assert!(body.starts_with("Year\n"), "{}", body);
+        assert!(body.contains("2018\n") && body.contains("2019\n"), "{}", body);
+        assert!(!body.contains(','), "{}", body);
+        assert_eq!(backend.queries().len(), 1);
+    }
+}
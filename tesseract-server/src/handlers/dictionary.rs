@@ -0,0 +1,76 @@
+use actix_web::{
+    http::header,
+    HttpRequest,
+    HttpResponse,
+    Path,
+    Result as ActixResult,
+};
+use log::*;
+use tesseract_core::format::{format_records_bytes, FormatType};
+use tesseract_core::format_xlsx::format_xlsx;
+use tesseract_core::schema::{dictionary, dictionary_headers};
+
+use crate::app::AppState;
+use crate::errors::ServerError;
+
+const XLSX_CONTENT_TYPE: &str = "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet";
+
+
+/// Handles the data dictionary when a format is not specified.
+/// Default format is CSV.
+pub fn dictionary_default_handler(
+    (req, cube): (HttpRequest<AppState>, Path<String>)
+    ) -> ActixResult<HttpResponse>
+{
+    do_dictionary(req, cube.into_inner(), "csv".to_owned())
+}
+
+/// Handles the data dictionary when a format is specified, `csv` or `xlsx`.
+pub fn dictionary_handler(
+    (req, cube_format): (HttpRequest<AppState>, Path<(String, String)>)
+    ) -> ActixResult<HttpResponse>
+{
+    let (cube, format) = cube_format.into_inner();
+    do_dictionary(req, cube, format)
+}
+
+/// The data dictionary of a cube: its dimensions, hierarchies, levels,
+/// properties and measures, with their annotations and sources.
+fn do_dictionary(req: HttpRequest<AppState>, cube: String, format: String) -> ActixResult<HttpResponse> {
+    info!("Dictionary for cube: {}, format: {}", cube, format);
+
+    let df = {
+        let schema = req.state().schema.read().unwrap();
+        let cube_schema = schema.get_cube_by_name(&cube)
+            .map_err(|_| ServerError::not_found(format!("Could not find cube {}", cube)))?;
+        dictionary(cube_schema)
+    };
+    let headers = dictionary_headers();
+
+    let internal = |err: failure::Error| ServerError::Internal { cause: err.to_string() };
+
+    match format.as_str() {
+        "csv" => {
+            let body = format_records_bytes(&headers, df, FormatType::Csv)
+                .map_err(internal)?;
+
+            Ok(HttpResponse::Ok()
+                .content_type("text/csv; charset=utf-8")
+                .body(body))
+        },
+        "xlsx" => {
+            let body = format_xlsx(&cube, &headers, &df)
+                .map_err(internal)?;
+
+            Ok(HttpResponse::Ok()
+                .content_type(XLSX_CONTENT_TYPE)
+                .header(header::CONTENT_DISPOSITION, format!("attachment; filename=\"{}_dictionary.xlsx\"", cube.replace('"', "")))
+                .body(body))
+        },
+        _ => Err(
+            ServerError::invalid_query(format!("{} is not a supported dictionary format", format))
+                .with_hint("supported formats are csv and xlsx")
+                .into()
+        ),
+    }
+}
@@ -0,0 +1,153 @@
+use actix_web::{
+    AsyncResponder,
+    FutureResponse,
+    HttpRequest,
+    HttpResponse,
+    Path,
+    ResponseError,
+};
+use futures::future::Future;
+use lazy_static::lazy_static;
+use log::*;
+use serde_derive::Deserialize;
+use serde_qs as qs;
+use std::convert::TryInto;
+use tesseract_core::format::{format_records_bytes, FormatType};
+use tesseract_core::Query as TsQuery;
+
+use crate::app::AppState;
+use crate::errors::ServerError;
+use crate::util::{boxed_error, format_error};
+use super::aggregate::{AggregateQueryOpt, AGGREGATE_PARAMS};
+use super::util;
+
+macro_rules! ok_or_error {
+    ($expr:expr, $to_err:expr) => {
+        match $expr {
+            Ok(val) => val,
+            Err(err) => return boxed_error($to_err(err)),
+        }
+    };
+}
+
+const DEFAULT_BUCKETS: u32 = 10;
+const MAX_BUCKETS: u32 = 1000;
+
+#[derive(Debug, Deserialize)]
+struct DistributionOpt {
+    measure: String,
+    buckets: Option<u32>,
+}
+
+/// Handles distribution queries when a format is not specified.
+/// Default format is CSV.
+pub fn distribution_default_handler(
+    (req, cube): (HttpRequest<AppState>, Path<String>)
+    ) -> FutureResponse<HttpResponse>
+{
+    let cube_format = (cube.into_inner(), "csv".to_owned());
+    do_distribution(req, cube_format)
+}
+
+/// Handles distribution queries when a format is specified.
+pub fn distribution_handler(
+    (req, cube_format): (HttpRequest<AppState>, Path<(String, String)>)
+    ) -> FutureResponse<HttpResponse>
+{
+    do_distribution(req, cube_format.into_inner())
+}
+
+/// Histogram of a measure over the rows of an aggregate query, computed by
+/// the backend. Takes the same query params as the aggregate endpoint, plus
+/// `measure` and `buckets` (default 10).
+pub fn do_distribution(
+    req: HttpRequest<AppState>,
+    cube_format: (String, String),
+    ) -> FutureResponse<HttpResponse>
+{
+    let (cube, format) = cube_format;
+
+    let format = format.parse::<FormatType>();
+    let format = ok_or_error!(format, format_error);
+
+    info!("Distribution for cube: {}, format: {:?}", cube, format);
+
+    let query = req.query_string();
+    lazy_static!{
+        static ref QS_NON_STRICT: qs::Config = qs::Config::new(5, false);
+    }
+    ok_or_error!(
+        util::check_params(
+            req.state(),
+            &query,
+            AGGREGATE_PARAMS,
+            |key| key == "measure" || key == "buckets",
+            vec!["measure", "buckets"],
+        ),
+        ServerError::from_query_error
+    );
+    let distribution_opt = ok_or_error!(QS_NON_STRICT.deserialize_str::<DistributionOpt>(&query), ServerError::invalid_query);
+    let mut agg_query = ok_or_error!(QS_NON_STRICT.deserialize_str::<AggregateQueryOpt>(&query), ServerError::invalid_query);
+
+    let buckets = distribution_opt.buckets.unwrap_or(DEFAULT_BUCKETS);
+    if buckets == 0 || buckets > MAX_BUCKETS {
+        return boxed_error(ServerError::invalid_query(
+            format!("buckets must be between 1 and {}", MAX_BUCKETS)
+        ));
+    }
+
+    ok_or_error!(agg_query.distribution_of(distribution_opt.measure), ServerError::invalid_query);
+
+    let query_settings = agg_query.settings.clone();
+    let hints = ok_or_error!(agg_query.hints(req.state().env_vars.max_query_priority), ServerError::invalid_query);
+
+    let ts_query: Result<TsQuery, _> = agg_query.try_into();
+    let mut ts_query = ok_or_error!(ts_query, ServerError::invalid_query);
+    ts_query.role = util::request_role(&req);
+
+    let sql_headers = {
+        let schema = req.state().schema.read().unwrap();
+        ok_or_error!(schema.get_cube_by_name(&cube), ServerError::not_found);
+        // the bucket bounds would reveal masked values
+        if ok_or_error!(schema.masking_plan(&cube, &mut ts_query), ServerError::invalid_query).is_some() {
+            return boxed_error(ServerError::invalid_query("distribution cannot be used with masked measures"));
+        }
+        util::generate_sql(req.state(), &schema, &cube, &ts_query)
+    };
+    let (sql, _headers) = ok_or_error!(sql_headers, ServerError::from_query_error);
+
+    let sql = ok_or_error!(
+        req.state().backend.distribution_sql(sql, buckets),
+        ServerError::invalid_query
+    );
+
+    let sql = ok_or_error!(
+        util::apply_clickhouse_settings(req.state(), query_settings.as_ref().map(|s| s.as_str()), &hints, sql),
+        ServerError::invalid_query
+    );
+
+    info!("Sql query: {}", sql);
+
+    util::exec_sql(req.state(), &cube, hints.priority, sql)
+        .and_then(move |mut df| {
+            let headers: Vec<String> = ["Bucket", "Lower", "Upper", "Count"].iter()
+                .map(|h| h.to_string())
+                .collect();
+            df.rename_columns(&headers)?;
+
+            let content_type = util::format_to_content_type(&format);
+
+            match format_records_bytes(&headers, df, format) {
+                Ok(res) => {
+                    Ok(HttpResponse::Ok()
+                        .set(content_type)
+                        .body(res))
+                },
+                Err(err) => Ok(ServerError::Internal { cause: err.to_string() }.error_response()),
+            }
+        })
+        .map_err(move |e| {
+            ServerError::from_backend_error(e, req.state().debug).into()
+        })
+        .responder()
+}
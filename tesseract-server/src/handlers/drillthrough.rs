@@ -0,0 +1,147 @@
+use actix_web::{
+    AsyncResponder,
+    FutureResponse,
+    HttpRequest,
+    HttpResponse,
+    Path,
+    ResponseError,
+};
+use failure::Error;
+use futures::future::Future;
+use lazy_static::lazy_static;
+use log::*;
+use serde_derive::Deserialize;
+use serde_qs as qs;
+use tesseract_core::format::{format_records_bytes, FormatType};
+use tesseract_core::names::Cut;
+
+use crate::app::AppState;
+use crate::errors::ServerError;
+use crate::query_hints::QueryHints;
+use crate::util::{boxed_error, format_error};
+use super::util;
+
+macro_rules! ok_or_error {
+    ($expr:expr, $to_err:expr) => {
+        match $expr {
+            Ok(val) => val,
+            Err(err) => return boxed_error($to_err(err)),
+        }
+    };
+}
+
+const DEFAULT_LIMIT: u64 = 100;
+const MAX_LIMIT: u64 = 10000;
+
+/// Query params accepted by `DrillthroughOpt`, for strict param checking.
+const DRILLTHROUGH_PARAMS: &[&str] = &["cuts", "limit", "timeout", "priority"];
+
+#[derive(Debug, Deserialize)]
+struct DrillthroughOpt {
+    cuts: Option<Vec<String>>,
+    limit: Option<u64>,
+    timeout: Option<u64>,
+    priority: Option<String>,
+}
+
+impl DrillthroughOpt {
+    fn cuts(&self) -> Result<Vec<Cut>, Error> {
+        self.cuts.iter()
+            .flatten()
+            .map(|c| c.parse())
+            .collect()
+    }
+}
+
+/// Handles drillthrough queries when a format is not specified.
+/// Default format is CSV.
+pub fn drillthrough_default_handler(
+    (req, cube): (HttpRequest<AppState>, Path<String>)
+    ) -> FutureResponse<HttpResponse>
+{
+    let cube_format = (cube.into_inner(), "csv".to_owned());
+    do_drillthrough(req, cube_format)
+}
+
+/// Handles drillthrough queries when a format is specified.
+pub fn drillthrough_handler(
+    (req, cube_format): (HttpRequest<AppState>, Path<(String, String)>)
+    ) -> FutureResponse<HttpResponse>
+{
+    do_drillthrough(req, cube_format.into_inner())
+}
+
+/// Fact rows behind an aggregate, with the cube's drillthrough columns.
+/// Takes the same `cuts` as the aggregate endpoint, plus `limit` (default
+/// 100) on the number of rows.
+pub fn do_drillthrough(
+    req: HttpRequest<AppState>,
+    cube_format: (String, String),
+    ) -> FutureResponse<HttpResponse>
+{
+    let (cube, format) = cube_format;
+
+    let format = format.parse::<FormatType>();
+    let format = ok_or_error!(format, format_error);
+
+    info!("Drillthrough for cube: {}, format: {:?}", cube, format);
+
+    let query = req.query_string();
+    lazy_static!{
+        static ref QS_NON_STRICT: qs::Config = qs::Config::new(5, false);
+    }
+    ok_or_error!(
+        util::check_params(req.state(), &query, DRILLTHROUGH_PARAMS, |_| false, std::iter::empty()),
+        ServerError::from_query_error
+    );
+    let drillthrough_opt = ok_or_error!(QS_NON_STRICT.deserialize_str::<DrillthroughOpt>(&query), ServerError::invalid_query);
+
+    let limit = drillthrough_opt.limit.unwrap_or(DEFAULT_LIMIT);
+    if limit == 0 || limit > MAX_LIMIT {
+        return boxed_error(ServerError::invalid_query(
+            format!("limit must be between 1 and {}", MAX_LIMIT)
+        ));
+    }
+
+    let cuts = ok_or_error!(drillthrough_opt.cuts(), ServerError::invalid_query);
+    let hints = ok_or_error!(
+        QueryHints::parse(drillthrough_opt.timeout, drillthrough_opt.priority.as_ref().map(|p| p.as_str()), req.state().env_vars.max_query_priority),
+        ServerError::invalid_query
+    );
+
+    let role = util::request_role(&req);
+
+    let sql_headers = {
+        let schema = req.state().schema.read().unwrap();
+        ok_or_error!(schema.get_cube_by_name(&cube), ServerError::not_found);
+        schema.drillthrough_sql(&cube, &cuts, role.as_ref().map(|r| r.as_str()), limit)
+    };
+    let (sql, headers) = ok_or_error!(sql_headers, ServerError::invalid_query);
+
+    let sql = ok_or_error!(
+        util::apply_clickhouse_settings(req.state(), None, &hints, sql),
+        ServerError::invalid_query
+    );
+
+    info!("Sql query: {}", sql);
+
+    util::exec_sql(req.state(), &cube, hints.priority, sql)
+        .and_then(move |mut df| {
+            df.rename_columns(&headers)?;
+
+            let content_type = util::format_to_content_type(&format);
+
+            match format_records_bytes(&headers, df, format) {
+                Ok(res) => {
+                    Ok(HttpResponse::Ok()
+                        .set(content_type)
+                        .body(res))
+                },
+                Err(err) => Ok(ServerError::Internal { cause: err.to_string() }.error_response()),
+            }
+        })
+        .map_err(move |e| {
+            ServerError::from_backend_error(e, req.state().debug).into()
+        })
+        .responder()
+}
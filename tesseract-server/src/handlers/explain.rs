@@ -0,0 +1,124 @@
+use actix_web::{
+    AsyncResponder,
+    FutureResponse,
+    HttpRequest,
+    HttpResponse,
+    Path,
+};
+use futures::future::Future;
+use lazy_static::lazy_static;
+use log::*;
+use serde_derive::{Serialize, Deserialize};
+use serde_qs as qs;
+use std::convert::TryInto;
+use tesseract_core::Query as TsQuery;
+
+use crate::app::AppState;
+use crate::errors::ServerError;
+use crate::util::boxed_error;
+use super::aggregate::{AggregateQueryOpt, AGGREGATE_PARAMS};
+use super::util;
+
+macro_rules! ok_or_error {
+    ($expr:expr, $to_err:expr) => {
+        match $expr {
+            Ok(val) => val,
+            Err(err) => return boxed_error($to_err(err)),
+        }
+    };
+}
+
+#[derive(Debug, Deserialize)]
+struct ExplainOpt {
+    explain: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct ExplainResponse {
+    sql: String,
+    explain: Vec<String>,
+}
+
+/// Runs the backend's EXPLAIN on the sql generated for an aggregate query.
+/// Takes the same query params as the aggregate endpoint, plus `explain`
+/// for the kind of explain (for ClickHouse: `plan` (default), `pipeline`
+/// or `syntax`).
+///
+/// Only available when the server is in debug mode, since it exposes
+/// the generated sql.
+pub fn explain_handler(
+    (req, cube): (HttpRequest<AppState>, Path<String>)
+    ) -> FutureResponse<HttpResponse>
+{
+    if !req.state().debug {
+        return boxed_error(
+            ServerError::not_found("explain is only available in debug mode")
+        );
+    }
+
+    let cube = cube.into_inner();
+    info!("Explain for cube: {}", cube);
+
+    let query = req.query_string();
+    lazy_static!{
+        static ref QS_NON_STRICT: qs::Config = qs::Config::new(5, false);
+    }
+    ok_or_error!(
+        util::check_params(req.state(), &query, AGGREGATE_PARAMS, |key| key == "explain", vec!["explain"]),
+        ServerError::from_query_error
+    );
+    let explain_opt = ok_or_error!(QS_NON_STRICT.deserialize_str::<ExplainOpt>(&query), ServerError::invalid_query);
+    let mut agg_query = ok_or_error!(QS_NON_STRICT.deserialize_str::<AggregateQueryOpt>(&query), ServerError::invalid_query);
+
+    {
+        let schema = req.state().schema.read().unwrap();
+        let cube = ok_or_error!(schema.get_cube_by_name(&cube), ServerError::not_found);
+        ok_or_error!(agg_query.members_with_data(cube), ServerError::invalid_query);
+    }
+
+    let query_settings = agg_query.settings.clone();
+    let hints = ok_or_error!(agg_query.hints(req.state().env_vars.max_query_priority), ServerError::invalid_query);
+
+    let ts_query: Result<TsQuery, _> = agg_query.try_into();
+    let mut ts_query = ok_or_error!(ts_query, ServerError::invalid_query);
+    ts_query.role = util::request_role(&req);
+
+    let sql_headers = {
+        let schema = req.state().schema.read().unwrap();
+        ok_or_error!(schema.get_cube_by_name(&cube), ServerError::not_found);
+        util::generate_sql(req.state(), &schema, &cube, &ts_query)
+    };
+    let (sql, _headers) = ok_or_error!(sql_headers, ServerError::from_query_error);
+
+    let sql = ok_or_error!(
+        util::apply_clickhouse_settings(req.state(), query_settings.as_ref().map(|s| s.as_str()), &hints, sql),
+        ServerError::invalid_query
+    );
+
+    info!("Sql query: {}", sql);
+
+    req.state()
+        .backend
+        .explain_sql(sql.clone(), explain_opt.explain.as_ref().map(|s| s.as_str()))
+        .and_then(move |df| {
+            // one line of explain output per row; columns are tab separated
+            let columns: Vec<_> = df.columns.iter()
+                .map(|col| col.column_data.stringify())
+                .collect();
+
+            let explain = (0..df.len())
+                .map(|i| {
+                    columns.iter()
+                        .map(|col| col[i].as_str())
+                        .collect::<Vec<_>>()
+                        .join("\t")
+                })
+                .collect();
+
+            Ok(HttpResponse::Ok().json(ExplainResponse { sql, explain }))
+        })
+        .map_err(|e| {
+            ServerError::Db { cause: e.to_string() }.into()
+        })
+        .responder()
+}
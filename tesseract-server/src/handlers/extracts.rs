@@ -0,0 +1,49 @@
+use actix_web::{
+    HttpRequest,
+    HttpResponse,
+    Path,
+    Result as ActixResult,
+};
+use std::fs;
+
+use crate::app::AppState;
+use crate::errors::ServerError;
+use crate::extracts::format_extension;
+use super::util;
+
+
+/// Serves the latest artifact of an extract (see `extracts`).
+pub fn extracts_handler(
+    (req, name): (HttpRequest<AppState>, Path<String>)
+    ) -> ActixResult<HttpResponse>
+{
+    let name = name.into_inner();
+
+    let extract = req.state().env_vars.extracts.iter()
+        .find(|extract| extract.name == name)
+        .ok_or_else(|| ServerError::not_found(format!("Extract {} not found", name)))?;
+
+    // an extract only has the rows of its role
+    if let Some(ref security) = req.state().schema.read().unwrap().security {
+        let role = |role: Option<String>| role.or_else(|| security.default_role.clone());
+        if role(util::request_role(&req)) != role(extract.role.clone()) {
+            return Err(ServerError::Unauthorized.into());
+        }
+    }
+
+    let path = extract.path();
+    if !path.exists() {
+        return Err(ServerError::not_found(format!("Extract {} has not run yet", name)).into());
+    }
+
+    let body = fs::read(&path)
+        .map_err(|err| ServerError::Internal { cause: err.to_string() })?;
+
+    Ok(HttpResponse::Ok()
+        .set(util::format_to_content_type(&extract.format))
+        .header(
+            "Content-Disposition",
+            format!("attachment; filename=\"{}.{}\"", extract.name, format_extension(&extract.format)),
+        )
+        .body(body))
+}
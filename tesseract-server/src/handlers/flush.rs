@@ -3,6 +3,8 @@ use serde_derive::{Serialize, Deserialize};
 use lazy_static::lazy_static;
 use log::*;
 use serde_qs as qs;
+use tesseract_core::Backend;
+use tesseract_core::schema::{lint, Diagnostic};
 
 use actix_web::{
     HttpRequest,
@@ -11,6 +13,7 @@ use actix_web::{
 };
 
 use crate::app::{AppState, SchemaSource};
+use crate::errors::ServerError;
 use crate::schema_config;
 
 
@@ -19,6 +22,13 @@ pub struct FlushQueryOpt {
     pub secret: String,
 }
 
+/// Lint diagnostics of the new schema, so that whoever flushes it sees
+/// likely mistakes.
+#[derive(Debug, Serialize)]
+struct FlushResponse {
+    diagnostics: Vec<Diagnostic>,
+}
+
 pub fn flush_handler(req: HttpRequest<AppState>) -> ActixResult<HttpResponse> {
     let query = req.query_string();
     lazy_static!{
@@ -28,13 +38,13 @@ pub fn flush_handler(req: HttpRequest<AppState>) -> ActixResult<HttpResponse> {
     let query = match query_res {
         Ok(q) => q,
         Err(err) => {
-            return Ok(HttpResponse::BadRequest().json(err.to_string()));
+            return Err(ServerError::invalid_query(err).into());
         },
     };
 
     let db_secret = match &req.state().env_vars.flush_secret {
         Some(db_secret) => db_secret,
-        None => { return Ok(HttpResponse::Unauthorized().finish()); }
+        None => { return Err(ServerError::Unauthorized.into()); }
     };
 
     if query.secret == *db_secret {
@@ -46,18 +56,32 @@ pub fn flush_handler(req: HttpRequest<AppState>) -> ActixResult<HttpResponse> {
             SchemaSource::LocalSchema { ref filepath } => filepath,
             SchemaSource::RemoteSchema { ref endpoint } => endpoint,
         };
-        let schema = match schema_config::read_schema(&schema_path) {
+        let mut schema = match schema_config::read_schema(&schema_path) {
             Ok(val) => val,
-            Err(err) => {
-                error!("{}", err);
-                return Ok(HttpResponse::InternalServerError().finish());
-            },
+            Err(err) => return Err(ServerError::Internal { cause: err.to_string() }.into()),
         };
 
+        let backend = &req.state().backend;
+        schema.set_time_grain_sql(|grain, column| backend.time_grain_sql(grain, column));
+
+        let diagnostics = lint(&schema);
+        for diagnostic in &diagnostics {
+            warn!("Schema: {}", diagnostic);
+        }
+
         // Update shared schema
         let mut w = req.state().schema.write().unwrap();
         *w = schema.clone();
 
+        // Sql and metadata generated with the old schema can't be reused. The
+        // schema lock is still held, so no requests can cache them in between.
+        req.state().sql_cache.write().unwrap().invalidate();
+        req.state().metadata_cache.write().unwrap().invalidate();
+
+        for (name, err) in req.state().saved_queries.read().unwrap().invalid(&schema) {
+            warn!("Saved query {} doesn't run on the new schema: {}", name, err);
+        }
+
         // TODO: Uncomment when issue with SystemRunner is solved
 //        // Re-populate cache with the new schema
 //        let cache = match populate_cache(schema, req.state().backend.clone()) {
@@ -72,8 +96,8 @@ pub fn flush_handler(req: HttpRequest<AppState>) -> ActixResult<HttpResponse> {
 //        let mut w = req.state().cache.write().unwrap();
 //        *w = cache;
 
-        Ok(HttpResponse::Ok().finish())
+        Ok(HttpResponse::Ok().json(FlushResponse { diagnostics }))
     } else {
-        Ok(HttpResponse::Unauthorized().finish())
+        Err(ServerError::Unauthorized.into())
     }
 }
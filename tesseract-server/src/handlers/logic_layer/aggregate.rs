@@ -7,25 +7,33 @@ use actix_web::{
     HttpRequest,
     HttpResponse,
     Path,
+    ResponseError,
 };
 use failure::{Error, format_err, bail};
 use futures::future::*;
+use futures::stream::{self, Stream};
 use lazy_static::lazy_static;
 use log::*;
 use serde_qs as qs;
 use serde_derive::Deserialize;
-use url::Url;
+use serde_json::{json, Value};
+use url::{form_urlencoded, Url};
 
-use tesseract_core::names::{Cut, Drilldown, Property, Measure, LevelName, Mask};
-use tesseract_core::format::{format_records, FormatType};
+use tesseract_core::names::{Cut, Drilldown, Property, Measure, LevelName, Mask, suggestion};
+use tesseract_core::format::{format_jsonrecords_with_metadata, format_records_bytes, FormatType};
 use tesseract_core::query::{FilterQuery, GrowthQuery, RcaQuery, TopQuery, RateQuery};
-use tesseract_core::{Query as TsQuery, MeaOrCalc, DataFrame, Column, ColumnData, is_same_columndata_type};
-use tesseract_core::schema::{Cube, DimensionType};
+use tesseract_core::{columns_first, Query as TsQuery, MeaOrCalc, DataFrame};
+use tesseract_core::masking::MaskingPlan;
+use tesseract_core::schema::{Cube, DefaultTime, DimensionType};
+use tesseract_core::query_ir::MemberType;
 
 use crate::app::AppState;
+use crate::conversion::{Conversion, CONVERSION_HEADER};
+use crate::db_config::Database;
 use crate::errors::ServerError;
-use crate::logic_layer::{LogicLayerConfig, CubeCache, Time};
-use crate::util::boxed_error;
+use crate::logic_layer::{LogicLayerConfig, CubeCache, DimensionCache, Time};
+use crate::query_hints::QueryHints;
+use crate::util::{boxed_error, format_error};
 use super::super::util;
 use crate::handlers::logic_layer::{query_geoservice, GeoserviceQuery};
 
@@ -49,6 +57,15 @@ pub fn logic_layer_handler(
 }
 
 
+/// Query params accepted by `LogicLayerQueryOpt`, other than cuts, for
+/// strict param checking.
+pub const LOGIC_LAYER_PARAMS: &[&str] = &[
+    "cube", "drilldowns", "time", "measures", "properties", "filters",
+    "parents", "top", "top_where", "sort", "limit", "growth", "rca", "debug",
+    "exclude_default_members", "locale", "sparse", "rate", "round", "settings",
+    "convert", "timeout", "priority", "column_order", "echo",
+];
+
 #[derive(Debug, Clone, Deserialize)]
 pub struct LogicLayerQueryOpt {
     pub cube: String,
@@ -73,6 +90,13 @@ pub struct LogicLayerQueryOpt {
     //    nonempty: Option<bool>,
     sparse: Option<bool>,
     rate: Option<String>,
+    round: Option<u32>,
+    settings: Option<String>,
+    convert: Option<String>,
+    timeout: Option<u64>,
+    priority: Option<String>,
+    column_order: Option<String>,
+    echo: Option<bool>,
 }
 
 
@@ -110,6 +134,30 @@ impl LogicLayerQueryOpt {
 }
 
 
+/// Merges repeated cut params in a query string, which would otherwise
+/// lose all but one value when flattened into `cuts`: `Year=2019&Year=2020`
+/// becomes `Year=2019;2020`. Each repeated value becomes its own `;`
+/// group, so `include:` and `exclude:` prefixes are kept.
+pub fn merge_repeated_cuts(query: &str) -> String {
+    let mut params: Vec<(String, Vec<String>)> = vec![];
+
+    for (key, value) in form_urlencoded::parse(query.as_bytes()) {
+        let is_cut = !LOGIC_LAYER_PARAMS.contains(&&*key);
+
+        match params.iter_mut().find(|(k, _)| is_cut && *k == key) {
+            Some((_, values)) => values.push(value.into_owned()),
+            None => params.push((key.into_owned(), vec![value.into_owned()])),
+        }
+    }
+
+    let mut serializer = form_urlencoded::Serializer::new(String::new());
+    for (key, values) in &params {
+        serializer.append_pair(key, &values.join(";"));
+    }
+    serializer.finish()
+}
+
+
 /// Performs data aggregation.
 pub fn logic_layer_aggregation(
     req: HttpRequest<AppState>,
@@ -119,14 +167,111 @@ pub fn logic_layer_aggregation(
     let format = format.parse::<FormatType>();
     let format = match format {
         Ok(f) => f,
-        Err(err) => return boxed_error(err.to_string()),
+        Err(err) => return boxed_error(format_error(err)),
     };
 
     info!("Format: {:?}", format);
 
+    let debug = req.state().debug;
+
+    let ll_sql = match logic_layer_sql(&req) {
+        Ok(ll_sql) => ll_sql,
+        Err(err) => return boxed_error(ServerError::from_query_error(err))
+    };
+    let LogicLayerSql { sql_strings, query_headers, headers: final_headers, localized_headers, column_order, masking, precisions, round, conversions, cube, hints, echo } = ll_sql;
+
+    match format {
+        FormatType::JsonRecords => (),
+        _ if echo.is_some() => {
+            return boxed_error(ServerError::invalid_query("echo is only available for the jsonrecords format"));
+        },
+        _ => (),
+    }
+
+    // Runs the query of each cut combination, at most `concurrency` at a
+    // time, keeping their order
+    let concurrency = req.state().env_vars.logic_layer_concurrency;
+    let exec_req = req.clone();
+    let futs = stream::iter_ok::<_, Error>(sql_strings)
+        .map(move |sql| util::exec_sql(exec_req.state(), &cube, hints.priority, sql))
+        .buffered(concurrency)
+        .collect();
+
+    // Process data received once all futures are resolved and return response
+    futs
+        .and_then(move |dfs| {
+            let mut final_df = DataFrame::concat(dfs)?;
+            if let Some(ref plan) = masking {
+                final_df.rename_columns(&query_headers)?;
+                plan.apply(&mut final_df)?;
+            }
+            final_df.rename_columns(&final_headers)?;
+            final_df.scale_columns(&Conversion::factors(&conversions));
+            final_df.round_columns(&precisions, round);
+            final_df.rename_columns(&localized_headers)?;
+            final_df.reorder_columns(&column_order)?;
+
+            let headers = final_df.column_names();
+            let content_type = util::format_to_content_type(&format);
+
+            let formatted = match echo {
+                Some(echo) => format_jsonrecords_with_metadata(&headers, final_df, &json!({ "queries": echo })),
+                None => format_records_bytes(&headers, final_df, format),
+            };
+
+            match formatted {
+                Ok(res) => {
+                    let mut response = HttpResponse::Ok();
+                    response.set(content_type);
+                    if !conversions.is_empty() {
+                        response.header(CONVERSION_HEADER, Conversion::header_value(&conversions));
+                    }
+                    Ok(response.body(res))
+                },
+                Err(err) => Ok(ServerError::Internal { cause: err.to_string() }.error_response()),
+            }
+        })
+        .map_err(move |e| {
+            ServerError::from_backend_error(e, debug).into()
+        })
+        .responder()
+}
+
+
+/// SQL and response headers generated from a logic layer query.
+/// Shared by the buffered and streaming aggregation handlers.
+pub struct LogicLayerSql {
+    /// One SQL query per cut combination, or a single one running all of
+    /// them when the backend can
+    pub sql_strings: Vec<String>,
+    /// Headers of the queries' results, as generated for them
+    pub query_headers: Vec<String>,
+    /// Headers of the response, with level aliases substituted, after
+    /// masking (if any)
+    pub headers: Vec<String>,
+    /// `headers` with measure names localized for `locale`, for the response
+    pub localized_headers: Vec<String>,
+    /// Positions of the columns in the response (see `Schema::column_order`)
+    pub column_order: Vec<usize>,
+    /// Masking of the cube's sensitive measures, applied to the results
+    /// named with `query_headers`
+    pub masking: Option<MaskingPlan>,
+    pub precisions: HashMap<String, u32>,
+    pub round: Option<u32>,
+    /// Measure conversions requested with `convert`
+    pub conversions: Vec<Conversion>,
+    pub cube: String,
+    pub hints: QueryHints,
+    /// With `echo=true`, the queries generated, one per cut combination
+    pub echo: Option<Value>,
+}
+
+
+/// Parses the logic layer query in the request and generates
+/// the SQL for each of the resulting Tesseract queries.
+pub fn logic_layer_sql(req: &HttpRequest<AppState>) -> Result<LogicLayerSql, Error> {
     let query = req.query_string();
     let schema = req.state().schema.read().unwrap();
-    let debug = req.state().debug;
 
     let logic_layer_config: Option<LogicLayerConfig> = match &req.state().logic_layer_config {
         Some(llc) => Some(llc.read().unwrap().clone()),
@@ -137,10 +282,8 @@ pub fn logic_layer_aggregation(
         static ref QS_NON_STRICT: qs::Config = qs::Config::new(5, false);
     }
 
-    let agg_query = match QS_NON_STRICT.deserialize_str::<LogicLayerQueryOpt>(query) {
-        Ok(q) => q,
-        Err(err) => return boxed_error(err.to_string())
-    };
+    let agg_query = QS_NON_STRICT.deserialize_str::<LogicLayerQueryOpt>(&merge_repeated_cuts(query))
+        .map_err(|err| format_err!("{}", err))?;
 
     // Check to see if the logic layer config has a alias with the
     // provided cube name
@@ -154,191 +297,237 @@ pub fn logic_layer_aggregation(
         None => agg_query.cube.clone()
     };
 
-    let cube = match schema.get_cube_by_name(&cube_name) {
-        Ok(c) => c,
-        Err(err) => return boxed_error(err.to_string())
-    };
+    let cube = schema.get_cube_by_name(&cube_name)
+        .map_err(ServerError::not_found)?;
 
-    let cube_cache = match req.state().cache.read().unwrap().find_cube_info(&cube_name) {
+    let cube_cache = match req.state().cache.read().unwrap().find_cube_info(&cube.name) {
         Some(cube_cache) => cube_cache,
-        None => return boxed_error("Unable to access cube cache".to_string())
+        None => return Err(ServerError::Internal { cause: "Unable to access cube cache".to_owned() }.into())
     };
 
+    // cut params are keyed by level or dimension name
+    util::check_params(
+        req.state(),
+        query,
+        LOGIC_LAYER_PARAMS,
+        // an ambiguous key is reported when resolving the cut
+        |key| {
+            cube_cache.find_level(key).map_or(true, |l| l.is_some()) ||
+            cube_cache.name_resolution.get(&cube_cache.dimension_caches, key).map_or(true, |d| d.is_some())
+        },
+        cube_cache.level_map.keys()
+            .chain(cube_cache.dimension_caches.keys())
+            .map(|k| k.as_str()),
+    )?;
+
     info!("Aggregate query: {:?}", agg_query);
 
     // Turn AggregateQueryOpt into TsQuery
-    let ts_queries = generate_ts_queries(
+    let (mut ts_queries, header_map) = generate_ts_queries(
         agg_query.clone(), &cube, &cube_cache,
-        &logic_layer_config, &req.state().env_vars.geoservice_url
-    );
-    let (ts_queries, header_map) = match ts_queries {
-        Ok((ts_queries, header_map)) => (ts_queries, header_map),
-        Err(err) => return boxed_error(err.to_string())
-    };
+        &logic_layer_config, &req.state().env_vars.geoservice_url,
+        req.state().env_vars.logic_layer_max_combinations,
+    )?;
+
+    let role = util::request_role(&req);
+    for ts_query in ts_queries.iter_mut() {
+        ts_query.role = role.clone();
+    }
 
     if ts_queries.len() == 0 {
-        return boxed_error("Unable to generate queries".to_string())
+        bail!("Unable to generate queries");
     }
 
-    let mut sql_strings: Vec<String> = vec![];
-    let mut final_headers: Vec<String> = vec![];
+    // the queries have the same measures, so the measures added for masking
+    // are the same; the plan is that of the first query, whose headers name
+    // the merged results
+    let masking = schema.masking_plan(&cube.name, &mut ts_queries[0])
+        .map_err(ServerError::invalid_query)?;
+    let masked_measures = ts_queries[0].measures.clone();
+    for ts_query in ts_queries.iter_mut().skip(1) {
+        ts_query.measures = masked_measures.clone();
+    }
+
+    let hints = QueryHints::parse(agg_query.timeout, agg_query.priority.as_ref().map(|p| p.as_str()), req.state().env_vars.max_query_priority)
+        .map_err(ServerError::invalid_query)?;
+
+    let mut sqls: Vec<String> = vec![];
+    let mut first_headers: Vec<String> = vec![];
+    let mut first_combination_columns = None;
+    let mut same_columns = true;
 
     for ts_query in &ts_queries {
         debug!("Tesseract query: {:?}", ts_query);
 
-        let query_ir_headers = req
-            .state()
-            .schema.read().unwrap()
-            .sql_query(&cube_name, &ts_query);
+        util::check_complexity(req.state(), &cube, &ts_query)
+            .map_err(ServerError::invalid_query)?;
 
-        let (query_ir, headers) = match query_ir_headers {
-            Ok(x) => x,
-            Err(err) => return boxed_error(err.to_string())
-        };
+        let (sql, headers) = util::generate_sql(req.state(), &schema, &cube.name, &ts_query)?;
 
-        debug!("Query IR: {:?}", query_ir);
+        let columns = combination_columns(&cube, ts_query, &headers, &header_map);
+        match first_combination_columns {
+            Some(ref first_columns) => same_columns &= columns == *first_columns,
+            None => first_combination_columns = Some(columns),
+        }
 
-        let sql = req.state()
-            .backend
-            .generate_sql(query_ir);
+        if first_headers.is_empty() {
+            first_headers = headers;
+        }
 
-        debug!("SQL query: {}", sql);
+        sqls.push(sql);
+    }
 
-        // Substitute header names (only need to do this once)
-        if final_headers.len() == 0 {
-            for header in &headers {
-                let mut new_header = header.clone();
+    let output_headers = match masking {
+        Some(ref plan) => plan.output_headers(&first_headers),
+        None => first_headers.clone(),
+    };
 
-                for (k, v) in header_map.iter() {
-                    if header.contains(k) {
-                        new_header = new_header.replace(k, v);
-                    }
-                }
+    // Substitute header names
+    let mut final_headers: Vec<String> = vec![];
+    for header in &output_headers {
+        let mut new_header = header.clone();
 
-                final_headers.push(new_header);
+        for (k, v) in header_map.iter() {
+            if header.contains(k) {
+                new_header = new_header.replace(k, v);
             }
         }
 
-        sql_strings.push(sql);
+        final_headers.push(new_header);
     }
 
-    debug!("Headers: {:?}", final_headers);
+    // With ClickHouse, the queries of all cut combinations run as a single
+    // query instead of being merged here, when their columns line up
+    let sqls = match req.state().db_type {
+        Database::Clickhouse if sqls.len() > 1 && same_columns => vec![union_all_sql(&sqls)],
+        _ => sqls,
+    };
 
-    // Joins all the futures for each TsQuery
-    let futs: JoinAll<Vec<Box<Future<Item=DataFrame, Error=Error>>>> = join_all(sql_strings
-            .iter()
-            .map(|sql| {
-                req.state()
-                    .backend
-                    .exec_sql(sql.clone())
-            })
-            .collect()
-        );
+    let mut sql_strings: Vec<String> = vec![];
 
-    // Process data received once all futures are resolved and return response
-    futs
-        .and_then(move |dfs| {
-            let mut final_columns: Vec<Column> = vec![];
+    for sql in sqls {
+        let sql = util::apply_clickhouse_settings(
+            req.state(),
+            agg_query.settings.as_ref().map(|s| s.as_str()),
+            &hints,
+            sql,
+        )?;
 
-            let num_cols = match dfs.get(0) {
-                Some(df) => df.columns.len(),
-                None => return Err(format_err!("No dataframes were returned."))
-            };
+        debug!("SQL query: {}", sql);
 
-            for col_i in 0..num_cols {
-                let mut same_type = true;
+        sql_strings.push(sql);
+    }
 
-                let first_col: &Column = match &dfs[0].columns.get(col_i) {
-                    Some(col) => col,
-                    None => return Err(format_err!("Unable to index column."))
-                };
+    debug!("Headers: {:?}", final_headers);
 
-                for df in &dfs {
-                    if !is_same_columndata_type(&first_col.column_data, &df.columns[col_i].column_data) {
-                        same_type = false;
-                        break;
-                    }
-                }
+    let conversions = util::conversions(
+        req.state(),
+        &schema,
+        &cube.name,
+        &ts_queries[0],
+        agg_query.convert.as_ref().map(|c| c.as_str()),
+    )?;
 
-                let mut col_data: Vec<String> = vec![];
+    let locale = agg_query.locale.as_ref()
+        .and_then(|locale| locale.split(",").next());
+    let localized_headers = localize_measure_headers(&final_headers, &cube, &ts_queries[0].measures, locale);
+
+    // default column order, with the columns in `column_order` first, named
+    // as in the response
+    let first_columns = agg_query.column_order.clone()
+        .map(LogicLayerQueryOpt::deserialize_args)
+        .unwrap_or_default();
+    let column_order = schema.column_order(&cube.name, &ts_queries[0], &output_headers)?;
+    let column_order = columns_first(&column_order, &localized_headers, &first_columns)
+        .map_err(ServerError::invalid_query)?;
+
+    // the queries as run, after aliases, named sets, time and defaults are
+    // resolved, so that the results can be reproduced with the aggregate
+    // endpoint even if the logic layer config changes
+    let echo = if agg_query.echo.unwrap_or(false) {
+        let resolved: Vec<TsQuery> = ts_queries.iter()
+            .map(|ts_query| schema.resolve_query(&cube, ts_query))
+            .collect();
+        Some(serde_json::to_value(resolved)?)
+    } else {
+        None
+    };
 
-                for df in &dfs {
-                    let c: &Column = &df.columns[col_i];
-                    let rows = c.stringify_column_data();
-                    col_data = [&col_data[..], &rows[..]].concat()
-                }
+    Ok(LogicLayerSql {
+        sql_strings,
+        query_headers: first_headers,
+        headers: final_headers,
+        localized_headers,
+        column_order,
+        masking,
+        precisions: cube.get_measure_precisions(),
+        round: agg_query.round,
+        conversions,
+        cube: cube.name.clone(),
+        hints,
+        echo,
+    })
+}
 
-                if same_type {
-                    let mut column_data: ColumnData = ColumnData::Text(col_data.clone());
 
-                    // TODO: Process nullable columns
-                    match first_col.column_data {
-                        ColumnData::Int8(_) => {
-                            column_data = ColumnData::Int8(col_data.iter().map(|x| x.parse::<i8>().unwrap()).collect());
-                        },
-                        ColumnData::Int16(_) => {
-                            column_data = ColumnData::Int16(col_data.iter().map(|x| x.parse::<i16>().unwrap()).collect());
-                        },
-                        ColumnData::Int32(_) => {
-                            column_data = ColumnData::Int32(col_data.iter().map(|x| x.parse::<i32>().unwrap()).collect());
-                        },
-                        ColumnData::Int64(_) => {
-                            column_data = ColumnData::Int64(col_data.iter().map(|x| x.parse::<i64>().unwrap()).collect());
-                        },
-                        ColumnData::UInt8(_) => {
-                            column_data = ColumnData::UInt8(col_data.iter().map(|x| x.parse::<u8>().unwrap()).collect());
-                        },
-                        ColumnData::UInt16(_) => {
-                            column_data = ColumnData::UInt16(col_data.iter().map(|x| x.parse::<u16>().unwrap()).collect());
-                        },
-                        ColumnData::UInt32(_) => {
-                            column_data = ColumnData::UInt32(col_data.iter().map(|x| x.parse::<u32>().unwrap()).collect());
-                        },
-                        ColumnData::UInt64(_) => {
-                            column_data = ColumnData::UInt64(col_data.iter().map(|x| x.parse::<u64>().unwrap()).collect());
-                        },
-                        ColumnData::Float32(_) => {
-                            column_data = ColumnData::Float32(col_data.iter().map(|x| x.parse::<f32>().unwrap()).collect());
-                        },
-                        ColumnData::Float64(_) => {
-                            column_data = ColumnData::Float64(col_data.iter().map(|x| x.parse::<f64>().unwrap()).collect());
-                        },
-                        _ => ()
-                    }
+/// Error for a param naming a level or measure that isn't in the cube,
+/// with the names that are valid in the hint.
+fn unknown_name_error<'a, I>(what: &str, name: &str, valid: I) -> Error
+    where I: IntoIterator<Item=&'a str>
+{
+    let mut valid: Vec<&str> = valid.into_iter().collect();
+    valid.sort();
+    valid.dedup();
+
+    ServerError::InvalidQuery {
+        cause: format!("Unable to find {} {}{}", what, name, suggestion(name, valid.iter().cloned())),
+        hint: Some(format!("Valid names are: {}", valid.join(", "))),
+        errors: vec![],
+    }.into()
+}
 
-                    final_columns.push(Column {
-                        name: "placeholder".to_string(),
-                        column_data
-                    });
-                } else {
-                    final_columns.push(Column {
-                        name: "placeholder".to_string(),
-                        column_data: ColumnData::Text(col_data)
-                    });
-                }
-            }
 
-            let final_df = DataFrame { columns: final_columns };
+/// The columns of a cut combination's results, as their headers (with the
+/// cut levels substituted by their dimensions, as in the response) and the
+/// key types of the drilldowns (non-text when the level has no `key_type`,
+/// as in the sql).
+///
+/// The cut combinations only differ in the levels they drill down on, so
+/// two combinations with the same columns return the same types: the
+/// measures are the same, and the captions and properties are text.
+fn combination_columns(
+    cube: &Cube,
+    ts_query: &TsQuery,
+    headers: &[String],
+    header_map: &HashMap<String, String>,
+) -> (Vec<String>, Vec<Option<MemberType>>)
+{
+    let headers = headers.iter()
+        .map(|header| {
+            header_map.iter().fold(header.clone(), |header, (k, v)| header.replace(k, v))
+        })
+        .collect();
+    let key_types = ts_query.drilldowns.iter()
+        .map(|drill| cube.get_level(&drill.0).map(|level| level.key_type.unwrap_or(MemberType::NonText)))
+        .collect();
 
-            let content_type = util::format_to_content_type(&format);
+    (headers, key_types)
+}
 
-            match format_records(&final_headers, final_df, format) {
-                Ok(res) => {
-                    Ok(HttpResponse::Ok()
-                        .set(content_type)
-                        .body(res))
-                },
-                Err(err) => Ok(HttpResponse::NotFound().json(err.to_string())),
-            }
+/// Combines the sql of each cut combination into a single query. Each query
+/// is wrapped in a subquery, so that its own `limit` still applies to it
+/// alone, and the rows are sorted by combination and then by their order
+/// in it, as when the results of separate queries are concatenated.
+fn union_all_sql(sqls: &[String]) -> String {
+    let union = sqls.iter()
+        .enumerate()
+        .map(|(i, sql)| {
+            format!("select *, {} as cut_combination, rowNumberInAllBlocks() as cut_combination_row from ({})", i, sql)
         })
-        .map_err(move |e| {
-            if debug {
-                ServerError::Db { cause: e.to_string() }.into()
-            } else {
-                ServerError::Db { cause: "Internal Server Error 1010".to_owned() }.into()
-            }
-        })
-        .responder()
+        .collect::<Vec<_>>()
+        .join(" union all ");
+
+    format!("select * except (cut_combination, cut_combination_row) from ({}) order by cut_combination, cut_combination_row", union)
 }
 
 
@@ -349,7 +538,8 @@ pub fn generate_ts_queries(
         cube: &Cube,
         cube_cache: &CubeCache,
         ll_config: &Option<LogicLayerConfig>,
-        geoservice_url: &Option<Url>
+        geoservice_url: &Option<Url>,
+        max_combinations: usize,
 ) -> Result<(Vec<TsQuery>, HashMap<String, String>), Error> {
 
     let level_map = &cube_cache.level_map;
@@ -363,10 +553,11 @@ pub fn generate_ts_queries(
 
     let mut cuts_map = clean_cuts_map(&agg_query_opt, &cube_cache, &ll_config)?;
 
+    let all_time = agg_query_opt.time.as_ref().map(|t| t == "all").unwrap_or(false);
     let parents = agg_query_opt.parents.unwrap_or(false);
 
     let drilldowns: Vec<_> = agg_query_opt.drilldowns
-        .map(|ds| {
+        .map(|ds| -> Result<_, Error> {
             let mut drilldowns: Vec<Drilldown> = vec![];
 
             for level_value in LogicLayerQueryOpt::deserialize_args(ds) {
@@ -386,15 +577,14 @@ pub fn generate_ts_queries(
                     None => level_value.clone()
                 };
 
-                let level_name = match level_map.get(&level_key) {
-                    Some(level_name) => level_name,
-                    None => break
-                };
+                let level_name = cube_cache.find_level(&level_key)?
+                    .ok_or_else(|| ServerError::not_found(format!("Unable to find drilldown {}{}",
+                        level_value,
+                        suggestion(&level_key, level_map.keys().map(|k| k.as_str())),
+                    )))?;
 
-                let level = match cube.get_level(level_name) {
-                    Some(level) => level,
-                    None => break
-                };
+                let level = cube.get_level(level_name)
+                    .ok_or_else(|| format_err!("Unable to find drilldown {} in cube {}", level_value, cube.name))?;
 
                 drilldowns.push(Drilldown(level_name.clone()));
 
@@ -409,43 +599,52 @@ pub fn generate_ts_queries(
                 }
             }
 
-            drilldowns
+            Ok(drilldowns)
         })
-        .unwrap_or(vec![]);
+        .unwrap_or(Ok(vec![]))?;
+
+    if !all_time {
+        if let Some((cut, cut_value)) = default_time_cut(cube, cube_cache, &drilldowns, &cuts_map)? {
+            cuts_map.insert(cut, cut_value);
+        }
+    }
 
     let measures: Vec<_> = agg_query_opt.measures
-        .map(|ms| {
+        .map(|ms| -> Result<_, Error> {
             let mut measures: Vec<Measure> = vec![];
 
             for measure in LogicLayerQueryOpt::deserialize_args(ms) {
-                let m = match measure.parse() {
-                    Ok(m) => m,
-                    Err(_) => break
+                let measure = match cube.resolve_measure_name(&measure, cube_cache.name_resolution) {
+                    Some(measure) => measure,
+                    None => bail!("Unable to find measure {}{}",
+                        measure,
+                        suggestion(&measure, cube.measures.iter().map(|m| m.name.as_str())),
+                    ),
                 };
-                measures.push(m);
+                measures.push(measure.parse()?);
             }
 
-            measures
+            Ok(measures)
         })
-        .unwrap_or(vec![]);
+        .unwrap_or(Ok(vec![]))?;
 
     let properties: Vec<_> = agg_query_opt.properties
-        .map(|ps| {
+        .map(|ps| -> Result<_, Error> {
             let mut properties: Vec<Property> = vec![];
 
             for property_value in LogicLayerQueryOpt::deserialize_args(ps) {
-                // TODO: Break or bail?
-                let property = match property_map.get(&property_value) {
-                    Some(p) => p,
-                    None => break
-                };
+                let property = cube_cache.find_property(&property_value)?
+                    .ok_or_else(|| format_err!("Unable to find property {}{}",
+                        property_value,
+                        suggestion(&property_value, property_map.keys().map(|k| k.as_str())),
+                    ))?;
 
                 properties.push(property.clone());
             }
 
-            properties
+            Ok(properties)
         })
-        .unwrap_or(vec![]);
+        .unwrap_or(Ok(vec![]))?;
 
     // TODO: Implement
     let filters: Vec<FilterQuery>= vec![];
@@ -458,7 +657,7 @@ pub fn generate_ts_queries(
                 return Err(format_err!("Bad formatting for top param."));
             }
 
-            let level_name = match level_map.get(&top_split[1]) {
+            let level_name = match cube_cache.find_level(&top_split[1])? {
                 Some(l) => l,
                 None => bail!("Unable to find top level")
             };
@@ -494,12 +693,11 @@ pub fn generate_ts_queries(
             }
 
             let level_key = gro_split[0].clone();
-            let measure = gro_split[1].clone();
 
-            let level_name = match level_map.get(&level_key) {
-                Some(l) => l,
-                None => bail!("Unable to find growth level")
-            };
+            let level_name = cube_cache.find_level(&level_key)?
+                .ok_or_else(|| unknown_name_error("growth level", &level_key, level_map.keys().map(|k| k.as_str())))?;
+            let measure = cube.resolve_measure_name(&gro_split[1], cube_cache.name_resolution)
+                .ok_or_else(|| unknown_name_error("growth measure", &gro_split[1], cube.measures.iter().map(|m| m.name.as_str())))?;
 
             let growth = GrowthQuery::new(
                 level_name.dimension.clone(),
@@ -523,17 +721,13 @@ pub fn generate_ts_queries(
 
             let drill1_level_key = rca_split[0].clone();
             let drill2_level_key = rca_split[1].clone();
-            let measure = rca_split[2].clone();
 
-            let level_name_1 = match level_map.get(&drill1_level_key) {
-                Some(l) => l,
-                None => bail!("Unable to find drill 1 level")
-            };
-
-            let level_name_2 = match level_map.get(&drill2_level_key) {
-                Some(l) => l,
-                None => bail!("Unable to find drill 2 level")
-            };
+            let level_name_1 = cube_cache.find_level(&drill1_level_key)?
+                .ok_or_else(|| unknown_name_error("rca drill 1 level", &drill1_level_key, level_map.keys().map(|k| k.as_str())))?;
+            let level_name_2 = cube_cache.find_level(&drill2_level_key)?
+                .ok_or_else(|| unknown_name_error("rca drill 2 level", &drill2_level_key, level_map.keys().map(|k| k.as_str())))?;
+            let measure = cube.resolve_measure_name(&rca_split[2], cube_cache.name_resolution)
+                .ok_or_else(|| unknown_name_error("rca measure", &rca_split[2], cube.measures.iter().map(|m| m.name.as_str())))?;
 
             let rca = RcaQuery::new(
                 level_name_1.dimension.clone(),
@@ -559,7 +753,7 @@ pub fn generate_ts_queries(
                 bail!("Bad formatting for rate calculation.");
             }
 
-            let level_name = match level_map.get(&level_value_split[0]) {
+            let level_name = match cube_cache.find_level(&level_value_split[0])? {
                 Some(level_name) => level_name.clone(),
                 None => bail!("Unrecognized level in rate calculation.")
             };
@@ -579,8 +773,11 @@ pub fn generate_ts_queries(
     // This is where all the different queries are ACTUALLY generated.
     // Everything before this is common to all queries being generated.
 
+    let exclusions = split_cut_exclusions(&mut cuts_map)?;
+    let exclusion_cuts = resolve_exclusions(&exclusions, &cube, &cube_cache)?;
+
     let (dimension_cuts_map, header_map) = resolve_cuts(
-        &cuts_map, &cube, &cube_cache, &level_map, &property_map, &geoservice_url
+        &cuts_map, &cube, &cube_cache, &property_map, &geoservice_url
     )?;
 
     // Groups together cuts for the same dimension
@@ -616,6 +813,19 @@ pub fn generate_ts_queries(
         dimension_cuts.push(inner_cuts);
     }
 
+    // Each combination is a query of its own, so refuse to build more
+    // than the server allows before computing them
+    let num_combinations = dimension_cuts.iter()
+        .fold(1usize, |acc, cuts| acc.saturating_mul(cuts.len()));
+
+    if !dimension_cuts.is_empty() && num_combinations > max_combinations {
+        return Err(ServerError::InvalidQuery {
+            cause: format!("Query expands into {} cut combinations, the limit is {}", num_combinations, max_combinations),
+            hint: Some("Cut on fewer levels of the same dimension, or split the query into several queries".to_owned()),
+            errors: vec![],
+        }.into());
+    }
+
     // All the different TsQuery's that need to be performed
     let mut queries: Vec<TsQuery> = vec![];
 
@@ -625,7 +835,8 @@ pub fn generate_ts_queries(
     if cut_combinations.len() == 0 {
         queries.push(TsQuery {
             drilldowns: drilldowns.clone(),
-            cuts: vec![],
+            property_drilldowns: vec![],
+            cuts: exclusion_cuts.clone(),
             measures: measures.clone(),
             parents: parents.clone(),
             properties: properties.clone(),
@@ -640,7 +851,13 @@ pub fn generate_ts_queries(
             exclude_default_members: exclude_default_members.clone(),
             filters: filters.clone(),
             rate: rate.clone(),
+            ratio_to_parent: None,
+            rate_against: None,
+            compare: None,
             sparse: sparse.clone(),
+            round: agg_query_opt.round,
+            fill: None,
+            role: None,
         });
     } else {
         // Create a TsQuery for each cut combination
@@ -653,10 +870,8 @@ pub fn generate_ts_queries(
                 if added_drilldowns.contains(&cut.level_name) {
                     drills.push(Drilldown(cut.level_name.clone()));
 
-                    let level = match cube.get_level(&cut.level_name) {
-                        Some(level) => level,
-                        None => break
-                    };
+                    let level = cube.get_level(&cut.level_name)
+                        .ok_or_else(|| format_err!("Unable to find cut level {} in cube {}", cut.level_name, cube.name))?;
 
                     // Add captions for this level
                     let new_captions = level.get_captions(&cut.level_name, &locales);
@@ -667,7 +882,8 @@ pub fn generate_ts_queries(
             // Populate queries vector
             queries.push(TsQuery {
                 drilldowns: drills,
-                cuts: cut_combination.clone(),
+                property_drilldowns: vec![],
+                cuts: cut_combination.iter().cloned().chain(exclusion_cuts.iter().cloned()).collect(),
                 measures: measures.clone(),
                 parents: parents.clone(),
                 properties: properties.clone(),
@@ -682,7 +898,13 @@ pub fn generate_ts_queries(
                 exclude_default_members: exclude_default_members.clone(),
                 filters: filters.clone(),
                 rate: rate.clone(),
+                ratio_to_parent: None,
+                rate_against: None,
+                compare: None,
                 sparse: sparse.clone(),
+                round: agg_query_opt.round,
+                fill: None,
+                role: None,
             });
         }
     }
@@ -724,6 +946,48 @@ pub fn cartesian_product<T: Clone>(lists: Vec<Vec<T>>) -> Vec<Vec<T>> {
 }
 
 
+/// For cubes with a `default_time`, the time cut to add to a query that
+/// neither drills down nor cuts on a time dimension (`time` params are
+/// already in the cuts).
+fn default_time_cut(
+        cube: &Cube,
+        cube_cache: &CubeCache,
+        drilldowns: &[Drilldown],
+        cuts_map: &HashMap<String, String>,
+) -> Result<Option<(String, String)>, Error> {
+    match cube.default_time {
+        Some(DefaultTime::Latest) => (),
+        None => return Ok(None),
+    }
+
+    let is_time_dim = |dim_name: &str| {
+        cube.dimensions.iter()
+            .filter(|dim| dim.dim_type == DimensionType::Time || dim.dim_type == DimensionType::GeneratedTime)
+            .any(|dim| cube_cache.name_resolution.matches(&dim.name, dim_name))
+    };
+
+    let drills_time = drilldowns.iter()
+        .any(|drill| is_time_dim(&drill.0.dimension));
+    // cut keys are level names, or dimension names for cuts on several
+    // levels. An ambiguous key is reported when resolving the cut.
+    let cuts_time = cuts_map.keys()
+        .any(|key| {
+            match cube_cache.find_level(key) {
+                Ok(Some(level_name)) => is_time_dim(&level_name.dimension),
+                _ => is_time_dim(key),
+            }
+        });
+
+    if drills_time || cuts_time {
+        return Ok(None);
+    }
+
+    cube_cache.latest_time_cut()
+        .map(Some)
+        .ok_or_else(|| format_err!("Cube {} has a default_time, but no time levels", cube.name))
+}
+
+
 /// Performs named set and time substitutions in the original cuts HashMap
 /// deserialized from the query.
 pub fn clean_cuts_map(
@@ -737,8 +1001,10 @@ pub fn clean_cuts_map(
         None => HashMap::new()
     };
 
-    // Process `time` param (latest/oldest)
+    // Process `time` param (latest/oldest). `time=all` only turns off the
+    // cube's `default_time`, see `default_time_cut`.
     match &agg_query_opt.time {
+        Some(time_param) if time_param == "all" => (),
         Some(time_param) => {
             let time_cuts: Vec<String> = time_param.split(",").map(|s| s.to_string()).collect();
 
@@ -765,39 +1031,208 @@ pub fn clean_cuts_map(
         None => ()
     };
 
-    // Find and perform any named set substitutions
+    // Find and perform any named set substitutions, in each of the cut's
+    // include and exclude groups (see `split_cut_exclusions`)
     for (cut_key, cut_values) in agg_query_opt_cuts.clone().iter() {
         if cut_values.is_empty() {
             continue;
         }
 
-        let mut final_cuts: Vec<String> = vec![];
+        let mut final_groups: Vec<String> = vec![];
 
-        let cut_values_split: Vec<String> = cut_values.split(",").map(|s| s.to_string()).collect();
+        for cut_group in cut_values.split(";") {
+            let (mask_prefix, cut_group) = split_mask_prefix(cut_group);
 
-        for cut_value in &cut_values_split {
-            match ll_config.clone() {
-                Some(ll_conf) => {
-                    let new_cut_values = ll_conf.substitute_cut(cut_key.clone(), cut_value.clone());
+            let mut final_cuts: Vec<String> = vec![];
 
-                    if &new_cut_values != cut_value {
-                        let new_cut_values_split: Vec<String> = new_cut_values.split(",").map(|s| s.to_string()).collect();
+            let cut_values_split: Vec<String> = cut_group.split(",").map(|s| s.to_string()).collect();
 
-                        final_cuts = [&final_cuts[..], &new_cut_values_split[..]].concat();
-                    } else {
-                        final_cuts.push(new_cut_values.clone());
+            for cut_value in &cut_values_split {
+                match ll_config.clone() {
+                    Some(ll_conf) => {
+                        let new_cut_values = ll_conf.substitute_cut(cut_key.clone(), cut_value.clone());
+
+                        if &new_cut_values != cut_value {
+                            let new_cut_values_split: Vec<String> = new_cut_values.split(",").map(|s| s.to_string()).collect();
+
+                            final_cuts = [&final_cuts[..], &new_cut_values_split[..]].concat();
+                        } else {
+                            final_cuts.push(new_cut_values.clone());
+                        }
+                    },
+                    None => {
+                        final_cuts.push(cut_value.clone());
+                    }
+                };
+            }
+
+            final_groups.push(format!("{}{}", mask_prefix, final_cuts.join(",")));
+        }
+
+        *agg_query_opt_cuts.get_mut(cut_key).unwrap() = final_groups.join(";");
+    }
+
+    Ok(agg_query_opt_cuts)
+}
+
+
+/// Splits the `include:` or `exclude:` prefix off a group of cut values.
+/// Groups without a prefix are included.
+fn split_mask_prefix(cut_group: &str) -> (&str, &str) {
+    for prefix in &["include:", "exclude:"] {
+        if cut_group.starts_with(prefix) {
+            return (*prefix, &cut_group[prefix.len()..]);
+        }
+    }
+    ("", cut_group)
+}
+
+
+/// A cut's values can be `;` separated groups of included and excluded
+/// members, e.g. `Country=5,10;exclude:20`. Leaves only the included
+/// members in `cuts_map` (removing cuts that only exclude), and returns the
+/// excluded members for each cut key.
+pub fn split_cut_exclusions(
+        cuts_map: &mut HashMap<String, String>
+) -> Result<HashMap<String, Vec<String>>, Error> {
+    let mut exclusions: HashMap<String, Vec<String>> = HashMap::new();
+
+    for (cut_key, cut_values) in cuts_map.clone().iter() {
+        if !cut_values.contains(";") && !cut_values.starts_with("include:") && !cut_values.starts_with("exclude:") {
+            continue;
+        }
+
+        let mut included: Vec<String> = vec![];
+        let mut excluded: Vec<String> = vec![];
+
+        for cut_group in cut_values.split(";") {
+            let (mask_prefix, cut_group) = split_mask_prefix(cut_group);
+            let members = cut_group.split(",")
+                .filter(|member| !member.is_empty())
+                .map(|member| member.to_string());
+
+            if mask_prefix == "exclude:" {
+                excluded.extend(members);
+            } else {
+                included.extend(members);
+            }
+        }
+
+        if excluded.iter().any(|member| member.contains(":")) {
+            bail!("Cut operations can't be used on excluded members, in cut {}", cut_key);
+        }
+
+        if included.is_empty() {
+            cuts_map.remove(cut_key);
+        } else {
+            cuts_map.insert(cut_key.clone(), included.join(","));
+        }
+
+        if !excluded.is_empty() {
+            exclusions.insert(cut_key.clone(), excluded);
+        }
+    }
+
+    Ok(exclusions)
+}
+
+
+/// Resolves the excluded members from `split_cut_exclusions` into exclude
+/// cuts, which are added to every generated query. Like included members,
+/// members cut on a dimension are matched to their level.
+pub fn resolve_exclusions(
+        exclusions: &HashMap<String, Vec<String>>,
+        cube: &Cube,
+        cube_cache: &CubeCache,
+) -> Result<Vec<Cut>, Error> {
+    let mut level_exclusions: HashMap<LevelName, Vec<String>> = HashMap::new();
+
+    for (cut_key, members) in exclusions.iter() {
+        for member in members {
+            let (level_name, member) = match cube_cache.name_resolution.get(&cube_cache.dimension_caches, cut_key)? {
+                Some(dimension_cache) => {
+                    match match_dimension_member(member, cube, dimension_cache)? {
+                        Some(level_member) => level_member,
+                        None => continue
                     }
                 },
                 None => {
-                    final_cuts.push(cut_value.clone());
+                    match cube_cache.find_level(cut_key)? {
+                        Some(level_name) => (level_name.clone(), coerce_member(member, cube, level_name)),
+                        None => continue
+                    }
                 }
             };
+
+            level_exclusions.entry(level_name)
+                .or_insert_with(|| vec![])
+                .push(member);
+        }
+    }
+
+    let mut cuts: Vec<Cut> = level_exclusions.into_iter()
+        .map(|(level_name, members)| {
+            Cut {
+                level_name,
+                members,
+                mask: Mask::Exclude,
+                for_match: false
+            }
+        })
+        .collect();
+    cuts.sort_by_key(|cut| cut.level_name.to_string());
+
+    Ok(cuts)
+}
+
+
+/// Matches a cut member to one of a dimension's levels through the cached
+/// id map. A member that isn't found as given is retried with each
+/// level's `key_coercion` applied. Returns the level and the member's id as
+/// cached.
+fn match_dimension_member(
+        member: &str,
+        cube: &Cube,
+        dimension_cache: &DimensionCache,
+) -> Result<Option<(LevelName, String)>, Error> {
+    let mut matches: Vec<(LevelName, String)> = match dimension_cache.get_levels(member) {
+        Some(level_names) => {
+            level_names.into_iter()
+                .map(|level_name| (level_name.clone(), member.to_owned()))
+                .collect()
+        },
+        None => {
+            cube.get_all_level_names().into_iter()
+                .filter_map(|level_name| {
+                    let level = cube.get_level(&level_name)?;
+                    if level.key_coercion.is_none() {
+                        return None;
+                    }
+
+                    let key = level.coerce_key(member);
+                    if dimension_cache.has_member(&key, &level_name) {
+                        Some((level_name, key))
+                    } else {
+                        None
+                    }
+                })
+                .collect()
         }
+    };
 
-        *agg_query_opt_cuts.get_mut(cut_key).unwrap() = final_cuts.join(",");
+    if matches.len() > 1 {
+        bail!("{} matches multiple levels in this dimension.", member);
     }
 
-    Ok(agg_query_opt_cuts)
+    Ok(matches.pop())
+}
+
+/// Applies the `key_coercion` of a cut's level to one of its members.
+fn coerce_member(member: &str, cube: &Cube, level_name: &LevelName) -> String {
+    match cube.get_level(level_name) {
+        Some(level) => level.coerce_key(member),
+        None => member.to_owned(),
+    }
 }
 
 
@@ -810,7 +1245,6 @@ pub fn resolve_cuts(
         cuts_map: &HashMap<String, String>,
         cube: &Cube,
         cube_cache: &CubeCache,
-        level_map: &HashMap<String, LevelName>,
         property_map: &HashMap<String, Property>,
         geoservice_url: &Option<Url>
 ) -> Result<(HashMap<String, HashMap<LevelName, Vec<String>>>, HashMap<String, String>), Error> {
@@ -848,183 +1282,217 @@ pub fn resolve_cuts(
 
             // Check to see if this matches any dimension names
             // Get LevelName based on cut_key and element
-            let mut level_name = match cube_cache.dimension_caches.get(cut_key) {
+            let (level_name, cut) = match cube_cache.name_resolution.get(&cube_cache.dimension_caches, cut_key)? {
                 Some(dimension_cache) => {
-                    match dimension_cache.id_map.get(cut) {
-                        Some(level_names) => {
-                            if level_names.len() > 1 {
-                                return Err(format_err!("{} matches multiple levels in this dimension.", cut))
-                            }
-
-                            match level_names.get(0) {
-                                Some(ln) => ln.clone(),
-                                None => return Err(format_err!("{} matches no levels in this dimension.", cut))
-                            }
-                        },
+                    match match_dimension_member(cut, cube, dimension_cache)? {
+                        Some(level_member) => level_member,
                         None => continue
                     }
                 },
                 None => {
-                    match level_map.get(cut_key) {
+                    match cube_cache.find_level(cut_key)? {
                         Some(level_name) => {
                             level_matches.push(level_name.clone());
-                            level_name.clone()
+                            (level_name.clone(), coerce_member(cut, cube, level_name))
                         },
                         None => continue
                     }
                 }
             };
+            let cut = &cut;
 
             header_map.entry(level_name.level.clone()).or_insert(level_name.dimension.clone());
 
-            if elements.len() == 1 {
-                // Simply add this cut to the map
-                dimension_cuts_map = add_cut_entries(dimension_cuts_map, &level_name, vec![cut.clone()]);
-            } else if elements.len() == 2 {
-                let operation = match elements.get(1) {
-                    Some(operation) => operation.clone(),
-                    None => return Err(format_err!("Unable to extract cut operation."))
-                };
+            // Operations are applied left to right, each one to the members
+            // the previous one resolved to (e.g. `06037:parents:children`
+            // for the siblings of 06037)
+            let operations = &elements[1..];
+            let mut step = (level_name, vec![cut.clone()]);
+            let mut entries = vec![step.clone()];
+
+            for (i, operation) in operations.iter().enumerate() {
+                let (next, next_entries) = apply_cut_operation(
+                    operation, &step.0, &step.1, cube, cube_cache, geoservice_url
+                )?;
+
+                if next.1.is_empty() && i < operations.len() - 1 {
+                    bail!(
+                        "Cut operation `{}` on {} yields no members, so `{}` can't be resolved.",
+                        operation, step.1.join(","), cut_value
+                    );
+                }
 
-                if operation == "children".to_string() {
+                step = next;
+                entries = next_entries;
+            }
 
-                    let child_level = match cube.get_child_level(&level_name)? {
-                        Some(child_level) => child_level,
-                        None => continue  // This level has no child
-                    };
+            for (entry_level_name, members) in entries {
+                if members.is_empty() {
+                    continue;
+                }
 
-                    let child_level_name = LevelName {
-                        dimension: level_name.dimension.clone(),
-                        hierarchy: level_name.hierarchy.clone(),
-                        level: child_level.name.clone()
-                    };
+                // Will help convert the column name for this level to its dimension name
+                header_map.entry(entry_level_name.level.clone()).or_insert(entry_level_name.dimension.clone());
 
-                    // Will help convert the column name for this level to its dimension name
-                    header_map.entry(child_level_name.level.clone()).or_insert(child_level_name.dimension.clone());
+                // Add this cut to the map
+                dimension_cuts_map = add_cut_entries(dimension_cuts_map, &entry_level_name, members);
+            }
+        }
+    }
 
-                    // Get children IDs from the cache
-                    let level_cache = match cube_cache.level_caches.get(&level_name.level) {
-                        Some(level_cache) => level_cache,
-                        None => return Err(format_err!("Could not find cached entries for {}.", level_name.level))
-                    };
+    // Check if anything needs to be removed from the header_map
+    for (_k1, level_name_map) in dimension_cuts_map.iter() {
+        if level_name_map.len() == 1 {
+            for (level_name, _v2) in level_name_map.iter() {
+                if level_matches.contains(&level_name) {
+                    header_map.remove_entry(&level_name.level);
+                }
+            }
+        }
+    }
 
-                    let children_ids = match &level_cache.children_map {
-                        Some(children_map) => {
-                            match children_map.get(cut) {
-                                Some(children_ids) => children_ids.clone(),
-                                None => continue
-                            }
-                        },
-                        None => continue
-                    };
+    Ok((dimension_cuts_map, header_map))
+}
 
-                    // Add children IDs to the `dimension_cuts_map`
-                    dimension_cuts_map = add_cut_entries(dimension_cuts_map, &child_level_name, children_ids);
 
-                } else if operation == "parents".to_string() {
+/// Applies a cut operation (`children`, `parents` or `neighbors`) to members
+/// of a level. Returns the level and members that the next operation in a
+/// chain applies to, and the cut entries to add if this is the last one:
+/// `parents` cuts on every parent level, but chains from the closest one.
+fn apply_cut_operation(
+        operation: &str,
+        level_name: &LevelName,
+        members: &[String],
+        cube: &Cube,
+        cube_cache: &CubeCache,
+        geoservice_url: &Option<Url>
+) -> Result<((LevelName, Vec<String>), Vec<(LevelName, Vec<String>)>), Error> {
+    match operation {
+        "children" => {
+            let child_level = match cube.get_child_level(level_name)? {
+                Some(child_level) => child_level,
+                // This level has no child
+                None => return Ok(((level_name.clone(), vec![]), vec![]))
+            };
+
+            let child_level_name = LevelName {
+                dimension: level_name.dimension.clone(),
+                hierarchy: level_name.hierarchy.clone(),
+                level: child_level.name.clone()
+            };
 
-                    let parent_levels = cube.get_level_parents(&level_name)?;
+            // Get children IDs from the cache
+            let level_cache = match cube_cache.find_level_cache(level_name) {
+                Some(level_cache) => level_cache,
+                None => return Err(format_err!("Could not find cached entries for {}.", level_name.level))
+            };
 
-                    if parent_levels.is_empty() {
-                        // This level has no parents
-                        continue;
+            let mut children_ids: Vec<String> = vec![];
+            if let Some(children_map) = &level_cache.children_map {
+                for member in members {
+                    if let Some(ids) = children_map.get(member) {
+                        push_unique(&mut children_ids, ids);
                     }
+                }
+            }
 
-                    for parent_level in (parent_levels.iter()).rev() {
-                        let parent_level_name = LevelName {
-                            dimension: level_name.dimension.clone(),
-                            hierarchy: level_name.hierarchy.clone(),
-                            level: parent_level.name.clone()
-                        };
-
-                        header_map.entry(parent_level_name.level.clone()).or_insert(parent_level_name.dimension.clone());
-
-                        // Get parent IDs from the cache
-                        let level_cache = match cube_cache.level_caches.get(&level_name.level) {
-                            Some(level_cache) => level_cache,
-                            None => return Err(format_err!("Could not find cached entries for {}.", level_name.level))
-                        };
-
-                        let parent_id = match &level_cache.parent_map {
-                            Some(parent_map) => {
-                                match parent_map.get(cut) {
-                                    Some(parent_id) => parent_id.clone(),
-                                    None => continue
-                                }
-                            },
-                            None => continue
-                        };
+            let next = (child_level_name, children_ids);
+            Ok((next.clone(), vec![next]))
+        },
+        "parents" => {
+            let parent_levels = cube.get_level_parents(level_name)?;
 
-                        // Add parent ID to the `dimension_cuts_map`
-                        dimension_cuts_map = add_cut_entries(dimension_cuts_map, &parent_level_name, vec![parent_id]);
+            let mut current = (level_name.clone(), members.to_vec());
+            let mut entries = vec![];
 
-                        // Update current level_name for the next iteration
-                        level_name = parent_level_name.clone();
+            for parent_level in (parent_levels.iter()).rev() {
+                let parent_level_name = LevelName {
+                    dimension: level_name.dimension.clone(),
+                    hierarchy: level_name.hierarchy.clone(),
+                    level: parent_level.name.clone()
+                };
+
+                // Get parent IDs from the cache
+                let level_cache = match cube_cache.find_level_cache(&current.0) {
+                    Some(level_cache) => level_cache,
+                    None => return Err(format_err!("Could not find cached entries for {}.", current.0.level))
+                };
+
+                let mut parent_ids: Vec<String> = vec![];
+                if let Some(parent_map) = &level_cache.parent_map {
+                    for member in &current.1 {
+                        if let Some(parent_id) = parent_map.get(member) {
+                            push_unique(&mut parent_ids, &[parent_id.clone()]);
+                        }
                     }
+                }
 
-                } else if operation == "neighbors".to_string() {
+                if parent_ids.is_empty() {
+                    break;
+                }
 
-                    // Find dimension for the level name
-                    let dimension = cube.get_dimension(&level_name)
-                        .ok_or_else(|| format_err!("Could not find dimension for {}.", level_name.level))?;
+                current = (parent_level_name, parent_ids);
+                entries.push(current.clone());
+            }
 
-                    match dimension.dim_type {
-                        DimensionType::Geo => {
-                            match geoservice_url {
-                                Some(geoservice_url) => {
-                                    let mut neighbors_ids: Vec<String> = vec![];
+            let next = entries.first().cloned()
+                .unwrap_or((level_name.clone(), vec![]));
+            Ok((next, entries))
+        },
+        "neighbors" => {
+            // Find dimension for the level name
+            let dimension = cube.get_dimension(level_name)
+                .ok_or_else(|| format_err!("Could not find dimension for {}.", level_name.level))?;
+
+            let mut neighbors_ids: Vec<String> = vec![];
+
+            match dimension.dim_type {
+                DimensionType::Geo => {
+                    let geoservice_url = match geoservice_url {
+                        Some(geoservice_url) => geoservice_url,
+                        None => return Err(format_err!("Unable to perform geoservice request: A Geoservice URL has not been provided."))
+                    };
 
-                                    let geoservice_response = query_geoservice(
-                                        geoservice_url, &GeoserviceQuery::Neighbors, &cut
-                                    )?;
+                    for member in members {
+                        let geoservice_response = query_geoservice(
+                            geoservice_url, &GeoserviceQuery::Neighbors, member
+                        )?;
 
-                                    for res in &geoservice_response {
-                                        neighbors_ids.push(res.geoid.clone());
-                                    }
+                        let ids: Vec<String> = geoservice_response.iter()
+                            .map(|res| res.geoid.clone())
+                            .collect();
+                        push_unique(&mut neighbors_ids, &ids);
+                    }
+                },
+                _ => {
+                    let level_cache = match cube_cache.find_level_cache(level_name) {
+                        Some(level_cache) => level_cache,
+                        None => return Err(format_err!("Could not find cached entries for {}.", level_name.level))
+                    };
 
-                                    // Add neighbors IDs to the `dimension_cuts_map`
-                                    dimension_cuts_map = add_cut_entries(dimension_cuts_map, &level_name, neighbors_ids);
-                                },
-                                None => return Err(format_err!("Unable to perform geoservice request: A Geoservice URL has not been provided."))
-                            };
-                        },
-                        _ => {
-                            let level_cache = match cube_cache.level_caches.get(&level_name.level) {
-                                Some(level_cache) => level_cache,
-                                None => return Err(format_err!("Could not find cached entries for {}.", level_name.level))
-                            };
-
-                            let neighbors_ids = match level_cache.neighbors_map.get(cut) {
-                                Some(neighbors_ids) => neighbors_ids.clone(),
-                                None => continue
-                            };
-
-                            // Add neighbors IDs to the `dimension_cuts_map`
-                            dimension_cuts_map = add_cut_entries(dimension_cuts_map, &level_name, neighbors_ids);
+                    for member in members {
+                        if let Some(ids) = level_cache.neighbors(member) {
+                            push_unique(&mut neighbors_ids, &ids);
                         }
                     }
-
-                } else {
-                    return Err(format_err!("Unrecognized operation: `{}`.", operation));
                 }
-            } else {
-                return Err(format_err!("Multiple cut operations are not supported on the same element."));
             }
-        }
+
+            let next = (level_name.clone(), neighbors_ids);
+            Ok((next.clone(), vec![next]))
+        },
+        _ => Err(format_err!("Unrecognized operation: `{}`.", operation))
     }
+}
 
-    // Check if anything needs to be removed from the header_map
-    for (_k1, level_name_map) in dimension_cuts_map.iter() {
-        if level_name_map.len() == 1 {
-            for (level_name, _v2) in level_name_map.iter() {
-                if level_matches.contains(&level_name) {
-                    header_map.remove_entry(&level_name.level);
-                }
-            }
+
+/// Appends the ids that aren't in `ids` yet.
+fn push_unique(ids: &mut Vec<String>, new_ids: &[String]) {
+    for id in new_ids {
+        if !ids.contains(id) {
+            ids.push(id.clone());
         }
     }
-
-    Ok((dimension_cuts_map, header_map))
 }
 
 
@@ -1050,35 +1518,190 @@ pub fn add_cut_entries(
 }
 
 
+/// Localizes measure headers for the first requested locale, as it
+/// replaces level names with its captions: the headers of a measure and the
+/// ones derived from it (e.g. `Quantity Growth`) use the measure's
+/// `caption_<locale>` annotation, if it has one.
+pub fn localize_measure_headers(
+    headers: &[String],
+    cube: &Cube,
+    measures: &[Measure],
+    locale: Option<&str>,
+) -> Vec<String>
+{
+    let locale = match locale {
+        Some(locale) => locale,
+        None => return headers.to_vec(),
+    };
+
+    let captions: Vec<(&str, &str)> = cube.measures.iter()
+        .filter(|mea| measures.iter().any(|m| m.0 == mea.name))
+        .filter_map(|mea| mea.caption(locale).map(|caption| (mea.name.as_str(), caption)))
+        .collect();
+
+    headers.iter()
+        .map(|header| {
+            // the longest measure name matching the header, so that e.g.
+            // `Online Quantity` isn't taken for `Online` with a suffix
+            let caption = captions.iter()
+                .filter(|(name, _)| header == *name || header.starts_with(&format!("{} ", name)))
+                .max_by_key(|(name, _)| name.len());
+
+            match caption {
+                Some((name, caption)) => format!("{}{}", caption, &header[name.len()..]),
+                None => header.clone(),
+            }
+        })
+        .collect()
+}
+
+
 /// Helper to get all the relevant parent captions given a locales list.
 pub fn get_parent_captions(cube: &Cube, level_name: &LevelName, locales: &Vec<String>) -> Vec<Property> {
     let mut captions: Vec<Property> = vec![];
 
     let level_parents = cube.get_level_parents(level_name).unwrap_or(vec![]);
     for parent_level in level_parents {
-        if let Some(ref props) = parent_level.properties {
-            for prop in props {
-                if let Some(ref cap) = prop.caption_set {
-                    for locale in locales {
-                        if locale == cap {
-                            captions.push(
-                                Property::new(
-                                    level_name.dimension.clone(),
-                                    level_name.hierarchy.clone(),
-                                    parent_level.name.clone(),
-                                    prop.name.clone(),
-                                )
-                            )
-                        }
-                    }
-                } else {
-                    continue
-                }
-            }
-        } else {
-            continue
-        }
+        let parent_level_name = LevelName::new(
+            level_name.dimension.clone(),
+            level_name.hierarchy.clone(),
+            parent_level.name.clone(),
+        );
+        captions.extend(parent_level.get_captions(&parent_level_name, locales));
     }
 
     captions
 }
+
+
+#[cfg(test)]
+mod test {
+    use tesseract_core::{Column, ColumnData, DataFrame};
+    use tesseract_test::MockBackend;
+
+    use tesseract_core::Schema;
+
+    use crate::app::test::{env_vars, get, test_clickhouse_server, test_server};
+    use super::union_all_sql;
+
+    fn year_quantity() -> DataFrame {
+        DataFrame::from_vec(vec![
+            Column::new("year".to_owned(), ColumnData::UInt32(vec![2018, 2019])),
+            Column::new("quantity".to_owned(), ColumnData::Int64(vec![10, 20])),
+        ])
+    }
+
+    fn year_geography_quantity() -> DataFrame {
+        DataFrame::from_vec(vec![
+            Column::new("year".to_owned(), ColumnData::UInt32(vec![2018, 2018])),
+            Column::new("geo_id".to_owned(), ColumnData::Text(vec!["04000US01".to_owned(), "05000US02".to_owned()])),
+            Column::new("geo_name".to_owned(), ColumnData::Text(vec!["State 1".to_owned(), "County 2".to_owned()])),
+            Column::new("quantity".to_owned(), ColumnData::Int64(vec![10, 20])),
+        ])
+    }
+
+    #[test]
+    fn logic_layer_cut() {
+        let backend = MockBackend::new()
+            .respond(r"sum\(quantity\)", year_quantity);
+        let mut srv = test_server(tesseract_test::schema(), backend.clone(), env_vars());
+
+        let (status, body) = get(
+            &mut srv,
+            "/data.csv?cube=sales&drilldowns=Year&measures=Quantity&State=04000US01",
+            &[],
+        );
+
+        assert_eq!(status, 200, "{}", body);
+        assert!(body.contains("2019,20"), "{}", body);
+        assert!(backend.last_query().unwrap().contains("'04000US01'"));
+    }
+
+    #[test]
+    fn logic_layer_unknown_measure() {
+        let backend = MockBackend::new()
+            .respond(r"sum\(quantity\)", year_quantity);
+        let mut srv = test_server(tesseract_test::schema(), backend.clone(), env_vars());
+
+        let (status, body) = get(
+            &mut srv,
+            "/data.csv?cube=sales&drilldowns=Year&measures=Quantityy&State=04000US01",
+            &[],
+        );
+
+        assert_eq!(status, 400, "{}", body);
+        assert!(body.contains("Quantityy"), "{}", body);
+        assert!(backend.queries().is_empty());
+    }
+
+    #[test]
+    fn logic_layer_unknown_drilldown() {
+        let backend = MockBackend::new()
+            .respond(r"sum\(quantity\)", year_quantity);
+        let mut srv = test_server(tesseract_test::schema(), backend.clone(), env_vars());
+
+        let (status, body) = get(&mut srv, "/data.csv?cube=sales&drilldowns=Stat&measures=Quantity", &[]);
+
+        assert_eq!(status, 404, "{}", body);
+        assert!(backend.queries().is_empty());
+    }
+
+    #[test]
+    fn union_all_sql_keeps_combination_order() {
+        let sql = union_all_sql(&["select a from t order by a limit 2".to_owned(), "select b from u".to_owned()]);
+
+        assert_eq!(
+            sql,
+            "select * except (cut_combination, cut_combination_row) from (\
+            select *, 0 as cut_combination, rowNumberInAllBlocks() as cut_combination_row from (select a from t order by a limit 2) \
+            union all \
+            select *, 1 as cut_combination, rowNumberInAllBlocks() as cut_combination_row from (select b from u)\
+            ) order by cut_combination, cut_combination_row"
+        );
+    }
+
+    #[test]
+    fn logic_layer_cut_combinations_union_all() {
+        let backend = MockBackend::new()
+            .respond(r"union all", year_geography_quantity);
+        let mut srv = test_clickhouse_server(tesseract_test::schema(), backend.clone(), env_vars());
+
+        let (status, body) = get(
+            &mut srv,
+            "/data.csv?cube=sales&drilldowns=Year&measures=Quantity&State=04000US01&County=05000US02",
+            &[],
+        );
+
+        assert_eq!(status, 200, "{}", body);
+        assert!(body.starts_with("Year,Geography ID,Geography,Quantity\n"), "{}", body);
+        assert!(body.contains("2018,05000US02,County 2,20"), "{}", body);
+
+        let queries = backend.queries();
+        assert_eq!(queries.len(), 1);
+        assert!(queries[0].contains("'04000US01'") && queries[0].contains("'05000US02'"), "{}", queries[0]);
+    }
+
+    #[test]
+    fn logic_layer_cut_combinations_different_key_types() {
+        // county ids are numbers, so they can't share a column with state ids
+        let schema = Schema::from_json(&tesseract_test::SCHEMA.replace(
+            r#""county_name", "key_type": "text""#,
+            r#""county_name", "key_type": "nontext""#,
+        )).expect("valid test schema");
+        let backend = MockBackend::new()
+            .respond(r"sum\(quantity\)", year_geography_quantity);
+        let mut srv = test_clickhouse_server(schema, backend.clone(), env_vars());
+
+        let (status, body) = get(
+            &mut srv,
+            "/data.csv?cube=sales&drilldowns=Year&measures=Quantity&State=04000US01&County=05000US02",
+            &[],
+        );
+
+        assert_eq!(status, 200, "{}", body);
+
+        let queries = backend.queries();
+        assert_eq!(queries.len(), 2);
+        assert!(queries.iter().all(|sql| !sql.contains("union all")), "{:?}", queries);
+    }
+}
@@ -0,0 +1,181 @@
+use actix_web::{
+    FutureResponse,
+    HttpRequest,
+    HttpResponse,
+    Path,
+};
+use failure::Error;
+use futures::Future;
+use futures::stream::{self, Stream};
+use log::*;
+
+use tesseract_core::format::FormatType;
+use tesseract_core::format_stream::format_records_stream;
+use tesseract_core::DataFrame;
+
+use crate::app::AppState;
+use crate::conversion::{Conversion, CONVERSION_HEADER};
+use crate::errors::ServerError;
+use crate::util::{boxed_error, format_error, stream_format_error};
+use super::super::util;
+use super::aggregate::{LogicLayerSql, logic_layer_sql};
+
+
+type DfStream = Box<Stream<Item=Result<DataFrame, Error>, Error=Error>>;
+
+
+/// Handles default aggregation when a format is not specified.
+/// Default format is jsonrecords.
+pub fn logic_layer_default_handler(
+    (req, _cube): (HttpRequest<AppState>, Path<()>)
+) -> FutureResponse<HttpResponse>
+{
+    logic_layer_aggregation(req, "jsonrecords".to_owned())
+}
+
+
+/// Handles aggregation when a format is specified.
+pub fn logic_layer_handler(
+    (req, cube_format): (HttpRequest<AppState>, Path<(String)>)
+) -> FutureResponse<HttpResponse>
+{
+    logic_layer_aggregation(req, cube_format.to_owned())
+}
+
+
+/// Performs data aggregation, streaming the response.
+///
+/// A query without cut combinations, or whose cut combinations run as a
+/// single query, is streamed straight from the backend.
+/// When the query expands into multiple cut combinations, each query's
+/// result is buffered, but the queries are executed with bounded
+/// concurrency and their rows are streamed as each one completes, instead
+/// of buffering all of them for a merge. Row order across cut combinations
+/// is therefore not guaranteed.
+pub fn logic_layer_aggregation(
+    req: HttpRequest<AppState>,
+    format: String,
+) -> FutureResponse<HttpResponse>
+{
+    let format = format.parse::<FormatType>();
+    let format = match format {
+        Ok(f) => f,
+        Err(err) => return boxed_error(format_error(err)),
+    };
+    if format.is_binary() {
+        return boxed_error(stream_format_error(&format));
+    }
+
+    info!("Format: {:?}", format);
+
+    let ll_sql = match logic_layer_sql(&req) {
+        Ok(ll_sql) => ll_sql,
+        Err(err) => return boxed_error(ServerError::from_query_error(err))
+    };
+    let LogicLayerSql { sql_strings, query_headers, headers, localized_headers, column_order, masking, precisions, round, conversions, cube, hints, echo } = ll_sql;
+    if echo.is_some() {
+        return boxed_error(ServerError::invalid_query("echo cannot be used with streaming"));
+    }
+    // complementary suppression needs all the rows at once
+    if masking.as_ref().map(|plan| plan.is_complementary()).unwrap_or(false) {
+        return boxed_error(ServerError::invalid_query("complementary suppression cannot be used with streaming"));
+    }
+    let localized_headers: Vec<String> = column_order.iter()
+        .map(|&idx| localized_headers[idx].clone())
+        .collect();
+
+    let factors = Conversion::factors(&conversions);
+    let df_headers = headers.clone();
+    let process_df = move |mut df: DataFrame| -> Result<DataFrame, Error> {
+        if let Some(ref plan) = masking {
+            df.rename_columns(&query_headers)?;
+            plan.apply(&mut df)?;
+        }
+        // conversions and rounding find their columns by name
+        df.rename_columns(&df_headers)?;
+        df.scale_columns(&factors);
+        df.round_columns(&precisions, round);
+        df.reorder_columns(&column_order)?;
+        Ok(df)
+    };
+
+    let df_stream: Box<Future<Item=DfStream, Error=Error>> = if sql_strings.len() == 1 {
+        let sql = sql_strings.into_iter().next().unwrap();
+
+        Box::new(
+            util::exec_sql_stream(req.state(), &cube, hints.priority, sql)
+                .map(move |df_stream| {
+                    let df_stream = df_stream
+                        .map(move |df_res| df_res.and_then(|df| process_df(df)));
+                    Box::new(df_stream) as DfStream
+                })
+        )
+    } else {
+        let concurrency = req.state().env_vars.logic_layer_concurrency;
+        let exec_req = req.clone();
+        let df_stream = stream::iter_ok::<_, Error>(sql_strings)
+            .map(move |sql| util::exec_sql(exec_req.state(), &cube, hints.priority, sql))
+            .buffer_unordered(concurrency)
+            .map(process_df);
+
+        util::peek_df_stream(df_stream)
+    };
+
+    let debug = req.state().debug;
+
+    // The first chunk is awaited before committing to a status code, see
+    // the native `aggregate_stream`.
+    Box::new(
+        df_stream
+            .map(move |df_stream| {
+                let content_type = util::format_to_content_type(&format);
+
+                let mut response = HttpResponse::Ok();
+                response.set(content_type);
+                if !conversions.is_empty() {
+                    response.header(CONVERSION_HEADER, Conversion::header_value(&conversions));
+                }
+
+                response.streaming(format_records_stream(localized_headers, df_stream, format))
+            })
+            .map_err(move |err| {
+                ServerError::from_backend_error(err, debug).into()
+            })
+    )
+}
+
+
+#[cfg(test)]
+mod test {
+    use tesseract_core::{Column, ColumnData, DataFrame};
+    use tesseract_test::MockBackend;
+
+    use crate::app::test::{env_vars, get, test_stream_server};
+
+    fn year_price() -> DataFrame {
+        DataFrame::from_vec(vec![
+            Column::new("year".to_owned(), ColumnData::UInt32(vec![2018, 2019])),
+            Column::new("price".to_owned(), ColumnData::Float64(vec![1.2345, 2.5])),
+        ])
+    }
+
+    #[test]
+    fn logic_layer_stream_round() {
+        let backend = MockBackend::new()
+            .respond(r"avg\(price\)", year_price);
+        let mut srv = test_stream_server(tesseract_test::schema(), backend.clone(), env_vars());
+
+        // Price has a precision of 2 in the schema
+        let (status, body) = get(&mut srv, "/data.csv?cube=sales&drilldowns=Year&measures=Price", &[]);
+
+        assert_eq!(status, 200, "{}", body);
+        assert!(body.contains("2018,1.23\n"), "{}", body);
+        assert!(body.contains("2019,2.5\n"), "{}", body);
+
+        let (status, body) = get(&mut srv, "/data.csv?cube=sales&drilldowns=Year&measures=Price&round=0", &[]);
+
+        assert_eq!(status, 200, "{}", body);
+        assert!(body.contains("2018,1\n"), "{}", body);
+        assert!(body.contains("2019,3\n"), "{}", body);
+    }
+}
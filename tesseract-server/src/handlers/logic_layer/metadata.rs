@@ -4,6 +4,7 @@ use actix_web::{
     HttpRequest,
     HttpResponse,
     Path,
+    ResponseError,
 };
 use failure::Error;
 use futures::future::{self, Future};
@@ -14,10 +15,12 @@ use serde_qs as qs;
 
 use crate::app::AppState;
 use crate::logic_layer::{LogicLayerConfig};
-use crate::util::boxed_error;
+use crate::errors::ServerError;
+use crate::query_hints::QueryPriority;
+use crate::util::{boxed_error, format_error};
 
-use tesseract_core::format::{format_records, FormatType};
-use tesseract_core::names::LevelName;
+use tesseract_core::format::{format_records_bytes, FormatType};
+use tesseract_core::names::{LevelName, suggestion};
 
 use super::super::util;
 
@@ -50,7 +53,7 @@ pub fn get_members(
     let format = format.parse::<FormatType>();
     let format = match format {
         Ok(f) => f,
-        Err(err) => return boxed_error(err.to_string()),
+        Err(err) => return boxed_error(format_error(err)),
     };
 
     info!("Format: {:?}", format);
@@ -70,7 +73,7 @@ pub fn get_members(
 
     let members_query = match QS_NON_STRICT.deserialize_str::<MembersQueryOpt>(query) {
         Ok(q) => q,
-        Err(err) => return boxed_error(err.to_string())
+        Err(err) => return boxed_error(ServerError::invalid_query(err))
     };
 
     let mut cube_name = members_query.cube.clone();
@@ -114,11 +117,11 @@ pub fn get_members(
             let mut level_name: Option<LevelName> = None;
 
             for cube in &schema.cubes {
-                if &cube.name == &cube_name {
+                if schema.name_resolution.matches(&cube.name, &cube_name) {
                     for dimension in &cube.dimensions {
                         for hierarchy in &dimension.hierarchies {
                             for level in &hierarchy.levels {
-                                if &level.name == &members_query.level {
+                                if schema.name_resolution.matches(&level.name, &members_query.level) {
                                     level_name = Some(LevelName {
                                         dimension: dimension.name.clone(),
                                         hierarchy: hierarchy.name.clone(),
@@ -137,43 +140,74 @@ pub fn get_members(
 
     let level_name = match level_name {
         Some(level_name) => level_name,
-        None => return boxed_error("Unable to find a level with the name provided".to_string())
+        None => {
+            let level_names: Vec<String> = schema.cubes.iter()
+                .filter(|cube| schema.name_resolution.matches(&cube.name, &cube_name))
+                .flat_map(|cube| cube.get_all_level_names())
+                .map(|level_name| level_name.level)
+                .collect();
+
+            return boxed_error(ServerError::not_found(format!(
+                "Unable to find level {}{}",
+                members_query.level,
+                suggestion(&members_query.level, level_names.iter().map(|l| l.as_str())),
+            )));
+        },
     };
 
     debug!("{:?}", cube_name);
     debug!("{:?}", level_name);
 
-    let members_sql_and_headers = match members_query.locale {
-        Some(locale) => schema.members_locale_sql(&cube_name, &level_name, &locale),
-        None => schema.members_sql(&cube_name, &level_name)
-    };
+    let search = members_query.search.clone();
+    let (offset, limit) = (members_query.offset, members_query.limit);
+    let role = util::request_role(&req);
+    let role = role.as_ref().map(|r| r.as_str());
+
+    // Members of the whole level can come from the cache
+    if members_query.locale.is_none() {
+        if let Some((df, header)) = util::cached_members(req.state(), &schema, &cube_name, &level_name, role) {
+            info!("Members served from cache");
+            let df = util::page_members(df, search.as_ref().map(|s| s.as_str()), offset, limit);
+            let content_type = util::format_to_content_type(&format);
+
+            let res = match format_records_bytes(&header, df, format) {
+                Ok(res) => HttpResponse::Ok().set(content_type).body(res),
+                Err(err) => ServerError::Internal { cause: err.to_string() }.error_response(),
+            };
+            return Box::new(future::ok(res));
+        }
+    }
+
+    let members_sql_and_headers = schema.members_context_sql(
+        &cube_name,
+        &level_name,
+        false,
+        members_query.locale.as_ref().map(|l| l.as_str()),
+        &[],
+        role,
+    );
 
     let (members_sql, header) = match members_sql_and_headers {
         Ok(s) => s,
-        Err(err) => {
-            return Box::new(
-                future::result(
-                    Ok(HttpResponse::BadRequest().json(err.to_string()))
-                )
-            );
-        },
+        Err(err) => return boxed_error(ServerError::not_found(err)),
     };
 
     debug!("{:?}", members_sql);
     debug!("{:?}", header);
 
-    req.state()
-        .backend
-        .exec_sql(members_sql)
-        .from_err()
+    util::exec_sql(req.state(), &cube_name, QueryPriority::default(), members_sql)
         .and_then(move |df| {
+            let df = util::page_members(df, search.as_ref().map(|s| s.as_str()), offset, limit);
             let content_type = util::format_to_content_type(&format);
 
-            match format_records(&header, df, format) {
+            match format_records_bytes(&header, df, format) {
                 Ok(res) => Ok(HttpResponse::Ok().set(content_type).body(res)),
-                Err(err) => Ok(HttpResponse::NotFound().json(err.to_string())),
+                Err(err) => Ok(ServerError::Internal { cause: err.to_string() }.error_response()),
             }
         })
+        .map_err(move |err| {
+            ServerError::from_backend_error(err, debug).into()
+        })
         .responder()
 }
 
@@ -183,4 +217,7 @@ pub struct MembersQueryOpt {
     pub cube: String,
     pub level: String,
     pub locale: Option<String>,
+    pub search: Option<String>,
+    pub offset: Option<usize>,
+    pub limit: Option<usize>,
 }
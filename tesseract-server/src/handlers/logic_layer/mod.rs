@@ -1,9 +1,13 @@
 mod aggregate;
+mod aggregate_stream;
 mod geoservice;
 mod metadata;
 
 pub use self::aggregate::logic_layer_handler;
 pub use self::aggregate::logic_layer_default_handler;
+pub use self::aggregate::{generate_ts_queries, LogicLayerQueryOpt};
+pub use self::aggregate_stream::logic_layer_handler as logic_layer_stream_handler;
+pub use self::aggregate_stream::logic_layer_default_handler as logic_layer_stream_default_handler;
 pub use self::geoservice::GeoserviceQuery;
 pub use self::geoservice::GeoServiceResponseJson;
 pub use self::geoservice::query_geoservice;
@@ -0,0 +1,120 @@
+use actix_web::{
+    FutureResponse,
+    HttpRequest,
+    HttpResponse,
+    Path,
+};
+use futures::future::{self, Future};
+use lazy_static::lazy_static;
+use log::*;
+use serde_qs as qs;
+use tesseract_core::format::FormatType;
+use tesseract_core::format_stream::format_records_stream;
+use tesseract_core::names::LevelName;
+
+use crate::app::AppState;
+use crate::errors::ServerError;
+use crate::query_hints::QueryPriority;
+use crate::util::{boxed_error, format_error, stream_format_error};
+use super::metadata::{MembersQueryOpt, cached_members_response};
+use super::util;
+
+/// Handles members when a format is not specified.
+/// Default format is CSV.
+pub fn members_default_handler(
+    (req, cube): (HttpRequest<AppState>, Path<String>)
+    ) -> FutureResponse<HttpResponse>
+{
+    let cube_format = (cube.into_inner(), "csv".to_owned());
+    do_members(req, cube_format)
+}
+
+/// Handles members when a format is specified.
+pub fn members_handler(
+    (req, cube_format): (HttpRequest<AppState>, Path<(String, String)>)
+    ) -> FutureResponse<HttpResponse>
+{
+    do_members(req, cube_format.into_inner())
+}
+
+/// Gets the members of a level, streaming the response.
+pub fn do_members(
+    req: HttpRequest<AppState>,
+    cube_format: (String, String),
+    ) -> FutureResponse<HttpResponse>
+{
+    let (cube, format) = cube_format;
+
+    let format = format.parse::<FormatType>();
+    let format = match format {
+        Ok(f) => f,
+        Err(err) => return boxed_error(format_error(err)),
+    };
+    if format.is_binary() {
+        return boxed_error(stream_format_error(&format));
+    }
+
+    let query = req.query_string();
+    lazy_static!{
+        static ref QS_NON_STRICT: qs::Config = qs::Config::new(5, false);
+    }
+    let query_res = QS_NON_STRICT.deserialize_str::<MembersQueryOpt>(&query);
+    let query = match query_res {
+        Ok(q) => q,
+        Err(err) => return boxed_error(ServerError::invalid_query(err)),
+    };
+
+    let level: LevelName = match query.level.parse() {
+        Ok(q) => q,
+        Err(err) => return boxed_error(ServerError::invalid_query(err)),
+    };
+
+    let cuts = match query.cuts() {
+        Ok(c) => c,
+        Err(err) => return boxed_error(ServerError::invalid_query(err)),
+    };
+
+    info!("Members for cube: {}, level: {}", cube, level);
+
+    let role = util::request_role(&req);
+    let role = role.as_ref().map(|r| r.as_str());
+
+    if let Some(res) = cached_members_response(req.state(), &cube, &level, &query, &cuts, role, &format) {
+        return Box::new(future::ok(res));
+    }
+
+    if query.search.is_some() || query.offset.is_some() || query.limit.is_some() {
+        return boxed_error(ServerError::invalid_query("search, offset and limit are only supported for members served from the cache when streaming"));
+    }
+
+    let members_sql_and_headers = req.state().schema.read().unwrap()
+        .members_context_sql(
+            &cube,
+            &level,
+            query.parents.unwrap_or(false),
+            query.locale.as_ref().map(|l| l.as_str()),
+            &cuts,
+            role,
+        );
+    let (members_sql, header) = match members_sql_and_headers {
+        Ok(s) => s,
+        Err(err) => return boxed_error(ServerError::not_found(err)),
+    };
+
+    let debug = req.state().debug;
+
+    // See `aggregate_stream` on how errors are reported.
+    Box::new(
+        util::exec_sql_stream(req.state(), &cube, QueryPriority::default(), members_sql)
+            .map(move |df_stream| {
+                let content_type = util::format_to_content_type(&format);
+
+                HttpResponse::Ok()
+                    .set(content_type)
+                    .streaming(format_records_stream(header, df_stream, format))
+            })
+            .map_err(move |err| {
+                ServerError::from_backend_error(err, debug).into()
+            })
+    )
+}
@@ -1,21 +1,33 @@
 use actix_web::{
+    http::header,
     AsyncResponder,
     FutureResponse,
     HttpRequest,
     HttpResponse,
     Path,
+    ResponseError,
     Result as ActixResult
 };
 
+use failure::Error;
 use futures::future::{self, Future};
 use lazy_static::lazy_static;
 use log::*;
 use serde_derive::Deserialize;
+use serde_json::{json, Value};
 use serde_qs as qs;
-use tesseract_core::format::{format_records, FormatType};
-use tesseract_core::names::LevelName;
+use tesseract_core::format::{format_records_bytes, FormatType};
+use tesseract_core::names::{Cut, LevelName};
+use tesseract_core::schema::Cube;
+use tesseract_core::schema::metadata::{AnnotationMetadata, CubeMetadata};
 
 use crate::app::AppState;
+use crate::errors::ServerError;
+use crate::logic_layer::CubeCache;
+use crate::metadata_cache::CachedMetadata;
+use crate::query_hints::QueryPriority;
+use crate::util::{boxed_error, format_error};
+use super::util;
 
 pub fn metadata_handler(
     (req, cube): (HttpRequest<AppState>, Path<String>)
@@ -23,11 +35,118 @@ pub fn metadata_handler(
 {
     info!("Metadata for cube: {}", cube);
 
+    lazy_static!{
+        static ref QS_NON_STRICT: qs::Config = qs::Config::new(5, false);
+    }
+    let query = QS_NON_STRICT.deserialize_str::<MetadataQueryOpt>(req.query_string())
+        .map_err(ServerError::invalid_query)?;
+
+    if !query.extended.unwrap_or(false) {
+        let cached = cached_cube_metadata(req.state(), &cube)?;
+
+        let not_modified = req.headers().get(header::IF_NONE_MATCH)
+            .and_then(|etags| etags.to_str().ok())
+            .map(|etags| etags.split(',').any(|etag| etag.trim() == cached.etag || etag.trim() == "*"))
+            .unwrap_or(false);
+
+        let mut response = if not_modified {
+            HttpResponse::NotModified()
+        } else {
+            HttpResponse::Ok()
+        };
+        response
+            .header(header::ETAG, cached.etag.as_str())
+            .header(header::CACHE_CONTROL, "no-cache");
+
+        return Ok(if not_modified {
+            response.finish()
+        } else {
+            response.content_type("application/json").body(cached.json)
+        });
+    }
+
     // currently, we do not check that cube names are distinct
     // TODO fix this
-    match req.state().schema.read().unwrap().cube_metadata(&cube) {
-        Some(cube) => Ok(HttpResponse::Ok().json(cube)),
-        None => Ok(HttpResponse::NotFound().finish()),
+    let cube_metadata = match req.state().schema.read().unwrap().cube_metadata(&cube) {
+        Some(cube_metadata) => cube_metadata,
+        None => return Err(ServerError::not_found(format!("Could not find cube {}", cube)).into()),
+    };
+
+    let mut cube_metadata = serde_json::to_value(cube_metadata)
+        .map_err(|err| ServerError::Internal { cause: err.to_string() })?;
+
+    if let Some(cube_cache) = req.state().cache.read().unwrap().find_cube_info(&cube) {
+        add_level_stats(&mut cube_metadata, &cube_cache);
+    }
+
+    Ok(HttpResponse::Ok().json(cube_metadata))
+}
+
+/// Serialized metadata of a cube, from the metadata cache. The extended
+/// metadata isn't cached, since the level stats change with the cache.
+fn cached_cube_metadata(state: &AppState, cube: &str) -> Result<CachedMetadata, ServerError> {
+    // the schema lock is held until the metadata is cached, so that a
+    // flush can't swap the schema in between (see `util::generate_sql`)
+    let schema = state.schema.read().unwrap();
+
+    if let Some(cached) = state.metadata_cache.read().unwrap().get(cube) {
+        return Ok(cached);
+    }
+
+    let cube_metadata = schema.cube_metadata(cube)
+        .ok_or_else(|| ServerError::not_found(format!("Could not find cube {}", cube)))?;
+    let json = serde_json::to_string(&cube_metadata)
+        .map_err(|err| ServerError::Internal { cause: err.to_string() })?;
+    let cached = CachedMetadata::new(json);
+
+    state.metadata_cache.write().unwrap().insert(cube, cached.clone());
+
+    Ok(cached)
+}
+
+/// Adds the member count and min/max keys from the cache to each level
+/// in the cube metadata, so that clients can decide how to present
+/// a level (e.g. a dropdown or a search box) and warn about large
+/// queries.
+fn add_level_stats(cube_metadata: &mut Value, cube_cache: &CubeCache) {
+    let dimensions = match cube_metadata["dimensions"].as_array_mut() {
+        Some(dimensions) => dimensions,
+        None => return,
+    };
+
+    for dimension in dimensions {
+        let dimension_name = dimension["name"].as_str().unwrap_or_default().to_owned();
+
+        let hierarchies = match dimension["hierarchies"].as_array_mut() {
+            Some(hierarchies) => hierarchies,
+            None => continue,
+        };
+
+        for hierarchy in hierarchies {
+            let hierarchy_name = hierarchy["name"].as_str().unwrap_or_default().to_owned();
+
+            let levels = match hierarchy["levels"].as_array_mut() {
+                Some(levels) => levels,
+                None => continue,
+            };
+
+            for level in levels {
+                let level_name = LevelName::new(
+                    dimension_name.clone(),
+                    hierarchy_name.clone(),
+                    level["name"].as_str().unwrap_or_default().to_owned(),
+                );
+
+                if let Some(level_cache) = cube_cache.find_level_cache(&level_name) {
+                    level["member_count"] = json!(level_cache.member_count);
+                    // the member count is known, so it replaces the declared estimates
+                    level["min_cardinality"] = json!(level_cache.member_count);
+                    level["max_cardinality"] = json!(level_cache.member_count);
+                    level["min_key"] = json!(level_cache.min_key);
+                    level["max_key"] = json!(level_cache.max_key);
+                }
+            }
+        }
     }
 }
 
@@ -37,7 +156,71 @@ pub fn metadata_all_handler(
 {
     info!("Metadata for all");
 
-    Ok(HttpResponse::Ok().json(req.state().schema.read().unwrap().metadata()))
+    lazy_static!{
+        static ref QS_NON_STRICT: qs::Config = qs::Config::new(5, false);
+    }
+    let query = QS_NON_STRICT.deserialize_str::<MetadataAllQueryOpt>(req.query_string())
+        .map_err(ServerError::invalid_query)?;
+
+    let schema = req.state().schema.read().unwrap();
+
+    let paged = query.offset.is_some() || query.limit.is_some();
+    if !paged && query.filter.is_none() && query.sort.is_none() && !query.cube_names_only.unwrap_or(false) {
+        return Ok(HttpResponse::Ok().json(schema.metadata()));
+    }
+
+    let filter = query.filter.as_ref().map(|filter| filter.to_lowercase());
+    let mut cubes: Vec<&Cube> = schema.cubes.iter()
+        .filter(|cube| filter.as_ref().map(|filter| cube_matches(cube, filter)).unwrap_or(true))
+        .collect();
+
+    match query.sort.as_ref().map(|sort| sort.as_str()) {
+        None => (),
+        Some("name") | Some("name.asc") => cubes.sort_by(|a, b| a.name.cmp(&b.name)),
+        Some("name.desc") => cubes.sort_by(|a, b| b.name.cmp(&a.name)),
+        Some(sort) => {
+            return Err(ServerError::invalid_query(
+                format!("Cannot sort cubes by {}, only by name.asc or name.desc", sort)
+            ).into());
+        },
+    }
+
+    let total = cubes.len();
+    let cubes: Vec<Value> = cubes.into_iter()
+        .skip(query.offset.unwrap_or(0))
+        .take(query.limit.unwrap_or(std::usize::MAX))
+        .map(|cube| {
+            if query.cube_names_only.unwrap_or(false) {
+                Ok(json!({ "name": cube.name }))
+            } else {
+                serde_json::to_value(CubeMetadata::from(cube))
+            }
+        })
+        .collect::<Result<_, _>>()
+        .map_err(|err| ServerError::Internal { cause: err.to_string() })?;
+
+    let mut metadata = json!({
+        "name": schema.name,
+        "cubes": cubes,
+        "annotations": AnnotationMetadata::from(&schema.annotations),
+    });
+    if paged {
+        metadata["total"] = json!(total);
+    }
+
+    Ok(HttpResponse::Ok().json(metadata))
+}
+
+/// Whether the cube's name or one of its annotations contains `filter`,
+/// which is lowercase, ignoring case.
+fn cube_matches(cube: &Cube, filter: &str) -> bool {
+    if cube.name.to_lowercase().contains(filter) {
+        return true;
+    }
+
+    cube.annotations.iter()
+        .flatten()
+        .any(|ann| ann.text.to_lowercase().contains(filter))
 }
 
 pub fn members_default_handler(
@@ -65,13 +248,7 @@ pub fn do_members(
     let format = format.parse::<FormatType>();
     let format = match format {
         Ok(f) => f,
-        Err(err) => {
-            return Box::new(
-                future::result(
-                    Ok(HttpResponse::NotFound().json(err.to_string()))
-                )
-            );
-        },
+        Err(err) => return boxed_error(format_error(err)),
     };
 
     let query = req.query_string();
@@ -81,55 +258,293 @@ pub fn do_members(
     let query_res = QS_NON_STRICT.deserialize_str::<MembersQueryOpt>(&query);
     let query = match query_res {
         Ok(q) => q,
-        Err(err) => {
-            return Box::new(
-                future::result(
-                    Ok(HttpResponse::BadRequest().json(err.to_string()))
-                )
-            );
-        },
+        Err(err) => return boxed_error(ServerError::invalid_query(err)),
     };
 
     let level: LevelName = match query.level.parse() {
         Ok(q) => q,
-        Err(err) => {
-            return Box::new(
-                future::result(
-                    Ok(HttpResponse::BadRequest().json(err.to_string()))
-                )
-            );
-        },
+        Err(err) => return boxed_error(ServerError::invalid_query(err)),
+    };
+
+    let cuts = match query.cuts() {
+        Ok(c) => c,
+        Err(err) => return boxed_error(ServerError::invalid_query(err)),
     };
 
     info!("Members for cube: {}, level: {}", cube, level);
 
+    let role = util::request_role(&req);
+    let role = role.as_ref().map(|r| r.as_str());
+
+    if let Some(res) = cached_members_response(req.state(), &cube, &level, &query, &cuts, role, &format) {
+        return Box::new(future::ok(res));
+    }
+
+    let search = query.search.clone();
+    let (offset, limit) = (query.offset, query.limit);
+
     let members_sql_and_headers = req.state().schema.read().unwrap()
-        .members_sql(&cube, &level);
+        .members_context_sql(
+            &cube,
+            &level,
+            query.parents.unwrap_or(false),
+            query.locale.as_ref().map(|l| l.as_str()),
+            &cuts,
+            role,
+        );
     let (members_sql, header) = match members_sql_and_headers {
         Ok(s) => s,
-        Err(err) => {
-            return Box::new(
-                future::result(
-                    Ok(HttpResponse::BadRequest().json(err.to_string()))
-                )
-            );
-        },
+        Err(err) => return boxed_error(ServerError::not_found(err)),
     };
 
-    req.state()
-        .backend
-        .exec_sql(members_sql)
-        .from_err()
+    let debug = req.state().debug;
+
+    util::exec_sql(req.state(), &cube, QueryPriority::default(), members_sql)
         .and_then(move |df| {
-            match format_records(&header, df, format) {
+            let df = util::page_members(df, search.as_ref().map(|s| s.as_str()), offset, limit);
+
+            match format_records_bytes(&header, df, format) {
                 Ok(res) => Ok(HttpResponse::Ok().body(res)),
-                Err(err) => Ok(HttpResponse::NotFound().json(err.to_string())),
+                Err(err) => Ok(ServerError::Internal { cause: err.to_string() }.error_response()),
             }
         })
+        .map_err(move |err| {
+            ServerError::from_backend_error(err, debug).into()
+        })
         .responder()
 }
 
+pub fn property_members_default_handler(
+    (req, cube): (HttpRequest<AppState>, Path<String>)
+    ) -> FutureResponse<HttpResponse>
+{
+    let cube_format = (cube.into_inner(), "csv".to_owned());
+    do_property_members(req, cube_format)
+}
+
+pub fn property_members_handler(
+    (req, cube_format): (HttpRequest<AppState>, Path<(String, String)>)
+    ) -> FutureResponse<HttpResponse>
+{
+    do_property_members(req, cube_format.into_inner())
+}
+
+/// The distinct values of a property, e.g. to populate a filter for
+/// cuts on the property.
+pub fn do_property_members(
+    req: HttpRequest<AppState>,
+    cube_format: (String, String),
+    ) -> FutureResponse<HttpResponse>
+{
+    let (cube, format) = cube_format;
+
+    let format = match format.parse::<FormatType>() {
+        Ok(f) => f,
+        Err(err) => return boxed_error(format_error(err)),
+    };
+
+    let query = req.query_string();
+    lazy_static!{
+        static ref QS_NON_STRICT: qs::Config = qs::Config::new(5, false);
+    }
+    if let Err(err) = util::check_params(req.state(), query, PROPERTY_MEMBERS_PARAMS, |_| false, std::iter::empty()) {
+        return boxed_error(ServerError::from_query_error(err));
+    }
+    let query = match QS_NON_STRICT.deserialize_str::<PropertyMembersQueryOpt>(query) {
+        Ok(q) => q,
+        Err(err) => return boxed_error(ServerError::invalid_query(err)),
+    };
+
+    info!("Property members for cube: {}, property: {}", cube, query.property);
+
+    let role = util::request_role(&req);
+    let sql_and_headers = req.state().schema.read().unwrap()
+        .property_members_sql(&cube, &query.property, query.key.unwrap_or(false), role.as_ref().map(|r| r.as_str()));
+    let (sql, header) = match sql_and_headers {
+        Ok(s) => s,
+        Err(err) => return boxed_error(ServerError::not_found(err)),
+    };
+
+    let search = query.search.clone();
+    let (offset, limit) = (query.offset, query.limit);
+    let content_type = util::format_to_content_type(&format);
+
+    let debug = req.state().debug;
+
+    util::exec_sql(req.state(), &cube, QueryPriority::default(), sql)
+        .and_then(move |df| {
+            let df = util::page_members(df, search.as_ref().map(|s| s.as_str()), offset, limit);
+
+            match format_records_bytes(&header, df, format) {
+                Ok(res) => Ok(HttpResponse::Ok().set(content_type).body(res)),
+                Err(err) => Ok(ServerError::Internal { cause: err.to_string() }.error_response()),
+            }
+        })
+        .map_err(move |err| {
+            ServerError::from_backend_error(err, debug).into()
+        })
+        .responder()
+}
+
+/// Responds with the members of a whole level from the logic layer cache,
+/// if it holds them (see `util::cached_members`).
+pub(crate) fn cached_members_response(
+    state: &AppState,
+    cube: &str,
+    level: &LevelName,
+    query: &MembersQueryOpt,
+    cuts: &[Cut],
+    role: Option<&str>,
+    format: &FormatType,
+    ) -> Option<HttpResponse>
+{
+    if !cuts.is_empty() || query.parents.unwrap_or(false) || query.locale.is_some() {
+        return None;
+    }
+
+    let schema = state.schema.read().unwrap();
+    let (df, header) = util::cached_members(state, &schema, cube, level, role)?;
+    info!("Members served from cache");
+
+    let df = util::page_members(df, query.search.as_ref().map(|s| s.as_str()), query.offset, query.limit);
+    let content_type = util::format_to_content_type(format);
+
+    match format_records_bytes(&header, df, format.clone()) {
+        Ok(res) => Some(HttpResponse::Ok().set(content_type).body(res)),
+        Err(err) => Some(ServerError::Internal { cause: err.to_string() }.error_response()),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct MetadataQueryOpt {
+    extended: Option<bool>,
+}
+
+#[derive(Debug, Deserialize)]
+struct MetadataAllQueryOpt {
+    cube_names_only: Option<bool>,
+    filter: Option<String>,
+    sort: Option<String>,
+    offset: Option<usize>,
+    limit: Option<usize>,
+}
+
 #[derive(Debug, Deserialize)]
-struct MembersQueryOpt {
-    level: String,
+pub struct MembersQueryOpt {
+    pub level: String,
+    pub parents: Option<bool>,
+    pub locale: Option<String>,
+    pub cuts: Option<Vec<String>>,
+    pub search: Option<String>,
+    pub offset: Option<usize>,
+    pub limit: Option<usize>,
+}
+
+const PROPERTY_MEMBERS_PARAMS: &[&str] = &["property", "key", "search", "offset", "limit"];
+
+#[derive(Debug, Deserialize)]
+struct PropertyMembersQueryOpt {
+    property: String,
+    /// Also return the keys of the level's members having each value
+    key: Option<bool>,
+    search: Option<String>,
+    offset: Option<usize>,
+    limit: Option<usize>,
+}
+
+impl MembersQueryOpt {
+    /// Only members in the facts matching these cuts are returned.
+    pub fn cuts(&self) -> Result<Vec<Cut>, Error> {
+        self.cuts.iter()
+            .flatten()
+            .map(|c| c.parse())
+            .collect()
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use tesseract_core::{Column, ColumnData, DataFrame, Schema};
+    use tesseract_test::{MockBackend, CACHE_MEMBERS};
+
+    use crate::app::test::{env_vars, get, test_server};
+
+    const ROLE_HEADER: &str = "X-Tesseract-Role";
+
+    /// The test schema, with a role that only sees the first state's rows.
+    fn row_filters_schema() -> Schema {
+        let schema_str = tesseract_test::SCHEMA.replacen(
+            r#""name": "test","#,
+            r#""name": "test", "security": { "roles": [{ "name": "state_00", "row_filters": [{ "cut": "[Geography].[Geography].[State].[04000US00]" }] }, { "name": "admin" }] },"#,
+            1,
+        );
+        let mut schema = Schema::from_json(&schema_str).expect("valid test schema");
+        schema.validate().expect("valid test schema");
+        schema
+    }
+
+    fn state_00_counties() -> DataFrame {
+        DataFrame::from_vec(vec![
+            Column::new("county_id".to_owned(), ColumnData::Text(vec!["05000US00".to_owned()])),
+            Column::new("county_name".to_owned(), ColumnData::Text(vec!["County 0".to_owned()])),
+        ])
+    }
+
+    #[test]
+    fn members_row_filters() {
+        let backend = MockBackend::new()
+            .respond(r"^select distinct county_id, county_name from customer_geo where ", state_00_counties);
+        let mut env_vars = env_vars();
+        env_vars.role_header = Some(ROLE_HEADER.to_owned());
+        let mut srv = test_server(row_filters_schema(), backend.clone(), env_vars);
+        let path = "/cubes/sales/members.csv?level=Geography.Geography.County";
+
+        // a role without row filters gets every member, from the cache
+        let (status, body) = get(&mut srv, path, &[(ROLE_HEADER, "admin")]);
+        assert_eq!(status, 200, "{}", body);
+        assert_eq!(body.lines().count(), CACHE_MEMBERS + 1, "{}", body);
+        assert!(backend.queries().is_empty());
+
+        // a restricted role only gets the members in its rows
+        let (status, body) = get(&mut srv, path, &[(ROLE_HEADER, "state_00")]);
+        assert_eq!(status, 200, "{}", body);
+        assert!(body.contains("05000US00"), "{}", body);
+        assert!(!body.contains("05000US01"), "{}", body);
+        assert_eq!(backend.queries().len(), 1);
+        assert!(backend.last_query().unwrap().contains("where state_id in ('04000US00')"));
+
+        // and there's no default role to fall back on
+        let (status, body) = get(&mut srv, path, &[]);
+        assert_eq!(status, 404, "{}", body);
+        assert!(body.contains("A role is required"), "{}", body);
+        assert_eq!(backend.queries().len(), 1);
+    }
+
+    #[test]
+    fn property_members_row_filters() {
+        let backend = MockBackend::new()
+            .respond(r"^select distinct form from dim_products where ", || DataFrame::from_vec(vec![
+                Column::new("form".to_owned(), ColumnData::Text(vec!["Liquid".to_owned()])),
+            ]));
+        let mut env_vars = env_vars();
+        env_vars.role_header = Some(ROLE_HEADER.to_owned());
+        let mut srv = test_server(row_filters_schema(), backend.clone(), env_vars);
+
+        let (status, body) = get(&mut srv, "/cubes/sales/property-members.csv?property=Form", &[(ROLE_HEADER, "state_00")]);
+        assert_eq!(status, 200, "{}", body);
+        assert_eq!(body.lines().collect::<Vec<_>>(), vec!["Form", "Liquid"], "{}", body);
+        assert!(backend.last_query().unwrap().contains("where state_id in ('04000US00')"));
+    }
+
+    #[test]
+    fn property_members_unknown_param() {
+        let backend = MockBackend::new();
+        let mut srv = test_server(tesseract_test::schema(), backend.clone(), env_vars());
+
+        let (status, body) = get(&mut srv, "/cubes/sales/property-members.csv?property=Form&keyy=true", &[]);
+        assert_eq!(status, 400, "{}", body);
+        assert!(body.contains("keyy"), "{}", body);
+        assert!(backend.queries().is_empty());
+    }
 }
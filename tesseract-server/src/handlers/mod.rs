@@ -1,24 +1,52 @@
 mod aggregate;
 mod aggregate_stream;
+mod dictionary;
+mod distribution;
+mod drillthrough;
+mod explain;
+mod extracts;
 mod flush;
 mod index;
+mod members_stream;
 mod metadata;
+mod saved_queries;
 pub mod logic_layer;
 mod util;
 
 pub use self::aggregate::aggregate_handler;
 pub use self::aggregate::aggregate_default_handler;
+pub use self::aggregate::AggregateQueryOpt;
 pub use self::aggregate_stream::aggregate_handler as aggregate_stream_handler;
 pub use self::aggregate_stream::aggregate_default_handler as aggregate_stream_default_handler;
+pub use self::dictionary::dictionary_handler;
+pub use self::dictionary::dictionary_default_handler;
+pub use self::distribution::distribution_handler;
+pub use self::distribution::distribution_default_handler;
+pub use self::drillthrough::drillthrough_handler;
+pub use self::drillthrough::drillthrough_default_handler;
+pub use self::explain::explain_handler;
+pub use self::extracts::extracts_handler;
 pub use self::logic_layer::logic_layer_handler;
 pub use self::logic_layer::logic_layer_default_handler;
+pub use self::logic_layer::logic_layer_stream_handler;
+pub use self::logic_layer::logic_layer_stream_default_handler;
 pub use self::logic_layer::logic_layer_non_unique_levels_handler;
 pub use self::logic_layer::logic_layer_non_unique_levels_default_handler;
 pub use self::logic_layer::logic_layer_members_handler;
 pub use self::logic_layer::logic_layer_members_default_handler;
 pub use self::flush::flush_handler;
 pub use self::index::index_handler;
+pub use self::members_stream::members_handler as members_stream_handler;
+pub use self::members_stream::members_default_handler as members_stream_default_handler;
 pub use self::metadata::members_handler;
 pub use self::metadata::members_default_handler;
 pub use self::metadata::metadata_handler;
 pub use self::metadata::metadata_all_handler;
+pub use self::metadata::property_members_handler;
+pub use self::metadata::property_members_default_handler;
+pub use self::saved_queries::saved_queries_handler;
+pub use self::saved_queries::saved_query_handler;
+pub use self::saved_queries::save_query_handler;
+pub use self::saved_queries::delete_query_handler;
+pub use self::saved_queries::run_saved_query_handler;
+pub use self::saved_queries::run_saved_query_default_handler;
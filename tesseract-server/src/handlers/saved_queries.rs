@@ -0,0 +1,274 @@
+use actix_web::{
+    FutureResponse,
+    HttpRequest,
+    HttpResponse,
+    Json,
+    Path,
+    Result as ActixResult,
+};
+use log::*;
+use serde_derive::Serialize;
+use serde_json::json;
+
+use crate::app::AppState;
+use crate::errors::ServerError;
+use crate::saved_queries::SavedQuery;
+use crate::util::boxed_error;
+use super::aggregate::aggregate_query;
+
+
+/// Header with the secret for saving and deleting queries. It's not taken
+/// from the url, so that it doesn't end up in access logs.
+pub const SECRET_HEADER: &str = "X-Tesseract-Secret";
+
+#[derive(Debug, Serialize)]
+struct SavedQueryResponse {
+    name: String,
+    cube: String,
+    query: String,
+}
+
+impl SavedQueryResponse {
+    fn new(name: &str, query: &SavedQuery) -> Self {
+        SavedQueryResponse {
+            name: name.to_owned(),
+            cube: query.cube.clone(),
+            query: query.query.clone(),
+        }
+    }
+}
+
+/// Saving and deleting queries needs the `X-Tesseract-Secret` header to
+/// match `TESSERACT_SAVED_QUERIES_SECRET`; without it, queries can't be
+/// changed.
+fn check_secret(req: &HttpRequest<AppState>) -> Result<(), ServerError> {
+    let given = req.headers().get(SECRET_HEADER)
+        .and_then(|value| value.to_str().ok());
+
+    match (&req.state().env_vars.saved_queries_secret, given) {
+        (Some(secret), Some(given)) if secrets_match(given, secret) => Ok(()),
+        _ => Err(ServerError::Unauthorized),
+    }
+}
+
+/// Compares secrets in time that only depends on their lengths, so that
+/// response times don't give away how much of a guess was right.
+fn secrets_match(given: &str, secret: &str) -> bool {
+    if given.len() != secret.len() {
+        return false;
+    }
+
+    given.bytes().zip(secret.bytes())
+        .fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+/// Lists the saved queries.
+pub fn saved_queries_handler(req: HttpRequest<AppState>) -> ActixResult<HttpResponse> {
+    let saved_queries = req.state().saved_queries.read().unwrap();
+
+    let queries: Vec<_> = saved_queries.list().iter()
+        .map(|(name, query)| SavedQueryResponse::new(name, query))
+        .collect();
+
+    Ok(HttpResponse::Ok().json(json!({ "queries": queries })))
+}
+
+pub fn saved_query_handler(
+    (req, name): (HttpRequest<AppState>, Path<String>)
+    ) -> ActixResult<HttpResponse>
+{
+    let name = name.into_inner();
+    let saved_queries = req.state().saved_queries.read().unwrap();
+
+    let query = saved_queries.get(&name)
+        .ok_or_else(|| ServerError::not_found(format!("Saved query {} not found", name)))?;
+
+    Ok(HttpResponse::Ok().json(SavedQueryResponse::new(&name, query)))
+}
+
+/// Saves a query under a name, replacing any query with that name. The
+/// query is checked against the schema first.
+pub fn save_query_handler(
+    (req, name, query): (HttpRequest<AppState>, Path<String>, Json<SavedQuery>)
+    ) -> ActixResult<HttpResponse>
+{
+    check_secret(&req)?;
+
+    let name = name.into_inner();
+    let query = query.into_inner();
+    query.validate(&req.state().schema.read().unwrap())
+        .map_err(ServerError::invalid_query)?;
+
+    info!("Saving query {}", name);
+
+    let response = SavedQueryResponse::new(&name, &query);
+    req.state().saved_queries.write().unwrap()
+        .insert(name, query)
+        .map_err(|err| ServerError::Internal { cause: err.to_string() })?;
+
+    Ok(HttpResponse::Ok().json(response))
+}
+
+pub fn delete_query_handler(
+    (req, name): (HttpRequest<AppState>, Path<String>)
+    ) -> ActixResult<HttpResponse>
+{
+    check_secret(&req)?;
+
+    let name = name.into_inner();
+    let removed = req.state().saved_queries.write().unwrap()
+        .remove(&name)
+        .map_err(|err| ServerError::Internal { cause: err.to_string() })?;
+
+    if !removed {
+        return Err(ServerError::not_found(format!("Saved query {} not found", name)).into());
+    }
+
+    info!("Deleted query {}", name);
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Runs a saved query when a format is not specified.
+/// Default format is CSV.
+pub fn run_saved_query_default_handler(
+    (req, name): (HttpRequest<AppState>, Path<String>)
+    ) -> FutureResponse<HttpResponse>
+{
+    run_saved_query(req, name.into_inner(), "csv".to_owned())
+}
+
+/// Runs a saved query when a format is specified.
+pub fn run_saved_query_handler(
+    (req, name_format): (HttpRequest<AppState>, Path<(String, String)>)
+    ) -> FutureResponse<HttpResponse>
+{
+    let (name, format) = name_format.into_inner();
+    run_saved_query(req, name, format)
+}
+
+/// Runs a saved query as an aggregate query. Params in the request are
+/// appended to the saved query's, e.g. to add a cut or a `limit`.
+fn run_saved_query(req: HttpRequest<AppState>, name: String, format: String) -> FutureResponse<HttpResponse> {
+    let saved_query = req.state().saved_queries.read().unwrap()
+        .get(&name)
+        .cloned();
+
+    let saved_query = match saved_query {
+        Some(saved_query) => saved_query,
+        None => return boxed_error(ServerError::not_found(format!("Saved query {} not found", name))),
+    };
+
+    let query = match req.query_string() {
+        "" => saved_query.query.clone(),
+        extra => format!("{}&{}", saved_query.query, extra),
+    };
+
+    aggregate_query(req, (saved_query.cube, format), query)
+}
+
+
+#[cfg(test)]
+mod test {
+    use actix_web::http::Method;
+    use serde_json::Value;
+    use tesseract_core::{Column, ColumnData, DataFrame};
+    use tesseract_test::MockBackend;
+
+    use crate::app::EnvVars;
+    use crate::app::test::{env_vars, get, send, test_server};
+    use super::*;
+
+    const SAVED_QUERY: &str = r#"{ "cube": "sales", "query": "drilldowns[]=Year.Year&measures[]=Quantity" }"#;
+
+    fn secret_env_vars() -> EnvVars {
+        EnvVars {
+            saved_queries_secret: Some("s3cret".to_owned()),
+            ..env_vars()
+        }
+    }
+
+    fn year_quantity() -> DataFrame {
+        DataFrame::from_vec(vec![
+            Column::new("year".to_owned(), ColumnData::UInt32(vec![2018, 2019])),
+            Column::new("quantity".to_owned(), ColumnData::Int64(vec![10, 20])),
+        ])
+    }
+
+    #[test]
+    fn secrets_compared() {
+        assert!(secrets_match("s3cret", "s3cret"));
+        assert!(!secrets_match("s3creT", "s3cret"));
+        assert!(!secrets_match("s3cre", "s3cret"));
+        assert!(!secrets_match("", "s3cret"));
+    }
+
+    #[test]
+    fn create_and_fetch() {
+        let backend = MockBackend::new()
+            .respond(r"sum\(quantity\)", year_quantity);
+        let mut srv = test_server(tesseract_test::schema(), backend, secret_env_vars());
+
+        let (status, body) = send(
+            &mut srv,
+            Method::PUT,
+            "/queries/by_year",
+            &[(SECRET_HEADER, "s3cret"), ("Content-Type", "application/json")],
+            SAVED_QUERY,
+        );
+        assert_eq!(status, 200, "{}", body);
+
+        let (status, body) = get(&mut srv, "/queries/by_year", &[]);
+        assert_eq!(status, 200, "{}", body);
+        let saved: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(saved["cube"], "sales");
+        assert_eq!(saved["query"], "drilldowns[]=Year.Year&measures[]=Quantity");
+
+        let (status, body) = get(&mut srv, "/queries", &[]);
+        assert_eq!(status, 200, "{}", body);
+        assert!(body.contains("by_year"), "{}", body);
+
+        let (status, body) = get(&mut srv, "/queries/by_year/run.csv", &[]);
+        assert_eq!(status, 200, "{}", body);
+        assert!(body.starts_with("Year,Quantity\n"), "{}", body);
+    }
+
+    #[test]
+    fn wrong_secret() {
+        let mut srv = test_server(tesseract_test::schema(), MockBackend::new(), secret_env_vars());
+
+        for headers in &[
+            vec![(SECRET_HEADER, "wrong"), ("Content-Type", "application/json")],
+            vec![("Content-Type", "application/json")],
+        ] {
+            let (status, body) = send(&mut srv, Method::PUT, "/queries/by_year", headers, SAVED_QUERY);
+            assert_eq!(status, 401, "{}", body);
+        }
+
+        // the secret isn't taken from the url
+        let (status, body) = send(
+            &mut srv,
+            Method::PUT,
+            "/queries/by_year?secret=s3cret",
+            &[("Content-Type", "application/json")],
+            SAVED_QUERY,
+        );
+        assert_eq!(status, 401, "{}", body);
+
+        let (status, _) = get(&mut srv, "/queries/by_year", &[]);
+        assert_eq!(status, 404);
+    }
+
+    #[test]
+    fn no_secret_configured() {
+        let mut srv = test_server(tesseract_test::schema(), MockBackend::new(), env_vars());
+
+        let (status, body) = send(
+            &mut srv,
+            Method::PUT,
+            "/queries/by_year",
+            &[(SECRET_HEADER, ""), ("Content-Type", "application/json")],
+            SAVED_QUERY,
+        );
+        assert_eq!(status, 401, "{}", body);
+    }
+}
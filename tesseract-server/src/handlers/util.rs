@@ -1,11 +1,451 @@
+use actix_web::HttpRequest;
 use actix_web::http::header::ContentType;
+use failure::{Error, bail, format_err};
+use futures::future::Future;
+use futures::stream::{self, Stream};
 use mime;
 use tesseract_core::format::FormatType;
+use tesseract_core::names::{LevelName, suggestion};
+use tesseract_core::query::TopQuery;
+use tesseract_core::{Column, ColumnData, Cube, DataFrame, MeaOrCalc, Query as TsQuery, QueryErrors, Schema};
+use url::form_urlencoded;
+
+use crate::app::AppState;
+use crate::clickhouse_settings::ClickhouseSettings;
+use crate::complexity::{self, RowEstimate};
+use crate::conversion::Conversion;
+use crate::db_config::Database;
+use crate::errors::ServerError;
+use crate::logic_layer::IdOrder;
+use crate::query_hints::{QueryHints, QueryPriority};
 
 pub(crate) fn format_to_content_type(format_type: &FormatType) -> ContentType {
     match format_type {
         FormatType::Csv => ContentType(mime::TEXT_CSV_UTF_8),
         FormatType::JsonRecords => ContentType(mime::APPLICATION_JSON),
         FormatType::JsonArrays => ContentType(mime::APPLICATION_JSON),
+        FormatType::Orc => ContentType(mime::APPLICATION_OCTET_STREAM),
+    }
+}
+
+/// Role of the principal making the request, from the header named by
+/// `TESSERACT_ROLE_HEADER`. `None` without the env var or the header, in
+/// which case the schema's default role applies.
+pub(crate) fn request_role(req: &HttpRequest<AppState>) -> Option<String> {
+    let header = req.state().env_vars.role_header.as_ref()?;
+
+    req.headers().get(header.as_str())
+        .and_then(|value| value.to_str().ok())
+        .map(|role| role.trim().to_owned())
+        .filter(|role| !role.is_empty())
+}
+
+/// Generates the sql and headers for a query, going through the sql cache.
+///
+/// `schema` should be the guard held on `state.schema`, so that a flush
+/// can't swap the schema between generating the sql and caching it.
+pub(crate) fn generate_sql(
+    state: &AppState,
+    schema: &Schema,
+    cube: &str,
+    query: &TsQuery,
+    ) -> Result<(String, Vec<String>), Error>
+{
+    if let Some(cached) = state.sql_cache.read().unwrap().get(cube, query) {
+        return Ok(cached);
+    }
+
+    let (query_ir, headers) = schema.sql_query(cube, query)?;
+    let sql = state.backend.generate_sql(query_ir);
+
+    state.sql_cache.write().unwrap()
+        .insert(cube, query, sql.clone(), headers.clone());
+
+    Ok((sql, headers))
+}
+
+/// Headers of the measure columns in the results of `query`: its measures
+/// with names resolved and repeats removed, as in `Schema::sql_query`.
+pub(crate) fn measure_headers(schema: &Schema, cube: &Cube, query: &TsQuery) -> Vec<String> {
+    schema.resolve_query(cube, query).deduplicated().measures
+        .into_iter()
+        .map(|measure| measure.0)
+        .collect()
+}
+
+/// Members of a level from the logic layer cache, with the headers of
+/// `Schema::members_sql`, so that members queries don't need the database.
+/// `None` if the cache can't answer like the database would: the level
+/// isn't cached, its labels aren't, or its members have a custom order.
+/// Also `None` if `role` has row filters on the cube (or isn't allowed to
+/// query it), since the cache holds every member.
+pub(crate) fn cached_members(
+    state: &AppState,
+    schema: &Schema,
+    cube: &str,
+    level_name: &LevelName,
+    role: Option<&str>,
+    ) -> Option<(DataFrame, Vec<String>)>
+{
+    let cube = schema.get_cube_by_name(cube).ok()?;
+    if !schema.row_filter_cuts(cube, role).ok()?.is_empty() {
+        return None;
+    }
+    let level_name = cube.resolve_level_name(level_name, schema.name_resolution)
+        .unwrap_or_else(|| level_name.clone());
+    let level = cube.get_level(&level_name)?;
+
+    if level.order_by.is_some() {
+        return None;
+    }
+
+    let cache = state.cache.read().unwrap();
+    let level_cache = cache.cubes.iter()
+        .find(|cube_cache| cube_cache.name == cube.name)?
+        .find_level_cache(&level_name)?;
+
+    let ids = &level_cache.distinct_ids;
+    let id_column = match level_cache.id_order {
+        IdOrder::Numeric => ids.iter()
+            .map(|id| id.parse::<i64>().ok())
+            .collect::<Option<Vec<_>>>()
+            .map(ColumnData::Int64),
+        _ => None,
+    };
+    let id_column = id_column.unwrap_or_else(|| ColumnData::Text(ids.clone()));
+
+    let mut columns = vec![Column::new("ID".into(), id_column)];
+    if level.name_column.is_some() {
+        columns.push(Column::new("Label".into(), ColumnData::Text(level_cache.labels.clone()?)));
+    }
+
+    let headers = columns.iter().map(|column| column.name.clone()).collect();
+    Some((DataFrame::from_vec(columns), headers))
+}
+
+/// Keeps the members with a column containing `search` (ignoring case),
+/// then skips `offset` of them and keeps up to `limit`.
+pub(crate) fn page_members(
+    df: DataFrame,
+    search: Option<&str>,
+    offset: Option<usize>,
+    limit: Option<usize>,
+    ) -> DataFrame
+{
+    if search.is_none() && offset.is_none() && limit.is_none() {
+        return df;
+    }
+
+    let search = search.map(|s| s.to_lowercase());
+    let values: Vec<Vec<String>> = match search {
+        Some(_) => df.columns.iter().map(|column| column.stringify_column_data()).collect(),
+        None => vec![],
+    };
+
+    let rows: Vec<Option<usize>> = (0..df.len())
+        .filter(|&row| {
+            match search {
+                Some(ref search) => values.iter().any(|column| column[row].to_lowercase().contains(search.as_str())),
+                None => true,
+            }
+        })
+        .skip(offset.unwrap_or(0))
+        .take(limit.unwrap_or(std::usize::MAX))
+        .map(Some)
+        .collect();
+
+    DataFrame::from_vec(
+        df.columns.iter()
+            .map(|column| Column::new(column.name.clone(), column.column_data.take(&rows, false)))
+            .collect()
+    )
+}
+
+/// Rejects a query whose estimated number of rows is above
+/// `TESSERACT_MAX_ESTIMATED_ROWS`, if set (see `complexity`).
+pub(crate) fn check_complexity(
+    state: &AppState,
+    cube: &Cube,
+    query: &TsQuery,
+    ) -> Result<(), Error>
+{
+    let max_rows = match state.env_vars.max_estimated_rows {
+        Some(max_rows) => max_rows,
+        None => return Ok(()),
+    };
+
+    match state.cache.read().unwrap().find_cube_info(&cube.name) {
+        Some(cube_cache) => RowEstimate::estimate(cube, &cube_cache, query).check(max_rows),
+        None => Ok(()),
+    }
+}
+
+/// Like `check_complexity`, but a query above the limit on a cube with an
+/// `auto_top` is truncated with that top instead of rejected, when it can
+/// be (see `complexity::auto_top`). Only clickhouse runs tops. Returns the
+/// top applied.
+///
+/// A query still above the limit with the top is a `ServerError::TooLarge`.
+pub(crate) fn limit_complexity(
+    state: &AppState,
+    cube: &Cube,
+    query: &mut TsQuery,
+    ) -> Result<Option<TopQuery>, Error>
+{
+    let max_rows = match state.env_vars.max_estimated_rows {
+        Some(max_rows) => max_rows,
+        None => return Ok(None),
+    };
+
+    let cube_cache = match state.cache.read().unwrap().find_cube_info(&cube.name) {
+        Some(cube_cache) => cube_cache,
+        None => return Ok(None),
+    };
+    let estimate = RowEstimate::estimate(cube, &cube_cache, query);
+
+    if let Database::Clickhouse = state.db_type {
+        if let Some(top) = complexity::auto_top(cube, &estimate, query, max_rows) {
+            let top_estimate = estimate.with_top(&top);
+            if top_estimate.rows > max_rows {
+                return Err(ServerError::TooLarge {
+                    cause: format!("Query could return an estimated {} rows even with the cube's auto top of {}, above the limit of {}. Add cuts or remove drilldowns to narrow it down",
+                        top_estimate.rows,
+                        complexity::auto_top_header_value(&top),
+                        max_rows,
+                    ),
+                }.into());
+            }
+
+            query.top = Some(top.clone());
+            return Ok(Some(top));
+        }
     }
+
+    estimate.check(max_rows)?;
+    Ok(None)
+}
+
+/// Runs sql on the backend once the query limiter lets a query on `cube`
+/// through. Errors from the limiter are `ServerError`s.
+///
+/// If the same sql is already running, waits for its result instead (see
+/// `SingleFlight`).
+pub(crate) fn exec_sql(
+    state: &AppState,
+    cube: &str,
+    priority: QueryPriority,
+    sql: String,
+    ) -> Box<Future<Item=DataFrame, Error=Error>>
+{
+    let backend = state.backend.clone();
+    let query_limiter = state.query_limiter.clone();
+    let limiter_cube = cube.to_owned();
+    let flight_sql = sql.clone();
+
+    state.single_flight.run(cube, &flight_sql, move || {
+        Box::new(
+            query_limiter
+                .acquire(&limiter_cube, priority)
+                .from_err()
+                .and_then(move |permit| {
+                    backend.exec_sql(sql)
+                        .then(move |res| {
+                            drop(permit);
+                            res
+                        })
+                })
+        )
+    })
+}
+
+/// Streaming version of `exec_sql`. The query counts as running until the
+/// stream is dropped, and the stream is only returned once its first chunk
+/// is in (see `peek_df_stream`).
+pub(crate) fn exec_sql_stream(
+    state: &AppState,
+    cube: &str,
+    priority: QueryPriority,
+    sql: String,
+    ) -> Box<Future<Item=Box<Stream<Item=Result<DataFrame, Error>, Error=Error>>, Error=Error>>
+{
+    let backend = state.backend.clone();
+
+    Box::new(
+        state.query_limiter
+            .acquire(cube, priority)
+            .from_err()
+            .and_then(move |permit| {
+                let df_stream = backend.exec_sql_stream(sql)
+                    .map(move |df_res| {
+                        let _permit = &permit;
+                        df_res
+                    });
+
+                peek_df_stream(df_stream)
+            })
+    )
+}
+
+/// Waits for the first `DataFrame` of a stream, so that a handler can hold
+/// off on committing to a 200 until the backend has produced a result; an
+/// error at that point is returned as an error response instead of an
+/// empty body. The first `DataFrame` is put back in front of the stream.
+pub(crate) fn peek_df_stream<S>(
+    df_stream: S,
+    ) -> Box<Future<Item=Box<Stream<Item=Result<DataFrame, Error>, Error=Error>>, Error=Error>>
+    where S: Stream<Item=Result<DataFrame, Error>, Error=Error> + 'static
+{
+    Box::new(
+        df_stream.into_future()
+            .map_err(|(err, _)| err)
+            .and_then(|(first, rest)| {
+                match first {
+                    Some(Err(err)) => Err(err),
+                    first => {
+                        let df_stream = stream::iter_ok(first).chain(rest);
+                        Ok(Box::new(df_stream) as Box<Stream<Item=Result<DataFrame, Error>, Error=Error>>)
+                    },
+                }
+            })
+    )
+}
+
+/// Appends the server's ClickHouse settings, combined with the settings
+/// from the query's `settings` param and the query's hints, to the sql.
+///
+/// Hints are only applied for ClickHouse, other databases ignore them.
+pub(crate) fn apply_clickhouse_settings(
+    state: &AppState,
+    query_settings: Option<&str>,
+    hints: &QueryHints,
+    sql: String,
+    ) -> Result<String, Error>
+{
+    match state.db_type {
+        Database::Clickhouse => {
+            let query_settings = match query_settings {
+                Some(raw) => ClickhouseSettings::parse(raw)?,
+                None => ClickhouseSettings::default(),
+            };
+
+            Ok(state.env_vars.clickhouse_settings
+                .with_query_settings(&query_settings)
+                .with_hints(hints)
+                .apply(sql))
+        },
+        _ => {
+            if query_settings.is_some() {
+                bail!("settings are only supported for ClickHouse");
+            }
+            Ok(sql)
+        },
+    }
+}
+
+/// Conversions for a query's `convert` param: each measure in the query
+/// that declares a unit is converted to `target`.
+///
+/// Results are converted once the query has run, so filters (and
+/// `top_where`) and growth, which are computed in sql, can't be used on a
+/// converted measure: they'd use the values before conversion. `top` ranks
+/// the same before and after conversion, since each measure is scaled by a
+/// single positive rate.
+pub(crate) fn conversions(
+    state: &AppState,
+    schema: &Schema,
+    cube: &str,
+    query: &TsQuery,
+    target: Option<&str>,
+    ) -> Result<Vec<Conversion>, Error>
+{
+    let target = match target {
+        Some(target) => target,
+        None => return Ok(vec![]),
+    };
+
+    let rates = state.env_vars.conversion_rates.as_ref()
+        .ok_or_else(|| format_err!("convert is not available, no conversion rates are configured"))?;
+
+    let cube = schema.get_cube_by_name(cube)?;
+
+    let resolve = |measure: &str| {
+        cube.resolve_measure_name(measure, schema.name_resolution)
+            .unwrap_or_else(|| measure.to_owned())
+    };
+
+    let measures: Vec<String> = query.measures.iter()
+        .map(|m| resolve(&m.0))
+        .collect();
+
+    let conversions = rates.read().unwrap()
+        .conversions(&cube.get_measure_units(), &measures, target)?;
+
+    let is_converted = |measure: &str| {
+        let measure = resolve(measure);
+        conversions.iter().any(|c| c.measure == measure)
+    };
+
+    let filtered = query.filters.iter().map(|f| &f.by_mea_or_calc)
+        .chain(query.top_where.iter().map(|t| &t.by_mea_or_calc))
+        .filter_map(|m| match m {
+            MeaOrCalc::Mea(m) => Some(&m.0),
+            _ => None,
+        })
+        .find(|m| is_converted(m));
+    if let Some(measure) = filtered {
+        bail!("convert cannot be used with a filter on {}, which is filtered before conversion", measure);
+    }
+
+    if let Some(ref growth) = query.growth {
+        if is_converted(&growth.mea.0) {
+            bail!("convert cannot be used with growth on {}, which is computed before conversion", growth.mea.0);
+        }
+    }
+
+    Ok(conversions)
+}
+
+/// In strict mode, rejects query params that are neither in `known` nor
+/// accepted by `is_known`, so that typos (like `drildowns`) aren't silently
+/// ignored. Every unknown param is reported, with suggestions from
+/// `candidates`.
+pub(crate) fn check_params<'a, F, I>(
+    state: &AppState,
+    query: &str,
+    known: &[&str],
+    is_known: F,
+    candidates: I,
+    ) -> Result<(), Error>
+    where F: Fn(&str) -> bool,
+          I: IntoIterator<Item=&'a str>,
+{
+    if !state.env_vars.strict_params {
+        return Ok(());
+    }
+
+    let mut unknown: Vec<String> = form_urlencoded::parse(query.as_bytes())
+        // serde_qs keys can have brackets, like `drilldowns[]`
+        .map(|(key, _)| key.split('[').next().unwrap_or("").to_owned())
+        .filter(|key| !key.is_empty())
+        .filter(|key| !known.contains(&key.as_str()) && !is_known(key))
+        .collect();
+    unknown.sort();
+    unknown.dedup();
+
+    if unknown.is_empty() {
+        return Ok(());
+    }
+
+    let candidates: Vec<&str> = known.iter().cloned().chain(candidates).collect();
+
+    let errors = unknown.iter()
+        .map(|key| {
+            format!("Unknown parameter {}{}",
+                key,
+                suggestion(key, candidates.iter().cloned()),
+            )
+        })
+        .collect();
+
+    Err(QueryErrors(errors).into())
 }
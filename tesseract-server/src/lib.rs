@@ -0,0 +1,42 @@
+//! tesseract-core contains Schema;
+//! Schema is stateless; it is constructed from the schema file.
+//! Schema is held in the AppState struct to provide access from a route
+//!
+//! Each route instance will apply a tesseract_core::Query to tesseract_core::Schema to get sql.
+//! The route instance then sends sql to a database and gets results back in a
+//! tesseract_core::DataFrame. DataFrame is then applied to Schema to format result (jsonrecords
+//! or csv).
+//!
+//!
+//! Backend trait: exec_sql() takes in a sql string, outputs a DataFrame.
+//! Because tesseract-core generates just sql (instead of taking a query and schema into a
+//! `Backend`, it allows different kinds of backends to be used. Don't have to worry about
+//! async/sync, which is the hardest difference to manage. Otherwise it would be easy to define
+//! a `Backend` trait. (Or, can I do this and define the Backend trait as defining futures,
+//! and have sync operations return a future? This might still be tricky dealing with actix,
+//! it's overall easier to leave backend handling on the server side entirely).
+//!
+//! The database is able to be declared in the schema, each fact table and dim can be from
+//! different databases. Supported: clickhouse, postgres, mysql, sqlite.
+
+pub mod alerts;
+pub mod app;
+pub mod cli;
+pub mod clickhouse_settings;
+pub mod complexity;
+pub mod conversion;
+pub mod db_config;
+pub mod errors;
+pub mod extracts;
+pub mod freshness;
+pub mod handlers;
+pub mod logic_layer;
+pub mod metadata_cache;
+pub mod query_hints;
+pub mod query_limiter;
+pub mod saved_queries;
+pub mod single_flight;
+pub mod schema_config;
+pub mod slow_query_log;
+pub mod sql_cache;
+pub mod util;
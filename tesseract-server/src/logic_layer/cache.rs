@@ -1,17 +1,23 @@
+use std::cmp::Ordering;
 use std::collections::HashMap;
 use actix::SystemRunner;
+use chrono::{Datelike, NaiveDate};
 use failure::{Error, format_err};
 use log::info;
 
 use serde_derive::Deserialize;
 
-use tesseract_core::{Schema, Backend};
-use tesseract_core::names::{LevelName, Property};
+use tesseract_core::{Schema, Backend, DataFrame};
+use tesseract_core::names::{LevelName, NameResolution, Property};
 use tesseract_core::schema::{Level, Cube, InlineTable};
 
 use crate::logic_layer::{LogicLayerConfig};
 
 
+/// Names of the levels whose members are cached as time members.
+const TIME_LEVEL_NAMES: [&str; 5] = ["Year", "Quarter", "Month", "Week", "Day"];
+
+
 #[derive(Debug, Clone)]
 pub enum TimeValue {
     First,
@@ -102,6 +108,55 @@ impl Time {
 }
 
 
+/// A cached time member. Members are ordered by their integer or date
+/// value when they have one, so that e.g. months stored as text sort `9`
+/// before `10`, and by their text otherwise.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+pub struct TimeMember {
+    order: TimeOrder,
+    pub value: String,
+}
+
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+enum TimeOrder {
+    Int(i64),
+    // Days since the common era
+    Date(i32),
+    Text,
+}
+
+
+impl TimeMember {
+    pub fn new(value: String) -> Self {
+        let trimmed = value.trim();
+
+        let order = if let Ok(n) = trimmed.parse::<i64>() {
+            TimeOrder::Int(n)
+        } else if let Ok(date) = NaiveDate::parse_from_str(trimmed, "%Y-%m-%d") {
+            TimeOrder::Date(date.num_days_from_ce())
+        } else {
+            TimeOrder::Text
+        };
+
+        TimeMember { order, value }
+    }
+}
+
+
+/// Sorted and deduplicated time members from stringified values.
+pub fn time_members(values: Vec<String>) -> Vec<TimeMember> {
+    let mut members: Vec<TimeMember> = values.into_iter()
+        .map(TimeMember::new)
+        .collect();
+
+    members.sort();
+    members.dedup();
+
+    members
+}
+
+
 /// Holds cache information.
 #[derive(Debug, Clone)]
 pub struct Cache {
@@ -128,25 +183,28 @@ pub struct CubeCache {
     pub name: String,
 
     pub year_level: Option<Level>,
-    pub year_values: Option<Vec<String>>,
+    pub year_values: Option<Vec<TimeMember>>,
 
     pub quarter_level: Option<Level>,
-    pub quarter_values: Option<Vec<String>>,
+    pub quarter_values: Option<Vec<TimeMember>>,
 
     pub month_level: Option<Level>,
-    pub month_values: Option<Vec<String>>,
+    pub month_values: Option<Vec<TimeMember>>,
 
     pub week_level: Option<Level>,
-    pub week_values: Option<Vec<String>>,
+    pub week_values: Option<Vec<TimeMember>>,
 
     pub day_level: Option<Level>,
-    pub day_values: Option<Vec<String>>,
+    pub day_values: Option<Vec<TimeMember>>,
 
     pub level_map: HashMap<String, LevelName>,
     pub property_map: HashMap<String, Property>,
 
-    // Maps a level name to a `LevelCache` object
-    pub level_caches: HashMap<String, LevelCache>,
+    // How names are looked up in `level_map` and `property_map`
+    pub name_resolution: NameResolution,
+
+    // Maps a level's full name to a `LevelCache` object
+    pub level_caches: HashMap<LevelName, LevelCache>,
 
     // Maps a dimension name to a `DimensionCache` object
     pub dimension_caches: HashMap<String, DimensionCache>,
@@ -154,30 +212,66 @@ pub struct CubeCache {
 
 
 impl CubeCache {
+    /// Looks up a level by its logic layer name, or by its full name
+    /// (`Dimension.Hierarchy.Level`, or with brackets), which picks a
+    /// level outside the default hierarchy when several hierarchies of a
+    /// dimension have levels of the same name. Errors if the name is
+    /// ambiguous (see `NameResolution::get`).
+    pub fn find_level(&self, name: &str) -> Result<Option<&LevelName>, Error> {
+        if let Some(level_name) = self.name_resolution.get(&self.level_map, name)? {
+            return Ok(Some(level_name));
+        }
+        if name.is_empty() {
+            return Ok(None);
+        }
+        let full_name: LevelName = match name.parse() {
+            Ok(full_name) => full_name,
+            Err(_) => return Ok(None),
+        };
+
+        // every level of the cube has a cache
+        Ok(self.level_caches.keys().find(|level_name| {
+            self.name_resolution.matches(&level_name.dimension, &full_name.dimension) &&
+                self.name_resolution.matches(&level_name.hierarchy, &full_name.hierarchy) &&
+                self.name_resolution.matches(&level_name.level, &full_name.level)
+        }))
+    }
+
+    /// Looks up a property by its logic layer name. Errors if the name is
+    /// ambiguous (see `NameResolution::get`).
+    pub fn find_property(&self, name: &str) -> Result<Option<&Property>, Error> {
+        self.name_resolution.get(&self.property_map, name)
+    }
+
+    /// Looks up the `LevelCache` for a level by its schema name.
+    pub fn find_level_cache(&self, level_name: &LevelName) -> Option<&LevelCache> {
+        self.level_caches.get(level_name)
+    }
+
     pub fn get_time_cut(&self, time: Time) -> Result<(String, String), Error> {
         let (val_res, ln_res) = match time.precision {
             TimePrecision::Year => {
-                let v = self.get_value(&time, self.year_values.clone());
+                let v = self.get_value(&time, &self.year_values);
                 let l = self.get_level_name(self.year_level.clone());
                 (v, l)
             },
             TimePrecision::Quarter => {
-                let v = self.get_value(&time, self.quarter_values.clone());
+                let v = self.get_value(&time, &self.quarter_values);
                 let l = self.get_level_name(self.quarter_level.clone());
                 (v, l)
             },
             TimePrecision::Month => {
-                let v = self.get_value(&time, self.month_values.clone());
+                let v = self.get_value(&time, &self.month_values);
                 let l = self.get_level_name(self.month_level.clone());
                 (v, l)
             },
             TimePrecision::Week => {
-                let v = self.get_value(&time, self.week_values.clone());
+                let v = self.get_value(&time, &self.week_values);
                 let l = self.get_level_name(self.week_level.clone());
                 (v, l)
             },
             TimePrecision::Day => {
-                let v = self.get_value(&time, self.day_values.clone());
+                let v = self.get_value(&time, &self.day_values);
                 let l = self.get_level_name(self.day_level.clone());
                 (v, l)
             }
@@ -196,6 +290,57 @@ impl CubeCache {
         Ok((ln, val))
     }
 
+    /// Cut on the latest member of the cube's coarsest time level, for
+    /// cubes with `default_time: latest`.
+    pub fn latest_time_cut(&self) -> Option<(String, String)> {
+        let precisions = vec![
+            (TimePrecision::Year, &self.year_level),
+            (TimePrecision::Quarter, &self.quarter_level),
+            (TimePrecision::Month, &self.month_level),
+            (TimePrecision::Week, &self.week_level),
+            (TimePrecision::Day, &self.day_level),
+        ];
+
+        precisions.into_iter()
+            .find(|(_, level)| level.is_some())
+            .and_then(|(precision, _)| {
+                self.get_time_cut(Time { precision, value: TimeValue::Last }).ok()
+            })
+    }
+
+    /// Latest member of the cube's finest time level, with the level's name.
+    pub fn latest_period(&self) -> Option<(String, String)> {
+        let levels = vec![
+            (&self.day_level, &self.day_values),
+            (&self.week_level, &self.week_values),
+            (&self.month_level, &self.month_values),
+            (&self.quarter_level, &self.quarter_values),
+            (&self.year_level, &self.year_values),
+        ];
+
+        levels.into_iter()
+            .find(|(level, _)| level.is_some())
+            .and_then(|(level, values)| {
+                let level = level.as_ref()?;
+                let value = values.as_ref()?.last()?;
+                Some((level.name.clone(), value.value.clone()))
+            })
+    }
+
+    /// Replaces the cached members of a time level (see `time_level_sql`).
+    pub fn set_time_values(&mut self, level_name: &str, values: Vec<String>) {
+        let values = Some(time_members(values));
+
+        match level_name {
+            "Year" => self.year_values = values,
+            "Quarter" => self.quarter_values = values,
+            "Month" => self.month_values = values,
+            "Week" => self.week_values = values,
+            "Day" => self.day_values = values,
+            _ => (),
+        }
+    }
+
     pub fn get_level_name(&self, level: Option<Level>) -> Option<String> {
         match level {
             Some(l) => Some(l.name),
@@ -203,19 +348,19 @@ impl CubeCache {
         }
     }
 
-    pub fn get_value(&self, time: &Time, opt: Option<Vec<String>>) -> Option<String> {
+    pub fn get_value(&self, time: &Time, opt: &Option<Vec<TimeMember>>) -> Option<String> {
         match opt {
             Some(v) => {
                 match time.value {
                     TimeValue::First => {
                         if v.len() >= 1 {
-                            return Some(v[0].clone());
+                            return Some(v[0].value.clone());
                         }
                         None
                     },
                     TimeValue::Last => {
                         if v.len() >= 1 {
-                            return Some(v.last().unwrap().clone())
+                            return Some(v.last().unwrap().value.clone())
                         }
                         None
                     },
@@ -232,13 +377,119 @@ impl CubeCache {
 pub struct LevelCache {
     pub parent_map: Option<HashMap<String, String>>,
     pub children_map: Option<HashMap<String, Vec<String>>>,
-    pub neighbors_map: HashMap<String, Vec<String>>,
+
+    // Member ids in key order, for neighbors lookups and members queries
+    pub distinct_ids: Vec<String>,
+    pub id_order: IdOrder,
+
+    // Label of each member in `distinct_ids`, for levels with a name column
+    // in a database table
+    pub labels: Option<Vec<String>>,
+
+    // Number of distinct members, and the smallest and largest member keys
+    pub member_count: usize,
+    pub min_key: Option<String>,
+    pub max_key: Option<String>,
 }
 
 
-#[derive(Debug, Clone, Deserialize)]
+impl LevelCache {
+    /// Up to two members before and two after `id`, in key order.
+    pub fn neighbors(&self, id: &str) -> Option<Vec<String>> {
+        let i = self.id_position(id)?;
+        let start = i.saturating_sub(2);
+        let end = (i + 3).min(self.distinct_ids.len());
+
+        Some(
+            self.distinct_ids[start..i].iter()
+                .chain(&self.distinct_ids[i + 1..end])
+                .cloned()
+                .collect()
+        )
+    }
+
+    fn id_position(&self, id: &str) -> Option<usize> {
+        match self.id_order {
+            IdOrder::Numeric => {
+                let value = id.parse::<i128>().ok()?;
+                self.distinct_ids
+                    .binary_search_by(|probe| {
+                        probe.parse::<i128>()
+                            .map(|p| p.cmp(&value))
+                            .unwrap_or(Ordering::Less)
+                    })
+                    .ok()
+            },
+            IdOrder::Text => self.distinct_ids.binary_search_by(|probe| probe.as_str().cmp(id)).ok(),
+            IdOrder::Unsorted => self.distinct_ids.iter().position(|probe| probe == id),
+        }
+    }
+}
+
+
+/// See `get_id_order`.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub enum IdOrder {
+    Numeric,
+    Text,
+    Unsorted,
+}
+
+
+/// Maps the member ids of a dimension to the levels they're members of.
+///
+/// Dimensions can have millions of members, so each id maps to a bitset of
+/// indexes into the dimension's levels instead of to `LevelName`s.
+#[derive(Debug, Clone, Default, Deserialize)]
 pub struct DimensionCache {
-    pub id_map: HashMap<String, Vec<LevelName>>,
+    levels: Vec<LevelName>,
+    id_map: HashMap<Box<str>, u64>,
+}
+
+
+impl DimensionCache {
+    /// Most levels a dimension can have, one per bit of the bitsets.
+    const MAX_LEVELS: usize = 64;
+
+    pub fn insert(&mut self, id: &str, level_name: &LevelName) -> Result<(), Error> {
+        let idx = match self.levels.iter().position(|ln| ln == level_name) {
+            Some(idx) => idx,
+            None => {
+                if self.levels.len() == Self::MAX_LEVELS {
+                    return Err(format_err!("Dimension {} has more than {} levels", level_name.dimension, Self::MAX_LEVELS));
+                }
+                self.levels.push(level_name.clone());
+                self.levels.len() - 1
+            }
+        };
+
+        match self.id_map.get_mut(id) {
+            Some(bits) => *bits |= 1 << idx,
+            None => { self.id_map.insert(id.into(), 1 << idx); },
+        }
+
+        Ok(())
+    }
+
+    /// Levels that have `id` as a member.
+    pub fn get_levels(&self, id: &str) -> Option<Vec<&LevelName>> {
+        let bits = self.id_map.get(id)?;
+
+        Some(
+            self.levels.iter()
+                .enumerate()
+                .filter(|(idx, _)| bits & (1 << idx) != 0)
+                .map(|(_, level_name)| level_name)
+                .collect()
+        )
+    }
+
+    pub fn has_member(&self, id: &str, level_name: &LevelName) -> bool {
+        match (self.id_map.get(id), self.levels.iter().position(|ln| ln == level_name)) {
+            (Some(bits), Some(idx)) => bits & (1 << idx) != 0,
+            _ => false,
+        }
+    }
 }
 
 
@@ -251,33 +502,26 @@ pub fn populate_cache(
 ) -> Result<Cache, Error> {
     info!("Populating cache...");
 
-    let time_column_names = vec![
-        "Year".to_string(),
-        "Quarter".to_string(),
-        "Month".to_string(),
-        "Week".to_string(),
-        "Day".to_string()
-    ];
-
     let mut cubes: Vec<CubeCache> = vec![];
+    let name_resolution = schema.name_resolution;
 
     for cube in schema.cubes {
         let mut year_level: Option<Level> = None;
-        let mut year_values: Option<Vec<String>> = None;
+        let mut year_values: Option<Vec<TimeMember>> = None;
         let mut quarter_level: Option<Level> = None;
-        let mut quarter_values: Option<Vec<String>> = None;
+        let mut quarter_values: Option<Vec<TimeMember>> = None;
         let mut month_level: Option<Level> = None;
-        let mut month_values: Option<Vec<String>> = None;
+        let mut month_values: Option<Vec<TimeMember>> = None;
         let mut week_level: Option<Level> = None;
-        let mut week_values: Option<Vec<String>> = None;
+        let mut week_values: Option<Vec<TimeMember>> = None;
         let mut day_level: Option<Level> = None;
-        let mut day_values: Option<Vec<String>> = None;
+        let mut day_values: Option<Vec<TimeMember>> = None;
 
-        let mut level_caches: HashMap<String, LevelCache> = HashMap::new();
+        let mut level_caches: HashMap<LevelName, LevelCache> = HashMap::new();
         let mut dimension_caches: HashMap<String, DimensionCache> = HashMap::new();
 
         for dimension in &cube.dimensions {
-            let mut id_map: HashMap<String, Vec<LevelName>> = HashMap::new();
+            let mut dimension_cache = DimensionCache::default();
 
             for hierarchy in &dimension.hierarchies {
                 let table = match &hierarchy.table {
@@ -285,36 +529,40 @@ pub fn populate_cache(
                     None => &cube.table.name
                 };
 
-                for level in &hierarchy.levels {
-                    if time_column_names.contains(&level.name) {
-                        let val = get_distinct_values(
-                            &level.key_column, &table, backend.clone(), sys
-                        )?;
+                // Members of all the levels, for hierarchies in the database
+                let hierarchy_members = if hierarchy.inline_table.is_none() {
+                    Some(get_hierarchy_members(&hierarchy.levels, table, backend.clone(), sys)?)
+                } else {
+                    None
+                };
+
+                for (level_idx, level) in hierarchy.levels.iter().enumerate() {
+                    if TIME_LEVEL_NAMES.contains(&level.name.as_str()) {
+                        let val = match &hierarchy_members {
+                            Some(members) => members.distinct_ids(level_idx),
+                            None => get_distinct_values(
+                                &level.key_column, &table, backend.clone(), sys
+                            )?,
+                        };
 
                         if level.name == "Year" {
                             year_level = Some(level.clone());
-                            year_values = Some(val);
+                            year_values = Some(time_members(val));
                         } else if level.name == "Quarter" {
                             quarter_level = Some(level.clone());
-                            quarter_values = Some(val);
+                            quarter_values = Some(time_members(val));
                         } else if level.name == "Month" {
                             month_level = Some(level.clone());
-                            month_values = Some(val);
+                            month_values = Some(time_members(val));
                         } else if level.name == "Week" {
                             week_level = Some(level.clone());
-                            week_values = Some(val);
+                            week_values = Some(time_members(val));
                         } else if level.name == "Day" {
                             day_level = Some(level.clone());
-                            day_values = Some(val);
+                            day_values = Some(time_members(val));
                         }
                     }
 
-                    // Get unique name for this level
-                    let unique_name = match get_unique_level_name(&cube, ll_config, &level)? {
-                        Some(name) => name,
-                        None => return Err(format_err!("Couldn't find unique name for {}", level.name.clone()))
-                    };
-
                     let level_name = LevelName::new(
                         dimension.name.clone(),
                         hierarchy.name.clone(),
@@ -328,6 +576,8 @@ pub fn populate_cache(
                     let child_level = cube.get_child_level(&level_name)?;
 
                     let mut distinct_ids: Vec<String> = vec![];
+                    let id_order;
+                    let mut labels: Option<Vec<String>> = None;
 
                     if hierarchy.inline_table.is_some() {
                         // Inline table
@@ -361,46 +611,52 @@ pub fn populate_cache(
                                 }
                             }
                         }
+
+                        id_order = get_id_order(&distinct_ids);
                     } else {
                         // Database table
 
+                        let members = match &hierarchy_members {
+                            Some(members) => members,
+                            None => return Err(format_err!("Could not get members for {}", level.name.clone()))
+                        };
+
                         if parent_levels.len() >= 1 {
-                            parent_map = Some(get_parent_data(
-                                &parent_levels[parent_levels.len() - 1], &level,
-                                table, backend.clone(), sys
-                            )?);
+                            parent_map = Some(members.parent_map(level_idx));
                         }
 
-                        match child_level {
-                            Some(child_level) => {
-                                children_map = Some(get_children_data(
-                                    &level, &child_level,
-                                    table, backend.clone(), sys
-                                )?);
-                            },
-                            None => ()
+                        if child_level.is_some() {
+                            children_map = Some(members.children_map(level_idx));
                         }
 
-                        // Get all IDs for this level
-                        distinct_ids = get_distinct_values(
-                            &level.key_column, &table, backend.clone(), sys
-                        )?;
+                        // Get all IDs for this level, and their labels
+                        distinct_ids = members.distinct_ids(level_idx);
+                        id_order = members.id_order(level_idx);
+                        labels = members.labels(level_idx);
                     }
 
-                    let neighbors_map = get_neighbors_map(&distinct_ids);
+                    let member_count = distinct_ids.len();
+                    let (min_key, max_key) = get_key_range(&distinct_ids);
 
-                    // Add each distinct ID to the id_map HashMap
-                    for distinct_id in distinct_ids {
-                        id_map.entry(distinct_id.clone()).or_insert(vec![]);
-                        let map_entry = id_map.get_mut(&distinct_id).unwrap();
-                        map_entry.push(level_name.clone());
+                    // Add each distinct ID to the dimension's id map
+                    for distinct_id in &distinct_ids {
+                        dimension_cache.insert(distinct_id, &level_name)?;
                     }
 
-                    level_caches.insert(unique_name.clone(), LevelCache { parent_map, children_map, neighbors_map });
+                    level_caches.insert(level_name.clone(), LevelCache {
+                        parent_map,
+                        children_map,
+                        distinct_ids,
+                        id_order,
+                        labels,
+                        member_count,
+                        min_key,
+                        max_key,
+                    });
                 }
             }
 
-            dimension_caches.insert(dimension.name.clone(), DimensionCache { id_map });
+            dimension_caches.insert(dimension.name.clone(), dimension_cache);
         }
 
         let level_map = get_level_map(&cube, ll_config)?;
@@ -420,6 +676,7 @@ pub fn populate_cache(
             day_values,
             level_map,
             property_map,
+            name_resolution,
             level_caches,
             dimension_caches,
         })
@@ -431,47 +688,6 @@ pub fn populate_cache(
 }
 
 
-pub fn get_unique_level_name(cube: &Cube, ll_config: &Option<LogicLayerConfig>, level: &Level) -> Result<Option<String>, Error> {
-    for dimension in &cube.dimensions {
-        for hierarchy in &dimension.hierarchies {
-            for curr_level in &hierarchy.levels {
-                if curr_level == level {
-                    let level_name = LevelName::new(
-                        dimension.name.clone(),
-                        hierarchy.name.clone(),
-                        curr_level.name.clone()
-                    );
-
-                    let unique_level_name = match ll_config {
-                        Some(ll_config) => {
-                            let unique_level_name_opt = if dimension.is_shared {
-                                ll_config.find_unique_shared_dimension_level_name(
-                                    &dimension.name, &cube.name, &level_name
-                                )?
-                            } else {
-                                ll_config.find_unique_cube_level_name(
-                                    &cube.name, &level_name
-                                )?
-                            };
-
-                            match unique_level_name_opt {
-                                Some(unique_level_name) => unique_level_name,
-                                None => curr_level.name.clone()
-                            }
-                        },
-                        None => curr_level.name.clone()
-                    };
-
-                    return Ok(Some(unique_level_name))
-                }
-            }
-        }
-    }
-
-    Ok(None)
-}
-
-
 pub fn get_level_map(cube: &Cube, ll_config: &Option<LogicLayerConfig>) -> Result<HashMap<String, LevelName>, Error> {
     let mut level_name_map = HashMap::new();
 
@@ -504,10 +720,15 @@ pub fn get_level_map(cube: &Cube, ll_config: &Option<LogicLayerConfig>) -> Resul
                     None => level.name.clone()
                 };
 
-                level_name_map.insert(
-                    unique_level_name.to_string(),
-                    level_name
-                );
+                // a level name shared by several hierarchies of the
+                // dimension is the default hierarchy's; the others are
+                // found by their full name (see `CubeCache::find_level`)
+                if dimension.is_default_hierarchy(&hierarchy.name) || !level_name_map.contains_key(&unique_level_name) {
+                    level_name_map.insert(
+                        unique_level_name.to_string(),
+                        level_name
+                    );
+                }
             }
         }
     }
@@ -551,10 +772,14 @@ pub fn get_property_map(cube: &Cube, ll_config: &Option<LogicLayerConfig>) -> Re
                             None => prop.name.clone()
                         };
 
-                        property_map.insert(
-                            unique_property_name.to_string(),
-                            property
-                        );
+                        // as for levels, the default hierarchy's property
+                        // takes a shared name
+                        if dimension.is_default_hierarchy(&hierarchy.name) || !property_map.contains_key(&unique_property_name) {
+                            property_map.insert(
+                                unique_property_name.to_string(),
+                                property
+                            );
+                        }
                     }
                 }
             }
@@ -637,56 +862,104 @@ pub fn get_inline_children_data(
 }
 
 
-pub fn get_parent_data(
-        parent_level: &Level,
-        current_level: &Level,
-        table: &str,
-        backend: Box<dyn Backend + Sync + Send>,
-        sys: &mut SystemRunner
-) -> Result<HashMap<String, String>, Error> {
-    let mut parent_data: HashMap<String, String> = HashMap::new();
+/// Members of the levels of a hierarchy, from a single query on its table
+/// for the distinct combinations of the level keys.
+pub struct HierarchyMembers {
+    // Sorted distinct ids of each level
+    distinct_ids: Vec<Vec<String>>,
+    // The key of each level in every row of the query results
+    rows: Vec<Vec<String>>,
+    // Whether each level's key column is an integer column
+    integer_keys: Vec<bool>,
+    // The label of each level's distinct ids, for levels with a name column
+    labels: Vec<Option<Vec<String>>>,
+}
 
-    let future = backend
-        .exec_sql(
-            format!(
-                "select distinct {0}, {1} from {2} group by {0}, {1} order by {0}, {1}",
-                parent_level.key_column, current_level.key_column, table,
-            ).to_string()
-        );
 
-    let df = match sys.block_on(future) {
-        Ok(df) => df,
-        Err(err) => {
-            return Err(format_err!("Error populating cache with backend data: {}", err));
+impl HierarchyMembers {
+    pub fn distinct_ids(&self, level_idx: usize) -> Vec<String> {
+        self.distinct_ids[level_idx].clone()
+    }
+
+    /// Integer keys are sorted by value, and text keys by their text.
+    pub fn id_order(&self, level_idx: usize) -> IdOrder {
+        if self.integer_keys[level_idx] {
+            IdOrder::Numeric
+        } else {
+            IdOrder::Text
         }
-    };
+    }
 
-    let parent_column = df.columns[0].stringify_column_data();
-    let current_column = df.columns[1].stringify_column_data();
+    pub fn labels(&self, level_idx: usize) -> Option<Vec<String>> {
+        self.labels[level_idx].clone()
+    }
 
-    for i in 0..current_column.len() {
-        parent_data.insert(current_column[i].clone(), parent_column[i].clone());
+    /// Maps each member of a level to its parent in the level above.
+    pub fn parent_map(&self, level_idx: usize) -> HashMap<String, String> {
+        self.rows[level_idx].iter()
+            .zip(&self.rows[level_idx - 1])
+            .map(|(current, parent)| (current.clone(), parent.clone()))
+            .collect()
     }
 
-    Ok(parent_data)
+    /// Maps each member of a level to its children in the level below,
+    /// sorted like the child level's distinct ids.
+    pub fn children_map(&self, level_idx: usize) -> HashMap<String, Vec<String>> {
+        let child_order: HashMap<&String, usize> = self.distinct_ids[level_idx + 1].iter()
+            .enumerate()
+            .map(|(i, id)| (id, i))
+            .collect();
+
+        let mut children_data: HashMap<String, Vec<String>> = HashMap::new();
+
+        for (current, child) in self.rows[level_idx].iter().zip(&self.rows[level_idx + 1]) {
+            let children = children_data.entry(current.clone()).or_insert(vec![]);
+            if !children.contains(child) {
+                children.push(child.clone());
+            }
+        }
+
+        for children in children_data.values_mut() {
+            children.sort_by_key(|child| child_order.get(child).cloned());
+        }
+
+        children_data
+    }
 }
 
 
-pub fn get_children_data(
-        current_level: &Level,
-        child_level: &Level,
+/// Queries the database once for all the levels of a hierarchy (see
+/// `HierarchyMembers`), instead of once per level and map.
+pub fn get_hierarchy_members(
+        levels: &[Level],
         table: &str,
         backend: Box<dyn Backend + Sync + Send>,
         sys: &mut SystemRunner
-) -> Result<HashMap<String, Vec<String>>, Error> {
-    let mut children_data: HashMap<String, Vec<String>> = HashMap::new();
+) -> Result<HierarchyMembers, Error> {
+    // Levels can share a key or name column, which is then only selected once
+    fn column_idx<'a>(column: &'a str, columns: &mut Vec<&'a str>) -> usize {
+        match columns.iter().position(|c| *c == column) {
+            Some(idx) => idx,
+            None => {
+                columns.push(column);
+                columns.len() - 1
+            }
+        }
+    }
+
+    let mut columns: Vec<&str> = vec![];
+
+    let mut level_columns: Vec<usize> = vec![];
+    let mut name_columns: Vec<Option<usize>> = vec![];
+
+    for level in levels {
+        level_columns.push(column_idx(&level.key_column, &mut columns));
+        name_columns.push(level.name_column.as_ref().map(|c| column_idx(c, &mut columns)));
+    }
 
     let future = backend
         .exec_sql(
-            format!(
-                "select distinct {0}, {1} from {2} group by {0}, {1} order by {0}, {1}",
-                current_level.key_column, child_level.key_column, table,
-            ).to_string()
+            format!("select distinct {} from {}", columns.join(", "), table)
         );
 
     let df = match sys.block_on(future) {
@@ -696,30 +969,48 @@ pub fn get_children_data(
         }
     };
 
-    let current_column = df.columns[0].stringify_column_data();
-    let children_column = df.columns[1].stringify_column_data();
+    if df.columns.len() != columns.len() {
+        return Err(format_err!("Error populating cache: expected {} columns from {}", columns.len(), table));
+    }
 
-    let mut current_value: String = "".to_string();
-    let mut current_children: Vec<String> = vec![];
+    let column_rows: Vec<Vec<String>> = df.columns.iter()
+        .map(|column| column.stringify_column_data())
+        .collect();
 
-    for i in 0..current_column.len() {
-        if current_value == "".to_string() {
-            current_value = current_column[i].clone();
-            current_children.push(children_column[i].clone())
-        } else {
-            if current_column[i].clone() != current_value {
-                children_data.insert(current_value.clone(), current_children.clone());
-                current_children = vec![];
+    let mut distinct_ids: Vec<Vec<String>> = vec![];
+    let mut integer_keys: Vec<bool> = vec![];
+    let mut labels: Vec<Option<Vec<String>>> = vec![];
+
+    for (&key_idx, name_idx) in level_columns.iter().zip(&name_columns) {
+        let column = &df.columns[key_idx];
+
+        let mut sorted = column.clone();
+        sorted.sort_column_data()?;
+        let mut ids = sorted.stringify_column_data();
+        ids.dedup();
+
+        integer_keys.push(column.column_data.type_name().contains("Int"));
+
+        labels.push(name_idx.map(|name_idx| {
+            let mut id_labels: HashMap<&String, &String> = HashMap::new();
+            for (id, label) in column_rows[key_idx].iter().zip(&column_rows[name_idx]) {
+                id_labels.entry(id).or_insert(label);
             }
-            current_value = current_column[i].clone();
-            current_children.push(children_column[i].clone())
-        }
-    }
 
-    // Add last set of IDs
-    children_data.insert(current_value, current_children);
+            ids.iter()
+                .map(|id| id_labels.get(id).map(|l| (*l).clone()).unwrap_or_default())
+                .collect()
+        }));
 
-    Ok(children_data)
+        distinct_ids.push(ids);
+    }
+
+    Ok(HierarchyMembers {
+        distinct_ids,
+        rows: level_columns.iter().map(|&c| column_rows[c].clone()).collect(),
+        integer_keys,
+        labels,
+    })
 }
 
 
@@ -731,17 +1022,26 @@ pub fn get_distinct_values(
         sys: &mut SystemRunner
 ) -> Result<Vec<String>, Error> {
     let future = backend
-        .exec_sql(
-            format!("select distinct {} from {}", column, table).to_string()
-        );
+        .exec_sql(distinct_values_sql(column, table));
 
-    let mut df = match sys.block_on(future) {
+    let df = match sys.block_on(future) {
         Ok(df) => df,
         Err(err) => {
             return Err(format_err!("Error populating cache with backend data: {}", err));
         }
     };
 
+    sorted_distinct_values(df)
+}
+
+
+pub fn distinct_values_sql(column: &str, table: &str) -> String {
+    format!("select distinct {} from {}", column, table)
+}
+
+
+/// Sorted values of the first column of the results of `distinct_values_sql`.
+pub fn sorted_distinct_values(mut df: DataFrame) -> Result<Vec<String>, Error> {
     if df.columns.len() >= 1 {
         df.columns[0].sort_column_data()?;
         let values: Vec<String> = df.columns[0].stringify_column_data();
@@ -752,49 +1052,297 @@ pub fn get_distinct_values(
 }
 
 
-pub fn get_neighbors_map(distinct_ids: &Vec<String>) -> HashMap<String, Vec<String>> {
-    let mut neighbors_map: HashMap<String, Vec<String>> = HashMap::new();
+/// The time levels of a cube that are kept in the cache, with the sql to get
+/// their members, in the order `populate_cache` goes through them.
+pub fn time_level_sql(cube: &Cube) -> Vec<(String, String)> {
+    let mut res = vec![];
 
-    // Populate neighbors map
-    let mut prev = 0;
-    let mut curr = 0;
-    let mut next = 2;
+    for dimension in &cube.dimensions {
+        for hierarchy in &dimension.hierarchies {
+            let table = match &hierarchy.table {
+                Some(t) => &t.name,
+                None => &cube.table.name
+            };
 
-    let max_index = distinct_ids.len();
+            for level in &hierarchy.levels {
+                if TIME_LEVEL_NAMES.contains(&level.name.as_str()) {
+                    res.push((level.name.clone(), distinct_values_sql(&level.key_column, table)));
+                }
+            }
+        }
+    }
 
-    let mut done = false; // mut done: bool
+    res
+}
 
-    while !done {
-        // Before
-        let mut before: Vec<String> = vec![];
 
-        if prev == 0 && curr <= 1 {
-            before = distinct_ids[0..curr].to_vec();
-        } else {
-            before = distinct_ids[prev..curr].to_vec();
+/// How a level's sorted member ids can be searched: integer columns are
+/// sorted by value and text columns by their text. Ids that match neither
+/// order (e.g. with nulls) are searched linearly.
+pub fn get_id_order(distinct_ids: &[String]) -> IdOrder {
+    let numeric: Option<Vec<i128>> = distinct_ids.iter()
+        .map(|id| id.parse::<i128>().ok())
+        .collect();
+
+    if let Some(values) = numeric {
+        if values.windows(2).all(|w| w[0] <= w[1]) {
+            return IdOrder::Numeric;
         }
+    }
 
-        // After
-        let mut after: Vec<String> = vec![];
+    if distinct_ids.windows(2).all(|w| w[0] <= w[1]) {
+        IdOrder::Text
+    } else {
+        IdOrder::Unsorted
+    }
+}
 
-        if next >= max_index {
-            after = distinct_ids[curr+1..].to_vec();
-        } else {
-            after = distinct_ids[curr+1..next+1].to_vec();
-        }
 
-        neighbors_map.insert(distinct_ids[curr].clone(), [&before[..], &after[..]].concat());
+/// Smallest and largest keys, compared as numbers if all keys are numeric.
+pub fn get_key_range(distinct_ids: &[String]) -> (Option<String>, Option<String>) {
+    let numeric: Option<Vec<f64>> = distinct_ids.iter()
+        .map(|id| id.parse::<f64>().ok())
+        .collect();
 
-        if curr >= 2 {
-            prev += 1;
-        }
-        curr += 1;
-        next += 1;
+    match numeric {
+        Some(ref values) if !values.is_empty() => {
+            let min = (0..values.len())
+                .min_by(|&a, &b| values[a].partial_cmp(&values[b]).unwrap_or(Ordering::Equal));
+            let max = (0..values.len())
+                .max_by(|&a, &b| values[a].partial_cmp(&values[b]).unwrap_or(Ordering::Equal));
 
-        if curr == max_index {
-            done = true;
-        }
+            (min.map(|i| distinct_ids[i].clone()), max.map(|i| distinct_ids[i].clone()))
+        },
+        _ => (distinct_ids.iter().min().cloned(), distinct_ids.iter().max().cloned()),
     }
+}
+
 
-    neighbors_map
+#[cfg(test)]
+mod test {
+    use tesseract_core::{Column, ColumnData};
+    use tesseract_test::MockBackend;
+
+    use crate::app::test::test_cache;
+    use super::*;
+
+    /// A cube with a time dimension, a dimension whose ids are numbers
+    /// stored as text, and an inline dimension whose rows aren't sorted.
+    const SCHEMA: &str = r#"{
+        "name": "test",
+        "cubes": [{
+            "name": "sales",
+            "table": { "name": "sales" },
+            "dimensions": [
+                {
+                    "name": "Date",
+                    "foreign_key": "date_id",
+                    "type": "time",
+                    "hierarchies": [{
+                        "name": "Date",
+                        "table": { "name": "dim_date" },
+                        "primary_key": "date_id",
+                        "levels": [
+                            { "name": "Year", "key_column": "year" },
+                            { "name": "Month", "key_column": "month", "key_type": "text" }
+                        ]
+                    }]
+                },
+                {
+                    "name": "Code",
+                    "foreign_key": "code_id",
+                    "hierarchies": [{
+                        "name": "Code",
+                        "table": { "name": "dim_codes" },
+                        "primary_key": "code_id",
+                        "levels": [
+                            { "name": "Group", "key_column": "group_id" },
+                            { "name": "Code", "key_column": "code_id", "name_column": "code_name", "key_type": "text" }
+                        ]
+                    }]
+                },
+                {
+                    "name": "Rank",
+                    "foreign_key": "rank_id",
+                    "hierarchies": [{
+                        "name": "Rank",
+                        "primary_key": "rank_id",
+                        "inline_table": {
+                            "alias": "ranks",
+                            "column_definitions": [{ "name": "rank_id", "key_type": "text" }],
+                            "rows": [
+                                { "row_values": [{ "column": "rank_id", "value": "b" }] },
+                                { "row_values": [{ "column": "rank_id", "value": "a" }] },
+                                { "row_values": [{ "column": "rank_id", "value": "c" }] }
+                            ]
+                        },
+                        "levels": [{ "name": "Rank", "key_column": "rank_id" }]
+                    }]
+                }
+            ],
+            "measures": [{ "name": "Quantity", "column": "quantity", "aggregator": "sum" }]
+        }]
+    }"#;
+
+    fn text(values: &[&str]) -> ColumnData {
+        ColumnData::Text(values.iter().map(|v| v.to_string()).collect())
+    }
+
+    /// Months of 2016 to 2020, as text, and codes in two groups, where code
+    /// `10` is in both.
+    fn backend() -> MockBackend {
+        MockBackend::new()
+            .respond(r"^select distinct year, month from dim_date$", || {
+                let (years, months): (Vec<u32>, Vec<String>) = (2016..2021)
+                    .flat_map(|year| (1..13).map(move |month| (year, month.to_string())))
+                    .unzip();
+
+                DataFrame::from_vec(vec![
+                    Column::new("year".to_owned(), ColumnData::UInt32(years)),
+                    Column::new("month".to_owned(), ColumnData::Text(months)),
+                ])
+            })
+            .respond(r"^select distinct group_id, code_id, code_name from dim_codes$", || {
+                DataFrame::from_vec(vec![
+                    Column::new("group_id".to_owned(), ColumnData::Int64(vec![1, 1, 2, 2, 2])),
+                    Column::new("code_id".to_owned(), text(&["1", "10", "10", "2", "20"])),
+                    Column::new("code_name".to_owned(), text(&["One", "Ten", "Ten", "Two", "Twenty"])),
+                ])
+            })
+    }
+
+    fn sales_cache(backend: MockBackend) -> CubeCache {
+        let schema = Schema::from_json(SCHEMA).unwrap();
+
+        test_cache(&schema, backend).unwrap()
+            .find_cube_info(&"sales".to_owned()).unwrap()
+    }
+
+    fn level(dimension: &str, level: &str) -> LevelName {
+        LevelName::new(dimension, dimension, level)
+    }
+
+    fn strings(values: &[&str]) -> Vec<String> {
+        values.iter().map(|v| v.to_string()).collect()
+    }
+
+    #[test]
+    fn neighbors_of_first_and_last_members() {
+        let cube_cache = sales_cache(backend());
+        let years = cube_cache.find_level_cache(&level("Date", "Year")).unwrap();
+
+        assert_eq!(years.id_order, IdOrder::Numeric);
+        assert_eq!(years.neighbors("2016"), Some(strings(&["2017", "2018"])));
+        assert_eq!(years.neighbors("2018"), Some(strings(&["2016", "2017", "2019", "2020"])));
+        assert_eq!(years.neighbors("2020"), Some(strings(&["2018", "2019"])));
+        assert_eq!(years.neighbors("2021"), None);
+    }
+
+    #[test]
+    fn neighbors_of_text_ids() {
+        let cube_cache = sales_cache(backend());
+        let codes = cube_cache.find_level_cache(&level("Code", "Code")).unwrap();
+
+        // numbers, but sorted as text
+        assert_eq!(codes.distinct_ids, strings(&["1", "10", "2", "20"]));
+        assert_eq!(codes.id_order, IdOrder::Text);
+        assert_eq!(codes.neighbors("1"), Some(strings(&["10", "2"])));
+        assert_eq!(codes.neighbors("2"), Some(strings(&["1", "10", "20"])));
+        assert_eq!(codes.neighbors("20"), Some(strings(&["10", "2"])));
+        assert_eq!(codes.neighbors("3"), None);
+    }
+
+    #[test]
+    fn neighbors_of_unsorted_ids() {
+        let cube_cache = sales_cache(backend());
+        let ranks = cube_cache.find_level_cache(&level("Rank", "Rank")).unwrap();
+
+        // inline table rows are kept in their order
+        assert_eq!(ranks.distinct_ids, strings(&["b", "a", "c"]));
+        assert_eq!(ranks.id_order, IdOrder::Unsorted);
+        assert_eq!(ranks.neighbors("b"), Some(strings(&["a", "c"])));
+        assert_eq!(ranks.neighbors("a"), Some(strings(&["b", "c"])));
+        assert_eq!(ranks.neighbors("c"), Some(strings(&["b", "a"])));
+        assert_eq!(ranks.neighbors("d"), None);
+    }
+
+    #[test]
+    fn one_query_per_hierarchy() {
+        let backend = backend();
+        sales_cache(backend.clone());
+
+        // none for the inline table
+        assert_eq!(backend.queries(), strings(&[
+            "select distinct year, month from dim_date",
+            "select distinct group_id, code_id, code_name from dim_codes",
+        ]));
+    }
+
+    #[test]
+    fn member_with_several_parents() {
+        let cube_cache = sales_cache(backend());
+        let groups = cube_cache.find_level_cache(&level("Code", "Group")).unwrap();
+        let codes = cube_cache.find_level_cache(&level("Code", "Code")).unwrap();
+
+        // code 10 is a child of both groups, in the child level's order
+        let children_map = groups.children_map.as_ref().unwrap();
+        assert_eq!(children_map["1"], strings(&["1", "10"]));
+        assert_eq!(children_map["2"], strings(&["10", "2", "20"]));
+
+        let parent_map = codes.parent_map.as_ref().unwrap();
+        assert_eq!(parent_map["1"], "1");
+        assert_eq!(parent_map["20"], "2");
+        assert!(parent_map["10"] == "1" || parent_map["10"] == "2", "{}", parent_map["10"]);
+
+        assert_eq!(codes.labels, Some(strings(&["One", "Ten", "Two", "Twenty"])));
+        assert_eq!(codes.member_count, 4);
+    }
+
+    #[test]
+    fn time_members_in_value_order() {
+        let cube_cache = sales_cache(backend());
+
+        // months are stored as text, which sorts 9 after 12
+        let month = |value: &str| cube_cache.get_time_cut(Time::from_key_value("month".to_owned(), value.to_owned()).unwrap()).unwrap();
+        assert_eq!(month("latest"), ("Month".to_owned(), "12".to_owned()));
+        assert_eq!(month("oldest"), ("Month".to_owned(), "1".to_owned()));
+
+        assert_eq!(cube_cache.latest_time_cut(), Some(("Year".to_owned(), "2020".to_owned())));
+        assert_eq!(cube_cache.latest_period(), Some(("Month".to_owned(), "12".to_owned())));
+
+        let mut cube_cache = cube_cache;
+        cube_cache.set_time_values("Month", strings(&["9", "10", "2020-01-01", "x"]));
+        let months: Vec<_> = cube_cache.month_values.unwrap().into_iter().map(|m| m.value).collect();
+        assert_eq!(months, strings(&["9", "10", "2020-01-01", "x"]));
+    }
+
+    #[test]
+    fn dimension_members_of_several_levels() {
+        let cube_cache = sales_cache(backend());
+        let codes = &cube_cache.dimension_caches["Code"];
+        let (group, code) = (level("Code", "Group"), level("Code", "Code"));
+
+        // 1 and 2 are both groups and codes
+        assert_eq!(codes.get_levels("1"), Some(vec![&group, &code]));
+        assert_eq!(codes.get_levels("2"), Some(vec![&group, &code]));
+        assert_eq!(codes.get_levels("10"), Some(vec![&code]));
+        assert_eq!(codes.get_levels("3"), None);
+
+        assert!(codes.has_member("20", &code));
+        assert!(!codes.has_member("20", &group));
+        assert!(!codes.has_member("1", &level("Rank", "Rank")));
+    }
+
+    #[test]
+    fn dimension_level_limit() {
+        let mut dimension_cache = DimensionCache::default();
+
+        for i in 0..DimensionCache::MAX_LEVELS {
+            dimension_cache.insert("1", &level("Code", &i.to_string())).unwrap();
+        }
+        assert_eq!(dimension_cache.get_levels("1").unwrap().len(), DimensionCache::MAX_LEVELS);
+
+        let err = dimension_cache.insert("1", &level("Code", "Extra")).unwrap_err();
+        assert_eq!(err.to_string(), "Dimension Code has more than 64 levels");
+    }
 }
@@ -1,5 +1,5 @@
 use failure::{Error, format_err};
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 
 use serde_derive::Deserialize;
 use serde_json;
@@ -331,11 +331,14 @@ impl LogicLayerConfig {
     }
 
     /// Ensures level and property names are unique inside each cube based on
-    /// name substitutions from a logic layer configuration.
+    /// name substitutions from a logic layer configuration. As in
+    /// `Schema::has_unique_levels_properties`, names can repeat across the
+    /// hierarchies of a dimension.
     pub fn has_unique_levels_properties(&self, schema: &Schema) -> Result<CubeHasUniqueLevelsAndProperties, Error> {
         for cube in &schema.cubes {
-            let mut levels = HashSet::new();
-            let mut properties = HashSet::new();
+            // names, to the dimension they're in
+            let mut levels = HashMap::new();
+            let mut properties = HashMap::new();
 
             for dimension in &cube.dimensions {
                 for hierarchy in &dimension.hierarchies {
@@ -364,7 +367,7 @@ impl LogicLayerConfig {
                         };
 
                         // TODO remove this clone?
-                        if !levels.insert(unique_level_name.clone()) {
+                        if *levels.entry(unique_level_name.clone()).or_insert(&dimension.name) != &dimension.name {
                             return Ok(CubeHasUniqueLevelsAndProperties::False {
                                 cube: cube.name.clone(),
                                 name: unique_level_name.to_string(),
@@ -395,7 +398,7 @@ impl LogicLayerConfig {
                                     None => property.name.clone()
                                 };
 
-                                if !properties.insert(unique_property_name) {
+                                if *properties.entry(unique_property_name).or_insert(&dimension.name) != &dimension.name {
                                     return Ok(CubeHasUniqueLevelsAndProperties::False {
                                         cube: cube.name.clone(),
                                         name: property_name.to_string(),
@@ -1,5 +1,13 @@
 mod cache;
 mod config;
 
-pub use self::cache::{Cache, CubeCache, Time, TimePrecision, TimeValue, populate_cache};
+pub use self::cache::{Cache, CubeCache, DimensionCache, IdOrder, Time, TimePrecision, TimeValue, populate_cache, sorted_distinct_values, time_level_sql};
 pub use self::config::{LogicLayerConfig, read_config};
+
+
+/// Default max number of cut combinations a query can expand into.
+pub const DEFAULT_MAX_CUT_COMBINATIONS: usize = 100;
+
+/// Default max number of a query's cut combinations running against the
+/// backend at the same time.
+pub const DEFAULT_CUT_COMBINATION_CONCURRENCY: usize = 4;
@@ -1,43 +1,34 @@
-//! tesseract-core contains Schema;
-//! Schema is stateless; it is constructed from the schema file.
-//! Schema is held in the AppState struct to provide access from a route
-//!
-//! Each route instance will apply a tesseract_core::Query to tesseract_core::Schema to get sql.
-//! The route instance then sends sql to a database and gets results back in a
-//! tesseract_core::DataFrame. DataFrame is then applied to Schema to format result (jsonrecords
-//! or csv).
-//!
-//!
-//! Backend trait: exec_sql() takes in a sql string, outputs a DataFrame.
-//! Because tesseract-core generates just sql (instead of taking a query and schema into a
-//! `Backend`, it allows different kinds of backends to be used. Don't have to worry about
-//! async/sync, which is the hardest difference to manage. Otherwise it would be easy to define
-//! a `Backend` trait. (Or, can I do this and define the Backend trait as defining futures,
-//! and have sync operations return a future? This might still be tricky dealing with actix,
-//! it's overall easier to leave backend handling on the server side entirely).
-//!
-//! The database is able to be declared in the schema, each fact table and dim can be from
-//! different databases. Supported: clickhouse, postgres, mysql, sqlite.
-
-mod app;
-mod db_config;
-mod errors;
-pub mod handlers;
-mod logic_layer;
-mod schema_config;
-mod util;
+//! The tesseract server binary: reads its configuration from env vars and
+//! cli options, and serves the app from `tesseract_olap::app`.
 
+use actix::Actor;
 use actix_web::server;
 use dotenv::dotenv;
 use failure::{Error, format_err};
 use log::*;
 use std::env;
+use std::path::PathBuf;
+use std::time::Duration;
 use structopt::StructOpt;
+use tesseract_core::Backend;
 use url::Url;
 
 use std::sync::{Arc, RwLock};
 
-use crate::app::{EnvVars, SchemaSource, create_app};
+use tesseract_olap::{alerts, cli, db_config, extracts, freshness, logic_layer, schema_config};
+use tesseract_olap::alerts::AlertScheduler;
+use tesseract_olap::app::{EnvVars, SchemaSource, create_app};
+use tesseract_olap::clickhouse_settings::ClickhouseSettings;
+use tesseract_olap::conversion::{ConversionRates, RatesRefresher};
+use tesseract_olap::extracts::ExtractScheduler;
+use tesseract_olap::freshness::{CacheRefresher, Webhook};
+use tesseract_olap::metadata_cache::MetadataCache;
+use tesseract_olap::query_hints::QueryPriority;
+use tesseract_olap::query_limiter::{QueryLimiter, QueryLimits, DEFAULT_QUERY_QUEUE_SIZE};
+use tesseract_olap::saved_queries::SavedQueries;
+use tesseract_olap::single_flight::SingleFlight;
+use tesseract_olap::slow_query_log::{SlowQueryLog, DEFAULT_SLOW_QUERY_MS};
+use tesseract_olap::sql_cache::{SqlCache, DEFAULT_SQL_CACHE_SIZE};
 
 
 fn main() -> Result<(), Error> {
@@ -47,6 +38,10 @@ fn main() -> Result<(), Error> {
     dotenv().ok();
     let opt = Opt::from_args();
 
+    if let Some(command) = opt.command {
+        return cli::run(command);
+    }
+
     // debug is boolean, but env var is Result.
     // cli opt overrides env var if env_var is false
     let env_var_debug = env::var("TESSERACT_DEBUG")
@@ -91,6 +86,12 @@ fn main() -> Result<(), Error> {
     // flush
     let flush_secret = env::var("TESSERACT_FLUSH_SECRET").ok();
 
+    // saved queries
+    let saved_queries_secret = env::var("TESSERACT_SAVED_QUERIES_SECRET").ok();
+
+    // row-level security
+    let role_header = env::var("TESSERACT_ROLE_HEADER").ok();
+
     // Database
     let db_url_full = env::var("TESSERACT_DATABASE_URL")
         .or(opt.database_url.ok_or(format_err!("")))
@@ -114,21 +115,39 @@ fn main() -> Result<(), Error> {
         }
     };
 
+    // ClickHouse settings appended to generated sql
+    let clickhouse_settings = match env::var("TESSERACT_CLICKHOUSE_SETTINGS") {
+        Ok(raw) => ClickhouseSettings::parse(&raw)
+            .map_err(|err| format_err!("TESSERACT_CLICKHOUSE_SETTINGS: {}", err))?,
+        Err(_) => ClickhouseSettings::default(),
+    };
+
+    // Cap on the `priority` query hint
+    let max_query_priority = match env::var("TESSERACT_MAX_QUERY_PRIORITY") {
+        Ok(raw) => raw.parse::<QueryPriority>()
+            .map_err(|err| format_err!("TESSERACT_MAX_QUERY_PRIORITY: {}", err))?,
+        Err(_) => QueryPriority::High,
+    };
+
+    // Strict query params: reject params that aren't options or cut keys
+    let strict_params = match env::var("TESSERACT_STRICT_PARAMS") {
+        Ok(strict) => strict.parse::<bool>()
+            .map_err(|_| format_err!("could not parse bool from env_var TESSERACT_STRICT_PARAMS"))?,
+        Err(_) => true,
+    };
+
     // NOTE: Local schema is the only supported SchemaSource for now
     let schema_source = SchemaSource::LocalSchema { filepath: schema_path.clone() };
 
     let mut schema = schema_config::read_schema(&schema_path)?;
+    schema.set_time_grain_sql(|grain, column| db.time_grain_sql(grain, column));
     schema.validate()?;
+    for diagnostic in tesseract_core::schema::lint(&schema) {
+        warn!("Schema: {}", diagnostic);
+    }
     let mut has_unique_levels_properties = schema.has_unique_levels_properties();
     let schema_arc = Arc::new(RwLock::new(schema.clone()));
 
-    // Env
-    let env_vars = EnvVars {
-        database_url: db_url.clone(),
-        geoservice_url,
-        schema_source,
-        flush_secret,
-    };
 
     // Logic Layer Config
     let logic_layer_config = match env::var("TESSERACT_LOGIC_LAYER_CONFIG_FILEPATH") {
@@ -147,6 +166,71 @@ fn main() -> Result<(), Error> {
     // Initialize actix system
     let mut sys = actix::System::new("tesseract");
 
+    // Unit and currency conversion rates, from a file or an endpoint, and
+    // the schedule they're reloaded on
+    let conversion_rates_source = env::var("TESSERACT_CONVERSION_RATES").ok();
+    let conversion_rates = match conversion_rates_source {
+        Some(ref source) => Some(Arc::new(RwLock::new(
+            ConversionRates::load(source, &mut sys)
+                .map_err(|err| format_err!("TESSERACT_CONVERSION_RATES: {}", err))?
+        ))),
+        None => None,
+    };
+    let conversion_rates_schedule = match env::var("TESSERACT_CONVERSION_RATES_REFRESH_SCHEDULE") {
+        Ok(raw) => Some(freshness::parse_schedule(&raw)
+            .map_err(|err| format_err!("TESSERACT_CONVERSION_RATES_REFRESH_SCHEDULE: {}", err))?),
+        Err(_) => None,
+    };
+    if conversion_rates_schedule.is_some() && conversion_rates.is_none() {
+        warn!("TESSERACT_CONVERSION_RATES_REFRESH_SCHEDULE is set without TESSERACT_CONVERSION_RATES, there are no rates to reload");
+    }
+
+    // Env
+    // Reject queries whose estimated number of rows is above this
+    let max_estimated_rows = match env::var("TESSERACT_MAX_ESTIMATED_ROWS") {
+        Ok(n) => Some(n.parse::<u64>()
+            .map_err(|_| format_err!("could not parse u64 from env_var TESSERACT_MAX_ESTIMATED_ROWS"))?),
+        Err(_) => None,
+    };
+
+    // Bounds on the queries a logic layer query expands into, one for each
+    // combination of cuts on levels of the same dimension
+    let logic_layer_max_combinations = match env::var("TESSERACT_LOGIC_LAYER_MAX_COMBINATIONS") {
+        Ok(n) => n.parse::<usize>()
+            .map_err(|_| format_err!("could not parse usize from env_var TESSERACT_LOGIC_LAYER_MAX_COMBINATIONS"))?,
+        Err(_) => logic_layer::DEFAULT_MAX_CUT_COMBINATIONS,
+    };
+    let logic_layer_concurrency = match env::var("TESSERACT_LOGIC_LAYER_CONCURRENCY") {
+        Ok(n) => n.parse::<usize>().ok()
+            .filter(|n| *n > 0)
+            .ok_or_else(|| format_err!("could not parse positive usize from env_var TESSERACT_LOGIC_LAYER_CONCURRENCY"))?,
+        Err(_) => logic_layer::DEFAULT_CUT_COMBINATION_CONCURRENCY,
+    };
+
+    // Extracts materialized on a schedule, served at /extracts/{name}
+    let extracts = match env::var("TESSERACT_EXTRACTS_FILEPATH") {
+        Ok(path) => extracts::read_config(&path, &schema)
+            .map_err(|err| format_err!("TESSERACT_EXTRACTS_FILEPATH: {}", err))?,
+        Err(_) => vec![],
+    };
+
+    let env_vars = EnvVars {
+        database_url: db_url.clone(),
+        geoservice_url,
+        schema_source,
+        flush_secret,
+        saved_queries_secret,
+        role_header,
+        clickhouse_settings,
+        max_query_priority,
+        strict_params,
+        conversion_rates,
+        max_estimated_rows,
+        logic_layer_max_combinations,
+        logic_layer_concurrency,
+        extracts: extracts.clone(),
+    };
+
     // Populate internal cache
     let cache = logic_layer::populate_cache(
         schema.clone(), &logic_layer_config, db.clone(), &mut sys
@@ -154,12 +238,135 @@ fn main() -> Result<(), Error> {
 
     let cache_arc = Arc::new(RwLock::new(cache));
 
+    // Scheduled refresh of the cached time members, and the webhook
+    // called when a cube's latest period changes
+    let cache_refresh_schedule = match env::var("TESSERACT_CACHE_REFRESH_SCHEDULE") {
+        Ok(raw) => Some(freshness::parse_schedule(&raw)
+            .map_err(|err| format_err!("TESSERACT_CACHE_REFRESH_SCHEDULE: {}", err))?),
+        Err(_) => None,
+    };
+    let freshness_webhook = env::var("TESSERACT_FRESHNESS_WEBHOOK_URL").ok()
+        .map(|url| Webhook {
+            url,
+            template: env::var("TESSERACT_FRESHNESS_WEBHOOK_PAYLOAD").ok(),
+        });
+    if freshness_webhook.is_some() && cache_refresh_schedule.is_none() {
+        warn!("TESSERACT_FRESHNESS_WEBHOOK_URL is set without TESSERACT_CACHE_REFRESH_SCHEDULE, the webhook won't be called");
+    }
+
+    // Generated sql cache
+    let sql_cache_size = match env::var("TESSERACT_SQL_CACHE_SIZE") {
+        Ok(size) => size.parse::<usize>()
+            .map_err(|_| format_err!("could not parse usize from env_var TESSERACT_SQL_CACHE_SIZE"))?,
+        Err(_) => DEFAULT_SQL_CACHE_SIZE,
+    };
+    let sql_cache_arc = Arc::new(RwLock::new(SqlCache::new(sql_cache_size)));
+
+    // Serialized cube metadata cache
+    let metadata_cache_arc = Arc::new(RwLock::new(MetadataCache::new()));
+
+    // Saved queries, run at /queries/{name}/run
+    let saved_queries = SavedQueries::load(env::var("TESSERACT_SAVED_QUERIES_FILEPATH").ok().map(PathBuf::from))
+        .map_err(|err| format_err!("TESSERACT_SAVED_QUERIES_FILEPATH: {}", err))?;
+    for (name, err) in saved_queries.invalid(&schema) {
+        warn!("Saved query {} doesn't run on the schema: {}", name, err);
+    }
+    let saved_queries_arc = Arc::new(RwLock::new(saved_queries));
+
+    // Alerts on saved queries, evaluated on a schedule
+    let alerts = match env::var("TESSERACT_ALERTS_FILEPATH") {
+        Ok(path) => alerts::read_config(&path)
+            .map_err(|err| format_err!("TESSERACT_ALERTS_FILEPATH: {}", err))?,
+        Err(_) => vec![],
+    };
+    for alert in &alerts {
+        if saved_queries_arc.read().unwrap().get(&alert.query).is_none() {
+            warn!("Alert {}: saved query {} not found", alert.name, alert.query);
+        }
+    }
+
+    // Limits on concurrent backend queries, shared by all workers
+    let max_concurrent = match env::var("TESSERACT_MAX_CONCURRENT_QUERIES") {
+        Ok(n) => Some(n.parse::<usize>()
+            .map_err(|_| format_err!("could not parse usize from env_var TESSERACT_MAX_CONCURRENT_QUERIES"))?),
+        Err(_) => None,
+    };
+    let max_concurrent_per_cube = match env::var("TESSERACT_MAX_CONCURRENT_QUERIES_PER_CUBE") {
+        Ok(n) => Some(n.parse::<usize>()
+            .map_err(|_| format_err!("could not parse usize from env_var TESSERACT_MAX_CONCURRENT_QUERIES_PER_CUBE"))?),
+        Err(_) => None,
+    };
+    let queue_size = match env::var("TESSERACT_QUERY_QUEUE_SIZE") {
+        Ok(n) => n.parse::<usize>()
+            .map_err(|_| format_err!("could not parse usize from env_var TESSERACT_QUERY_QUEUE_SIZE"))?,
+        Err(_) => DEFAULT_QUERY_QUEUE_SIZE,
+    };
+    let query_limiter = QueryLimiter::new(QueryLimits {
+        max_concurrent,
+        max_concurrent_per_cube,
+        queue_size,
+    });
+
+    // Identical queries running at once are only run once, shared by all workers
+    let single_flight = SingleFlight::new();
+
+    // Slow aggregate queries, for `advise-views`
+    let slow_query_ms = match env::var("TESSERACT_SLOW_QUERY_MS") {
+        Ok(ms) => ms.parse::<u64>()
+            .map_err(|_| format_err!("could not parse u64 from env_var TESSERACT_SLOW_QUERY_MS"))?,
+        Err(_) => DEFAULT_SLOW_QUERY_MS,
+    };
+    let slow_query_log = match env::var("TESSERACT_SLOW_QUERY_LOG_FILEPATH") {
+        Ok(path) => Some(SlowQueryLog::open(&PathBuf::from(path), Duration::from_millis(slow_query_ms))
+            .map_err(|err| format_err!("TESSERACT_SLOW_QUERY_LOG_FILEPATH: {}", err))?),
+        Err(_) => None,
+    };
+
     // Create lock on logic layer config
     let logic_layer_config = match logic_layer_config {
         Some(ll_config) => Some(Arc::new(RwLock::new(ll_config))),
         None => None
     };
 
+    if !extracts.is_empty() {
+        ExtractScheduler {
+            extracts,
+            backend: db.clone(),
+            db_type: db_type.clone(),
+            clickhouse_settings: env_vars.clickhouse_settings.clone(),
+            schema: schema_arc.clone(),
+        }.start();
+    }
+
+    if !alerts.is_empty() {
+        AlertScheduler {
+            alerts,
+            backend: db.clone(),
+            db_type: db_type.clone(),
+            clickhouse_settings: env_vars.clickhouse_settings.clone(),
+            schema: schema_arc.clone(),
+            saved_queries: saved_queries_arc.clone(),
+        }.start();
+    }
+
+    if let Some(schedule) = cache_refresh_schedule {
+        CacheRefresher {
+            schedule,
+            backend: db.clone(),
+            schema: schema_arc.clone(),
+            cache: cache_arc.clone(),
+            webhook: freshness_webhook,
+        }.start();
+    }
+
+    if let (Some(schedule), Some(source), Some(rates)) = (conversion_rates_schedule, conversion_rates_source, env_vars.conversion_rates.clone()) {
+        RatesRefresher {
+            schedule,
+            source,
+            rates,
+        }.start();
+    }
+
     // Initialize Server
     server::new(
         move|| create_app(
@@ -169,6 +376,12 @@ fn main() -> Result<(), Error> {
                 env_vars.clone(),
                 schema_arc.clone(),
                 cache_arc.clone(),
+                sql_cache_arc.clone(),
+                metadata_cache_arc.clone(),
+                saved_queries_arc.clone(),
+                query_limiter.clone(),
+                single_flight.clone(),
+                slow_query_log.clone(),
                 logic_layer_config.clone(),
                 streaming_response,
                 has_unique_levels_properties.clone(),
@@ -213,4 +426,7 @@ struct Opt {
 
     #[structopt(long="streaming")]
     streaming_response: bool,
+
+    #[structopt(subcommand)]
+    command: Option<cli::Command>,
 }
@@ -0,0 +1,64 @@
+//! Cache of serialized cube metadata, so that metadata of large cubes isn't
+//! serialized again on every request.
+//!
+//! Each entry holds the metadata json and its ETag, for conditional
+//! requests. Keys include the schema version, which is bumped whenever the
+//! schema is flushed.
+
+use std::collections::HashMap;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+
+#[derive(Debug, Clone)]
+pub struct CachedMetadata {
+    pub json: String,
+    /// Quoted hash of `json`, so that it changes with the metadata, also
+    /// across server restarts.
+    pub etag: String,
+}
+
+impl CachedMetadata {
+    pub fn new(json: String) -> Self {
+        let mut hasher = DefaultHasher::new();
+        json.hash(&mut hasher);
+        let etag = format!("\"{:x}\"", hasher.finish());
+
+        CachedMetadata { json, etag }
+    }
+}
+
+
+#[derive(Debug)]
+pub struct MetadataCache {
+    schema_version: u64,
+    entries: HashMap<String, CachedMetadata>,
+}
+
+impl MetadataCache {
+    pub fn new() -> Self {
+        MetadataCache {
+            schema_version: 0,
+            entries: HashMap::new(),
+        }
+    }
+
+    pub fn get(&self, cube: &str) -> Option<CachedMetadata> {
+        self.entries.get(&self.key(cube)).cloned()
+    }
+
+    pub fn insert(&mut self, cube: &str, metadata: CachedMetadata) {
+        let key = self.key(cube);
+        self.entries.insert(key, metadata);
+    }
+
+    /// Drops all entries; called when the schema changes.
+    pub fn invalidate(&mut self) {
+        self.schema_version += 1;
+        self.entries.clear();
+    }
+
+    fn key(&self, cube: &str) -> String {
+        format!("{}/{}", self.schema_version, cube)
+    }
+}
@@ -0,0 +1,104 @@
+//! Hints from clients on how a query should be run, from the `timeout`
+//! (in seconds) and `priority` (`low`, `normal` or `high`) query params.
+//!
+//! For ClickHouse, the timeout becomes `max_execution_time` (so it can't go
+//! above the server's setting) and the priority becomes `priority`, so that
+//! ad-hoc exploration doesn't starve production dashboards.
+//!
+//! The server's `TESSERACT_MAX_QUERY_PRIORITY` caps the priority a client
+//! can ask for; higher hints are lowered to it.
+
+use failure::{Error, bail};
+use std::str::FromStr;
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum QueryPriority {
+    Low,
+    Normal,
+    High,
+}
+
+impl QueryPriority {
+    /// ClickHouse's `priority` setting; a lower value is a higher priority,
+    /// and 0 opts out of priorities, so it's never used.
+    pub fn clickhouse_priority(&self) -> u64 {
+        match self {
+            QueryPriority::High => 1,
+            QueryPriority::Normal => 2,
+            QueryPriority::Low => 3,
+        }
+    }
+}
+
+impl Default for QueryPriority {
+    fn default() -> Self {
+        QueryPriority::Normal
+    }
+}
+
+impl FromStr for QueryPriority {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "low" => Ok(QueryPriority::Low),
+            "normal" => Ok(QueryPriority::Normal),
+            "high" => Ok(QueryPriority::High),
+            _ => bail!("Could not parse priority {}, expected low, normal or high", s),
+        }
+    }
+}
+
+
+#[derive(Debug, Clone, Default)]
+pub struct QueryHints {
+    pub timeout: Option<u64>,
+    pub priority: QueryPriority,
+}
+
+impl QueryHints {
+    /// Parses the hints, lowering the priority to `max_priority` if it's
+    /// above it.
+    pub fn parse(timeout: Option<u64>, priority: Option<&str>, max_priority: QueryPriority) -> Result<Self, Error> {
+        if timeout == Some(0) {
+            bail!("timeout must be at least 1 second");
+        }
+
+        let priority = priority
+            .map(|p| p.parse::<QueryPriority>())
+            .transpose()?
+            .unwrap_or_default()
+            .min(max_priority);
+
+        Ok(QueryHints {
+            timeout,
+            priority,
+        })
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn priority_clamped_to_max() {
+        let hints = QueryHints::parse(None, Some("high"), QueryPriority::High).unwrap();
+        assert_eq!(hints.priority, QueryPriority::High);
+
+        let hints = QueryHints::parse(None, Some("high"), QueryPriority::Normal).unwrap();
+        assert_eq!(hints.priority, QueryPriority::Normal);
+
+        // the default priority is clamped too
+        let hints = QueryHints::parse(None, None, QueryPriority::Low).unwrap();
+        assert_eq!(hints.priority, QueryPriority::Low);
+
+        let hints = QueryHints::parse(None, Some("low"), QueryPriority::Normal).unwrap();
+        assert_eq!(hints.priority, QueryPriority::Low);
+
+        assert!(QueryHints::parse(None, Some("urgent"), QueryPriority::High).is_err());
+        assert!(QueryHints::parse(Some(0), None, QueryPriority::High).is_err());
+    }
+}
@@ -0,0 +1,296 @@
+//! Limits the number of backend queries running at once, globally and per
+//! cube, so that bursts of dashboard traffic wait in a queue instead of
+//! overwhelming the database.
+//!
+//! Queued queries are let through by priority (see `QueryHints`), first
+//! come first served within a priority. When the queue is full, queries are
+//! rejected with `ServerError::TooManyRequests` (429). Queued queries whose
+//! request has gone away are dropped from the queue, so they don't hold a
+//! place in it or get a permit.
+//!
+//! Configured with the env vars `TESSERACT_MAX_CONCURRENT_QUERIES`,
+//! `TESSERACT_MAX_CONCURRENT_QUERIES_PER_CUBE` and
+//! `TESSERACT_QUERY_QUEUE_SIZE`. With no limits set, queries always run
+//! right away.
+
+use futures::future::{self, Future};
+use futures::sync::oneshot;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use crate::errors::ServerError;
+use crate::query_hints::QueryPriority;
+
+
+pub const DEFAULT_QUERY_QUEUE_SIZE: usize = 100;
+
+#[derive(Debug, Clone)]
+pub struct QueryLimits {
+    pub max_concurrent: Option<usize>,
+    pub max_concurrent_per_cube: Option<usize>,
+    pub queue_size: usize,
+}
+
+impl Default for QueryLimits {
+    fn default() -> Self {
+        QueryLimits {
+            max_concurrent: None,
+            max_concurrent_per_cube: None,
+            queue_size: DEFAULT_QUERY_QUEUE_SIZE,
+        }
+    }
+}
+
+/// Shared between server workers; clones refer to the same limiter.
+#[derive(Debug, Clone)]
+pub struct QueryLimiter {
+    limits: QueryLimits,
+    state: Arc<Mutex<LimiterState>>,
+}
+
+#[derive(Debug, Default)]
+struct LimiterState {
+    running: usize,
+    running_per_cube: HashMap<String, usize>,
+    /// Ordered by priority, then by arrival.
+    queue: Vec<Waiter>,
+}
+
+#[derive(Debug)]
+struct Waiter {
+    cube: String,
+    priority: QueryPriority,
+    sender: oneshot::Sender<Permit>,
+}
+
+impl LimiterState {
+    fn can_run(&self, limits: &QueryLimits, cube: &str) -> bool {
+        let global_ok = limits.max_concurrent
+            .map(|max| self.running < max)
+            .unwrap_or(true);
+        let cube_ok = limits.max_concurrent_per_cube
+            .map(|max| self.running_per_cube.get(cube).cloned().unwrap_or(0) < max)
+            .unwrap_or(true);
+
+        global_ok && cube_ok
+    }
+
+    fn start(&mut self, cube: &str) {
+        self.running += 1;
+        *self.running_per_cube.entry(cube.to_owned()).or_insert(0) += 1;
+    }
+
+    /// Drops waiters whose receiver is gone, e.g. because the client
+    /// disconnected while the query was queued.
+    fn remove_cancelled(&mut self) {
+        self.queue.retain(|w| !w.sender.is_canceled());
+    }
+
+    fn finish(&mut self, cube: &str) {
+        self.running -= 1;
+        let remove = match self.running_per_cube.get_mut(cube) {
+            Some(n) => {
+                *n -= 1;
+                *n == 0
+            },
+            None => false,
+        };
+        if remove {
+            self.running_per_cube.remove(cube);
+        }
+    }
+}
+
+impl QueryLimiter {
+    pub fn new(limits: QueryLimits) -> Self {
+        QueryLimiter {
+            limits,
+            state: Arc::new(Mutex::new(LimiterState::default())),
+        }
+    }
+
+    /// Resolves to a `Permit` once a query on `cube` may run; the query
+    /// counts as running until the permit is dropped.
+    pub fn acquire(&self, cube: &str, priority: QueryPriority) -> Box<Future<Item=Permit, Error=ServerError>> {
+        let mut state = self.state.lock().unwrap();
+
+        if state.can_run(&self.limits, cube) {
+            state.start(cube);
+            return Box::new(future::ok(self.permit(cube)));
+        }
+
+        state.remove_cancelled();
+        if state.queue.len() >= self.limits.queue_size {
+            return Box::new(future::err(ServerError::TooManyRequests {
+                cause: format!("Too many queries running; the queue of {} is full", self.limits.queue_size),
+            }));
+        }
+
+        let (sender, receiver) = oneshot::channel();
+        let idx = state.queue.iter()
+            .position(|w| w.priority < priority)
+            .unwrap_or(state.queue.len());
+        state.queue.insert(idx, Waiter {
+            cube: cube.to_owned(),
+            priority,
+            sender,
+        });
+
+        Box::new(receiver.map_err(|_| ServerError::Internal {
+            cause: "query limiter dropped a queued query".to_owned(),
+        }))
+    }
+
+    fn permit(&self, cube: &str) -> Permit {
+        Permit {
+            limiter: self.clone(),
+            cube: cube.to_owned(),
+        }
+    }
+
+    fn release(&self, cube: &str) {
+        let mut ready = vec![];
+        {
+            let mut state = self.state.lock().unwrap();
+            state.finish(cube);
+            state.remove_cancelled();
+
+            let mut i = 0;
+            while i < state.queue.len() {
+                if state.can_run(&self.limits, &state.queue[i].cube) {
+                    let waiter = state.queue.remove(i);
+                    state.start(&waiter.cube);
+                    ready.push(waiter);
+                } else {
+                    i += 1;
+                }
+            }
+        }
+
+        // sent outside of the lock: if the request has gone away, the
+        // permit is dropped right away, which releases it again.
+        for waiter in ready {
+            let _ = waiter.sender.send(self.permit(&waiter.cube));
+        }
+    }
+}
+
+/// A slot for a running query, released on drop.
+#[derive(Debug)]
+pub struct Permit {
+    limiter: QueryLimiter,
+    cube: String,
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        self.limiter.release(&self.cube);
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn limiter(max_concurrent: usize, queue_size: usize) -> QueryLimiter {
+        QueryLimiter::new(QueryLimits {
+            max_concurrent: Some(max_concurrent),
+            max_concurrent_per_cube: None,
+            queue_size,
+        })
+    }
+
+    /// Cubes of the queued queries, in the order they'll be let through.
+    fn queued(limiter: &QueryLimiter) -> Vec<String> {
+        limiter.state.lock().unwrap().queue.iter()
+            .map(|w| w.cube.clone())
+            .collect()
+    }
+
+    #[test]
+    fn first_come_first_served_within_a_priority() {
+        let limiter = limiter(1, 10);
+
+        let running = limiter.acquire("a", QueryPriority::Normal).wait().unwrap();
+        let first = limiter.acquire("b", QueryPriority::Normal);
+        let second = limiter.acquire("c", QueryPriority::Normal);
+        assert_eq!(queued(&limiter), vec!["b", "c"]);
+
+        drop(running);
+        let permit = first.wait().unwrap();
+        assert_eq!(permit.cube, "b");
+        assert_eq!(queued(&limiter), vec!["c"]);
+
+        drop(permit);
+        assert_eq!(second.wait().unwrap().cube, "c");
+    }
+
+    #[test]
+    fn higher_priority_jumps_the_queue() {
+        let limiter = limiter(1, 10);
+
+        let running = limiter.acquire("a", QueryPriority::Normal).wait().unwrap();
+        let _low = limiter.acquire("low", QueryPriority::Low);
+        let _normal = limiter.acquire("normal", QueryPriority::Normal);
+        let high = limiter.acquire("high", QueryPriority::High);
+        assert_eq!(queued(&limiter), vec!["high", "normal", "low"]);
+
+        drop(running);
+        let permit = high.wait().unwrap();
+        assert_eq!(permit.cube, "high");
+        assert_eq!(queued(&limiter), vec!["normal", "low"]);
+    }
+
+    #[test]
+    fn full_queue_is_rejected() {
+        let limiter = limiter(1, 1);
+
+        let _running = limiter.acquire("a", QueryPriority::Normal).wait().unwrap();
+        let _queued = limiter.acquire("b", QueryPriority::Normal);
+
+        match limiter.acquire("c", QueryPriority::High).wait() {
+            Err(ServerError::TooManyRequests { .. }) => (),
+            res => panic!("expected too many requests, got {:?}", res),
+        }
+    }
+
+    #[test]
+    fn permit_released_on_drop() {
+        let limiter = QueryLimiter::new(QueryLimits {
+            max_concurrent: Some(2),
+            max_concurrent_per_cube: Some(1),
+            queue_size: 10,
+        });
+
+        let permit = limiter.acquire("a", QueryPriority::Normal).wait().unwrap();
+        {
+            let state = limiter.state.lock().unwrap();
+            assert_eq!(state.running, 1);
+            assert_eq!(state.running_per_cube.get("a"), Some(&1));
+        }
+
+        drop(permit);
+        let state = limiter.state.lock().unwrap();
+        assert_eq!(state.running, 0);
+        assert!(state.running_per_cube.is_empty());
+    }
+
+    #[test]
+    fn cancelled_waiters_are_skipped() {
+        let limiter = limiter(1, 1);
+
+        let running = limiter.acquire("a", QueryPriority::Normal).wait().unwrap();
+        let cancelled = limiter.acquire("gone", QueryPriority::High);
+        drop(cancelled);
+
+        // the cancelled waiter doesn't hold the only place in the queue
+        let waiting = limiter.acquire("b", QueryPriority::Normal);
+        assert_eq!(queued(&limiter), vec!["b"]);
+
+        drop(running);
+        let permit = waiting.wait().unwrap();
+        assert_eq!(permit.cube, "b");
+        assert_eq!(limiter.state.lock().unwrap().running, 1);
+    }
+}
@@ -0,0 +1,130 @@
+//! Saved queries: aggregate queries saved under a name, so that they can
+//! be shared and run as `/queries/{name}/run.{format}` instead of as long
+//! urls.
+//!
+//! They're kept in memory, and also in the json file at
+//! `TESSERACT_SAVED_QUERIES_FILEPATH` when it's set, so that they survive
+//! restarts:
+//!
+//! ```json
+//! {
+//!     "queries": {
+//!         "sales_by_year": {
+//!             "cube": "sales",
+//!             "query": "drilldowns[]=Date.Year&measures[]=Quantity"
+//!         }
+//!     }
+//! }
+//! ```
+//!
+//! Like an extract's, `query` is the query string of an aggregate query on
+//! `cube`. An optional `role` is the role the query is checked and alerted
+//! on with, for schemas with row filters; run from a request, the query
+//! has the request's role.
+
+use failure::{Error, format_err};
+use log::*;
+use serde_derive::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+use tesseract_core::Schema;
+
+use crate::extracts::parse_query;
+
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SavedQuery {
+    pub cube: String,
+    pub query: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub role: Option<String>,
+}
+
+impl SavedQuery {
+    /// Checks that the query parses and can run on `schema`.
+    pub fn validate(&self, schema: &Schema) -> Result<(), Error> {
+        let mut query = parse_query(&self.query)?;
+        query.role = self.role.clone();
+        schema.sql_query(&self.cube, &query)?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct SavedQueriesJson {
+    queries: BTreeMap<String, SavedQuery>,
+}
+
+
+#[derive(Debug)]
+pub struct SavedQueries {
+    path: Option<PathBuf>,
+    queries: BTreeMap<String, SavedQuery>,
+}
+
+impl SavedQueries {
+    /// Saved queries in the file at `path`, if it exists. Without a path,
+    /// queries are only kept in memory.
+    pub fn load(path: Option<PathBuf>) -> Result<Self, Error> {
+        let queries = match path {
+            Some(ref path) if path.exists() => {
+                let raw = fs::read_to_string(path)?;
+                let json: SavedQueriesJson = serde_json::from_str(&raw)
+                    .map_err(|err| format_err!("{}: {}", path.display(), err))?;
+                json.queries
+            },
+            _ => BTreeMap::new(),
+        };
+
+        Ok(SavedQueries { path, queries })
+    }
+
+    pub fn get(&self, name: &str) -> Option<&SavedQuery> {
+        self.queries.get(name)
+    }
+
+    pub fn list(&self) -> &BTreeMap<String, SavedQuery> {
+        &self.queries
+    }
+
+    /// Saves a query, replacing any query with the same name.
+    pub fn insert(&mut self, name: String, query: SavedQuery) -> Result<(), Error> {
+        self.queries.insert(name, query);
+        self.write()
+    }
+
+    /// Deletes a query, returning whether it existed.
+    pub fn remove(&mut self, name: &str) -> Result<bool, Error> {
+        if self.queries.remove(name).is_none() {
+            return Ok(false);
+        }
+        self.write()?;
+        Ok(true)
+    }
+
+    /// Queries that no longer run on `schema`, e.g. after a flush.
+    pub fn invalid(&self, schema: &Schema) -> Vec<(String, Error)> {
+        self.queries.iter()
+            .filter_map(|(name, query)| {
+                query.validate(schema).err().map(|err| (name.clone(), err))
+            })
+            .collect()
+    }
+
+    fn write(&self) -> Result<(), Error> {
+        let path = match self.path {
+            Some(ref path) => path,
+            None => return Ok(()),
+        };
+
+        let json = serde_json::to_string_pretty(&SavedQueriesJson { queries: self.queries.clone() })?;
+
+        let tmp_path = path.with_extension("tmp");
+        fs::write(&tmp_path, json)?;
+        fs::rename(&tmp_path, path)?;
+
+        debug!("Saved queries written to {}", path.display());
+        Ok(())
+    }
+}
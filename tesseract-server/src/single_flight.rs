@@ -0,0 +1,236 @@
+//! Coalesces identical queries that are running at the same time (e.g. a
+//! dashboard's fan-out from several users), so that the database runs each
+//! one once and every request waiting on it gets a copy of the result.
+//!
+//! Queries are keyed by cube and their final sql. Since sql is generated
+//! from the normalized query (see `SqlCache`), queries that only differ in
+//! e.g. cut order are coalesced too. Only queries in flight are shared;
+//! nothing is kept once a query is done.
+
+use failure::{Error, format_err};
+use futures::future::{self, Future};
+use futures::sync::oneshot;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tesseract_core::DataFrame;
+
+use crate::errors::ServerError;
+
+
+type SharedResult = Result<DataFrame, Error>;
+
+/// Cube and sql of a query.
+type QueryKey = (String, String);
+
+/// Shared between server workers; clones refer to the same queries.
+#[derive(Debug, Clone, Default)]
+pub struct SingleFlight {
+    // waiters for each query in flight
+    in_flight: Arc<Mutex<HashMap<QueryKey, Vec<oneshot::Sender<SharedResult>>>>>,
+}
+
+impl SingleFlight {
+    pub fn new() -> Self {
+        SingleFlight::default()
+    }
+
+    /// Runs the query from `run`, unless the same sql on `cube` is already
+    /// running, in which case this waits for that query's result instead.
+    ///
+    /// If the running query is dropped before it's done (e.g. its request
+    /// went away), the waiting queries don't fail: one of them runs the
+    /// query from its own `run`, and the others wait on it in turn.
+    pub fn run<F>(&self, cube: &str, sql: &str, run: F) -> Box<Future<Item=DataFrame, Error=Error>>
+        where F: FnOnce() -> Box<Future<Item=DataFrame, Error=Error>> + 'static
+    {
+        let key = (cube.to_owned(), sql.to_owned());
+
+        {
+            let mut in_flight = self.in_flight.lock().unwrap();
+
+            if let Some(waiters) = in_flight.get_mut(&key) {
+                let (sender, receiver) = oneshot::channel();
+                waiters.push(sender);
+
+                let single_flight = self.clone();
+                let (cube, sql) = key;
+
+                return Box::new(
+                    receiver.then(move |res| -> Box<Future<Item=DataFrame, Error=Error>> {
+                        match res {
+                            Ok(res) => Box::new(future::result(res)),
+                            Err(oneshot::Canceled) => single_flight.run(&cube, &sql, run),
+                        }
+                    })
+                );
+            }
+
+            in_flight.insert(key.clone(), vec![]);
+        }
+
+        let mut flight = Flight {
+            single_flight: self.clone(),
+            key,
+            done: false,
+        };
+
+        Box::new(
+            run().then(move |res| {
+                for waiter in flight.finish() {
+                    let _ = waiter.send(share_result(&res));
+                }
+                res
+            })
+        )
+    }
+}
+
+/// A query in flight, which stops being shared when it finishes or is
+/// dropped (e.g. the request went away); in that case the waiters' senders
+/// are dropped, so they run the query themselves instead of waiting
+/// forever.
+struct Flight {
+    single_flight: SingleFlight,
+    key: QueryKey,
+    done: bool,
+}
+
+impl Flight {
+    fn finish(&mut self) -> Vec<oneshot::Sender<SharedResult>> {
+        // once done, the key may already belong to a newer query
+        if self.done {
+            return vec![];
+        }
+        self.done = true;
+
+        self.single_flight.in_flight.lock().unwrap()
+            .remove(&self.key)
+            .unwrap_or_default()
+    }
+}
+
+impl Drop for Flight {
+    fn drop(&mut self) {
+        self.finish();
+    }
+}
+
+/// Copy of a query's result for a waiter. Errors are copied as
+/// `ServerError`s when they are one (e.g. from the query limiter), and
+/// otherwise by message.
+fn share_result(res: &SharedResult) -> SharedResult {
+    match res {
+        Ok(df) => Ok(df.clone()),
+        Err(err) => {
+            match err.downcast_ref::<ServerError>() {
+                Some(server_err) => Err(server_err.clone().into()),
+                None => Err(format_err!("{}", err)),
+            }
+        },
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use futures::executor::{self, Notify};
+    use std::cell::Cell;
+    use std::rc::Rc;
+    use tesseract_core::{Column, ColumnData};
+
+    use super::*;
+
+    struct NoNotify;
+
+    impl Notify for NoNotify {
+        fn notify(&self, _id: usize) {}
+    }
+
+    fn df() -> DataFrame {
+        DataFrame::from_vec(vec![
+            Column::new("year".to_owned(), ColumnData::UInt32(vec![2018, 2019])),
+        ])
+    }
+
+    /// A query whose result is sent on the returned channel, and a count of
+    /// the times it was run.
+    fn query() -> (impl FnOnce() -> Box<Future<Item=DataFrame, Error=Error>>, oneshot::Sender<SharedResult>, Rc<Cell<usize>>) {
+        let (sender, receiver) = oneshot::channel();
+        let runs = Rc::new(Cell::new(0));
+        let run_count = runs.clone();
+
+        let run = move || -> Box<Future<Item=DataFrame, Error=Error>> {
+            run_count.set(run_count.get() + 1);
+            Box::new(receiver.map_err(|_| format_err!("query dropped")).and_then(|res| res))
+        };
+
+        (run, sender, runs)
+    }
+
+    #[test]
+    fn coalesces_identical_queries() {
+        let single_flight = SingleFlight::new();
+        let (run, sender, runs) = query();
+        let (waiter_run, _waiter_sender, waiter_runs) = query();
+        let (other_run, other_sender, other_runs) = query();
+
+        let leader = single_flight.run("sales", "select 1", run);
+        let waiter = single_flight.run("sales", "select 1", waiter_run);
+        // same sql on another cube
+        let other = single_flight.run("other", "select 1", other_run);
+
+        sender.send(Ok(df())).unwrap();
+        other_sender.send(Ok(df())).unwrap();
+
+        assert_eq!(leader.wait().unwrap().len(), 2);
+        assert_eq!(waiter.wait().unwrap().len(), 2);
+        assert_eq!(other.wait().unwrap().len(), 2);
+        assert_eq!((runs.get(), waiter_runs.get(), other_runs.get()), (1, 0, 1));
+        assert!(single_flight.in_flight.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn shares_errors() {
+        let single_flight = SingleFlight::new();
+        let (run, sender, _) = query();
+        let (waiter_run, _waiter_sender, waiter_runs) = query();
+
+        let leader = single_flight.run("sales", "select 1", run);
+        let waiter = single_flight.run("sales", "select 1", waiter_run);
+
+        sender.send(Err(ServerError::invalid_query("bad query").into())).unwrap();
+
+        assert_eq!(leader.wait().unwrap_err().to_string(), "bad query");
+        let err = waiter.wait().unwrap_err();
+        assert!(err.downcast_ref::<ServerError>().is_some(), "{}", err);
+        assert_eq!(err.to_string(), "bad query");
+        assert_eq!(waiter_runs.get(), 0);
+    }
+
+    #[test]
+    fn waiter_runs_dropped_query() {
+        let single_flight = SingleFlight::new();
+        let (run, _sender, _) = query();
+        let (waiter_run, waiter_sender, waiter_runs) = query();
+        let (second_waiter_run, _second_waiter_sender, second_waiter_runs) = query();
+
+        let leader = single_flight.run("sales", "select 1", run);
+        let waiter = single_flight.run("sales", "select 1", waiter_run);
+        let second_waiter = single_flight.run("sales", "select 1", second_waiter_run);
+
+        drop(leader);
+
+        // the first waiter to notice runs the query, and the other waits on it
+        let notify = Arc::new(NoNotify);
+        let mut waiter = executor::spawn(waiter);
+        assert!(waiter.poll_future_notify(&notify, 0).unwrap().is_not_ready());
+        let mut second_waiter = executor::spawn(second_waiter);
+        assert!(second_waiter.poll_future_notify(&notify, 0).unwrap().is_not_ready());
+
+        waiter_sender.send(Ok(df())).unwrap();
+
+        assert_eq!(waiter.wait_future().unwrap().len(), 2);
+        assert_eq!(second_waiter.wait_future().unwrap().len(), 2);
+        assert_eq!((waiter_runs.get(), second_waiter_runs.get()), (1, 0));
+        assert!(single_flight.in_flight.lock().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,129 @@
+//! Log of slow aggregate queries, appended as json lines to
+//! `TESSERACT_SLOW_QUERY_LOG_FILEPATH`. A query is logged when its sql
+//! takes longer than `TESSERACT_SLOW_QUERY_MS` (default 1000) to run,
+//! including the time spent waiting in the query queue.
+//!
+//! Only the shape of the query is logged (cube, drilldowns, cut levels and
+//! measures), not the cut members, so that the log can be aggregated by
+//! `tesseract-olap advise-views` to find the combinations worth
+//! pre-aggregating.
+
+use chrono::Utc;
+use failure::{Error, format_err};
+use log::*;
+use serde_derive::{Deserialize, Serialize};
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tesseract_core::Query as TsQuery;
+
+
+pub const DEFAULT_SLOW_QUERY_MS: u64 = 1000;
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct SlowQuery {
+    /// RFC 3339
+    pub timestamp: String,
+    pub cube: String,
+    pub drilldowns: Vec<String>,
+    /// Levels that were cut on
+    pub cuts: Vec<String>,
+    pub measures: Vec<String>,
+    pub duration_ms: u64,
+}
+
+/// Shared between server workers; clones append to the same file.
+#[derive(Debug, Clone)]
+pub struct SlowQueryLog {
+    threshold: Duration,
+    file: Arc<Mutex<File>>,
+}
+
+impl SlowQueryLog {
+    pub fn open(path: &Path, threshold: Duration) -> Result<Self, Error> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|err| format_err!("could not open {}: {}", path.display(), err))?;
+
+        Ok(SlowQueryLog {
+            threshold,
+            file: Arc::new(Mutex::new(file)),
+        })
+    }
+
+    /// Starts timing a query; it's logged by `PendingQuery::finish` if slow.
+    pub fn start(&self, cube: &str, query: &TsQuery) -> PendingQuery {
+        PendingQuery {
+            log: self.clone(),
+            started: Instant::now(),
+            query: SlowQuery {
+                timestamp: String::new(),
+                cube: cube.to_owned(),
+                drilldowns: query.drilldowns.iter().map(|d| d.to_string()).collect(),
+                cuts: query.cuts.iter().map(|c| c.level_name.to_string()).collect(),
+                measures: query.measures.iter().map(|m| m.to_string()).collect(),
+                duration_ms: 0,
+            },
+        }
+    }
+
+    fn write(&self, query: &SlowQuery) -> Result<(), Error> {
+        let mut line = serde_json::to_string(query)?;
+        line.push('\n');
+
+        // one write per line, so that lines from different workers
+        // don't interleave
+        self.file.lock().unwrap().write_all(line.as_bytes())?;
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub struct PendingQuery {
+    log: SlowQueryLog,
+    started: Instant,
+    query: SlowQuery,
+}
+
+impl PendingQuery {
+    pub fn finish(mut self) {
+        let elapsed = self.started.elapsed();
+        if elapsed < self.log.threshold {
+            return;
+        }
+
+        self.query.timestamp = Utc::now().to_rfc3339();
+        self.query.duration_ms = elapsed.as_secs() * 1000 + u64::from(elapsed.subsec_millis());
+
+        if let Err(err) = self.log.write(&self.query) {
+            warn!("Could not write to the slow query log: {}", err);
+        }
+    }
+}
+
+/// Reads a slow query log. Lines that can't be parsed are skipped with a
+/// warning, e.g. a line cut off by a crash.
+pub fn read_log(path: &Path) -> Result<Vec<SlowQuery>, Error> {
+    let file = File::open(path)
+        .map_err(|err| format_err!("could not open {}: {}", path.display(), err))?;
+
+    let mut queries = vec![];
+
+    for (i, line) in BufReader::new(file).lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        match serde_json::from_str(&line) {
+            Ok(query) => queries.push(query),
+            Err(err) => warn!("Skipping line {} of {}: {}", i + 1, path.display(), err),
+        }
+    }
+
+    Ok(queries)
+}
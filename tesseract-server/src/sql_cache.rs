@@ -0,0 +1,181 @@
+//! Cache of generated sql and headers, so that repeated queries (e.g. from
+//! dashboards) skip schema traversal and sql string building.
+//!
+//! Entries are keyed on the cube name and the serialized normalized query,
+//! so queries only differing in cut order share an entry. Keys also include
+//! the schema version, which is bumped whenever the schema is flushed.
+
+use std::collections::{HashMap, VecDeque};
+
+use tesseract_core::Query as TsQuery;
+
+
+/// Default number of queries kept in the cache.
+pub const DEFAULT_SQL_CACHE_SIZE: usize = 1000;
+
+
+/// Schema version, cube name and serialized normalized query.
+type CacheKey = (u64, String, String);
+
+#[derive(Debug)]
+pub struct SqlCache {
+    schema_version: u64,
+    capacity: usize,
+    entries: HashMap<CacheKey, (String, Vec<String>)>,
+    // insertion order, for evicting the oldest entries
+    keys: VecDeque<CacheKey>,
+}
+
+impl SqlCache {
+    /// A capacity of 0 disables the cache.
+    pub fn new(capacity: usize) -> Self {
+        SqlCache {
+            schema_version: 0,
+            capacity,
+            entries: HashMap::new(),
+            keys: VecDeque::new(),
+        }
+    }
+
+    pub fn get(&self, cube: &str, query: &TsQuery) -> Option<(String, Vec<String>)> {
+        if self.capacity == 0 {
+            return None;
+        }
+
+        self.entries.get(&self.key(cube, query)?).cloned()
+    }
+
+    pub fn insert(&mut self, cube: &str, query: &TsQuery, sql: String, headers: Vec<String>) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        let key = match self.key(cube, query) {
+            Some(key) => key,
+            None => return,
+        };
+
+        if self.entries.insert(key.clone(), (sql, headers)).is_none() {
+            self.keys.push_back(key);
+        }
+
+        while self.keys.len() > self.capacity {
+            if let Some(oldest) = self.keys.pop_front() {
+                self.entries.remove(&oldest);
+            }
+        }
+    }
+
+    /// Drops all entries; called when the schema changes.
+    pub fn invalidate(&mut self) {
+        self.schema_version += 1;
+        self.entries.clear();
+        self.keys.clear();
+    }
+
+    /// `None` if the query can't be serialized, in which case it's not cached.
+    fn key(&self, cube: &str, query: &TsQuery) -> Option<CacheKey> {
+        let query = serde_json::to_string(&query.normalized()).ok()?;
+
+        Some((self.schema_version, cube.to_owned(), query))
+    }
+
+    #[cfg(test)]
+    fn len(&self) -> usize {
+        self.entries.len()
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn query(year: &str) -> TsQuery {
+        TsQuery::builder()
+            .drilldown("Geography.State")
+            .cut(&format!("Year.Year.Year.{}", year))
+            .measure("Quantity")
+            .build()
+            .unwrap()
+    }
+
+    fn insert(cache: &mut SqlCache, cube: &str, year: &str) {
+        cache.insert(cube, &query(year), format!("sql {}", year), vec![year.to_owned()]);
+    }
+
+    #[test]
+    fn get_cached() {
+        let mut cache = SqlCache::new(10);
+        insert(&mut cache, "sales", "2018");
+
+        assert_eq!(
+            cache.get("sales", &query("2018")),
+            Some(("sql 2018".to_owned(), vec!["2018".to_owned()])),
+        );
+        assert_eq!(cache.get("sales", &query("2019")), None);
+        assert_eq!(cache.get("returns", &query("2018")), None);
+    }
+
+    #[test]
+    fn cut_order_shares_entry() {
+        let mut cache = SqlCache::new(10);
+        let a = TsQuery::builder()
+            .drilldown("Geography.State")
+            .cut("Year.Year.Year.2019,2018")
+            .cut("Geography.State.State.06")
+            .measure("Quantity")
+            .build()
+            .unwrap();
+        let b = TsQuery::builder()
+            .drilldown("Geography.State")
+            .cut("Geography.State.State.06")
+            .cut("Year.Year.Year.2018,2019")
+            .measure("Quantity")
+            .build()
+            .unwrap();
+
+        cache.insert("sales", &a, "sql".to_owned(), vec![]);
+
+        assert_eq!(cache.get("sales", &b), Some(("sql".to_owned(), vec![])));
+        assert_eq!(cache.len(), 1);
+    }
+
+    #[test]
+    fn evicts_oldest() {
+        let mut cache = SqlCache::new(2);
+        insert(&mut cache, "sales", "2016");
+        insert(&mut cache, "sales", "2017");
+        // re-inserting doesn't refresh an entry's place
+        insert(&mut cache, "sales", "2016");
+        insert(&mut cache, "sales", "2018");
+
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.get("sales", &query("2016")), None);
+        assert!(cache.get("sales", &query("2017")).is_some());
+        assert!(cache.get("sales", &query("2018")).is_some());
+    }
+
+    #[test]
+    fn invalidate() {
+        let mut cache = SqlCache::new(10);
+        insert(&mut cache, "sales", "2018");
+        cache.invalidate();
+
+        assert_eq!(cache.len(), 0);
+        assert_eq!(cache.get("sales", &query("2018")), None);
+
+        insert(&mut cache, "sales", "2018");
+        assert!(cache.get("sales", &query("2018")).is_some());
+    }
+
+    #[test]
+    fn disabled() {
+        let mut cache = SqlCache::new(0);
+        insert(&mut cache, "sales", "2018");
+
+        assert_eq!(cache.len(), 0);
+        assert_eq!(cache.get("sales", &query("2018")), None);
+    }
+}
+
@@ -3,13 +3,27 @@ use actix_web::{
     HttpResponse,
 };
 use futures::future::{self};
+use std::fmt::Display;
+use tesseract_core::format::FormatType;
+
+use crate::errors::ServerError;
 
 
 /// Helper method to return errors (FutureResponse<HttpResponse>).
-pub fn boxed_error(message: String) -> FutureResponse<HttpResponse> {
+pub fn boxed_error(err: ServerError) -> FutureResponse<HttpResponse> {
     Box::new(
-        future::result(
-            Ok(HttpResponse::NotFound().json(message))
-        )
+        future::err(err.into())
     )
 }
+
+/// Error for an unsupported output format in the request path.
+pub fn format_error<E: Display>(err: E) -> ServerError {
+    ServerError::invalid_query(err)
+        .with_hint("supported formats are csv, jsonrecords, jsonarrays and orc")
+}
+
+/// Error for a format that can't be streamed.
+pub fn stream_format_error(format: &FormatType) -> ServerError {
+    ServerError::invalid_query(format!("{:?} output is not supported for streaming responses", format))
+        .with_hint("streaming formats are csv, jsonrecords and jsonarrays")
+}
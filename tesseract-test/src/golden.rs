@@ -0,0 +1,161 @@
+//! Golden sql snapshots: a library of queries on the test schema, each with
+//! the sql and headers it's expected to generate committed next to the
+//! backend's tests, so that a change to sql generation can't silently change
+//! what an existing query means.
+//!
+//! A backend checks every fixture against its own snapshot directory:
+//!
+//! ```ignore
+//! tesseract_test::golden::assert_fixtures(
+//!     &ClickhouseDialect,
+//!     concat!(env!("CARGO_MANIFEST_DIR"), "/src/sql/golden"),
+//! );
+//! ```
+//!
+//! Each fixture has a `<name>.sql` and a `<name>.headers` (one header per
+//! line) file. When adding a fixture, or when a change to the sql is
+//! intended, run the tests with `TESSERACT_UPDATE_GOLDEN=1` to (re)write the
+//! snapshots, and review the diff before committing it.
+
+use failure::{Error, bail, format_err};
+use std::env;
+use std::fs;
+use std::path::Path;
+use tesseract_core::{Query, SqlDialect, dialect_sql};
+
+use crate::schema;
+
+pub const UPDATE_GOLDEN_VAR: &str = "TESSERACT_UPDATE_GOLDEN";
+
+#[derive(Debug, Clone)]
+pub struct Fixture {
+    /// Name of the snapshot files.
+    pub name: &'static str,
+    pub cube: &'static str,
+    pub query: Query,
+}
+
+impl Fixture {
+    fn new(name: &'static str, query: Result<Query, Error>) -> Self {
+        Fixture {
+            name,
+            cube: "sales",
+            query: query.expect("valid fixture query"),
+        }
+    }
+
+    /// The sql and headers generated for the fixture's query.
+    pub fn generate<D: SqlDialect + ?Sized>(&self, dialect: &D) -> Result<(String, Vec<String>), Error> {
+        let (query_ir, headers) = schema().sql_query(self.cube, &self.query)?;
+
+        Ok((dialect_sql(dialect, &query_ir), headers))
+    }
+}
+
+/// Every fixture, on the test schema (see `SCHEMA`). New sql features
+/// should add a fixture here rather than change an existing one.
+pub fn fixtures() -> Vec<Fixture> {
+    vec![
+        Fixture::new(
+            "inline_drilldown",
+            Query::builder()
+                .drilldown("Year.Year.Year")
+                .measure("Quantity")
+                .build(),
+        ),
+        Fixture::new(
+            "dim_table_drilldown_with_cut",
+            Query::builder()
+                .drilldown("Product.Product.Product")
+                .cut("Year.Year.Year.2018")
+                .measure("Quantity")
+                .measure("Price")
+                .build(),
+        ),
+        Fixture::new(
+            "sort_and_limit",
+            Query::builder()
+                .drilldown("Year.Year.Year")
+                .measure("Quantity")
+                .sort("Quantity.desc")
+                .limit(5, None)
+                .build(),
+        ),
+        Fixture::new(
+            "filter_on_growth",
+            Query::builder()
+                .drilldown("Year.Year.Year")
+                .drilldown("Geography.Geography.State")
+                .measure("Quantity")
+                .growth("Year.Year.Year,Quantity")
+                .filter("Quantity Growth,gte.0.1")
+                .build(),
+        ),
+        Fixture::new(
+            "filter_and_sort_on_rate",
+            Query::builder()
+                .drilldown("Geography.Geography.State")
+                .measure("Quantity")
+                .rate("Product.Product.Product Group.1")
+                .filter("Rate,gt.0.5")
+                .sort("Rate.desc")
+                .build(),
+        ),
+    ]
+}
+
+/// Checks a fixture's generated sql and headers against its snapshots in
+/// `dir`, or writes the snapshots when `TESSERACT_UPDATE_GOLDEN` is set.
+pub fn check_fixture<D, P>(dialect: &D, dir: P, fixture: &Fixture) -> Result<(), Error>
+    where D: SqlDialect + ?Sized,
+          P: AsRef<Path>,
+{
+    let (sql, headers) = fixture.generate(dialect)
+        .map_err(|err| format_err!("{}: could not generate sql: {}", fixture.name, err))?;
+    let sql = sql.trim();
+
+    let sql_path = dir.as_ref().join(format!("{}.sql", fixture.name));
+    let headers_path = dir.as_ref().join(format!("{}.headers", fixture.name));
+
+    if env::var_os(UPDATE_GOLDEN_VAR).is_some() {
+        fs::write(&sql_path, format!("{}\n", sql))?;
+        fs::write(&headers_path, format!("{}\n", headers.join("\n")))?;
+        return Ok(());
+    }
+
+    let read = |path: &Path| {
+        fs::read_to_string(path)
+            .map_err(|err| format_err!("{}: could not read {} ({}); run with {}=1 to write it", fixture.name, path.display(), err, UPDATE_GOLDEN_VAR))
+    };
+    let expected_sql = read(&sql_path)?;
+    let expected_headers = read(&headers_path)?;
+    let expected_headers: Vec<_> = expected_headers.lines().collect();
+
+    if sql != expected_sql.trim() {
+        bail!("{}: sql changed\nexpected: {}\n     got: {}", fixture.name, expected_sql.trim(), sql);
+    }
+    if headers != expected_headers {
+        bail!("{}: headers changed\nexpected: {:?}\n     got: {:?}", fixture.name, expected_headers, headers);
+    }
+
+    Ok(())
+}
+
+/// Checks every fixture, panicking with all the mismatches.
+pub fn assert_fixtures<D, P>(dialect: &D, dir: P)
+    where D: SqlDialect + ?Sized,
+          P: AsRef<Path>,
+{
+    let failures: Vec<_> = fixtures().iter()
+        .filter_map(|fixture| check_fixture(dialect, dir.as_ref(), fixture).err())
+        .map(|err| err.to_string())
+        .collect();
+
+    if !failures.is_empty() {
+        panic!("{} golden sql snapshot(s) changed; if intended, run with {}=1 and review the diff:\n\n{}",
+            failures.len(),
+            UPDATE_GOLDEN_VAR,
+            failures.join("\n\n"),
+        );
+    }
+}
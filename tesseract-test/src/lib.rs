@@ -0,0 +1,226 @@
+//! In-memory `Backend` and schema fixtures, for testing query handling
+//! end-to-end without a database.
+
+pub mod golden;
+
+use failure::{Error, format_err};
+use futures::future::{self, Future};
+use futures::stream::{self, Stream};
+use regex::Regex;
+use std::sync::{Arc, Mutex};
+use tesseract_core::{Backend, Column, ColumnData, DataFrame, Engine, Schema};
+
+/// The sales schema: a cube with an inline time dimension, an external
+/// geography dimension with three levels, and an external product
+/// dimension with properties.
+pub const SCHEMA: &str = include_str!("../fixtures/schema.json");
+
+/// Logic layer config for `SCHEMA`: a cube alias, a level alias and
+/// a named set.
+pub const LOGIC_LAYER_CONFIG: &str = include_str!("../fixtures/logic_layer.json");
+
+pub fn schema() -> Schema {
+    let mut schema = Schema::from_json(SCHEMA).expect("valid test schema");
+    schema.validate().expect("valid test schema");
+    schema
+}
+
+/// An `Engine` over the test schema, executing against `backend`.
+pub fn engine(backend: &MockBackend) -> Engine {
+    Engine::new(schema(), Box::new(backend.clone())).expect("valid test schema")
+}
+
+/// Number of members of each level of the geography and product
+/// dimensions in `cache_backend`.
+pub const CACHE_MEMBERS: usize = 56;
+
+/// A `MockBackend` answering the queries that populate the logic layer
+/// cache for `SCHEMA` (one per hierarchy): the years 2010 to 2019, and
+/// `CACHE_MEMBERS` members of each geography and product level, where the
+/// `i`th member of a level is the parent of the `i`th member of the level
+/// below. States are `04000US00`, `04000US01`..., counties `05000US00`...,
+/// tracts `14000US00`..., and product groups and products are numbered
+/// from 0.
+pub fn cache_backend() -> MockBackend {
+    fn text(f: impl Fn(usize) -> String) -> ColumnData {
+        ColumnData::Text((0..CACHE_MEMBERS).map(f).collect())
+    }
+    fn int(f: impl Fn(usize) -> i64) -> ColumnData {
+        ColumnData::Int64((0..CACHE_MEMBERS).map(f).collect())
+    }
+
+    MockBackend::new()
+        .respond(r"^select distinct year from sales$", || DataFrame::from_vec(vec![
+            Column::new("year".to_owned(), ColumnData::UInt32((2010..2020).collect())),
+        ]))
+        .respond(r"^select distinct state_id, state_name, county_id, county_name, tract_id, tract_name from customer_geo$", || {
+            DataFrame::from_vec(vec![
+                Column::new("state_id".to_owned(), text(|i| format!("04000US{:02}", i))),
+                Column::new("state_name".to_owned(), text(|i| format!("State {}", i))),
+                Column::new("county_id".to_owned(), text(|i| format!("05000US{:02}", i))),
+                Column::new("county_name".to_owned(), text(|i| format!("County {}", i))),
+                Column::new("tract_id".to_owned(), text(|i| format!("14000US{:02}", i))),
+                Column::new("tract_name".to_owned(), text(|i| format!("Tract {}", i))),
+            ])
+        })
+        .respond(r"^select distinct product_group_id, product_group_label, product_id, product_label from dim_products$", || {
+            DataFrame::from_vec(vec![
+                Column::new("product_group_id".to_owned(), int(|i| i as i64)),
+                Column::new("product_group_label".to_owned(), text(|i| format!("Group {}", i))),
+                Column::new("product_id".to_owned(), int(|i| i as i64)),
+                Column::new("product_label".to_owned(), text(|i| format!("Product {}", i))),
+            ])
+        })
+}
+
+type Response = Arc<dyn Fn() -> DataFrame + Send + Sync>;
+
+/// A `Backend` answering sql with canned `DataFrame`s.
+///
+/// Responses are checked in the order they were added, and the first one
+/// whose pattern matches the sql is used. Sql that matches no pattern is
+/// an error. Clones share responses and the query log, so a test can keep
+/// a clone to inspect the sql after handing the backend off.
+#[derive(Clone, Default)]
+pub struct MockBackend {
+    responses: Arc<Mutex<Vec<(Regex, Response)>>>,
+    queries: Arc<Mutex<Vec<String>>>,
+}
+
+impl MockBackend {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Answers sql matching the regex `pattern` with the `DataFrame`
+    /// returned by `response`. Panics if `pattern` is not a valid regex.
+    pub fn respond<F>(self, pattern: &str, response: F) -> Self
+        where F: Fn() -> DataFrame + Send + Sync + 'static
+    {
+        let pattern = Regex::new(pattern).expect("valid response pattern");
+        self.responses.lock().unwrap().push((pattern, Arc::new(response)));
+        self
+    }
+
+    /// All sql received so far, in order.
+    pub fn queries(&self) -> Vec<String> {
+        self.queries.lock().unwrap().clone()
+    }
+
+    /// The most recent sql received.
+    pub fn last_query(&self) -> Option<String> {
+        self.queries.lock().unwrap().last().cloned()
+    }
+
+    fn response(&self, sql: &str) -> Result<DataFrame, Error> {
+        self.queries.lock().unwrap().push(sql.to_owned());
+
+        self.responses.lock().unwrap()
+            .iter()
+            .find(|(pattern, _)| pattern.is_match(sql))
+            .map(|(_, response)| response())
+            .ok_or_else(|| format_err!("MockBackend has no response for sql: {}", sql))
+    }
+}
+
+impl Backend for MockBackend {
+    fn exec_sql(&self, sql: String) -> Box<Future<Item=DataFrame, Error=Error>> {
+        Box::new(future::result(self.response(&sql)))
+    }
+
+    /// Streams the response to `sql` as a single chunk.
+    fn exec_sql_stream(&self, sql: String) -> Box<Stream<Item=Result<DataFrame, Error>, Error=Error>> {
+        Box::new(stream::once(self.response(&sql).map(Ok)))
+    }
+
+    fn box_clone(&self) -> Box<dyn Backend + Send + Sync> {
+        Box::new((*self).clone())
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tesseract_core::Query;
+
+    fn year_quantity() -> DataFrame {
+        DataFrame::from_vec(vec![
+            Column::new("year".to_owned(), ColumnData::UInt32(vec![2018, 2019])),
+            Column::new("quantity".to_owned(), ColumnData::Int64(vec![10, 20])),
+        ])
+    }
+
+    #[test]
+    fn test_engine_execute() {
+        let backend = MockBackend::new()
+            .respond(r"year", year_quantity);
+        let engine = engine(&backend);
+
+        let query = Query::builder()
+            .drilldown("Year.Year")
+            .measure("Quantity")
+            .build()
+            .unwrap();
+
+        let df = engine.execute("sales", &query).wait().unwrap();
+
+        assert_eq!(df.column_names(), vec!["Year", "Quantity"]);
+        assert_eq!(df.len(), 2);
+        assert_eq!(backend.queries().len(), 1);
+        assert!(backend.last_query().unwrap().contains("sales"));
+    }
+
+    #[test]
+    fn test_cache_backend() {
+        let backend = cache_backend();
+
+        let df = backend.exec_sql("select distinct year from sales".to_owned()).wait().unwrap();
+        assert_eq!(df.len(), 10);
+
+        let df = backend.exec_sql("select distinct state_id, state_name, county_id, county_name, tract_id, tract_name from customer_geo".to_owned())
+            .wait()
+            .unwrap();
+        assert_eq!(df.len(), CACHE_MEMBERS);
+        assert_eq!(df.columns[2].stringify_column_data()[1], "05000US01");
+    }
+
+    #[test]
+    fn test_no_response() {
+        let backend = MockBackend::new()
+            .respond(r"^never$", year_quantity);
+
+        let res = backend.exec_sql("select 1".to_owned()).wait();
+
+        assert!(res.is_err());
+        assert_eq!(backend.queries(), vec!["select 1"]);
+    }
+
+    #[test]
+    fn test_exec_sql_stream() {
+        let backend = MockBackend::new()
+            .respond(r"year", year_quantity);
+
+        let chunks: Vec<_> = backend.exec_sql_stream("select year".to_owned()).wait().collect();
+        assert_eq!(chunks.len(), 1);
+        assert_eq!(chunks[0].as_ref().unwrap().as_ref().unwrap().len(), 2);
+
+        let chunks: Vec<_> = backend.exec_sql_stream("select 1".to_owned()).wait().collect();
+        assert_eq!(chunks.len(), 1);
+        assert!(chunks[0].is_err());
+    }
+
+    #[test]
+    fn test_first_match_wins() {
+        let backend = MockBackend::new()
+            .respond(r"select", || DataFrame::from_vec(vec![
+                Column::new("a".to_owned(), ColumnData::Int8(vec![1])),
+            ]))
+            .respond(r".*", year_quantity);
+
+        let df = backend.box_clone().exec_sql("select 1".to_owned()).wait().unwrap();
+
+        assert_eq!(df.column_names(), vec!["a"]);
+        assert_eq!(backend.queries().len(), 1);
+    }
+}
@@ -0,0 +1,6 @@
+//! The wasm module for the browser, built with
+//! `wasm-pack build tesseract-wasm`. The bindings are in
+//! `tesseract_core::wasm`; this crate only builds them as a cdylib, so that
+//! tesseract-core itself stays a plain rlib.
+
+pub use tesseract_core::wasm::*;